@@ -0,0 +1,354 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use serde::Serialize;
+use smithay::reexports::calloop::{
+    channel::{channel, Event, Sender},
+    LoopHandle,
+};
+use xdg::BaseDirectories;
+
+use crate::state::LoopData;
+
+/// Icons of this size are preferred, the switcher and most docks draw them around there
+pub const PREFERRED_SIZE: u32 = 64;
+
+/// What the desktop entry of an application says about it
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppInfo {
+    /// `Name` of the desktop entry
+    pub name: Option<String>,
+    /// Icon file resolved through the icon theme, PNG if there is one
+    pub icon: Option<PathBuf>,
+    pub desktop_entry: Option<PathBuf>,
+}
+
+/// Resolves app ids to desktop entries and icons. Answers come from an index built on a worker
+/// thread, so looking something up never touches the disk and nothing clients name is kept
+/// around. Until the first index is built everything is unknown.
+#[derive(Default)]
+pub struct AppInfoCache {
+    index: AppIndex,
+}
+
+impl AppInfoCache {
+    /// Starts indexing the applications and the icons of `theme`, and indexing them again
+    /// whenever they change
+    pub fn new(theme: &str, handle: &LoopHandle<'static, LoopData>) -> Self {
+        let (sender, indexed) = channel::<AppIndex>();
+        let inserted = handle.insert_source(indexed, |event, _, data| {
+            if let Event::Msg(index) = event {
+                data.state.common.app_info.index = index;
+            }
+        });
+        if inserted.is_err() {
+            slog_scope::warn!("Failed to register the app index channel");
+            return Self::default();
+        }
+        let theme = theme.to_string();
+        let spawned = std::thread::Builder::new()
+            .name("app-index".to_string())
+            .spawn(move || index_thread(&theme, sender));
+        if let Err(err) = spawned {
+            slog_scope::warn!("Failed to start indexing applications: {}", err);
+        }
+        Self::default()
+    }
+
+    pub fn lookup(&self, app_id: &str) -> AppInfo {
+        let apps = &self.index.apps;
+        let lowercase = app_id.to_lowercase();
+        let entry = apps
+            .get(app_id)
+            .or_else(|| apps.get(&lowercase))
+            .or_else(|| {
+                self.index
+                    .classes
+                    .get(&lowercase)
+                    .and_then(|id| apps.get(id))
+            });
+        let icon_name = entry
+            .and_then(|entry| entry.icon.as_deref())
+            .unwrap_or(app_id);
+        // Desktop entries may name an icon file instead of a theme icon
+        let icon = if entry.is_some() && Path::new(icon_name).is_absolute() {
+            Some(PathBuf::from(icon_name))
+        } else {
+            self.icon(icon_name)
+        };
+        AppInfo {
+            name: entry.and_then(|entry| entry.name.clone()),
+            icon,
+            desktop_entry: entry.map(|entry| entry.path.clone()),
+        }
+    }
+
+    /// The file of the icon called `name` in the icon theme, PNG if there is one
+    pub fn icon(&self, name: &str) -> Option<PathBuf> {
+        self.index.icons.get(name).cloned()
+    }
+}
+
+/// How often the worker checks whether applications or icons were installed or removed
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Indexes everything once, then again whenever a directory it read from changed. Stops once the
+/// compositor is gone.
+fn index_thread(theme: &str, sender: Sender<AppIndex>) {
+    let mut indexed = None;
+    loop {
+        let dirs = match BaseDirectories::new() {
+            Ok(dirs) => dirs,
+            Err(err) => {
+                slog_scope::warn!("No XDG directories to look up applications in: {}", err);
+                return;
+            }
+        };
+        let stamp = stamp(&dirs);
+        if indexed.as_ref() != Some(&stamp) {
+            if sender.send(AppIndex::build(&dirs, theme)).is_err() {
+                return;
+            }
+            indexed = Some(stamp);
+        }
+        std::thread::sleep(REFRESH_INTERVAL);
+    }
+}
+
+/// Modification times of the directories the index is built from. Installing an application or
+/// an icon theme changes one of them, installing icons into a theme updates its cache file and
+/// with it the theme directory.
+fn stamp(dirs: &BaseDirectories) -> Vec<Option<SystemTime>> {
+    let mut watched = vec![dirs.get_data_home()];
+    watched.extend(dirs.get_data_dirs());
+    let mut stamp = Vec::new();
+    for root in icon_roots(dirs) {
+        stamp.push(modified(&root));
+        if let Ok(themes) = fs::read_dir(&root) {
+            stamp.extend(themes.flatten().map(|theme| modified(&theme.path())));
+        }
+    }
+    for dir in watched {
+        stamp.push(modified(&dir.join("applications")));
+        stamp.push(modified(&dir.join("pixmaps")));
+    }
+    stamp
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// What a desktop entry says, by the name of its file
+struct DesktopEntry {
+    name: Option<String>,
+    icon: Option<String>,
+    path: PathBuf,
+}
+
+#[derive(Default)]
+struct AppIndex {
+    /// Desktop entries by their file name without `.desktop`
+    apps: HashMap<String, DesktopEntry>,
+    /// Lowercase `StartupWMClass` to the name of the entry
+    classes: HashMap<String, String>,
+    /// Icon names to the file the theme has for them
+    icons: HashMap<String, PathBuf>,
+}
+
+impl AppIndex {
+    fn build(dirs: &BaseDirectories, theme: &str) -> Self {
+        let mut apps = HashMap::new();
+        let mut classes = HashMap::new();
+        // Entries of the user come first and hide system ones of the same name
+        let files = dirs
+            .list_data_files("applications")
+            .into_iter()
+            .filter(|path| {
+                path.extension()
+                    .map(|ext| ext == "desktop")
+                    .unwrap_or(false)
+            });
+        for path in files {
+            let id = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(id) if !apps.contains_key(id) => id.to_string(),
+                _ => continue,
+            };
+            let mut entry = fs::read_to_string(&path)
+                .map(|content| parse_ini(&content))
+                .ok()
+                .and_then(|mut sections| sections.remove("Desktop Entry"))
+                .unwrap_or_default();
+            if let Some(class) = entry.remove("StartupWMClass") {
+                classes
+                    .entry(class.to_lowercase())
+                    .or_insert_with(|| id.clone());
+            }
+            apps.insert(
+                id,
+                DesktopEntry {
+                    name: entry.remove("Name"),
+                    icon: entry.remove("Icon"),
+                    path,
+                },
+            );
+        }
+
+        Self {
+            apps,
+            classes,
+            icons: index_icons(dirs, theme),
+        }
+    }
+}
+
+fn icon_roots(dirs: &BaseDirectories) -> Vec<PathBuf> {
+    let mut roots = vec![dirs.get_data_home().join("icons")];
+    roots.extend(
+        dirs.get_data_dirs()
+            .into_iter()
+            .map(|dir| dir.join("icons")),
+    );
+    if let Some(home) = std::env::var_os("HOME") {
+        roots.insert(0, PathBuf::from(home).join(".icons"));
+    }
+    roots
+}
+
+/// Every icon of `theme`, the themes it inherits from and hicolor, then pixmaps. The PNG closest
+/// to the preferred size wins, SVGs are only taken for icons without any PNG.
+fn index_icons(dirs: &BaseDirectories, theme: &str) -> HashMap<String, PathBuf> {
+    let roots = icon_roots(dirs);
+    let mut pngs = HashMap::new();
+    let mut svgs = HashMap::new();
+
+    let mut visited = HashSet::new();
+    let mut themes = vec![theme.to_string()];
+    // Inherited themes are searched depth first, hicolor always comes last
+    while let Some(theme) = themes.pop() {
+        if theme == "hicolor" || !visited.insert(theme.clone()) {
+            continue;
+        }
+        let index = theme_index(&roots, &theme);
+        index_theme(&roots, &theme, &index, &mut pngs, &mut svgs);
+        let inherits = index
+            .get("Icon Theme")
+            .and_then(|section| section.get("Inherits"))
+            .map(|inherits| inherits.split(',').map(str::to_string).collect::<Vec<_>>())
+            .unwrap_or_default();
+        themes.extend(inherits.into_iter().rev());
+    }
+    let index = theme_index(&roots, "hicolor");
+    index_theme(&roots, "hicolor", &index, &mut pngs, &mut svgs);
+
+    for (name, svg) in svgs {
+        pngs.entry(name).or_insert(svg);
+    }
+    for pixmap in dirs.list_data_files("pixmaps") {
+        if let Some(name) = icon_name(&pixmap, "png") {
+            pngs.entry(name).or_insert(pixmap);
+        }
+    }
+    pngs
+}
+
+fn theme_index(roots: &[PathBuf], theme: &str) -> Ini {
+    roots
+        .iter()
+        .map(|root| root.join(theme).join("index.theme"))
+        .find_map(|path| fs::read_to_string(path).ok())
+        .map(|content| parse_ini(&content))
+        .unwrap_or_default()
+}
+
+/// Adds the icons of one theme that aren't known yet, directories closest to the preferred size
+/// first
+fn index_theme(
+    roots: &[PathBuf],
+    theme: &str,
+    index: &Ini,
+    pngs: &mut HashMap<String, PathBuf>,
+    svgs: &mut HashMap<String, PathBuf>,
+) {
+    for (dir, is_scalable) in theme_directories(index) {
+        for root in roots {
+            let files = match fs::read_dir(root.join(theme).join(&dir)) {
+                Ok(files) => files,
+                Err(_) => continue,
+            };
+            for path in files.flatten().map(|file| file.path()) {
+                if let Some(name) = icon_name(&path, "png") {
+                    pngs.entry(name).or_insert(path);
+                } else if let Some(name) = icon_name(&path, "svg").filter(|_| is_scalable) {
+                    svgs.entry(name).or_insert(path);
+                }
+            }
+        }
+    }
+}
+
+/// The name of the icon at `path`, if it has the extension `ext`
+fn icon_name(path: &Path, ext: &str) -> Option<String> {
+    if path.extension()? != ext {
+        return None;
+    }
+    path.file_stem()?.to_str().map(str::to_string)
+}
+
+/// Directories of an icon theme, closest to the preferred size first
+fn theme_directories(index: &Ini) -> Vec<(String, bool)> {
+    let mut directories = index
+        .get("Icon Theme")
+        .and_then(|section| section.get("Directories"))
+        .map(|dirs| {
+            dirs.split(',')
+                .filter_map(|dir| {
+                    let section = index.get(dir)?;
+                    let size = section.get("Size")?.parse::<u32>().ok()?;
+                    let scalable = section
+                        .get("Type")
+                        .map(|t| t == "Scalable")
+                        .unwrap_or(false);
+                    Some((dir.to_string(), size, scalable))
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    directories.sort_by_key(|(_, size, _)| (*size as i64 - PREFERRED_SIZE as i64).abs());
+    directories
+        .into_iter()
+        .map(|(dir, _, scalable)| (dir, scalable))
+        .collect()
+}
+
+type Ini = HashMap<String, HashMap<String, String>>;
+
+/// Sections of key-value pairs, as used by desktop entries and icon themes
+fn parse_ini(content: &str) -> Ini {
+    let mut sections = HashMap::new();
+    let mut current = None;
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = Some(name.to_string());
+            continue;
+        }
+        if let (Some(section), Some((key, value))) = (&current, line.split_once('=')) {
+            sections
+                .entry(section.clone())
+                .or_insert_with(HashMap::new)
+                .entry(key.trim().to_string())
+                .or_insert_with(|| value.trim().to_string());
+        }
+    }
+    sections
+}
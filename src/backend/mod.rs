@@ -14,12 +14,13 @@ pub fn init_backend(
     dh: &DisplayHandle,
     event_loop: &mut EventLoop<'static, LoopData>,
     state: &mut State,
+    backend: Option<String>,
 ) -> Result<(), Box<dyn Error>> {
-    let res = match env::var("ELECTRUM_BACKEND") {
-        Ok(x) if x == "winit" => winit::init_backend(dh, event_loop, state),
-        Ok(x) => unimplemented!("Backend {} does not exist", x),
+    let res = match backend.or_else(|| env::var("ELECTRUM_BACKEND").ok()) {
+        Some(x) if x == "winit" => winit::init_backend(dh, event_loop, state),
+        Some(x) => return Err(format!("Backend {} does not exist, try winit", x).into()),
         // TODO create gpu backend
-        Err(_) => {
+        None => {
             slog_scope::warn!(
                 "Backend does not exist or not identified, falling back to winit backend."
             );
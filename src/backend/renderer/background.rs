@@ -0,0 +1,348 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail};
+use calloop::{
+    channel::{channel, Event, Sender},
+    LoopHandle,
+};
+use image::{imageops::FilterType, RgbaImage};
+use serde::Deserialize;
+use smithay::{
+    backend::renderer::{
+        gles2::{Gles2Renderer, Gles2Texture},
+        Frame, ImportMem, Renderer,
+    },
+    desktop::space::{RenderElement, SpaceOutputTuple},
+    utils::{Logical, Physical, Point, Rectangle, Scale, Size, Transform},
+    wayland::output::Output,
+};
+
+use crate::state::{CommonState, LoopData};
+
+use super::{element::SolidColorElement, raster::premultiply, textures::texture_bytes, CustomElem};
+
+/// Below the background layer of wlr-layer-shell, wallpaper clients still cover it
+const BACKGROUND_INDEX: u8 = 0;
+
+/// What a workspace shows below its windows, so workspaces can be told apart without a
+/// wallpaper client
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Background {
+    /// RGBA
+    Color([u8; 4]),
+    /// An image file, scaled to cover the output and cut off at the sides
+    Image(PathBuf),
+}
+
+/// A background image for one output, decoded and scaled on a worker thread so drawing only
+/// has to upload it
+enum BackgroundImage {
+    Loading,
+    Decoded(RgbaImage),
+    Uploaded(Gles2Texture),
+    Failed,
+}
+
+/// What a worker sends back
+struct Decoded {
+    output: String,
+    path: PathBuf,
+    size: Size<i32, Physical>,
+    image: Option<RgbaImage>,
+}
+
+/// Background images of each output, images that failed to load are not tried again until
+/// the background is set again
+#[derive(Default)]
+pub struct BackgroundTextures {
+    outputs: HashMap<String, (PathBuf, Size<i32, Physical>, BackgroundImage)>,
+    /// Where workers send the images they decoded, set up the first time one starts
+    sender: Option<Sender<Decoded>>,
+}
+
+impl BackgroundTextures {
+    /// Loads every image again
+    pub fn reload(&mut self) {
+        self.outputs.clear();
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.outputs
+            .values()
+            .filter(|(_, _, image)| matches!(image, BackgroundImage::Uploaded(_)))
+            .map(|(_, size, _)| texture_bytes(*size))
+            .sum()
+    }
+
+    /// Starts decoding `path` at `size` for the output called `output`, unless it already was
+    fn load(
+        &mut self,
+        handle: &LoopHandle<'static, LoopData>,
+        output: String,
+        path: &Path,
+        size: Size<i32, Physical>,
+    ) {
+        if let Some((loaded, loaded_size, _)) = self.outputs.get(&output) {
+            if loaded == path && *loaded_size == size {
+                return;
+            }
+        }
+        let sender = match self.sender(handle) {
+            Some(sender) => sender,
+            None => return,
+        };
+
+        self.outputs.insert(
+            output.clone(),
+            (path.to_path_buf(), size, BackgroundImage::Loading),
+        );
+        let path = path.to_path_buf();
+        let spawned = std::thread::Builder::new()
+            .name("background".to_string())
+            .spawn(move || {
+                let image = decode(&path, size)
+                    .map_err(|err| {
+                        slog_scope::warn!("Failed to load background {}: {}", path.display(), err)
+                    })
+                    .ok();
+                // The compositor is gone if this fails
+                let _ = sender.send(Decoded {
+                    output,
+                    path,
+                    size,
+                    image,
+                });
+            });
+        if let Err(err) = spawned {
+            slog_scope::warn!("Failed to start decoding a background: {}", err);
+        }
+    }
+
+    fn sender(&mut self, handle: &LoopHandle<'static, LoopData>) -> Option<Sender<Decoded>> {
+        if self.sender.is_none() {
+            let (sender, decoded) = channel::<Decoded>();
+            let inserted = handle.insert_source(decoded, |event, _, data| {
+                if let Event::Msg(decoded) = event {
+                    data.state.common.background_textures.decoded(decoded);
+                }
+            });
+            match inserted {
+                Ok(_) => self.sender = Some(sender),
+                Err(_) => slog_scope::warn!("Failed to register the background channel"),
+            }
+        }
+        self.sender.clone()
+    }
+
+    /// Keeps the image a worker decoded, if the output still wants it
+    fn decoded(&mut self, decoded: Decoded) {
+        if let Some((path, size, image)) = self.outputs.get_mut(&decoded.output) {
+            if *path == decoded.path
+                && *size == decoded.size
+                && matches!(image, BackgroundImage::Loading)
+            {
+                *image = match decoded.image {
+                    Some(decoded) => BackgroundImage::Decoded(decoded),
+                    None => BackgroundImage::Failed,
+                };
+            }
+        }
+    }
+
+    /// The texture of `path` for `output`, uploading it if it was decoded since the last frame.
+    /// `None` while it is still loading, which starts if it did not yet.
+    fn texture(
+        &mut self,
+        renderer: &mut Gles2Renderer,
+        handle: &LoopHandle<'static, LoopData>,
+        output: String,
+        path: &Path,
+        size: Size<i32, Physical>,
+    ) -> Option<Gles2Texture> {
+        self.load(handle, output.clone(), path, size);
+        let (_, _, image) = self.outputs.get_mut(&output)?;
+        if let BackgroundImage::Decoded(decoded) = image {
+            *image = match renderer.import_memory(decoded.as_raw(), (size.w, size.h).into(), false)
+            {
+                Ok(texture) => BackgroundImage::Uploaded(texture),
+                Err(err) => {
+                    slog_scope::warn!("Failed to upload background {}: {}", path.display(), err);
+                    BackgroundImage::Failed
+                }
+            };
+        }
+        match image {
+            BackgroundImage::Uploaded(texture) => Some(texture.clone()),
+            _ => None,
+        }
+    }
+}
+
+fn decode(path: &Path, size: Size<i32, Physical>) -> anyhow::Result<RgbaImage> {
+    let mut image = image::open(path)?
+        .resize_to_fill(size.w as u32, size.h as u32, FilterType::Triangle)
+        .to_rgba8();
+    premultiply(&mut image);
+    Ok(image)
+}
+
+impl CommonState {
+    /// Changes the background of a workspace, on the output called `output` or on all of them
+    pub fn set_background(
+        &mut self,
+        workspace: u32,
+        output: Option<String>,
+        background: Option<Background>,
+    ) -> anyhow::Result<()> {
+        if let Some(Background::Image(path)) = &background {
+            if !path.is_file() {
+                bail!("No image at {}", path.display());
+            }
+        }
+        let workspace = self
+            .shell
+            .workspaces
+            .iter_mut()
+            .find(|w| w.idx as u32 == workspace)
+            .ok_or_else(|| anyhow!("No workspace {}", workspace))?;
+        workspace.set_background(output, background);
+        self.background_textures.reload();
+
+        // Decoding starts right away instead of with the next frame
+        let active = self.shell.active_workspace();
+        for output in self.shell.outputs() {
+            let path = match active.background(output) {
+                Some(Background::Image(path)) => path,
+                _ => continue,
+            };
+            if let Some(mode) = output.current_mode() {
+                self.background_textures.load(
+                    &self.event_loop_handle,
+                    output.name(),
+                    path,
+                    mode.size,
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The background of the active workspace on `output` as an element
+pub fn background_elements(
+    renderer: &mut Gles2Renderer,
+    state: &mut CommonState,
+    output: &Output,
+) -> Vec<CustomElem> {
+    let background = match state.shell.active_workspace().background(output) {
+        Some(background) => background.clone(),
+        None => return Vec::new(),
+    };
+    let mode = match output.current_mode() {
+        Some(mode) => mode,
+        None => return Vec::new(),
+    };
+    let scale = output.current_scale().fractional_scale();
+    let size = mode.size.to_f64().to_logical(scale).to_i32_round();
+    let geometry = Rectangle::from_loc_and_size((0, 0), size);
+
+    // A different background is a different element, so the space damages it for us
+    let mut hasher = DefaultHasher::new();
+    ("background", output.name(), &background).hash(&mut hasher);
+    (mode.size.w, mode.size.h).hash(&mut hasher);
+    let id = hasher.finish() as usize;
+
+    let element = match background {
+        Background::Color(color) => CustomElem::from(SolidColorElement::with_id(
+            id,
+            geometry,
+            color.map(|c| c as f32 / 255.0),
+            BACKGROUND_INDEX,
+        )),
+        Background::Image(path) => {
+            let texture = state.background_textures.texture(
+                renderer,
+                &state.event_loop_handle,
+                output.name(),
+                &path,
+                mode.size,
+            );
+            match texture {
+                Some(texture) => CustomElem::from(BackgroundElement {
+                    id,
+                    texture,
+                    texture_size: mode.size,
+                    size,
+                }),
+                None => return Vec::new(),
+            }
+        }
+    };
+    vec![element]
+}
+
+/// A background image covering a whole output
+pub struct BackgroundElement {
+    id: usize,
+    texture: Gles2Texture,
+    texture_size: Size<i32, Physical>,
+    size: Size<i32, Logical>,
+}
+
+impl RenderElement<Gles2Renderer> for BackgroundElement {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn location(&self, _scale: impl Into<Scale<f64>>) -> Point<f64, Physical> {
+        Point::from((0.0, 0.0))
+    }
+
+    fn geometry(&self, scale: impl Into<Scale<f64>>) -> Rectangle<i32, Physical> {
+        Rectangle::from_loc_and_size((0, 0), self.size.to_physical_precise_round(scale))
+    }
+
+    fn accumulated_damage(
+        &self,
+        _scale: impl Into<Scale<f64>>,
+        _for_values: Option<SpaceOutputTuple<'_, '_>>,
+    ) -> Vec<Rectangle<i32, Physical>> {
+        Vec::new()
+    }
+
+    fn opaque_regions(
+        &self,
+        scale: impl Into<Scale<f64>>,
+    ) -> Option<Vec<Rectangle<i32, Physical>>> {
+        let geometry = RenderElement::<Gles2Renderer>::geometry(self, scale);
+        Some(vec![Rectangle::from_loc_and_size((0, 0), geometry.size)])
+    }
+
+    fn draw(
+        &self,
+        _renderer: &mut Gles2Renderer,
+        frame: &mut <Gles2Renderer as Renderer>::Frame,
+        scale: impl Into<Scale<f64>>,
+        location: Point<f64, Physical>,
+        damage: &[Rectangle<i32, Physical>],
+        _log: &slog::Logger,
+    ) -> Result<(), <Gles2Renderer as Renderer>::Error> {
+        let src = Rectangle::from_loc_and_size(
+            (0.0, 0.0),
+            (self.texture_size.w as f64, self.texture_size.h as f64),
+        );
+        let dst = Rectangle::from_loc_and_size(location, self.size.to_f64().to_physical(scale));
+        frame.render_texture_from_to(&self.texture, src, dst, damage, Transform::Normal, 1.0)
+    }
+
+    fn z_index(&self) -> u8 {
+        BACKGROUND_INDEX
+    }
+}
@@ -0,0 +1,418 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    cell::Cell,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+use anyhow::anyhow;
+use serde::Deserialize;
+use smithay::{
+    backend::renderer::{
+        gles2::{ffi, Gles2Renderer},
+        Renderer,
+    },
+    desktop::{
+        layer_map_for_output,
+        space::{RenderElement, RenderZindex, SpaceOutputTuple},
+    },
+    reexports::wayland_server::Resource,
+    utils::{Logical, Physical, Point, Rectangle, Scale, Size, Transform},
+    wayland::{output::Output, shell::wlr_layer::Layer as WlrLayer},
+};
+
+use crate::{shell::layer_rules::layer_rule, state::CommonState};
+
+use super::{
+    color::{attrib_location, link_program, uniform_location},
+    textures::texture_bytes,
+    CustomElem,
+};
+
+/// Horizontal and vertical passes, repeated to get closer to a gaussian
+const PASSES: usize = 2;
+
+const VERTEX_SHADER: &str = r#"
+attribute vec2 position;
+uniform vec2 offset;
+uniform vec2 scale;
+varying vec2 v_coords;
+
+void main() {
+    v_coords = offset + position * scale;
+    gl_Position = vec4(position * 2.0 - 1.0, 0.0, 1.0);
+}
+"#;
+
+// Nine taps in five samples, the ones in between are interpolated by the sampler. With a zero
+// step the weights add up to a plain copy.
+const FRAGMENT_SHADER: &str = r#"
+precision mediump float;
+uniform sampler2D tex;
+uniform vec2 step;
+varying vec2 v_coords;
+
+void main() {
+    vec4 color = texture2D(tex, v_coords) * 0.2270270270;
+    color += texture2D(tex, v_coords + step * 1.3846153846) * 0.3162162162;
+    color += texture2D(tex, v_coords - step * 1.3846153846) * 0.3162162162;
+    color += texture2D(tex, v_coords + step * 3.2307692308) * 0.0702702703;
+    color += texture2D(tex, v_coords - step * 3.2307692308) * 0.0702702703;
+    gl_FragColor = vec4(color.rgb, 1.0);
+}
+"#;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct BlurConfig {
+    /// Logical pixels the content behind is blurred over, 0 turns blurring off
+    pub radius: u32,
+    /// Stop blurring in low power mode, see [`crate::power`]
+    pub disable_on_low_power: bool,
+}
+
+impl Default for BlurConfig {
+    fn default() -> Self {
+        Self {
+            radius: 12,
+            disable_on_low_power: true,
+        }
+    }
+}
+
+/// The shader and scratch textures of blurring, created on the first blurred frame
+#[derive(Default)]
+pub struct BlurState {
+    /// `Some(None)` if the renderer can't blur
+    pass: Option<Option<Rc<BlurPass>>>,
+}
+
+impl BlurState {
+    pub fn bytes(&self) -> usize {
+        self.pass.iter().flatten().map(|pass| pass.bytes()).sum()
+    }
+}
+
+struct BlurPass {
+    program: ffi::types::GLuint,
+    framebuffer: ffi::types::GLuint,
+    /// Two textures the passes go back and forth between, both of `size`
+    textures: [ffi::types::GLuint; 2],
+    size: Cell<Size<i32, Physical>>,
+}
+
+impl BlurPass {
+    fn new(renderer: &mut Gles2Renderer) -> anyhow::Result<Self> {
+        renderer
+            .with_context(|_, gl| unsafe {
+                let program = link_program(gl, VERTEX_SHADER, FRAGMENT_SHADER)?;
+                let mut framebuffer = 0;
+                gl.GenFramebuffers(1, &mut framebuffer);
+                let mut textures = [0; 2];
+                gl.GenTextures(2, textures.as_mut_ptr());
+                for texture in textures {
+                    gl.BindTexture(ffi::TEXTURE_2D, texture);
+                    for (parameter, value) in [
+                        (ffi::TEXTURE_MIN_FILTER, ffi::LINEAR),
+                        (ffi::TEXTURE_MAG_FILTER, ffi::LINEAR),
+                        (ffi::TEXTURE_WRAP_S, ffi::CLAMP_TO_EDGE),
+                        (ffi::TEXTURE_WRAP_T, ffi::CLAMP_TO_EDGE),
+                    ] {
+                        gl.TexParameteri(ffi::TEXTURE_2D, parameter, value as i32);
+                    }
+                }
+                gl.BindTexture(ffi::TEXTURE_2D, 0);
+                Ok(Self {
+                    program,
+                    framebuffer,
+                    textures,
+                    size: Cell::new((0, 0).into()),
+                })
+            })
+            .map_err(|err| anyhow!("Failed to set up blurring: {}", err))?
+    }
+
+    fn bytes(&self) -> usize {
+        texture_bytes(self.size.get()) * 2
+    }
+
+    /// Blurs what is in `source` of the bound framebuffer and draws it back into `target`,
+    /// limited to `clip`. All rectangles are in framebuffer coordinates, `target` lies in
+    /// `source`. `spread` is the distance between two taps in pixels.
+    unsafe fn blur(
+        &self,
+        gl: &ffi::Gles2,
+        source: Rectangle<i32, Physical>,
+        target: Rectangle<i32, Physical>,
+        clip: &[Rectangle<i32, Physical>],
+        spread: f32,
+    ) {
+        let program = self.program;
+        let [front, back] = self.textures;
+        let mut bound = 0;
+        gl.GetIntegerv(ffi::FRAMEBUFFER_BINDING, &mut bound);
+        let mut viewport = [0; 4];
+        gl.GetIntegerv(ffi::VIEWPORT, viewport.as_mut_ptr());
+
+        if self.size.get() != source.size {
+            for texture in self.textures {
+                gl.BindTexture(ffi::TEXTURE_2D, texture);
+                gl.TexImage2D(
+                    ffi::TEXTURE_2D,
+                    0,
+                    ffi::RGBA as i32,
+                    source.size.w,
+                    source.size.h,
+                    0,
+                    ffi::RGBA,
+                    ffi::UNSIGNED_BYTE,
+                    std::ptr::null(),
+                );
+            }
+            self.size.set(source.size);
+        }
+        gl.BindTexture(ffi::TEXTURE_2D, front);
+        gl.CopyTexSubImage2D(
+            ffi::TEXTURE_2D,
+            0,
+            0,
+            0,
+            source.loc.x,
+            source.loc.y,
+            source.size.w,
+            source.size.h,
+        );
+
+        gl.Disable(ffi::BLEND);
+        gl.UseProgram(program);
+        gl.ActiveTexture(ffi::TEXTURE0);
+        gl.Uniform1i(uniform_location(gl, program, "tex"), 0);
+        gl.Uniform2f(uniform_location(gl, program, "offset"), 0.0, 0.0);
+        gl.Uniform2f(uniform_location(gl, program, "scale"), 1.0, 1.0);
+        let step = uniform_location(gl, program, "step");
+        let position = attrib_location(gl, program, "position") as u32;
+        let vertices: [f32; 8] = [0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+        gl.EnableVertexAttribArray(position);
+        gl.VertexAttribPointer(
+            position,
+            2,
+            ffi::FLOAT,
+            ffi::FALSE,
+            0,
+            vertices.as_ptr() as *const _,
+        );
+
+        gl.BindFramebuffer(ffi::FRAMEBUFFER, self.framebuffer);
+        gl.Viewport(0, 0, source.size.w, source.size.h);
+        let (w, h) = (source.size.w as f32, source.size.h as f32);
+        for _ in 0..PASSES {
+            for (from, to, x, y) in [
+                (front, back, spread / w, 0.0),
+                (back, front, 0.0, spread / h),
+            ] {
+                gl.FramebufferTexture2D(
+                    ffi::FRAMEBUFFER,
+                    ffi::COLOR_ATTACHMENT0,
+                    ffi::TEXTURE_2D,
+                    to,
+                    0,
+                );
+                gl.BindTexture(ffi::TEXTURE_2D, from);
+                gl.Uniform2f(step, x, y);
+                gl.DrawArrays(ffi::TRIANGLE_STRIP, 0, 4);
+            }
+        }
+
+        // Copied into the target, only where the frame is redrawn
+        gl.BindFramebuffer(ffi::FRAMEBUFFER, bound as u32);
+        gl.Viewport(target.loc.x, target.loc.y, target.size.w, target.size.h);
+        gl.BindTexture(ffi::TEXTURE_2D, front);
+        gl.Uniform2f(step, 0.0, 0.0);
+        gl.Uniform2f(
+            uniform_location(gl, program, "offset"),
+            (target.loc.x - source.loc.x) as f32 / w,
+            (target.loc.y - source.loc.y) as f32 / h,
+        );
+        gl.Uniform2f(
+            uniform_location(gl, program, "scale"),
+            target.size.w as f32 / w,
+            target.size.h as f32 / h,
+        );
+        gl.Enable(ffi::SCISSOR_TEST);
+        for rect in clip {
+            gl.Scissor(rect.loc.x, rect.loc.y, rect.size.w, rect.size.h);
+            gl.DrawArrays(ffi::TRIANGLE_STRIP, 0, 4);
+        }
+        gl.Disable(ffi::SCISSOR_TEST);
+
+        gl.DisableVertexAttribArray(position);
+        gl.BindTexture(ffi::TEXTURE_2D, 0);
+        gl.UseProgram(0);
+        gl.Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+        // Left on by the renderer for the whole frame
+        gl.Enable(ffi::BLEND);
+    }
+}
+
+/// Blurred backdrops of the layer surfaces on `output` a rule asked to blur the content behind,
+/// each right below the layer of its surface
+pub fn blur_elements(
+    renderer: &mut Gles2Renderer,
+    state: &mut CommonState,
+    output: &Output,
+) -> Vec<CustomElem> {
+    let BlurConfig {
+        radius,
+        disable_on_low_power,
+    } = state.config.blur;
+    if radius == 0 || (disable_on_low_power && state.power.low_power) {
+        return Vec::new();
+    }
+
+    let layer_map = layer_map_for_output(output);
+    let layers = [
+        (WlrLayer::Background, RenderZindex::Background),
+        (WlrLayer::Bottom, RenderZindex::Bottom),
+        (WlrLayer::Top, RenderZindex::Top),
+        (WlrLayer::Overlay, RenderZindex::Overlay),
+    ];
+    let blurred = layers
+        .into_iter()
+        .flat_map(|(layer, z_index)| {
+            layer_map
+                .layers_on(layer)
+                .map(move |surface| (surface, z_index as u8 - 1))
+        })
+        .filter(|(surface, _)| layer_rule(surface.wl_surface()).map_or(false, |rule| rule.blur))
+        .filter_map(|(surface, z_index)| {
+            let geometry = layer_map.layer_geometry(surface)?;
+            let mut hasher = DefaultHasher::new();
+            ("blur", surface.wl_surface().id()).hash(&mut hasher);
+            Some((hasher.finish() as usize, geometry, z_index))
+        })
+        .collect::<Vec<_>>();
+    if blurred.is_empty() {
+        return Vec::new();
+    }
+
+    let pass = state.blur.pass.get_or_insert_with(|| {
+        BlurPass::new(renderer)
+            .map(Rc::new)
+            .map_err(|err| slog_scope::warn!("{:?}, blurring is off", err))
+            .ok()
+    });
+    let pass = match pass {
+        Some(pass) => pass.clone(),
+        None => return Vec::new(),
+    };
+    let transform = Transform::from(output.current_transform());
+    let mode_size = output
+        .current_mode()
+        .map(|mode| mode.size)
+        .unwrap_or_else(|| (0, 0).into());
+    blurred
+        .into_iter()
+        .map(|(id, geometry, z_index)| {
+            CustomElem::from(BlurElement {
+                id,
+                geometry,
+                radius,
+                z_index,
+                transform,
+                mode_size,
+                pass: pass.clone(),
+            })
+        })
+        .collect()
+}
+
+/// The content behind a layer surface, blurred
+pub struct BlurElement {
+    id: usize,
+    /// Relative to the output
+    geometry: Rectangle<i32, Logical>,
+    radius: u32,
+    z_index: u8,
+    transform: Transform,
+    mode_size: Size<i32, Physical>,
+    pass: Rc<BlurPass>,
+}
+
+impl BlurElement {
+    /// `rect` of the output in framebuffer coordinates, which start at the bottom
+    fn to_framebuffer(&self, rect: Rectangle<i32, Physical>) -> Rectangle<i32, Physical> {
+        let area = self.transform.transform_size(self.mode_size);
+        let mut rect = self.transform.transform_rect_in(rect, &area);
+        rect.loc.y = self.mode_size.h - rect.loc.y - rect.size.h;
+        rect
+    }
+}
+
+impl RenderElement<Gles2Renderer> for BlurElement {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn location(&self, scale: impl Into<Scale<f64>>) -> Point<f64, Physical> {
+        self.geometry.loc.to_f64().to_physical(scale)
+    }
+
+    fn geometry(&self, scale: impl Into<Scale<f64>>) -> Rectangle<i32, Physical> {
+        self.geometry.to_physical_precise_round(scale)
+    }
+
+    fn accumulated_damage(
+        &self,
+        scale: impl Into<Scale<f64>>,
+        _for_values: Option<SpaceOutputTuple<'_, '_>>,
+    ) -> Vec<Rectangle<i32, Physical>> {
+        // Anything behind may have changed, the space only knows about damage right below
+        let size = RenderElement::<Gles2Renderer>::geometry(self, scale).size;
+        vec![Rectangle::from_loc_and_size((0, 0), size)]
+    }
+
+    fn draw(
+        &self,
+        renderer: &mut Gles2Renderer,
+        _frame: &mut <Gles2Renderer as Renderer>::Frame,
+        scale: impl Into<Scale<f64>>,
+        position: Point<f64, Physical>,
+        damage: &[Rectangle<i32, Physical>],
+        _log: &slog::Logger,
+    ) -> Result<(), <Gles2Renderer as Renderer>::Error> {
+        let scale = scale.into();
+        let position = position.to_i32_round();
+        let size = RenderElement::<Gles2Renderer>::geometry(self, scale).size;
+        let spread = (self.radius as f64 * scale.x).round() as i32;
+
+        let output =
+            Rectangle::from_loc_and_size((0, 0), self.transform.transform_size(self.mode_size));
+        let target = Rectangle::from_loc_and_size(position, size);
+        let source = Rectangle::from_loc_and_size(
+            target.loc - Point::from((spread, spread)),
+            (size.w + 2 * spread, size.h + 2 * spread),
+        );
+        let (target, source) = match (target.intersection(output), source.intersection(output)) {
+            (Some(target), Some(source)) => (target, source),
+            _ => return Ok(()),
+        };
+        let clip = damage
+            .iter()
+            .map(|rect| {
+                self.to_framebuffer(Rectangle::from_loc_and_size(rect.loc + position, rect.size))
+            })
+            .collect::<Vec<_>>();
+
+        let (source, target) = (self.to_framebuffer(source), self.to_framebuffer(target));
+        let pass = &self.pass;
+        // A pass reaches about four steps to either side, repeating it widens that by the root
+        let step = spread as f32 / 4.0 / (PASSES as f32).sqrt();
+        renderer.with_context(|_, gl| unsafe { pass.blur(gl, source, target, &clip, step) })
+    }
+
+    fn z_index(&self) -> u8 {
+        self.z_index
+    }
+}
@@ -0,0 +1,202 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use smithay::{
+    backend::renderer::{
+        gles2::{Gles2Renderer, Gles2Texture},
+        Frame, Renderer, Unbind,
+    },
+    desktop::{
+        space::{RenderElement, SpaceOutputTuple},
+        Space, Window,
+    },
+    reexports::wayland_server::Resource,
+    utils::{Logical, Physical, Point, Rectangle, Scale, Size, Transform},
+};
+
+use super::{draw_offscreen, textures::texture_bytes, CustomElem};
+
+/// How much smaller a closing window gets until it is gone
+const SHRINK: f64 = 0.1;
+
+/// The last frame of a window that was closed, kept until its close animation is over
+pub struct ClosingWindow {
+    id: usize,
+    texture: Gles2Texture,
+    texture_size: Size<i32, Physical>,
+    /// Where the window was, in global coordinates
+    geometry: Rectangle<i32, Logical>,
+    started: Instant,
+    duration: Duration,
+    /// Layer of the space the window was at, so the animation stays below what was above it
+    z_index: u8,
+}
+
+impl ClosingWindow {
+    /// Draws `window` as it is mapped at `z_index` in `space` before its surfaces go away
+    pub fn snapshot(
+        renderer: &mut Gles2Renderer,
+        space: &Space,
+        window: &Window,
+        z_index: u8,
+        duration: Duration,
+    ) -> anyhow::Result<Self> {
+        let location = space
+            .window_location(window)
+            .ok_or_else(|| anyhow!("Window is not mapped"))?;
+        let scale = space
+            .outputs_for_window(window)
+            .iter()
+            .map(|output| output.current_scale().fractional_scale())
+            .fold(1.0, f64::max);
+        let bbox = window.bbox();
+
+        // The snapshot outlives the window, so it can't use the buffer of the window
+        let result = draw_offscreen(renderer, None, window, bbox, scale);
+        let _ = renderer.unbind();
+        let (texture, texture_size) = result?;
+
+        let mut hasher = DefaultHasher::new();
+        window.toplevel().wl_surface().id().hash(&mut hasher);
+
+        let origin = location - window.geometry().loc;
+        Ok(Self {
+            id: hasher.finish() as usize,
+            texture,
+            texture_size,
+            geometry: Rectangle::from_loc_and_size(origin + bbox.loc, bbox.size),
+            started: Instant::now(),
+            duration,
+            z_index,
+        })
+    }
+
+    fn progress(&self, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.started).as_secs_f64();
+        (elapsed / self.duration.as_secs_f64()).min(1.0)
+    }
+}
+
+/// Windows fading out after they were closed
+#[derive(Default)]
+pub struct ClosingWindows(Vec<ClosingWindow>);
+
+impl ClosingWindows {
+    /// Starts the animation of `window`, ending the oldest ones early to keep at most `max`
+    /// snapshots around
+    pub fn push(&mut self, window: ClosingWindow, max: usize) {
+        self.0.push(window);
+        let excess = self.0.len().saturating_sub(max);
+        self.0.drain(..excess);
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.0
+            .iter()
+            .map(|window| texture_bytes(window.texture_size))
+            .sum()
+    }
+
+    /// Elements for the windows still closing over `output_geometry`. Finished ones are dropped
+    /// here, which gives their buffers back to the renderer.
+    pub fn elements(&mut self, output_geometry: Rectangle<i32, Logical>) -> Vec<CustomElem> {
+        let now = Instant::now();
+        self.0.retain(|window| window.progress(now) < 1.0);
+
+        self.0
+            .iter()
+            .filter(|window| window.geometry.overlaps(output_geometry))
+            .map(|window| {
+                let progress = window.progress(now);
+                let shrink = 1.0 - SHRINK * progress;
+                let geometry = window.geometry.to_f64();
+                let size = geometry.size.upscale(shrink);
+                let offset = (geometry.size.to_point() - size.to_point()).downscale(2.0);
+                let location = geometry.loc + offset - output_geometry.loc.to_f64();
+
+                CustomElem::from(ClosingElement {
+                    id: window.id,
+                    texture: window.texture.clone(),
+                    texture_size: window.texture_size,
+                    location,
+                    size,
+                    alpha: (1.0 - progress) as f32,
+                    z_index: window.z_index,
+                })
+            })
+            .collect()
+    }
+}
+
+/// One frame of a close animation
+pub struct ClosingElement {
+    id: usize,
+    texture: Gles2Texture,
+    texture_size: Size<i32, Physical>,
+    location: Point<f64, Logical>,
+    size: Size<f64, Logical>,
+    alpha: f32,
+    z_index: u8,
+}
+
+impl RenderElement<Gles2Renderer> for ClosingElement {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn location(&self, scale: impl Into<Scale<f64>>) -> Point<f64, Physical> {
+        self.location.to_physical(scale)
+    }
+
+    fn geometry(&self, scale: impl Into<Scale<f64>>) -> Rectangle<i32, Physical> {
+        let scale = scale.into();
+        Rectangle::from_loc_and_size(
+            self.location.to_physical(scale).to_i32_round(),
+            self.size.to_physical(scale).to_i32_round(),
+        )
+    }
+
+    fn accumulated_damage(
+        &self,
+        scale: impl Into<Scale<f64>>,
+        _for_values: Option<SpaceOutputTuple<'_, '_>>,
+    ) -> Vec<Rectangle<i32, Physical>> {
+        // Every frame of the animation looks different
+        let geometry = RenderElement::<Gles2Renderer>::geometry(self, scale);
+        vec![Rectangle::from_loc_and_size((0, 0), geometry.size)]
+    }
+
+    fn draw(
+        &self,
+        _renderer: &mut Gles2Renderer,
+        frame: &mut <Gles2Renderer as Renderer>::Frame,
+        scale: impl Into<Scale<f64>>,
+        location: Point<f64, Physical>,
+        damage: &[Rectangle<i32, Physical>],
+        _log: &slog::Logger,
+    ) -> Result<(), <Gles2Renderer as Renderer>::Error> {
+        let src = Rectangle::from_loc_and_size(
+            (0.0, 0.0),
+            (self.texture_size.w as f64, self.texture_size.h as f64),
+        );
+        let dst = Rectangle::from_loc_and_size(location, self.size.to_physical(scale));
+        frame.render_texture_from_to(
+            &self.texture,
+            src,
+            dst,
+            damage,
+            Transform::Normal,
+            self.alpha,
+        )
+    }
+
+    fn z_index(&self) -> u8 {
+        self.z_index
+    }
+}
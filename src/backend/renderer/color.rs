@@ -0,0 +1,388 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    cell::RefCell,
+    ffi::CString,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use anyhow::{anyhow, Context};
+use smithay::{
+    backend::renderer::{
+        gles2::{ffi, Gles2Renderer, Gles2Texture},
+        Offscreen,
+    },
+    utils::{Physical, Size},
+    wayland::output::Output,
+};
+
+use super::{filter::OutputFilter, textures::texture_bytes};
+
+/// Points per channel of the lookup table
+const LUT_SIZE: usize = 33;
+
+const VERTEX_SHADER: &str = r#"
+attribute vec2 position;
+varying vec2 v_coords;
+
+void main() {
+    v_coords = position;
+    gl_Position = vec4(position * 2.0 - 1.0, 0.0, 1.0);
+}
+"#;
+
+// The 3D table is stored as a row of blue slices, GLES 2 has no 3D textures. Red and green are
+// interpolated by the sampler, blue between two slices.
+const FRAGMENT_SHADER: &str = r#"
+precision mediump float;
+uniform sampler2D tex;
+uniform sampler2D lut;
+uniform float size;
+varying vec2 v_coords;
+
+vec3 lookup(vec3 color) {
+    float b = color.b * (size - 1.0);
+    float b0 = floor(b);
+    float b1 = min(b0 + 1.0, size - 1.0);
+    vec2 rg = color.rg * (size - 1.0) + 0.5;
+    vec2 uv0 = vec2((b0 * size + rg.x) / (size * size), rg.y / size);
+    vec2 uv1 = vec2((b1 * size + rg.x) / (size * size), rg.y / size);
+    return mix(texture2D(lut, uv0).rgb, texture2D(lut, uv1).rgb, b - b0);
+}
+
+void main() {
+    vec4 color = texture2D(tex, v_coords);
+    gl_FragColor = vec4(lookup(color.rgb), 1.0);
+}
+"#;
+
+/// The filter and display profile of an output, sampled into a lookup table from sRGB
+#[derive(Debug)]
+pub struct ColorLut {
+    /// RGB8 rows of green, each row has the blue slices side by side
+    data: Vec<u8>,
+}
+
+/// Samples `filter` followed by the transform from sRGB into the ICC profile at `profile`
+pub fn load_lut(profile: Option<&Path>, filter: Option<OutputFilter>) -> anyhow::Result<ColorLut> {
+    let step = |i: usize| (i * 255 / (LUT_SIZE - 1)) as u8;
+    let mut input = Vec::with_capacity(LUT_SIZE.pow(3));
+    for g in 0..LUT_SIZE {
+        for b in 0..LUT_SIZE {
+            for r in 0..LUT_SIZE {
+                input.push([step(r), step(g), step(b)]);
+            }
+        }
+    }
+    if let Some(filter) = filter {
+        for color in input.iter_mut() {
+            let filtered = filter.apply(color.map(|c| c as f32 / 255.0));
+            *color = filtered.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        }
+    }
+
+    let path = match profile {
+        Some(path) => path,
+        None => {
+            return Ok(ColorLut {
+                data: input.concat(),
+            })
+        }
+    };
+    let display = lcms2::Profile::new_file(path)
+        .with_context(|| format!("Failed to load ICC profile {}", path.display()))?;
+    let srgb = lcms2::Profile::new_srgb();
+    let transform = lcms2::Transform::new(
+        &srgb,
+        lcms2::PixelFormat::RGB_8,
+        &display,
+        lcms2::PixelFormat::RGB_8,
+        lcms2::Intent::Perceptual,
+    )
+    .with_context(|| format!("Failed to create a transform to {}", path.display()))?;
+
+    let mut output = vec![[0u8; 3]; input.len()];
+    transform.transform_pixels(&input, &mut output);
+
+    Ok(ColorLut {
+        data: output.concat(),
+    })
+}
+
+#[derive(Default)]
+struct ColorSettings {
+    profile: Option<PathBuf>,
+    filter: Option<OutputFilter>,
+    lut: Option<Rc<ColorLut>>,
+}
+
+#[derive(Default)]
+struct OutputColor(RefCell<ColorSettings>);
+
+fn output_color(output: &Output) -> &OutputColor {
+    output.user_data().insert_if_missing(OutputColor::default);
+    output.user_data().get::<OutputColor>().unwrap()
+}
+
+/// Loads the ICC profile at `path` for `output`, or goes back to unmanaged colors with `None`
+pub fn set_icc_profile(output: &Output, path: Option<&Path>) -> anyhow::Result<()> {
+    let mut color = output_color(output).0.borrow_mut();
+    if color.profile.as_deref() == path {
+        return Ok(());
+    }
+    color.lut = build_lut(path, color.filter)?;
+    color.profile = path.map(Path::to_path_buf);
+    if let Some(path) = path {
+        slog_scope::info!("Using ICC profile {} on {}", path.display(), output.name());
+    }
+    Ok(())
+}
+
+/// Puts `filter` over everything shown on `output`, `None` removes it
+pub fn set_output_filter(output: &Output, filter: Option<OutputFilter>) -> anyhow::Result<()> {
+    let mut color = output_color(output).0.borrow_mut();
+    if color.filter == filter {
+        return Ok(());
+    }
+    color.lut = build_lut(color.profile.as_deref(), filter)?;
+    color.filter = filter;
+    Ok(())
+}
+
+pub fn output_filter(output: &Output) -> Option<OutputFilter> {
+    output_color(output).0.borrow().filter
+}
+
+pub fn icc_profile(output: &Output) -> Option<PathBuf> {
+    output_color(output).0.borrow().profile.clone()
+}
+
+/// Checks that `profile` and `filter` can be applied, without touching any output
+pub fn test_color(profile: Option<&Path>, filter: Option<OutputFilter>) -> anyhow::Result<()> {
+    build_lut(profile, filter).map(|_| ())
+}
+
+/// Drops the profile and filter of `output`, for when the renderer can't apply them
+pub fn reset_output_color(output: &Output) {
+    *output_color(output).0.borrow_mut() = ColorSettings::default();
+}
+
+fn build_lut(
+    profile: Option<&Path>,
+    filter: Option<OutputFilter>,
+) -> anyhow::Result<Option<Rc<ColorLut>>> {
+    if profile.is_none() && filter.is_none() {
+        return Ok(None);
+    }
+    load_lut(profile, filter).map(|lut| Some(Rc::new(lut)))
+}
+
+pub fn output_lut(output: &Output) -> Option<Rc<ColorLut>> {
+    output
+        .user_data()
+        .get::<OutputColor>()
+        .and_then(|color| color.0.borrow().lut.clone())
+}
+
+/// Renders an output into an offscreen texture and draws that through the lookup table
+pub struct ColorPass {
+    pub lut: Rc<ColorLut>,
+    program: ffi::types::GLuint,
+    lut_texture: ffi::types::GLuint,
+    target: Option<(Gles2Texture, Size<i32, Physical>)>,
+}
+
+impl ColorPass {
+    pub fn new(renderer: &mut Gles2Renderer, lut: Rc<ColorLut>) -> anyhow::Result<Self> {
+        let (program, lut_texture) = renderer
+            .with_context(|_, gl| unsafe {
+                let program = link_program(gl, VERTEX_SHADER, FRAGMENT_SHADER)?;
+                let lut_texture = upload_lut(gl, &lut);
+                Ok::<_, anyhow::Error>((program, lut_texture))
+            })
+            .map_err(|err| anyhow!("Failed to set up color management: {}", err))??;
+
+        Ok(Self {
+            lut,
+            program,
+            lut_texture,
+            target: None,
+        })
+    }
+
+    /// The texture to render the output into, the bool is true if it was just created and
+    /// holds nothing from the previous frame
+    pub fn target(
+        &mut self,
+        renderer: &mut Gles2Renderer,
+        size: Size<i32, Physical>,
+    ) -> anyhow::Result<(Gles2Texture, bool)> {
+        if let Some((texture, target_size)) = &self.target {
+            if *target_size == size {
+                return Ok((texture.clone(), false));
+            }
+        }
+        let texture: Gles2Texture = renderer
+            .create_buffer((size.w, size.h).into())
+            .map_err(|err| anyhow!("Failed to create color management buffer: {}", err))?;
+        self.target = Some((texture.clone(), size));
+        Ok((texture, true))
+    }
+
+    /// The target and the lookup table, which is stored as RGB
+    pub fn bytes(&self) -> usize {
+        let target = self.target.as_ref().map(|(_, size)| texture_bytes(*size));
+        target.unwrap_or(0) + LUT_SIZE.pow(3) * 3
+    }
+
+    /// Draws the target through the lookup table into the currently bound framebuffer
+    pub fn apply(&self, renderer: &mut Gles2Renderer) -> anyhow::Result<()> {
+        let (texture, size) = match &self.target {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+        let (program, lut_texture) = (self.program, self.lut_texture);
+
+        renderer
+            .with_context(|_, gl| unsafe {
+                let vertices: [f32; 8] = [0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+                let position = attrib_location(gl, program, "position");
+
+                gl.Viewport(0, 0, size.w, size.h);
+                gl.Disable(ffi::BLEND);
+                gl.UseProgram(program);
+
+                gl.ActiveTexture(ffi::TEXTURE0);
+                gl.BindTexture(ffi::TEXTURE_2D, texture.tex_id());
+                gl.Uniform1i(uniform_location(gl, program, "tex"), 0);
+                gl.ActiveTexture(ffi::TEXTURE1);
+                gl.BindTexture(ffi::TEXTURE_2D, lut_texture);
+                gl.Uniform1i(uniform_location(gl, program, "lut"), 1);
+                gl.Uniform1f(uniform_location(gl, program, "size"), LUT_SIZE as f32);
+
+                gl.EnableVertexAttribArray(position as u32);
+                gl.VertexAttribPointer(
+                    position as u32,
+                    2,
+                    ffi::FLOAT,
+                    ffi::FALSE,
+                    0,
+                    vertices.as_ptr() as *const _,
+                );
+                gl.DrawArrays(ffi::TRIANGLE_STRIP, 0, 4);
+                gl.DisableVertexAttribArray(position as u32);
+
+                gl.BindTexture(ffi::TEXTURE_2D, 0);
+                gl.ActiveTexture(ffi::TEXTURE0);
+                gl.BindTexture(ffi::TEXTURE_2D, 0);
+                gl.UseProgram(0);
+            })
+            .map_err(|err| anyhow!("Failed to apply output colors: {}", err))
+    }
+
+    /// Frees the GL objects, the renderer has to be the one the pass was created with
+    pub fn destroy(self, renderer: &mut Gles2Renderer) {
+        let _ = renderer.with_context(|_, gl| unsafe {
+            gl.DeleteProgram(self.program);
+            gl.DeleteTextures(1, &self.lut_texture);
+        });
+    }
+}
+
+unsafe fn compile_shader(
+    gl: &ffi::Gles2,
+    kind: ffi::types::GLenum,
+    source: &str,
+) -> anyhow::Result<ffi::types::GLuint> {
+    let shader = gl.CreateShader(kind);
+    let source = CString::new(source)?;
+    gl.ShaderSource(shader, 1, &source.as_ptr(), std::ptr::null());
+    gl.CompileShader(shader);
+
+    let mut status = ffi::FALSE as i32;
+    gl.GetShaderiv(shader, ffi::COMPILE_STATUS, &mut status);
+    if status == ffi::FALSE as i32 {
+        gl.DeleteShader(shader);
+        return Err(anyhow!("Failed to compile shader"));
+    }
+    Ok(shader)
+}
+
+/// Compiles and links a program from the sources of its shaders, shared with the other passes
+pub(super) unsafe fn link_program(
+    gl: &ffi::Gles2,
+    vertex: &str,
+    fragment: &str,
+) -> anyhow::Result<ffi::types::GLuint> {
+    let vertex = compile_shader(gl, ffi::VERTEX_SHADER, vertex)?;
+    let fragment = compile_shader(gl, ffi::FRAGMENT_SHADER, fragment)?;
+    let program = gl.CreateProgram();
+    gl.AttachShader(program, vertex);
+    gl.AttachShader(program, fragment);
+    gl.LinkProgram(program);
+    gl.DetachShader(program, vertex);
+    gl.DetachShader(program, fragment);
+    gl.DeleteShader(vertex);
+    gl.DeleteShader(fragment);
+
+    let mut status = ffi::FALSE as i32;
+    gl.GetProgramiv(program, ffi::LINK_STATUS, &mut status);
+    if status == ffi::FALSE as i32 {
+        gl.DeleteProgram(program);
+        return Err(anyhow!("Failed to link shader"));
+    }
+    Ok(program)
+}
+
+unsafe fn upload_lut(gl: &ffi::Gles2, lut: &ColorLut) -> ffi::types::GLuint {
+    let mut texture = 0;
+    gl.GenTextures(1, &mut texture);
+    gl.BindTexture(ffi::TEXTURE_2D, texture);
+    gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_MIN_FILTER, ffi::LINEAR as i32);
+    gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_MAG_FILTER, ffi::LINEAR as i32);
+    gl.TexParameteri(
+        ffi::TEXTURE_2D,
+        ffi::TEXTURE_WRAP_S,
+        ffi::CLAMP_TO_EDGE as i32,
+    );
+    gl.TexParameteri(
+        ffi::TEXTURE_2D,
+        ffi::TEXTURE_WRAP_T,
+        ffi::CLAMP_TO_EDGE as i32,
+    );
+    // Rows of RGB8 are not 4 byte aligned
+    gl.PixelStorei(ffi::UNPACK_ALIGNMENT, 1);
+    gl.TexImage2D(
+        ffi::TEXTURE_2D,
+        0,
+        ffi::RGB as i32,
+        (LUT_SIZE * LUT_SIZE) as i32,
+        LUT_SIZE as i32,
+        0,
+        ffi::RGB,
+        ffi::UNSIGNED_BYTE,
+        lut.data.as_ptr() as *const _,
+    );
+    gl.PixelStorei(ffi::UNPACK_ALIGNMENT, 4);
+    gl.BindTexture(ffi::TEXTURE_2D, 0);
+    texture
+}
+
+pub(super) unsafe fn uniform_location(
+    gl: &ffi::Gles2,
+    program: ffi::types::GLuint,
+    name: &str,
+) -> ffi::types::GLint {
+    let name = CString::new(name).unwrap();
+    gl.GetUniformLocation(program, name.as_ptr())
+}
+
+pub(super) unsafe fn attrib_location(
+    gl: &ffi::Gles2,
+    program: ffi::types::GLuint,
+    name: &str,
+) -> ffi::types::GLint {
+    let name = CString::new(name).unwrap();
+    gl.GetAttribLocation(program, name.as_ptr())
+}
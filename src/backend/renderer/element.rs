@@ -0,0 +1,325 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use smithay::{
+    backend::renderer::{gles2::Gles2Renderer, utils::draw_surface_tree, Frame, Renderer},
+    desktop::{
+        layer_map_for_output,
+        space::{RenderElement, RenderZindex, SpaceOutputTuple},
+        utils::{bbox_from_surface_tree, damage_from_surface_tree},
+        PopupManager,
+    },
+    reexports::wayland_server::{protocol::wl_surface::WlSurface, Resource},
+    utils::{Logical, Physical, Point, Rectangle, Scale, Size},
+    wayland::{
+        compositor::{
+            with_states, with_surface_tree_downward, SubsurfaceCachedState, TraversalAction,
+        },
+        output::Output,
+        shell::wlr_layer::{Layer, LayerSurfaceCachedState},
+        viewporter::ViewportCachedState,
+    },
+};
+
+use crate::{shell::workspace::Workspace, state::single_pixel_buffer::single_pixel_color};
+
+use super::CustomElem;
+
+/// A rectangle filled with a single color, used for single pixel buffers and drop zone outlines
+pub struct SolidColorElement {
+    id: usize,
+    geometry: Rectangle<i32, Logical>,
+    color: [f32; 4],
+    z_index: u8,
+}
+
+impl SolidColorElement {
+    pub fn new(
+        surface: &WlSurface,
+        geometry: Rectangle<i32, Logical>,
+        color: [f32; 4],
+        z_index: u8,
+    ) -> Self {
+        // A new color is a new element, so the space damages it for us
+        let mut hasher = DefaultHasher::new();
+        surface.id().hash(&mut hasher);
+        color.iter().for_each(|c| c.to_bits().hash(&mut hasher));
+
+        Self::with_id(hasher.finish() as usize, geometry, color, z_index)
+    }
+
+    /// A rectangle drawn by the compositor itself, `id` has to change with the color
+    pub fn with_id(
+        id: usize,
+        geometry: Rectangle<i32, Logical>,
+        color: [f32; 4],
+        z_index: u8,
+    ) -> Self {
+        Self {
+            id,
+            geometry,
+            color,
+            z_index,
+        }
+    }
+}
+
+impl RenderElement<Gles2Renderer> for SolidColorElement {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn location(&self, scale: impl Into<Scale<f64>>) -> Point<f64, Physical> {
+        self.geometry.loc.to_f64().to_physical(scale)
+    }
+
+    fn geometry(&self, scale: impl Into<Scale<f64>>) -> Rectangle<i32, Physical> {
+        self.geometry.to_physical_precise_round(scale)
+    }
+
+    fn accumulated_damage(
+        &self,
+        _scale: impl Into<Scale<f64>>,
+        _for_values: Option<SpaceOutputTuple<'_, '_>>,
+    ) -> Vec<Rectangle<i32, Physical>> {
+        Vec::new()
+    }
+
+    fn opaque_regions(
+        &self,
+        scale: impl Into<Scale<f64>>,
+    ) -> Option<Vec<Rectangle<i32, Physical>>> {
+        if self.color[3] >= 1.0 {
+            let geometry = RenderElement::<Gles2Renderer>::geometry(self, scale);
+            Some(vec![Rectangle::from_loc_and_size((0, 0), geometry.size)])
+        } else {
+            None
+        }
+    }
+
+    fn draw(
+        &self,
+        _renderer: &mut Gles2Renderer,
+        frame: &mut <Gles2Renderer as Renderer>::Frame,
+        _scale: impl Into<Scale<f64>>,
+        position: Point<f64, Physical>,
+        damage: &[Rectangle<i32, Physical>],
+        _log: &slog::Logger,
+    ) -> Result<(), <Gles2Renderer as Renderer>::Error> {
+        let position = position.to_i32_round();
+        let damage = damage
+            .iter()
+            .map(|rect| {
+                let mut rect = *rect;
+                rect.loc += position;
+                rect
+            })
+            .collect::<Vec<_>>();
+        frame.clear(self.color, &damage)
+    }
+
+    fn z_index(&self) -> u8 {
+        self.z_index
+    }
+}
+
+/// A surface tree drawn larger than the client made it, used for big cursors
+pub struct ScaledSurfaceTree {
+    id: usize,
+    surface: WlSurface,
+    position: Point<i32, Logical>,
+    scale: f64,
+    z_index: u8,
+}
+
+impl ScaledSurfaceTree {
+    pub fn new(surface: WlSurface, position: Point<i32, Logical>, scale: f64, z_index: u8) -> Self {
+        let mut hasher = DefaultHasher::new();
+        surface.id().hash(&mut hasher);
+        scale.to_bits().hash(&mut hasher);
+
+        Self {
+            id: hasher.finish() as usize,
+            surface,
+            position,
+            scale,
+            z_index,
+        }
+    }
+
+    fn scaled(&self, scale: impl Into<Scale<f64>>) -> Scale<f64> {
+        let scale = scale.into();
+        Scale {
+            x: scale.x * self.scale,
+            y: scale.y * self.scale,
+        }
+    }
+}
+
+impl RenderElement<Gles2Renderer> for ScaledSurfaceTree {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn location(&self, scale: impl Into<Scale<f64>>) -> Point<f64, Physical> {
+        self.position.to_f64().to_physical(scale)
+    }
+
+    fn geometry(&self, scale: impl Into<Scale<f64>>) -> Rectangle<i32, Physical> {
+        let scale = scale.into();
+        let bbox = bbox_from_surface_tree(&self.surface, (0, 0));
+        let location = self.position.to_f64().to_physical(scale)
+            + bbox.loc.to_f64().to_physical(self.scaled(scale));
+        Rectangle::from_loc_and_size(
+            location.to_i32_round(),
+            bbox.size.to_physical_precise_round(self.scaled(scale)),
+        )
+    }
+
+    fn accumulated_damage(
+        &self,
+        scale: impl Into<Scale<f64>>,
+        for_values: Option<SpaceOutputTuple<'_, '_>>,
+    ) -> Vec<Rectangle<i32, Physical>> {
+        damage_from_surface_tree(
+            &self.surface,
+            (0.0, 0.0),
+            self.scaled(scale),
+            for_values.map(|SpaceOutputTuple(space, output)| (space, output)),
+        )
+    }
+
+    fn draw(
+        &self,
+        renderer: &mut Gles2Renderer,
+        frame: &mut <Gles2Renderer as Renderer>::Frame,
+        scale: impl Into<Scale<f64>>,
+        location: Point<f64, Physical>,
+        damage: &[Rectangle<i32, Physical>],
+        log: &slog::Logger,
+    ) -> Result<(), <Gles2Renderer as Renderer>::Error> {
+        draw_surface_tree(
+            renderer,
+            frame,
+            &self.surface,
+            self.scaled(scale),
+            location,
+            damage,
+            log,
+        )
+    }
+
+    fn z_index(&self) -> u8 {
+        self.z_index
+    }
+}
+
+/// Elements for every single pixel buffer attached to a window mapped on `output` of
+/// `workspace`, to a layer surface of `output` or to one of their popups, each at the layer the
+/// surface is drawn at
+pub fn single_pixel_elements(workspace: &Workspace, output: &Output) -> Vec<CustomElem> {
+    let space = &workspace.space;
+    let output_location = match space.output_geometry(output) {
+        Some(geometry) => geometry.loc,
+        None => return Vec::new(),
+    };
+
+    let mut elements = Vec::new();
+    for window in space.windows() {
+        let location = match space.window_location(window) {
+            Some(location) => location,
+            None => continue,
+        };
+        let origin = location - window.geometry().loc - output_location;
+        let surface = window.toplevel().wl_surface();
+        push_single_pixel(&mut elements, surface, origin, workspace.z_index(window));
+        for (popup, offset) in PopupManager::popups_for_surface(surface) {
+            let origin = origin + window.geometry().loc + offset - popup.geometry().loc;
+            let z_index = RenderZindex::Popups as u8;
+            push_single_pixel(&mut elements, popup.wl_surface(), origin, z_index);
+        }
+    }
+
+    let layer_map = layer_map_for_output(output);
+    for layer_surface in layer_map.layers() {
+        let origin = match layer_map.layer_geometry(layer_surface) {
+            Some(geometry) => geometry.loc,
+            None => continue,
+        };
+        let surface = layer_surface.wl_surface();
+        let layer = with_states(surface, |states| {
+            states
+                .cached_state
+                .current::<LayerSurfaceCachedState>()
+                .layer
+        });
+        let (z_index, popup_z_index) = match layer {
+            Layer::Background => (RenderZindex::Background, RenderZindex::Popups),
+            Layer::Bottom => (RenderZindex::Bottom, RenderZindex::Popups),
+            Layer::Top => (RenderZindex::Top, RenderZindex::Popups),
+            Layer::Overlay => (RenderZindex::Overlay, RenderZindex::PopupsOverlay),
+        };
+        push_single_pixel(&mut elements, surface, origin, z_index as u8);
+        for (popup, offset) in PopupManager::popups_for_surface(surface) {
+            let origin = origin + offset - popup.geometry().loc;
+            push_single_pixel(
+                &mut elements,
+                popup.wl_surface(),
+                origin,
+                popup_z_index as u8,
+            );
+        }
+    }
+    elements
+}
+
+/// Adds the single pixel buffers in the surface tree of `surface` drawn at `origin`
+fn push_single_pixel(
+    elements: &mut Vec<CustomElem>,
+    surface: &WlSurface,
+    origin: Point<i32, Logical>,
+    z_index: u8,
+) {
+    with_surface_tree_downward(
+        surface,
+        origin,
+        |_, states, location| {
+            let mut location = *location;
+            if states.role == Some("subsurface") {
+                location += states
+                    .cached_state
+                    .current::<SubsurfaceCachedState>()
+                    .location;
+            }
+            TraversalAction::DoChildren(location)
+        },
+        |surface, states, location| {
+            let mut location = *location;
+            if states.role == Some("subsurface") {
+                location += states
+                    .cached_state
+                    .current::<SubsurfaceCachedState>()
+                    .location;
+            }
+
+            if let Some(color) = single_pixel_color(states) {
+                let size = states
+                    .cached_state
+                    .current::<ViewportCachedState>()
+                    .dst
+                    .unwrap_or_else(|| Size::from((1, 1)));
+                elements.push(CustomElem::from(SolidColorElement::new(
+                    surface,
+                    Rectangle::from_loc_and_size(location, size),
+                    color,
+                    z_index,
+                )));
+            }
+        },
+        |_, _, _| true,
+    );
+}
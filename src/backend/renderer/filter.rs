@@ -0,0 +1,98 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use serde::Deserialize;
+
+/// Color filters for outputs, for users who have trouble seeing the screen as it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFilter {
+    Grayscale,
+    Invert,
+    HighContrast,
+    /// Shifts red-green differences, which people without red cones can't see, into blue
+    Protanopia,
+    /// Shifts red-green differences, which people without green cones can't see, into blue
+    Deuteranopia,
+    /// Shifts blue-yellow differences, which people without blue cones can't see, into red
+    Tritanopia,
+    /// Shows what people without red cones see
+    SimulateProtanopia,
+    /// Shows what people without green cones see
+    SimulateDeuteranopia,
+    /// Shows what people without blue cones see
+    SimulateTritanopia,
+}
+
+type Matrix = [[f32; 3]; 3];
+
+// Machado, Oliveira and Fernandes 2009, full severity, on linear RGB
+const PROTANOPIA: Matrix = [
+    [0.152286, 1.052583, -0.204868],
+    [0.114503, 0.786281, 0.099216],
+    [-0.003882, -0.048116, 1.051998],
+];
+const DEUTERANOPIA: Matrix = [
+    [0.367322, 0.860646, -0.227968],
+    [0.280085, 0.672501, 0.047413],
+    [-0.011820, 0.042940, 0.968881],
+];
+const TRITANOPIA: Matrix = [
+    [1.255528, -0.076749, -0.178779],
+    [-0.078411, 0.930809, 0.147602],
+    [0.004733, 0.691367, 0.303900],
+];
+
+/// Where the colors lost to the simulated deficiency go when daltonizing
+const ERROR_SHIFT: Matrix = [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]];
+const ERROR_SHIFT_TRITAN: Matrix = [[1.0, 0.0, 0.7], [0.0, 1.0, 0.7], [0.0, 0.0, 0.0]];
+
+impl OutputFilter {
+    /// Filters one sRGB color, channels go from 0 to 1
+    pub fn apply(self, color: [f32; 3]) -> [f32; 3] {
+        let linear = color.map(to_linear);
+        let filtered = match self {
+            OutputFilter::Grayscale => {
+                let luma = 0.2126 * linear[0] + 0.7152 * linear[1] + 0.0722 * linear[2];
+                [luma; 3]
+            }
+            OutputFilter::Invert => return color.map(|c| 1.0 - c),
+            // Steeper around the middle, done on the encoded values so midtones move apart
+            OutputFilter::HighContrast => return color.map(|c| (c - 0.5) * 2.0 + 0.5),
+            OutputFilter::Protanopia => daltonize(linear, &PROTANOPIA, &ERROR_SHIFT),
+            OutputFilter::Deuteranopia => daltonize(linear, &DEUTERANOPIA, &ERROR_SHIFT),
+            OutputFilter::Tritanopia => daltonize(linear, &TRITANOPIA, &ERROR_SHIFT_TRITAN),
+            OutputFilter::SimulateProtanopia => multiply(&PROTANOPIA, linear),
+            OutputFilter::SimulateDeuteranopia => multiply(&DEUTERANOPIA, linear),
+            OutputFilter::SimulateTritanopia => multiply(&TRITANOPIA, linear),
+        };
+        filtered.map(to_srgb)
+    }
+}
+
+fn daltonize(color: [f32; 3], simulation: &Matrix, shift: &Matrix) -> [f32; 3] {
+    let simulated = multiply(simulation, color);
+    let error = [0, 1, 2].map(|i| color[i] - simulated[i]);
+    let correction = multiply(shift, error);
+    [0, 1, 2].map(|i| color[i] + correction[i])
+}
+
+fn multiply(matrix: &Matrix, color: [f32; 3]) -> [f32; 3] {
+    matrix.map(|row| row[0] * color[0] + row[1] * color[1] + row[2] * color[2])
+}
+
+fn to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
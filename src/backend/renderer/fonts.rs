@@ -0,0 +1,110 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{collections::HashSet, path::PathBuf, sync::mpsc};
+
+use calloop::{
+    channel::{channel, Event, Sender},
+    LoopHandle,
+};
+use fontdue::Font;
+use indexmap::IndexMap;
+
+use super::raster::load_font;
+use crate::state::{CommonState, LoopData};
+
+/// Fonts kept at most, the ones loaded first are dropped first. The fontconfig default stays.
+const MAX_FONTS: usize = 16;
+
+/// A font file, `None` for the one fontconfig picks for sans-serif
+pub type FontPath = Option<PathBuf>;
+
+/// Fonts loaded on a worker thread, so drawing never runs fontconfig or reads font files. They
+/// are loaded when whatever uses them is configured, until then text is left out.
+#[derive(Default)]
+pub struct Fonts {
+    /// `None` for fonts that failed to load
+    loaded: IndexMap<FontPath, Option<Font>>,
+    loading: HashSet<FontPath>,
+    /// Where fonts to load go, set up the first time one is requested
+    requests: Option<mpsc::Sender<FontPath>>,
+}
+
+impl Fonts {
+    /// Starts loading the font at `path`, unless it was already
+    pub fn load(&mut self, handle: &LoopHandle<'static, LoopData>, path: &FontPath) {
+        if self.loaded.contains_key(path) || self.loading.contains(path) {
+            return;
+        }
+        let sent = self
+            .requests(handle)
+            .map(|requests| requests.send(path.clone()).is_ok())
+            .unwrap_or(false);
+        if sent {
+            self.loading.insert(path.clone());
+        }
+    }
+
+    /// The font at `path`, if it finished loading
+    pub fn get(&self, path: &FontPath) -> Option<&Font> {
+        self.loaded.get(path).and_then(Option::as_ref)
+    }
+
+    fn requests(
+        &mut self,
+        handle: &LoopHandle<'static, LoopData>,
+    ) -> Option<&mpsc::Sender<FontPath>> {
+        if self.requests.is_none() {
+            let (sender, loaded) = channel::<(FontPath, Option<Font>)>();
+            let inserted = handle.insert_source(loaded, |event, _, data| {
+                if let Event::Msg((path, font)) = event {
+                    data.state.common.font_loaded(path, font);
+                }
+            });
+            if inserted.is_err() {
+                slog_scope::warn!("Failed to register the font channel");
+                return None;
+            }
+            let (requests, paths) = mpsc::channel();
+            let spawned = std::thread::Builder::new()
+                .name("fonts".to_string())
+                .spawn(move || load_thread(paths, sender));
+            match spawned {
+                Ok(_) => self.requests = Some(requests),
+                Err(err) => slog_scope::warn!("Failed to start loading fonts: {}", err),
+            }
+        }
+        self.requests.as_ref()
+    }
+}
+
+fn load_thread(paths: mpsc::Receiver<FontPath>, sender: Sender<(FontPath, Option<Font>)>) {
+    for path in paths {
+        let font = load_font(path.as_deref());
+        // The compositor is gone if this fails
+        if sender.send((path, font)).is_err() {
+            return;
+        }
+    }
+}
+
+impl CommonState {
+    /// Keeps a font a worker loaded and redraws what shows text in it
+    fn font_loaded(&mut self, path: FontPath, font: Option<Font>) {
+        let fonts = &mut self.fonts;
+        fonts.loading.remove(&path);
+        if fonts.loaded.len() >= MAX_FONTS {
+            if let Some(oldest) = fonts.loaded.keys().position(Option::is_some) {
+                fonts.loaded.shift_remove_index(oldest);
+            }
+        }
+        fonts.loaded.insert(path.clone(), font);
+
+        if self.switcher.style.font == path {
+            self.switcher.generation += 1;
+        }
+        if path.is_none() {
+            self.shade_bars.redraw();
+        }
+        self.overlays.font_loaded(&path);
+    }
+}
@@ -0,0 +1,187 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+};
+
+use anyhow::ensure;
+use calloop::{
+    channel::{channel, Event, Sender},
+    LoopHandle,
+};
+use image::{
+    imageops::{self, FilterType},
+    io::{Limits, Reader},
+    RgbaImage,
+};
+use indexmap::IndexMap;
+use smithay::desktop::Window;
+
+use crate::{
+    appinfo::PREFERRED_SIZE,
+    state::{toplevel_icon::named_icon_decoded, CommonState, LoopData},
+};
+
+/// Icon files larger than this are not decoded
+const MAX_FILE_SIZE: u64 = 4 * 1024 * 1024;
+/// Icons wider or higher than this are not decoded
+const MAX_DIMENSION: u32 = 1024;
+/// Decoded icons are scaled down to at most this size, the switcher draws them smaller still
+const STORED_SIZE: u32 = PREFERRED_SIZE * 4;
+/// Decoded icons kept at most, the ones decoded first are dropped first
+const MAX_ICONS: usize = 128;
+
+/// Icons of the icon theme, decoded on a worker thread. Only paths the app index resolved are
+/// ever decoded, never anything a client names directly.
+#[derive(Default)]
+pub struct Icons {
+    /// `None` for icons that failed to decode
+    decoded: IndexMap<PathBuf, Option<Arc<RgbaImage>>>,
+    loading: HashSet<PathBuf>,
+    /// Windows waiting for the icon they named, with the name and the path it resolved to
+    windows: Vec<(Window, String, PathBuf)>,
+    /// Where paths to decode go, set up the first time one is requested
+    requests: Option<mpsc::Sender<PathBuf>>,
+}
+
+impl Icons {
+    /// The icon at `path` if it was decoded already, otherwise it starts decoding
+    pub fn get(
+        &mut self,
+        handle: &LoopHandle<'static, LoopData>,
+        path: &Path,
+    ) -> Option<Arc<RgbaImage>> {
+        if let Some(icon) = self.decoded.get(path) {
+            return icon.clone();
+        }
+        self.load(handle, path);
+        None
+    }
+
+    /// Like `get`, also setting the icon of `window` called `name` once it was decoded
+    pub fn get_for_window(
+        &mut self,
+        handle: &LoopHandle<'static, LoopData>,
+        window: &Window,
+        name: &str,
+        path: &Path,
+    ) -> Option<Arc<RgbaImage>> {
+        self.windows.retain(|(waiting, _, _)| waiting != window);
+        let icon = self.get(handle, path);
+        if self.loading.contains(path) {
+            self.windows
+                .push((window.clone(), name.to_string(), path.to_path_buf()));
+        }
+        icon
+    }
+
+    /// Forgets the icon `window` is waiting for, once it is unmapped or sets another one
+    pub fn forget_window(&mut self, window: &Window) {
+        self.windows.retain(|(waiting, _, _)| waiting != window);
+    }
+
+    fn load(&mut self, handle: &LoopHandle<'static, LoopData>, path: &Path) {
+        if self.loading.contains(path) {
+            return;
+        }
+        let sent = self
+            .requests(handle)
+            .map(|requests| requests.send(path.to_path_buf()).is_ok())
+            .unwrap_or(false);
+        if sent {
+            self.loading.insert(path.to_path_buf());
+        }
+    }
+
+    fn requests(
+        &mut self,
+        handle: &LoopHandle<'static, LoopData>,
+    ) -> Option<&mpsc::Sender<PathBuf>> {
+        if self.requests.is_none() {
+            let (sender, decoded) = channel::<(PathBuf, Option<RgbaImage>)>();
+            let inserted = handle.insert_source(decoded, |event, _, data| {
+                if let Event::Msg((path, icon)) = event {
+                    data.state.common.icon_decoded(path, icon.map(Arc::new));
+                }
+            });
+            if inserted.is_err() {
+                slog_scope::warn!("Failed to register the icon channel");
+                return None;
+            }
+            let (requests, paths) = mpsc::channel();
+            let spawned = std::thread::Builder::new()
+                .name("icons".to_string())
+                .spawn(move || decode_thread(paths, sender));
+            match spawned {
+                Ok(_) => self.requests = Some(requests),
+                Err(err) => slog_scope::warn!("Failed to start decoding icons: {}", err),
+            }
+        }
+        self.requests.as_ref()
+    }
+}
+
+fn decode_thread(paths: mpsc::Receiver<PathBuf>, sender: Sender<(PathBuf, Option<RgbaImage>)>) {
+    for path in paths {
+        let icon = decode(&path)
+            .map_err(|err| slog_scope::debug!("Failed to load icon {}: {}", path.display(), err))
+            .ok();
+        // The compositor is gone if this fails
+        if sender.send((path, icon)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Only PNGs are drawn, the app info falls back to SVGs for other consumers
+fn decode(path: &Path) -> anyhow::Result<RgbaImage> {
+    let size = fs::metadata(path)?.len();
+    ensure!(
+        size <= MAX_FILE_SIZE,
+        "{} bytes is too large for an icon",
+        size
+    );
+    let mut reader = Reader::open(path)?.with_guessed_format()?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_DIMENSION as u64 * MAX_DIMENSION as u64 * 4);
+    reader.limits(limits);
+    let icon = reader.decode()?.to_rgba8();
+    if icon.width() <= STORED_SIZE && icon.height() <= STORED_SIZE {
+        return Ok(icon);
+    }
+    Ok(imageops::resize(
+        &icon,
+        STORED_SIZE,
+        STORED_SIZE,
+        FilterType::Triangle,
+    ))
+}
+
+impl CommonState {
+    /// Keeps an icon a worker decoded and hands it to the windows that named it
+    fn icon_decoded(&mut self, path: PathBuf, icon: Option<Arc<RgbaImage>>) {
+        let icons = &mut self.icons;
+        icons.loading.remove(&path);
+        if icons.decoded.len() >= MAX_ICONS {
+            icons.decoded.shift_remove_index(0);
+        }
+        icons.decoded.insert(path.clone(), icon.clone());
+
+        let (ready, waiting) = icons
+            .windows
+            .drain(..)
+            .partition::<Vec<_>, _>(|(_, _, waiting)| *waiting == path);
+        icons.windows = waiting;
+        if let Some(icon) = icon {
+            for (window, name, _) in ready {
+                named_icon_decoded(window.toplevel().wl_surface(), &name, icon.clone());
+            }
+            self.refresh_switcher();
+        }
+    }
+}
@@ -1,23 +1,80 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
+use anyhow::anyhow;
+use image::{Rgba, RgbaImage};
 use smithay::{
-    backend::renderer::{gles2::Gles2Renderer, Frame, ImportAll, Renderer},
+    backend::renderer::{
+        gles2::{Gles2Renderer, Gles2Texture},
+        Bind, ExportMem, Frame, ImportAll, Offscreen, Renderer, TextureMapping, Unbind,
+    },
     desktop::{
         draw_layer_popups, draw_layer_surface, draw_window, draw_window_popups,
         layer_map_for_output,
-        space::{RenderElement, RenderError, SurfaceTree},
+        space::{RenderElement, RenderError, RenderZindex, SurfaceTree},
         utils::damage_from_surface_tree,
         Window,
     },
-    utils::{Physical, Rectangle, Transform},
-    wayland::{output::Output, shell::wlr_layer::Layer as WlrLayer},
+    utils::{Buffer, IsAlive, Logical, Physical, Point, Rectangle, Size, Transform},
+    wayland::{
+        compositor::with_states,
+        output::Output,
+        seat::{CursorImageAttributes, CursorImageStatus, Seat},
+        shell::wlr_layer::Layer as WlrLayer,
+    },
+};
+use std::{cell::RefCell, sync::Mutex};
+
+use crate::{
+    shell::{
+        grab::{move_grab_elements, moved_window, MoveGrabRenderElement},
+        layer_rules::layer_rule,
+        layout::zones::drop_zone_elements,
+        magnifier::Magnification,
+        window::WindowExt,
+    },
+    state::{idle::idle_element, output::OutputExt, CommonState, State},
+};
+
+use self::{
+    background::{background_elements, BackgroundElement},
+    blur::{blur_elements, BlurElement},
+    closing::ClosingElement,
+    element::{single_pixel_elements, ScaledSurfaceTree, SolidColorElement},
+    overlay::{overlay_elements, OverlayElement},
+    plugin::{plugin_elements, PluginElement},
+    shade::{shade_elements, ShadeElement},
+    switcher::{switcher_elements, SwitcherElement},
+    textures::TextureCache,
 };
 
-use crate::state::CommonState;
+pub mod background;
+pub mod blur;
+pub mod closing;
+pub mod color;
+pub mod element;
+pub mod filter;
+pub mod fonts;
+pub mod icons;
+pub mod overlay;
+pub mod plugin;
+mod raster;
+pub mod shade;
+pub mod switcher;
+pub mod textures;
 
 smithay::custom_elements! {
     pub CustomElem<=Gles2Renderer>;
     SurfaceTree=SurfaceTree,
+    Background=BackgroundElement,
+    Blur=BlurElement,
+    SolidColor=SolidColorElement,
+    ScaledSurfaceTree=ScaledSurfaceTree,
+    Closing=ClosingElement,
+    Overlay=OverlayElement,
+    Plugin=PluginElement,
+    Switcher=SwitcherElement,
+    MoveGrab=MoveGrabRenderElement,
+    Shade=ShadeElement,
 }
 
 pub trait AsGles2Renderer {
@@ -30,17 +87,82 @@ impl AsGles2Renderer for Gles2Renderer {
 }
 
 static CLEAR_COLOR: [f32; 4] = [0.153, 1.0, 0.165, 1.0];
+/// Shown instead of private windows in captures
+static PRIVATE_COLOR: Rgba<u8> = Rgba([0x30, 0x30, 0x30, 0xff]);
+
+pub fn cursor_status(seat: &Seat<State>) -> CursorImageStatus {
+    seat.user_data()
+        .get::<RefCell<CursorImageStatus>>()
+        .map(|status| status.borrow().clone())
+        .unwrap_or(CursorImageStatus::Default)
+}
 
+/// Client provided cursor surfaces of every seat with its pointer on `output`.
+///
+/// Cursors are rendered as their own elements, so moving them only damages the previous and the
+/// new cursor rectangle instead of the whole output. The default cursor is left to the backend
+/// if it can show a `hardware_cursor`.
 pub fn cursor_custom_elements<R>(
     _renderer: &mut R,
-    _state: &CommonState,
-    _output: &Output,
-    _hardware_cursor: bool,
+    state: &CommonState,
+    output: &Output,
+    hardware_cursor: bool,
 ) -> Vec<CustomElem>
 where
     R: AsGles2Renderer,
 {
-    Vec::new()
+    let output_geometry = output.geometry();
+    let cursor_scale = state.accessibility.cursor_scale;
+
+    state
+        .seats
+        .iter()
+        .filter_map(|seat| {
+            let location = seat.get_pointer()?.current_location();
+            if !output_geometry.to_f64().contains(location) {
+                return None;
+            }
+
+            match cursor_status(seat) {
+                CursorImageStatus::Image(surface) if surface.alive() => {
+                    let hotspot = with_states(&surface, |states| {
+                        states
+                            .data_map
+                            .get::<Mutex<CursorImageAttributes>>()
+                            .map(|attrs| attrs.lock().unwrap().hotspot)
+                    })
+                    .unwrap_or_else(|| Point::from((0, 0)));
+                    let hotspot = Point::<i32, Logical>::from((
+                        (hotspot.x as f64 * cursor_scale).round() as i32,
+                        (hotspot.y as f64 * cursor_scale).round() as i32,
+                    ));
+                    let position =
+                        (location - output_geometry.loc.to_f64()).to_i32_round() - hotspot;
+                    let z_index = RenderZindex::Overlay as u8 + 1;
+
+                    if cursor_scale == 1.0 {
+                        Some(CustomElem::from(SurfaceTree {
+                            surface,
+                            position,
+                            z_index,
+                        }))
+                    } else {
+                        Some(CustomElem::from(ScaledSurfaceTree::new(
+                            surface,
+                            position,
+                            cursor_scale,
+                            z_index,
+                        )))
+                    }
+                }
+                CursorImageStatus::Default if !hardware_cursor => {
+                    // TODO: Render a cursor theme without hardware cursors
+                    None
+                }
+                _ => None,
+            }
+        })
+        .collect()
 }
 
 pub fn needs_buffer_reset(output: &Output, state: &CommonState) -> bool {
@@ -49,7 +171,7 @@ pub fn needs_buffer_reset(output: &Output, state: &CommonState) -> bool {
 
     let will_render_custom = {
         let workspace = state.shell.active_workspace();
-        workspace.get_fullscreen(output).is_some()
+        workspace.get_filling(output).is_some() || state.shell.transition().is_some()
     };
 
     let userdata = output.user_data();
@@ -62,6 +184,172 @@ pub fn needs_buffer_reset(output: &Output, state: &CommonState) -> bool {
         != will_render_custom
 }
 
+/// Reads back the currently bound framebuffer of `output`
+pub fn capture_output<R>(renderer: &mut R, output: &Output) -> anyhow::Result<RgbaImage>
+where
+    R: ExportMem,
+{
+    let size = output
+        .current_mode()
+        .map(|m| m.size)
+        .unwrap_or_else(|| (0, 0).into());
+    read_framebuffer(renderer, (size.w, size.h).into())
+}
+
+/// Paints over the private windows in a captured frame of `output`, including their popups and
+/// windows that are being moved, and over layer surfaces a rule made private
+pub fn mask_private_windows(image: &mut RgbaImage, state: &CommonState, output: &Output) {
+    let workspace = state.shell.active_workspace();
+    let output_geometry = output.geometry();
+
+    let mut regions = Vec::<Rectangle<i32, Logical>>::new();
+    match workspace.get_filling(output).map(|filling| filling.window) {
+        Some(window) => {
+            if window.private() {
+                regions.push(Rectangle::from_loc_and_size((0, 0), output_geometry.size));
+            }
+        }
+        None => {
+            for window in workspace.space.windows().filter(|w| w.private()) {
+                if let Some(location) = workspace.space.window_location(window) {
+                    let mut bbox = window.bbox_with_popups();
+                    bbox.loc += location - window.geometry().loc - output_geometry.loc;
+                    regions.push(bbox);
+                }
+            }
+        }
+    }
+    for seat in &state.seats {
+        if let Some((window, location)) = moved_window(seat, output) {
+            if window.private() {
+                let mut bbox = window.bbox_with_popups();
+                bbox.loc += location.to_i32_round() - window.geometry().loc;
+                regions.push(bbox);
+            }
+        }
+    }
+    let layer_map = layer_map_for_output(output);
+    for layer_surface in layer_map.layers() {
+        let private = layer_rule(layer_surface.wl_surface()).map_or(false, |rule| rule.private);
+        if let (true, Some(geometry)) = (private, layer_map.layer_geometry(layer_surface)) {
+            let mut bbox = layer_surface.bbox_with_popups();
+            bbox.loc += geometry.loc;
+            regions.push(bbox);
+        }
+    }
+
+    let scale = output.current_scale().fractional_scale();
+    for region in regions {
+        let loc: Point<i32, Physical> = region.loc.to_f64().to_physical(scale).to_i32_round();
+        let size = region.size.to_physical_precise_round(scale);
+        let x_range = loc.x.max(0) as u32..(loc.x + size.w).max(0) as u32;
+        let y_range = loc.y.max(0) as u32..(loc.y + size.h).max(0) as u32;
+        for y in y_range.clone().take_while(|y| *y < image.height()) {
+            for x in x_range.clone().take_while(|x| *x < image.width()) {
+                image.put_pixel(x, y, PRIVATE_COLOR);
+            }
+        }
+    }
+}
+
+/// Renders `window` without its decorations into an offscreen buffer, scaled down so that
+/// neither edge is longer than `max_size`.
+///
+/// Leaves the renderer unbound, so this has to happen after the frame of the output is submitted.
+pub fn render_thumbnail(
+    renderer: &mut Gles2Renderer,
+    textures: &mut TextureCache,
+    window: &Window,
+    max_size: u32,
+) -> anyhow::Result<RgbaImage> {
+    let geometry = window.geometry();
+    if geometry.size.w <= 0 || geometry.size.h <= 0 {
+        return Err(anyhow!("Window has no size yet"));
+    }
+    let scale = (max_size as f64 / geometry.size.w.max(geometry.size.h) as f64).min(1.0);
+    if window.private() {
+        let size: Size<i32, Physical> = geometry.size.to_physical_precise_round(scale);
+        return Ok(RgbaImage::from_pixel(
+            size.w.max(1) as u32,
+            size.h.max(1) as u32,
+            PRIVATE_COLOR,
+        ));
+    }
+    let result = draw_offscreen(renderer, Some(textures), window, geometry, scale);
+    let result = result.and_then(|(_, size)| {
+        read_framebuffer(renderer, (size.w, size.h).into())
+            .map_err(|err| anyhow!("Failed to render thumbnail: {}", err))
+    });
+
+    let _ = renderer.unbind();
+    result
+}
+
+/// Draws the part of `window` in `region`, relative to its surface origin, into a buffer and
+/// leaves that bound. The buffer is the one of the window in `textures`, or a new one.
+pub fn draw_offscreen(
+    renderer: &mut Gles2Renderer,
+    textures: Option<&mut TextureCache>,
+    window: &Window,
+    region: Rectangle<i32, Logical>,
+    scale: f64,
+) -> anyhow::Result<(Gles2Texture, Size<i32, Physical>)> {
+    let size: Size<i32, Physical> = region.size.to_physical_precise_round(scale);
+    let size = Size::<i32, Physical>::from((size.w.max(1), size.h.max(1)));
+    let location =
+        Point::<f64, Physical>::from((0.0, 0.0)) - region.loc.to_f64().to_physical(scale);
+    let damage = [Rectangle::from_loc_and_size((0, 0), size)];
+
+    let texture: Gles2Texture = match textures {
+        Some(textures) => textures.window_buffer(renderer, window.id(), size)?,
+        None => renderer
+            .create_buffer((size.w, size.h).into())
+            .map_err(|err| anyhow!("Failed to create offscreen buffer: {}", err))?,
+    };
+    renderer
+        .bind(texture.clone())
+        .map_err(|err| anyhow!("Failed to bind offscreen buffer: {}", err))?;
+
+    renderer
+        .render(size, Transform::Normal, |renderer, frame| {
+            frame.clear([0.0, 0.0, 0.0, 0.0], &damage)?;
+            draw_window(
+                renderer,
+                frame,
+                window,
+                scale,
+                location,
+                &damage,
+                &slog_scope::logger(),
+            )
+        })
+        .map_err(|err| anyhow!("Failed to render window: {}", err))
+        .and_then(|drawn| drawn.map_err(|err| anyhow!("Failed to render window: {}", err)))?;
+    Ok((texture, size))
+}
+
+fn read_framebuffer<R>(renderer: &mut R, size: Size<i32, Buffer>) -> anyhow::Result<RgbaImage>
+where
+    R: ExportMem,
+{
+    let region = Rectangle::<i32, Buffer>::from_loc_and_size((0, 0), size);
+
+    let mapping = renderer
+        .copy_framebuffer(region)
+        .map_err(|err| anyhow!("Failed to copy framebuffer: {}", err))?;
+    let flipped = mapping.flipped();
+    let data = renderer
+        .map_texture(&mapping)
+        .map_err(|err| anyhow!("Failed to map framebuffer: {}", err))?;
+
+    let mut image = RgbaImage::from_raw(size.w as u32, size.h as u32, data.to_vec())
+        .ok_or_else(|| anyhow!("Framebuffer size does not match output mode"))?;
+    if flipped {
+        image::imageops::flip_vertical_in_place(&mut image);
+    }
+    Ok(image)
+}
+
 pub fn render_output<R>(
     renderer: &mut R,
     age: u8,
@@ -74,11 +362,26 @@ where
     <R as Renderer>::TextureId: Clone + 'static,
     CustomElem: RenderElement<R>,
 {
-    let workspace = state.shell.active_workspace();
-    let is_fullscreen = workspace.get_fullscreen(output).cloned();
+    if state.shell.transition().is_some() {
+        return render_transition(renderer, state, output, hardware_cursor);
+    }
 
-    if let Some(window) = is_fullscreen {
-        render_window(renderer, window, state, output, hardware_cursor)
+    let workspace = state.shell.active_workspace();
+    if let Some(filling) = workspace.get_filling(output) {
+        let Magnification {
+            window,
+            zoom,
+            location,
+        } = filling;
+        render_window(
+            renderer,
+            window,
+            zoom,
+            location,
+            state,
+            output,
+            hardware_cursor,
+        )
     } else {
         render_desktop(renderer, age, state, output, hardware_cursor)
     }
@@ -104,6 +407,23 @@ where
         output,
         hardware_cursor,
     ));
+    custom_elements.extend(background_elements(renderer.as_gles2(), state, output));
+    custom_elements.extend(blur_elements(renderer.as_gles2(), state, output));
+    custom_elements.extend(single_pixel_elements(
+        state.shell.active_workspace(),
+        output,
+    ));
+    custom_elements.extend(shade_elements(renderer.as_gles2(), state, output));
+    if let Some(geometry) = state.shell.active_workspace().space.output_geometry(output) {
+        custom_elements.extend(state.closing_windows.elements(geometry));
+    }
+    custom_elements.extend(drop_zone_elements(&state.seats, output));
+    custom_elements.extend(move_grab_elements(&state.seats, output));
+    custom_elements.extend(state.shell.auto_hide.elements(output));
+    custom_elements.extend(overlay_elements(renderer.as_gles2(), state, output));
+    custom_elements.extend(plugin_elements(state, output));
+    custom_elements.extend(switcher_elements(renderer.as_gles2(), state, output));
+    custom_elements.extend(idle_element(state, output));
 
     state.shell.active_workspace_mut().space.render_output(
         renderer,
@@ -114,10 +434,13 @@ where
     )
 }
 
-/// Renders a Wayland window
+/// Renders a Wayland window filling the output, scaled by `zoom` with the origin of its surface
+/// at `location`
 fn render_window<R>(
     renderer: &mut R,
     window: Window,
+    zoom: f64,
+    location: Point<f64, Logical>,
     state: &mut CommonState,
     output: &Output,
     hardware_cursor: bool,
@@ -130,6 +453,8 @@ where
     let transform = Transform::from(output.current_transform());
     let mode = output.current_mode().unwrap();
     let scale = output.current_scale().fractional_scale();
+    let window_scale = scale * zoom;
+    let window_loc = location.to_physical(scale);
 
     let mut custom_elements = Vec::<CustomElem>::new();
 
@@ -139,10 +464,13 @@ where
         output,
         hardware_cursor,
     ));
+    custom_elements.extend(overlay_elements(renderer.as_gles2(), state, output));
+    custom_elements.extend(switcher_elements(renderer.as_gles2(), state, output));
+    custom_elements.extend(idle_element(state, output));
 
     renderer
         .render(mode.size, transform, |renderer, frame| {
-            let mut damage = window.accumulated_damage((0.0, 0.0), scale, None);
+            let mut damage = window.accumulated_damage(window_loc, window_scale, None);
             frame.clear(
                 CLEAR_COLOR,
                 &[Rectangle::from_loc_and_size((0, 0), mode.size)],
@@ -151,8 +479,8 @@ where
                 renderer,
                 frame,
                 &window,
-                scale,
-                (0.0, 0.0),
+                window_scale,
+                window_loc,
                 &[Rectangle::from_loc_and_size((0, 0), mode.size)],
                 &slog_scope::logger(),
             )?;
@@ -160,8 +488,8 @@ where
                 renderer,
                 frame,
                 &window,
-                scale,
-                (0.0, 0.0),
+                window_scale,
+                window_loc,
                 &[Rectangle::from_loc_and_size((0, 0), mode.size)],
                 &slog_scope::logger(),
             )?;
@@ -221,3 +549,163 @@ where
         .and_then(std::convert::identity)
         .map_err(RenderError::<R>::Rendering)
 }
+
+/// Renders a frame of the slide between workspaces, the windows of both move while the
+/// background and layer surfaces stay in place
+fn render_transition<R>(
+    renderer: &mut R,
+    state: &mut CommonState,
+    output: &Output,
+    hardware_cursor: bool,
+) -> Result<Option<Vec<Rectangle<i32, Physical>>>, RenderError<R>>
+where
+    R: Renderer + ImportAll + AsGles2Renderer,
+    <R as Renderer>::TextureId: Clone + 'static,
+    CustomElem: RenderElement<R>,
+{
+    let transform = Transform::from(output.current_transform());
+    let mode = output.current_mode().unwrap();
+    let scale = output.current_scale().fractional_scale();
+    let output_geometry = Rectangle::from_loc_and_size((0, 0), output.geometry().size);
+
+    let below = background_elements(renderer.as_gles2(), state, output);
+    let mut above = Vec::<CustomElem>::new();
+    above.extend(cursor_custom_elements(
+        renderer,
+        state,
+        output,
+        hardware_cursor,
+    ));
+    above.extend(overlay_elements(renderer.as_gles2(), state, output));
+    above.extend(switcher_elements(renderer.as_gles2(), state, output));
+    above.extend(idle_element(state, output));
+
+    let transition = match state.shell.transition() {
+        Some(transition) => transition,
+        None => return Ok(None),
+    };
+    let incoming = state.shell.active_workspace();
+    let spaces = transition
+        .from
+        .map(|from| (&from.space, transition.from_offset))
+        .into_iter()
+        .chain(std::iter::once((&incoming.space, transition.to_offset)));
+    // Bottom to top, with the surface origins relative to the output
+    let mut windows = Vec::new();
+    for (space, offset) in spaces {
+        let offset = Point::<f64, Logical>::from((offset * output_geometry.size.w as f64, 0.0));
+        windows.extend(space.windows().filter_map(|window| {
+            let origin =
+                space.window_location(window)? - window.geometry().loc - output.current_location();
+            let bbox = window.bbox();
+            let visible = Rectangle::from_loc_and_size(origin + bbox.loc, bbox.size)
+                .overlaps(output_geometry);
+            let location = (origin.to_f64() + offset).to_physical(scale);
+            visible.then(|| (window.clone(), location))
+        }));
+    }
+
+    let full_damage = Rectangle::from_loc_and_size((0, 0), mode.size);
+    renderer
+        .render(mode.size, transform, |renderer, frame| {
+            frame.clear(CLEAR_COLOR, &[full_damage])?;
+            for elem in &below {
+                draw_element(renderer, frame, elem, scale)?;
+            }
+            draw_layer(renderer, frame, output, WlrLayer::Background, scale)?;
+            draw_layer(renderer, frame, output, WlrLayer::Bottom, scale)?;
+            for (window, location) in &windows {
+                draw_window(
+                    renderer,
+                    frame,
+                    window,
+                    scale,
+                    *location,
+                    &[full_damage],
+                    &slog_scope::logger(),
+                )?;
+                draw_window_popups(
+                    renderer,
+                    frame,
+                    window,
+                    scale,
+                    *location,
+                    &[full_damage],
+                    &slog_scope::logger(),
+                )?;
+            }
+            draw_layer(renderer, frame, output, WlrLayer::Top, scale)?;
+            draw_layer(renderer, frame, output, WlrLayer::Overlay, scale)?;
+            for elem in &above {
+                draw_element(renderer, frame, elem, scale)?;
+            }
+            // Everything moves, so the whole output changes every frame
+            Ok(Some(vec![full_damage]))
+        })
+        .and_then(std::convert::identity)
+        .map_err(RenderError::<R>::Rendering)
+}
+
+/// Draws the layer surfaces of `output` on `layer` with their popups
+fn draw_layer<R>(
+    renderer: &mut R,
+    frame: &mut <R as Renderer>::Frame,
+    output: &Output,
+    layer: WlrLayer,
+    scale: f64,
+) -> Result<(), <R as Renderer>::Error>
+where
+    R: Renderer + ImportAll,
+    <R as Renderer>::TextureId: 'static,
+{
+    let layer_map = layer_map_for_output(output);
+    for layer_surface in layer_map.layers_on(layer) {
+        let geo = layer_map.layer_geometry(layer_surface).unwrap();
+        let location = geo.loc.to_f64().to_physical(scale);
+        let damage = [Rectangle::from_loc_and_size(
+            (0, 0),
+            geo.size.to_physical_precise_round(scale),
+        )];
+        draw_layer_surface(
+            renderer,
+            frame,
+            layer_surface,
+            scale,
+            location,
+            &damage,
+            &slog_scope::logger(),
+        )?;
+        draw_layer_popups(
+            renderer,
+            frame,
+            layer_surface,
+            scale,
+            location,
+            &damage,
+            &slog_scope::logger(),
+        )?;
+    }
+    Ok(())
+}
+
+fn draw_element<R>(
+    renderer: &mut R,
+    frame: &mut <R as Renderer>::Frame,
+    elem: &CustomElem,
+    scale: f64,
+) -> Result<(), <R as Renderer>::Error>
+where
+    R: Renderer + ImportAll,
+    <R as Renderer>::TextureId: 'static,
+    CustomElem: RenderElement<R>,
+{
+    let geo = elem.geometry(scale);
+    elem.draw(
+        renderer,
+        frame,
+        scale,
+        elem.location(scale),
+        &[Rectangle::from_loc_and_size((0, 0), geo.size)],
+        &slog_scope::logger(),
+    )
+}
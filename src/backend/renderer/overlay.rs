@@ -0,0 +1,486 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::mpsc,
+};
+
+use anyhow::ensure;
+use calloop::{
+    channel::{channel, Event, Sender},
+    LoopHandle,
+};
+use image::{
+    imageops,
+    io::{Limits, Reader},
+    RgbaImage,
+};
+use serde::Deserialize;
+use smithay::{
+    backend::renderer::{
+        gles2::{Gles2Renderer, Gles2Texture},
+        Frame, ImportMem, Renderer,
+    },
+    desktop::space::{RenderElement, RenderZindex, SpaceOutputTuple},
+    utils::{Logical, Physical, Point, Rectangle, Scale, Size, Transform},
+    wayland::output::Output,
+};
+
+use crate::state::{CommonState, LoopData};
+
+use super::{
+    fonts::{FontPath, Fonts},
+    raster::{draw_text, fill_rect, premultiply},
+    textures::texture_bytes,
+    CustomElem,
+};
+
+/// Image files larger than this are not decoded
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+/// Images wider or higher than this are not decoded
+const MAX_DIMENSION: u32 = 4096;
+
+/// A part of an overlay. Positions and sizes are logical and relative to the overlay.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum OverlayNode {
+    Rect {
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        /// RGBA
+        color: [u8; 4],
+    },
+    /// One line of text, `y` is the top of the line
+    #[serde(rename_all = "camelCase")]
+    Text {
+        x: i32,
+        y: i32,
+        text: String,
+        size: f32,
+        color: [u8; 4],
+        /// Font file, picked by fontconfig if not set
+        font: Option<PathBuf>,
+        /// Cut off after this width, at the edge of the overlay if not set
+        max_width: Option<i32>,
+    },
+    /// A PNG scaled to the given size
+    Image {
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        path: PathBuf,
+    },
+}
+
+/// A rectangle on an output the runtime draws into, above windows
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Overlay {
+    /// Logical position on the output
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    /// Drawn in order, later ones on top
+    #[serde(default)]
+    pub nodes: Vec<OverlayNode>,
+}
+
+struct OverlayLayer {
+    output: String,
+    name: String,
+    overlay: Overlay,
+    /// Changes with every update, so the space damages the whole overlay
+    id: usize,
+    /// The scale and size it was drawn for, and the texture
+    texture: Option<((f64, Size<i32, Logical>), Gles2Texture, Size<i32, Physical>)>,
+}
+
+/// Overlays set by the runtime, and what drawing them needs to keep around
+#[derive(Default)]
+pub struct Overlays {
+    /// Stacked in the order they were first set
+    layers: Vec<OverlayLayer>,
+    updates: usize,
+    /// `None` for images that failed to decode
+    images: HashMap<PathBuf, Option<RgbaImage>>,
+    loading: HashSet<PathBuf>,
+    /// Where images to decode go, set up the first time one is used
+    requests: Option<mpsc::Sender<PathBuf>>,
+}
+
+impl Overlays {
+    /// Replaces the overlay called `name` on the output called `output`, or removes it
+    fn set(&mut self, output: String, name: String, overlay: Option<Overlay>) {
+        let position = self
+            .layers
+            .iter()
+            .position(|layer| layer.output == output && layer.name == name);
+        let overlay = match (overlay, position) {
+            (Some(overlay), _) => overlay,
+            (None, Some(position)) => {
+                self.layers.remove(position);
+                return;
+            }
+            (None, None) => return,
+        };
+
+        self.updates += 1;
+        let mut hasher = DefaultHasher::new();
+        ("overlay", &output, &name, self.updates).hash(&mut hasher);
+        let layer = OverlayLayer {
+            output,
+            name,
+            overlay,
+            id: hasher.finish() as usize,
+            texture: None,
+        };
+        match position {
+            Some(position) => self.layers[position] = layer,
+            None => self.layers.push(layer),
+        }
+
+        // Only the images still shown are worth keeping decoded
+        let layers = &self.layers;
+        self.images.retain(|path, _| {
+            layers.iter().any(|layer| {
+                layer.overlay.nodes.iter().any(
+                    |node| matches!(node, OverlayNode::Image { path: used, .. } if used == path),
+                )
+            })
+        });
+    }
+
+    /// Starts decoding the image at `path`, unless it was already
+    fn load_image(&mut self, handle: &LoopHandle<'static, LoopData>, path: &Path) {
+        if self.images.contains_key(path) || self.loading.contains(path) {
+            return;
+        }
+        let sent = self
+            .requests(handle)
+            .map(|requests| requests.send(path.to_path_buf()).is_ok())
+            .unwrap_or(false);
+        if sent {
+            self.loading.insert(path.to_path_buf());
+        }
+    }
+
+    fn requests(
+        &mut self,
+        handle: &LoopHandle<'static, LoopData>,
+    ) -> Option<&mpsc::Sender<PathBuf>> {
+        if self.requests.is_none() {
+            let (sender, decoded) = channel::<(PathBuf, Option<RgbaImage>)>();
+            let inserted = handle.insert_source(decoded, |event, _, data| {
+                if let Event::Msg((path, image)) = event {
+                    data.state.common.overlays.image_decoded(path, image);
+                }
+            });
+            if inserted.is_err() {
+                slog_scope::warn!("Failed to register the overlay image channel");
+                return None;
+            }
+            let (requests, paths) = mpsc::channel();
+            let spawned = std::thread::Builder::new()
+                .name("overlay-images".to_string())
+                .spawn(move || decode_thread(paths, sender));
+            match spawned {
+                Ok(_) => self.requests = Some(requests),
+                Err(err) => slog_scope::warn!("Failed to start decoding overlay images: {}", err),
+            }
+        }
+        self.requests.as_ref()
+    }
+
+    /// Keeps an image a worker decoded, if it is still used, and draws what uses it again
+    fn image_decoded(&mut self, path: PathBuf, image: Option<RgbaImage>) {
+        self.loading.remove(&path);
+        let mut used = false;
+        for layer in &mut self.layers {
+            let uses = layer.overlay.nodes.iter().any(
+                |node| matches!(node, OverlayNode::Image { path: shown, .. } if *shown == path),
+            );
+            if uses {
+                layer.texture = None;
+                used = true;
+            }
+        }
+        if used {
+            self.images.insert(path, image);
+        }
+    }
+
+    /// Draws the overlays showing text in the font at `path` again, once it was loaded
+    pub(super) fn font_loaded(&mut self, path: &FontPath) {
+        for layer in &mut self.layers {
+            let uses = layer
+                .overlay
+                .nodes
+                .iter()
+                .any(|node| matches!(node, OverlayNode::Text { font, .. } if font == path));
+            if uses {
+                layer.texture = None;
+            }
+        }
+    }
+
+    /// Removes every overlay
+    pub fn clear(&mut self) {
+        self.layers.clear();
+        self.images.clear();
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.layers
+            .iter()
+            .filter_map(|layer| layer.texture.as_ref())
+            .map(|(_, _, size)| texture_bytes(*size))
+            .sum()
+    }
+}
+
+/// The overlays of `output` as elements, drawing the ones that changed
+pub fn overlay_elements(
+    renderer: &mut Gles2Renderer,
+    state: &mut CommonState,
+    output: &Output,
+) -> Vec<CustomElem> {
+    let scale = output.current_scale().fractional_scale();
+    let output_name = output.name();
+    let output_size = output
+        .current_mode()
+        .map(|mode| mode.size.to_f64().to_logical(scale).to_i32_round())
+        .unwrap_or_default();
+    let overlays = &mut state.overlays;
+
+    let mut elements = Vec::new();
+    for layer in overlays
+        .layers
+        .iter_mut()
+        .filter(|layer| layer.output == output_name)
+    {
+        // Never larger than the output
+        let overlay = &layer.overlay;
+        let size = Size::<i32, Logical>::from((
+            overlay.width.clamp(0, output_size.w),
+            overlay.height.clamp(0, output_size.h),
+        ));
+        let current = matches!(&layer.texture, Some((drawn, _, _)) if *drawn == (scale, size));
+        if !current {
+            let image = draw_overlay(overlay, size, &state.fonts, &overlays.images, scale);
+            let texture_size =
+                Size::<i32, Physical>::from((image.width() as i32, image.height() as i32));
+            match renderer.import_memory(
+                image.as_raw(),
+                (texture_size.w, texture_size.h).into(),
+                false,
+            ) {
+                Ok(texture) => layer.texture = Some(((scale, size), texture, texture_size)),
+                Err(err) => {
+                    slog_scope::warn!("Failed to upload overlay {}: {}", layer.name, err);
+                    continue;
+                }
+            }
+        }
+
+        let (_, texture, texture_size) = layer.texture.clone().unwrap();
+        elements.push(CustomElem::from(OverlayElement {
+            id: layer.id,
+            texture,
+            texture_size,
+            location: Point::from((overlay.x as f64, overlay.y as f64)),
+            size: size.to_f64(),
+            redrawn: !current,
+        }));
+    }
+    elements
+}
+
+/// Draws the nodes of `overlay` at `scale` into an image of `size`, with premultiplied alpha.
+/// Nodes are cut off at its edges.
+fn draw_overlay(
+    overlay: &Overlay,
+    size: Size<i32, Logical>,
+    fonts: &Fonts,
+    images: &HashMap<PathBuf, Option<RgbaImage>>,
+    scale: f64,
+) -> RgbaImage {
+    let scaled = |v: i32| (v as f64 * scale).round() as i32;
+    let mut image = RgbaImage::new(scaled(size.w).max(1) as u32, scaled(size.h).max(1) as u32);
+
+    for node in &overlay.nodes {
+        match node {
+            OverlayNode::Rect {
+                x,
+                y,
+                width,
+                height,
+                color,
+            } => fill_rect(
+                &mut image,
+                (scaled(*x), scaled(*y)),
+                (scaled(*width), scaled(*height)),
+                *color,
+            ),
+            OverlayNode::Text {
+                x,
+                y,
+                text,
+                size,
+                color,
+                font,
+                max_width,
+            } => {
+                if let Some(font) = fonts.get(font) {
+                    // NaN ends up as 1
+                    let size = (size * scale as f32).max(1.0).min(image.height() as f32);
+                    let ascent = font
+                        .horizontal_line_metrics(size)
+                        .map(|metrics| metrics.ascent)
+                        .unwrap_or(size);
+                    let max_x = max_width
+                        .map(|width| scaled(x.saturating_add(width)) as f32)
+                        .unwrap_or(f32::MAX)
+                        .min(image.width() as f32);
+                    let pen = (scaled(*x) as f32, scaled(*y) as f32 + ascent);
+                    draw_text(&mut image, font, text, size, pen, max_x, *color);
+                }
+            }
+            OverlayNode::Image {
+                x,
+                y,
+                width,
+                height,
+                path,
+            } => {
+                if let Some(source) = images.get(path).and_then(Option::as_ref) {
+                    let (width, height) = (
+                        scaled(*width).clamp(1, image.width() as i32),
+                        scaled(*height).clamp(1, image.height() as i32),
+                    );
+                    let resized =
+                        imageops::resize(source, width as u32, height as u32, imageops::Triangle);
+                    imageops::overlay(&mut image, &resized, scaled(*x) as i64, scaled(*y) as i64);
+                }
+            }
+        }
+    }
+
+    premultiply(&mut image);
+    image
+}
+
+fn decode_thread(paths: mpsc::Receiver<PathBuf>, sender: Sender<(PathBuf, Option<RgbaImage>)>) {
+    for path in paths {
+        let image = decode(&path)
+            .map_err(|err| slog_scope::warn!("Failed to load {}: {}", path.display(), err))
+            .ok();
+        // The compositor is gone if this fails
+        if sender.send((path, image)).is_err() {
+            return;
+        }
+    }
+}
+
+fn decode(path: &Path) -> anyhow::Result<RgbaImage> {
+    let size = fs::metadata(path)?.len();
+    ensure!(
+        size <= MAX_FILE_SIZE,
+        "{} bytes is too large for an overlay image",
+        size
+    );
+    let mut reader = Reader::open(path)?.with_guessed_format()?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_DIMENSION as u64 * MAX_DIMENSION as u64 * 4);
+    reader.limits(limits);
+    Ok(reader.decode()?.to_rgba8())
+}
+
+impl CommonState {
+    /// Replaces the overlay called `name` on the output called `output`, or removes it. The
+    /// fonts and images it uses are loaded in the background, it is drawn without them until
+    /// they are there.
+    pub fn set_overlay(&mut self, output: String, name: String, overlay: Option<Overlay>) {
+        for node in overlay.iter().flat_map(|overlay| &overlay.nodes) {
+            match node {
+                OverlayNode::Text { font, .. } => self.fonts.load(&self.event_loop_handle, font),
+                OverlayNode::Image { path, .. } => {
+                    self.overlays.load_image(&self.event_loop_handle, path)
+                }
+                OverlayNode::Rect { .. } => {}
+            }
+        }
+        self.overlays.set(output, name, overlay);
+    }
+}
+
+/// An overlay of the runtime, above windows and below the switcher
+pub struct OverlayElement {
+    id: usize,
+    texture: Gles2Texture,
+    texture_size: Size<i32, Physical>,
+    location: Point<f64, Logical>,
+    size: Size<f64, Logical>,
+    /// Whether the texture was drawn for this frame
+    redrawn: bool,
+}
+
+impl RenderElement<Gles2Renderer> for OverlayElement {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn location(&self, scale: impl Into<Scale<f64>>) -> Point<f64, Physical> {
+        self.location.to_physical(scale)
+    }
+
+    fn geometry(&self, scale: impl Into<Scale<f64>>) -> Rectangle<i32, Physical> {
+        let scale = scale.into();
+        Rectangle::from_loc_and_size(
+            self.location.to_physical(scale).to_i32_round(),
+            self.size.to_physical(scale).to_i32_round(),
+        )
+    }
+
+    fn accumulated_damage(
+        &self,
+        scale: impl Into<Scale<f64>>,
+        _for_values: Option<SpaceOutputTuple<'_, '_>>,
+    ) -> Vec<Rectangle<i32, Physical>> {
+        // Every update comes with a new id, which the space damages as a whole anyway. Fullscreen
+        // windows are drawn without the space and need to hear about it.
+        if !self.redrawn {
+            return Vec::new();
+        }
+        let geometry = RenderElement::<Gles2Renderer>::geometry(self, scale);
+        vec![Rectangle::from_loc_and_size((0, 0), geometry.size)]
+    }
+
+    fn draw(
+        &self,
+        _renderer: &mut Gles2Renderer,
+        frame: &mut <Gles2Renderer as Renderer>::Frame,
+        scale: impl Into<Scale<f64>>,
+        location: Point<f64, Physical>,
+        damage: &[Rectangle<i32, Physical>],
+        _log: &slog::Logger,
+    ) -> Result<(), <Gles2Renderer as Renderer>::Error> {
+        let src = Rectangle::from_loc_and_size(
+            (0.0, 0.0),
+            (self.texture_size.w as f64, self.texture_size.h as f64),
+        );
+        let dst = Rectangle::from_loc_and_size(location, self.size.to_physical(scale));
+        frame.render_texture_from_to(&self.texture, src, dst, damage, Transform::Normal, 1.0)
+    }
+
+    fn z_index(&self) -> u8 {
+        RenderZindex::Top as u8
+    }
+}
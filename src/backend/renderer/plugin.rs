@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use smithay::{
+    backend::renderer::{gles2::Gles2Renderer, Renderer},
+    desktop::space::{RenderElement, RenderZindex, SpaceOutputTuple},
+    utils::{Logical, Physical, Point, Rectangle, Scale, Size},
+    wayland::output::Output,
+};
+
+use crate::{
+    plugins::RenderHook,
+    state::{output::OutputExt, CommonState},
+};
+
+use super::CustomElem;
+
+/// The render hooks of native plugins for `output`, each covering all of it
+pub fn plugin_elements(state: &CommonState, output: &Output) -> Vec<CustomElem> {
+    let size = output.geometry().size.to_f64();
+    state
+        .native_plugins
+        .render_hooks()
+        .into_iter()
+        .map(|(id, hook)| {
+            CustomElem::from(PluginElement {
+                id,
+                hook,
+                output: output.name(),
+                size,
+            })
+        })
+        .collect()
+}
+
+/// Where a plugin draws with GL directly, above overlays and below the switcher
+pub struct PluginElement {
+    /// Changes when the plugin asks for a redraw, so the space damages the whole output
+    id: usize,
+    hook: RenderHook,
+    output: String,
+    size: Size<f64, Logical>,
+}
+
+impl RenderElement<Gles2Renderer> for PluginElement {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn location(&self, _scale: impl Into<Scale<f64>>) -> Point<f64, Physical> {
+        Point::from((0.0, 0.0))
+    }
+
+    fn geometry(&self, scale: impl Into<Scale<f64>>) -> Rectangle<i32, Physical> {
+        Rectangle::from_loc_and_size((0, 0), self.size.to_physical(scale).to_i32_round())
+    }
+
+    fn accumulated_damage(
+        &self,
+        _scale: impl Into<Scale<f64>>,
+        _for_values: Option<SpaceOutputTuple<'_, '_>>,
+    ) -> Vec<Rectangle<i32, Physical>> {
+        Vec::new()
+    }
+
+    fn draw(
+        &self,
+        _renderer: &mut Gles2Renderer,
+        _frame: &mut <Gles2Renderer as Renderer>::Frame,
+        scale: impl Into<Scale<f64>>,
+        location: Point<f64, Physical>,
+        damage: &[Rectangle<i32, Physical>],
+        _log: &slog::Logger,
+    ) -> Result<(), <Gles2Renderer as Renderer>::Error> {
+        if damage.is_empty() {
+            return Ok(());
+        }
+        let scale = scale.into();
+        let location = location.to_i32_round();
+        let size = self.size.to_physical(scale).to_i32_round();
+        let area = (location.x, location.y, size.w, size.h);
+        // Relative to the element, like the area they are passed on in
+        let damage = damage
+            .iter()
+            .map(|rect| {
+                let loc = rect.loc + location;
+                (loc.x, loc.y, rect.size.w, rect.size.h)
+            })
+            .collect::<Vec<_>>();
+        // The frame keeps the context of the renderer current while elements are drawn
+        self.hook.render(&self.output, area, scale.x, &damage);
+        Ok(())
+    }
+
+    fn z_index(&self) -> u8 {
+        RenderZindex::Top as u8
+    }
+}
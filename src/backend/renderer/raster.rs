@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use fontdue::{Font, FontSettings};
+use image::{Rgba, RgbaImage};
+
+/// Loads the font at `path`, or the one fontconfig picks for sans-serif
+pub fn load_font(path: Option<&Path>) -> Option<Font> {
+    let path = path.map(Path::to_path_buf).or_else(default_font)?;
+    let data = fs::read(&path)
+        .map_err(|err| slog_scope::warn!("Failed to read {}: {}", path.display(), err))
+        .ok()?;
+    Font::from_bytes(data, FontSettings::default())
+        .map_err(|err| slog_scope::warn!("Failed to load {}: {}", path.display(), err))
+        .ok()
+}
+
+fn default_font() -> Option<PathBuf> {
+    let output = Command::new("fc-match")
+        .args(["--format", "%{file}", "sans-serif"])
+        .output()
+        .ok()?;
+    let path = PathBuf::from(String::from_utf8(output.stdout).ok()?);
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Blends `color` over one pixel, with `alpha` from 0 to 255 on top of the color's own
+fn blend(Rgba(pixel): &mut Rgba<u8>, color: [u8; 4], alpha: u32) {
+    let alpha = alpha * color[3] as u32 / 255;
+    for (channel, color) in pixel.iter_mut().zip(color).take(3) {
+        *channel = ((color as u32 * alpha + *channel as u32 * (255 - alpha)) / 255) as u8;
+    }
+    pixel[3] = (alpha + pixel[3] as u32 * (255 - alpha) / 255) as u8;
+}
+
+/// Draws a single line of text starting at `pen`, cut off where it would cross `max_x`
+pub fn draw_text(
+    image: &mut RgbaImage,
+    font: &Font,
+    text: &str,
+    size: f32,
+    pen: (f32, f32),
+    max_x: f32,
+    color: [u8; 4],
+) {
+    let (mut x, baseline) = pen;
+    for character in text.chars() {
+        let (metrics, coverage) = font.rasterize(character, size);
+        if x + metrics.advance_width > max_x {
+            break;
+        }
+        let left = x as i32 + metrics.xmin;
+        let top = baseline as i32 - metrics.height as i32 - metrics.ymin;
+        for (i, coverage) in coverage.iter().enumerate() {
+            let px = left + (i % metrics.width.max(1)) as i32;
+            let py = top + (i / metrics.width.max(1)) as i32;
+            if px < 0 || py < 0 || px >= image.width() as i32 || py >= image.height() as i32 {
+                continue;
+            }
+            blend(
+                image.get_pixel_mut(px as u32, py as u32),
+                color,
+                *coverage as u32,
+            );
+        }
+        x += metrics.advance_width;
+    }
+}
+
+/// Blends a rectangle of `color` over `image`, clipped to it
+pub fn fill_rect(image: &mut RgbaImage, (x, y): (i32, i32), (w, h): (i32, i32), color: [u8; 4]) {
+    let width = i32::try_from(image.width()).unwrap_or(i32::MAX);
+    let height = i32::try_from(image.height()).unwrap_or(i32::MAX);
+    let (left, top) = (x.clamp(0, width) as u32, y.clamp(0, height) as u32);
+    let right = x.saturating_add(w.max(0)).clamp(0, width) as u32;
+    let bottom = y.saturating_add(h.max(0)).clamp(0, height) as u32;
+    for py in top..bottom {
+        for px in left..right {
+            blend(image.get_pixel_mut(px, py), color, 255);
+        }
+    }
+}
+
+/// Textures are uploaded with premultiplied alpha, the drawing above works on straight alpha
+pub fn premultiply(image: &mut RgbaImage) {
+    for Rgba(pixel) in image.pixels_mut() {
+        let alpha = pixel[3] as u32;
+        for channel in pixel.iter_mut().take(3) {
+            *channel = (*channel as u32 * alpha / 255) as u8;
+        }
+    }
+}
@@ -0,0 +1,216 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use fontdue::Font;
+use image::RgbaImage;
+use smithay::{
+    backend::renderer::{
+        gles2::{Gles2Renderer, Gles2Texture},
+        Frame, ImportMem, Renderer,
+    },
+    desktop::space::{RenderElement, RenderZindex, SpaceOutputTuple},
+    utils::{Logical, Physical, Point, Rectangle, Scale, Size, Transform},
+    wayland::output::Output,
+};
+
+use crate::{shell::window::WindowExt, state::CommonState};
+
+use super::{
+    raster::{draw_text, fill_rect, premultiply},
+    textures::texture_bytes,
+    CustomElem,
+};
+
+/// Logical height of the bar a shaded window collapses to
+pub const SHADE_HEIGHT: i32 = 28;
+const BACKGROUND: [u8; 4] = [0x30, 0x30, 0x30, 0xff];
+const TEXT: [u8; 4] = [0xee, 0xee, 0xee, 0xff];
+const FONT_SIZE: f32 = 14.0;
+const PADDING: i32 = 8;
+/// Above every window, including picture-in-picture and grabbed ones, below top layer surfaces
+const SHADE_INDEX: u8 = RenderZindex::Shell as u8 + 3;
+
+struct ShadeBar {
+    /// Title, width and scale it was drawn for
+    drawn: (String, i32, u64),
+    id: usize,
+    texture: Gles2Texture,
+    size: Size<i32, Physical>,
+}
+
+/// Bars shaded windows are drawn as, by window id
+#[derive(Default)]
+pub struct ShadeBars {
+    bars: HashMap<usize, ShadeBar>,
+}
+
+impl ShadeBars {
+    /// Draws every bar again, e.g. once the font was loaded
+    pub fn redraw(&mut self) {
+        self.bars.clear();
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bars.values().map(|bar| texture_bytes(bar.size)).sum()
+    }
+}
+
+/// Bars of the shaded windows on `output`, drawing the ones whose title or size changed
+pub fn shade_elements(
+    renderer: &mut Gles2Renderer,
+    state: &mut CommonState,
+    output: &Output,
+) -> Vec<CustomElem> {
+    let space = &state.shell.active_workspace().space;
+    let output_geometry = match space.output_geometry(output) {
+        Some(geometry) => geometry,
+        None => return Vec::new(),
+    };
+    let shaded = space
+        .windows()
+        .filter_map(|window| window.shaded().map(|location| (window.clone(), location)))
+        .collect::<Vec<_>>();
+    let font = state.fonts.get(&None);
+    let bars = &mut state.shade_bars;
+    bars.bars
+        .retain(|id, _| shaded.iter().any(|(window, _)| window.id() == *id));
+
+    let scale = output.current_scale().fractional_scale();
+    let mut elements = Vec::new();
+    for (window, location) in shaded {
+        let geometry =
+            Rectangle::from_loc_and_size(location, (window.geometry().size.w, SHADE_HEIGHT));
+        if !geometry.overlaps(output_geometry) {
+            continue;
+        }
+
+        let drawn = (
+            window.title().unwrap_or_default(),
+            geometry.size.w,
+            scale.to_bits(),
+        );
+        let current = matches!(bars.bars.get(&window.id()), Some(bar) if bar.drawn == drawn);
+        if !current {
+            let image = draw_bar(&drawn.0, geometry.size.w, font, scale);
+            let size = Size::<i32, Physical>::from((image.width() as i32, image.height() as i32));
+            let texture =
+                match renderer.import_memory(image.as_raw(), (size.w, size.h).into(), false) {
+                    Ok(texture) => texture,
+                    Err(err) => {
+                        slog_scope::warn!("Failed to upload shade bar: {}", err);
+                        continue;
+                    }
+                };
+            let mut hasher = DefaultHasher::new();
+            ("shade", window.id(), &drawn).hash(&mut hasher);
+            bars.bars.insert(
+                window.id(),
+                ShadeBar {
+                    drawn,
+                    id: hasher.finish() as usize,
+                    texture,
+                    size,
+                },
+            );
+        }
+
+        let bar = &bars.bars[&window.id()];
+        elements.push(CustomElem::from(ShadeElement {
+            id: bar.id,
+            texture: bar.texture.clone(),
+            texture_size: bar.size,
+            location: (geometry.loc - output_geometry.loc).to_f64(),
+            size: geometry.size.to_f64(),
+        }));
+    }
+    elements
+}
+
+/// The title on a plain bar, with premultiplied alpha
+fn draw_bar(title: &str, width: i32, font: Option<&Font>, scale: f64) -> RgbaImage {
+    let scaled = |v: i32| (v as f64 * scale).round() as i32;
+    let (w, h) = (scaled(width).max(1), scaled(SHADE_HEIGHT));
+    let mut image = RgbaImage::new(w as u32, h as u32);
+    fill_rect(&mut image, (0, 0), (w, h), BACKGROUND);
+    if let Some(font) = font {
+        let size = FONT_SIZE * scale as f32;
+        let ascent = font
+            .horizontal_line_metrics(size)
+            .map(|metrics| metrics.ascent)
+            .unwrap_or(size);
+        let pen = (scaled(PADDING) as f32, (h as f32 - size) / 2.0 + ascent);
+        let max_x = (w - scaled(PADDING)) as f32;
+        draw_text(&mut image, font, title, size, pen, max_x, TEXT);
+    }
+    premultiply(&mut image);
+    image
+}
+
+/// The bar of a shaded window
+pub struct ShadeElement {
+    id: usize,
+    texture: Gles2Texture,
+    texture_size: Size<i32, Physical>,
+    location: Point<f64, Logical>,
+    size: Size<f64, Logical>,
+}
+
+impl RenderElement<Gles2Renderer> for ShadeElement {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn location(&self, scale: impl Into<Scale<f64>>) -> Point<f64, Physical> {
+        self.location.to_physical(scale)
+    }
+
+    fn geometry(&self, scale: impl Into<Scale<f64>>) -> Rectangle<i32, Physical> {
+        let scale = scale.into();
+        Rectangle::from_loc_and_size(
+            self.location.to_physical(scale).to_i32_round(),
+            self.size.to_physical(scale).to_i32_round(),
+        )
+    }
+
+    fn accumulated_damage(
+        &self,
+        _scale: impl Into<Scale<f64>>,
+        _for_values: Option<SpaceOutputTuple<'_, '_>>,
+    ) -> Vec<Rectangle<i32, Physical>> {
+        // Redrawn bars get a new id, which the space damages as a whole
+        Vec::new()
+    }
+
+    fn opaque_regions(
+        &self,
+        scale: impl Into<Scale<f64>>,
+    ) -> Option<Vec<Rectangle<i32, Physical>>> {
+        let geometry = RenderElement::<Gles2Renderer>::geometry(self, scale);
+        Some(vec![Rectangle::from_loc_and_size((0, 0), geometry.size)])
+    }
+
+    fn draw(
+        &self,
+        _renderer: &mut Gles2Renderer,
+        frame: &mut <Gles2Renderer as Renderer>::Frame,
+        scale: impl Into<Scale<f64>>,
+        location: Point<f64, Physical>,
+        damage: &[Rectangle<i32, Physical>],
+        _log: &slog::Logger,
+    ) -> Result<(), <Gles2Renderer as Renderer>::Error> {
+        let src = Rectangle::from_loc_and_size(
+            (0.0, 0.0),
+            (self.texture_size.w as f64, self.texture_size.h as f64),
+        );
+        let dst = Rectangle::from_loc_and_size(location, self.size.to_physical(scale));
+        frame.render_texture_from_to(&self.texture, src, dst, damage, Transform::Normal, 1.0)
+    }
+
+    fn z_index(&self) -> u8 {
+        SHADE_INDEX
+    }
+}
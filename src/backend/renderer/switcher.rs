@@ -0,0 +1,273 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::sync::Arc;
+
+use fontdue::Font;
+use image::{imageops, Rgba, RgbaImage};
+use smithay::{
+    backend::renderer::{
+        gles2::{Gles2Renderer, Gles2Texture},
+        Frame, ImportMem, Renderer,
+    },
+    desktop::{
+        space::{RenderElement, RenderZindex, SpaceOutputTuple},
+        Window,
+    },
+    reexports::calloop::LoopHandle,
+    utils::{Logical, Physical, Point, Rectangle, Scale, Size, Transform},
+    wayland::output::Output,
+};
+
+use crate::{
+    appinfo::AppInfoCache,
+    shell::{switcher::SwitcherStyle, window::WindowExt},
+    state::{toplevel_icon::toplevel_icon, CommonState, LoopData},
+};
+
+use super::{
+    icons::Icons,
+    raster::{draw_text, premultiply},
+    textures::texture_bytes,
+    CustomElem,
+};
+
+/// Rows the switcher shows at most, it scrolls to the selected window beyond that
+const MAX_ROWS: u32 = 32;
+
+/// What drawing the switcher needs to keep around between frames
+#[derive(Default)]
+pub struct SwitcherCache {
+    /// The generation it shows, and the texture
+    texture: Option<(usize, Gles2Texture, Size<i32, Physical>)>,
+}
+
+impl SwitcherCache {
+    pub fn bytes(&self) -> usize {
+        self.texture
+            .as_ref()
+            .map_or(0, |(_, _, size)| texture_bytes(*size))
+    }
+
+    /// Titles and icons of `windows`, looked up when the switcher opens instead of while
+    /// drawing it. Icons that were not decoded yet are left out.
+    pub fn entries(
+        &mut self,
+        app_info: &AppInfoCache,
+        icons: &mut Icons,
+        handle: &LoopHandle<'static, LoopData>,
+        windows: &[Window],
+    ) -> Vec<(String, Option<Arc<RgbaImage>>)> {
+        windows
+            .iter()
+            .map(|window| {
+                let info = window.info();
+                // The icon of the window itself wins over the one of its application
+                let own_icon =
+                    toplevel_icon(window.toplevel().wl_surface()).and_then(|icon| icon.image);
+                let app = info.app_id.as_deref().map(|app_id| app_info.lookup(app_id));
+                let app_icon = app.as_ref().and_then(|app| app.icon.clone());
+                let title = info
+                    .title
+                    .or_else(|| app.and_then(|app| app.name))
+                    .or(info.app_id);
+                let icon = own_icon.or_else(|| app_icon.and_then(|path| icons.get(handle, &path)));
+                (title.unwrap_or_default(), icon)
+            })
+            .collect()
+    }
+}
+
+/// The switcher as an element, if it is open on `output`
+pub fn switcher_elements(
+    renderer: &mut Gles2Renderer,
+    state: &mut CommonState,
+    output: &Output,
+) -> Vec<CustomElem> {
+    let switcher_state = &mut state.switcher;
+    let switcher = match &switcher_state.open {
+        Some(switcher) if &switcher.output == output => switcher,
+        Some(_) => return Vec::new(),
+        None => {
+            // Give the texture back
+            switcher_state.cache.texture = None;
+            return Vec::new();
+        }
+    };
+
+    let scale = output.current_scale().fractional_scale();
+    let output_size = output
+        .current_mode()
+        .map(|mode| mode.size)
+        .unwrap_or_default();
+    let generation = switcher_state.generation;
+    let current =
+        matches!(&switcher_state.cache.texture, Some((shown, _, _)) if *shown == generation);
+    if !current {
+        let font = state.fonts.get(&switcher_state.style.font);
+        let image = draw_switcher(
+            &switcher_state.style,
+            font,
+            &switcher.entries,
+            switcher.selected,
+            scale,
+            (output_size.w.max(0) as u32, output_size.h.max(0) as u32),
+        );
+        let size = Size::<i32, Physical>::from((image.width() as i32, image.height() as i32));
+        match renderer.import_memory(image.as_raw(), (size.w, size.h).into(), false) {
+            Ok(texture) => switcher_state.cache.texture = Some((generation, texture, size)),
+            Err(err) => {
+                slog_scope::warn!("Failed to upload window switcher: {}", err);
+                return Vec::new();
+            }
+        }
+    }
+
+    let (_, texture, texture_size) = switcher_state.cache.texture.clone().unwrap();
+    let size = texture_size.to_f64().to_logical(scale);
+    let output_size = output_size.to_f64().to_logical(scale);
+    let location = Point::<f64, Logical>::from((
+        (output_size.w - size.w) / 2.0,
+        (output_size.h - size.h) / 2.0,
+    ));
+
+    vec![CustomElem::from(SwitcherElement {
+        id: generation,
+        texture,
+        texture_size,
+        location,
+        size,
+    })]
+}
+
+/// Draws one row per window, with premultiplied alpha. Sizes are clamped so the switcher
+/// fits on an output of `max_width` by `max_height` physical pixels, rows that don't fit are
+/// left out.
+fn draw_switcher(
+    style: &SwitcherStyle,
+    font: Option<&Font>,
+    entries: &[(String, Option<Arc<RgbaImage>>)],
+    selected: usize,
+    scale: f64,
+    (max_width, max_height): (u32, u32),
+) -> RgbaImage {
+    let scaled = |v: u32| ((v as f64 * scale).round() as u32).max(1);
+    let padding = scaled(style.padding).min(max_width / 8);
+    let icon_size = scaled(style.icon_size)
+        .min(max_width / 4)
+        .min(max_height / 4);
+    let font_size = (style.font_size * scale as f32)
+        .max(1.0)
+        .min((max_height / 4).max(1) as f32);
+    let row_height = icon_size.max((font_size * 1.5) as u32) + padding;
+    // Room for the padding left and right of the icon and at least a pixel of text
+    let width = scaled(style.width)
+        .min(max_width)
+        .max(padding * 3 + icon_size + 1);
+    let rows = (max_height.saturating_sub(padding * 2) / row_height)
+        .max(1)
+        .min(MAX_ROWS)
+        .min(entries.len() as u32) as usize;
+    let height = padding * 2 + row_height * rows as u32;
+    // Scrolled just far enough for the selected window to be shown
+    let first = (selected + 1).saturating_sub(rows);
+
+    let mut image = RgbaImage::from_pixel(width, height, Rgba(style.background));
+    for (row, (title, icon)) in entries.iter().enumerate().skip(first).take(rows) {
+        let top = padding + row_height * (row - first) as u32;
+        if row == selected {
+            for y in top..top + row_height {
+                for x in padding / 2..width - padding / 2 {
+                    image.put_pixel(x, y, Rgba(style.highlight));
+                }
+            }
+        }
+
+        let icon = icon
+            .as_deref()
+            .map(|icon| imageops::resize(icon, icon_size, icon_size, imageops::Triangle));
+        if let Some(icon) = icon {
+            let y = top + (row_height - icon_size) / 2;
+            imageops::overlay(&mut image, &icon, padding as i64, y as i64);
+        }
+
+        if let Some(font) = font {
+            let ascent = font
+                .horizontal_line_metrics(font_size)
+                .map(|metrics| metrics.ascent)
+                .unwrap_or(font_size);
+            let baseline = top as f32 + (row_height as f32 + ascent) / 2.0 - font_size * 0.15;
+            let x = (padding * 2 + icon_size) as f32;
+            let max_x = (width - padding) as f32;
+            draw_text(
+                &mut image,
+                font,
+                title,
+                font_size,
+                (x, baseline),
+                max_x,
+                style.text,
+            );
+        }
+    }
+
+    premultiply(&mut image);
+    image
+}
+
+/// The switcher, drawn above everything but the cursor
+pub struct SwitcherElement {
+    id: usize,
+    texture: Gles2Texture,
+    texture_size: Size<i32, Physical>,
+    location: Point<f64, Logical>,
+    size: Size<f64, Logical>,
+}
+
+impl RenderElement<Gles2Renderer> for SwitcherElement {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn location(&self, scale: impl Into<Scale<f64>>) -> Point<f64, Physical> {
+        self.location.to_physical(scale)
+    }
+
+    fn geometry(&self, scale: impl Into<Scale<f64>>) -> Rectangle<i32, Physical> {
+        let scale = scale.into();
+        Rectangle::from_loc_and_size(
+            self.location.to_physical(scale).to_i32_round(),
+            self.size.to_physical(scale).to_i32_round(),
+        )
+    }
+
+    fn accumulated_damage(
+        &self,
+        scale: impl Into<Scale<f64>>,
+        _for_values: Option<SpaceOutputTuple<'_, '_>>,
+    ) -> Vec<Rectangle<i32, Physical>> {
+        // Fullscreen windows are drawn without damage tracking, and it is small
+        let geometry = RenderElement::<Gles2Renderer>::geometry(self, scale);
+        vec![Rectangle::from_loc_and_size((0, 0), geometry.size)]
+    }
+
+    fn draw(
+        &self,
+        _renderer: &mut Gles2Renderer,
+        frame: &mut <Gles2Renderer as Renderer>::Frame,
+        scale: impl Into<Scale<f64>>,
+        location: Point<f64, Physical>,
+        damage: &[Rectangle<i32, Physical>],
+        _log: &slog::Logger,
+    ) -> Result<(), <Gles2Renderer as Renderer>::Error> {
+        let src = Rectangle::from_loc_and_size(
+            (0.0, 0.0),
+            (self.texture_size.w as f64, self.texture_size.h as f64),
+        );
+        let dst = Rectangle::from_loc_and_size(location, self.size.to_physical(scale));
+        frame.render_texture_from_to(&self.texture, src, dst, damage, Transform::Normal, 1.0)
+    }
+
+    fn z_index(&self) -> u8 {
+        RenderZindex::Overlay as u8
+    }
+}
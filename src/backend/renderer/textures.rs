@@ -0,0 +1,112 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use serde::Serialize;
+use smithay::{
+    backend::renderer::{
+        gles2::{Gles2Renderer, Gles2Texture},
+        Offscreen,
+    },
+    utils::{Physical, Size},
+};
+
+use crate::state::{BackendData, State};
+
+/// Offscreen buffers windows are drawn into, kept per window so that updating a thumbnail does
+/// not allocate a new one every time
+#[derive(Default)]
+pub struct TextureCache {
+    windows: HashMap<usize, (Gles2Texture, Size<i32, Physical>)>,
+}
+
+impl TextureCache {
+    /// The buffer of window `id`, replaced if it doesn't have `size`
+    pub fn window_buffer(
+        &mut self,
+        renderer: &mut Gles2Renderer,
+        id: usize,
+        size: Size<i32, Physical>,
+    ) -> anyhow::Result<Gles2Texture> {
+        if let Some((texture, buffer_size)) = self.windows.get(&id) {
+            if *buffer_size == size {
+                return Ok(texture.clone());
+            }
+        }
+        // Give the old one back first
+        self.windows.remove(&id);
+        let texture: Gles2Texture = renderer
+            .create_buffer((size.w, size.h).into())
+            .map_err(|err| anyhow!("Failed to create offscreen buffer: {}", err))?;
+        self.windows.insert(id, (texture.clone(), size));
+        Ok(texture)
+    }
+
+    /// Drops the buffer of window `id`, once it is unmapped or nobody needs it drawn anymore
+    pub fn evict(&mut self, id: usize) {
+        self.windows.remove(&id);
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.windows
+            .values()
+            .map(|(_, size)| texture_bytes(*size))
+            .sum()
+    }
+}
+
+/// Bytes of an RGBA texture of `size`
+pub fn texture_bytes(size: Size<i32, Physical>) -> usize {
+    size.w.max(0) as usize * size.h.max(0) as usize * 4
+}
+
+/// Estimated bytes of GPU memory held by the compositor itself, client buffers are not included
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GpuMemory {
+    /// Offscreen buffers of window thumbnails
+    pub window_buffers: usize,
+    /// Last frames of windows that are fading out
+    pub closing_windows: usize,
+    pub overlays: usize,
+    /// Background images of workspaces, one per output
+    pub backgrounds: usize,
+    /// Title bars of shaded windows
+    pub shaded_windows: usize,
+    pub switcher: usize,
+    /// Offscreen targets and lookup tables of outputs with an ICC profile
+    pub color_management: usize,
+    /// Copies of what is behind blurred layer surfaces
+    pub blur: usize,
+    pub total: usize,
+}
+
+impl State {
+    pub fn gpu_memory(&self) -> GpuMemory {
+        let color_management = match &self.backend {
+            BackendData::Winit(winit) => winit.color_pass.as_ref().map(|pass| pass.bytes()),
+            BackendData::Unset => None,
+        };
+        let mut memory = GpuMemory {
+            window_buffers: self.common.textures.bytes(),
+            closing_windows: self.common.closing_windows.bytes(),
+            overlays: self.common.overlays.bytes(),
+            backgrounds: self.common.background_textures.bytes(),
+            shaded_windows: self.common.shade_bars.bytes(),
+            switcher: self.common.switcher.cache.bytes(),
+            color_management: color_management.unwrap_or(0),
+            blur: self.common.blur.bytes(),
+            total: 0,
+        };
+        memory.total = memory.window_buffers
+            + memory.closing_windows
+            + memory.overlays
+            + memory.backgrounds
+            + memory.shaded_windows
+            + memory.switcher
+            + memory.color_management
+            + memory.blur;
+        memory
+    }
+}
@@ -10,7 +10,7 @@ use crate::{
 use anyhow::{anyhow, Context};
 use smithay::{
     backend::{
-        renderer::ImportDma,
+        renderer::{ImportDma, ImportMemWl},
         winit::{self, WinitEvent, WinitGraphicsBackend, WinitVirtualDevice},
     },
     desktop::layer_map_for_output,
@@ -72,6 +72,7 @@ pub fn init_backend(
         event_loop
             .handle()
             .insert_source(render_source, move |_, _, data| {
+                data.state.common.watchdog.event("rendering");
                 if let Err(err) = data
                     .state
                     .backend
@@ -88,6 +89,7 @@ pub fn init_backend(
     event_loop
         .handle()
         .insert_source(event_source, move |_, _, data| {
+            data.state.common.watchdog.event("winit events");
             let handle = &data.display.handle();
             match winit.dispatch_new_events(|event| {
                 data.state
@@ -100,7 +102,7 @@ pub fn init_backend(
                 Err(winit::WinitError::WindowClosed) => {
                     if let Some(token) = token.take() {
                         let output = data.state.backend.winit().output.clone();
-                        data.state.common.shell.remove_output(&output);
+                        data.state.output_disconnected(&output);
                         event_loop_handle.remove(token);
                     }
                 }
@@ -113,9 +115,10 @@ pub fn init_backend(
         backend,
         output: output.clone(),
         age_reset: 0,
+        color_pass: None,
+        last_frame: None,
     });
-    state.common.shell.add_output(&output);
-    state.common.shell.refresh_outputs();
+    state.output_connected(&output);
 
     Ok(())
 }
@@ -136,6 +139,9 @@ fn init_egl(
         .dmabuf_state
         .create_global::<State, _>(dh, formats, slog_scope::logger());
 
+    let shm_formats = renderer.renderer().shm_formats().to_vec();
+    state.init_shm(dh, &shm_formats);
+
     Ok(())
 }
 
@@ -160,6 +166,9 @@ impl State {
                 output.change_current_state(Some(mode), None, None, None);
                 layer_map_for_output(output).arrange(dh);
                 self.common.shell.refresh_outputs();
+                self.common.shell.rearrange_output(output);
+                self.common.constrain_windows();
+                self.common.report_outputs();
                 render_ping.ping();
             }
             WinitEvent::Focus(true) => {
@@ -1,51 +1,127 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::error::Error;
+use std::{error::Error, rc::Rc, time::Instant};
 
 use anyhow::Context;
-use smithay::{backend::winit::WinitGraphicsBackend, wayland::output::Output};
+use smithay::{
+    backend::{renderer::Bind, winit::WinitGraphicsBackend},
+    reexports::winit::window::CursorIcon,
+    wayland::{output::Output, seat::CursorImageStatus},
+};
 
-use crate::{backend::renderer, state::CommonState};
+use crate::{
+    backend::renderer::{
+        self,
+        color::{self, ColorPass},
+    },
+    portal,
+    state::{
+        cursor_shape::{cursor_shape, Shape},
+        output_config::OutputState,
+        CommonState,
+    },
+};
 
 pub struct WinitState {
     pub backend: WinitGraphicsBackend,
     pub output: Output,
     pub age_reset: u8,
+    /// Set while the output has an ICC profile
+    pub color_pass: Option<ColorPass>,
+    /// When the last frame was rendered, to hold the frame rate down in low power mode
+    pub last_frame: Option<Instant>,
 }
 
 impl WinitState {
     pub fn render_output(&mut self, state: &mut CommonState) -> Result<(), Box<dyn Error>> {
+        let start = Instant::now();
+        if let (Some(interval), Some(last_frame)) = (state.frame_interval(), self.last_frame) {
+            if start.duration_since(last_frame) < interval {
+                return Ok(());
+            }
+        }
+        self.last_frame = Some(start);
         if renderer::needs_buffer_reset(&self.output, state) {
             self.reset_buffers();
         }
+        self.update_color_pass();
+
+        // The host draws the default cursor, so moving it does not need a new frame
+        let cursor_status = renderer::cursor_status(&state.last_active_seat);
+        let window = self.backend.window();
+        window.set_cursor_visible(matches!(cursor_status, CursorImageStatus::Default));
+        window.set_cursor_icon(cursor_icon(cursor_shape(&state.last_active_seat)));
 
         self.backend
             .bind()
             .with_context(|| "Failed to bind buffer")?;
 
-        let age = if self.age_reset > 0 {
-            self.age_reset -= 1;
+        // A screenshot reads back the whole frame, what an older one left in the buffer must
+        // not show up in it
+        let resetting = self.age_reset > 0
+            || state
+                .screenshot_requests
+                .iter()
+                .any(|request| request.output == self.output);
+        let age = if resetting {
+            self.age_reset = self.age_reset.saturating_sub(1);
             0
         } else {
             self.backend.buffer_age().unwrap_or(0)
         };
 
-        match renderer::render_output(
-            self.backend.renderer(),
-            age as u8,
-            state,
-            &self.output,
-            true,
-        ) {
-            Ok(damage) => {
-                state
-                    .shell
-                    .active_workspace_mut()
-                    .space
-                    .send_frames(state.start_time.elapsed().as_millis() as u32);
+        let result = match &mut self.color_pass {
+            Some(pass) => {
+                let size = self.output.current_mode().unwrap().size;
+                let (target, fresh) = pass.target(self.backend.renderer(), size)?;
                 self.backend
-                    .submit(damage.as_ref().map(|x| &**x))
-                    .with_context(|| "Failed to submit buffer for display")?;
+                    .renderer()
+                    .bind(target)
+                    .with_context(|| "Failed to bind color management buffer")?;
+                // The target keeps its contents, unlike the window surface
+                let age = if fresh || resetting { 0 } else { 1 };
+                renderer::render_output(self.backend.renderer(), age, state, &self.output, true)
+            }
+            None => renderer::render_output(
+                self.backend.renderer(),
+                age as u8,
+                state,
+                &self.output,
+                true,
+            ),
+        };
+
+        match result {
+            Ok(mut damage) => {
+                if let Some(pass) = &self.color_pass {
+                    if damage.is_some() || !state.screenshot_requests.is_empty() {
+                        self.backend
+                            .bind()
+                            .with_context(|| "Failed to bind buffer")?;
+                        pass.apply(self.backend.renderer())?;
+                        // The whole surface was redrawn
+                        damage = damage.map(|_| Vec::new());
+                    }
+                }
+                self.take_screenshots(state);
+                state.shell.send_frames(
+                    &self.output,
+                    &state.seats,
+                    state.frame_policy(),
+                    state.start_time.elapsed().as_millis() as u32,
+                );
+                // Nothing changed, keep the last frame instead of swapping a full redraw
+                if let Some(damage) = damage {
+                    let damage = Some(&*damage).filter(|damage| !damage.is_empty());
+                    self.backend
+                        .submit(damage)
+                        .with_context(|| "Failed to submit buffer for display")?;
+                    state.latency.presented();
+                    state.metrics.frame_rendered(start.elapsed());
+                } else {
+                    state.metrics.frame_skipped();
+                }
+                self.render_thumbnails(state);
             }
             Err(err) => {
                 // TODO handle errors better
@@ -57,7 +133,116 @@ impl WinitState {
         Ok(())
     }
 
+    /// Answers pending screenshot requests for this output, needs the frame to still be bound.
+    /// The frame is read back here, encoding and writing it happens on a worker.
+    fn take_screenshots(&mut self, state: &mut CommonState) {
+        let (requests, others): (Vec<_>, Vec<_>) = std::mem::take(&mut state.screenshot_requests)
+            .into_iter()
+            .partition(|request| request.output == self.output);
+        state.screenshot_requests = others;
+        if requests.is_empty() {
+            return;
+        }
+
+        let replies = requests.into_iter().map(|request| request.reply);
+        match renderer::capture_output(self.backend.renderer(), &self.output) {
+            Ok(mut image) => {
+                renderer::mask_private_windows(&mut image, state, &self.output);
+                portal::save_screenshot(image, replies.collect());
+            }
+            Err(err) => {
+                slog_scope::warn!("Screenshot failed: {:?}", err);
+                for reply in replies {
+                    let _ = reply.send(Err(err.to_string()));
+                }
+            }
+        }
+    }
+
+    /// Updates outdated window thumbnails, unbinds the window surface
+    fn render_thumbnails(&mut self, state: &mut CommonState) {
+        for (window, max_size) in state.due_thumbnails() {
+            let gles = self.backend.renderer();
+            let result = renderer::render_thumbnail(gles, &mut state.textures, &window, max_size)
+                .and_then(|image| state.thumbnail_rendered(&window, image));
+            if let Err(err) = result {
+                slog_scope::debug!("Thumbnail failed: {:?}", err);
+            }
+        }
+    }
+
+    /// Follows changes of the ICC profile of the output
+    fn update_color_pass(&mut self) {
+        let lut = color::output_lut(&self.output);
+        let current = self.color_pass.as_ref().map(|pass| Rc::as_ptr(&pass.lut));
+        if current == lut.as_ref().map(Rc::as_ptr) {
+            return;
+        }
+
+        if let Some(pass) = self.color_pass.take() {
+            pass.destroy(self.backend.renderer());
+        }
+        if let Some(lut) = lut {
+            match ColorPass::new(self.backend.renderer(), lut) {
+                Ok(pass) => self.color_pass = Some(pass),
+                Err(err) => {
+                    slog_scope::warn!("{:?}", err);
+                    color::reset_output_color(&self.output);
+                }
+            }
+        }
+        self.reset_buffers();
+    }
+
+    /// The mode of the output follows the size of the host window, so it can't be changed
+    pub fn test_output(&self, output: &Output, state: &OutputState) -> anyhow::Result<()> {
+        if output == &self.output && state.mode.is_some() && state.mode != output.current_mode() {
+            anyhow::bail!("The mode of {} follows its window size", output.name());
+        }
+        Ok(())
+    }
+
     pub fn reset_buffers(&mut self) {
         self.age_reset = 3;
     }
 }
+
+/// The themed host cursor closest to a cursor shape
+fn cursor_icon(shape: Shape) -> CursorIcon {
+    match shape {
+        Shape::Default => CursorIcon::Default,
+        Shape::ContextMenu => CursorIcon::ContextMenu,
+        Shape::Help => CursorIcon::Help,
+        Shape::Pointer => CursorIcon::Hand,
+        Shape::Progress => CursorIcon::Progress,
+        Shape::Wait => CursorIcon::Wait,
+        Shape::Cell => CursorIcon::Cell,
+        Shape::Crosshair => CursorIcon::Crosshair,
+        Shape::Text => CursorIcon::Text,
+        Shape::VerticalText => CursorIcon::VerticalText,
+        Shape::Alias => CursorIcon::Alias,
+        Shape::Copy => CursorIcon::Copy,
+        Shape::Move => CursorIcon::Move,
+        Shape::NoDrop => CursorIcon::NoDrop,
+        Shape::NotAllowed => CursorIcon::NotAllowed,
+        Shape::Grab => CursorIcon::Grab,
+        Shape::Grabbing => CursorIcon::Grabbing,
+        Shape::EResize => CursorIcon::EResize,
+        Shape::NResize => CursorIcon::NResize,
+        Shape::NeResize => CursorIcon::NeResize,
+        Shape::NwResize => CursorIcon::NwResize,
+        Shape::SResize => CursorIcon::SResize,
+        Shape::SeResize => CursorIcon::SeResize,
+        Shape::SwResize => CursorIcon::SwResize,
+        Shape::WResize => CursorIcon::WResize,
+        Shape::EwResize => CursorIcon::EwResize,
+        Shape::NsResize => CursorIcon::NsResize,
+        Shape::NeswResize => CursorIcon::NeswResize,
+        Shape::NwseResize => CursorIcon::NwseResize,
+        Shape::ColResize => CursorIcon::ColResize,
+        Shape::RowResize => CursorIcon::RowResize,
+        Shape::AllScroll => CursorIcon::AllScroll,
+        Shape::ZoomIn => CursorIcon::ZoomIn,
+        Shape::ZoomOut => CursorIcon::ZoomOut,
+    }
+}
@@ -0,0 +1,225 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
+};
+
+use anyhow::Context;
+use calloop::timer::{TimeoutAction, Timer};
+use serde::{Deserialize, Serialize};
+use smithay::reexports::calloop::EventLoop;
+use zbus::blocking::Connection;
+
+use crate::{
+    runtime::messages::RuntimeMessage,
+    state::{CommonState, LoopData},
+};
+
+/// How often brightness is read back, sysfs doesn't announce changes made by others
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BacklightKind {
+    Display,
+    Keyboard,
+}
+
+/// A backlight device in sysfs
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Backlight {
+    pub kind: BacklightKind,
+    /// Directory name in `/sys/class/backlight` or `/sys/class/leds`
+    pub name: String,
+    pub brightness: u32,
+    pub max_brightness: u32,
+}
+
+impl Backlight {
+    fn subsystem(&self) -> &'static str {
+        match self.kind {
+            BacklightKind::Display => "backlight",
+            BacklightKind::Keyboard => "leds",
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        Path::new("/sys/class")
+            .join(self.subsystem())
+            .join(&self.name)
+    }
+
+    fn read_brightness(&self) -> Option<u32> {
+        read_value(&self.path().join("brightness"))
+    }
+}
+
+/// The display and keyboard backlights found at startup
+#[derive(Debug, Default)]
+pub struct Backlights {
+    pub devices: Vec<Backlight>,
+    /// Passes brightness changes to the thread talking to logind
+    setter: Option<mpsc::Sender<(&'static str, String, u32)>>,
+}
+
+fn read_value(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// The backlight of the panel, firmware interfaces are preferred over raw ones like logind does
+fn find_display() -> Option<Backlight> {
+    let mut found = fs::read_dir("/sys/class/backlight")
+        .ok()?
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let rank = match fs::read_to_string(path.join("type")).ok()?.trim() {
+                "firmware" => 0,
+                "platform" => 1,
+                _ => 2,
+            };
+            Some((rank, entry.file_name().to_string_lossy().into_owned()))
+        })
+        .collect::<Vec<_>>();
+    found.sort();
+    found
+        .into_iter()
+        .find_map(|(_, name)| backlight(BacklightKind::Display, name))
+}
+
+/// The keyboard backlight, a LED named like `tpacpi::kbd_backlight`
+fn find_keyboard() -> Option<Backlight> {
+    fs::read_dir("/sys/class/leds")
+        .ok()?
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with("kbd_backlight"))
+        .find_map(|name| backlight(BacklightKind::Keyboard, name))
+}
+
+fn backlight(kind: BacklightKind, name: String) -> Option<Backlight> {
+    let mut backlight = Backlight {
+        kind,
+        name,
+        brightness: 0,
+        max_brightness: 0,
+    };
+    backlight.max_brightness = read_value(&backlight.path().join("max_brightness"))?;
+    backlight.brightness = backlight.read_brightness()?;
+    (backlight.max_brightness > 0).then(|| backlight)
+}
+
+/// Finds the backlights and reads them back periodically to report changes by others, e.g. the
+/// firmware reacting to brightness keys on its own
+pub fn init_backlight(event_loop: &mut EventLoop<LoopData>) -> anyhow::Result<Backlights> {
+    let devices = [find_display(), find_keyboard()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    if devices.is_empty() {
+        slog_scope::info!("No backlight found");
+        return Ok(Backlights::default());
+    }
+    for device in &devices {
+        slog_scope::info!("Controlling {:?} backlight {}", device.kind, device.name);
+    }
+
+    let (setter, requests) = mpsc::channel();
+    std::thread::Builder::new()
+        .name("backlight".to_string())
+        .spawn(move || set_brightness_thread(requests))
+        .with_context(|| "Failed to start backlight thread")?;
+
+    event_loop
+        .handle()
+        .insert_source(Timer::from_duration(POLL_INTERVAL), |_, _, data| {
+            data.state.common.poll_backlights();
+            TimeoutAction::ToDuration(POLL_INTERVAL)
+        })
+        .map_err(|_| anyhow::anyhow!("Failed to insert backlight timer"))?;
+
+    Ok(Backlights {
+        devices,
+        setter: Some(setter),
+    })
+}
+
+/// Sets brightness through logind, which needs no write access to sysfs. Writing the file is the
+/// fallback without a logind session.
+fn set_brightness_thread(requests: mpsc::Receiver<(&'static str, String, u32)>) {
+    let connection = Connection::system()
+        .map_err(|err| slog_scope::warn!("No system bus for setting brightness: {}", err))
+        .ok();
+    for (subsystem, name, brightness) in requests {
+        let result = connection.as_ref().map(|connection| {
+            connection.call_method(
+                Some("org.freedesktop.login1"),
+                "/org/freedesktop/login1/session/auto",
+                Some("org.freedesktop.login1.Session"),
+                "SetBrightness",
+                &(subsystem, name.as_str(), brightness),
+            )
+        });
+        if let Some(Ok(_)) = result {
+            continue;
+        }
+        let path = Path::new("/sys/class")
+            .join(subsystem)
+            .join(&name)
+            .join("brightness");
+        if let Err(err) = fs::write(&path, brightness.to_string()) {
+            slog_scope::warn!("Failed to set brightness of {}: {}", name, err);
+        }
+    }
+}
+
+impl CommonState {
+    /// Sets the backlight of `kind` to `percent` of its maximum, or changes it by `percent` if
+    /// `relative`. The display backlight never goes fully dark.
+    pub fn set_brightness(&mut self, kind: BacklightKind, percent: f64, relative: bool) {
+        let setter = match &self.backlights.setter {
+            Some(setter) if percent.is_finite() && !(relative && percent == 0.0) => setter,
+            _ => return,
+        };
+        let device = match self.backlights.devices.iter_mut().find(|d| d.kind == kind) {
+            Some(device) => device,
+            None => return,
+        };
+        let max = device.max_brightness as f64;
+        let value = percent / 100.0 * max;
+        let target = if relative {
+            // Keyboards often have only a few levels, every step moves at least one
+            device.brightness as f64 + value.signum() * value.abs().round().max(1.0)
+        } else {
+            value.round()
+        };
+        let min = match kind {
+            BacklightKind::Display => 1.0,
+            BacklightKind::Keyboard => 0.0,
+        };
+        let brightness = target.clamp(min, max) as u32;
+        if brightness == device.brightness {
+            return;
+        }
+        // Known right away, so polling doesn't report it as a change by someone else
+        device.brightness = brightness;
+        let _ = setter.send((device.subsystem(), device.name.clone(), brightness));
+    }
+
+    /// Tells the script about brightness changed by anything but the compositor
+    fn poll_backlights(&mut self) {
+        for device in &mut self.backlights.devices {
+            let brightness = match device.read_brightness() {
+                Some(brightness) if brightness != device.brightness => brightness,
+                _ => continue,
+            };
+            device.brightness = brightness;
+            let runtime_sender = &self.shell.active_workspace().runtime_sender;
+            let _ = runtime_sender.send(RuntimeMessage::BrightnessChanged(device.clone()));
+        }
+    }
+}
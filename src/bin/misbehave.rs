@@ -0,0 +1,247 @@
+//! Test application that breaks the xdg-shell rules in various ways. Every case runs on its own
+//! connection, which the compositor is expected to close with a protocol error (or to shrug
+//! off), and has to stay up for the next one.
+
+use std::{env, error::Error, process};
+
+use sctk::reexports::{
+    client::{
+        protocol::{wl_compositor, wl_registry, wl_seat, wl_surface},
+        Connection, Dispatch, EventQueue, QueueHandle,
+    },
+    protocols::xdg::shell::client::{xdg_surface, xdg_toplevel, xdg_wm_base},
+};
+
+/// What a case expects from the compositor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    ProtocolError,
+    Ignored,
+}
+
+const CASES: &[(&str, Outcome)] = &[
+    ("ack-unknown-serial", Outcome::ProtocolError),
+    ("double-role", Outcome::ProtocolError),
+    ("empty-geometry", Outcome::ProtocolError),
+    ("negative-geometry", Outcome::ProtocolError),
+    ("min-above-max", Outcome::ProtocolError),
+    ("move-before-map", Outcome::Ignored),
+    ("resize-before-map", Outcome::Ignored),
+];
+
+fn main() {
+    let only = env::args().nth(1);
+    let mut failed = 0;
+    for (name, expected) in CASES {
+        if only.as_deref().map(|only| only != *name).unwrap_or(false) {
+            continue;
+        }
+
+        let result = run_case(name);
+        let outcome = match &result {
+            Ok(()) => Outcome::Ignored,
+            Err(_) => Outcome::ProtocolError,
+        };
+        let alive = still_alive();
+        let passed = outcome == *expected && alive;
+        if !passed {
+            failed += 1;
+        }
+        println!(
+            "{} {} ({}{})",
+            if passed { "PASS" } else { "FAIL" },
+            name,
+            match result {
+                Ok(()) => "no error".to_string(),
+                Err(err) => err.to_string(),
+            },
+            if alive { "" } else { ", compositor is gone" }
+        );
+        if !alive {
+            break;
+        }
+    }
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+/// Whether a fresh connection still gets through a roundtrip
+fn still_alive() -> bool {
+    connect().is_ok()
+}
+
+fn connect() -> Result<(Connection, EventQueue<Globals>, Globals), Box<dyn Error>> {
+    let conn = Connection::connect_to_env()?;
+    let mut queue = conn.new_event_queue();
+    let qh = queue.handle();
+    conn.display().get_registry(&qh, ());
+
+    let mut globals = Globals::default();
+    queue.roundtrip(&mut globals)?;
+    Ok((conn, queue, globals))
+}
+
+fn run_case(name: &str) -> Result<(), Box<dyn Error>> {
+    let (_conn, mut queue, mut globals) = connect()?;
+    let qh = queue.handle();
+    let compositor = globals.compositor.clone().ok_or("no wl_compositor")?;
+    let wm_base = globals.wm_base.clone().ok_or("no xdg_wm_base")?;
+
+    let surface = compositor.create_surface(&qh, ());
+    let xdg_surface = wm_base.get_xdg_surface(&surface, &qh, ());
+    let toplevel = xdg_surface.get_toplevel(&qh, ());
+    surface.commit();
+    queue.roundtrip(&mut globals)?;
+
+    match name {
+        "ack-unknown-serial" => xdg_surface.ack_configure(globals.last_serial.wrapping_add(1000)),
+        // The surface already is a toplevel
+        "double-role" => {
+            wm_base
+                .get_xdg_surface(&surface, &qh, ())
+                .get_toplevel(&qh, ());
+        }
+        "empty-geometry" => {
+            xdg_surface.set_window_geometry(0, 0, 0, 100);
+            surface.commit();
+        }
+        "negative-geometry" => {
+            xdg_surface.set_window_geometry(0, 0, -20, -20);
+            surface.commit();
+        }
+        "min-above-max" => {
+            toplevel.set_min_size(400, 400);
+            toplevel.set_max_size(200, 200);
+            surface.commit();
+        }
+        "move-before-map" | "resize-before-map" => {
+            let seat = globals.seat.clone().ok_or("no wl_seat")?;
+            if name == "move-before-map" {
+                toplevel._move(&seat, globals.last_serial);
+            } else {
+                toplevel.resize(
+                    &seat,
+                    globals.last_serial,
+                    xdg_toplevel::ResizeEdge::BottomRight,
+                );
+            }
+        }
+        _ => return Err(format!("unknown case {}", name).into()),
+    }
+    queue.roundtrip(&mut globals)?;
+    queue.roundtrip(&mut globals)?;
+    Ok(())
+}
+
+#[derive(Default)]
+struct Globals {
+    compositor: Option<wl_compositor::WlCompositor>,
+    wm_base: Option<xdg_wm_base::XdgWmBase>,
+    seat: Option<wl_seat::WlSeat>,
+    last_serial: u32,
+}
+
+impl Dispatch<wl_registry::WlRegistry, ()> for Globals {
+    fn event(
+        state: &mut Self,
+        registry: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_registry::Event::Global {
+            name, interface, ..
+        } = event
+        {
+            match interface.as_str() {
+                "wl_compositor" => state.compositor = Some(registry.bind(name, 4, qh, ())),
+                "xdg_wm_base" => state.wm_base = Some(registry.bind(name, 1, qh, ())),
+                "wl_seat" if state.seat.is_none() => {
+                    state.seat = Some(registry.bind(name, 1, qh, ()))
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Dispatch<xdg_wm_base::XdgWmBase, ()> for Globals {
+    fn event(
+        _: &mut Self,
+        wm_base: &xdg_wm_base::XdgWmBase,
+        event: xdg_wm_base::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let xdg_wm_base::Event::Ping { serial } = event {
+            wm_base.pong(serial);
+        }
+    }
+}
+
+impl Dispatch<xdg_surface::XdgSurface, ()> for Globals {
+    fn event(
+        state: &mut Self,
+        _: &xdg_surface::XdgSurface,
+        event: xdg_surface::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // Never acked, the cases want the window unmapped
+        if let xdg_surface::Event::Configure { serial } = event {
+            state.last_serial = serial;
+        }
+    }
+}
+
+impl Dispatch<wl_compositor::WlCompositor, ()> for Globals {
+    fn event(
+        _: &mut Self,
+        _: &wl_compositor::WlCompositor,
+        _: wl_compositor::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<wl_surface::WlSurface, ()> for Globals {
+    fn event(
+        _: &mut Self,
+        _: &wl_surface::WlSurface,
+        _: wl_surface::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<wl_seat::WlSeat, ()> for Globals {
+    fn event(
+        _: &mut Self,
+        _: &wl_seat::WlSeat,
+        _: wl_seat::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<xdg_toplevel::XdgToplevel, ()> for Globals {
+    fn event(
+        _: &mut Self,
+        _: &xdg_toplevel::XdgToplevel,
+        _: xdg_toplevel::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
@@ -0,0 +1,178 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use clap::{Args, Parser, Subcommand};
+
+use crate::{
+    config::Config,
+    input::keybindings::{parse_button, parse_chord, parse_pointer_trigger},
+    ipc,
+    log::parse_level,
+    runtime::{self, plugin::ElectrumExtension},
+};
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "electrum",
+    version,
+    about = "A Wayland compositor scripted in TypeScript"
+)]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Start the compositor, the default without a subcommand
+    Run(RunArgs),
+    /// Validate electrum.toml and check the syntax and imports of the main script without
+    /// starting the compositor
+    CheckConfig,
+    /// Send a command to a running instance, e.g. `electrum msg logs count=10`
+    Msg(MsgArgs),
+    /// Print version information
+    Version,
+}
+
+#[derive(Args, Debug, Default)]
+pub struct RunArgs {
+    /// Backend to use, overrides ELECTRUM_BACKEND
+    #[clap(long)]
+    pub backend: Option<String>,
+    /// Name of the wayland socket, picked automatically by default
+    #[clap(long)]
+    pub socket: Option<String>,
+    /// Log to this file instead of $XDG_STATE_HOME/electrum/electrum.log, even if `file` is off
+    #[clap(long)]
+    pub log_file: Option<PathBuf>,
+    /// Don't log to stderr
+    #[clap(long, short)]
+    pub quiet: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct MsgArgs {
+    /// IPC command, see src/ipc/README.md
+    pub command: String,
+    /// Arguments as key=value, values are parsed as JSON if possible
+    pub args: Vec<String>,
+    /// IPC socket, defaults to ELECTRUM_SOCKET
+    #[clap(long)]
+    pub socket: Option<PathBuf>,
+}
+
+pub fn version() {
+    println!("electrum {}", std::env!("CARGO_PKG_VERSION"));
+    if cfg!(feature = "debug") {
+        println!(
+            "Debug build ({})",
+            std::option_env!("GIT_HASH").unwrap_or("Unknown")
+        );
+    }
+}
+
+/// Prints every problem found and returns whether the config is usable
+pub fn check_config(extensions: &mut [Box<dyn ElectrumExtension>]) -> bool {
+    let mut ok = true;
+
+    match Config::check() {
+        Ok(Some((path, config))) => {
+            println!("{}: parsed", path.display());
+            for problem in config_problems(&config) {
+                println!("  {}", problem);
+                ok = false;
+            }
+        }
+        Ok(None) => println!("No electrum.toml found, using defaults"),
+        Err(err) => {
+            println!("{:#}", err);
+            ok = false;
+        }
+    }
+
+    match runtime::check_main_module_syntax(extensions) {
+        Ok(Some(path)) => println!(
+            "{}: syntax and imports checked, types are not",
+            path.display()
+        ),
+        Ok(None) => println!("No main script found, only the declarative config is used"),
+        Err(err) => {
+            println!("{:#}", err);
+            ok = false;
+        }
+    }
+
+    ok
+}
+
+/// Values that parse as TOML but would be ignored at startup
+fn config_problems(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+    if let Some(level) = &config.log_level {
+        if let Err(err) = parse_level(level) {
+            problems.push(err.to_string());
+        }
+    }
+    for level in config.log.modules.values() {
+        if let Err(err) = parse_level(level) {
+            problems.push(err.to_string());
+        }
+    }
+    for binding in config.keybindings.iter().flatten() {
+        if let Err(err) = parse_chord(&binding.keys) {
+            problems.push(err.to_string());
+        }
+    }
+    for binding in &config.pointer_bindings {
+        if let Err(err) = parse_pointer_trigger(&binding.button) {
+            problems.push(err.to_string());
+        }
+    }
+    let pointer_bindings = [
+        &config.input.move_binding,
+        &config.input.resize_binding,
+        &config.input.edge_resize,
+    ];
+    for binding in pointer_bindings.into_iter().flatten() {
+        if binding.is_empty() {
+            continue;
+        }
+        if let Err(err) = parse_button(binding) {
+            problems.push(err.to_string());
+        }
+    }
+    problems
+}
+
+pub fn msg(args: MsgArgs) -> anyhow::Result<bool> {
+    let path = args
+        .socket
+        .or_else(ipc::client_socket_path)
+        .ok_or_else(|| anyhow!("No IPC socket, is electrum running?"))?;
+
+    let mut request = serde_json::Map::new();
+    request.insert("command".into(), args.command.into());
+    for arg in args.args {
+        let (key, value) = arg
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected key=value, got {}", arg))?;
+        let value = serde_json::from_str(value).unwrap_or_else(|_| value.into());
+        request.insert(key.into(), value);
+    }
+
+    let response = ipc::send_request(&path, &request.into())?;
+    if let Some(error) = response.get("error") {
+        eprintln!("{}", error.as_str().unwrap_or_default());
+        return Ok(false);
+    }
+    let value = response.get("ok").cloned().unwrap_or_default();
+    // Text like the metrics is meant to be read or piped on as it is
+    match value {
+        serde_json::Value::String(text) => println!("{}", text.trim_end()),
+        value => println!("{}", serde_json::to_string_pretty(&value)?),
+    }
+    Ok(true)
+}
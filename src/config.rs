@@ -0,0 +1,397 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{fs, path::PathBuf};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{
+    backend::renderer::{blur::BlurConfig, filter::OutputFilter},
+    input::{
+        accessibility::AccessibilityConfig, barriers::BarrierConfig, click_raise::RaisePolicy,
+        grab_focus::GrabFocus, pointer_bindings::PointerContext, warp::WarpConfig,
+    },
+    log::LogConfig,
+    plugins::PluginConfig,
+    power::PowerConfig,
+    shell::{
+        autohide::AutoHideConfig,
+        cycle::Cycle,
+        decisions::DecisionConfig,
+        direction::{Direction, DirectionalFocusConfig},
+        frames::FramePolicy,
+        pip::PipConfig,
+        workspace::WorkspaceConfig,
+    },
+    state::{
+        access::AccessRule, idle::IdleConfig, sandbox::SandboxConfig,
+        transfers::TransferFilterConfig,
+    },
+    watchdog::WatchdogConfig,
+};
+
+/// Compositor-level settings that have to be known before the runtime is up.
+///
+/// Loaded from `electrum.toml` in the XDG config directory. Every field is optional,
+/// a missing or broken file falls back to the defaults.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Export `WAYLAND_DISPLAY` and friends to the systemd user manager and dbus
+    pub export_environment: bool,
+    /// Register the xdg-desktop-portal backend on the session bus
+    pub portal: bool,
+    /// Color scheme exposed through the portal settings interface
+    pub color_scheme: ColorScheme,
+    /// Act as the session notification daemon and forward notifications to the runtime
+    pub notifications: bool,
+    /// Minimum level of log records, can be changed at runtime over IPC
+    pub log_level: Option<String>,
+    /// The log file and levels of single modules
+    pub log: LogConfig,
+    /// Milliseconds the runtime gets to run its shutdown hook
+    pub shutdown_timeout: u64,
+    /// Key combinations handled by the compositor, see [`Keybinding`]
+    pub keybindings: Option<Vec<Keybinding>>,
+    /// Mouse buttons and scrolling on the desktop or parts of windows, see [`PointerBinding`]
+    pub pointer_bindings: Vec<PointerBinding>,
+    /// Settings of outputs, matched by name, used when no profile matches
+    pub outputs: Vec<OutputConfig>,
+    /// Pick the scale of outputs without a configured one from their physical size
+    pub auto_scale: bool,
+    /// Named output layouts, applied when exactly their outputs are connected
+    pub profiles: Vec<OutputProfile>,
+    /// Keyboard layout and repeat settings
+    pub input: InputConfig,
+    /// Sticky, slow and bounce keys, can be changed at runtime
+    pub accessibility: AccessibilityConfig,
+    /// When hidden windows get frame callbacks, can be changed at runtime
+    pub frame_policy: FramePolicy,
+    /// Minimum milliseconds between two updates of a window thumbnail
+    pub thumbnail_interval: u64,
+    /// App ids of windows that show up as a placeholder in screenshots and thumbnails
+    pub private_apps: Vec<String>,
+    /// Milliseconds closed windows take to fade out, 0 turns that off
+    pub close_animation: u64,
+    /// Most windows fading out at once, each keeps a snapshot texture of its last frame
+    pub max_closing_windows: usize,
+    /// Logical pixels of every floating window kept on some output, 0 lets windows go anywhere
+    pub min_visible: i32,
+    /// Icon theme used to look up application icons, hicolor is searched after it
+    pub icon_theme: String,
+    /// Name of the wayland socket in `XDG_RUNTIME_DIR`, `--socket` takes precedence
+    pub socket: Option<String>,
+    /// Commands spawned once the backend is up
+    pub autostart: Vec<Autostart>,
+    /// Executables allowed to bind `electrum_shell_v1`
+    pub shell_clients: Vec<PathBuf>,
+    /// Executables allowed to bind privileged globals
+    pub access: Vec<AccessRule>,
+    /// The socket of clients that get no privileged globals
+    pub sandbox: SandboxConfig,
+    /// wl_shm formats advertised besides argb8888 and xrgb8888, if the renderer can import them
+    pub shm_formats: Vec<String>,
+    /// Panels that slide out of view while the pointer is not over them
+    pub auto_hide: AutoHideConfig,
+    /// Size and placement of picture-in-picture windows
+    pub pip: PipConfig,
+    /// Named workspaces that always exist, going back and forth and removing empty ones
+    pub workspaces: WorkspaceConfig,
+    /// How long the runtime gets to decide on maximize and resize requests
+    pub decisions: DecisionConfig,
+    /// How long the runtime gets to allow a client to see the selection of another app
+    pub transfer_filter: TransferFilterConfig,
+    /// Detection of a stuck event loop
+    pub watchdog: WatchdogConfig,
+    /// What focusing the next window to the left, right, above or below considers
+    pub directional_focus: DirectionalFocusConfig,
+    /// Start the apps that had windows again after a crash
+    pub restore_session: bool,
+    /// Edges between outputs the pointer is held back at
+    pub barriers: Vec<BarrierConfig>,
+    /// How the pointer moves when the script warps it
+    pub pointer_warp: WarpConfig,
+    /// When outputs dim and turn off without input
+    pub idle: IdleConfig,
+    /// Blurring behind layer surfaces that ask for it through a layer rule
+    pub blur: BlurConfig,
+    /// What counts as low power and how far frame rates drop then
+    pub power: PowerConfig,
+    /// Shared objects loaded as plugins, only those allowed by name or path
+    pub plugins: PluginConfig,
+}
+
+/// Values as defined by `org.freedesktop.appearance.color-scheme`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[repr(u32)]
+pub enum ColorScheme {
+    NoPreference = 0,
+    PreferDark = 1,
+    PreferLight = 2,
+}
+
+/// A program spawned once the backend is up, either just its command or a table that also
+/// says where its first windows go
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Autostart {
+    Command(String),
+    Assigned {
+        command: String,
+        /// Matches windows by app id instead of by process, for programs that hand their
+        /// windows to an instance that is already running or started over dbus
+        app_id: Option<String>,
+        /// Matches windows by the binary of their client, for programs whose windows come
+        /// from a helper process
+        executable: Option<PathBuf>,
+        /// Told to the runtime, which decides what workspaces are
+        workspace: Option<u32>,
+        output: Option<String>,
+        /// Seconds new windows of the program are placed
+        #[serde(default = "autostart_timeout_default")]
+        timeout: u64,
+    },
+}
+
+impl Autostart {
+    pub fn command(&self) -> &str {
+        match self {
+            Autostart::Command(command) | Autostart::Assigned { command, .. } => command,
+        }
+    }
+}
+
+fn autostart_timeout_default() -> u64 {
+    10
+}
+
+/// A key combination like `Super+Shift+Return`, or a chord like `Super+r h`, and what it does
+#[derive(Debug, Clone, Deserialize)]
+pub struct Keybinding {
+    pub keys: String,
+    /// Binding mode the keys are handled in, the default mode without one
+    pub mode: Option<String>,
+    pub action: Action,
+    /// Send the keys to the focused window too, e.g. media keys a player handles itself
+    #[serde(default)]
+    pub forward: bool,
+}
+
+/// A mouse button or scroll direction like `Middle` or `Super+WheelUp`, where the pointer has to
+/// be for it and what it does
+#[derive(Debug, Clone, Deserialize)]
+pub struct PointerBinding {
+    pub button: String,
+    pub context: PointerContext,
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// Run a command through `sh -c`
+    Spawn(String),
+    /// Ask the focused window to close
+    Close,
+    /// Toggle fullscreen of the focused window
+    Fullscreen,
+    /// Collapse the focused window to its title bar, or bring it back
+    ToggleShade,
+    /// Turn a color filter on or off for the output with the pointer
+    ToggleFilter(OutputFilter),
+    /// Focus the closest window in a direction from the focused one
+    Focus(Direction),
+    /// Focus the window of the workspace opened after or before the focused one
+    CycleWindows(Cycle),
+    /// Open the window switcher, or select the next window in it
+    SwitchWindows,
+    /// Show the workspace with this name, created if there is none
+    Workspace(String),
+    /// Go back to the workspace shown before
+    WorkspaceBackAndForth,
+    /// Start the shutdown sequence
+    Quit,
+    /// Switch to the bindings of a mode, `default` goes back to those without a mode
+    Mode(String),
+    /// Send a `Keybinding` event with this name to the runtime
+    Runtime(String),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OutputConfig {
+    /// Connector name like `DP-1`, or `make model` as reported by the EDID
+    pub name: String,
+    #[serde(default = "enabled_default")]
+    pub enabled: bool,
+    /// `<width>x<height>` with an optional `@<refresh rate>`
+    pub mode: Option<String>,
+    pub position: Option<(i32, i32)>,
+    pub scale: Option<f64>,
+    /// Display profile colors are converted into from sRGB
+    pub icc_profile: Option<PathBuf>,
+    pub filter: Option<OutputFilter>,
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OutputProfile {
+    pub name: String,
+    pub outputs: Vec<OutputConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct InputConfig {
+    /// Milliseconds before a held key starts repeating
+    pub repeat_delay: i32,
+    /// Repeated keys per second
+    pub repeat_rate: i32,
+    pub xkb_rules: String,
+    pub xkb_model: String,
+    pub xkb_layout: String,
+    pub xkb_variant: String,
+    pub xkb_options: Option<String>,
+    /// Number of fingers of a touchpad swipe that moves the focused window
+    pub gesture_move_fingers: Option<u32>,
+    /// Switch to the keyboard layout a window used last when it gets focus
+    pub per_window_layout: bool,
+    /// Modifiers and button that move the window under the pointer, empty turns it off
+    pub move_binding: Option<String>,
+    /// Modifiers and button that resize the window under the pointer from its closest corner
+    pub resize_binding: Option<String>,
+    /// Modifiers and button like `Alt+Left` that resize a floating window from the edge
+    /// under the pointer
+    pub edge_resize: Option<String>,
+    /// Logical pixels around a window border that count as its edge
+    pub resize_border: i32,
+    /// Where pointer motion goes while a button is held down on a window
+    pub grab_motion: GrabFocus,
+    /// Where scrolling goes while a button is held down on a window
+    pub grab_scroll: GrabFocus,
+    /// Whether clicking a window raises it as well as focusing it, rules can override it
+    pub click_raise: RaisePolicy,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            repeat_delay: 200,
+            repeat_rate: 25,
+            xkb_rules: String::new(),
+            xkb_model: String::new(),
+            xkb_layout: String::new(),
+            xkb_variant: String::new(),
+            xkb_options: None,
+            gesture_move_fingers: None,
+            per_window_layout: false,
+            move_binding: Some(String::from("Super+Left")),
+            resize_binding: Some(String::from("Super+Right")),
+            edge_resize: None,
+            resize_border: 12,
+            grab_motion: GrabFocus::Owner,
+            grab_scroll: GrabFocus::Owner,
+            click_raise: RaisePolicy::FocusAndRaise,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            export_environment: true,
+            portal: true,
+            color_scheme: ColorScheme::NoPreference,
+            notifications: false,
+            log_level: None,
+            log: LogConfig::default(),
+            shutdown_timeout: 3000,
+            keybindings: None,
+            pointer_bindings: Vec::new(),
+            outputs: Vec::new(),
+            auto_scale: true,
+            profiles: Vec::new(),
+            input: InputConfig::default(),
+            accessibility: AccessibilityConfig::default(),
+            frame_policy: FramePolicy::default(),
+            thumbnail_interval: 500,
+            private_apps: Vec::new(),
+            close_animation: 200,
+            max_closing_windows: 8,
+            min_visible: 64,
+            icon_theme: "hicolor".to_string(),
+            socket: None,
+            autostart: Vec::new(),
+            shell_clients: Vec::new(),
+            access: Vec::new(),
+            sandbox: SandboxConfig::default(),
+            shm_formats: ["abgr8888", "xbgr8888"].map(String::from).to_vec(),
+            auto_hide: AutoHideConfig::default(),
+            pip: PipConfig::default(),
+            workspaces: WorkspaceConfig::default(),
+            decisions: DecisionConfig::default(),
+            transfer_filter: TransferFilterConfig::default(),
+            watchdog: WatchdogConfig::default(),
+            directional_focus: DirectionalFocusConfig::default(),
+            restore_session: false,
+            barriers: Vec::new(),
+            pointer_warp: WarpConfig::default(),
+            idle: IdleConfig::default(),
+            blur: BlurConfig::default(),
+            power: PowerConfig::default(),
+            plugins: PluginConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Self {
+        let path = match config_path() {
+            Some(path) => path,
+            None => return Self::default(),
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => match toml::from_str(&contents) {
+                Ok(config) => {
+                    slog_scope::info!("Loaded config from {}", path.display());
+                    Self::clamped(config)
+                }
+                Err(err) => {
+                    slog_scope::warn!("Failed to parse {}: {}", path.display(), err);
+                    Self::default()
+                }
+            },
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Like [`Config::load`], but reports a broken file instead of falling back
+    pub fn check() -> anyhow::Result<Option<(PathBuf, Self)>> {
+        let path = match config_path() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(Some((path, Self::clamped(config))))
+    }
+
+    /// Brings settings that parsed but are out of range back into it
+    fn clamped(mut self) -> Self {
+        self.accessibility.clamp_cursor_scale();
+        self
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    xdg::BaseDirectories::with_prefix("electrum")
+        .ok()
+        .and_then(|dirs| dirs.find_config_file("electrum.toml"))
+}
@@ -0,0 +1,293 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    any::Any,
+    cell::Cell,
+    collections::{HashMap, VecDeque},
+    fs,
+    panic::{self, PanicInfo, UnwindSafe},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use backtrace::Backtrace;
+use serde::{Deserialize, Serialize};
+use smithay::desktop::Window;
+
+use crate::{
+    config::Autostart,
+    log::{LogRecord, LogState},
+    session,
+    shell::{
+        rules::is_descendant,
+        window::{WindowExt, WindowInfo},
+    },
+    state::{CommonState, State},
+};
+
+/// How often the state kept for a crash report is brought up to date
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+/// Seconds restored apps have to open their windows on their old output
+const RESTORE_TIMEOUT: u64 = 30;
+const SESSION_FILE: &str = "session.json";
+
+thread_local! {
+    /// Set while a panic is caught and handled, those are no crashes
+    static CATCHING: Cell<bool> = Cell::new(false);
+}
+
+/// An app that had a window open, started again by `restore_session`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionApp {
+    /// From `/proc/<pid>/cmdline`, quoted for `sh -c`
+    pub command: String,
+    pub app_id: Option<String>,
+    pub output: Option<String>,
+}
+
+/// What the compositor looked like about a second before a crash
+#[derive(Default)]
+struct Snapshot {
+    clients: Vec<String>,
+    windows: Vec<WindowInfo>,
+    session: Vec<SessionApp>,
+    /// The error the script stopped with, with its JS stack
+    script_error: Option<String>,
+    /// Processes started through `autostart`, they come back on their own
+    autostarted: Vec<u32>,
+    /// Command lines by pid, read once per client
+    commands: HashMap<u32, Option<String>>,
+    updated: Option<Instant>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CrashReport<'a> {
+    /// Milliseconds since the unix epoch
+    time: u128,
+    version: &'static str,
+    thread: Option<&'a str>,
+    message: String,
+    location: Option<String>,
+    backtrace: String,
+    clients: &'a [String],
+    windows: &'a [WindowInfo],
+    script_error: Option<&'a str>,
+    log: Vec<LogRecord>,
+}
+
+/// Writes a report to `$XDG_STATE_HOME/electrum/crashes` when the compositor panics. Everything
+/// in it is gathered ahead of time, the panicking thread may hold any lock of the compositor.
+#[derive(Clone, Default)]
+pub struct CrashReporter {
+    snapshot: Arc<Mutex<Snapshot>>,
+    records: Arc<Mutex<VecDeque<LogRecord>>>,
+}
+
+impl CrashReporter {
+    /// Installs the panic hook, the default hook still prints the panic first
+    pub fn install(log: &LogState) -> Self {
+        let reporter = CrashReporter {
+            snapshot: Arc::default(),
+            records: log.shared_records(),
+        };
+
+        let default_hook = panic::take_hook();
+        let hook_reporter = reporter.clone();
+        panic::set_hook(Box::new(move |info| {
+            default_hook(info);
+            if CATCHING.with(Cell::get) {
+                return;
+            }
+            match hook_reporter.write_report(info) {
+                Ok(path) => eprintln!("Crash report written to {}", path.display()),
+                Err(err) => eprintln!("Failed to write a crash report: {:#}", err),
+            }
+        }));
+        reporter
+    }
+
+    /// Brings the clients, windows and session up to date, at most once every second
+    pub fn update(&self, common: &CommonState) {
+        let mut guard = self.snapshot.lock().unwrap();
+        let snapshot = &mut *guard;
+        if matches!(snapshot.updated, Some(updated) if updated.elapsed() < UPDATE_INTERVAL) {
+            return;
+        }
+        snapshot.updated = Some(Instant::now());
+
+        snapshot.clients = common
+            .diagnostics
+            .client_ids()
+            .iter()
+            .map(|id| format!("{:?}", id))
+            .collect();
+        snapshot.windows = common.shell.window_infos();
+
+        let space = &common.shell.active_workspace().space;
+        let mut session: Vec<SessionApp> = Vec::new();
+        for window in space.windows() {
+            let info = window.info();
+            let pid = match info.pid {
+                Some(pid) => pid,
+                None => continue,
+            };
+            if snapshot.autostarted.iter().any(|a| is_descendant(pid, *a)) {
+                continue;
+            }
+            let command = snapshot
+                .commands
+                .entry(pid)
+                .or_insert_with(|| command_line(pid))
+                .clone();
+            if let Some(command) = command {
+                if !session.iter().any(|app| app.command == command) {
+                    session.push(SessionApp {
+                        command,
+                        app_id: info.app_id,
+                        output: window_output(common, window),
+                    });
+                }
+            }
+        }
+        snapshot.session = session;
+
+        let clients = &snapshot.windows;
+        snapshot
+            .commands
+            .retain(|pid, _| clients.iter().any(|info| info.pid == Some(*pid)));
+    }
+
+    /// Leaves autostarted programs out of the restored session
+    pub fn autostarted(&self, pid: u32) {
+        self.snapshot.lock().unwrap().autostarted.push(pid);
+    }
+
+    pub fn script_error(&self, error: String) {
+        self.snapshot.lock().unwrap().script_error = Some(error);
+    }
+
+    fn write_report(&self, info: &PanicInfo) -> anyhow::Result<PathBuf> {
+        let dirs = xdg::BaseDirectories::with_prefix("electrum")?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let path = dirs
+            .place_state_file(format!("crashes/crash-{}.json", time))
+            .context("Failed to create the crash report directory")?;
+
+        let empty = Snapshot::default();
+        let snapshot = self.snapshot.try_lock();
+        let snapshot = snapshot.as_deref().unwrap_or(&empty);
+        let log = self
+            .records
+            .try_lock()
+            .map(|records| records.iter().cloned().collect())
+            .unwrap_or_default();
+        let thread = std::thread::current();
+        let report = CrashReport {
+            time,
+            version: env!("CARGO_PKG_VERSION"),
+            thread: thread.name(),
+            message: panic_message(info.payload()),
+            location: info.location().map(|l| l.to_string()),
+            backtrace: format!("{:?}", Backtrace::new()),
+            clients: &snapshot.clients,
+            windows: &snapshot.windows,
+            script_error: snapshot.script_error.as_deref(),
+            log,
+        };
+        fs::write(&path, serde_json::to_vec_pretty(&report)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        // For `restore_session` on the next start
+        let session_path = dirs.place_state_file(SESSION_FILE)?;
+        fs::write(session_path, serde_json::to_vec(&snapshot.session)?)?;
+        Ok(path)
+    }
+}
+
+/// Runs `f` like `std::panic::catch_unwind`, without a crash report for a panic it catches
+pub fn catch_unwind<R>(f: impl FnOnce() -> R + UnwindSafe) -> std::thread::Result<R> {
+    let catching = CATCHING.with(|catching| catching.replace(true));
+    let result = panic::catch_unwind(f);
+    CATCHING.with(|c| c.set(catching));
+    result
+}
+
+impl State {
+    /// Starts the apps that had windows when the compositor last crashed, on the outputs they
+    /// were on. The session is only restored once, also with `restore_session` turned off.
+    pub fn restore_session(&mut self) {
+        let path = match xdg::BaseDirectories::with_prefix("electrum")
+            .ok()
+            .and_then(|dirs| dirs.find_state_file(SESSION_FILE))
+        {
+            Some(path) => path,
+            None => return,
+        };
+        let session = fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok(serde_json::from_slice::<Vec<SessionApp>>(&bytes)?));
+        if let Err(err) = fs::remove_file(&path) {
+            slog_scope::warn!("Failed to remove {}: {}", path.display(), err);
+        }
+        if !self.common.config.restore_session {
+            return;
+        }
+        let session = match session {
+            Ok(session) => session,
+            Err(err) => {
+                slog_scope::warn!("Failed to read the crashed session: {:#}", err);
+                return;
+            }
+        };
+
+        slog_scope::info!("Restoring {} apps of the crashed session", session.len());
+        for app in session {
+            let autostart = Autostart::Assigned {
+                command: app.command,
+                app_id: app.app_id,
+                executable: None,
+                workspace: None,
+                output: app.output,
+                timeout: RESTORE_TIMEOUT,
+            };
+            let pid = session::spawn(autostart.command(), &self.common.socket);
+            self.common.shell.rules.add_autostart(&autostart, pid);
+        }
+    }
+}
+
+fn window_output(common: &CommonState, window: &Window) -> Option<String> {
+    common
+        .shell
+        .active_workspace()
+        .space
+        .outputs_for_window(window)
+        .first()
+        .map(|output| output.name())
+}
+
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/// The command line of `pid`, each argument quoted for `sh -c`
+fn command_line(pid: u32) -> Option<String> {
+    let cmdline = fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let args = cmdline
+        .split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| format!("'{}'", String::from_utf8_lossy(arg).replace('\'', r"'\''")))
+        .collect::<Vec<_>>();
+    (!args.is_empty()).then(|| args.join(" "))
+}
@@ -0,0 +1,282 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use smithay::{
+    backend::input::{ButtonState, KeyState},
+    reexports::{
+        calloop::{
+            timer::{TimeoutAction, Timer},
+            RegistrationToken,
+        },
+        wayland_server::DisplayHandle,
+    },
+    wayland::seat::Seat,
+};
+
+use crate::{input::keybindings::BTN_LEFT, state::State};
+
+/// Shift, Control, Alt and Super on both sides, as evdev key codes
+const MODIFIER_KEYS: [u32; 8] = [42, 54, 29, 97, 56, 100, 125, 126];
+
+/// Input handling for users who can't hold several keys, hit them precisely or click, and
+/// less motion for users bothered by it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessibilityConfig {
+    /// Modifiers stay pressed after a tap until the next key, a second tap locks them
+    pub sticky_keys: bool,
+    /// Milliseconds a key has to be held before it counts as pressed
+    pub slow_keys: Option<u64>,
+    /// Milliseconds after a release in which the same key is ignored
+    pub bounce_keys: Option<u64>,
+    /// Milliseconds the pointer has to rest before it clicks by itself
+    pub dwell_click: Option<u64>,
+    /// Size multiplier of client cursors, between 1 and 4
+    pub cursor_scale: f64,
+    /// Switches workspaces at once instead of sliding between them
+    pub reduce_motion: bool,
+}
+
+impl Default for AccessibilityConfig {
+    fn default() -> Self {
+        Self {
+            sticky_keys: false,
+            slow_keys: None,
+            bounce_keys: None,
+            dwell_click: None,
+            cursor_scale: 1.0,
+            reduce_motion: false,
+        }
+    }
+}
+
+impl AccessibilityConfig {
+    /// Keeps the cursor scale between 1 and 4, a scale that is not a number at all is reset
+    pub fn clamp_cursor_scale(&mut self) {
+        self.cursor_scale = match self.cursor_scale {
+            scale if scale.is_nan() => 1.0,
+            scale => scale.clamp(1.0, 4.0),
+        };
+    }
+}
+
+#[derive(Default)]
+struct StickyKeys {
+    /// Tapped modifiers, released with the next key
+    latched: Vec<u32>,
+    /// Modifiers tapped twice, released by a third tap
+    locked: Vec<u32>,
+    /// The modifier currently held, as long as no other key was pressed with it
+    tapping: Option<u32>,
+    /// Modifier presses that were swallowed, so their releases are too
+    swallowed: HashSet<u32>,
+}
+
+/// Per-seat state of the accessibility filters
+#[derive(Default)]
+pub struct AccessibilityState {
+    sticky: RefCell<StickyKeys>,
+    /// Presses waiting for the slow keys delay
+    pending: RefCell<HashMap<u32, RegistrationToken>>,
+    /// Keys whose press was dropped, their release is dropped as well
+    dropped: RefCell<HashSet<u32>>,
+    /// Time of the last release per key, for bounce keys
+    released: RefCell<HashMap<u32, u32>>,
+    /// Clicks once the pointer stopped moving
+    dwell: RefCell<Option<RegistrationToken>>,
+}
+
+fn accessibility_state(seat: &Seat<State>) -> &AccessibilityState {
+    seat.user_data()
+        .insert_if_missing(AccessibilityState::default);
+    seat.user_data().get::<AccessibilityState>().unwrap()
+}
+
+impl State {
+    /// Entry point for every key event, ahead of keybindings and focus delivery
+    pub fn process_key(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &Seat<State>,
+        key_code: u32,
+        state: KeyState,
+        time: u32,
+    ) {
+        let config = self.common.accessibility;
+        let a11y = accessibility_state(seat);
+
+        match state {
+            KeyState::Pressed => {
+                if let Some(delay) = config.bounce_keys {
+                    let bounced = a11y
+                        .released
+                        .borrow()
+                        .get(&key_code)
+                        .map(|released| (time.wrapping_sub(*released) as u64) < delay)
+                        .unwrap_or(false);
+                    if bounced {
+                        a11y.dropped.borrow_mut().insert(key_code);
+                        return;
+                    }
+                }
+
+                if let Some(delay) = config.slow_keys {
+                    let seat = seat.clone();
+                    let token = self.common.event_loop_handle.insert_source(
+                        Timer::from_duration(Duration::from_millis(delay)),
+                        move |_, _, data| {
+                            let a11y = accessibility_state(&seat);
+                            if a11y.pending.borrow_mut().remove(&key_code).is_some() {
+                                let dh = data.display.handle();
+                                let time = time.wrapping_add(delay as u32);
+                                data.state.sticky_key(&dh, &seat, key_code, state, time);
+                            }
+                            TimeoutAction::Drop
+                        },
+                    );
+                    if let Ok(token) = token {
+                        a11y.pending.borrow_mut().insert(key_code, token);
+                        return;
+                    }
+                }
+            }
+            KeyState::Released => {
+                a11y.released.borrow_mut().insert(key_code, time);
+                if a11y.dropped.borrow_mut().remove(&key_code) {
+                    return;
+                }
+                // Let go before the slow keys delay, it never happened
+                if let Some(token) = a11y.pending.borrow_mut().remove(&key_code) {
+                    self.common.event_loop_handle.remove(token);
+                    return;
+                }
+            }
+        }
+
+        self.sticky_key(dh, seat, key_code, state, time);
+    }
+
+    fn sticky_key(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &Seat<State>,
+        key_code: u32,
+        state: KeyState,
+        time: u32,
+    ) {
+        if !self.common.accessibility.sticky_keys {
+            self.deliver_key(dh, seat, key_code, state, time);
+            return;
+        }
+
+        let is_modifier = MODIFIER_KEYS.contains(&key_code);
+        let mut to_release = Vec::new();
+        let deliver = {
+            let mut sticky = accessibility_state(seat).sticky.borrow_mut();
+            match (state, is_modifier) {
+                (KeyState::Pressed, true) => {
+                    if let Some(pos) = sticky.latched.iter().position(|k| *k == key_code) {
+                        // Second tap, keep it down until the third one
+                        sticky.latched.remove(pos);
+                        sticky.locked.push(key_code);
+                        sticky.swallowed.insert(key_code);
+                        false
+                    } else if let Some(pos) = sticky.locked.iter().position(|k| *k == key_code) {
+                        // Third tap, the release of this press lets go
+                        sticky.locked.remove(pos);
+                        false
+                    } else {
+                        sticky.tapping = Some(key_code);
+                        true
+                    }
+                }
+                (KeyState::Released, true) => {
+                    if sticky.swallowed.remove(&key_code) {
+                        false
+                    } else if sticky.tapping == Some(key_code) {
+                        sticky.tapping = None;
+                        sticky.latched.push(key_code);
+                        false
+                    } else {
+                        true
+                    }
+                }
+                (KeyState::Pressed, false) => {
+                    sticky.tapping = None;
+                    true
+                }
+                (KeyState::Released, false) => {
+                    to_release = std::mem::take(&mut sticky.latched);
+                    true
+                }
+            }
+        };
+
+        if deliver {
+            self.deliver_key(dh, seat, key_code, state, time);
+        }
+        for modifier in to_release {
+            self.deliver_key(dh, seat, modifier, KeyState::Released, time);
+        }
+    }
+
+    /// Restarts the dwell click timer of `seat`, called on every pointer motion
+    pub fn pointer_moved(&mut self, seat: &Seat<State>, time: u32) {
+        let a11y = accessibility_state(seat);
+        if let Some(token) = a11y.dwell.borrow_mut().take() {
+            self.common.event_loop_handle.remove(token);
+        }
+        let delay = match self.common.accessibility.dwell_click {
+            Some(delay) => delay,
+            None => return,
+        };
+
+        let owned_seat = seat.clone();
+        let token = self.common.event_loop_handle.insert_source(
+            Timer::from_duration(Duration::from_millis(delay)),
+            move |_, _, data| {
+                let seat = &owned_seat;
+                accessibility_state(seat).dwell.borrow_mut().take();
+                // Don't interfere with moves, resizes and held buttons
+                if !seat.get_pointer().map(|p| p.is_grabbed()).unwrap_or(true) {
+                    let dh = data.display.handle();
+                    let time = time.wrapping_add(delay as u32);
+                    let state = &mut data.state;
+                    state.pointer_button(&dh, seat, BTN_LEFT, ButtonState::Pressed, time);
+                    state.pointer_button(&dh, seat, BTN_LEFT, ButtonState::Released, time);
+                }
+                TimeoutAction::Drop
+            },
+        );
+        *a11y.dwell.borrow_mut() = token.ok();
+    }
+
+    /// Applies new settings, modifiers held down by sticky keys are let go when it gets disabled
+    pub fn set_accessibility(&mut self, dh: &DisplayHandle, mut config: AccessibilityConfig) {
+        config.clamp_cursor_scale();
+        let disable_sticky = self.common.accessibility.sticky_keys && !config.sticky_keys;
+        self.common.accessibility = config;
+        if !disable_sticky {
+            return;
+        }
+
+        for seat in self.common.seats.clone() {
+            let held = {
+                let mut sticky = accessibility_state(&seat).sticky.borrow_mut();
+                let mut held = std::mem::take(&mut sticky.latched);
+                held.append(&mut sticky.locked);
+                sticky.tapping = None;
+                held
+            };
+            for modifier in held {
+                self.deliver_key(dh, &seat, modifier, KeyState::Released, 0);
+            }
+        }
+    }
+}
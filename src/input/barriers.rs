@@ -0,0 +1,133 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use smithay::{
+    utils::{Logical, Point, Rectangle},
+    wayland::{output::Output, seat::Seat},
+};
+
+use crate::{
+    shell::direction::Direction,
+    state::{output::OutputExt, State},
+};
+
+/// Pressure against a barrier halves every this long, so pushing with pauses takes longer
+const PRESSURE_HALF_LIFE: Duration = Duration::from_millis(250);
+
+/// Holds the pointer back at the edge from one output to another
+#[derive(Debug, Clone, Deserialize)]
+pub struct BarrierConfig {
+    /// Output the pointer leaves
+    pub from: String,
+    /// Output the pointer enters, any if not set
+    pub to: Option<String>,
+    /// Edge of `from` the barrier is on, any if not set
+    pub edge: Option<Direction>,
+    /// Logical pixels the pointer has to be pushed past the edge to cross, it never crosses
+    /// without
+    pub pressure: Option<f64>,
+}
+
+/// How hard the pointer of a seat pushed against a barrier
+#[derive(Default)]
+struct BarrierPressure(Cell<Option<(f64, Instant)>>);
+
+impl BarrierPressure {
+    /// What is left of the pressure built up so far
+    fn current(&self) -> f64 {
+        self.0.get().map_or(0.0, |(total, last)| {
+            let half_lives = last.elapsed().as_secs_f64() / PRESSURE_HALF_LIFE.as_secs_f64();
+            total * 0.5f64.powf(half_lives)
+        })
+    }
+}
+
+fn pressure(seat: &Seat<State>) -> &BarrierPressure {
+    seat.user_data().insert_if_missing(BarrierPressure::default);
+    seat.user_data().get::<BarrierPressure>().unwrap()
+}
+
+/// The edge of `from` the pointer crosses to get onto `to`
+fn crossed_edge(from: Rectangle<i32, Logical>, to: Rectangle<i32, Logical>) -> Option<Direction> {
+    if to.loc.x >= from.loc.x + from.size.w {
+        Some(Direction::Right)
+    } else if to.loc.x + to.size.w <= from.loc.x {
+        Some(Direction::Left)
+    } else if to.loc.y >= from.loc.y + from.size.h {
+        Some(Direction::Down)
+    } else if to.loc.y + to.size.h <= from.loc.y {
+        Some(Direction::Up)
+    } else {
+        None
+    }
+}
+
+/// How far `position` is past `edge` of `geometry`
+fn overshoot(
+    geometry: Rectangle<f64, Logical>,
+    edge: Direction,
+    position: Point<f64, Logical>,
+) -> f64 {
+    match edge {
+        Direction::Left => geometry.loc.x - position.x,
+        Direction::Right => position.x - (geometry.loc.x + geometry.size.w),
+        Direction::Up => geometry.loc.y - position.y,
+        Direction::Down => position.y - (geometry.loc.y + geometry.size.h),
+    }
+    .max(0.0)
+}
+
+impl State {
+    /// Where the pointer of `seat` goes when moving to `position` on `target` from `current`,
+    /// by relative motion or jumping there with an absolute device. `None` lets it cross,
+    /// otherwise a barrier keeps it at the edge of `current` until it was pushed hard enough.
+    pub(super) fn pointer_barrier(
+        &self,
+        seat: &Seat<State>,
+        current: &Output,
+        target: &Output,
+        position: Point<f64, Logical>,
+    ) -> Option<Point<f64, Logical>> {
+        if current == target {
+            return None;
+        }
+
+        let geometry = current.geometry();
+        let edge = crossed_edge(geometry, target.geometry())?;
+        let (from, to) = (current.name(), target.name());
+        let barrier = self.common.config.barriers.iter().find(|barrier| {
+            barrier.from == from
+                && barrier.to.as_ref().map_or(true, |name| *name == to)
+                && barrier.edge.map_or(true, |side| side == edge)
+        })?;
+
+        let geometry = geometry.to_f64();
+        let pushed = overshoot(geometry, edge, position);
+        if let Some(required) = barrier.pressure {
+            let pressure = pressure(seat);
+            let total = pressure.current() + pushed;
+            if total >= required {
+                pressure.0.set(None);
+                return None;
+            }
+            pressure.0.set(Some((total, Instant::now())));
+        }
+
+        // Back onto the last pixel of `current` along the crossed edge
+        let mut held = position;
+        held.x = held
+            .x
+            .max(geometry.loc.x)
+            .min(geometry.loc.x + geometry.size.w - 1.0);
+        held.y = held
+            .y
+            .max(geometry.loc.y)
+            .min(geometry.loc.y + geometry.size.h - 1.0);
+        Some(held)
+    }
+}
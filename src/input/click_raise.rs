@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::cell::Cell;
+
+use serde::{Deserialize, Serialize};
+use smithay::{
+    desktop::WindowSurfaceType,
+    reexports::wayland_server::{protocol::wl_surface::WlSurface, DisplayHandle},
+    wayland::{seat::Seat, Serial},
+};
+
+use crate::{
+    shell::{focus::set_unraised, window::WindowExt},
+    state::State,
+};
+
+/// Milliseconds between two presses of a button on the same window that make a double click
+const DOUBLE_CLICK_TIME: u32 = 400;
+
+/// What a click on a window that does not have the focus does to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RaisePolicy {
+    /// Focuses the window and brings it to the front
+    FocusAndRaise,
+    /// Focuses the window where it is in the stack, other ways of focusing it still raise it
+    Focus,
+    /// Focuses the window where it is, a double click brings it to the front
+    DoubleClick,
+}
+
+impl Default for RaisePolicy {
+    fn default() -> Self {
+        RaisePolicy::FocusAndRaise
+    }
+}
+
+/// Window, button and time of the last press of a seat that went to a window
+#[derive(Default)]
+struct LastClick(Cell<Option<(usize, u32, u32)>>);
+
+impl State {
+    /// Focuses `surface` after `button` was pressed on it, raising its window if its policy or
+    /// else the one of `[input]` says so
+    pub(crate) fn click_focus(
+        &mut self,
+        dh: &DisplayHandle,
+        surface: Option<&WlSurface>,
+        seat: &Seat<State>,
+        button: u32,
+        serial: Serial,
+        time: u32,
+    ) {
+        let window = surface.and_then(|surface| {
+            self.common
+                .shell
+                .active_workspace()
+                .space
+                .window_for_surface(surface, WindowSurfaceType::ALL)
+                .cloned()
+        });
+        if let Some(window) = &window {
+            let policy = window
+                .click_raise()
+                .unwrap_or(self.common.config.input.click_raise);
+            let double_click = double_click(seat, window.id(), button, time);
+            let raise = match policy {
+                RaisePolicy::FocusAndRaise => true,
+                RaisePolicy::Focus => false,
+                RaisePolicy::DoubleClick => double_click,
+            };
+            set_unraised(window, !raise);
+        }
+        self.common.set_focus(dh, surface, seat, Some(serial));
+    }
+}
+
+/// Whether this press follows one of the same button on the same window closely enough to
+/// count as a double click. A third press starts over.
+fn double_click(seat: &Seat<State>, window: usize, button: u32, time: u32) -> bool {
+    seat.user_data().insert_if_missing(LastClick::default);
+    let last_click = &seat.user_data().get::<LastClick>().unwrap().0;
+    let double = match last_click.get() {
+        Some((last_window, last_button, last_time)) => {
+            last_window == window
+                && last_button == button
+                && time.wrapping_sub(last_time) <= DOUBLE_CLICK_TIME
+        }
+        None => false,
+    };
+    last_click.set(if double {
+        None
+    } else {
+        Some((window, button, time))
+    });
+    double
+}
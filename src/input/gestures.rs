@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+#[cfg(feature = "backend_libinput")]
+use std::cell::Cell;
+
+use smithay::{
+    backend::input::UnusedEvent,
+    utils::{Logical, Point},
+};
+#[cfg(feature = "backend_libinput")]
+use smithay::{
+    reexports::{
+        input::event::{
+            gesture::{
+                GestureEndEvent, GestureEvent as LibinputGestureEvent, GestureEventCoordinates,
+                GestureEventTrait, GestureSwipeEvent,
+            },
+            Event as LibinputEvent,
+        },
+        wayland_server::DisplayHandle,
+    },
+    wayland::{
+        seat::{MotionEvent, PointerGrabStartData, Seat},
+        SERIAL_COUNTER,
+    },
+};
+
+#[cfg(feature = "backend_libinput")]
+use crate::state::{output::active_output, State};
+
+/// Touchpad swipe gestures, as reported by libinput
+#[derive(Debug, Clone, Copy)]
+pub enum GestureEvent {
+    SwipeBegin {
+        fingers: u32,
+        time: u32,
+    },
+    SwipeUpdate {
+        delta: Point<f64, Logical>,
+        time: u32,
+    },
+    SwipeEnd {
+        cancelled: bool,
+        time: u32,
+    },
+}
+
+/// Backends report gestures as special events, if at all
+pub trait SpecialEventExt {
+    fn gesture(&self) -> Option<GestureEvent>;
+}
+
+impl SpecialEventExt for UnusedEvent {
+    fn gesture(&self) -> Option<GestureEvent> {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "backend_libinput")]
+impl SpecialEventExt for LibinputEvent {
+    fn gesture(&self) -> Option<GestureEvent> {
+        match self {
+            LibinputEvent::Gesture(LibinputGestureEvent::Swipe(swipe)) => Some(match swipe {
+                GestureSwipeEvent::Begin(event) => GestureEvent::SwipeBegin {
+                    fingers: event.finger_count() as u32,
+                    time: event.time(),
+                },
+                GestureSwipeEvent::Update(event) => GestureEvent::SwipeUpdate {
+                    delta: (event.dx(), event.dy()).into(),
+                    time: event.time(),
+                },
+                GestureSwipeEvent::End(event) => GestureEvent::SwipeEnd {
+                    cancelled: event.cancelled(),
+                    time: event.time(),
+                },
+                _ => return None,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Whether a swipe on this seat currently drives a move grab
+#[cfg(feature = "backend_libinput")]
+#[derive(Default)]
+struct SwipeMove(Cell<bool>);
+
+#[cfg(feature = "backend_libinput")]
+impl State {
+    /// Swipes with the configured number of fingers move the focused window, the
+    /// gesture delta is fed to the move grab as pointer motion.
+    pub fn process_gesture_event(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &Seat<State>,
+        event: GestureEvent,
+    ) {
+        seat.user_data().insert_if_missing(SwipeMove::default);
+        let moving = &seat.user_data().get::<SwipeMove>().unwrap().0;
+        let pointer = match seat.get_pointer() {
+            Some(pointer) => pointer,
+            None => return,
+        };
+
+        match event {
+            GestureEvent::SwipeBegin { fingers, .. } => {
+                if Some(fingers) != self.common.config.input.gesture_move_fingers
+                    || pointer.is_grabbed()
+                {
+                    return;
+                }
+                let workspace = self.common.shell.active_workspace();
+                let window = match workspace.focus_stack(seat).last() {
+                    Some(window) => window,
+                    None => return,
+                };
+
+                let start_data = PointerGrabStartData {
+                    focus: None,
+                    button: 0,
+                    location: pointer.current_location(),
+                };
+                self.common.shell.move_request(
+                    &window,
+                    seat,
+                    SERIAL_COUNTER.next_serial(),
+                    start_data,
+                );
+                moving.set(pointer.is_grabbed());
+            }
+            GestureEvent::SwipeUpdate { delta, time } if moving.get() => {
+                pointer.motion(
+                    self,
+                    dh,
+                    &MotionEvent {
+                        location: pointer.current_location() + delta,
+                        focus: None,
+                        serial: SERIAL_COUNTER.next_serial(),
+                        time,
+                    },
+                );
+            }
+            GestureEvent::SwipeEnd { cancelled, time } if moving.get() => {
+                // A cancelled swipe still leaves the window where it was dragged to
+                slog_scope::debug!("Swipe move ended (cancelled: {})", cancelled);
+                moving.set(false);
+                pointer.unset_grab(SERIAL_COUNTER.next_serial(), time);
+                let output = active_output(seat, &self.common);
+                self.common.shell.drop_move(dh, seat, &output);
+                self.common.constrain_windows();
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,159 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use serde::Deserialize;
+use smithay::{
+    reexports::wayland_server::{protocol::wl_surface::WlSurface, DisplayHandle},
+    utils::{Logical, Point},
+    wayland::{
+        seat::{
+            AxisFrame, ButtonEvent, Focus, MotionEvent, PointerGrab, PointerGrabStartData,
+            PointerInnerHandle, Seat,
+        },
+        Serial, SERIAL_COUNTER,
+    },
+};
+
+use crate::state::{cursor_shape::pointer_entered, State};
+
+/// Who gets pointer input while a button is held down on a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GrabFocus {
+    /// The surface the button was pressed on, until every button is let go. What Wayland
+    /// compositors and X11 with its implicit grabs do.
+    Owner,
+    /// Whatever is under the pointer. Button presses and releases still go to the surface the
+    /// first button was pressed on, so it sees every release it needs.
+    UnderPointer,
+}
+
+impl Default for GrabFocus {
+    fn default() -> Self {
+        GrabFocus::Owner
+    }
+}
+
+/// The implicit grab of a client while a button is held down, replacing the one of smithay when
+/// motion or scrolling go to what is under the pointer
+struct ClickGrab {
+    seat: Seat<State>,
+    start_data: PointerGrabStartData,
+    motion: GrabFocus,
+    scroll: GrabFocus,
+    /// The surface under the pointer as of the last motion
+    under: Option<(WlSurface, Point<i32, Logical>)>,
+    /// Where pointer events currently go
+    focus: Option<(WlSurface, Point<i32, Logical>)>,
+    /// Of the last motion or button event, for moving the focus before a scroll
+    time: u32,
+}
+
+impl ClickGrab {
+    /// Moves the pointer focus to `focus`, if it isn't there already
+    fn focus(
+        &mut self,
+        handle: &mut PointerInnerHandle<'_, State>,
+        focus: Option<(WlSurface, Point<i32, Logical>)>,
+        serial: Serial,
+        time: u32,
+    ) {
+        let surface = |focus: &Option<(WlSurface, Point<i32, Logical>)>| {
+            focus.as_ref().map(|(surface, _)| surface.clone())
+        };
+        if surface(&focus) != surface(&self.focus) {
+            pointer_entered(&self.seat, surface(&focus).as_ref(), serial);
+            handle.motion(handle.current_location(), focus.clone(), serial, time);
+            self.focus = focus;
+        }
+    }
+}
+
+impl PointerGrab<State> for ClickGrab {
+    fn motion(
+        &mut self,
+        _state: &mut State,
+        _dh: &DisplayHandle,
+        handle: &mut PointerInnerHandle<'_, State>,
+        event: &MotionEvent,
+    ) {
+        self.under = event.focus.clone();
+        self.time = event.time;
+        let focus = match self.motion {
+            GrabFocus::Owner => self.start_data.focus.clone(),
+            GrabFocus::UnderPointer => event.focus.clone(),
+        };
+        let surface = focus.as_ref().map(|(surface, _)| surface);
+        pointer_entered(&self.seat, surface, event.serial);
+        handle.motion(event.location, focus.clone(), event.serial, event.time);
+        self.focus = focus;
+    }
+
+    fn button(
+        &mut self,
+        _state: &mut State,
+        _dh: &DisplayHandle,
+        handle: &mut PointerInnerHandle<'_, State>,
+        event: &ButtonEvent,
+    ) {
+        self.time = event.time;
+        // The owner saw the first press, it gets every other press and release until the end
+        let owner = self.start_data.focus.clone();
+        self.focus(handle, owner, event.serial, event.time);
+        handle.button(event.button, event.state, event.serial, event.time);
+        if handle.current_pressed().is_empty() {
+            // Focus goes back to what is under the pointer
+            handle.unset_grab(event.serial, event.time);
+        }
+    }
+
+    fn axis(
+        &mut self,
+        _state: &mut State,
+        _dh: &DisplayHandle,
+        handle: &mut PointerInnerHandle<'_, State>,
+        details: AxisFrame,
+    ) {
+        let focus = match self.scroll {
+            GrabFocus::Owner => self.start_data.focus.clone(),
+            GrabFocus::UnderPointer => self.under.clone(),
+        };
+        let serial = SERIAL_COUNTER.next_serial();
+        self.focus(handle, focus, serial, self.time);
+        handle.axis(details);
+    }
+
+    fn start_data(&self) -> &PointerGrabStartData {
+        &self.start_data
+    }
+}
+
+impl State {
+    /// Called after a press reached a client without a grab going on. smithay grabs the pointer
+    /// for that client until every button is released, sending everything to it. If motion or
+    /// scrolling go under the pointer instead, a grab that does that takes over, under the
+    /// serial of the press so the client can still start a move or resize with it.
+    pub(crate) fn click_grab_started(&mut self, seat: &Seat<State>, serial: Serial, time: u32) {
+        let input = &self.common.config.input;
+        if input.grab_motion == GrabFocus::Owner && input.grab_scroll == GrabFocus::Owner {
+            return;
+        }
+        let pointer = seat.get_pointer().unwrap();
+        if !pointer.has_grab(serial) {
+            return;
+        }
+        let start_data = match pointer.grab_start_data() {
+            Some(start_data) => start_data,
+            None => return,
+        };
+        let grab = ClickGrab {
+            seat: seat.clone(),
+            motion: input.grab_motion,
+            scroll: input.grab_scroll,
+            under: start_data.focus.clone(),
+            focus: start_data.focus.clone(),
+            time,
+            start_data,
+        };
+        pointer.set_grab(grab, serial, Focus::Keep);
+    }
+}
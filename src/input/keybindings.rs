@@ -0,0 +1,398 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use anyhow::anyhow;
+use smithay::wayland::seat::ModifiersState;
+use xkbcommon::xkb::{self, keysyms, Keysym};
+
+use crate::{
+    config::{Action, Config, Keybinding},
+    runtime::{messages::RuntimeMessage, registry::RuntimeKeybinding},
+    state::CommonState,
+};
+
+/// The mode of bindings that don't name one, active unless a `mode` action switched away
+pub const DEFAULT_MODE: &str = "default";
+
+/// Button codes from linux/input-event-codes.h
+pub const BTN_LEFT: u32 = 0x110;
+pub const BTN_RIGHT: u32 = 0x111;
+pub const BTN_MIDDLE: u32 = 0x112;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+    logo: bool,
+}
+
+impl From<&ModifiersState> for Modifiers {
+    fn from(state: &ModifiersState) -> Self {
+        Self {
+            ctrl: state.ctrl,
+            alt: state.alt,
+            shift: state.shift,
+            logo: state.logo,
+        }
+    }
+}
+
+/// One key combination of a binding
+type Step = (Modifiers, Keysym);
+
+#[derive(Debug)]
+struct Binding {
+    /// A single combination, or a chord of several pressed one after another
+    keys: Vec<Step>,
+    /// `None` for the default mode
+    mode: Option<String>,
+    action: Action,
+    /// The focused window gets the keys as well
+    forward: bool,
+}
+
+impl Binding {
+    fn new(
+        keys: &str,
+        mode: Option<String>,
+        action: Action,
+        forward: bool,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            keys: parse_chord(keys)?,
+            mode: mode.filter(|mode| mode != DEFAULT_MODE),
+            action,
+            forward,
+        })
+    }
+
+    /// A media key like `XF86AudioPlay` on its own, bound in the default mode it works in every
+    /// mode
+    fn is_media_key(&self) -> bool {
+        match self.keys[..] {
+            [(mods, keysym)] => mods == Modifiers::default() && is_media_key(keysym),
+            _ => false,
+        }
+    }
+}
+
+/// What a key press amounts to with the bindings of the current mode
+pub enum KeyMatch {
+    /// `forward` if the focused window gets the key as well
+    Action {
+        action: Action,
+        forward: bool,
+    },
+    /// The press started or continued a chord, or ended one that matches nothing
+    Chord,
+    None,
+}
+
+/// Key combinations the compositor handles itself instead of forwarding them to clients
+#[derive(Debug, Default)]
+pub struct Keybindings {
+    configured: Vec<Binding>,
+    /// Registered by the committed generation of the runtime, configured ones take precedence
+    runtime: Vec<Binding>,
+    /// `None` while in the default mode
+    mode: Option<String>,
+    /// Steps of a chord pressed so far
+    pending: Vec<Step>,
+}
+
+impl Keybindings {
+    /// Without a main script a few bindings are needed to make the session usable at all
+    pub fn new(config: &Config, scripted: bool) -> Self {
+        let configured = match &config.keybindings {
+            Some(bindings) => bindings.clone(),
+            None if scripted => Vec::new(),
+            None => default_bindings(),
+        };
+
+        Self {
+            configured: configured
+                .into_iter()
+                .filter_map(|binding| {
+                    match Binding::new(&binding.keys, binding.mode, binding.action, binding.forward)
+                    {
+                        Ok(binding) => Some(binding),
+                        Err(err) => {
+                            slog_scope::warn!("Ignoring keybinding: {}", err);
+                            None
+                        }
+                    }
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Replaces every binding of the runtime, the keys were checked when they were registered
+    pub fn set_runtime(&mut self, bindings: &[RuntimeKeybinding]) {
+        self.runtime = bindings
+            .iter()
+            .filter_map(|binding| {
+                let action = Action::Runtime(binding.name.clone());
+                Binding::new(&binding.keys, binding.mode.clone(), action, binding.forward).ok()
+            })
+            .collect();
+        self.pending.clear();
+    }
+
+    /// Name of the active binding mode
+    pub fn mode(&self) -> &str {
+        self.mode.as_deref().unwrap_or(DEFAULT_MODE)
+    }
+
+    /// Switches to the bindings of `mode`, returns whether it was another one before
+    pub fn set_mode(&mut self, mode: &str) -> bool {
+        self.pending.clear();
+        if self.mode() == mode {
+            return false;
+        }
+        self.mode = (mode != DEFAULT_MODE).then(|| mode.to_string());
+        true
+    }
+
+    fn active(&self) -> impl Iterator<Item = &Binding> + Clone {
+        let mode = self.mode.as_deref();
+        self.configured
+            .iter()
+            .chain(self.runtime.iter())
+            .filter(move |binding| {
+                binding.mode.as_deref() == mode
+                    || (binding.mode.is_none() && binding.is_media_key())
+            })
+    }
+
+    /// The action of a single key combination of the current mode, ignoring chords. Matches
+    /// against the unshifted keysyms, so `Super+Shift+e` works as written.
+    pub fn find(&self, modifiers: &ModifiersState, keysyms: &[Keysym]) -> Option<&Action> {
+        let modifiers = Modifiers::from(modifiers);
+        self.active()
+            .find(|binding| match binding.keys[..] {
+                [(mods, keysym)] => mods == modifiers && keysyms.contains(&keysym),
+                _ => false,
+            })
+            .map(|binding| &binding.action)
+    }
+
+    /// Moves a chord along by a pressed key. Bindings of the keys pressed so far win over
+    /// chords continuing with more keys, a key no binding continues with ends the chord. Outside
+    /// of the default mode Escape goes back to it, unless it is bound there.
+    pub fn press(&mut self, modifiers: &ModifiersState, keysyms: &[Keysym]) -> KeyMatch {
+        // Letting go of Super between the steps of `Super+r h` is fine
+        if keysyms.iter().copied().all(is_modifier) {
+            return KeyMatch::None;
+        }
+        let modifiers = Modifiers::from(modifiers);
+        let step = self.pending.len();
+        let pending = &self.pending;
+        let mut matching = self.active().filter(|binding| {
+            binding.keys.len() > step
+                && binding.keys[..step] == pending[..]
+                && binding.keys[step].0 == modifiers
+                && keysyms.contains(&binding.keys[step].1)
+        });
+        let complete = matching
+            .clone()
+            .find(|binding| binding.keys.len() == step + 1)
+            .map(|binding| (binding.action.clone(), binding.forward));
+        let next = matching.next().map(|binding| binding.keys[step]);
+
+        if let Some((action, forward)) = complete {
+            self.pending.clear();
+            return KeyMatch::Action { action, forward };
+        }
+        if let Some(next) = next {
+            self.pending.push(next);
+            return KeyMatch::Chord;
+        }
+        if !self.pending.is_empty() {
+            self.pending.clear();
+            return KeyMatch::Chord;
+        }
+        if self.mode.is_some() && keysyms.contains(&keysyms::KEY_Escape) {
+            return KeyMatch::Action {
+                action: Action::Mode(DEFAULT_MODE.to_string()),
+                forward: false,
+            };
+        }
+        KeyMatch::None
+    }
+}
+
+impl CommonState {
+    /// Switches the binding mode and tells the runtime, so bars can show it
+    pub fn set_keybinding_mode(&mut self, mode: &str) {
+        if self.keybindings.set_mode(mode) {
+            let _ =
+                self.shell
+                    .active_workspace()
+                    .runtime_sender
+                    .send(RuntimeMessage::KeybindingMode {
+                        mode: mode.to_string(),
+                    });
+        }
+    }
+}
+
+fn is_modifier(keysym: Keysym) -> bool {
+    matches!(
+        keysym,
+        keysyms::KEY_Shift_L
+            ..=keysyms::KEY_Hyper_R
+                | keysyms::KEY_ISO_Level3_Shift
+                | keysyms::KEY_ISO_Level5_Shift
+                | keysyms::KEY_Mode_switch
+    )
+}
+
+/// Keysyms of the XF86 range, volume, playback, brightness and the like
+fn is_media_key(keysym: Keysym) -> bool {
+    (0x1008_ff00..=0x1008_ffff).contains(&keysym)
+}
+
+/// Sets the modifier named `part`, if it names one
+fn parse_modifier(part: &str, modifiers: &mut Modifiers) -> bool {
+    match part.to_lowercase().as_str() {
+        "ctrl" | "control" => modifiers.ctrl = true,
+        "alt" => modifiers.alt = true,
+        "shift" => modifiers.shift = true,
+        "super" | "logo" | "mod4" => modifiers.logo = true,
+        _ => return false,
+    }
+    true
+}
+
+pub fn parse_keys(keys: &str) -> anyhow::Result<(Modifiers, Keysym)> {
+    let mut modifiers = Modifiers::default();
+    let mut keysym = None;
+
+    for part in keys.split('+').map(str::trim) {
+        match part {
+            _ if parse_modifier(part, &mut modifiers) => {}
+            _ if keysym.is_none() => {
+                let sym = xkb::keysym_from_name(part, xkb::KEYSYM_CASE_INSENSITIVE);
+                if sym == xkb::keysyms::KEY_NoSymbol {
+                    return Err(anyhow!("Unknown key {} in {}", part, keys));
+                }
+                keysym = Some(sym);
+            }
+            _ => return Err(anyhow!("More than one key in {}", keys)),
+        }
+    }
+
+    keysym
+        .map(|keysym| (modifiers, keysym))
+        .ok_or_else(|| anyhow!("No key in {}", keys))
+}
+
+/// Parses a chord of key combinations separated by spaces, like `Super+r h`
+pub fn parse_chord(keys: &str) -> anyhow::Result<Vec<(Modifiers, Keysym)>> {
+    let steps = keys
+        .split_whitespace()
+        .map(parse_keys)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if steps.is_empty() {
+        return Err(anyhow!("No key in {}", keys));
+    }
+    Ok(steps)
+}
+
+/// A mouse button, or a click of the scroll wheel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerTrigger {
+    Button(u32),
+    Scroll(ScrollDirection),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScrollDirection {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// Parses a modifiers and mouse button combination like `Alt+Left`, into the button code
+pub fn parse_button(keys: &str) -> anyhow::Result<(Modifiers, u32)> {
+    match parse_pointer_trigger(keys)? {
+        (modifiers, PointerTrigger::Button(button)) => Ok((modifiers, button)),
+        (_, PointerTrigger::Scroll(_)) => Err(anyhow!("{} is scrolling, not a button", keys)),
+    }
+}
+
+/// Parses a modifiers and mouse button or scroll combination like `Super+WheelUp`
+pub fn parse_pointer_trigger(keys: &str) -> anyhow::Result<(Modifiers, PointerTrigger)> {
+    let mut modifiers = Modifiers::default();
+    let mut trigger = None;
+
+    for part in keys.split('+').map(str::trim) {
+        if parse_modifier(part, &mut modifiers) {
+            continue;
+        }
+        if trigger.is_some() {
+            return Err(anyhow!("More than one button in {}", keys));
+        }
+        trigger = Some(match part.to_lowercase().as_str() {
+            "left" => PointerTrigger::Button(BTN_LEFT),
+            "right" => PointerTrigger::Button(BTN_RIGHT),
+            "middle" => PointerTrigger::Button(BTN_MIDDLE),
+            "wheelup" => PointerTrigger::Scroll(ScrollDirection::Up),
+            "wheeldown" => PointerTrigger::Scroll(ScrollDirection::Down),
+            "wheelleft" => PointerTrigger::Scroll(ScrollDirection::Left),
+            "wheelright" => PointerTrigger::Scroll(ScrollDirection::Right),
+            _ => return Err(anyhow!("Unknown button {} in {}", part, keys)),
+        });
+    }
+
+    trigger
+        .map(|trigger| (modifiers, trigger))
+        .ok_or_else(|| anyhow!("No button in {}", keys))
+}
+
+/// Ctrl+Alt+Shift+BackSpace enters safe mode, it can't be bound or taken over by the script
+pub fn is_safe_mode_key(modifiers: &ModifiersState, keysyms: &[Keysym]) -> bool {
+    modifiers.ctrl
+        && modifiers.alt
+        && modifiers.shift
+        && !modifiers.logo
+        && keysyms.contains(&keysyms::KEY_BackSpace)
+}
+
+fn default_bindings() -> Vec<Keybinding> {
+    let terminal = std::env::var("TERMINAL").unwrap_or_else(|_| String::from("foot"));
+    vec![
+        Keybinding {
+            keys: String::from("Super+Return"),
+            mode: None,
+            forward: false,
+            action: Action::Spawn(terminal),
+        },
+        Keybinding {
+            keys: String::from("Super+q"),
+            mode: None,
+            forward: false,
+            action: Action::Close,
+        },
+        Keybinding {
+            keys: String::from("Super+f"),
+            mode: None,
+            forward: false,
+            action: Action::Fullscreen,
+        },
+        Keybinding {
+            keys: String::from("Super+Shift+e"),
+            mode: None,
+            forward: false,
+            action: Action::Quit,
+        },
+        Keybinding {
+            keys: String::from("Alt+Tab"),
+            mode: None,
+            forward: false,
+            action: Action::SwitchWindows,
+        },
+    ]
+}
@@ -0,0 +1,221 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::cell::Cell;
+
+use anyhow::anyhow;
+use smithay::{
+    desktop::Window,
+    reexports::wayland_server::DisplayHandle,
+    wayland::{seat::Seat, SERIAL_COUNTER},
+};
+
+use super::add_keyboard;
+use crate::{
+    config::InputConfig,
+    runtime::messages::RuntimeMessage,
+    state::{CommonState, State},
+};
+
+/// One entry of the layout list, written like `de` or `de(nodeadkeys)`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyboardLayout {
+    pub layout: String,
+    pub variant: String,
+}
+
+impl KeyboardLayout {
+    pub fn parse(name: &str) -> Self {
+        match name.trim().split_once('(') {
+            Some((layout, variant)) => KeyboardLayout {
+                layout: layout.to_string(),
+                variant: variant.trim_end_matches(')').to_string(),
+            },
+            None => KeyboardLayout {
+                layout: name.trim().to_string(),
+                variant: String::new(),
+            },
+        }
+    }
+
+    pub fn name(&self) -> String {
+        if self.variant.is_empty() {
+            self.layout.clone()
+        } else {
+            format!("{}({})", self.layout, self.variant)
+        }
+    }
+}
+
+/// The layouts that can be switched between, and which one is in use.
+///
+/// Smithay can't change the group of a keymap, so switching compiles a new keymap with the
+/// active layout first.
+#[derive(Debug)]
+pub struct KeyboardLayouts {
+    layouts: Vec<KeyboardLayout>,
+    active: usize,
+}
+
+impl KeyboardLayouts {
+    pub fn new(config: &InputConfig) -> Self {
+        let mut variants = config.xkb_variant.split(',');
+        let layouts = config
+            .xkb_layout
+            .split(',')
+            .map(|layout| KeyboardLayout {
+                layout: layout.trim().to_string(),
+                variant: variants.next().unwrap_or_default().trim().to_string(),
+            })
+            .collect();
+        Self { layouts, active: 0 }
+    }
+
+    pub fn active(&self) -> &KeyboardLayout {
+        &self.layouts[self.active]
+    }
+
+    /// `xkb_layout` and `xkb_variant` with the active layout first
+    fn xkb_names(&self) -> (String, String) {
+        let mut ordered = self.layouts.clone();
+        ordered.rotate_left(self.active);
+        let layout = ordered.iter().map(|l| l.layout.as_str());
+        let variant = ordered.iter().map(|l| l.variant.as_str());
+        (
+            layout.collect::<Vec<_>>().join(","),
+            variant.collect::<Vec<_>>().join(","),
+        )
+    }
+}
+
+/// The layout a window used when it last had focus
+#[derive(Default)]
+struct WindowLayout(Cell<Option<usize>>);
+
+impl CommonState {
+    /// Replaces the layout list, the first one becomes active
+    pub fn set_keyboard_layouts(
+        &mut self,
+        dh: &DisplayHandle,
+        names: Vec<String>,
+    ) -> anyhow::Result<()> {
+        if names.is_empty() {
+            return Err(anyhow!("At least one keyboard layout is needed"));
+        }
+        let layouts = names
+            .iter()
+            .map(|name| KeyboardLayout::parse(name))
+            .collect();
+        let previous = std::mem::replace(
+            &mut self.keyboard_layouts,
+            KeyboardLayouts { layouts, active: 0 },
+        );
+        if let Err(err) = self.apply_keyboard_layout(dh) {
+            self.keyboard_layouts = previous;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Switches to the layout at `index`, or the next one
+    pub fn switch_keyboard_layout(
+        &mut self,
+        dh: &DisplayHandle,
+        index: Option<usize>,
+    ) -> anyhow::Result<()> {
+        let count = self.keyboard_layouts.layouts.len();
+        let index = index.unwrap_or((self.keyboard_layouts.active + 1) % count);
+        if index >= count {
+            return Err(anyhow!("There are only {} keyboard layouts", count));
+        }
+        if index == self.keyboard_layouts.active {
+            return Ok(());
+        }
+
+        let previous = std::mem::replace(&mut self.keyboard_layouts.active, index);
+        if let Err(err) = self.apply_keyboard_layout(dh) {
+            self.keyboard_layouts.active = previous;
+            return Err(err);
+        }
+        if let Some(window) = self.focused_window() {
+            remember_layout(&window, index);
+        }
+        Ok(())
+    }
+
+    /// Restores the layout `window` used last, called when it gets focus
+    pub fn restore_window_layout(&mut self, dh: &DisplayHandle, window: &Window) {
+        if !self.config.input.per_window_layout {
+            return;
+        }
+        let remembered = window
+            .user_data()
+            .get::<WindowLayout>()
+            .and_then(|layout| layout.0.get());
+        match remembered {
+            Some(index) if index < self.keyboard_layouts.layouts.len() => {
+                if let Err(err) = self.switch_keyboard_layout(dh, Some(index)) {
+                    slog_scope::warn!("Failed to restore keyboard layout: {}", err);
+                }
+            }
+            _ => remember_layout(window, self.keyboard_layouts.active),
+        }
+    }
+
+    fn focused_window(&self) -> Option<Window> {
+        self.shell
+            .active_workspace()
+            .focus_stack(&self.last_active_seat)
+            .last()
+    }
+
+    /// Gives `seat` a keyboard with the active layout, focusing the window it focused last
+    pub fn add_seat_keyboard(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &mut Seat<State>,
+    ) -> anyhow::Result<()> {
+        let (layout, variant) = self.keyboard_layouts.xkb_names();
+        add_keyboard(dh, seat, &self.config.input, &layout, &variant)
+            .map_err(|err| anyhow!("Failed to compile keymap {}: {:?}", layout, err))?;
+        let focus = self
+            .shell
+            .active_workspace()
+            .focus_stack(seat)
+            .last()
+            .map(|window| window.toplevel().wl_surface().clone());
+        self.set_focus(dh, focus.as_ref(), seat, None);
+        Ok(())
+    }
+
+    /// Gives every seat with a keyboard one with the new keymap, keeping its focus
+    fn apply_keyboard_layout(&mut self, dh: &DisplayHandle) -> anyhow::Result<()> {
+        let (layout, variant) = self.keyboard_layouts.xkb_names();
+        for seat in self.seats.clone().iter_mut() {
+            // Seats without a keyboard get the new keymap once one is plugged in
+            let focus = match seat.get_keyboard() {
+                Some(keyboard) => keyboard.current_focus(),
+                None => continue,
+            };
+            let keyboard = add_keyboard(dh, seat, &self.config.input, &layout, &variant)
+                .map_err(|err| anyhow!("Failed to compile keymap {}: {:?}", layout, err))?;
+            keyboard.set_focus(dh, focus.as_ref(), SERIAL_COUNTER.next_serial());
+        }
+
+        let runtime_sender = &self.shell.active_workspace().runtime_sender;
+        let _ = runtime_sender.send(RuntimeMessage::KeyboardLayout {
+            index: self.keyboard_layouts.active,
+            name: self.keyboard_layouts.active().name(),
+        });
+        Ok(())
+    }
+}
+
+fn remember_layout(window: &Window, index: usize) {
+    window.user_data().insert_if_missing(WindowLayout::default);
+    window
+        .user_data()
+        .get::<WindowLayout>()
+        .unwrap()
+        .0
+        .set(Some(index));
+}
@@ -2,36 +2,82 @@
 
 use smithay::backend::input::{
     Axis, AxisSource, ButtonState, Device, DeviceCapability, Event, InputBackend, InputEvent,
-    PointerAxisEvent, PointerButtonEvent, PointerMotionAbsoluteEvent, PointerMotionEvent, KeyboardKeyEvent,
+    PointerAxisEvent, PointerButtonEvent, PointerMotionAbsoluteEvent, PointerMotionEvent, KeyboardKeyEvent, KeyState,
 };
 
-use smithay::desktop::{layer_map_for_output, WindowSurfaceType};
+use smithay::desktop::{layer_map_for_output, Kind, WindowSurfaceType};
 use smithay::reexports::wayland_server::protocol::wl_pointer::{self};
 use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
-use smithay::reexports::wayland_server::DisplayHandle;
+use smithay::reexports::wayland_server::{DisplayHandle, Resource};
 use smithay::utils::{Logical, Point, Rectangle};
 use smithay::wayland::output::Output;
-use smithay::wayland::seat::{AxisFrame, ButtonEvent, CursorImageStatus, MotionEvent, Seat, FilterResult, XkbConfig};
+use smithay::wayland::data_device::set_data_device_focus;
+use smithay::wayland::primary_selection::set_primary_focus;
+use smithay::wayland::seat::{keysyms, AxisFrame, ButtonEvent, CursorImageStatus, KeyboardError, KeyboardHandle, KeysymHandle, ModifiersState, MotionEvent, Seat, FilterResult, XkbConfig};
 use smithay::wayland::shell::wlr_layer::Layer as WlrLayer;
 use smithay::wayland::SERIAL_COUNTER;
 use std::cell::RefCell;
 use std::collections::HashMap;
 
+use crate::backend::renderer::color::{output_filter, set_output_filter};
+use crate::config::{Action, InputConfig};
 use crate::id::id_gen;
+use crate::input::gestures::SpecialEventExt;
+use crate::input::keybindings::{is_safe_mode_key, KeyMatch, Keybindings, PointerTrigger, ScrollDirection};
+use crate::runtime::messages::RuntimeMessage;
 use crate::shell::grab::SeatMoveGrabState;
+use crate::shell::switcher::SwitcherKey;
+use crate::shell::window::WindowExt;
 use crate::shell::workspace::Workspace;
+use crate::state::cursor_shape::{pointer_entered, reset_cursor_shape};
 use crate::state::output::{active_output, set_active_output, OutputExt};
+use crate::state::transfers::TransferFilter;
 use crate::state::State;
 
+pub mod accessibility;
+pub mod barriers;
+pub mod click_raise;
+pub mod gestures;
+pub mod grab_focus;
+pub mod keybindings;
+pub mod layouts;
+pub mod pointer_bindings;
+pub mod seats;
+pub mod warp;
+
 id_gen!(next_seat_id, SEAT_ID, SEAT_IDS);
 
 #[repr(transparent)]
 pub struct SeatId(pub usize);
 
+/// Keys whose press the compositor consumed, their release is not sent to clients either
+#[derive(Default)]
+pub struct SuppressedKeys(RefCell<Vec<u32>>);
+/// Buttons whose press went to a pointer binding, their release is not sent to clients either
 #[derive(Default)]
-pub struct SupressedKeys(RefCell<Vec<u32>>);
+pub struct SuppressedButtons(SuppressedKeys);
+/// Input devices of a seat by id, the seat has a keyboard or pointer while one of them does
 #[derive(Default)]
 pub struct Devices(RefCell<HashMap<String, Vec<DeviceCapability>>>);
+/// What the seat was created as, for listing it over IPC
+pub struct SeatName(pub String);
+
+impl SuppressedKeys {
+    fn add(&self, key_code: u32) {
+        let mut keys = self.0.borrow_mut();
+        if !keys.contains(&key_code) {
+            keys.push(key_code);
+        }
+    }
+
+    /// Whether the release of `key_code` is swallowed, only once per press
+    fn take(&self, key_code: u32) -> bool {
+        let mut keys = self.0.borrow_mut();
+        let len = keys.len();
+        keys.retain(|key| *key != key_code);
+        keys.len() != len
+    }
+}
 
 impl Default for SeatId {
     fn default() -> SeatId {
@@ -76,33 +122,132 @@ impl Devices {
     pub fn has_device<D: Device>(&self, device: &D) -> bool {
         self.0.borrow().contains_key(&device.id())
     }
+
+    fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
 }
 
-pub fn add_seat(dh: &DisplayHandle, name: String) -> Seat<State> {
-    let mut seat = Seat::<State>::new(dh, name, None);
+/// Creates a seat without capabilities, it gets a keyboard and pointer once devices of that kind are added
+pub fn add_seat(dh: &DisplayHandle, name: String, transfers: &TransferFilter) -> Seat<State> {
+    let seat = Seat::<State>::new(dh, name.clone(), None);
     let userdata = seat.user_data();
+    userdata.insert_if_missing(|| SeatName(name));
+    userdata.insert_if_missing(|| transfers.clone());
     // userdata.insert_if_missing(SeatId::default);
     userdata.insert_if_missing(Devices::default);
-    userdata.insert_if_missing(SupressedKeys::default);
+    userdata.insert_if_missing(SuppressedKeys::default);
     userdata.insert_if_missing(SeatMoveGrabState::default);
     userdata.insert_if_missing(|| RefCell::new(CursorImageStatus::Default));
 
+    seat
+}
+
+pub(crate) fn add_pointer(seat: &mut Seat<State>) {
     let owned_seat = seat.clone();
     seat.add_pointer(move |status| {
+        reset_cursor_shape(&owned_seat);
         *owned_seat
             .user_data()
             .get::<RefCell<CursorImageStatus>>()
             .unwrap()
             .borrow_mut() = status;
     });
+}
+
+/// Adds a keyboard to `seat`, replacing the current one. Clients are told to get the new one.
+pub(crate) fn add_keyboard(
+    dh: &DisplayHandle,
+    seat: &mut Seat<State>,
+    config: &InputConfig,
+    layout: &str,
+    variant: &str,
+) -> Result<KeyboardHandle, KeyboardError> {
+    let xkb_config = XkbConfig {
+        rules: &config.xkb_rules,
+        model: &config.xkb_model,
+        layout,
+        variant,
+        options: config.xkb_options.clone(),
+    };
+    // Selections are offered to whichever client has keyboard focus, if it may have them
+    let dh = dh.clone();
     seat.add_keyboard(
-        XkbConfig::default(),
-        200, 
-        25,
-        move |_, _|{()})
-    .expect("Failed to initialize keyboard");
+        xkb_config,
+        config.repeat_delay,
+        config.repeat_rate,
+        move |seat, focus| {
+            match seat.user_data().get::<TransferFilter>() {
+                Some(transfers) => transfers.focus_changed(seat, focus),
+                None => {
+                    let client = focus.and_then(|surface| dh.get_client(surface.id()).ok());
+                    set_data_device_focus(&dh, seat, client.clone());
+                    set_primary_focus(&dh, seat, client);
+                }
+            }
+        })
+}
 
-    seat
+/// Keys kept from the focused window
+enum Intercepted {
+    Action(Action),
+    Switcher(SwitcherKey),
+    /// A step of a chord, or the key ending one that matches nothing
+    Chord,
+    /// The release of a key whose press was intercepted
+    Release,
+    SafeMode,
+}
+
+/// What a key does while the window switcher is open, pressing the binding again moves on
+fn switcher_key(keybindings: &Keybindings, modifiers: &ModifiersState, handle: &KeysymHandle<'_>, state: KeyState) -> SwitcherKey {
+    if state == KeyState::Released {
+        return SwitcherKey::Ignore;
+    }
+    if let Some(Action::SwitchWindows) = keybindings.find(modifiers, handle.raw_syms()) {
+        return SwitcherKey::Next;
+    }
+    match handle.modified_sym() {
+        keysyms::KEY_Escape => SwitcherKey::Cancel,
+        keysyms::KEY_ISO_Left_Tab | keysyms::KEY_Up | keysyms::KEY_Left => SwitcherKey::Previous,
+        keysyms::KEY_Tab | keysyms::KEY_Down | keysyms::KEY_Right => SwitcherKey::Next,
+        _ => SwitcherKey::Ignore,
+    }
+}
+
+/// The direction of a wheel click, vertical scrolling wins
+fn scroll_direction(horizontal: Option<f64>, vertical: Option<f64>) -> Option<ScrollDirection> {
+    match (horizontal.unwrap_or(0.0), vertical.unwrap_or(0.0)) {
+        (_, v) if v < 0.0 => Some(ScrollDirection::Up),
+        (_, v) if v > 0.0 => Some(ScrollDirection::Down),
+        (h, _) if h < 0.0 => Some(ScrollDirection::Left),
+        (h, _) if h > 0.0 => Some(ScrollDirection::Right),
+        _ => None,
+    }
+}
+
+/// What an input event is counted as in the metrics, `None` for devices coming and going
+fn input_kind<B: InputBackend>(event: &InputEvent<B>) -> Option<&'static str> {
+    let kind = match event {
+        InputEvent::DeviceAdded { .. } | InputEvent::DeviceRemoved { .. } => return None,
+        InputEvent::Keyboard { .. } => "keyboard",
+        InputEvent::PointerMotion { .. } | InputEvent::PointerMotionAbsolute { .. } => {
+            "pointer_motion"
+        }
+        InputEvent::PointerButton { .. } => "pointer_button",
+        InputEvent::PointerAxis { .. } => "pointer_axis",
+        InputEvent::TouchDown { .. }
+        | InputEvent::TouchMotion { .. }
+        | InputEvent::TouchUp { .. }
+        | InputEvent::TouchCancel { .. }
+        | InputEvent::TouchFrame { .. } => "touch",
+        InputEvent::TabletToolAxis { .. }
+        | InputEvent::TabletToolProximity { .. }
+        | InputEvent::TabletToolTip { .. }
+        | InputEvent::TabletToolButton { .. } => "tablet",
+        InputEvent::Special(_) => "other",
+    };
+    Some(kind)
 }
 
 impl State {
@@ -110,30 +255,33 @@ impl State {
         &mut self,
         dh: &DisplayHandle,
         event: InputEvent<B>,
-    ) {
+    ) where
+        B::SpecialEvent: SpecialEventExt,
+    {
+        let arrival = match &event {
+            InputEvent::DeviceAdded { .. } | InputEvent::DeviceRemoved { .. } => None,
+            _ => self.common.latency.arrived(),
+        };
+        if let Some(kind) = input_kind(&event) {
+            self.common.metrics.input_event(kind);
+            self.common.idle_activity();
+        }
+
         match event {
             InputEvent::DeviceAdded { device } => {
-                let seat = &mut self.common.last_active_seat;
+                let seat = self.common.last_active_seat.clone();
                 let userdata = seat.user_data();
                 let devices = userdata.get::<Devices>().unwrap();
-                for cap in devices.add_device(&device) {
-                    match cap {
-                        // TODO: Handle touch, tablet
-                        _ => {}
-                    }
-                }
+                let caps = devices.add_device(&device);
+                self.add_capabilities(dh, &seat, &caps);
             }
             InputEvent::DeviceRemoved { device } => {
-                for seat in &mut self.common.seats {
+                for seat in self.common.seats.clone().iter() {
                     let userdata = seat.user_data();
                     let devices = userdata.get::<Devices>().unwrap();
                     if devices.has_device(&device) {
-                        for cap in devices.remove_device(&device) {
-                            match cap {
-                                // TODO: Handle touch, tablet
-                                _ => {}
-                            }
-                        }
+                        let caps = devices.remove_device(&device);
+                        self.remove_capabilities(dh, seat, &caps);
                         break;
                     }
                 }
@@ -147,14 +295,7 @@ impl State {
                     let userdata = seat.user_data();
                     let devices = userdata.get::<Devices>().unwrap();
                     if devices.has_device(&device) {
-                        seat.get_keyboard().unwrap().input(
-                            dh, 
-                            key_code, 
-                            state, 
-                            SERIAL_COUNTER.next_serial(), 
-                            time,
-                            //TODO: filter out shell keyboard shortcuts
-                            |_, _|{FilterResult::Forward::<()>});
+                        self.process_key(dh, seat, key_code, state, time);
                     }
                 }
             }
@@ -169,13 +310,19 @@ impl State {
                         let mut position = seat.get_pointer().unwrap().current_location();
                         position += event.delta();
 
-                        let output = self
+                        let mut output = self
                             .common
                             .shell
                             .outputs()
                             .find(|output| output.geometry().to_f64().contains(position))
                             .cloned()
                             .unwrap_or(current_output.clone());
+                        let held =
+                            self.pointer_barrier(seat, &current_output, &output, position);
+                        if let Some(held) = held {
+                            position = held;
+                            output = current_output.clone();
+                        }
                         if output != current_output {
                             set_active_output(seat, &output);
                         }
@@ -201,6 +348,11 @@ impl State {
                             output_geometry,
                             &workspace,
                         );
+                        let focus = under.as_ref().map(|(surface, _)| surface.clone());
+                        let under = self.common.transfers.drag_target(seat, under);
+                        if !seat.get_pointer().unwrap().is_grabbed() {
+                            pointer_entered(seat, under.as_ref().map(|(surface, _)| surface), serial);
+                        }
                         seat.get_pointer().unwrap().motion(
                             self,
                             dh,
@@ -211,6 +363,9 @@ impl State {
                                 time: event.time(),
                             },
                         );
+                        self.cancel_warp(seat);
+                        self.pointer_moved(seat, event.time());
+                        self.auto_hide_pointer_moved(position, focus.as_ref());
 
                         break;
                     }
@@ -222,10 +377,28 @@ impl State {
                     let userdata = seat.user_data();
                     let devices = userdata.get::<Devices>().unwrap();
                     if devices.has_device(&device) {
-                        let output = active_output(seat, &self.common);
+                        let active = active_output(seat, &self.common);
+                        let active_geometry = active.geometry();
+                        let mut position = active_geometry.loc.to_f64()
+                            + event.position_transformed(active_geometry.size);
+
+                        // Barriers hold back jumps from one output to another as well
+                        let previous = seat.get_pointer().unwrap().current_location();
+                        let current_output = self
+                            .common
+                            .shell
+                            .output_at(previous)
+                            .cloned()
+                            .unwrap_or_else(|| active.clone());
+                        let mut output =
+                            self.common.shell.output_at(position).cloned().unwrap_or(active);
+                        let held =
+                            self.pointer_barrier(seat, &current_output, &output, position);
+                        if let Some(held) = held {
+                            position = held;
+                            output = current_output;
+                        }
                         let geometry = output.geometry();
-                        let position =
-                            geometry.loc.to_f64() + event.position_transformed(geometry.size);
                         let relative_pos = self
                             .common
                             .shell
@@ -239,6 +412,11 @@ impl State {
                             geometry,
                             &workspace,
                         );
+                        let focus = under.as_ref().map(|(surface, _)| surface.clone());
+                        let under = self.common.transfers.drag_target(seat, under);
+                        if !seat.get_pointer().unwrap().is_grabbed() {
+                            pointer_entered(seat, under.as_ref().map(|(surface, _)| surface), serial);
+                        }
                         seat.get_pointer().unwrap().motion(
                             self,
                             dh,
@@ -249,6 +427,9 @@ impl State {
                                 time: event.time(),
                             },
                         );
+                        self.cancel_warp(seat);
+                        self.pointer_moved(seat, event.time());
+                        self.auto_hide_pointer_moved(position, focus.as_ref());
                         break;
                     }
                 }
@@ -259,112 +440,8 @@ impl State {
                     let userdata = seat.user_data();
                     let devices = userdata.get::<Devices>().unwrap();
                     if devices.has_device(&device) {
-                        let serial = SERIAL_COUNTER.next_serial();
                         let button = event.button_code();
-                        let state = match event.state() {
-                            ButtonState::Pressed => {
-                                // change the keyboard focus unless the pointer or keyboard is grabbed
-                                // We test for any matching surface type here but always use the root
-                                // (in case of a window the toplevel) surface for the focus.
-                                // see: https://gitlab.freedesktop.org/wayland/wayland/-/issues/294
-                                if !seat.get_pointer().unwrap().is_grabbed()
-                                    && !seat.get_keyboard().map(|k| k.is_grabbed()).unwrap_or(false)
-                                {
-                                    let output = active_output(seat, &self.common);
-                                    let pos = seat.get_pointer().unwrap().current_location();
-                                    let output_geo = output.geometry();
-                                    let relative_pos = self
-                                        .common
-                                        .shell
-                                        .space_relative_output_geometry(pos, &output);
-                                    let workspace = self.common.shell.active_workspace_mut();
-                                    let layers = layer_map_for_output(&output);
-                                    let mut under = None;
-
-                                    if let Some(window) = workspace.get_fullscreen(&output) {
-                                        if let Some(layer) =
-                                            layers.layer_under(WlrLayer::Overlay, relative_pos)
-                                        {
-                                            if layer.can_receive_keyboard_focus() {
-                                                let layer_loc =
-                                                    layers.layer_geometry(layer).unwrap().loc;
-                                                under = layer
-                                                    .surface_under(
-                                                        pos - output_geo.loc.to_f64()
-                                                            - layer_loc.to_f64(),
-                                                        WindowSurfaceType::ALL,
-                                                    )
-                                                    .map(|(_, _)| layer.wl_surface().clone());
-                                            }
-                                        } else {
-                                            under = window
-                                                .surface_under(
-                                                    pos - output_geo.loc.to_f64(),
-                                                    WindowSurfaceType::ALL,
-                                                )
-                                                .map(|(_, _)| {
-                                                    window.toplevel().wl_surface().clone()
-                                                });
-                                        }
-                                    } else {
-                                        if let Some(layer) = layers
-                                            .layer_under(WlrLayer::Overlay, relative_pos)
-                                            .or_else(|| {
-                                                layers.layer_under(WlrLayer::Top, relative_pos)
-                                            })
-                                        {
-                                            if layer.can_receive_keyboard_focus() {
-                                                let layer_loc =
-                                                    layers.layer_geometry(layer).unwrap().loc;
-                                                under = layer
-                                                    .surface_under(
-                                                        pos - output_geo.loc.to_f64()
-                                                            - layer_loc.to_f64(),
-                                                        WindowSurfaceType::ALL,
-                                                    )
-                                                    .map(|(_, _)| layer.wl_surface().clone());
-                                            }
-                                        } else if let Some((window, _, _)) = workspace
-                                            .space
-                                            .surface_under(relative_pos, WindowSurfaceType::ALL)
-                                        {
-                                            under = Some(window.toplevel().wl_surface().clone());
-                                        } else if let Some(layer) =
-                                            layers.layer_under(WlrLayer::Bottom, pos).or_else(
-                                                || layers.layer_under(WlrLayer::Background, pos),
-                                            )
-                                        {
-                                            if layer.can_receive_keyboard_focus() {
-                                                let layer_loc =
-                                                    layers.layer_geometry(layer).unwrap().loc;
-                                                under = layer
-                                                    .surface_under(
-                                                        pos - output_geo.loc.to_f64()
-                                                            - layer_loc.to_f64(),
-                                                        WindowSurfaceType::ALL,
-                                                    )
-                                                    .map(|(_, _)| layer.wl_surface().clone());
-                                            }
-                                        };
-                                    }
-
-                                    self.common
-                                        .set_focus(dh, under.as_ref(), seat, Some(serial));
-                                }
-                                wl_pointer::ButtonState::Pressed
-                            }
-                            ButtonState::Released => wl_pointer::ButtonState::Released,
-                        };
-                        seat.get_pointer().unwrap().button(
-                            self,
-                            dh,
-                            &ButtonEvent {
-                                button,
-                                state,
-                                serial,
-                                time: event.time(),
-                            },
-                        );
+                        self.pointer_button(dh, seat, button, event.state(), event.time());
                         break;
                     }
                 }
@@ -392,6 +469,16 @@ impl State {
                         let horizontal_amount_discrete = event.amount_discrete(Axis::Horizontal);
                         let vertical_amount_discrete = event.amount_discrete(Axis::Vertical);
 
+                        // Wheel clicks bound on the desktop or a window don't scroll anything
+                        let direction = scroll_direction(horizontal_amount_discrete, vertical_amount_discrete);
+                        if let Some(direction) = direction {
+                            if !seat.get_pointer().unwrap().is_grabbed()
+                                && self.context_pointer_binding(dh, seat, PointerTrigger::Scroll(direction))
+                            {
+                                break;
+                            }
+                        }
+
                         {
                             let mut frame = AxisFrame::new(event.time()).source(source);
                             if horizontal_amount != 0.0 {
@@ -433,8 +520,303 @@ impl State {
             InputEvent::TabletToolProximity { event: _ } => {}
             InputEvent::TabletToolTip { event: _ } => {}
             InputEvent::TabletToolButton { event: _ } => {}
+            #[cfg(feature = "backend_libinput")]
+            InputEvent::Special(event) => {
+                if let Some(gesture) = event.gesture() {
+                    let seat = self.common.last_active_seat.clone();
+                    self.process_gesture_event(dh, &seat, gesture);
+                }
+            }
+            #[cfg(not(feature = "backend_libinput"))]
             InputEvent::Special(_) => {}
         }
+
+        if let Some(arrival) = arrival {
+            self.common.latency.delivered(arrival);
+        }
+    }
+
+    /// Presses or releases a pointer button, focusing what is under the pointer on press
+    pub(crate) fn pointer_button(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &Seat<State>,
+        button: u32,
+        state: ButtonState,
+        time: u32,
+    ) {
+        let serial = SERIAL_COUNTER.next_serial();
+        seat.user_data().insert_if_missing(SuppressedButtons::default);
+        let suppressed = seat.user_data().get::<SuppressedButtons>().unwrap();
+        let grabbed = seat.get_pointer().unwrap().is_grabbed()
+            || seat.get_keyboard().map(|k| k.is_grabbed()).unwrap_or(false);
+        match state {
+            // The client under the pointer never sees the button
+            ButtonState::Pressed if !grabbed && self.context_pointer_binding(dh, seat, PointerTrigger::Button(button)) => {
+                suppressed.0.add(button);
+                return;
+            }
+            ButtonState::Released if suppressed.0.take(button) => return,
+            _ => {}
+        }
+        let state = match state {
+            ButtonState::Pressed => {
+                // change the keyboard focus unless the pointer or keyboard is grabbed
+                // We test for any matching surface type here but always use the root
+                // (in case of a window the toplevel) surface for the focus.
+                // see: https://gitlab.freedesktop.org/wayland/wayland/-/issues/294
+                if !seat.get_pointer().unwrap().is_grabbed()
+                    && !seat.get_keyboard().map(|k| k.is_grabbed()).unwrap_or(false)
+                    && !self.start_pointer_binding(dh, seat, button, serial)
+                {
+                    let pos = seat.get_pointer().unwrap().current_location();
+                    // The pointer may have come to rest on a picture-in-picture window since it
+                    // last moved, the press goes to it then
+                    if !self.common.shell.pip.is_empty() {
+                        self.move_pointer(dh, seat, pos);
+                    }
+                    let output = active_output(seat, &self.common);
+                    let output_geo = output.geometry();
+                    let relative_pos = self
+                        .common
+                        .shell
+                        .space_relative_output_geometry(pos, &output);
+                    let shaded = self.common.shell.shaded_under(relative_pos);
+                    let workspace = self.common.shell.active_workspace_mut();
+                    let layers = layer_map_for_output(&output);
+                    let mut under = None;
+                    let mut unshade = None;
+
+                    if let Some(filling) = workspace.get_filling(&output) {
+                        if let Some(layer) =
+                            layers.layer_under(WlrLayer::Overlay, relative_pos)
+                        {
+                            if layer.can_receive_keyboard_focus() {
+                                let layer_loc =
+                                    layers.layer_geometry(layer).unwrap().loc;
+                                under = layer
+                                    .surface_under(
+                                        pos - output_geo.loc.to_f64()
+                                            - layer_loc.to_f64(),
+                                        WindowSurfaceType::ALL,
+                                    )
+                                    .map(|(_, _)| layer.wl_surface().clone());
+                            }
+                        } else {
+                            let window_pos =
+                                filling.to_window(pos - output_geo.loc.to_f64());
+                            let window = filling.window;
+                            under = window
+                                .surface_under(window_pos, WindowSurfaceType::ALL)
+                                .map(|(_, _)| {
+                                    window.toplevel().wl_surface().clone()
+                                });
+                        }
+                    } else {
+                        if let Some(layer) = layers
+                            .layer_under(WlrLayer::Overlay, relative_pos)
+                            .or_else(|| {
+                                layers.layer_under(WlrLayer::Top, relative_pos)
+                            })
+                        {
+                            if layer.can_receive_keyboard_focus() {
+                                let layer_loc =
+                                    layers.layer_geometry(layer).unwrap().loc;
+                                under = layer
+                                    .surface_under(
+                                        pos - output_geo.loc.to_f64()
+                                            - layer_loc.to_f64(),
+                                        WindowSurfaceType::ALL,
+                                    )
+                                    .map(|(_, _)| layer.wl_surface().clone());
+                            }
+                        } else if let Some(window) = shaded {
+                            // Clicking the bar of a shaded window brings it back
+                            under = Some(window.toplevel().wl_surface().clone());
+                            unshade = Some(window);
+                        } else if let Some((window, _, _)) =
+                            workspace.surface_under(relative_pos)
+                        {
+                            under = Some(window.toplevel().wl_surface().clone());
+                        } else if let Some(layer) =
+                            layers.layer_under(WlrLayer::Bottom, pos).or_else(
+                                || layers.layer_under(WlrLayer::Background, pos),
+                            )
+                        {
+                            if layer.can_receive_keyboard_focus() {
+                                let layer_loc =
+                                    layers.layer_geometry(layer).unwrap().loc;
+                                under = layer
+                                    .surface_under(
+                                        pos - output_geo.loc.to_f64()
+                                            - layer_loc.to_f64(),
+                                        WindowSurfaceType::ALL,
+                                    )
+                                    .map(|(_, _)| layer.wl_surface().clone());
+                            }
+                        };
+                    }
+
+                    if let Some(window) = unshade {
+                        self.common.shell.set_shaded(&window, false);
+                    }
+                    self.click_focus(dh, under.as_ref(), seat, button, serial, time);
+                }
+                wl_pointer::ButtonState::Pressed
+            }
+            ButtonState::Released => wl_pointer::ButtonState::Released,
+        };
+        let implicit_grab = state == wl_pointer::ButtonState::Pressed && !seat.get_pointer().unwrap().is_grabbed();
+        seat.get_pointer().unwrap().button(
+            self,
+            dh,
+            &ButtonEvent {
+                button,
+                state,
+                serial,
+                time,
+            },
+        );
+        if implicit_grab {
+            self.click_grab_started(seat, serial, time);
+        }
+    }
+
+    /// Hands a key to the keyboard, after the accessibility filters
+    pub(crate) fn deliver_key(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &Seat<State>,
+        key_code: u32,
+        state: KeyState,
+        time: u32,
+    ) {
+        // Keys of a device that ended up on a seat without a keyboard go nowhere
+        let keyboard = match seat.get_keyboard() {
+            Some(keyboard) => keyboard,
+            None => return,
+        };
+        let keybindings = &mut self.common.keybindings;
+        let switching = self.common.switcher.is_open();
+        let mut confirm_switcher = false;
+        let mut forwarded = None;
+        seat.user_data().insert_if_missing(SuppressedKeys::default);
+        let suppressed = seat.user_data().get::<SuppressedKeys>().unwrap();
+        let intercepted = keyboard.input(
+            dh,
+            key_code,
+            state,
+            SERIAL_COUNTER.next_serial(),
+            time,
+            |modifiers, handle| {
+                if state == KeyState::Released && suppressed.take(key_code) {
+                    return FilterResult::Intercept(Intercepted::Release);
+                }
+                // Ahead of everything the config or the script could have broken
+                if state == KeyState::Pressed && is_safe_mode_key(modifiers, handle.raw_syms()) {
+                    return FilterResult::Intercept(Intercepted::SafeMode);
+                }
+                if switching {
+                    // Let the modifier release through, the client saw it being pressed
+                    if state == KeyState::Released && !(modifiers.ctrl || modifiers.alt || modifiers.logo) {
+                        confirm_switcher = true;
+                        return FilterResult::Forward;
+                    }
+                    return FilterResult::Intercept(Intercepted::Switcher(switcher_key(keybindings, modifiers, &handle, state)));
+                }
+                if state == KeyState::Pressed {
+                    match keybindings.press(modifiers, handle.raw_syms()) {
+                        KeyMatch::Action { action, forward: true } => forwarded = Some(action),
+                        KeyMatch::Action { action, forward: false } => return FilterResult::Intercept(Intercepted::Action(action)),
+                        KeyMatch::Chord => return FilterResult::Intercept(Intercepted::Chord),
+                        KeyMatch::None => {}
+                    }
+                }
+                FilterResult::Forward
+            });
+        // Clients never saw the press, so they don't get the release either
+        if state == KeyState::Pressed && intercepted.is_some() {
+            suppressed.add(key_code);
+        }
+        match intercepted {
+            Some(Intercepted::Action(action)) => self.handle_action(dh, seat, action),
+            Some(Intercepted::Switcher(key)) => self.common.switcher_key(key),
+            Some(Intercepted::SafeMode) => self.common.enter_safe_mode(dh),
+            Some(Intercepted::Chord) | Some(Intercepted::Release) | None => {}
+        }
+        if let Some(action) = forwarded {
+            self.handle_action(dh, seat, action);
+        }
+        if confirm_switcher {
+            self.common.confirm_switcher(dh);
+        }
+    }
+
+    fn handle_action(&mut self, dh: &DisplayHandle, seat: &Seat<State>, action: Action) {
+        match action {
+            Action::Spawn(command) => {
+                crate::session::spawn(&command, &self.common.socket);
+            }
+            Action::Close => {
+                let workspace = self.common.shell.active_workspace();
+                let focused = workspace.focus_stack(seat).last();
+                #[allow(irrefutable_let_patterns)]
+                if let Some(Kind::Xdg(xdg)) = focused.as_ref().map(|window| window.toplevel()) {
+                    xdg.send_close();
+                }
+            }
+            Action::Fullscreen => {
+                let output = active_output(seat, &self.common);
+                let workspace = self.common.shell.active_workspace_mut();
+                let focused = workspace.focus_stack(seat).last();
+                if let Some(window) = focused {
+                    if workspace.is_fullscreen(&window) {
+                        workspace.unfullscreen_request(&window);
+                    } else {
+                        workspace.fullscreen_request(&window, &output);
+                    }
+                }
+            }
+            Action::ToggleShade => {
+                let focused = self.common.shell.active_workspace().focus_stack(seat).last();
+                if let Some(window) = focused {
+                    self.common.shell.set_shaded(&window, window.shaded().is_none());
+                }
+            }
+            Action::ToggleFilter(filter) => {
+                let output = active_output(seat, &self.common);
+                let filter = match output_filter(&output) {
+                    Some(current) if current == filter => None,
+                    _ => Some(filter),
+                };
+                if let Err(err) = set_output_filter(&output, filter) {
+                    slog_scope::warn!("{:?}", err);
+                }
+            }
+            Action::Focus(direction) => {
+                self.focus_direction(dh, seat, direction);
+            }
+            Action::CycleWindows(cycle) => {
+                self.cycle_windows(dh, seat, cycle);
+            }
+            Action::SwitchWindows => self.common.open_switcher(seat),
+            Action::Workspace(name) => {
+                if let Err(err) = self.common.switch_workspace(dh, &name) {
+                    slog_scope::warn!("{}", err);
+                }
+            }
+            Action::WorkspaceBackAndForth => {
+                if let Err(err) = self.common.workspace_back_and_forth(dh) {
+                    slog_scope::warn!("{}", err);
+                }
+            }
+            Action::Quit => self.common.request_shutdown(),
+            Action::Mode(mode) => self.common.set_keybinding_mode(&mode),
+            Action::Runtime(name) => {
+                let generation = self.common.runtime_registry.generation();
+                let _ = self.common.shell.active_workspace().runtime_sender.send(RuntimeMessage::Keybinding { name, generation });
+            }
+        }
     }
 
     pub fn surface_under(
@@ -445,11 +827,9 @@ impl State {
         workspace: &Workspace,
     ) -> Option<(WlSurface, Point<i32, Logical>)> {
         let layers = layer_map_for_output(output);
-        if let Some(window) = workspace.get_fullscreen(output) {
-            if let Some(layer) = layers
-                .layer_under(WlrLayer::Overlay, relative_pos)
-                .or_else(|| layers.layer_under(WlrLayer::Top, relative_pos))
-            {
+        if let Some(filling) = workspace.get_filling(output) {
+            // Top surfaces are not drawn above fullscreen or magnified windows
+            if let Some(layer) = layers.layer_under(WlrLayer::Overlay, relative_pos) {
                 let layer_loc = layers.layer_geometry(layer).unwrap().loc;
                 layer
                     .surface_under(
@@ -458,9 +838,13 @@ impl State {
                     )
                     .map(|(s, loc)| (s, loc + layer_loc + output_geo.loc))
             } else {
-                window
-                    .surface_under(global_pos - output_geo.loc.to_f64(), WindowSurfaceType::ALL)
-                    .map(|(s, loc)| (s, loc + output_geo.loc))
+                // Scaled back into the window, the origin is made up so that the pointer ends
+                // up at the right surface local position
+                let window_pos = filling.to_window(global_pos - output_geo.loc.to_f64());
+                filling
+                    .window
+                    .surface_under(window_pos, WindowSurfaceType::ALL)
+                    .map(|(s, loc)| (s, (global_pos - window_pos + loc.to_f64()).to_i32_round()))
             }
         } else {
             if let Some(layer) = layers
@@ -474,10 +858,7 @@ impl State {
                         WindowSurfaceType::ALL,
                     )
                     .map(|(s, loc)| (s, loc + layer_loc + output_geo.loc))
-            } else if let Some((_, surface, loc)) = workspace
-                .space
-                .surface_under(relative_pos, WindowSurfaceType::ALL)
-            {
+            } else if let Some((_, surface, loc)) = workspace.surface_under(relative_pos) {
                 Some((surface, loc + (global_pos - relative_pos).to_i32_round()))
             } else if let Some(layer) = layers
                 .layer_under(WlrLayer::Bottom, relative_pos)
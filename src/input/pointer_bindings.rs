@@ -0,0 +1,404 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use serde::{Deserialize, Serialize};
+use smithay::{
+    desktop::{layer_map_for_output, Kind, Window},
+    reexports::{
+        wayland_protocols::xdg::shell::server::xdg_toplevel::{ResizeEdge, State as XdgState},
+        wayland_server::DisplayHandle,
+    },
+    utils::{Logical, Point, Rectangle},
+    wayland::{
+        output::Output,
+        seat::{PointerGrabStartData, Seat},
+        shell::wlr_layer::Layer as WlrLayer,
+        Serial, SERIAL_COUNTER,
+    },
+};
+
+use crate::{
+    backend::renderer::shade::SHADE_HEIGHT,
+    config::{Action, Config},
+    input::keybindings::{parse_button, parse_pointer_trigger, Modifiers, PointerTrigger},
+    runtime::{messages::RuntimeMessage, registry::RuntimePointerBinding},
+    shell::window::WindowExt,
+    state::{
+        output::{active_output, OutputExt},
+        State,
+    },
+};
+
+/// Where the pointer has to be for a pointer binding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PointerContext {
+    /// The desktop, with no window or panel other than a background under the pointer
+    Root,
+    /// The bar of a shaded window, or the top of a window where client side title bars are
+    Titlebar,
+    /// The edge of a window, as wide as `resize_border`
+    Border,
+    /// Anywhere on a window, usually together with a modifier
+    Window,
+    /// The outermost pixels of an output, whatever is there. Bindings here don't focus the
+    /// window under the pointer.
+    Edge,
+}
+
+#[derive(Debug)]
+struct ContextBinding {
+    modifiers: Modifiers,
+    trigger: PointerTrigger,
+    context: PointerContext,
+    action: Action,
+}
+
+impl ContextBinding {
+    fn new(button: &str, context: PointerContext, action: Action) -> anyhow::Result<Self> {
+        let (modifiers, trigger) = parse_pointer_trigger(button)?;
+        Ok(Self {
+            modifiers,
+            trigger,
+            context,
+            action,
+        })
+    }
+}
+
+/// Modifier and mouse button combinations that act on the window under the pointer, and
+/// buttons and scrolling bound in a context
+#[derive(Debug, Default)]
+pub struct PointerBindings {
+    /// Moves the window, wherever it was grabbed
+    window_move: Option<(Modifiers, u32)>,
+    /// Resizes from the corner closest to the pointer, wherever the window was grabbed
+    window_resize: Option<(Modifiers, u32)>,
+    /// Resizes from the edge the pointer is close to
+    edge_resize: Option<(Modifiers, u32)>,
+    /// Logical pixels around the window border that count as its edge
+    border: i32,
+    configured: Vec<ContextBinding>,
+    /// Registered by the committed generation of the runtime, configured ones take precedence
+    runtime: Vec<ContextBinding>,
+}
+
+impl PointerBindings {
+    pub fn new(config: &Config) -> Self {
+        let input = &config.input;
+        Self {
+            window_move: input.move_binding.as_deref().and_then(parse_binding),
+            window_resize: input.resize_binding.as_deref().and_then(parse_binding),
+            edge_resize: input.edge_resize.as_deref().and_then(parse_binding),
+            border: input.resize_border.max(1),
+            configured: config
+                .pointer_bindings
+                .iter()
+                .filter_map(|binding| {
+                    ContextBinding::new(&binding.button, binding.context, binding.action.clone())
+                        .map_err(|err| slog_scope::warn!("Ignoring pointer binding: {}", err))
+                        .ok()
+                })
+                .collect(),
+            runtime: Vec::new(),
+        }
+    }
+
+    /// Replaces every binding of the runtime, the buttons were checked when they were registered
+    pub fn set_runtime(&mut self, bindings: &[RuntimePointerBinding]) {
+        self.runtime = bindings
+            .iter()
+            .filter_map(|binding| {
+                let action = Action::Runtime(binding.name.clone());
+                ContextBinding::new(&binding.button, binding.context, action).ok()
+            })
+            .collect();
+    }
+
+    /// The binding of `trigger` in the first of `contexts` that has one
+    fn find(
+        &self,
+        modifiers: Modifiers,
+        trigger: PointerTrigger,
+        contexts: &[PointerContext],
+    ) -> Option<(PointerContext, &Action)> {
+        contexts.iter().find_map(|context| {
+            self.configured
+                .iter()
+                .chain(self.runtime.iter())
+                .find(|binding| {
+                    binding.context == *context
+                        && binding.modifiers == modifiers
+                        && binding.trigger == trigger
+                })
+                .map(|binding| (*context, &binding.action))
+        })
+    }
+}
+
+impl PointerContext {
+    pub fn name(self) -> &'static str {
+        match self {
+            PointerContext::Root => "root",
+            PointerContext::Titlebar => "titlebar",
+            PointerContext::Border => "border",
+            PointerContext::Window => "window",
+            PointerContext::Edge => "edge",
+        }
+    }
+}
+
+/// An empty binding turns it off
+fn parse_binding(binding: &str) -> Option<(Modifiers, u32)> {
+    if binding.is_empty() {
+        return None;
+    }
+    parse_button(binding)
+        .map_err(|err| slog_scope::warn!("Ignoring pointer binding: {}", err))
+        .ok()
+}
+
+impl State {
+    /// Runs the binding of `trigger` in the context under the pointer of `seat`, if there is one.
+    /// Windows get focus first, so actions like `close` act on the window under the pointer.
+    pub(crate) fn context_pointer_binding(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &Seat<State>,
+        trigger: PointerTrigger,
+    ) -> bool {
+        let modifiers = match seat.get_keyboard() {
+            Some(keyboard) => Modifiers::from(&keyboard.modifier_state()),
+            None => return false,
+        };
+        let (contexts, window) = match self.pointer_contexts(seat) {
+            Some(under) => under,
+            None => return false,
+        };
+        let bindings = &self.common.pointer_bindings;
+        let (context, action) = match bindings.find(modifiers, trigger, &contexts) {
+            Some((context, action)) => (context, action.clone()),
+            None => return false,
+        };
+        let window = window.filter(|_| context != PointerContext::Edge);
+
+        if let Some(window) = &window {
+            let surface = window.toplevel().wl_surface().clone();
+            let serial = SERIAL_COUNTER.next_serial();
+            self.common
+                .set_focus(dh, Some(&surface), seat, Some(serial));
+        }
+        match action {
+            Action::Runtime(name) => {
+                let generation = self.common.runtime_registry.generation();
+                let runtime_sender = &self.common.shell.active_workspace().runtime_sender;
+                let _ = runtime_sender.send(RuntimeMessage::PointerBinding {
+                    name,
+                    generation,
+                    context: context.name().to_string(),
+                    window: window.map(|window| window.id()),
+                });
+            }
+            action => self.handle_action(dh, seat, action),
+        }
+        true
+    }
+
+    /// The contexts the pointer of `seat` is in, the most specific first, and the window they
+    /// belong to. Panels and popups above the desktop are in none, except at the output edge.
+    fn pointer_contexts(
+        &self,
+        seat: &Seat<State>,
+    ) -> Option<(Vec<PointerContext>, Option<Window>)> {
+        let pos = seat.get_pointer()?.current_location();
+        let output = active_output(seat, &self.common);
+        let under = self.contexts_under(pos, &output);
+        let geometry = output.geometry().to_f64();
+        let at_edge = pos.x < geometry.loc.x + 1.0
+            || pos.y < geometry.loc.y + 1.0
+            || pos.x >= geometry.loc.x + geometry.size.w - 1.0
+            || pos.y >= geometry.loc.y + geometry.size.h - 1.0;
+        if !at_edge {
+            return under;
+        }
+        let (mut contexts, window) = under.unwrap_or_default();
+        contexts.insert(0, PointerContext::Edge);
+        Some((contexts, window))
+    }
+
+    fn contexts_under(
+        &self,
+        pos: Point<f64, Logical>,
+        output: &Output,
+    ) -> Option<(Vec<PointerContext>, Option<Window>)> {
+        let shell = &self.common.shell;
+        let relative_pos = shell.space_relative_output_geometry(pos, output);
+        let workspace = shell.active_workspace();
+        let layers = layer_map_for_output(output);
+
+        let above = [WlrLayer::Overlay, WlrLayer::Top];
+        if above
+            .into_iter()
+            .any(|layer| layers.layer_under(layer, relative_pos).is_some())
+        {
+            return None;
+        }
+        if let Some(filling) = workspace.get_filling(output) {
+            return Some((vec![PointerContext::Window], Some(filling.window)));
+        }
+        if let Some(window) = shell.shaded_under(relative_pos) {
+            let contexts = vec![PointerContext::Titlebar, PointerContext::Window];
+            return Some((contexts, Some(window)));
+        }
+        let window = match workspace.surface_under(relative_pos) {
+            Some((window, _, _)) => window,
+            None if layers.layer_under(WlrLayer::Bottom, relative_pos).is_some() => return None,
+            None => return Some((vec![PointerContext::Root], None)),
+        };
+        let location = workspace.space.window_location(&window)?;
+        let geometry = Rectangle::from_loc_and_size(location, window.geometry().size);
+
+        let mut contexts = Vec::new();
+        if border_edges(geometry, relative_pos, self.common.pointer_bindings.border).is_some() {
+            contexts.push(PointerContext::Border);
+        }
+        if relative_pos.y < (geometry.loc.y + SHADE_HEIGHT) as f64 {
+            contexts.push(PointerContext::Titlebar);
+        }
+        contexts.push(PointerContext::Window);
+        Some((contexts, Some(window)))
+    }
+
+    /// Starts a compositor side move or resize if `button` completes a pointer binding over a
+    /// window. The window gets focus, the click only goes to the grab.
+    pub(crate) fn start_pointer_binding(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &Seat<State>,
+        button: u32,
+        serial: Serial,
+    ) -> bool {
+        let modifiers = match seat.get_keyboard() {
+            Some(keyboard) => Modifiers::from(&keyboard.modifier_state()),
+            None => return false,
+        };
+        let pressed = Some((modifiers, button));
+        let bindings = &self.common.pointer_bindings;
+        let edge_resize = bindings.edge_resize == pressed;
+        let window_move = bindings.window_move == pressed;
+        let window_resize = bindings.window_resize == pressed;
+        if !(edge_resize || window_move || window_resize) {
+            return false;
+        }
+        let border = bindings.border;
+
+        let pointer = seat.get_pointer().unwrap();
+        let pos = pointer.current_location();
+        let workspace = self.common.shell.active_workspace_mut();
+        let (window, location) = match workspace.space.window_under(pos) {
+            Some(window) => match workspace.space.window_location(window) {
+                Some(location) => (window.clone(), location),
+                None => return false,
+            },
+            None => return false,
+        };
+        if workspace.is_fullscreen(&window) {
+            return false;
+        }
+        let maximized = match window.toplevel() {
+            Kind::Xdg(toplevel) => toplevel
+                .current_state()
+                .states
+                .contains(XdgState::Maximized),
+        };
+
+        let geometry = Rectangle::from_loc_and_size(location, window.geometry().size);
+        // Close to the edge the edge binding wins, so both can share a button
+        let edges = if edge_resize && !maximized {
+            border_edges(geometry, pos, border)
+        } else {
+            None
+        };
+        let start_data = PointerGrabStartData {
+            focus: None,
+            button,
+            location: pos,
+        };
+        if let Some(edges) = edges {
+            workspace.layer.resize_request(
+                &workspace.space,
+                &window,
+                seat,
+                serial,
+                start_data,
+                edges,
+            );
+        } else if window_move {
+            // Maximized windows are unmaximized by the move
+            self.common
+                .shell
+                .move_request(&window, seat, serial, start_data);
+        } else if window_resize && !maximized {
+            let edges = closest_corner(geometry, pos);
+            workspace.layer.resize_request(
+                &workspace.space,
+                &window,
+                seat,
+                serial,
+                start_data,
+                edges,
+            );
+        } else {
+            return false;
+        }
+
+        let surface = window.toplevel().wl_surface().clone();
+        self.common
+            .set_focus(dh, Some(&surface), seat, Some(serial));
+        true
+    }
+}
+
+/// The corner of `geometry` in the same quarter as `pos`
+fn closest_corner(geometry: Rectangle<i32, Logical>, pos: Point<f64, Logical>) -> ResizeEdge {
+    let center = geometry.to_f64().loc + geometry.to_f64().size.downscale(2.0).to_point();
+    match (pos.x < center.x, pos.y < center.y) {
+        (true, true) => ResizeEdge::TopLeft,
+        (true, false) => ResizeEdge::BottomLeft,
+        (false, true) => ResizeEdge::TopRight,
+        (false, false) => ResizeEdge::BottomRight,
+    }
+}
+
+/// The edges of `geometry` that `pos` is within `border` of, both of them in corners
+fn border_edges(
+    geometry: Rectangle<i32, Logical>,
+    pos: Point<f64, Logical>,
+    border: i32,
+) -> Option<ResizeEdge> {
+    let (left, top) = (geometry.loc.x, geometry.loc.y);
+    let (right, bottom) = (left + geometry.size.w, top + geometry.size.h);
+    let (x, y) = (pos.x as i32, pos.y as i32);
+
+    let horizontal = if x < left + border {
+        Some(ResizeEdge::Left)
+    } else if x >= right - border {
+        Some(ResizeEdge::Right)
+    } else {
+        None
+    };
+    let vertical = if y < top + border {
+        Some(ResizeEdge::Top)
+    } else if y >= bottom - border {
+        Some(ResizeEdge::Bottom)
+    } else {
+        None
+    };
+
+    match (horizontal, vertical) {
+        (Some(ResizeEdge::Left), Some(ResizeEdge::Top)) => Some(ResizeEdge::TopLeft),
+        (Some(ResizeEdge::Left), Some(_)) => Some(ResizeEdge::BottomLeft),
+        (Some(_), Some(ResizeEdge::Top)) => Some(ResizeEdge::TopRight),
+        (Some(_), Some(_)) => Some(ResizeEdge::BottomRight),
+        (edge, None) | (None, edge) => edge,
+    }
+}
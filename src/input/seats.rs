@@ -0,0 +1,110 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use serde::Serialize;
+use smithay::{
+    backend::input::DeviceCapability,
+    reexports::wayland_server::DisplayHandle,
+    wayland::{seat::Seat, SERIAL_COUNTER},
+};
+
+use super::{add_pointer, Devices, SeatName};
+use crate::state::{output::active_output, State};
+
+/// A seat as listed over IPC
+#[derive(Debug, Serialize)]
+pub struct SeatInfo {
+    pub name: String,
+    /// `keyboard` and `pointer`, for as long as the seat has a device of that kind
+    pub capabilities: Vec<&'static str>,
+    pub devices: usize,
+}
+
+impl State {
+    /// Gives `seat` a keyboard or pointer for capabilities its first device of that kind brought
+    pub(super) fn add_capabilities(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &Seat<State>,
+        capabilities: &[DeviceCapability],
+    ) {
+        let mut seat = seat.clone();
+        for capability in capabilities {
+            match capability {
+                DeviceCapability::Keyboard if seat.get_keyboard().is_none() => {
+                    if let Err(err) = self.common.add_seat_keyboard(dh, &mut seat) {
+                        slog_scope::warn!("Failed to add a keyboard: {:#}", err);
+                    }
+                }
+                DeviceCapability::Pointer if seat.get_pointer().is_none() => add_pointer(&mut seat),
+                // TODO: Handle touch, tablet
+                _ => {}
+            }
+        }
+    }
+
+    /// Takes the keyboard or pointer from `seat` once its last device of that kind is gone,
+    /// ending grabs and focus first
+    pub(super) fn remove_capabilities(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &Seat<State>,
+        capabilities: &[DeviceCapability],
+    ) {
+        let mut seat = seat.clone();
+        for capability in capabilities {
+            match capability {
+                DeviceCapability::Keyboard => {
+                    if let Some(keyboard) = seat.get_keyboard() {
+                        // The modifiers are never going to be let go
+                        self.common.cancel_switcher(&seat);
+                        keyboard.set_focus(dh, None, SERIAL_COUNTER.next_serial());
+                        seat.remove_keyboard();
+                    }
+                }
+                DeviceCapability::Pointer => {
+                    if let Some(pointer) = seat.get_pointer() {
+                        if self.common.shell.outputs().next().is_some() {
+                            let output = active_output(&seat, &self.common);
+                            self.common.shell.drop_move(dh, &seat, &output);
+                        }
+                        if pointer.is_grabbed() {
+                            pointer.unset_grab(SERIAL_COUNTER.next_serial(), 0);
+                        }
+                        // Also drops the cursor callback, which holds on to the seat
+                        seat.remove_pointer();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn seat_infos(&self) -> Vec<SeatInfo> {
+        self.common
+            .seats
+            .iter()
+            .map(|seat| {
+                let devices = seat.user_data().get::<Devices>().unwrap();
+                let mut capabilities = Vec::new();
+                if seat.get_keyboard().is_some() {
+                    capabilities.push("keyboard");
+                }
+                if seat.get_pointer().is_some() {
+                    capabilities.push("pointer");
+                }
+                SeatInfo {
+                    name: seat_name(seat),
+                    capabilities,
+                    devices: devices.len(),
+                }
+            })
+            .collect()
+    }
+}
+
+fn seat_name(seat: &Seat<State>) -> String {
+    seat.user_data()
+        .get::<SeatName>()
+        .map(|name| name.0.clone())
+        .unwrap_or_default()
+}
@@ -0,0 +1,238 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    cell::RefCell,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use serde::Deserialize;
+use smithay::{
+    desktop::Window,
+    reexports::{
+        calloop::{
+            timer::{TimeoutAction, Timer},
+            RegistrationToken,
+        },
+        wayland_server::DisplayHandle,
+    },
+    utils::{Logical, Point, Rectangle},
+    wayland::{
+        seat::{MotionEvent, Seat},
+        SERIAL_COUNTER,
+    },
+};
+
+use crate::state::{
+    cursor_shape::pointer_entered,
+    output::{active_output, set_active_output, OutputExt},
+    State,
+};
+
+/// Time between two steps of a smooth warp
+const STEP: Duration = Duration::from_millis(8);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WarpConfig {
+    /// Milliseconds a smooth warp takes
+    pub duration: u64,
+    /// Focus the window under the pointer where a warp ends
+    pub focus: bool,
+}
+
+impl Default for WarpConfig {
+    fn default() -> Self {
+        Self {
+            duration: 150,
+            focus: false,
+        }
+    }
+}
+
+/// Where the pointer is warped to, the center of an output or a window
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarpTarget {
+    Output(String),
+    Window(usize),
+}
+
+/// The smooth warp a seat is in the middle of
+#[derive(Default)]
+struct WarpState(RefCell<Option<RegistrationToken>>);
+
+fn warp_state(seat: &Seat<State>) -> &WarpState {
+    seat.user_data().insert_if_missing(WarpState::default);
+    seat.user_data().get::<WarpState>().unwrap()
+}
+
+fn center(rect: Rectangle<i32, Logical>) -> Point<f64, Logical> {
+    let rect = rect.to_f64();
+    rect.loc + Point::from((rect.size.w / 2.0, rect.size.h / 2.0))
+}
+
+impl State {
+    /// Moves the pointer of `seat` to the center of `target`, gliding there if `animate`
+    pub fn warp_pointer(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &Seat<State>,
+        target: WarpTarget,
+        animate: bool,
+    ) -> anyhow::Result<()> {
+        let position = match target {
+            WarpTarget::Output(name) => self
+                .common
+                .shell
+                .outputs()
+                .find(|output| output.name() == name)
+                .map(|output| center(output.geometry()))
+                .ok_or_else(|| anyhow!("No output {}", name))?,
+            WarpTarget::Window(id) => {
+                let window = self
+                    .common
+                    .shell
+                    .find_window(id)
+                    .ok_or_else(|| anyhow!("No window {}", id))?;
+                self.window_center(&window)
+                    .ok_or_else(|| anyhow!("Window {} is not on the active workspace", id))?
+            }
+        };
+        self.warp_pointer_to(dh, seat, position, animate);
+        Ok(())
+    }
+
+    /// The center of `window` in global coordinates, if it is on the active workspace
+    pub(crate) fn window_center(&self, window: &Window) -> Option<Point<f64, Logical>> {
+        let space = &self.common.shell.active_workspace().space;
+        let location = space.window_location(window)?;
+        Some(center(Rectangle::from_loc_and_size(
+            location,
+            window.geometry().size,
+        )))
+    }
+
+    /// Moves the pointer of `seat` to `position` in global coordinates, a warp that is still
+    /// going is replaced
+    pub(crate) fn warp_pointer_to(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &Seat<State>,
+        position: Point<f64, Logical>,
+        animate: bool,
+    ) {
+        self.cancel_warp(seat);
+        let duration = Duration::from_millis(self.common.config.pointer_warp.duration);
+        let pointer = match seat.get_pointer() {
+            Some(pointer) => pointer,
+            None => return,
+        };
+        if !animate || duration.is_zero() || !self.common.animations() {
+            self.move_pointer(dh, seat, position);
+            self.warp_finished(dh, seat, position);
+            return;
+        }
+
+        let start = pointer.current_location();
+        let started = Instant::now();
+        let owned_seat = seat.clone();
+        let token = self.common.event_loop_handle.insert_source(
+            Timer::from_duration(STEP),
+            move |_, _, data| {
+                let seat = &owned_seat;
+                let dh = data.display.handle();
+                let t = (started.elapsed().as_secs_f64() / duration.as_secs_f64()).min(1.0);
+                // Ease out, fast at first and settling on the target
+                let eased = 1.0 - (1.0 - t).powi(3);
+                let step = start + (position - start).upscale(eased);
+                data.state.move_pointer(&dh, seat, step);
+                if t < 1.0 {
+                    return TimeoutAction::ToDuration(STEP);
+                }
+                warp_state(seat).0.borrow_mut().take();
+                data.state.warp_finished(&dh, seat, position);
+                TimeoutAction::Drop
+            },
+        );
+        *warp_state(seat).0.borrow_mut() = token.ok();
+    }
+
+    /// Stops a smooth warp of `seat` where it is, the user moving the pointer wins
+    pub(crate) fn cancel_warp(&mut self, seat: &Seat<State>) {
+        if let Some(token) = warp_state(seat).0.borrow_mut().take() {
+            self.common.event_loop_handle.remove(token);
+        }
+    }
+
+    /// Sends pointer motion to `position` without any input behind it
+    pub(crate) fn move_pointer(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &Seat<State>,
+        position: Point<f64, Logical>,
+    ) {
+        let pointer = match seat.get_pointer() {
+            Some(pointer) => pointer,
+            None => return,
+        };
+        let output = match self.common.shell.output_at(position) {
+            Some(output) => output.clone(),
+            None => return,
+        };
+        if output != active_output(seat, &self.common) {
+            set_active_output(seat, &output);
+        }
+        let geometry = output.geometry();
+        let relative_pos = self
+            .common
+            .shell
+            .space_relative_output_geometry(position, &output);
+        let workspace = self.common.shell.active_workspace_mut();
+        let under = State::surface_under(position, relative_pos, &output, geometry, &workspace);
+        let focus = under.as_ref().map(|(surface, _)| surface.clone());
+        let time = self.common.start_time.elapsed().as_millis() as u32;
+        let serial = SERIAL_COUNTER.next_serial();
+        if !pointer.is_grabbed() {
+            pointer_entered(seat, focus.as_ref(), serial);
+        }
+        pointer.motion(
+            self,
+            dh,
+            &MotionEvent {
+                location: position,
+                focus: under,
+                serial,
+                time,
+            },
+        );
+        self.auto_hide_pointer_moved(position, focus.as_ref());
+    }
+
+    /// Focuses the window the warp ended on, if focus follows warps
+    fn warp_finished(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &Seat<State>,
+        position: Point<f64, Logical>,
+    ) {
+        if !self.common.focus_follows_warp {
+            return;
+        }
+        let output = match self.common.shell.output_at(position) {
+            Some(output) => output.clone(),
+            None => return,
+        };
+        let workspace = self.common.shell.active_workspace();
+        let filling = workspace.get_filling(&output).map(|filling| filling.window);
+        let window = filling.or_else(|| {
+            workspace
+                .surface_under(position)
+                .map(|(window, _, _)| window)
+        });
+        if let Some(window) = window {
+            let surface = window.toplevel().wl_surface().clone();
+            self.common.set_focus(dh, Some(&surface), seat, None);
+        }
+    }
+}
@@ -0,0 +1,389 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    env,
+    ffi::OsStr,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::{
+        fs::PermissionsExt,
+        io::AsRawFd,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use nix::{
+    sys::socket::{getsockopt, sockopt::PeerCredentials},
+    unistd::getuid,
+};
+use serde::{Deserialize, Serialize};
+use smithay::reexports::{
+    calloop::{generic::Generic, EventLoop, Interest, LoopHandle, Mode, PostAction},
+    wayland_server::DisplayHandle,
+};
+
+use crate::{
+    input::accessibility::AccessibilityConfig,
+    log::parse_level,
+    runtime::messages::RuntimeMessage,
+    shell::frames::FramePolicy,
+    state::{LoopData, State},
+};
+
+/// Longer edge of thumbnails in pixels, unless asked for something else
+const DEFAULT_THUMBNAIL_SIZE: u32 = 256;
+/// Longest request accepted, longer ones close the connection
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+/// How much of its replies a client may leave unread before it is disconnected
+const MAX_UNREAD_REPLIES: usize = 16 * 1024 * 1024;
+
+/// Requests are read as one JSON object per line, e.g. `{"command": "clients"}`
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum IpcRequest {
+    /// Per-client resource usage and recent protocol errors
+    Clients,
+    /// The last `count` log records, or all that are kept in memory
+    Logs { count: Option<usize> },
+    /// Changes the minimum level of records that get logged, of one module and those in it if given
+    SetLogLevel {
+        level: String,
+        module: Option<String>,
+    },
+    /// All mapped windows with their id, title, app id and urgency
+    Windows,
+    /// Names of the workspaces and of the shown one
+    Workspaces,
+    /// Shows the workspace called `name`, created if there is none
+    SwitchWorkspace { name: String },
+    /// Goes back to the workspace shown before
+    WorkspaceBackAndForth,
+    /// Outputs, workspaces, windows and seats with everything the compositor tracks about them
+    Tree,
+    /// Changes when hidden windows get frame callbacks
+    SetFramePolicy { policy: FramePolicy },
+    /// The last downscaled snapshot of a window, kept updated while it is requested
+    Thumbnail { id: usize, max_size: Option<u32> },
+    /// Replaces the sticky, slow and bounce keys settings
+    SetAccessibility(AccessibilityConfig),
+    /// The wayland socket name and the path of the IPC socket
+    Socket,
+    /// Name and icon of an application, for docks and task bars
+    AppInfo { app_id: String },
+    /// Textures the compositor keeps around, by what they are for
+    GpuMemory,
+    /// Starts or stops measuring input latency, keeping the samples so far
+    SetLatencyTracking { enabled: bool },
+    /// Drops the input latency samples so far
+    ResetLatency,
+    /// Percentiles of the input latency measured so far
+    Latency,
+    /// Frame, input, client and runtime counters in the Prometheus text format
+    Metrics,
+    /// Clients that were refused privileged globals
+    AccessDenials,
+    /// Seats with their capabilities and number of devices
+    Seats,
+    /// Asks the runtime to register its keybindings, rules and subscriptions again
+    Reload,
+    /// Stops the script and drops what it registered
+    SafeMode,
+    /// Whether low power mode is on and why
+    Power,
+    /// Turns low power mode on or off, or follows the system again without `enabled`
+    SetLowPower { enabled: Option<bool> },
+}
+
+/// Written back as one JSON object per line, either `{"ok": ...}` or `{"error": "..."}`
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum IpcResponse {
+    Ok(serde_json::Value),
+    Error(String),
+}
+
+impl IpcResponse {
+    pub fn from_value<T: Serialize>(value: T) -> Self {
+        serde_json::to_value(value)
+            .map(IpcResponse::Ok)
+            .unwrap_or_else(|err| IpcResponse::Error(err.to_string()))
+    }
+}
+
+pub struct IpcState {
+    path: PathBuf,
+}
+
+impl Drop for IpcState {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/electrum-<wayland socket>.sock`, `None` without a runtime directory since
+/// there is no other place only the user can reach
+pub fn socket_path(wayland_socket: &OsStr) -> Option<PathBuf> {
+    let mut path = PathBuf::from(env::var_os("XDG_RUNTIME_DIR")?);
+    path.push(format!(
+        "electrum-{}.sock",
+        wayland_socket.to_string_lossy()
+    ));
+    Some(path)
+}
+
+/// `$ELECTRUM_SOCKET`, or the socket belonging to `$WAYLAND_DISPLAY`
+pub fn client_socket_path() -> Option<PathBuf> {
+    env::var_os("ELECTRUM_SOCKET")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("WAYLAND_DISPLAY").and_then(|display| socket_path(&display)))
+}
+
+/// Sends a single request to a running instance and waits for the answer
+pub fn send_request(path: &Path, request: &serde_json::Value) -> anyhow::Result<serde_json::Value> {
+    let mut stream = UnixStream::connect(path)
+        .with_context(|| format!("Failed to connect to {}", path.display()))?;
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    stream.write_all(&line)?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    serde_json::from_str(&response).with_context(|| "Invalid response")
+}
+
+pub fn init_ipc(
+    event_loop: &mut EventLoop<LoopData>,
+    wayland_socket: &OsStr,
+) -> anyhow::Result<IpcState> {
+    let path = socket_path(wayland_socket)
+        .ok_or_else(|| anyhow!("XDG_RUNTIME_DIR is not set, not opening the IPC socket"))?;
+    // A previous instance might not have cleaned up
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("Failed to bind IPC socket {}", path.display()))?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to restrict IPC socket {}", path.display()))?;
+    listener.set_nonblocking(true)?;
+
+    let handle = event_loop.handle();
+    event_loop
+        .handle()
+        .insert_source(
+            Generic::new(listener, Interest::READ, Mode::Level),
+            move |_, listener, _| {
+                loop {
+                    match listener.accept() {
+                        Ok((stream, _)) if !same_user(&stream) => {
+                            slog_scope::warn!("Refusing IPC connection of another user");
+                        }
+                        Ok((stream, _)) => {
+                            if let Err(err) = add_connection(&handle, stream) {
+                                slog_scope::warn!("Failed to accept IPC connection: {}", err);
+                            }
+                        }
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(err) => {
+                            slog_scope::warn!("IPC socket error: {}", err);
+                            break;
+                        }
+                    }
+                }
+                Ok(PostAction::Continue)
+            },
+        )
+        .map_err(|_| anyhow!("Failed to initialise IPC socket source"))?;
+
+    env::set_var("ELECTRUM_SOCKET", &path);
+    slog_scope::info!("IPC listening on {}", path.display());
+    Ok(IpcState { path })
+}
+
+/// Whether the peer of `stream` runs as our user, the IPC commands are as privileged as the user
+fn same_user(stream: &UnixStream) -> bool {
+    getsockopt(stream.as_raw_fd(), PeerCredentials)
+        .map(|credentials| credentials.uid() == getuid().as_raw())
+        .unwrap_or(false)
+}
+
+fn add_connection(
+    handle: &LoopHandle<'static, LoopData>,
+    stream: UnixStream,
+) -> anyhow::Result<()> {
+    stream.set_nonblocking(true)?;
+    let mut connection = Connection::default();
+
+    // Edge triggered, so a connection waiting to write its replies only wakes the loop once the
+    // client read some
+    handle
+        .insert_source(
+            Generic::new(stream, Interest::BOTH, Mode::Edge),
+            move |_, stream, data| {
+                data.state.common.watchdog.event("ipc");
+                match connection.dispatch(stream, data) {
+                    Ok(action) => Ok(action),
+                    Err(err) => {
+                        slog_scope::debug!("Closing IPC connection: {}", err);
+                        Ok(PostAction::Remove)
+                    }
+                }
+            },
+        )
+        .map_err(|_| anyhow!("Failed to insert IPC connection"))?;
+
+    Ok(())
+}
+
+/// What was read from a client and is not a whole request yet, and the replies it did not read
+#[derive(Default)]
+struct Connection {
+    input: Vec<u8>,
+    output: Vec<u8>,
+}
+
+impl Connection {
+    /// Reads and answers all requests that came in, then writes as much as the socket takes.
+    /// Errors close the connection.
+    fn dispatch(&mut self, stream: &mut UnixStream, data: &mut LoopData) -> io::Result<PostAction> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            match stream.read(&mut chunk) {
+                Ok(0) => return Ok(PostAction::Remove),
+                Ok(n) => {
+                    self.input.extend_from_slice(&chunk[..n]);
+                    self.handle_requests(data)?;
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        self.flush(stream)?;
+        Ok(PostAction::Continue)
+    }
+
+    fn handle_requests(&mut self, data: &mut LoopData) -> io::Result<()> {
+        while let Some(pos) = self.input.iter().position(|b| *b == b'\n') {
+            let line = self.input.drain(..=pos).collect::<Vec<_>>();
+            let response = match serde_json::from_slice::<IpcRequest>(&line) {
+                Ok(request) => {
+                    let dh = data.display.handle();
+                    data.state.handle_ipc_request(&dh, request)
+                }
+                Err(err) => IpcResponse::Error(format!("Invalid request: {}", err)),
+            };
+            serde_json::to_writer(&mut self.output, &response)?;
+            self.output.push(b'\n');
+        }
+        if self.input.len() > MAX_REQUEST_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request longer than the limit",
+            ));
+        }
+        if self.output.len() > MAX_UNREAD_REPLIES {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "client does not read its replies",
+            ));
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, stream: &mut UnixStream) -> io::Result<()> {
+        while !self.output.is_empty() {
+            match stream.write(&self.output) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.output.drain(..n);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl State {
+    pub fn handle_ipc_request(&mut self, dh: &DisplayHandle, request: IpcRequest) -> IpcResponse {
+        match request {
+            IpcRequest::Clients => IpcResponse::from_value(self.common.diagnostics.report()),
+            IpcRequest::Logs { count } => IpcResponse::from_value(self.common.log.records(count)),
+            IpcRequest::SetLogLevel { level, module } => match parse_level(&level) {
+                Ok(level) => {
+                    match module {
+                        Some(module) => self.common.log.set_module_level(&module, level),
+                        None => self.common.log.set_level(level),
+                    }
+                    IpcResponse::from_value(level.as_str())
+                }
+                Err(err) => IpcResponse::Error(err.to_string()),
+            },
+            IpcRequest::Windows => IpcResponse::from_value(self.common.shell.window_infos()),
+            IpcRequest::Workspaces => IpcResponse::from_value(self.common.shell.workspaces_info()),
+            IpcRequest::SwitchWorkspace { name } => match self.common.switch_workspace(dh, &name) {
+                Ok(()) => IpcResponse::from_value(self.common.shell.workspaces_info()),
+                Err(err) => IpcResponse::Error(err.to_string()),
+            },
+            IpcRequest::WorkspaceBackAndForth => match self.common.workspace_back_and_forth(dh) {
+                Ok(()) => IpcResponse::from_value(self.common.shell.workspaces_info()),
+                Err(err) => IpcResponse::Error(err.to_string()),
+            },
+            IpcRequest::Tree => IpcResponse::from_value(self.tree()),
+            IpcRequest::SetFramePolicy { policy } => {
+                self.common.frame_policy = policy;
+                IpcResponse::from_value(policy)
+            }
+            IpcRequest::SetAccessibility(config) => {
+                self.set_accessibility(dh, config);
+                IpcResponse::from_value(config)
+            }
+            IpcRequest::Thumbnail { id, max_size } => {
+                let max_size = max_size.unwrap_or(DEFAULT_THUMBNAIL_SIZE).max(1);
+                match self.common.request_thumbnail(id, max_size) {
+                    Ok(thumbnail) => IpcResponse::from_value(thumbnail),
+                    Err(err) => IpcResponse::Error(err.to_string()),
+                }
+            }
+            IpcRequest::Socket => IpcResponse::from_value(serde_json::json!({
+                "wayland_display": self.common.socket.to_string_lossy(),
+                "ipc": self.common.ipc.as_ref().map(|ipc| &ipc.path),
+            })),
+            IpcRequest::AppInfo { app_id } => {
+                IpcResponse::from_value(self.common.app_info.lookup(&app_id))
+            }
+            IpcRequest::GpuMemory => IpcResponse::from_value(self.gpu_memory()),
+            IpcRequest::SetLatencyTracking { enabled } => {
+                self.common.latency.set_enabled(enabled);
+                IpcResponse::from_value(enabled)
+            }
+            IpcRequest::ResetLatency => {
+                self.common.latency.reset();
+                IpcResponse::from_value(self.latency_report())
+            }
+            IpcRequest::Latency => IpcResponse::from_value(self.latency_report()),
+            IpcRequest::Metrics => IpcResponse::from_value(self.metrics_text()),
+            IpcRequest::AccessDenials => IpcResponse::from_value(self.common.access_log.denials()),
+            IpcRequest::Seats => IpcResponse::from_value(self.seat_infos()),
+            IpcRequest::SafeMode => {
+                self.common.enter_safe_mode(dh);
+                IpcResponse::from_value(self.common.runtime_registry.generation())
+            }
+            IpcRequest::Power => IpcResponse::from_value(self.common.power),
+            IpcRequest::SetLowPower { enabled } => {
+                self.common.set_low_power(enabled);
+                IpcResponse::from_value(self.common.power)
+            }
+            IpcRequest::Reload => {
+                let runtime_sender = &self.common.shell.active_workspace().runtime_sender;
+                match runtime_sender.send(RuntimeMessage::Reload) {
+                    Ok(()) => IpcResponse::from_value(self.common.runtime_registry.generation()),
+                    Err(_) => IpcResponse::Error("The runtime is not running".into()),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,776 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! The compositor as a library, for binaries that build native plugins into it. The `electrum`
+//! binary is [`main`] without any.
+
+use std::{error::Error, ffi::OsString, io, os::unix::net::UnixStream, path::Path, sync::Arc};
+
+use anyhow::{anyhow, Context};
+use calloop::channel::Event;
+use clap::Parser;
+use smithay::{
+    reexports::{
+        calloop::{generic::Generic, EventLoop, Interest, Mode, PostAction, RegistrationToken},
+        wayland_server::Display,
+    },
+    wayland::socket::ListeningSocketSource,
+};
+use state::{
+    credentials::ClientCredentials, output::active_output, sandbox, Data, LoopData, State,
+};
+
+use crate::backend::renderer::color::{set_icc_profile, set_output_filter};
+use crate::cli::{Cli, Command, RunArgs};
+use crate::input::accessibility::AccessibilityConfig;
+use crate::log::init_logger;
+use crate::runtime::bridge::RequestSender;
+use crate::runtime::messages::{CompositorMessage, RuntimeMessage};
+use crate::runtime::plugin::init_plugin_events;
+use crate::shell::layout::{
+    set_struts,
+    zones::{set_drop_zones, DropZone},
+};
+use crate::shell::window::WindowExt;
+
+mod appinfo;
+mod backend;
+mod backlight;
+mod cli;
+mod config;
+mod crash;
+mod id;
+mod input;
+mod ipc;
+mod log;
+mod notifications;
+mod plugins;
+mod portal;
+mod power;
+mod runtime;
+mod session;
+mod shell;
+mod shutdown;
+mod state;
+mod watchdog;
+
+pub use deno_core;
+pub use runtime::plugin::{ElectrumExtension, PluginEvents};
+
+/// Parses the command line and runs the compositor or the subcommand, with `extensions` added
+/// to the runtime of the script
+pub fn main(mut extensions: Vec<Box<dyn ElectrumExtension>>) -> Result<(), Box<dyn Error>> {
+    let command = Cli::parse()
+        .command
+        .unwrap_or_else(|| Command::Run(RunArgs::default()));
+    match command {
+        Command::Run(args) => run(args, extensions),
+        Command::CheckConfig => {
+            if !cli::check_config(&mut extensions) {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Msg(args) => {
+            if !cli::msg(args)? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Version => {
+            cli::version();
+            Ok(())
+        }
+    }
+}
+
+fn run(
+    args: RunArgs,
+    mut extensions: Vec<Box<dyn ElectrumExtension>>,
+) -> Result<(), Box<dyn Error>> {
+    let log = init_logger(args.quiet)?;
+    let crash = crash::CrashReporter::install(&log);
+    slog_scope::info!("Starting up");
+
+    let config = config::Config::load();
+    if let Some(level) = &config.log_level {
+        match log::parse_level(level) {
+            Ok(level) => log.set_level(level),
+            Err(err) => slog_scope::warn!("Invalid log_level in config: {}", err),
+        }
+    }
+    for (module, level) in &config.log.modules {
+        match log::parse_level(level) {
+            Ok(level) => log.set_module_level(module, level),
+            Err(err) => slog_scope::warn!("Invalid level for {} in config: {}", module, err),
+        }
+    }
+    if config.log.file || args.log_file.is_some() {
+        match log.open_file(args.log_file.clone(), &config.log) {
+            Ok(path) => slog_scope::info!("Logging to {}", path.display()),
+            Err(err) => slog_scope::warn!("No log file: {:?}", err),
+        }
+    }
+
+    let mut event_loop =
+        EventLoop::try_new_high_precision().with_context(|| "Failed to initialise event loop")?;
+    let watchdog = watchdog::Watchdog::new(config.watchdog);
+    if let Err(err) = watchdog.start(&mut event_loop, config.watchdog) {
+        slog_scope::warn!("Watchdog unavailable: {:?}", err);
+    }
+
+    let (display, socket, socket_token) = init_wayland_display(
+        &mut event_loop,
+        args.socket.as_deref().or(config.socket.as_deref()),
+    )?;
+    let ipc = ipc::init_ipc(&mut event_loop, &socket)
+        .map_err(|err| slog_scope::warn!("IPC unavailable: {:?}", err))
+        .ok();
+    if config.export_environment {
+        session::export_environment(&socket);
+    }
+
+    let compositor_sender = init_compositor_channel(&mut event_loop);
+
+    let runtime = runtime::Runtime::new(compositor_sender, &mut extensions);
+    let runtime_sender = runtime.runtime_sender.clone();
+    let scripted = runtime.has_main_module();
+    let runtime_registry = runtime.registry.clone();
+    let runtime_bridge = runtime.bridge.clone();
+    runtime.run_with_calloop(&mut event_loop, &watchdog)?;
+    let plugin_events = init_plugin_events(&mut event_loop)?;
+    for extension in &mut extensions {
+        extension.start(plugin_events.for_plugin(extension.name()));
+    }
+
+    let mut state = State::new(
+        &display.handle(),
+        socket,
+        event_loop.handle(),
+        event_loop.get_signal(),
+        log,
+        config,
+        runtime_sender,
+    );
+
+    state.common.ipc = ipc;
+    state.common.watchdog = watchdog;
+    state.common.crash = crash;
+    state.common.keybindings = input::keybindings::Keybindings::new(&state.common.config, scripted);
+    state.common.socket_token = Some(socket_token);
+    if state.common.config.sandbox.socket {
+        let config = &state.common.config.sandbox;
+        match sandbox::init_sandbox_socket(&mut event_loop, config, &state.common.socket) {
+            Ok((name, token)) => {
+                state.common.sandbox_socket = Some(name);
+                state.common.sandbox_token = Some(token);
+            }
+            Err(err) => slog_scope::warn!("No sandbox socket: {:?}", err),
+        }
+    }
+    state.common.runtime_registry = runtime_registry;
+    state.common.runtime_bridge = runtime_bridge;
+    if let Err(err) = power::init_power(&mut event_loop) {
+        slog_scope::warn!("Not following the power state: {:?}", err);
+    }
+    state.common.native_plugins = plugins::NativePlugins::load(&state.common.config.plugins);
+    match backlight::init_backlight(&mut event_loop) {
+        Ok(backlights) => state.common.backlights = backlights,
+        Err(err) => slog_scope::warn!("Failed to set up backlight control: {:?}", err),
+    }
+    if let Err(err) = shutdown::init_signals(&mut event_loop) {
+        slog_scope::warn!("Failed to register signal handlers: {:?}", err);
+    }
+
+    backend::init_backend(&display.handle(), &mut event_loop, &mut state, args.backend)?;
+
+    if state.common.config.portal {
+        match portal::init_portal(&mut event_loop, state.common.config.color_scheme) {
+            Ok(portal) => state.common.portal = Some(portal),
+            Err(err) => slog_scope::warn!("Portal backend unavailable: {:?}", err),
+        }
+    }
+
+    if state.common.config.notifications {
+        match notifications::init_notifications(&mut event_loop) {
+            Ok(notifications) => state.common.notifications = Some(notifications),
+            Err(err) => slog_scope::warn!("Notification daemon unavailable: {:?}", err),
+        }
+    }
+
+    for autostart in &state.common.config.autostart {
+        let pid = session::spawn(autostart.command(), &state.common.socket);
+        state.common.shell.rules.add_autostart(autostart, pid);
+        if let Some(pid) = pid {
+            state.common.crash.autostarted(pid);
+        }
+    }
+    state.restore_session();
+
+    let mut data = Data { display, state };
+
+    event_loop
+        .run(None, &mut data, |data| {
+            data.state.common.watchdog.event("refresh");
+            // Shut down
+            if data.state.common.shell.outputs().next().is_none()
+                || data.state.common.should_stop
+                || data.state.common.clients_closed()
+            {
+                slog_scope::info!("Shutting down");
+                data.state.common.event_loop_signal.stop();
+                data.state.common.event_loop_signal.wakeup();
+                return;
+            }
+
+            let handle = &data.display.handle();
+            data.state.common.shell.refresh(handle);
+            data.state.common.refresh_auto_hide(handle);
+            data.state.common.refresh_idle();
+            data.state.common.refresh_focus(handle);
+            data.state.common.flush_configures();
+            data.state.common.crash.update(&data.state.common);
+
+            // Send events to Clients
+            let _ = data.display.flush_clients();
+        })
+        .expect("Failed to run Event Loop");
+
+    data.teardown();
+    std::mem::drop(event_loop);
+    Ok(())
+}
+
+fn init_compositor_channel(event_loop: &mut EventLoop<LoopData>) -> RequestSender {
+    let (sender, channel) = runtime::bridge::request_channel();
+    event_loop
+        .handle()
+        .insert_source(channel, |message, _, data| {
+            data.state.common.watchdog.event("runtime requests");
+            match message {
+                Event::Msg(CompositorMessage::Ping) => {
+                    slog_scope::info!("The compositor got a ping!");
+                    let _ = data
+                        .state
+                        .common
+                        .shell
+                        .active_workspace()
+                        .runtime_sender
+                        .send(RuntimeMessage::Ping);
+                }
+                Event::Msg(CompositorMessage::NotificationClosed { id, reason }) => {
+                    if let Some(notifications) = &data.state.common.notifications {
+                        notifications.closed(id, reason.into());
+                    }
+                }
+                Event::Msg(CompositorMessage::NotificationAction { id, action }) => {
+                    if let Some(notifications) = &data.state.common.notifications {
+                        notifications.action_invoked(id, &action);
+                    }
+                }
+                Event::Msg(CompositorMessage::ClientDiagnostics { reply }) => {
+                    let _ = reply.send(data.state.common.diagnostics.report());
+                }
+                Event::Msg(CompositorMessage::SocketName { reply }) => {
+                    let _ = reply.send(data.state.common.socket.to_string_lossy().into_owned());
+                }
+                Event::Msg(CompositorMessage::SpawnSandboxed { command, reply }) => {
+                    let _ = reply.send(
+                        data.spawn_sandboxed(&command)
+                            .map_err(|err| format!("{:?}", err)),
+                    );
+                }
+                Event::Msg(CompositorMessage::Windows { reply }) => {
+                    let _ = reply.send(data.state.common.shell.window_infos());
+                }
+                Event::Msg(CompositorMessage::Workspaces { reply }) => {
+                    let _ = reply.send(data.state.common.shell.workspaces_info());
+                }
+                Event::Msg(CompositorMessage::Tree { reply }) => {
+                    let _ = reply.send(data.state.tree());
+                }
+                Event::Msg(CompositorMessage::SwitchWorkspace { name }) => {
+                    let dh = data.display.handle();
+                    if let Err(err) = data.state.common.switch_workspace(&dh, &name) {
+                        slog_scope::warn!("The runtime failed to switch workspaces: {}", err);
+                    }
+                }
+                Event::Msg(CompositorMessage::WorkspaceBackAndForth) => {
+                    let dh = data.display.handle();
+                    if let Err(err) = data.state.common.workspace_back_and_forth(&dh) {
+                        slog_scope::warn!("The runtime failed to switch workspaces: {}", err);
+                    }
+                }
+                Event::Msg(CompositorMessage::AppInfo { app_id, reply }) => {
+                    let _ = reply.send(data.state.common.app_info.lookup(&app_id));
+                }
+                Event::Msg(CompositorMessage::GpuMemory { reply }) => {
+                    let _ = reply.send(data.state.gpu_memory());
+                }
+                Event::Msg(CompositorMessage::Latency { reply }) => {
+                    let _ = reply.send(data.state.latency_report());
+                }
+                Event::Msg(CompositorMessage::SetLatencyTracking(enabled)) => {
+                    data.state.common.latency.set_enabled(enabled);
+                }
+                Event::Msg(CompositorMessage::ResetLatency) => data.state.common.latency.reset(),
+                Event::Msg(CompositorMessage::SetLogLevel { level, module }) => match module {
+                    Some(module) => data.state.common.log.set_module_level(&module, level),
+                    None => data.state.common.log.set_level(level),
+                },
+                Event::Msg(CompositorMessage::ShutdownReady) => {
+                    data.state.common.runtime_ready_for_shutdown();
+                }
+                Event::Msg(CompositorMessage::SetWindowUrgent { id, urgent }) => {
+                    let shell = &mut data.state.common.shell;
+                    if let Some(window) = shell.find_window(id) {
+                        shell.set_urgent(&window, urgent);
+                    }
+                }
+                Event::Msg(CompositorMessage::Decide { request, decision }) => {
+                    data.state.common.decide(request, Some(decision));
+                }
+                Event::Msg(CompositorMessage::SetFramePolicy(policy)) => {
+                    data.state.common.frame_policy = policy;
+                }
+                Event::Msg(CompositorMessage::SetIccProfile {
+                    output,
+                    path,
+                    reply,
+                }) => {
+                    let shell = &data.state.common.shell;
+                    let result = match shell.outputs().find(|o| o.name() == output) {
+                        Some(output) => set_icc_profile(output, path.as_deref().map(Path::new))
+                            .map_err(|err| format!("{:#}", err)),
+                        None => Err(format!("No output named {}", output)),
+                    };
+                    let _ = reply.send(result);
+                }
+                Event::Msg(CompositorMessage::SetStruts {
+                    output,
+                    struts,
+                    reply,
+                }) => {
+                    let shell = &data.state.common.shell;
+                    let result = match shell.outputs().find(|o| o.name() == output) {
+                        Some(output) => {
+                            set_struts(output, struts);
+                            Ok(())
+                        }
+                        None => Err(format!("No output named {}", output)),
+                    };
+                    let _ = reply.send(result);
+                }
+                Event::Msg(CompositorMessage::SetDropZones {
+                    output,
+                    zones,
+                    reply,
+                }) => {
+                    let shell = &data.state.common.shell;
+                    let result = match shell.outputs().find(|o| o.name() == output) {
+                        Some(_) if !zones.iter().all(DropZone::has_size) => {
+                            Err("Drop zones and their targets need a positive size".to_string())
+                        }
+                        Some(output) => {
+                            set_drop_zones(output, zones);
+                            Ok(())
+                        }
+                        None => Err(format!("No output named {}", output)),
+                    };
+                    let _ = reply.send(result);
+                }
+                Event::Msg(CompositorMessage::SetOutputFilter {
+                    output,
+                    filter,
+                    reply,
+                }) => {
+                    let shell = &data.state.common.shell;
+                    let result = match shell.outputs().find(|o| o.name() == output) {
+                        Some(output) => {
+                            set_output_filter(output, filter).map_err(|err| format!("{:#}", err))
+                        }
+                        None => Err(format!("No output named {}", output)),
+                    };
+                    let _ = reply.send(result);
+                }
+                Event::Msg(CompositorMessage::ConfigureOutputs {
+                    outputs,
+                    test,
+                    reply,
+                }) => {
+                    let result = data.state.configure_outputs(&outputs, test);
+                    let _ = reply.send(result.map_err(|err| format!("{:#}", err)));
+                }
+                Event::Msg(CompositorMessage::BeginGeneration { reply }) => {
+                    let _ = reply.send(data.state.common.runtime_registry.begin());
+                }
+                Event::Msg(CompositorMessage::BindKey {
+                    generation,
+                    binding,
+                    reply,
+                }) => {
+                    let result = data
+                        .state
+                        .common
+                        .runtime_registry
+                        .bind_key(generation, binding);
+                    let _ = reply.send(result.map_err(|err| format!("{:#}", err)));
+                }
+                Event::Msg(CompositorMessage::BindPointer {
+                    generation,
+                    binding,
+                    reply,
+                }) => {
+                    let result = data
+                        .state
+                        .common
+                        .runtime_registry
+                        .bind_pointer(generation, binding);
+                    let _ = reply.send(result.map_err(|err| format!("{:#}", err)));
+                }
+                Event::Msg(CompositorMessage::AddWindowRule {
+                    generation,
+                    rule,
+                    reply,
+                }) => {
+                    let result = data
+                        .state
+                        .common
+                        .runtime_registry
+                        .add_rule(generation, rule);
+                    let _ = reply.send(result.map_err(|err| format!("{:#}", err)));
+                }
+                Event::Msg(CompositorMessage::AddLayerRule {
+                    generation,
+                    rule,
+                    reply,
+                }) => {
+                    let result = data
+                        .state
+                        .common
+                        .runtime_registry
+                        .add_layer_rule(generation, rule);
+                    let _ = reply.send(result.map_err(|err| format!("{:#}", err)));
+                }
+                Event::Msg(CompositorMessage::Subscribe {
+                    generation,
+                    events,
+                    reply,
+                }) => {
+                    let result = data
+                        .state
+                        .common
+                        .runtime_registry
+                        .subscribe(generation, events);
+                    let _ = reply.send(result.map_err(|err| format!("{:#}", err)));
+                }
+                Event::Msg(CompositorMessage::CommitGeneration { generation, reply }) => {
+                    let result = data.state.common.commit_generation(generation);
+                    let _ = reply.send(result.map_err(|err| format!("{:#}", err)));
+                }
+                Event::Msg(CompositorMessage::SetKeyboardLayouts { layouts, reply }) => {
+                    let dh = data.display.handle();
+                    let result = data.state.common.set_keyboard_layouts(&dh, layouts);
+                    let _ = reply.send(result.map_err(|err| err.to_string()));
+                }
+                Event::Msg(CompositorMessage::SwitchKeyboardLayout { index, reply }) => {
+                    let dh = data.display.handle();
+                    let result = data.state.common.switch_keyboard_layout(&dh, index);
+                    let _ = reply.send(result.map_err(|err| err.to_string()));
+                }
+                Event::Msg(CompositorMessage::SetKeyAccessibility {
+                    sticky_keys,
+                    slow_keys,
+                    bounce_keys,
+                }) => {
+                    let config = AccessibilityConfig {
+                        sticky_keys,
+                        slow_keys,
+                        bounce_keys,
+                        ..data.state.common.accessibility
+                    };
+                    data.state.set_accessibility(&data.display.handle(), config);
+                }
+                Event::Msg(CompositorMessage::SetDwellClick(dwell_click)) => {
+                    let config = AccessibilityConfig {
+                        dwell_click,
+                        ..data.state.common.accessibility
+                    };
+                    data.state.set_accessibility(&data.display.handle(), config);
+                }
+                Event::Msg(CompositorMessage::SetSwitcherStyle(style)) => {
+                    data.state.common.set_switcher_style(style);
+                }
+                Event::Msg(CompositorMessage::SetOverlay {
+                    output,
+                    name,
+                    overlay,
+                }) => {
+                    data.state.common.set_overlay(output, name, overlay);
+                }
+                Event::Msg(CompositorMessage::SetBackground {
+                    workspace,
+                    output,
+                    background,
+                    reply,
+                }) => {
+                    let result = data
+                        .state
+                        .common
+                        .set_background(workspace, output, background);
+                    let _ = reply.send(result.map_err(|err| format!("{:#}", err)));
+                }
+                Event::Msg(CompositorMessage::SetCursorScale(cursor_scale)) => {
+                    let config = AccessibilityConfig {
+                        cursor_scale,
+                        ..data.state.common.accessibility
+                    };
+                    data.state.set_accessibility(&data.display.handle(), config);
+                }
+                Event::Msg(CompositorMessage::SetReduceMotion(reduce_motion)) => {
+                    let config = AccessibilityConfig {
+                        reduce_motion,
+                        ..data.state.common.accessibility
+                    };
+                    data.state.set_accessibility(&data.display.handle(), config);
+                }
+                Event::Msg(CompositorMessage::SetLowPower(forced)) => {
+                    data.state.common.set_low_power(forced);
+                }
+                Event::Msg(CompositorMessage::SetBrightness {
+                    kind,
+                    percent,
+                    relative,
+                }) => {
+                    data.state.common.set_brightness(kind, percent, relative);
+                }
+                Event::Msg(CompositorMessage::Backlights { reply }) => {
+                    let _ = reply.send(data.state.common.backlights.devices.clone());
+                }
+                Event::Msg(CompositorMessage::SetTransferFilter(enabled)) => {
+                    data.state.common.transfers.set_enabled(enabled);
+                }
+                Event::Msg(CompositorMessage::DecideTransfer { request, allow }) => {
+                    data.state.common.transfers.decide(request, Some(allow));
+                }
+                Event::Msg(CompositorMessage::SetAutoHide(namespaces)) => {
+                    let shell = &mut data.state.common.shell;
+                    let outputs = shell.outputs.clone();
+                    let dh = data.display.handle();
+                    shell.auto_hide.set_namespaces(&dh, &outputs, namespaces);
+                }
+                Event::Msg(CompositorMessage::ThrottleWindow { id, fps }) => {
+                    if let Some(window) = data.state.common.shell.find_window(id) {
+                        shell::frames::throttle_window(&window, fps);
+                    }
+                }
+                Event::Msg(CompositorMessage::SetGameMode { id, enabled }) => {
+                    if let Some(window) = data.state.common.shell.find_window(id) {
+                        window.set_game_mode(enabled);
+                    }
+                }
+                Event::Msg(CompositorMessage::SetWindowPrivate { id, private }) => {
+                    if let Some(window) = data.state.common.shell.find_window(id) {
+                        window.set_private(private);
+                    }
+                }
+                Event::Msg(CompositorMessage::SetWindowShaded { id, shaded }) => {
+                    if let Some(window) = data.state.common.shell.find_window(id) {
+                        data.state.common.shell.set_shaded(&window, shaded);
+                    }
+                }
+                Event::Msg(CompositorMessage::SetFakeFullscreen { id, enabled }) => {
+                    if let Some(window) = data.state.common.shell.find_window(id) {
+                        let surface = window.toplevel().wl_surface();
+                        if let Some(workspace) =
+                            data.state.common.shell.space_for_window_mut(surface)
+                        {
+                            workspace.set_fake_fullscreen(&window, enabled);
+                        }
+                    }
+                }
+                Event::Msg(CompositorMessage::SetPip { id, corner }) => {
+                    let seat = data.state.common.last_active_seat.clone();
+                    let active = active_output(&seat, &data.state.common);
+                    if let Some(window) = data.state.common.shell.find_window(id) {
+                        let surface = window.toplevel().wl_surface();
+                        if let Some(workspace) =
+                            data.state.common.shell.space_for_window_mut(surface)
+                        {
+                            match corner {
+                                Some(corner) => {
+                                    let output = workspace
+                                        .space
+                                        .outputs_for_window(&window)
+                                        .into_iter()
+                                        .next()
+                                        .unwrap_or(active);
+                                    workspace.enter_pip(&window, &output, corner);
+                                }
+                                None => workspace.leave_pip(&window),
+                            }
+                        }
+                    }
+                }
+                Event::Msg(CompositorMessage::SetWindowMagnified { id, magnified }) => {
+                    if let Some(window) = data.state.common.shell.find_window(id) {
+                        data.state.common.shell.set_magnified(&window, magnified);
+                        if magnified {
+                            let dh = data.display.handle();
+                            let seat = data.state.common.last_active_seat.clone();
+                            let surface = window.toplevel().wl_surface().clone();
+                            data.state
+                                .common
+                                .set_focus(&dh, Some(&surface), &seat, None);
+                        }
+                    }
+                }
+                Event::Msg(CompositorMessage::FocusDirection { direction, reply }) => {
+                    let dh = data.display.handle();
+                    let seat = data.state.common.last_active_seat.clone();
+                    let focused = data.state.focus_direction(&dh, &seat, direction);
+                    let _ = reply.send(focused.map(|window| window.id()));
+                }
+                Event::Msg(CompositorMessage::PluginCall {
+                    plugin,
+                    op,
+                    args,
+                    reply,
+                }) => {
+                    let _ = reply.send(data.state.common.native_plugins.call(&plugin, &op, &args));
+                }
+                Event::Msg(CompositorMessage::CycleWindows { cycle, reply }) => {
+                    let dh = data.display.handle();
+                    let seat = data.state.common.last_active_seat.clone();
+                    let focused = data.state.cycle_windows(&dh, &seat, cycle);
+                    let _ = reply.send(focused.map(|window| window.id()));
+                }
+                Event::Msg(CompositorMessage::WarpPointer {
+                    target,
+                    animate,
+                    reply,
+                }) => {
+                    let dh = data.display.handle();
+                    let seat = data.state.common.last_active_seat.clone();
+                    let result = data.state.warp_pointer(&dh, &seat, target, animate);
+                    let _ = reply.send(result.map_err(|err| err.to_string()));
+                }
+                Event::Msg(CompositorMessage::StartDrag { offers, reply }) => {
+                    let dh = data.display.handle();
+                    let result = data.state.start_drag(&dh, offers);
+                    let _ = reply.send(result.map_err(|err| err.to_string()));
+                }
+                Event::Msg(CompositorMessage::Outputs { reply }) => {
+                    let _ = reply.send(data.state.common.output_infos());
+                }
+                Event::Msg(CompositorMessage::SetFocusFollowsWarp { enabled }) => {
+                    data.state.common.focus_follows_warp = enabled;
+                }
+                Event::Msg(CompositorMessage::ResetIdle) => data.state.common.idle_activity(),
+                Event::Msg(CompositorMessage::SetKeybindingMode { mode }) => {
+                    data.state.common.set_keybinding_mode(&mode);
+                }
+                Event::Msg(CompositorMessage::SetIdleInhibited { inhibited }) => {
+                    data.state.common.set_idle_inhibited(inhibited);
+                }
+                Event::Msg(CompositorMessage::ReadSelection {
+                    kind,
+                    mime_type,
+                    limit,
+                    reply,
+                }) => {
+                    data.state
+                        .common
+                        .read_selection(kind, mime_type, limit, reply);
+                }
+                // The runtime keeps a sender until it is dropped with the event loop
+                Event::Closed => {}
+            }
+        })
+        .expect("Failed to initalize compositor message channel");
+
+    sender
+}
+
+fn init_wayland_display(
+    event_loop: &mut EventLoop<LoopData>,
+    socket: Option<&str>,
+) -> Result<(Display<State>, OsString, RegistrationToken), Box<dyn Error>> {
+    let mut display = Display::new().unwrap();
+
+    let (socket_name, socket_token) = if let Some((listener, name)) = session::activated_socket() {
+        slog_scope::info!("Listening on {:?}, passed by the service manager", name);
+        let token = event_loop
+            .handle()
+            .insert_source(
+                Generic::new(listener, Interest::READ, Mode::Level),
+                |_, listener, data: &mut LoopData| {
+                    loop {
+                        match listener.accept() {
+                            Ok((stream, _)) => insert_client(data, stream, false),
+                            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                            Err(err) => {
+                                slog_scope::warn!("Wayland socket error: {}", err);
+                                break;
+                            }
+                        }
+                    }
+                    Ok(PostAction::Continue)
+                },
+            )
+            .map_err(|_| anyhow!("Failed to initialise Wayland socket"))?;
+        (name, token)
+    } else {
+        let socket_source = match socket {
+            // The name is joined to XDG_RUNTIME_DIR, it must not point anywhere else
+            Some(name) if name.is_empty() || name.contains('/') => {
+                return Err(format!("Invalid socket name {:?}", name).into());
+            }
+            Some(name) => ListeningSocketSource::with_name(name, None)
+                .with_context(|| format!("Failed to listen on {}", name))?,
+            None => ListeningSocketSource::new_auto(None)
+                .with_context(|| "Failed to find a free socket name")?,
+        };
+        let socket_name = socket_source.socket_name().to_os_string();
+        slog_scope::info!("Listening on {:?}", socket_name);
+
+        let token = event_loop
+            .handle()
+            .insert_source(socket_source, |stream, _, data| {
+                insert_client(data, stream, false)
+            })
+            .with_context(|| "Failed to initialise Wayland socket")?;
+        (socket_name, token)
+    };
+
+    event_loop
+        .handle()
+        .insert_source(
+            Generic::new(display.backend().poll_fd(), Interest::READ, Mode::Level),
+            move |_, _, data: &mut LoopData| {
+                data.state.common.watchdog.event("wayland clients");
+                match data.dispatch_clients() {
+                    Ok(_) => Ok(PostAction::Continue),
+                    Err(e) => {
+                        slog_scope::error!("I/O Error on display: {}", e);
+                        data.state.common.should_stop = true;
+                        Err(e)
+                    }
+                }
+            },
+        )
+        .with_context(|| "Failed to initialise Wayland event source")?;
+
+    Ok((display, socket_name, socket_token))
+}
+
+fn insert_client(data: &mut LoopData, stream: UnixStream, sandboxed: bool) {
+    let credentials = ClientCredentials::from_stream(&stream);
+    let client_state = data.state.new_client_state(credentials, sandboxed);
+    if let Err(err) = data
+        .display
+        .handle()
+        .insert_client(stream, Arc::new(client_state))
+    {
+        slog_scope::warn!("Error adding wayland client: {}", err);
+    };
+}
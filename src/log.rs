@@ -1,23 +1,389 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::error::Error;
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, RwLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use slog::Drain;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use slog::{Drain, Level, Never, OwnedKVList, Record, KV};
+
+/// How many records are kept in memory for post-mortem debugging
+const RING_BUFFER_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Write records to `$XDG_STATE_HOME/electrum/electrum.log` as well
+    pub file: bool,
+    /// Mebibytes the log file grows to before it is rotated, 0 never rotates it
+    pub max_size: u64,
+    /// How many rotated files are kept as `electrum.log.1` and up, the oldest is deleted
+    pub keep: usize,
+    /// Minimum levels by module path, for the module and everything in it. Wins over `log_level`.
+    pub modules: HashMap<String, String>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            file: true,
+            max_size: 10,
+            keep: 3,
+            modules: HashMap::new(),
+        }
+    }
+}
 
 pub struct LogState {
-    _guard: slog_scope::GlobalLoggerGuard,
+    /// Not set for the test fixture, which leaves the global logger alone
+    _guard: Option<slog_scope::GlobalLoggerGuard>,
+    level: Arc<AtomicUsize>,
+    modules: Arc<RwLock<Vec<(String, Level)>>>,
+    records: Arc<Mutex<VecDeque<LogRecord>>>,
+    file: Arc<Mutex<Option<mpsc::Sender<LogRecord>>>>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LogRecord {
+    /// Milliseconds since the unix epoch
+    pub time: u128,
+    pub level: &'static str,
+    pub module: &'static str,
+    pub message: String,
+    pub fields: Vec<(String, String)>,
+}
+
+impl LogState {
+    /// Logs nowhere, for compositors of the test fixture. Many of them share the process,
+    /// and with it the global logger.
+    #[cfg(test)]
+    pub fn detached() -> Self {
+        LogState {
+            _guard: None,
+            level: Arc::new(AtomicUsize::new(Level::Info.as_usize())),
+            modules: Arc::default(),
+            records: Arc::default(),
+            file: Arc::default(),
+        }
+    }
+
+    pub fn level(&self) -> Level {
+        Level::from_usize(self.level.load(Ordering::Relaxed)).unwrap_or(Level::Info)
+    }
+
+    pub fn set_level(&self, level: Level) {
+        self.level.store(level.as_usize(), Ordering::Relaxed);
+        slog_scope::info!("Log level set to {}", level.as_str());
+    }
+
+    /// Overrides the level for `module` and the modules in it, e.g. `electrum::input` or `smithay`
+    pub fn set_module_level(&self, module: &str, level: Level) {
+        let mut modules = self.modules.write().unwrap();
+        modules.retain(|(other, _)| other != module);
+        modules.push((module.to_string(), level));
+        drop(modules);
+        slog_scope::info!("Log level of {} set to {}", module, level.as_str());
+    }
+
+    /// Starts writing records to `path`, or the default log file, from a thread of its own. The
+    /// records kept in memory go first, so the file starts with what was logged before.
+    pub fn open_file(&self, path: Option<PathBuf>, config: &LogConfig) -> anyhow::Result<PathBuf> {
+        let path = match path {
+            Some(path) => path,
+            None => xdg::BaseDirectories::with_prefix("electrum")?
+                .place_state_file("electrum.log")
+                .context("Failed to create the log directory")?,
+        };
+        let max_size = config.max_size.saturating_mul(1024 * 1024);
+        let log_file = LogFile::open(path.clone(), max_size, config.keep)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+
+        let (sender, receiver) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("log-file".to_string())
+            .spawn(move || write_log_file(log_file, receiver))
+            .context("Failed to start the log file thread")?;
+
+        // Locked in the same order as the drain does, nothing is lost or written twice
+        let records = self.records.lock().unwrap();
+        let mut file = self.file.lock().unwrap();
+        for record in records.iter() {
+            let _ = sender.send(record.clone());
+        }
+        *file = Some(sender);
+        Ok(path)
+    }
+
+    /// The buffer itself, for the crash reporter
+    pub fn shared_records(&self) -> Arc<Mutex<VecDeque<LogRecord>>> {
+        self.records.clone()
+    }
+
+    /// The last `count` records, oldest first
+    pub fn records(&self, count: Option<usize>) -> Vec<LogRecord> {
+        let records = self.records.lock().unwrap();
+        let skip = count
+            .map(|count| records.len().saturating_sub(count))
+            .unwrap_or(0);
+        records.iter().skip(skip).cloned().collect()
+    }
+}
+
+pub fn parse_level(level: &str) -> anyhow::Result<Level> {
+    level
+        .parse::<Level>()
+        .map_err(|_| anyhow::anyhow!("Unknown log level {}", level))
+}
+
+/// Drops records below a level that can be changed at runtime, per module or for all of them
+struct RuntimeLevelFilter<D> {
+    drain: D,
+    level: Arc<AtomicUsize>,
+    modules: Arc<RwLock<Vec<(String, Level)>>>,
+}
+
+impl<D> RuntimeLevelFilter<D> {
+    /// The level of the innermost module with one set that `module` is or is in
+    fn module_level(&self, module: &str) -> Option<Level> {
+        let modules = self.modules.read().unwrap();
+        modules
+            .iter()
+            .filter(|(prefix, _)| {
+                module
+                    .strip_prefix(prefix.as_str())
+                    .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, level)| *level)
+    }
+}
+
+impl<D: Drain<Ok = (), Err = Never>> Drain for RuntimeLevelFilter<D> {
+    type Ok = ();
+    type Err = Never;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), Never> {
+        let level = self.module_level(record.module()).unwrap_or_else(|| {
+            Level::from_usize(self.level.load(Ordering::Relaxed)).unwrap_or(Level::Info)
+        });
+        if record.level().is_at_least(level) {
+            self.drain.log(record, values)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Keeps the last records around, so they can be queried over IPC, and passes them on to the log
+/// file once there is one
+struct RingBufferDrain {
+    records: Arc<Mutex<VecDeque<LogRecord>>>,
+    file: Arc<Mutex<Option<mpsc::Sender<LogRecord>>>>,
+}
+
+impl Drain for RingBufferDrain {
+    type Ok = ();
+    type Err = Never;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), Never> {
+        let mut fields = FieldCollector(Vec::new());
+        let _ = record.kv().serialize(record, &mut fields);
+        let _ = values.serialize(record, &mut fields);
+
+        let mut records = self.records.lock().unwrap();
+        if records.len() == RING_BUFFER_SIZE {
+            records.pop_front();
+        }
+        let record = LogRecord {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or(0),
+            level: record.level().as_str(),
+            module: record.module(),
+            message: record.msg().to_string(),
+            fields: fields.0,
+        };
+        let mut file = self.file.lock().unwrap();
+        if let Some(sender) = &*file {
+            // The thread only goes away after failing to write, the file is given up then
+            if sender.send(record.clone()).is_err() {
+                *file = None;
+            }
+        }
+        records.push_back(record);
+        Ok(())
+    }
+}
+
+/// The log file and the rotated ones next to it
+struct LogFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    size: u64,
+    /// In bytes, 0 doesn't rotate
+    max_size: u64,
+    keep: usize,
+}
+
+impl LogFile {
+    fn open(path: PathBuf, max_size: u64, keep: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+            size,
+            max_size,
+            keep,
+        })
+    }
+
+    fn write(&mut self, record: &LogRecord) -> io::Result<()> {
+        let line = format_record(record);
+        let len = line.len() as u64;
+        if self.max_size > 0 && self.size > 0 && self.size.saturating_add(len) > self.max_size {
+            self.rotate()?;
+        }
+        self.writer.write_all(line.as_bytes())?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// Moves every file one number up, `electrum.log` becomes `electrum.log.1`, and starts over
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        if self.keep > 0 {
+            let _ = fs::remove_file(self.rotated(self.keep));
+            for index in (1..self.keep).rev() {
+                let _ = fs::rename(self.rotated(index), self.rotated(index + 1));
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.writer = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
 }
 
-pub fn init_logger() -> Result<LogState, Box<dyn Error>> {
+/// Writes whatever records queued up at once and flushes, until the logger goes away. Logging
+/// from here would only queue up more, errors go to stderr.
+fn write_log_file(mut file: LogFile, records: mpsc::Receiver<LogRecord>) {
+    while let Ok(record) = records.recv() {
+        for record in std::iter::once(record).chain(records.try_iter()) {
+            if let Err(err) = file.write(&record) {
+                eprintln!("Failed to write {}: {}", file.path.display(), err);
+                return;
+            }
+        }
+        if let Err(err) = file.writer.flush() {
+            eprintln!("Failed to write {}: {}", file.path.display(), err);
+            return;
+        }
+    }
+}
+
+/// One line like `2022-08-20 14:03:07.215 INFO electrum::shell: Message key=value`, in UTC
+fn format_record(record: &LogRecord) -> String {
+    let secs = (record.time / 1000) as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let time = secs.rem_euclid(86400);
+    let mut line = format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03} {} {}: {}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        record.time % 1000,
+        record.level.to_uppercase(),
+        record.module,
+        record.message,
+    );
+    for (key, value) in &record.fields {
+        line.push_str(&format!(" {}={}", key, value));
+    }
+    line.push('\n');
+    line
+}
+
+/// The date `days` after the unix epoch, after Howard Hinnant's `civil_from_days`
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+struct FieldCollector(Vec<(String, String)>);
+
+impl slog::Serializer for FieldCollector {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        self.0.push((key.to_string(), val.to_string()));
+        Ok(())
+    }
+}
+
+/// Logs to stderr unless `quiet`, the log file is opened once the config is known
+pub fn init_logger(quiet: bool) -> Result<LogState, Box<dyn Error>> {
     let decorator = slog_term::TermDecorator::new().stderr().build();
 
+    let level = Arc::new(AtomicUsize::new(if cfg!(feature = "debug") {
+        Level::Trace.as_usize()
+    } else {
+        Level::Debug.as_usize()
+    }));
+    let modules = Arc::new(RwLock::new(Vec::new()));
+    let records = Arc::new(Mutex::new(VecDeque::with_capacity(RING_BUFFER_SIZE)));
+    let file = Arc::new(Mutex::new(None));
+
     let logger = slog::Logger::root(
-        std::sync::Mutex::new(
-            slog_term::CompactFormat::new(decorator)
-                .build()
-                .ignore_res(),
-        )
-        .fuse(),
+        RuntimeLevelFilter {
+            drain: slog::Duplicate::new(
+                std::sync::Mutex::new(
+                    slog_term::CompactFormat::new(decorator)
+                        .build()
+                        .filter(move |_| !quiet)
+                        .ignore_res(),
+                ),
+                RingBufferDrain {
+                    records: records.clone(),
+                    file: file.clone(),
+                },
+            )
+            .fuse(),
+            level: level.clone(),
+            modules: modules.clone(),
+        },
         slog::o!(),
     );
 
@@ -32,5 +398,11 @@ pub fn init_logger() -> Result<LogState, Box<dyn Error>> {
         );
     }
 
-    Ok(LogState { _guard })
+    Ok(LogState {
+        _guard: Some(_guard),
+        level,
+        modules,
+        records,
+        file,
+    })
 }
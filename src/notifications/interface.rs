@@ -0,0 +1,100 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use calloop::channel::Sender;
+use zbus::{dbus_interface, zvariant::OwnedValue};
+
+use super::{hint_string, Notification, NotificationRequest};
+
+/// `org.freedesktop.Notifications` server, rendering is left to the runtime
+pub struct Notifications {
+    sender: Sender<NotificationRequest>,
+    next_id: AtomicU32,
+}
+
+impl Notifications {
+    pub fn new(sender: Sender<NotificationRequest>) -> Self {
+        Self {
+            sender,
+            next_id: AtomicU32::new(1),
+        }
+    }
+}
+
+#[dbus_interface(name = "org.freedesktop.Notifications")]
+impl Notifications {
+    fn get_capabilities(&self) -> Vec<&str> {
+        vec![
+            "actions",
+            "body",
+            "body-markup",
+            "icon-static",
+            "persistence",
+        ]
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: String,
+        replaces_id: u32,
+        app_icon: String,
+        summary: String,
+        body: String,
+        actions: Vec<String>,
+        hints: HashMap<String, OwnedValue>,
+        expire_timeout: i32,
+    ) -> u32 {
+        let id = if replaces_id != 0 {
+            replaces_id
+        } else {
+            self.next_id.fetch_add(1, Ordering::SeqCst)
+        };
+
+        let urgency = hints
+            .get("urgency")
+            .and_then(|value| u8::try_from(value).ok())
+            .unwrap_or(1);
+        let notification = Notification {
+            id,
+            app_name,
+            app_icon,
+            summary,
+            body,
+            actions: actions
+                .chunks_exact(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .collect(),
+            urgency,
+            category: hint_string(&hints, "category"),
+            desktop_entry: hint_string(&hints, "desktop-entry"),
+            expire_timeout,
+        };
+
+        if self
+            .sender
+            .send(NotificationRequest::Notify(notification))
+            .is_err()
+        {
+            slog_scope::warn!("Dropped notification {}, compositor is gone", id);
+        }
+        id
+    }
+
+    fn close_notification(&self, id: u32) {
+        let _ = self.sender.send(NotificationRequest::Close(id));
+    }
+
+    fn get_server_information(&self) -> (&str, &str, &str, &str) {
+        (
+            "electrum",
+            "Fyra Labs",
+            std::env!("CARGO_PKG_VERSION"),
+            "1.2",
+        )
+    }
+}
@@ -0,0 +1,133 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use calloop::channel::{channel, Event};
+use serde::Serialize;
+use smithay::reexports::calloop::EventLoop;
+use zbus::blocking::{Connection, ConnectionBuilder};
+
+use crate::{runtime::messages::RuntimeMessage, state::LoopData};
+
+use self::interface::Notifications;
+
+mod interface;
+
+pub const BUS_NAME: &str = "org.freedesktop.Notifications";
+pub const OBJECT_PATH: &str = "/org/freedesktop/Notifications";
+
+/// A notification as received over dbus, forwarded as-is into the runtime
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    pub id: u32,
+    pub app_name: String,
+    pub app_icon: String,
+    pub summary: String,
+    pub body: String,
+    /// Pairs of action key and label
+    pub actions: Vec<(String, String)>,
+    pub urgency: u8,
+    pub category: Option<String>,
+    pub desktop_entry: Option<String>,
+    /// Milliseconds, `-1` lets the runtime decide and `0` never expires
+    pub expire_timeout: i32,
+}
+
+/// Why a notification went away, as defined by the notification spec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum CloseReason {
+    Expired = 1,
+    Dismissed = 2,
+    Closed = 3,
+    Undefined = 4,
+}
+
+impl From<u32> for CloseReason {
+    fn from(reason: u32) -> Self {
+        match reason {
+            1 => CloseReason::Expired,
+            2 => CloseReason::Dismissed,
+            3 => CloseReason::Closed,
+            _ => CloseReason::Undefined,
+        }
+    }
+}
+
+pub enum NotificationRequest {
+    Notify(Notification),
+    Close(u32),
+}
+
+pub struct NotificationState {
+    connection: Connection,
+}
+
+impl NotificationState {
+    pub fn closed(&self, id: u32, reason: CloseReason) {
+        self.emit("NotificationClosed", &(id, reason as u32));
+    }
+
+    pub fn action_invoked(&self, id: u32, action_key: &str) {
+        self.emit("ActionInvoked", &(id, action_key));
+    }
+
+    fn emit<B>(&self, signal: &str, body: &B)
+    where
+        B: serde::Serialize + zbus::zvariant::DynamicType,
+    {
+        if let Err(err) =
+            self.connection
+                .emit_signal(None::<()>, OBJECT_PATH, BUS_NAME, signal, body)
+        {
+            slog_scope::warn!("Failed to emit {}: {}", signal, err);
+        }
+    }
+}
+
+pub fn init_notifications(
+    event_loop: &mut EventLoop<LoopData>,
+) -> anyhow::Result<NotificationState> {
+    let (sender, channel) = channel::<NotificationRequest>();
+
+    event_loop
+        .handle()
+        .insert_source(channel, |message, _, data| {
+            let message = match message {
+                Event::Msg(NotificationRequest::Notify(notification)) => {
+                    RuntimeMessage::Notification(notification)
+                }
+                Event::Msg(NotificationRequest::Close(id)) => RuntimeMessage::NotificationClose(id),
+                Event::Closed => return,
+            };
+            data.state
+                .common
+                .shell
+                .active_workspace()
+                .runtime_sender
+                .send(message)
+                .unwrap();
+        })
+        .map_err(|_| anyhow::anyhow!("Failed to initialise notification channel"))?;
+
+    let connection = ConnectionBuilder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, Notifications::new(sender))?
+        .build()
+        .with_context(|| "Failed to register notification daemon, is another one running?")?;
+    slog_scope::info!("Notification daemon registered as {}", BUS_NAME);
+
+    Ok(NotificationState { connection })
+}
+
+pub(crate) fn hint_string(
+    hints: &HashMap<String, zbus::zvariant::OwnedValue>,
+    key: &str,
+) -> Option<String> {
+    hints
+        .get(key)
+        .and_then(|value| <&str>::try_from(value).ok())
+        .map(str::to_string)
+}
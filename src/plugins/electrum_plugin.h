@@ -0,0 +1,71 @@
+/* SPDX-License-Identifier: GPL-3.0-only */
+
+/* The interface between Electrum and plugins it loads at runtime, see README.md next to this file */
+
+#ifndef ELECTRUM_PLUGIN_H
+#define ELECTRUM_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define ELECTRUM_PLUGIN_ABI_VERSION 2
+
+#define ELECTRUM_LOG_ERROR 1
+#define ELECTRUM_LOG_WARNING 2
+#define ELECTRUM_LOG_INFO 3
+#define ELECTRUM_LOG_DEBUG 4
+
+typedef struct ElectrumHost ElectrumHost;
+
+/* Called with the arguments of op_electrum_plugin_call as JSON. Returns 0 and sets `result` to a
+ * JSON value, or anything else and sets `result` to an error message. `result` is handed back to
+ * `free_string` of the plugin, or left alone if it is NULL. */
+typedef int (*ElectrumOpFn)(void *data, const char *args, char **result);
+
+/* A rectangle in physical pixels with the origin at the top left */
+typedef struct {
+    int32_t x;
+    int32_t y;
+    int32_t width;
+    int32_t height;
+} ElectrumRect;
+
+/* Draws into the area of `output` in the framebuffer, in physical pixels with the origin at the top
+ * left. Only the `n_damage` rectangles in `damage` are redrawn, everything outside of them is on
+ * screen already and must not be drawn over again, e.g. use them as scissor boxes. The GLES 2
+ * context of the compositor is current and has to be left in the state it was found in. */
+typedef void (*ElectrumRenderFn)(void *data, const char *output, int32_t x, int32_t y,
+                                 int32_t width, int32_t height, double scale,
+                                 const ElectrumRect *damage, size_t n_damage);
+
+typedef struct {
+    uint32_t abi_version;
+    ElectrumHost *host;
+    /* Fails with -1 if the plugin has an op called `name` already */
+    int (*register_op)(ElectrumHost *host, const char *name, ElectrumOpFn op, void *data);
+    /* Hooks draw above windows and overlays, in the order they were registered */
+    void (*register_render_hook)(ElectrumHost *host, ElectrumRenderFn render, void *data);
+    /* The render hooks are only called again after this, for every output */
+    void (*request_redraw)(ElectrumHost *host);
+    void (*log)(ElectrumHost *host, int level, const char *message);
+} ElectrumHostApi;
+
+typedef struct {
+    /* ELECTRUM_PLUGIN_ABI_VERSION as the plugin was built */
+    uint32_t abi_version;
+    /* Has to match the file name without `.so` for plugins found in the plugin directories */
+    const char *name;
+    const char *version;
+    /* Returns 0 on success. `api` stays valid until `deinit` returns. Plugins without it are
+     * refused. */
+    int (*init)(const ElectrumHostApi *api);
+    /* May be NULL */
+    void (*deinit)(void);
+    /* Frees what ops return, may be NULL if they return static strings */
+    void (*free_string)(char *string);
+} ElectrumPlugin;
+
+/* Every plugin exports this */
+const ElectrumPlugin *electrum_plugin(void);
+
+#endif
@@ -0,0 +1,342 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::hash_map::DefaultHasher,
+    ffi::{c_void, CStr, CString},
+    hash::{Hash, Hasher},
+    os::raw::{c_char, c_int},
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use anyhow::{anyhow, Context};
+use libloading::{Library, Symbol};
+use serde::Deserialize;
+
+/// `ELECTRUM_PLUGIN_ABI_VERSION` in `electrum_plugin.h`, bumped with every change to the structs
+pub const ABI_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PluginConfig {
+    /// Plugins that may be loaded, by name from `electrum/plugins` in the XDG data directories, or
+    /// as absolute paths. Nothing else is ever opened.
+    pub allow: Vec<String>,
+}
+
+type OpFn = unsafe extern "C" fn(*mut c_void, *const c_char, *mut *mut c_char) -> c_int;
+type RenderFn = unsafe extern "C" fn(
+    *mut c_void,
+    *const c_char,
+    i32,
+    i32,
+    i32,
+    i32,
+    f64,
+    *const PluginRect,
+    usize,
+);
+
+/// `ElectrumRect`
+#[repr(C)]
+struct PluginRect {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+/// `ElectrumPlugin`
+#[repr(C)]
+struct PluginInfo {
+    abi_version: u32,
+    name: *const c_char,
+    version: *const c_char,
+    init: Option<unsafe extern "C" fn(*const HostApi) -> c_int>,
+    deinit: Option<unsafe extern "C" fn()>,
+    free_string: Option<unsafe extern "C" fn(*mut c_char)>,
+}
+
+/// `ElectrumHostApi`
+#[repr(C)]
+struct HostApi {
+    abi_version: u32,
+    host: *mut PluginHost,
+    register_op: unsafe extern "C" fn(*mut PluginHost, *const c_char, OpFn, *mut c_void) -> c_int,
+    register_render_hook: unsafe extern "C" fn(*mut PluginHost, RenderFn, *mut c_void),
+    request_redraw: unsafe extern "C" fn(*mut PluginHost),
+    log: unsafe extern "C" fn(*mut PluginHost, c_int, *const c_char),
+}
+
+/// A render hook of a plugin, called while a frame is drawn
+#[derive(Clone, Copy)]
+pub struct RenderHook {
+    render: RenderFn,
+    data: *mut c_void,
+}
+
+impl RenderHook {
+    /// Lets the plugin draw into the physical `area` of `output`, where it is in `damage`
+    pub fn render(
+        &self,
+        output: &str,
+        area: (i32, i32, i32, i32),
+        scale: f64,
+        damage: &[(i32, i32, i32, i32)],
+    ) {
+        let output = CString::new(output).unwrap_or_default();
+        let (x, y, width, height) = area;
+        let damage = damage
+            .iter()
+            .map(|&(x, y, width, height)| PluginRect {
+                x,
+                y,
+                width,
+                height,
+            })
+            .collect::<Vec<_>>();
+        unsafe {
+            (self.render)(
+                self.data,
+                output.as_ptr(),
+                x,
+                y,
+                width,
+                height,
+                scale,
+                damage.as_ptr(),
+                damage.len(),
+            )
+        }
+    }
+}
+
+/// What a plugin registered, `ElectrumHost` to the plugin
+struct PluginHost {
+    name: String,
+    ops: RefCell<Vec<(String, OpFn, *mut c_void)>>,
+    render_hooks: RefCell<Vec<RenderHook>>,
+    /// Counts redraw requests, part of the id of render hook elements
+    redraws: Cell<usize>,
+}
+
+/// A plugin that was initialized, deinitialized when dropped
+struct LoadedPlugin {
+    version: String,
+    info: *const PluginInfo,
+    host: Box<PluginHost>,
+    _api: Box<HostApi>,
+    // Dropped last, everything above points into it
+    _library: Library,
+}
+
+impl Drop for LoadedPlugin {
+    fn drop(&mut self) {
+        if let Some(deinit) = unsafe { (*self.info).deinit } {
+            unsafe { deinit() };
+        }
+    }
+}
+
+/// Shared objects loaded through the C interface in `electrum_plugin.h`. All of their callbacks
+/// run on the compositor thread.
+#[derive(Default)]
+pub struct NativePlugins {
+    plugins: Vec<LoadedPlugin>,
+}
+
+impl NativePlugins {
+    /// Loads every plugin in `allow`, those that fail are logged and left out
+    pub fn load(config: &PluginConfig) -> Self {
+        let mut plugins = Vec::new();
+        for entry in &config.allow {
+            match load_plugin(entry) {
+                Ok(plugin) => {
+                    slog_scope::info!("Loaded plugin {} {}", plugin.host.name, plugin.version);
+                    plugins.push(plugin);
+                }
+                Err(err) => slog_scope::warn!("Failed to load plugin {}: {:?}", entry, err),
+            }
+        }
+        Self { plugins }
+    }
+
+    /// Calls the op `op` of `plugin` with `args`, and parses what it returns
+    pub fn call(
+        &self,
+        plugin: &str,
+        op: &str,
+        args: &serde_json::Value,
+    ) -> Result<serde_json::Value, String> {
+        let loaded = self
+            .plugins
+            .iter()
+            .find(|loaded| loaded.host.name == plugin)
+            .ok_or_else(|| format!("No plugin {} is loaded", plugin))?;
+        let (call, data) = loaded
+            .host
+            .ops
+            .borrow()
+            .iter()
+            .find(|(name, _, _)| name == op)
+            .map(|(_, call, data)| (*call, *data))
+            .ok_or_else(|| format!("Plugin {} has no op {}", plugin, op))?;
+
+        let args = CString::new(args.to_string()).map_err(|err| err.to_string())?;
+        let mut result = ptr::null_mut();
+        let status = unsafe { call(data, args.as_ptr(), &mut result) };
+        let text = if result.is_null() {
+            String::new()
+        } else {
+            let text = unsafe { CStr::from_ptr(result) }
+                .to_string_lossy()
+                .into_owned();
+            if let Some(free_string) = unsafe { (*loaded.info).free_string } {
+                unsafe { free_string(result) };
+            }
+            text
+        };
+        match status {
+            0 if text.is_empty() => Ok(serde_json::Value::Null),
+            0 => serde_json::from_str(&text)
+                .map_err(|err| format!("Op {} of {} returned invalid JSON: {}", op, plugin, err)),
+            _ => Err(text),
+        }
+    }
+
+    /// Every render hook with an id that changes when its plugin asks for a redraw
+    pub fn render_hooks(&self) -> Vec<(usize, RenderHook)> {
+        let mut hooks = Vec::new();
+        for plugin in &self.plugins {
+            let host = &plugin.host;
+            for (index, hook) in host.render_hooks.borrow().iter().enumerate() {
+                let mut hasher = DefaultHasher::new();
+                ("plugin", &host.name, index, host.redraws.get()).hash(&mut hasher);
+                hooks.push((hasher.finish() as usize, *hook));
+            }
+        }
+        hooks
+    }
+}
+
+/// The shared object `entry` stands for, if it may be loaded
+fn plugin_path(entry: &str) -> anyhow::Result<PathBuf> {
+    if Path::new(entry).is_absolute() {
+        return Ok(PathBuf::from(entry));
+    }
+    if entry.contains('/') {
+        return Err(anyhow!("Plugins are given by name or absolute path"));
+    }
+    xdg::BaseDirectories::with_prefix("electrum")?
+        .find_data_file(format!("plugins/{}.so", entry))
+        .ok_or_else(|| anyhow!("Not found in the plugin directories"))
+}
+
+fn load_plugin(entry: &str) -> anyhow::Result<LoadedPlugin> {
+    let path = plugin_path(entry)?;
+    let library = unsafe { Library::new(&path) }
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let info = unsafe {
+        let symbol: Symbol<unsafe extern "C" fn() -> *const PluginInfo> = library
+            .get(b"electrum_plugin\0")
+            .context("No electrum_plugin function")?;
+        symbol()
+    };
+    if info.is_null() {
+        return Err(anyhow!("electrum_plugin returned nothing"));
+    }
+
+    let abi_version = unsafe { (*info).abi_version };
+    if abi_version != ABI_VERSION {
+        return Err(anyhow!(
+            "Built for plugin ABI {}, this is {}",
+            abi_version,
+            ABI_VERSION
+        ));
+    }
+    let init = unsafe { (*info).init }.ok_or_else(|| anyhow!("Has no init function"))?;
+    let name = unsafe { string((*info).name) };
+    let version = unsafe { string((*info).version) };
+    if !Path::new(entry).is_absolute() && name != entry {
+        return Err(anyhow!("Calls itself {}", name));
+    }
+
+    let mut host = Box::new(PluginHost {
+        name,
+        ops: RefCell::default(),
+        render_hooks: RefCell::default(),
+        redraws: Cell::new(0),
+    });
+    let api = Box::new(HostApi {
+        abi_version: ABI_VERSION,
+        host: &mut *host,
+        register_op,
+        register_render_hook,
+        request_redraw,
+        log,
+    });
+    let status = unsafe { init(&*api) };
+    if status != 0 {
+        return Err(anyhow!("Initialization failed with {}", status));
+    }
+
+    Ok(LoadedPlugin {
+        version,
+        info,
+        host,
+        _api: api,
+        _library: library,
+    })
+}
+
+unsafe fn string(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(ptr).to_string_lossy().into_owned()
+}
+
+unsafe extern "C" fn register_op(
+    host: *mut PluginHost,
+    name: *const c_char,
+    op: OpFn,
+    data: *mut c_void,
+) -> c_int {
+    let host = &*host;
+    let name = string(name);
+    let mut ops = host.ops.borrow_mut();
+    if ops.iter().any(|(other, _, _)| *other == name) {
+        return -1;
+    }
+    ops.push((name, op, data));
+    0
+}
+
+unsafe extern "C" fn register_render_hook(
+    host: *mut PluginHost,
+    render: RenderFn,
+    data: *mut c_void,
+) {
+    let host = &*host;
+    host.render_hooks
+        .borrow_mut()
+        .push(RenderHook { render, data });
+    host.redraws.set(host.redraws.get() + 1);
+}
+
+unsafe extern "C" fn request_redraw(host: *mut PluginHost) {
+    let host = &*host;
+    host.redraws.set(host.redraws.get() + 1);
+}
+
+unsafe extern "C" fn log(host: *mut PluginHost, level: c_int, message: *const c_char) {
+    let host = &*host;
+    let message = string(message);
+    match level {
+        1 => slog_scope::error!("{}: {}", host.name, message),
+        2 => slog_scope::warn!("{}: {}", host.name, message),
+        3 => slog_scope::info!("{}: {}", host.name, message),
+        _ => slog_scope::debug!("{}: {}", host.name, message),
+    }
+}
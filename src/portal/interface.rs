@@ -0,0 +1,110 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::HashMap;
+
+use calloop::channel::Sender;
+use futures::channel::oneshot;
+use zbus::{dbus_interface, fdo, zvariant::OwnedValue};
+
+use crate::config::ColorScheme;
+
+use super::PortalRequest;
+
+const APPEARANCE_NAMESPACE: &str = "org.freedesktop.appearance";
+
+/// Compositor side helpers for portal backends
+pub struct Portal {
+    sender: Sender<PortalRequest>,
+}
+
+impl Portal {
+    pub fn new(sender: Sender<PortalRequest>) -> Self {
+        Self { sender }
+    }
+
+    async fn request<T>(
+        &self,
+        make: impl FnOnce(oneshot::Sender<T>) -> PortalRequest,
+    ) -> fdo::Result<T> {
+        let (reply, receiver) = oneshot::channel();
+        self.sender
+            .send(make(reply))
+            .map_err(|_| fdo::Error::Failed("Compositor is shutting down".to_string()))?;
+        receiver
+            .await
+            .map_err(|_| fdo::Error::Failed("Request was dropped".to_string()))
+    }
+}
+
+#[dbus_interface(name = "dev.fyralabs.Electrum.Portal")]
+impl Portal {
+    /// Captures an output, the active one if `output` is empty. Returns a file uri.
+    async fn screenshot(&self, output: String) -> fdo::Result<String> {
+        let output = Some(output).filter(|o| !o.is_empty());
+        self.request(|reply| PortalRequest::Screenshot { output, reply })
+            .await?
+            .map_err(fdo::Error::Failed)
+    }
+
+    /// Lets the user click on an output, used for screencast source selection
+    async fn pick_output(&self) -> fdo::Result<String> {
+        self.request(|reply| PortalRequest::PickOutput { reply })
+            .await?
+            .ok_or_else(|| fdo::Error::Failed("Selection cancelled".to_string()))
+    }
+
+    async fn list_outputs(&self) -> fdo::Result<Vec<String>> {
+        self.request(|reply| PortalRequest::ListOutputs { reply })
+            .await
+    }
+}
+
+pub struct Settings {
+    color_scheme: ColorScheme,
+}
+
+impl Settings {
+    pub fn new(color_scheme: ColorScheme) -> Self {
+        Self { color_scheme }
+    }
+
+    fn color_scheme(&self) -> OwnedValue {
+        OwnedValue::from(self.color_scheme as u32)
+    }
+}
+
+#[dbus_interface(name = "org.freedesktop.impl.portal.Settings")]
+impl Settings {
+    fn read_all(&self, namespaces: Vec<String>) -> HashMap<String, HashMap<String, OwnedValue>> {
+        let mut settings = HashMap::new();
+        let matches = namespaces.is_empty()
+            || namespaces.iter().any(|namespace| {
+                namespace == APPEARANCE_NAMESPACE
+                    || namespace
+                        .strip_suffix('*')
+                        .map(|prefix| APPEARANCE_NAMESPACE.starts_with(prefix))
+                        .unwrap_or(false)
+            });
+        if matches {
+            let mut appearance = HashMap::new();
+            appearance.insert("color-scheme".to_string(), self.color_scheme());
+            settings.insert(APPEARANCE_NAMESPACE.to_string(), appearance);
+        }
+        settings
+    }
+
+    fn read(&self, namespace: &str, key: &str) -> fdo::Result<OwnedValue> {
+        match (namespace, key) {
+            (APPEARANCE_NAMESPACE, "color-scheme") => Ok(self.color_scheme()),
+            _ => Err(fdo::Error::Failed(format!(
+                "Requested setting {}.{} not found",
+                namespace, key
+            ))),
+        }
+    }
+
+    #[dbus_interface(property)]
+    fn version(&self) -> u32 {
+        1
+    }
+}
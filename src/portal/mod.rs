@@ -0,0 +1,190 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    env,
+    fs::OpenOptions,
+    io::BufWriter,
+    os::unix::fs::OpenOptionsExt,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use calloop::channel::{channel, Event, Sender};
+use futures::channel::oneshot;
+use image::{codecs::png::PngEncoder, ColorType, ImageEncoder, RgbaImage};
+use smithay::{
+    reexports::calloop::EventLoop,
+    wayland::{
+        output::Output,
+        seat::{Focus, PointerGrabStartData},
+        SERIAL_COUNTER,
+    },
+};
+use zbus::blocking::{Connection, ConnectionBuilder};
+
+use crate::{
+    config::ColorScheme,
+    state::{output::active_output, LoopData},
+};
+
+use self::{
+    interface::{Portal, Settings},
+    picker::OutputPickerGrab,
+};
+
+mod interface;
+mod picker;
+
+pub const BUS_NAME: &str = "org.freedesktop.impl.portal.desktop.electrum";
+pub const OBJECT_PATH: &str = "/org/freedesktop/portal/desktop";
+
+/// Requests coming in from the portal dbus interfaces, answered on the compositor thread
+pub enum PortalRequest {
+    Screenshot {
+        output: Option<String>,
+        reply: oneshot::Sender<Result<String, String>>,
+    },
+    PickOutput {
+        reply: oneshot::Sender<Option<String>>,
+    },
+    ListOutputs {
+        reply: oneshot::Sender<Vec<String>>,
+    },
+}
+
+/// A screenshot waiting for the next frame of `output` to be rendered
+pub struct ScreenshotRequest {
+    pub output: Output,
+    pub reply: oneshot::Sender<Result<String, String>>,
+}
+
+pub struct PortalState {
+    _connection: Connection,
+}
+
+pub fn init_portal(
+    event_loop: &mut EventLoop<LoopData>,
+    color_scheme: ColorScheme,
+) -> anyhow::Result<PortalState> {
+    let (sender, channel) = channel::<PortalRequest>();
+
+    event_loop
+        .handle()
+        .insert_source(channel, |message, _, data| match message {
+            Event::Msg(request) => data.state.common.handle_portal_request(request),
+            Event::Closed => {}
+        })
+        .map_err(|_| anyhow::anyhow!("Failed to initialise portal channel"))?;
+
+    let connection = build_connection(sender, color_scheme)
+        .with_context(|| "Failed to register portal on the session bus")?;
+    slog_scope::info!("Portal backend registered as {}", BUS_NAME);
+
+    Ok(PortalState {
+        _connection: connection,
+    })
+}
+
+fn build_connection(
+    sender: Sender<PortalRequest>,
+    color_scheme: ColorScheme,
+) -> zbus::Result<Connection> {
+    ConnectionBuilder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, Portal::new(sender))?
+        .serve_at(OBJECT_PATH, Settings::new(color_scheme))?
+        .build()
+}
+
+impl crate::state::CommonState {
+    fn handle_portal_request(&mut self, request: PortalRequest) {
+        match request {
+            PortalRequest::Screenshot { output, reply } => {
+                let output = match output {
+                    Some(name) => self.shell.outputs().find(|o| o.name() == name).cloned(),
+                    None => Some(active_output(&self.last_active_seat, self)),
+                };
+                match output {
+                    Some(output) => self
+                        .screenshot_requests
+                        .push(ScreenshotRequest { output, reply }),
+                    None => {
+                        let _ = reply.send(Err("No such output".to_string()));
+                    }
+                }
+            }
+            PortalRequest::PickOutput { reply } => {
+                let seat = self.last_active_seat.clone();
+                let pointer = match seat.get_pointer() {
+                    Some(pointer) => pointer,
+                    None => {
+                        let _ = reply.send(None);
+                        return;
+                    }
+                };
+                let start_data = PointerGrabStartData {
+                    focus: None,
+                    button: 0,
+                    location: pointer.current_location(),
+                };
+                pointer.set_grab(
+                    OutputPickerGrab::new(start_data, reply),
+                    SERIAL_COUNTER.next_serial(),
+                    Focus::Clear,
+                );
+            }
+            PortalRequest::ListOutputs { reply } => {
+                let _ = reply.send(self.shell.outputs().map(|o| o.name()).collect());
+            }
+        }
+    }
+}
+
+/// Encodes a captured frame on a worker thread and answers `replies` with its uri, or the
+/// error
+pub fn save_screenshot(image: RgbaImage, replies: Vec<oneshot::Sender<Result<String, String>>>) {
+    let spawned = std::thread::Builder::new()
+        .name("screenshot".to_string())
+        .spawn(move || {
+            let result = write_screenshot(&image).map_err(|err| {
+                slog_scope::warn!("Screenshot failed: {:?}", err);
+                err.to_string()
+            });
+            for reply in replies {
+                let _ = reply.send(result.clone());
+            }
+        });
+    if let Err(err) = spawned {
+        slog_scope::warn!("Failed to start saving a screenshot: {}", err);
+    }
+}
+
+/// Writes `image` to the runtime directory, only readable by the user, and returns its uri.
+/// Without a runtime directory there is nowhere private to put it.
+fn write_screenshot(image: &RgbaImage) -> anyhow::Result<String> {
+    let mut path = env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow::anyhow!("XDG_RUNTIME_DIR is not set"))?;
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    path.push(format!("electrum-screenshot-{}.png", timestamp));
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    PngEncoder::new(BufWriter::new(file))
+        .write_image(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            ColorType::Rgba8,
+        )
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(format!("file://{}", path.display()))
+}
@@ -0,0 +1,83 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use futures::channel::oneshot;
+use smithay::{
+    reexports::wayland_server::DisplayHandle,
+    wayland::seat::{
+        AxisFrame, ButtonEvent, MotionEvent, PointerGrab, PointerGrabStartData, PointerInnerHandle,
+    },
+};
+
+use crate::state::{output::OutputExt, State};
+
+/// Pointer grab selecting the output under the next click
+pub struct OutputPickerGrab {
+    start_data: PointerGrabStartData,
+    reply: Option<oneshot::Sender<Option<String>>>,
+}
+
+impl OutputPickerGrab {
+    pub fn new(start_data: PointerGrabStartData, reply: oneshot::Sender<Option<String>>) -> Self {
+        Self {
+            start_data,
+            reply: Some(reply),
+        }
+    }
+}
+
+impl Drop for OutputPickerGrab {
+    fn drop(&mut self) {
+        // Grab got replaced before anything was picked
+        if let Some(reply) = self.reply.take() {
+            let _ = reply.send(None);
+        }
+    }
+}
+
+impl PointerGrab<State> for OutputPickerGrab {
+    fn motion(
+        &mut self,
+        _state: &mut State,
+        _dh: &DisplayHandle,
+        handle: &mut PointerInnerHandle<'_, State>,
+        event: &MotionEvent,
+    ) {
+        // While picking, no client has pointer focus
+        handle.motion(event.location, None, event.serial, event.time);
+    }
+
+    fn button(
+        &mut self,
+        state: &mut State,
+        _dh: &DisplayHandle,
+        handle: &mut PointerInnerHandle<'_, State>,
+        event: &ButtonEvent,
+    ) {
+        if handle.current_pressed().is_empty() {
+            let location = handle.current_location();
+            let output = state
+                .common
+                .shell
+                .outputs()
+                .find(|o| o.geometry().to_f64().contains(location))
+                .map(|o| o.name());
+            if let Some(reply) = self.reply.take() {
+                let _ = reply.send(output);
+            }
+            handle.unset_grab(event.serial, event.time);
+        }
+    }
+
+    fn axis(
+        &mut self,
+        _state: &mut State,
+        _dh: &DisplayHandle,
+        _handle: &mut PointerInnerHandle<'_, State>,
+        _details: AxisFrame,
+    ) {
+    }
+
+    fn start_data(&self) -> &PointerGrabStartData {
+        &self.start_data
+    }
+}
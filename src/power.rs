@@ -0,0 +1,163 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::time::Duration;
+
+use calloop::channel::{channel, Event, Sender};
+use serde::{Deserialize, Serialize};
+use smithay::reexports::calloop::EventLoop;
+use zbus::blocking::{Connection, Proxy};
+
+use crate::{
+    runtime::messages::RuntimeMessage,
+    shell::frames::FramePolicy,
+    state::{CommonState, LoopData},
+};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PowerConfig {
+    /// Running on battery is low power as well, not only the `power-saver` profile
+    pub on_battery: bool,
+    /// Most frames per second rendered in low power mode, 0 doesn't limit them
+    pub max_fps: u32,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self {
+            on_battery: true,
+            max_fps: 30,
+        }
+    }
+}
+
+/// What the system reported about power, sent by the watcher threads
+enum PowerReport {
+    OnBattery(bool),
+    PowerSaver(bool),
+}
+
+/// Whether effects and frame rates are cut back to save power
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PowerState {
+    pub low_power: bool,
+    /// As reported by UPower
+    pub on_battery: bool,
+    /// power-profiles-daemon has the `power-saver` profile active
+    pub power_saver: bool,
+    /// Set by the script, wins over what the system reports
+    pub forced: Option<bool>,
+}
+
+/// Follows UPower and power-profiles-daemon on the system bus, neither has to be running
+pub fn init_power(event_loop: &mut EventLoop<LoopData>) -> anyhow::Result<()> {
+    let (sender, channel) = channel::<PowerReport>();
+    event_loop
+        .handle()
+        .insert_source(channel, |report, _, data| {
+            let common = &mut data.state.common;
+            match report {
+                Event::Msg(PowerReport::OnBattery(battery)) => common.power.on_battery = battery,
+                Event::Msg(PowerReport::PowerSaver(saver)) => common.power.power_saver = saver,
+                Event::Closed => return,
+            }
+            common.update_power();
+        })
+        .map_err(|_| anyhow::anyhow!("Failed to initialise power channel"))?;
+
+    // Waiting for property changes blocks, so each property gets a thread
+    let upower = sender.clone();
+    std::thread::spawn(move || {
+        let result = watch(
+            "org.freedesktop.UPower",
+            "/org/freedesktop/UPower",
+            "OnBattery",
+            |on_battery: bool| upower.send(PowerReport::OnBattery(on_battery)).is_ok(),
+        );
+        if let Err(err) = result {
+            slog_scope::debug!("Not following the battery: {}", err);
+        }
+    });
+    std::thread::spawn(move || {
+        let result = watch(
+            "net.hadess.PowerProfiles",
+            "/net/hadess/PowerProfiles",
+            "ActiveProfile",
+            |profile: String| {
+                let saver = profile == "power-saver";
+                sender.send(PowerReport::PowerSaver(saver)).is_ok()
+            },
+        );
+        if let Err(err) = result {
+            slog_scope::debug!("Not following the power profile: {}", err);
+        }
+    });
+    Ok(())
+}
+
+/// Calls `report` with `property` of the service `name` and again whenever it changes, until
+/// `report` returns false
+fn watch<T>(
+    name: &'static str,
+    path: &'static str,
+    property: &'static str,
+    report: impl Fn(T) -> bool,
+) -> zbus::Result<()>
+where
+    T: TryFrom<zbus::zvariant::OwnedValue>,
+    T::Error: Into<zbus::Error>,
+{
+    let connection = Connection::system()?;
+    let proxy = Proxy::new(&connection, name, path, name)?;
+    if !report(proxy.get_property(property)?) {
+        return Ok(());
+    }
+    for changed in proxy.receive_property_changed::<T>(property) {
+        if !report(changed.get()?) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl CommonState {
+    /// Overrides what the system reports, `None` follows it again
+    pub fn set_low_power(&mut self, forced: Option<bool>) {
+        self.power.forced = forced;
+        self.update_power();
+    }
+
+    /// Works out whether to save power and tells the script what is known now
+    fn update_power(&mut self) {
+        let system =
+            self.power.power_saver || (self.config.power.on_battery && self.power.on_battery);
+        let low_power = self.power.forced.unwrap_or(system);
+        if low_power != self.power.low_power {
+            slog_scope::info!("Low power mode {}", if low_power { "on" } else { "off" });
+        }
+        self.power.low_power = low_power;
+        let runtime_sender = &self.shell.active_workspace().runtime_sender;
+        let _ = runtime_sender.send(RuntimeMessage::PowerChanged(self.power));
+    }
+
+    /// Slides and fades are left out with reduced motion and in low power mode
+    pub fn animations(&self) -> bool {
+        !self.accessibility.reduce_motion && !self.power.low_power
+    }
+
+    /// Hidden windows get frame callbacks less often in low power mode
+    pub fn frame_policy(&self) -> FramePolicy {
+        match (self.frame_policy, self.power.low_power) {
+            (FramePolicy::Always, true) => FramePolicy::Throttle,
+            (FramePolicy::Throttle, true) => FramePolicy::Suspend,
+            (policy, _) => policy,
+        }
+    }
+
+    /// Shortest time between two frames, `None` if frames are not limited
+    pub fn frame_interval(&self) -> Option<Duration> {
+        let max_fps = self.config.power.max_fps;
+        (self.power.low_power && max_fps > 0).then(|| Duration::from_secs(1) / max_fps)
+    }
+}
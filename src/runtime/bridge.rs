@@ -0,0 +1,182 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    cell::RefCell,
+    pin::Pin,
+    rc::Rc,
+    sync::mpsc::TrySendError,
+    task::{Context, Poll},
+};
+
+use anyhow::anyhow;
+use calloop::channel::{sync_channel, Channel, SyncSender};
+use deno_core::error::AnyError;
+use futures::{
+    channel::mpsc::{self, Receiver, UnboundedReceiver, UnboundedSender},
+    future::AbortHandle,
+    Stream, StreamExt,
+};
+
+use super::{extension::Event, messages::CompositorMessage};
+
+/// Requests of the runtime the compositor has not handled yet, before ops start failing
+const REQUEST_QUEUE: usize = 256;
+/// Events the runtime has not polled yet, before new ones are dropped
+const EVENT_QUEUE: usize = 256;
+
+/// The runtime end of the channel into the compositor, bounded so a runaway script fails
+/// instead of growing the queue forever
+#[derive(Clone)]
+pub struct RequestSender(SyncSender<CompositorMessage>);
+
+impl RequestSender {
+    pub fn send(&self, message: CompositorMessage) -> Result<(), AnyError> {
+        self.0.try_send(message).map_err(|err| match err {
+            TrySendError::Full(_) => anyhow!(
+                "The compositor has {} requests queued, try again later",
+                REQUEST_QUEUE
+            ),
+            TrySendError::Disconnected(_) => anyhow!("Compositor is no longer running"),
+        })
+    }
+}
+
+/// The channel for `CompositorMessage`s, the receiving end is a calloop source
+pub fn request_channel() -> (RequestSender, Channel<CompositorMessage>) {
+    let (sender, channel) = sync_channel(REQUEST_QUEUE);
+    (RequestSender(sender), channel)
+}
+
+/// The compositor end of the event queue. Events something waits on the answer to skip the
+/// bound, so they can't be dropped because of the events queued before them.
+pub struct EventSender {
+    events: mpsc::Sender<Event>,
+    urgent: UnboundedSender<Event>,
+}
+
+/// The end `op_electrum_poll_events` reads from, urgent events come first
+pub struct EventReceiver {
+    events: Receiver<Event>,
+    urgent: UnboundedReceiver<Event>,
+}
+
+impl Stream for EventReceiver {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        let urgent = self.urgent.poll_next_unpin(cx);
+        if let Poll::Ready(Some(event)) = urgent {
+            return Poll::Ready(Some(event));
+        }
+        match self.events.poll_next_unpin(cx) {
+            // Both are closed together, but an urgent event may still be on its way
+            Poll::Ready(None) if urgent.is_pending() => Poll::Pending,
+            poll => poll,
+        }
+    }
+}
+
+/// The queue for the events of one worker
+pub fn event_queue() -> (EventSender, EventReceiver) {
+    let (events, receiver) = mpsc::channel(EVENT_QUEUE);
+    let (urgent, urgent_receiver) = mpsc::unbounded();
+    (
+        EventSender { events, urgent },
+        EventReceiver {
+            events: receiver,
+            urgent: urgent_receiver,
+        },
+    )
+}
+
+struct Worker {
+    events: EventSender,
+    abort: AbortHandle,
+    dropped: usize,
+}
+
+impl Worker {
+    fn cancel(mut self) {
+        self.events.events.close_channel();
+        self.events.urgent.close_channel();
+        self.abort.abort();
+    }
+}
+
+/// The compositor end of the event queue of the running worker, if there is one
+#[derive(Clone, Default)]
+pub struct RuntimeBridge(Rc<RefCell<Option<Worker>>>);
+
+impl RuntimeBridge {
+    /// Routes events to a new worker, cancelling the one before it
+    pub fn attach(&self, events: EventSender, abort: AbortHandle) {
+        let previous = self.0.borrow_mut().replace(Worker {
+            events,
+            abort,
+            dropped: 0,
+        });
+        if let Some(previous) = previous {
+            slog_scope::info!("Cancelling the previous runtime worker");
+            previous.cancel();
+        }
+    }
+
+    /// Cancels the worker, a pending `op_electrum_poll_events` resolves with `null`
+    pub fn detach(&self) {
+        if let Some(worker) = self.0.borrow_mut().take() {
+            worker.cancel();
+        }
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.0.borrow().is_some()
+    }
+
+    /// Queues an event that needs an answer, like a request to decide on or `Shutdown`. It
+    /// only fails when there is no worker polling, the compositor answers it itself then.
+    pub fn deliver(&self, event: Event) -> bool {
+        let worker = self.0.borrow();
+        let worker = match worker.as_ref() {
+            Some(worker) => worker,
+            None => return false,
+        };
+        match worker.events.urgent.unbounded_send(event) {
+            Ok(()) => true,
+            Err(_) => {
+                slog_scope::debug!("The runtime stopped polling events");
+                false
+            }
+        }
+    }
+
+    /// Queues `event` for the worker, dropping it if the worker is gone or falls behind
+    pub fn forward(&self, event: Event) {
+        let mut worker = self.0.borrow_mut();
+        let worker = match worker.as_mut() {
+            Some(worker) => worker,
+            None => return,
+        };
+        match worker.events.events.try_send(event) {
+            Ok(()) => {
+                if worker.dropped > 0 {
+                    slog_scope::warn!(
+                        "The runtime caught up, {} events were dropped",
+                        worker.dropped
+                    );
+                    worker.dropped = 0;
+                }
+            }
+            Err(err) if err.is_full() => {
+                if worker.dropped == 0 {
+                    slog_scope::warn!(
+                        "The runtime has {} events queued, dropping {:?}",
+                        EVENT_QUEUE,
+                        err.into_inner()
+                    );
+                }
+                worker.dropped += 1;
+            }
+            Err(_) => slog_scope::debug!("The runtime stopped polling events"),
+        }
+    }
+}
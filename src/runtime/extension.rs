@@ -1,40 +1,713 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use deno_core::error::AnyError;
 use deno_core::serde::Serialize;
-use deno_core::{Extension, op, OpState};
-use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender, unbounded};
+use deno_core::{Extension, op, OpState, ZeroCopyBuf};
+use futures::channel::oneshot;
 use futures::StreamExt;
 
+use crate::appinfo::AppInfo;
+use crate::backend::renderer::background::Background;
+use crate::backend::renderer::filter::OutputFilter;
+use crate::backend::renderer::overlay::Overlay;
+use crate::backend::renderer::textures::GpuMemory;
+use crate::backlight::{Backlight, BacklightKind};
+use crate::config::OutputConfig;
+use crate::input::pointer_bindings::PointerContext;
+use crate::input::warp::WarpTarget;
+use crate::notifications::Notification;
+use crate::power::PowerState;
+use crate::shell::cycle::Cycle;
+use crate::shell::decisions::Decision;
+use crate::shell::direction::Direction;
+use crate::shell::frames::FramePolicy;
+use crate::shell::layout::zones::DropZone;
+use crate::shell::layout::Struts;
+use crate::shell::pip::Corner;
+use crate::shell::switcher::SwitcherStyle;
+use crate::shell::window::WindowInfo;
+use crate::shell::workspace::WorkspacesInfo;
+use crate::state::diagnostics::DiagnosticsReport;
+use crate::state::latency::LatencyReport;
+use crate::state::output::OutputInfo;
+use crate::state::selection::{SelectionKind, DEFAULT_READ_LIMIT};
+use crate::state::tree::Tree;
+
+use super::bridge::{event_queue, EventReceiver, EventSender, RequestSender};
+use super::messages::CompositorMessage;
+use super::registry::{RegistryDiff, RuntimeKeybinding, RuntimeLayerRule, RuntimePointerBinding, RuntimeRule};
+
 #[derive(Serialize, Debug)]
 pub enum Event {
-  Ping
+  Ping,
+  Notification(Notification),
+  NotificationClose { id: u32 },
+  Shutdown,
+  WindowUrgency { id: usize, urgent: bool },
+  OutputProfile { name: Option<String> },
+  OutputsChanged { outputs: Vec<OutputInfo> },
+  DragEnded { dropped: bool, action: Option<String> },
+  IdleStage { stage: String },
+  ScaleChanged { output: String, scale: f64 },
+  #[serde(rename_all = "camelCase")]
+  SelectionOwnerChanged { selection: SelectionKind, mime_types: Vec<String> },
+  WorkspaceRequested { index: u32 },
+  WorkspacesChanged { active: String, workspaces: Vec<String> },
+  WindowAssigned { id: usize, workspace: u32 },
+  KeyboardLayout { index: usize, name: String },
+  PanelVisibility { namespace: String, output: String, visible: bool },
+  MaximizeRequest { request: u64, window: usize, output: String },
+  UnmaximizeRequest { request: u64, window: usize },
+  ResizeRequest { request: u64, window: usize, edges: u32 },
+  #[serde(rename_all = "camelCase")]
+  TransferRequest { request: u64, selection: SelectionKind, source: Option<String>, target: Option<String>, mime_types: Vec<String> },
+  ClientPanicked { client: String, pid: Option<u32>, executable: Option<PathBuf>, message: String },
+  Keybinding { name: String, generation: u64 },
+  PointerBinding { name: String, generation: u64, context: String, window: Option<usize> },
+  KeybindingMode { mode: String },
+  Reload,
+  PowerChanged { power: PowerState },
+  BrightnessChanged { backlight: Backlight },
+  Plugin { plugin: &'static str, name: String, data: serde_json::Value },
+}
+
+impl Event {
+  /// What the runtime subscribes to this event by
+  pub fn name(&self) -> &'static str {
+    match self {
+      Event::Ping => "Ping",
+      Event::Notification(_) => "Notification",
+      Event::NotificationClose { .. } => "NotificationClose",
+      Event::Shutdown => "Shutdown",
+      Event::WindowUrgency { .. } => "WindowUrgency",
+      Event::OutputProfile { .. } => "OutputProfile",
+      Event::OutputsChanged { .. } => "OutputsChanged",
+      Event::DragEnded { .. } => "DragEnded",
+      Event::IdleStage { .. } => "IdleStage",
+      Event::ScaleChanged { .. } => "ScaleChanged",
+      Event::SelectionOwnerChanged { .. } => "SelectionOwnerChanged",
+      Event::WorkspaceRequested { .. } => "WorkspaceRequested",
+      Event::WorkspacesChanged { .. } => "WorkspacesChanged",
+      Event::WindowAssigned { .. } => "WindowAssigned",
+      Event::KeyboardLayout { .. } => "KeyboardLayout",
+      Event::PanelVisibility { .. } => "PanelVisibility",
+      Event::MaximizeRequest { .. } => "MaximizeRequest",
+      Event::UnmaximizeRequest { .. } => "UnmaximizeRequest",
+      Event::ResizeRequest { .. } => "ResizeRequest",
+      Event::TransferRequest { .. } => "TransferRequest",
+      Event::ClientPanicked { .. } => "ClientPanicked",
+      Event::Keybinding { .. } => "Keybinding",
+      Event::PointerBinding { .. } => "PointerBinding",
+      Event::KeybindingMode { .. } => "KeybindingMode",
+      Event::Reload => "Reload",
+      Event::PowerChanged { .. } => "PowerChanged",
+      Event::BrightnessChanged { .. } => "BrightnessChanged",
+      Event::Plugin { .. } => "Plugin",
+    }
+  }
 }
 
+/// Generation the runtime last committed, keybinding and pointer binding events of older ones
+/// are dropped
+#[derive(Clone, Default)]
+struct CommittedGeneration(Rc<Cell<u64>>);
+
 #[op]
 pub async fn op_electrum_poll_events(state: &mut OpState) -> Result<Option<Event>, AnyError> {
-  let mut channel = state.borrow_mut::<Rc<RefCell<UnboundedReceiver<Event>>>>().try_borrow_mut()?;
-  let val = channel.next().await;
-  println!("{:?}", val);
-  Ok(val)
+  let receiver = state.borrow::<Rc<RefCell<EventReceiver>>>().clone();
+  let committed = state.borrow::<CommittedGeneration>().clone();
+  let mut channel = receiver.try_borrow_mut()?;
+  loop {
+    let val = channel.next().await;
+    slog_scope::trace!("Polled runtime event {:?}", val);
+    match val {
+      // Pressed before a reload finished, the name may mean something else by now
+      Some(Event::Keybinding { generation, .. }) if generation < committed.0.get() => continue,
+      Some(Event::PointerBinding { generation, .. }) if generation < committed.0.get() => continue,
+      val => return Ok(val),
+    }
+  }
+}
+
+fn send(state: &mut OpState, message: CompositorMessage) -> Result<(), AnyError> {
+  state.borrow::<RequestSender>().send(message)
+}
+
+/// Sends a request to the compositor and waits for its answer
+async fn request<T>(
+  state: &Rc<RefCell<OpState>>,
+  make: impl FnOnce(oneshot::Sender<T>) -> CompositorMessage,
+) -> Result<T, AnyError> {
+  let (reply, receiver) = oneshot::channel();
+  send(&mut state.borrow_mut(), make(reply))?;
+  Ok(receiver.await?)
+}
+
+/// Tells the sender of a notification that it went away, see `CloseReason` for `reason`
+#[op]
+pub fn op_electrum_notification_closed(state: &mut OpState, id: u32, reason: u32) -> Result<(), AnyError> {
+  send(state, CompositorMessage::NotificationClosed { id, reason })
+}
+
+#[op]
+pub fn op_electrum_notification_action(state: &mut OpState, id: u32, action: String) -> Result<(), AnyError> {
+  send(state, CompositorMessage::NotificationAction { id, action })
+}
+
+/// Writes into the compositor log, `fields` are attached as a JSON string
+#[op]
+pub fn op_electrum_log(level: String, message: String, fields: Option<serde_json::Value>) -> Result<(), AnyError> {
+  let level = crate::log::parse_level(&level)?;
+  let fields = fields.map(|fields| fields.to_string()).unwrap_or_default();
+  slog::log!(slog_scope::logger(), level, "", "{}", message; "source" => "runtime", "fields" => fields);
+  Ok(())
+}
+
+#[op]
+pub fn op_electrum_set_log_level(state: &mut OpState, level: String, module: Option<String>) -> Result<(), AnyError> {
+  let level = crate::log::parse_level(&level)?;
+  send(state, CompositorMessage::SetLogLevel { level, module })
+}
+
+/// Lets the compositor continue shutting down after a `Shutdown` event
+#[op]
+pub fn op_electrum_shutdown_ready(state: &mut OpState) -> Result<(), AnyError> {
+  send(state, CompositorMessage::ShutdownReady)
+}
+
+/// Marks a window as demanding attention, or clears it
+#[op]
+pub fn op_electrum_set_window_urgent(state: &mut OpState, id: usize, urgent: bool) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetWindowUrgent { id, urgent })
+}
+
+/// Answers the request with correlation id `request`, see `Decision`
+#[op]
+pub fn op_electrum_decide(state: &mut OpState, request: u64, decision: Decision) -> Result<(), AnyError> {
+  send(state, CompositorMessage::Decide { request, decision })
+}
+
+/// Changes when hidden windows get frame callbacks
+#[op]
+pub fn op_electrum_set_frame_policy(state: &mut OpState, policy: FramePolicy) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetFramePolicy(policy))
+}
+
+/// Limits a window to `fps` frame callbacks per second, `null` lifts the limit
+#[op]
+pub fn op_electrum_throttle_window(state: &mut OpState, id: usize, fps: Option<u32>) -> Result<(), AnyError> {
+  send(state, CompositorMessage::ThrottleWindow { id, fps })
+}
+
+/// Puts a window into game mode or takes it out, `null` follows its content type and tearing hints
+#[op]
+pub fn op_electrum_set_game_mode(state: &mut OpState, id: usize, enabled: Option<bool>) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetGameMode { id, enabled })
+}
+
+/// Maximizes a window instead of making it fullscreen when it asks for that, or stops doing so
+#[op]
+pub fn op_electrum_set_fake_fullscreen(state: &mut OpState, id: usize, enabled: bool) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetFakeFullscreen { id, enabled })
+}
+
+/// Shrinks a window into `corner` of its output above the other windows, `null` puts it back
+#[op]
+pub fn op_electrum_set_pip(state: &mut OpState, id: usize, corner: Option<Corner>) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetPip { id, corner })
+}
+
+/// Shows a placeholder instead of a window in screenshots and thumbnails, or stops doing so
+#[op]
+pub fn op_electrum_set_window_private(state: &mut OpState, id: usize, private: bool) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetWindowPrivate { id, private })
+}
+
+/// Collapses a window to its title bar, or brings it back
+#[op]
+pub fn op_electrum_set_window_shaded(state: &mut OpState, id: usize, shaded: bool) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetWindowShaded { id, shaded })
+}
+
+/// Scales a window up to fill its output for a closer look, or goes back to the desktop
+#[op]
+pub fn op_electrum_set_window_magnified(state: &mut OpState, id: usize, magnified: bool) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetWindowMagnified { id, magnified })
+}
+
+/// Focuses the closest window to the left, right, above or below the focused one, returning its
+/// id or `null` if there is none
+#[op]
+pub async fn op_electrum_focus_direction(state: Rc<RefCell<OpState>>, direction: Direction) -> Result<Option<usize>, AnyError> {
+  request(&state, |reply| CompositorMessage::FocusDirection { direction, reply }).await
+}
+
+/// Calls the op `name` of the shared object plugin `plugin` with `args` and resolves with what it returns
+#[op]
+pub async fn op_electrum_plugin_call(state: Rc<RefCell<OpState>>, plugin: String, name: String, args: serde_json::Value) -> Result<serde_json::Value, AnyError> {
+  request(&state, |reply| CompositorMessage::PluginCall { plugin, op: name, args, reply }).await?.map_err(|err| anyhow!(err))
+}
+
+/// Focuses the window of the workspace opened after or before the focused one and returns its id
+#[op]
+pub async fn op_electrum_cycle_windows(state: Rc<RefCell<OpState>>, cycle: Cycle) -> Result<Option<usize>, AnyError> {
+  request(&state, |reply| CompositorMessage::CycleWindows { cycle, reply }).await
+}
+
+/// Turns the pointer button being held into a drag of `offers`, data by mime type
+#[op]
+pub async fn op_electrum_start_drag(state: Rc<RefCell<OpState>>, offers: HashMap<String, String>) -> Result<(), AnyError> {
+  request(&state, |reply| CompositorMessage::StartDrag { offers, reply }).await?.map_err(|err| anyhow!(err))
+}
+
+/// Every connected output with its geometry, mode, scale and transform
+#[op]
+pub async fn op_electrum_outputs(state: Rc<RefCell<OpState>>) -> Result<Vec<OutputInfo>, AnyError> {
+  request(&state, |reply| CompositorMessage::Outputs { reply }).await
+}
+
+/// Moves the pointer to the center of `{ output: name }` or `{ window: id }`, gliding there if
+/// `animate`
+#[op]
+pub async fn op_electrum_warp_pointer(state: Rc<RefCell<OpState>>, target: WarpTarget, animate: bool) -> Result<(), AnyError> {
+  request(&state, |reply| CompositorMessage::WarpPointer { target, animate, reply }).await?.map_err(|err| anyhow!(err))
+}
+
+/// Whether warping the pointer focuses the window it lands on
+#[op]
+pub fn op_electrum_set_focus_follows_warp(state: &mut OpState, enabled: bool) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetFocusFollowsWarp { enabled })
+}
+
+/// Switches to the keybindings of `mode`, `"default"` goes back to those without a mode
+#[op]
+pub fn op_electrum_set_keybinding_mode(state: &mut OpState, mode: String) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetKeybindingMode { mode })
+}
+
+/// Starts the idle time over, to cancel a stage from an `IdleStage` handler
+#[op]
+pub fn op_electrum_reset_idle(state: &mut OpState) -> Result<(), AnyError> {
+  send(state, CompositorMessage::ResetIdle)
+}
+
+/// Keeps outputs from dimming or turning off until turned off again
+#[op]
+pub fn op_electrum_set_idle_inhibited(state: &mut OpState, inhibited: bool) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetIdleInhibited { inhibited })
+}
+
+/// Converts colors on `output` into the ICC profile at `path`, `null` turns that off again
+#[op]
+pub async fn op_electrum_set_icc_profile(
+  state: Rc<RefCell<OpState>>,
+  output: String,
+  path: Option<String>,
+) -> Result<(), AnyError> {
+  request(&state, |reply| CompositorMessage::SetIccProfile { output, path, reply })
+    .await?
+    .map_err(|err| anyhow!(err))
+}
+
+/// Puts a color filter like `"grayscale"` over everything on `output`, `null` removes it
+#[op]
+pub async fn op_electrum_set_output_filter(
+  state: Rc<RefCell<OpState>>,
+  output: String,
+  filter: Option<OutputFilter>,
+) -> Result<(), AnyError> {
+  request(&state, |reply| CompositorMessage::SetOutputFilter { output, filter, reply })
+    .await?
+    .map_err(|err| anyhow!(err))
+}
+
+/// Changes mode, position, scale, ICC profile and filter of several outputs at once. Nothing is
+/// applied if one of them fails, with `test` nothing is applied at all.
+#[op]
+pub async fn op_electrum_configure_outputs(
+  state: Rc<RefCell<OpState>>,
+  outputs: Vec<OutputConfig>,
+  test: bool,
+) -> Result<(), AnyError> {
+  request(&state, |reply| CompositorMessage::ConfigureOutputs { outputs, test, reply })
+    .await?
+    .map_err(|err| anyhow!(err))
+}
+
+/// Keeps windows away from the edges of `output`, like `{ top: 32 }` for a bar drawn by the runtime
+#[op]
+pub async fn op_electrum_reserve_area(
+  state: Rc<RefCell<OpState>>,
+  output: String,
+  struts: Struts,
+) -> Result<(), AnyError> {
+  request(&state, |reply| CompositorMessage::SetStruts { output, struts, reply })
+    .await?
+    .map_err(|err| anyhow!(err))
+}
+
+/// Replaces the areas of `output` windows can be dragged into to tile them
+#[op]
+pub async fn op_electrum_set_drop_zones(
+  state: Rc<RefCell<OpState>>,
+  output: String,
+  zones: Vec<DropZone>,
+) -> Result<(), AnyError> {
+  request(&state, |reply| CompositorMessage::SetDropZones { output, zones, reply })
+    .await?
+    .map_err(|err| anyhow!(err))
+}
+
+/// Replaces the keyboard layouts that can be switched between, like `["us", "de(nodeadkeys)"]`
+#[op]
+pub async fn op_electrum_set_keyboard_layouts(
+  state: Rc<RefCell<OpState>>,
+  layouts: Vec<String>,
+) -> Result<(), AnyError> {
+  request(&state, |reply| CompositorMessage::SetKeyboardLayouts { layouts, reply })
+    .await?
+    .map_err(|err| anyhow!(err))
+}
+
+/// Switches to the keyboard layout at `index`, or the next one without it
+#[op]
+pub async fn op_electrum_switch_keyboard_layout(
+  state: Rc<RefCell<OpState>>,
+  index: Option<usize>,
+) -> Result<(), AnyError> {
+  request(&state, |reply| CompositorMessage::SwitchKeyboardLayout { index, reply })
+    .await?
+    .map_err(|err| anyhow!(err))
+}
+
+/// Turns sticky keys on or off, and sets the slow and bounce keys delays in milliseconds
+#[op]
+pub fn op_electrum_set_accessibility(
+  state: &mut OpState,
+  sticky_keys: bool,
+  slow_keys: Option<u64>,
+  bounce_keys: Option<u64>,
+) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetKeyAccessibility { sticky_keys, slow_keys, bounce_keys })
+}
+
+/// Clicks after the pointer rested for `delay` milliseconds, `null` turns it off
+#[op]
+pub fn op_electrum_set_dwell_click(state: &mut OpState, delay: Option<u64>) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetDwellClick(delay))
+}
+
+/// Changes the looks of the window switcher, omitted fields go back to their defaults
+#[op]
+pub fn op_electrum_set_switcher_style(state: &mut OpState, style: SwitcherStyle) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetSwitcherStyle(style))
+}
+
+/// Replaces the overlay called `name` on `output`, `null` removes it
+#[op]
+pub fn op_electrum_set_overlay(state: &mut OpState, output: String, name: String, overlay: Option<Overlay>) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetOverlay { output, name, overlay })
+}
+
+/// Draws `background` below the windows of `workspace`, on `output` or on every output without
+/// one of its own. `null` removes it.
+#[op]
+pub async fn op_electrum_set_background(state: Rc<RefCell<OpState>>, workspace: u32, output: Option<String>, background: Option<Background>) -> Result<(), AnyError> {
+  request(&state, |reply| CompositorMessage::SetBackground { workspace, output, background, reply }).await?.map_err(|err| anyhow!(err))
+}
+
+/// Draws client cursors `scale` times larger, between 1 and 4
+#[op]
+pub fn op_electrum_set_cursor_scale(state: &mut OpState, scale: f64) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetCursorScale(scale))
+}
+
+/// Switches workspaces at once instead of sliding between them
+#[op]
+pub fn op_electrum_set_reduce_motion(state: &mut OpState, enabled: bool) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetReduceMotion(enabled))
+}
+
+/// Turns low power mode on or off whatever the system reports, `None` follows it again
+#[op]
+pub fn op_electrum_set_low_power(state: &mut OpState, enabled: Option<bool>) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetLowPower(enabled))
+}
+
+/// The display and keyboard backlights and their brightness
+#[op]
+pub async fn op_electrum_backlights(state: Rc<RefCell<OpState>>) -> Result<Vec<Backlight>, AnyError> {
+  request(&state, |reply| CompositorMessage::Backlights { reply }).await
+}
+
+/// Sets the backlight of `kind` to `percent` of its maximum
+#[op]
+pub fn op_electrum_set_brightness(state: &mut OpState, kind: BacklightKind, percent: f64) -> Result<(), AnyError> {
+  let percent = finite_percent(percent)?;
+  send(state, CompositorMessage::SetBrightness { kind, percent, relative: false })
+}
+
+/// Changes the backlight of `kind` by `percent` of its maximum, negative to dim it
+#[op]
+pub fn op_electrum_adjust_brightness(state: &mut OpState, kind: BacklightKind, percent: f64) -> Result<(), AnyError> {
+  let percent = finite_percent(percent)?;
+  send(state, CompositorMessage::SetBrightness { kind, percent, relative: true })
+}
+
+/// NaN and infinities would end up as a brightness of 0 or the maximum
+fn finite_percent(percent: f64) -> Result<f64, AnyError> {
+  if percent.is_finite() {
+    Ok(percent)
+  } else {
+    Err(anyhow!("Brightness has to be a finite percentage, not {}", percent))
+  }
+}
+
+/// Replaces the namespaces of layer surfaces that hide while the pointer is not over them
+#[op]
+pub fn op_electrum_set_auto_hide(state: &mut OpState, namespaces: Vec<String>) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetAutoHide(namespaces))
+}
+
+/// Reads a selection owned by a client, at most `limit` bytes and never more than 1 MiB
+#[op]
+pub async fn op_electrum_read_selection(
+  state: Rc<RefCell<OpState>>,
+  selection: SelectionKind,
+  mime_type: String,
+  limit: Option<usize>,
+) -> Result<ZeroCopyBuf, AnyError> {
+  let limit = limit.unwrap_or(DEFAULT_READ_LIMIT).min(DEFAULT_READ_LIMIT);
+  let contents = request(&state, |reply| CompositorMessage::ReadSelection { kind: selection, mime_type, limit, reply })
+    .await?
+    .map_err(|err| anyhow!(err))?;
+  Ok(contents.into())
+}
+
+/// Asks with `TransferRequest` events before a client is offered a selection of another app
+#[op]
+pub fn op_electrum_set_transfer_filter(state: &mut OpState, enabled: bool) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetTransferFilter(enabled))
+}
+
+/// Answers the `TransferRequest` with correlation id `request`
+#[op]
+pub fn op_electrum_decide_transfer(state: &mut OpState, request: u64, allow: bool) -> Result<(), AnyError> {
+  send(state, CompositorMessage::DecideTransfer { request, allow })
+}
+
+#[op]
+pub async fn op_electrum_client_diagnostics(state: Rc<RefCell<OpState>>) -> Result<DiagnosticsReport, AnyError> {
+  request(&state, |reply| CompositorMessage::ClientDiagnostics { reply }).await
+}
+
+/// Name of the wayland socket clients connect to, what `WAYLAND_DISPLAY` should be set to
+#[op]
+pub async fn op_electrum_socket_name(state: Rc<RefCell<OpState>>) -> Result<String, AnyError> {
+  request(&state, |reply| CompositorMessage::SocketName { reply }).await
+}
+
+/// Runs `command` through `sh -c` as a client that never sees privileged globals, resolves with the pid of the shell
+#[op]
+pub async fn op_electrum_spawn_sandboxed(state: Rc<RefCell<OpState>>, command: String) -> Result<u32, AnyError> {
+  request(&state, |reply| CompositorMessage::SpawnSandboxed { command, reply }).await?.map_err(|err| anyhow!(err))
+}
+
+/// Mapped windows with their title, app id and the process of their client
+#[op]
+pub async fn op_electrum_windows(state: Rc<RefCell<OpState>>) -> Result<Vec<WindowInfo>, AnyError> {
+  request(&state, |reply| CompositorMessage::Windows { reply }).await
+}
+
+/// The names of all workspaces and of the shown one
+#[op]
+pub async fn op_electrum_workspaces(state: Rc<RefCell<OpState>>) -> Result<WorkspacesInfo, AnyError> {
+  request(&state, |reply| CompositorMessage::Workspaces { reply }).await
+}
+
+/// Outputs, workspaces, windows and seats as the compositor tracks them, for bug reports
+#[op]
+pub async fn op_electrum_dump_tree(state: Rc<RefCell<OpState>>) -> Result<Tree, AnyError> {
+  request(&state, |reply| CompositorMessage::Tree { reply }).await
+}
+
+/// Shows the workspace called `name`, created if there is none
+#[op]
+pub fn op_electrum_switch_workspace(state: &mut OpState, name: String) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SwitchWorkspace { name })
+}
+
+/// Goes back to the workspace shown before
+#[op]
+pub fn op_electrum_workspace_back_and_forth(state: &mut OpState) -> Result<(), AnyError> {
+  send(state, CompositorMessage::WorkspaceBackAndForth)
+}
+
+/// Name and icon of an application, from its desktop entry and the icon theme
+#[op]
+pub async fn op_electrum_app_info(state: Rc<RefCell<OpState>>, app_id: String) -> Result<AppInfo, AnyError> {
+  request(&state, |reply| CompositorMessage::AppInfo { app_id, reply }).await
+}
+
+/// Bytes of GPU memory the compositor holds on to for its own drawing
+#[op]
+pub async fn op_electrum_gpu_memory(state: Rc<RefCell<OpState>>) -> Result<GpuMemory, AnyError> {
+  request(&state, |reply| CompositorMessage::GpuMemory { reply }).await
+}
+
+/// Starts or stops measuring input latency, the samples so far are kept
+#[op]
+pub fn op_electrum_set_latency_tracking(state: &mut OpState, enabled: bool) -> Result<(), AnyError> {
+  send(state, CompositorMessage::SetLatencyTracking(enabled))
+}
+
+/// Drops the input latency samples measured so far
+#[op]
+pub fn op_electrum_reset_latency(state: &mut OpState) -> Result<(), AnyError> {
+  send(state, CompositorMessage::ResetLatency)
+}
+
+/// Percentiles of the input latency measured since the last reset
+#[op]
+pub async fn op_electrum_latency(state: Rc<RefCell<OpState>>) -> Result<LatencyReport, AnyError> {
+  request(&state, |reply| CompositorMessage::Latency { reply }).await
+}
+
+/// Starts a new generation of keybindings, window rules and subscriptions, the current one stays
+/// in effect until it is committed
+#[op]
+pub async fn op_electrum_begin_generation(state: Rc<RefCell<OpState>>) -> Result<u64, AnyError> {
+  request(&state, |reply| CompositorMessage::BeginGeneration { reply }).await
+}
+
+/// Sends a `Keybinding` event named `name` when `keys` are pressed in `mode`
+#[op]
+pub async fn op_electrum_bind_key(state: Rc<RefCell<OpState>>, generation: u64, keys: String, name: String, mode: Option<String>, forward: Option<bool>) -> Result<(), AnyError> {
+  let binding = RuntimeKeybinding { keys, name, mode, forward: forward.unwrap_or(false) };
+  request(&state, |reply| CompositorMessage::BindKey { generation, binding, reply }).await?.map_err(|err| anyhow!(err))
+}
+
+/// Sends a `PointerBinding` event named `name` when `button` is used in `context`
+#[op]
+pub async fn op_electrum_bind_pointer(state: Rc<RefCell<OpState>>, generation: u64, context: PointerContext, button: String, name: String) -> Result<(), AnyError> {
+  let binding = RuntimePointerBinding { button, context, name };
+  request(&state, |reply| CompositorMessage::BindPointer { generation, binding, reply }).await?.map_err(|err| anyhow!(err))
+}
+
+#[op]
+pub async fn op_electrum_add_window_rule(state: Rc<RefCell<OpState>>, generation: u64, rule: RuntimeRule) -> Result<(), AnyError> {
+  request(&state, |reply| CompositorMessage::AddWindowRule { generation, rule, reply }).await?.map_err(|err| anyhow!(err))
+}
+
+/// Overrides the layer, exclusive zone, focus or privacy of layer surfaces of a namespace
+#[op]
+pub async fn op_electrum_add_layer_rule(state: Rc<RefCell<OpState>>, generation: u64, rule: RuntimeLayerRule) -> Result<(), AnyError> {
+  request(&state, |reply| CompositorMessage::AddLayerRule { generation, rule, reply }).await?.map_err(|err| anyhow!(err))
+}
+
+/// Limits the events the runtime gets to `events`, `Shutdown` and `Reload` always come through
+#[op]
+pub async fn op_electrum_subscribe(state: Rc<RefCell<OpState>>, generation: u64, events: Vec<String>) -> Result<(), AnyError> {
+  request(&state, |reply| CompositorMessage::Subscribe { generation, events, reply }).await?.map_err(|err| anyhow!(err))
+}
+
+/// Replaces the current generation with `generation` and tells what changed
+#[op]
+pub async fn op_electrum_commit_generation(state: Rc<RefCell<OpState>>, generation: u64) -> Result<RegistryDiff, AnyError> {
+  let diff = request(&state, |reply| CompositorMessage::CommitGeneration { generation, reply }).await?.map_err(|err| anyhow!(err))?;
+  state.borrow().borrow::<CommittedGeneration>().0.set(generation);
+  Ok(diff)
 }
 
 pub struct MainExtensionInstance {
   pub extension: Extension,
-  pub event_sender: UnboundedSender<Event>
+  pub event_sender: EventSender
 }
 
-pub fn main_extension() -> MainExtensionInstance {
-  let (sender, reciever) = unbounded();
+pub fn main_extension(compositor_sender: RequestSender) -> MainExtensionInstance {
+  let (sender, reciever) = event_queue();
   let reciever = Rc::new(RefCell::new(reciever));
   let extension = Extension::builder()
     .state(move |state| {
         state.put(reciever.clone());
+        state.put(compositor_sender.clone());
+        state.put(CommittedGeneration::default());
         Ok(())
       })
-      .ops(vec![op_electrum_poll_events::decl()])
+      .ops(vec![
+        op_electrum_poll_events::decl(),
+        op_electrum_notification_closed::decl(),
+        op_electrum_notification_action::decl(),
+        op_electrum_client_diagnostics::decl(),
+        op_electrum_app_info::decl(),
+        op_electrum_socket_name::decl(),
+        op_electrum_spawn_sandboxed::decl(),
+        op_electrum_windows::decl(),
+        op_electrum_workspaces::decl(),
+        op_electrum_switch_workspace::decl(),
+        op_electrum_workspace_back_and_forth::decl(),
+        op_electrum_dump_tree::decl(),
+        op_electrum_gpu_memory::decl(),
+        op_electrum_set_latency_tracking::decl(),
+        op_electrum_reset_latency::decl(),
+        op_electrum_latency::decl(),
+        op_electrum_begin_generation::decl(),
+        op_electrum_bind_key::decl(),
+        op_electrum_set_keybinding_mode::decl(),
+        op_electrum_bind_pointer::decl(),
+        op_electrum_add_window_rule::decl(),
+        op_electrum_add_layer_rule::decl(),
+        op_electrum_subscribe::decl(),
+        op_electrum_commit_generation::decl(),
+        op_electrum_log::decl(),
+        op_electrum_set_log_level::decl(),
+        op_electrum_shutdown_ready::decl(),
+        op_electrum_set_window_urgent::decl(),
+        op_electrum_read_selection::decl(),
+        op_electrum_set_transfer_filter::decl(),
+        op_electrum_decide_transfer::decl(),
+        op_electrum_decide::decl(),
+        op_electrum_set_frame_policy::decl(),
+        op_electrum_throttle_window::decl(),
+        op_electrum_set_game_mode::decl(),
+        op_electrum_set_fake_fullscreen::decl(),
+        op_electrum_set_pip::decl(),
+        op_electrum_set_window_private::decl(),
+        op_electrum_set_window_shaded::decl(),
+        op_electrum_set_window_magnified::decl(),
+        op_electrum_focus_direction::decl(),
+        op_electrum_cycle_windows::decl(),
+        op_electrum_plugin_call::decl(),
+        op_electrum_outputs::decl(),
+        op_electrum_start_drag::decl(),
+        op_electrum_warp_pointer::decl(),
+        op_electrum_set_focus_follows_warp::decl(),
+        op_electrum_reset_idle::decl(),
+        op_electrum_set_idle_inhibited::decl(),
+        op_electrum_set_icc_profile::decl(),
+        op_electrum_set_output_filter::decl(),
+        op_electrum_configure_outputs::decl(),
+        op_electrum_reserve_area::decl(),
+        op_electrum_set_drop_zones::decl(),
+        op_electrum_set_keyboard_layouts::decl(),
+        op_electrum_switch_keyboard_layout::decl(),
+        op_electrum_set_accessibility::decl(),
+        op_electrum_set_dwell_click::decl(),
+        op_electrum_set_cursor_scale::decl(),
+        op_electrum_set_reduce_motion::decl(),
+        op_electrum_set_low_power::decl(),
+        op_electrum_backlights::decl(),
+        op_electrum_set_brightness::decl(),
+        op_electrum_adjust_brightness::decl(),
+        op_electrum_set_auto_hide::decl(),
+        op_electrum_set_switcher_style::decl(),
+        op_electrum_set_overlay::decl(),
+        op_electrum_set_background::decl(),
+      ])
       .build();
     
       MainExtensionInstance {
@@ -1,3 +1,4 @@
+use super::bridge::{EventSender, RequestSender};
 use super::extension::main_extension;
 use super::module::TypescriptModuleLoader;
 use deno_core::error::AnyError;
@@ -7,7 +8,6 @@ use deno_runtime::deno_web::BlobStore;
 use deno_runtime::permissions::Permissions;
 use deno_runtime::worker::{MainWorker, WorkerOptions};
 use deno_runtime::BootstrapOptions;
-use futures::channel::mpsc::UnboundedSender;
 use std::{rc::Rc, sync::Arc};
 
 // https://github.com/denoland/deno/blob/main/runtime/examples/hello_runtime.rs
@@ -62,12 +62,19 @@ fn options(extensions: Vec<Extension>) -> WorkerOptions {
 
 pub struct MainWorkerInstance {
     pub worker: MainWorker,
-    pub event_sender: UnboundedSender<super::extension::Event>
+    pub event_sender: EventSender
 }
 
-pub fn new(main_module_path: ModuleSpecifier) -> MainWorkerInstance {
-    let extension_instance = main_extension();
-    let worker = MainWorker::bootstrap_from_options(main_module_path, Permissions::allow_all(), options(vec![extension_instance.extension]));
+pub fn new(
+    main_module_path: ModuleSpecifier,
+    compositor_sender: RequestSender,
+    plugin_extensions: Vec<Extension>,
+    permissions: Permissions,
+) -> MainWorkerInstance {
+    let extension_instance = main_extension(compositor_sender);
+    let mut extensions = vec![extension_instance.extension];
+    extensions.extend(plugin_extensions);
+    let worker = MainWorker::bootstrap_from_options(main_module_path, permissions, options(extensions));
 
     MainWorkerInstance {
         worker,
@@ -1,14 +1,42 @@
+use std::{collections::HashMap, path::PathBuf};
+
 use smithay::{
     desktop::Window,
     reexports::wayland_protocols::xdg::shell::server::xdg_toplevel::ResizeEdge,
-    wayland::{
-        output::Output,
-        seat::{PointerGrabStartData, Seat},
-        Serial,
-    },
+    wayland::{output::Output, seat::Seat, Serial},
 };
 
-use crate::state::State;
+use futures::channel::oneshot;
+
+use super::registry::{
+    RegistryDiff, RuntimeKeybinding, RuntimeLayerRule, RuntimePointerBinding, RuntimeRule,
+};
+use crate::{
+    appinfo::AppInfo,
+    backend::renderer::{
+        background::Background, filter::OutputFilter, overlay::Overlay, textures::GpuMemory,
+    },
+    backlight::{Backlight, BacklightKind},
+    config::OutputConfig,
+    input::warp::WarpTarget,
+    notifications::Notification,
+    power::PowerState,
+    shell::{
+        cycle::Cycle,
+        decisions::Decision,
+        direction::Direction,
+        frames::FramePolicy,
+        layout::{zones::DropZone, Struts},
+        pip::Corner,
+        switcher::SwitcherStyle,
+        window::WindowInfo,
+        workspace::WorkspacesInfo,
+    },
+    state::{
+        diagnostics::DiagnosticsReport, latency::LatencyReport, output::OutputInfo,
+        selection::SelectionKind, tree::Tree, State,
+    },
+};
 
 // Messages from the runtime to the compositor
 pub enum RuntimeMessage {
@@ -24,15 +52,351 @@ pub enum RuntimeMessage {
         window: Window,
         seat: Seat<State>,
         serial: Serial,
-        start_data: PointerGrabStartData,
         edges: ResizeEdge,
     },
     UnfullscreenRequest {
         window: Window,
     },
+    Notification(Notification),
+    NotificationClose(u32),
+    Shutdown,
+    WindowUrgency {
+        id: usize,
+        urgent: bool,
+    },
+    OutputProfile {
+        name: Option<String>,
+    },
+    /// A drag the runtime started was dropped, with the action the client picked, or cancelled
+    DragEnded {
+        dropped: bool,
+        action: Option<String>,
+    },
+    /// Something about the connected outputs changed, with all of them
+    OutputsChanged {
+        outputs: Vec<OutputInfo>,
+    },
+    /// Idling reached `dim`, `off` or `lock`, or input brought outputs back to `active`
+    IdleStage {
+        stage: String,
+    },
+    ScaleChanged {
+        output: String,
+        scale: f64,
+    },
+    SelectionChanged {
+        kind: SelectionKind,
+        mime_types: Vec<String>,
+    },
+    WorkspaceRequested {
+        index: u32,
+    },
+    /// A workspace was shown, created or removed
+    WorkspacesChanged {
+        active: String,
+        workspaces: Vec<String>,
+    },
+    WindowAssigned {
+        id: usize,
+        workspace: u32,
+    },
+    KeyboardLayout {
+        index: usize,
+        name: String,
+    },
+    PanelVisibility {
+        namespace: String,
+        output: String,
+        visible: bool,
+    },
+    /// The focused client of app `target` would be offered a selection owned by app `source`
+    TransferRequest {
+        request: u64,
+        kind: SelectionKind,
+        source: Option<String>,
+        target: Option<String>,
+        mime_types: Vec<String>,
+    },
+    /// Handling a request of `client` panicked, it was disconnected
+    ClientPanicked {
+        client: String,
+        pid: Option<u32>,
+        executable: Option<PathBuf>,
+        message: String,
+    },
+    /// A key combination bound by the runtime in `generation` was pressed
+    Keybinding {
+        name: String,
+        generation: u64,
+    },
+    /// A pointer binding of the runtime was used in `context`, on `window` unless on the desktop
+    PointerBinding {
+        name: String,
+        generation: u64,
+        context: String,
+        window: Option<usize>,
+    },
+    /// A `mode` action, Escape or the runtime switched the binding mode
+    KeybindingMode {
+        mode: String,
+    },
+    /// Asked over IPC to register everything again
+    Reload,
+    /// Low power mode turned on or off, or what the system reports changed
+    PowerChanged(PowerState),
+    /// Something else than the compositor changed the brightness of a backlight
+    BrightnessChanged(Backlight),
+    /// Sent by a native plugin through its `PluginEvents`
+    Plugin {
+        plugin: &'static str,
+        name: String,
+        data: serde_json::Value,
+    },
 }
 
 // Messages from the compositor to the runtime
 pub enum CompositorMessage {
     Ping,
+    NotificationClosed {
+        id: u32,
+        reason: u32,
+    },
+    NotificationAction {
+        id: u32,
+        action: String,
+    },
+    ClientDiagnostics {
+        reply: oneshot::Sender<DiagnosticsReport>,
+    },
+    SocketName {
+        reply: oneshot::Sender<String>,
+    },
+    /// Runs a command as a client that never sees privileged globals, replies with its pid
+    SpawnSandboxed {
+        command: String,
+        reply: oneshot::Sender<Result<u32, String>>,
+    },
+    Windows {
+        reply: oneshot::Sender<Vec<WindowInfo>>,
+    },
+    Workspaces {
+        reply: oneshot::Sender<WorkspacesInfo>,
+    },
+    /// Shows the workspace called `name`, created if there is none
+    SwitchWorkspace {
+        name: String,
+    },
+    WorkspaceBackAndForth,
+    Tree {
+        reply: oneshot::Sender<Tree>,
+    },
+    AppInfo {
+        app_id: String,
+        reply: oneshot::Sender<AppInfo>,
+    },
+    GpuMemory {
+        reply: oneshot::Sender<GpuMemory>,
+    },
+    Latency {
+        reply: oneshot::Sender<LatencyReport>,
+    },
+    SetLatencyTracking(bool),
+    ResetLatency,
+    SetLogLevel {
+        level: slog::Level,
+        module: Option<String>,
+    },
+    ShutdownReady,
+    SetWindowUrgent {
+        id: usize,
+        urgent: bool,
+    },
+    SetFramePolicy(FramePolicy),
+    SetKeyAccessibility {
+        sticky_keys: bool,
+        slow_keys: Option<u64>,
+        bounce_keys: Option<u64>,
+    },
+    SetDwellClick(Option<u64>),
+    SetSwitcherStyle(SwitcherStyle),
+    SetOverlay {
+        output: String,
+        name: String,
+        overlay: Option<Overlay>,
+    },
+    SetBackground {
+        workspace: u32,
+        output: Option<String>,
+        background: Option<Background>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    SetCursorScale(f64),
+    SetReduceMotion(bool),
+    SetLowPower(Option<bool>),
+    SetBrightness {
+        kind: BacklightKind,
+        percent: f64,
+        relative: bool,
+    },
+    Backlights {
+        reply: oneshot::Sender<Vec<Backlight>>,
+    },
+    SetAutoHide(Vec<String>),
+    SetTransferFilter(bool),
+    DecideTransfer {
+        request: u64,
+        allow: bool,
+    },
+    SetKeyboardLayouts {
+        layouts: Vec<String>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    SwitchKeyboardLayout {
+        index: Option<usize>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    SetIccProfile {
+        output: String,
+        path: Option<String>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    SetStruts {
+        output: String,
+        struts: Struts,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    SetDropZones {
+        output: String,
+        zones: Vec<DropZone>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    SetOutputFilter {
+        output: String,
+        filter: Option<OutputFilter>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    BeginGeneration {
+        reply: oneshot::Sender<u64>,
+    },
+    BindKey {
+        generation: u64,
+        binding: RuntimeKeybinding,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    BindPointer {
+        generation: u64,
+        binding: RuntimePointerBinding,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    AddWindowRule {
+        generation: u64,
+        rule: RuntimeRule,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    AddLayerRule {
+        generation: u64,
+        rule: RuntimeLayerRule,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    Subscribe {
+        generation: u64,
+        events: Vec<String>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    CommitGeneration {
+        generation: u64,
+        reply: oneshot::Sender<Result<RegistryDiff, String>>,
+    },
+    /// Applies all of `outputs` or nothing, only checks them with `test`
+    ConfigureOutputs {
+        outputs: Vec<OutputConfig>,
+        test: bool,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    ThrottleWindow {
+        id: usize,
+        fps: Option<u32>,
+    },
+    SetGameMode {
+        id: usize,
+        enabled: Option<bool>,
+    },
+    SetWindowPrivate {
+        id: usize,
+        private: bool,
+    },
+    SetFakeFullscreen {
+        id: usize,
+        enabled: bool,
+    },
+    /// Shrinks a window into a corner of its output, `None` puts it back
+    SetPip {
+        id: usize,
+        corner: Option<Corner>,
+    },
+    SetWindowShaded {
+        id: usize,
+        shaded: bool,
+    },
+    /// Scales a window up to fill its output, or goes back to the desktop
+    SetWindowMagnified {
+        id: usize,
+        magnified: bool,
+    },
+    /// Focuses the closest window in `direction` for the last active seat, replies with its id
+    FocusDirection {
+        direction: Direction,
+        reply: oneshot::Sender<Option<usize>>,
+    },
+    CycleWindows {
+        cycle: Cycle,
+        reply: oneshot::Sender<Option<usize>>,
+    },
+    /// Calls an op a shared object plugin registered, with arguments and result as JSON
+    PluginCall {
+        plugin: String,
+        op: String,
+        args: serde_json::Value,
+        reply: oneshot::Sender<Result<serde_json::Value, String>>,
+    },
+    /// Turns the held pointer button of the last active seat into a drag offering the data by
+    /// mime type
+    StartDrag {
+        offers: HashMap<String, String>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    /// Every connected output, also those that are turned off
+    Outputs {
+        reply: oneshot::Sender<Vec<OutputInfo>>,
+    },
+    /// Moves the pointer of the last active seat to the center of an output or window
+    WarpPointer {
+        target: WarpTarget,
+        animate: bool,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    SetFocusFollowsWarp {
+        enabled: bool,
+    },
+    /// Starts the idle time over as if there was input, outputs light up again
+    ResetIdle,
+    /// Switches to the keybindings of `mode`
+    SetKeybindingMode {
+        mode: String,
+    },
+    /// Keeps outputs from dimming or turning off, e.g. while a video plays
+    SetIdleInhibited {
+        inhibited: bool,
+    },
+    /// The answer to a request forwarded with correlation id `request`
+    Decide {
+        request: u64,
+        decision: Decision,
+    },
+    ReadSelection {
+        kind: SelectionKind,
+        mime_type: String,
+        limit: usize,
+        reply: oneshot::Sender<Result<Vec<u8>, String>>,
+    },
 }
@@ -1,101 +1,398 @@
 use std::env::{self, current_dir};
 use std::path::PathBuf;
 
+use crate::shell::window::WindowExt;
+use crate::watchdog::{Watchdog, RUNTIME_EVENT};
 use crate::LoopData;
+use anyhow::{anyhow, Context};
 use calloop::channel::{channel, Channel, Event, Sender};
 use calloop::futures::{Executor, Scheduler};
 use calloop::EventLoop;
 use deno_core::error::AnyError;
 use deno_core::ModuleSpecifier;
+use deno_runtime::permissions::Permissions;
 use deno_runtime::worker::MainWorker;
 
+pub mod bridge;
 mod main;
 pub mod messages;
 mod module;
 mod extension;
+pub mod plugin;
+pub mod registry;
+mod safe_mode;
 
-use futures::channel::mpsc::UnboundedSender;
+use bridge::{EventSender, RequestSender, RuntimeBridge};
+use futures::future::{abortable, Aborted};
 use messages::{CompositorMessage, RuntimeMessage};
+use plugin::ElectrumExtension;
+use registry::Registry;
 
 pub struct Runtime {
-    main_worker: MainWorker,
-    main_module: ModuleSpecifier,
+    main: Option<MainModule>,
+    /// What the script registered, the compositor state gets a handle to it
+    pub registry: Registry,
+    /// The worker events go to, the compositor state gets a handle to stop it
+    pub bridge: RuntimeBridge,
     runtime_channel: Channel<RuntimeMessage>,
-    compositor_sender: Sender<CompositorMessage>,
-    event_sender: UnboundedSender<extension::Event>,
+    compositor_sender: RequestSender,
 
     pub runtime_sender: Sender<RuntimeMessage>,
 }
 
+struct MainModule {
+    worker: MainWorker,
+    specifier: ModuleSpecifier,
+    event_sender: EventSender,
+}
+
 impl Runtime {
-    pub fn new(compositor_sender: Sender<CompositorMessage>) -> Self {
+    /// Sets up the worker for the main module, with the ops of `extensions` next to those of the
+    /// compositor
+    pub fn new(
+        compositor_sender: RequestSender,
+        extensions: &mut [Box<dyn ElectrumExtension>],
+    ) -> Self {
         let (runtime_sender, runtime_channel) = channel::<RuntimeMessage>();
-        let mut config_path: PathBuf;
-        if cfg!(feature = "devel") {
-            config_path = match env::var("TS_PREFIX") {
-                Ok(x) => PathBuf::from(x),
-                Err(_) => current_dir().unwrap(),
-            };
-            config_path.push("src");
-            config_path.push("ts");
-            config_path.push("main.ts");
-        } else {
-            let xdg_dirs = xdg::BaseDirectories::with_prefix("electrum").unwrap();
-            config_path = xdg_dirs.get_config_file("main.ts");
-        }
 
-        let main_module = deno_core::resolve_path(config_path.to_str().unwrap())
-            .expect("failed to resolve main module");
-        let main_worker_instance = main::new(main_module.clone());
+        let main = match main_module_path() {
+            Some(path) => {
+                slog_scope::info!("Loading main module {}", path.display());
+                let specifier = deno_core::resolve_path(path.to_str().unwrap())
+                    .expect("failed to resolve main module");
+                for extension in extensions.iter() {
+                    slog_scope::info!("Adding the ops of plugin {}", extension.name());
+                }
+                let instance = main::new(
+                    specifier.clone(),
+                    compositor_sender.clone(),
+                    plugin_extensions(extensions),
+                    Permissions::allow_all(),
+                );
+                Some(MainModule {
+                    worker: instance.worker,
+                    specifier,
+                    event_sender: instance.event_sender,
+                })
+            }
+            None => {
+                slog_scope::info!("No main module found, using the declarative config only");
+                None
+            }
+        };
 
         Runtime {
-            main_worker: main_worker_instance.worker,
-            event_sender: main_worker_instance.event_sender,
-            main_module,
+            main,
+            registry: Registry::default(),
+            bridge: RuntimeBridge::default(),
             runtime_channel,
             runtime_sender,
             compositor_sender,
         }
     }
 
-    pub fn run_with_calloop(mut self, event_loop: &mut EventLoop<LoopData>) {
-        let (exec, sched): (
-            Executor<Result<(), AnyError>>,
-            Scheduler<Result<(), AnyError>>,
-        ) = calloop::futures::executor().unwrap();
+    /// Whether a script drives the window management
+    pub fn has_main_module(&self) -> bool {
+        self.main.is_some()
+    }
+
+    /// Registers both ends of the bridge with the event loop and schedules the main module
+    pub fn run_with_calloop(
+        self,
+        event_loop: &mut EventLoop<LoopData>,
+        watchdog: &Watchdog,
+    ) -> anyhow::Result<()> {
+        let compositor_sender = self.compositor_sender.clone();
+        let bridge = self.bridge.clone();
+        let events = bridge.clone();
+        let registry = self.registry.clone();
 
         event_loop
             .handle()
-            .insert_source(exec, |evt, _metadata, _shared| {
-                evt.unwrap();
+            .insert_source(self.runtime_channel, move |message, _metadata, data| {
+                data.state.common.watchdog.event("events for the runtime");
+                // Events the script did not subscribe to are dropped, requests get the default
+                let wanted = |name: &str| events.is_attached() && registry.subscribed(name);
+                let forward = |event: extension::Event| {
+                    if registry.subscribed(event.name()) {
+                        events.forward(event);
+                    }
+                };
+                // Only requests the script asked for by name wait for it
+                let deciding =
+                    |name: &str| events.is_attached() && registry.explicitly_subscribed(name);
+                match message {
+                    Event::Msg(RuntimeMessage::Ping) => {
+                        slog_scope::info!("The runtime got a ping!");
+                        if let Err(err) = compositor_sender.send(CompositorMessage::Ping) {
+                            slog_scope::warn!("Failed to answer a ping: {}", err);
+                        }
+                    }
+                    Event::Msg(RuntimeMessage::Notification(notification)) => {
+                        forward(extension::Event::Notification(notification));
+                    }
+                    Event::Msg(RuntimeMessage::NotificationClose(id)) => {
+                        forward(extension::Event::NotificationClose { id });
+                    }
+                    Event::Msg(RuntimeMessage::WindowUrgency { id, urgent }) => {
+                        forward(extension::Event::WindowUrgency { id, urgent });
+                    }
+                    Event::Msg(RuntimeMessage::OutputProfile { name }) => {
+                        forward(extension::Event::OutputProfile { name });
+                    }
+                    Event::Msg(RuntimeMessage::DragEnded { dropped, action }) => {
+                        forward(extension::Event::DragEnded { dropped, action });
+                    }
+                    Event::Msg(RuntimeMessage::OutputsChanged { outputs }) => {
+                        forward(extension::Event::OutputsChanged { outputs });
+                    }
+                    Event::Msg(RuntimeMessage::IdleStage { stage }) => {
+                        forward(extension::Event::IdleStage { stage });
+                    }
+                    Event::Msg(RuntimeMessage::ScaleChanged { output, scale }) => {
+                        forward(extension::Event::ScaleChanged { output, scale });
+                    }
+                    Event::Msg(RuntimeMessage::SelectionChanged { kind, mime_types }) => {
+                        forward(extension::Event::SelectionOwnerChanged {
+                            selection: kind,
+                            mime_types,
+                        });
+                    }
+                    Event::Msg(RuntimeMessage::WorkspaceRequested { index }) => {
+                        forward(extension::Event::WorkspaceRequested { index });
+                    }
+                    Event::Msg(RuntimeMessage::WorkspacesChanged { active, workspaces }) => {
+                        forward(extension::Event::WorkspacesChanged { active, workspaces });
+                    }
+                    Event::Msg(RuntimeMessage::WindowAssigned { id, workspace }) => {
+                        forward(extension::Event::WindowAssigned { id, workspace });
+                    }
+                    Event::Msg(RuntimeMessage::KeyboardLayout { index, name }) => {
+                        forward(extension::Event::KeyboardLayout { index, name });
+                    }
+                    Event::Msg(RuntimeMessage::PanelVisibility {
+                        namespace,
+                        output,
+                        visible,
+                    }) => {
+                        forward(extension::Event::PanelVisibility {
+                            namespace,
+                            output,
+                            visible,
+                        });
+                    }
+                    Event::Msg(RuntimeMessage::TransferRequest {
+                        request,
+                        kind,
+                        source,
+                        target,
+                        mime_types,
+                    }) => {
+                        // Answered right away if nobody polls for it
+                        let queued = wanted("TransferRequest")
+                            && events.deliver(extension::Event::TransferRequest {
+                                request,
+                                selection: kind,
+                                source,
+                                target,
+                                mime_types,
+                            });
+                        if !queued {
+                            data.state.common.transfers.decide(request, None);
+                        }
+                    }
+                    Event::Msg(RuntimeMessage::ClientPanicked {
+                        client,
+                        pid,
+                        executable,
+                        message,
+                    }) => {
+                        forward(extension::Event::ClientPanicked {
+                            client,
+                            pid,
+                            executable,
+                            message,
+                        });
+                    }
+                    Event::Msg(RuntimeMessage::Keybinding { name, generation }) => {
+                        forward(extension::Event::Keybinding { name, generation });
+                    }
+                    Event::Msg(RuntimeMessage::PointerBinding {
+                        name,
+                        generation,
+                        context,
+                        window,
+                    }) => {
+                        forward(extension::Event::PointerBinding {
+                            name,
+                            generation,
+                            context,
+                            window,
+                        });
+                    }
+                    Event::Msg(RuntimeMessage::KeybindingMode { mode }) => {
+                        forward(extension::Event::KeybindingMode { mode });
+                    }
+                    Event::Msg(RuntimeMessage::Reload) => {
+                        forward(extension::Event::Reload);
+                    }
+                    Event::Msg(RuntimeMessage::PowerChanged(power)) => {
+                        forward(extension::Event::PowerChanged { power });
+                    }
+                    Event::Msg(RuntimeMessage::BrightnessChanged(backlight)) => {
+                        forward(extension::Event::BrightnessChanged { backlight });
+                    }
+                    Event::Msg(RuntimeMessage::Plugin { plugin, name, data }) => {
+                        forward(extension::Event::Plugin { plugin, name, data });
+                    }
+                    // Nobody is left to acknowledge it once the worker stopped
+                    Event::Msg(RuntimeMessage::Shutdown) => {
+                        if !events.deliver(extension::Event::Shutdown) {
+                            data.state.common.runtime_ready_for_shutdown();
+                        }
+                    }
+                    // Window management requests wait for the script to decide, if it subscribed
+                    // to them. The others get the configured policy while a script runs.
+                    Event::Msg(request) => {
+                        let id = data.state.common.decisions.next_id();
+                        let event =
+                            decision_event(id, &request).filter(|event| deciding(event.name()));
+                        if let Some(event) = event {
+                            if events.deliver(event) {
+                                data.state.common.defer_request(id, request);
+                                return;
+                            }
+                        }
+                        if events.is_attached() {
+                            data.state.common.undecided_request(request);
+                        } else {
+                            data.state
+                                .common
+                                .shell
+                                .active_workspace_mut()
+                                .default_request(request);
+                        }
+                    }
+                    // The state owns the other end, this only happens during teardown
+                    Event::Closed => {}
+                }
             })
-            .unwrap();
+            .map_err(|_| anyhow!("Failed to register the runtime event channel"))?;
 
-        let compositor_sender = self.compositor_sender.clone();
+        let mut main = match self.main {
+            Some(main) => main,
+            None => return Ok(()),
+        };
+
+        let (exec, sched): (
+            Executor<Result<Result<(), AnyError>, Aborted>>,
+            Scheduler<Result<Result<(), AnyError>, Aborted>>,
+        ) = calloop::futures::executor().context("Failed to create the runtime executor")?;
 
+        let stopped = bridge.clone();
         event_loop
             .handle()
-            .insert_source(
-                self.runtime_channel,
-                move |message, _metadata, _shared| match message {
-                    Event::Msg(RuntimeMessage::Ping) => {
-                        slog_scope::info!("The runtime got a ping!");
-                        compositor_sender.send(CompositorMessage::Ping).unwrap();
+            .insert_source(exec, move |evt, _metadata, data| {
+                // Nobody is left to answer transfer requests
+                data.state.common.transfers.set_enabled(false);
+                match evt {
+                    // Also how a runtime terminated by the watchdog ends up
+                    Ok(Err(err)) => {
+                        slog_scope::error!("The runtime stopped: {:?}", err);
+                        data.state.common.crash.script_error(format!("{:?}", err));
+                    }
+                    Ok(Ok(())) => slog_scope::info!("The runtime finished"),
+                    Err(Aborted) => {
+                        slog_scope::info!("The runtime worker was cancelled");
+                        return;
                     }
-                    Event::Msg(_) => todo!(),
-                    Event::Closed => todo!(),
-                },
-            )
-            .unwrap();
+                }
+                stopped.detach();
+            })
+            .map_err(|_| anyhow!("Failed to register the runtime executor"))?;
 
+        watchdog.set_runtime(main.worker.js_runtime.v8_isolate().thread_safe_handle());
+        let (future, abort) = abortable(watchdog.watch_future(RUNTIME_EVENT, async move {
+            main.worker.execute_main_module(&main.specifier).await?;
+            main.worker.run_event_loop(false).await
+        }));
         sched
-            .schedule(async move {
-                self.main_worker
-                    .execute_main_module(&self.main_module)
-                    .await
-                    .unwrap();
-                self.main_worker.run_event_loop(false).await
-            })
-            .unwrap();
+            .schedule(future)
+            .map_err(|_| anyhow!("The runtime executor is gone"))?;
+        bridge.attach(main.event_sender, abort);
+        Ok(())
     }
 }
+
+/// The event asking the runtime to decide on `request`, `None` for requests that are only
+/// informational
+fn decision_event(id: u64, request: &RuntimeMessage) -> Option<extension::Event> {
+    let event = match request {
+        RuntimeMessage::MaximizeRequest { window, output } => extension::Event::MaximizeRequest {
+            request: id,
+            window: window.id(),
+            output: output.name(),
+        },
+        RuntimeMessage::UnmaximizeRequest { window } => extension::Event::UnmaximizeRequest {
+            request: id,
+            window: window.id(),
+        },
+        RuntimeMessage::ResizeRequest { window, edges, .. } => extension::Event::ResizeRequest {
+            request: id,
+            window: window.id(),
+            edges: (*edges).into(),
+        },
+        _ => return None,
+    };
+    Some(event)
+}
+
+/// Syntax and import check of the main module: it and everything it imports are loaded
+/// without evaluating them, so syntax errors and unresolvable imports show up without starting
+/// the compositor. TypeScript is only transpiled, its types are not checked.
+pub fn check_main_module_syntax(
+    extensions: &mut [Box<dyn ElectrumExtension>],
+) -> Result<Option<PathBuf>, AnyError> {
+    let path = match main_module_path() {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+
+    let specifier = deno_core::resolve_path(&path.to_string_lossy())?;
+    // Nothing is evaluated, so nobody ever sends on this and it needs no permissions
+    let (compositor_sender, _channel) = bridge::request_channel();
+    let mut instance = main::new(
+        specifier.clone(),
+        compositor_sender,
+        plugin_extensions(extensions),
+        Permissions::default(),
+    );
+    futures::executor::block_on(instance.worker.preload_main_module(&specifier))?;
+    Ok(Some(path))
+}
+
+fn plugin_extensions(extensions: &mut [Box<dyn ElectrumExtension>]) -> Vec<deno_core::Extension> {
+    extensions
+        .iter_mut()
+        .map(|extension| extension.extension())
+        .collect()
+}
+
+/// `main.ts` or `main.js` in the config directory, `src/ts/main.ts` for devel builds
+fn main_module_path() -> Option<PathBuf> {
+    if cfg!(feature = "devel") {
+        let mut path = match env::var("TS_PREFIX") {
+            Ok(x) => PathBuf::from(x),
+            Err(_) => current_dir().unwrap(),
+        };
+        path.push("src");
+        path.push("ts");
+        path.push("main.ts");
+        return Some(path).filter(|path| path.exists());
+    }
+
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("electrum").ok()?;
+    xdg_dirs
+        .find_config_file("main.ts")
+        .or_else(|| xdg_dirs.find_config_file("main.js"))
+}
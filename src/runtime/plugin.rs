@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use anyhow::anyhow;
+use calloop::channel::{channel, Event, Sender};
+use deno_core::Extension;
+use serde::Serialize;
+use smithay::reexports::calloop::EventLoop;
+
+use super::messages::RuntimeMessage;
+use crate::state::LoopData;
+
+/// A native plugin built into the compositor. It adds ops, and JS to go with them, to the runtime
+/// the script runs in, and can send the script events of its own.
+///
+/// Plugins are passed to [`crate::main`] by a binary of their own depending on this crate. Their
+/// ops run on the runtime thread like those of the compositor, they should not block.
+pub trait ElectrumExtension {
+    /// Events of the plugin carry it as `plugin`, also used in logs
+    fn name(&self) -> &'static str;
+
+    /// The ops of the plugin, called before the main module loads and by `electrum check-config`.
+    /// Names starting with the plugin's keep them apart from `op_electrum_` ones.
+    fn extension(&mut self) -> Extension;
+
+    /// Called once the event loop is set up with what the plugin sends its events through, from
+    /// any thread
+    fn start(&mut self, _events: PluginEvents) {}
+}
+
+/// An event of a plugin on its way to the script
+struct PluginEvent {
+    plugin: &'static str,
+    name: String,
+    data: serde_json::Value,
+}
+
+/// Sends events of a plugin to the script, as `Plugin` events
+#[derive(Clone)]
+pub struct PluginEvents {
+    plugin: &'static str,
+    sender: Sender<PluginEvent>,
+}
+
+impl PluginEvents {
+    /// The same channel, with events sent as those of `plugin`
+    pub(crate) fn for_plugin(&self, plugin: &'static str) -> Self {
+        Self {
+            plugin,
+            sender: self.sender.clone(),
+        }
+    }
+
+    /// Queues the event `name` with `data`. Fails if `data` can't be turned into JSON, or once the
+    /// compositor stopped.
+    pub fn send(&self, name: &str, data: impl Serialize) -> anyhow::Result<()> {
+        let event = PluginEvent {
+            plugin: self.plugin,
+            name: name.to_string(),
+            data: serde_json::to_value(data)?,
+        };
+        self.sender
+            .send(event)
+            .map_err(|_| anyhow!("The compositor is no longer running"))
+    }
+}
+
+/// The channel plugin events come in through, before they go to the runtime like any other
+pub fn init_plugin_events(event_loop: &mut EventLoop<LoopData>) -> anyhow::Result<PluginEvents> {
+    let (sender, channel) = channel::<PluginEvent>();
+    event_loop
+        .handle()
+        .insert_source(channel, |event, _, data| {
+            let event = match event {
+                Event::Msg(event) => event,
+                Event::Closed => return,
+            };
+            let runtime_sender = &data.state.common.shell.active_workspace().runtime_sender;
+            let _ = runtime_sender.send(RuntimeMessage::Plugin {
+                plugin: event.plugin,
+                name: event.name,
+                data: event.data,
+            });
+        })
+        .map_err(|_| anyhow!("Failed to insert the plugin event channel"))?;
+    Ok(PluginEvents { plugin: "", sender })
+}
@@ -0,0 +1,286 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{cell::RefCell, path::PathBuf, rc::Rc};
+
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    input::{
+        click_raise::RaisePolicy,
+        keybindings::{parse_chord, parse_pointer_trigger},
+        pointer_bindings::PointerContext,
+    },
+    shell::layer_rules::LayerName,
+    state::CommonState,
+};
+
+/// Events that reach the runtime whatever it subscribed to
+const ALWAYS_DELIVERED: &[&str] = &["Shutdown", "Reload"];
+
+/// A key combination the runtime gets a `Keybinding` event for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuntimeKeybinding {
+    pub keys: String,
+    pub name: String,
+    /// Binding mode the keys are handled in, the default mode without one
+    pub mode: Option<String>,
+    /// The focused window gets the keys as well
+    #[serde(default)]
+    pub forward: bool,
+}
+
+/// A mouse button or scroll direction in a context the runtime gets a `PointerBinding` event for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuntimePointerBinding {
+    pub button: String,
+    pub context: PointerContext,
+    pub name: String,
+}
+
+/// Where new windows of an app or binary go, for as long as the generation that added it is
+/// active
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeRule {
+    pub app_id: Option<String>,
+    pub executable: Option<PathBuf>,
+    pub workspace: Option<u32>,
+    pub output: Option<String>,
+    /// Maximize the windows when they ask for fullscreen
+    #[serde(default)]
+    pub fake_fullscreen: bool,
+    /// Overrides `click_raise` of `[input]` for the windows
+    pub click_raise: Option<RaisePolicy>,
+}
+
+/// Overrides for layer surfaces of a namespace that are mapped while the generation that added it
+/// is active
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeLayerRule {
+    pub namespace: String,
+    pub layer: Option<LayerName>,
+    #[serde(default)]
+    pub ignore_exclusive_zone: bool,
+    #[serde(default)]
+    pub no_focus: bool,
+    #[serde(default)]
+    pub private: bool,
+    #[serde(default)]
+    pub blur: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Generation {
+    id: u64,
+    keybindings: Vec<RuntimeKeybinding>,
+    pointer_bindings: Vec<RuntimePointerBinding>,
+    rules: Vec<RuntimeRule>,
+    layer_rules: Vec<RuntimeLayerRule>,
+    /// Names of the events the runtime gets, empty for all of them
+    subscriptions: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Changes<T> {
+    pub added: Vec<T>,
+    pub removed: Vec<T>,
+}
+
+impl<T: Clone + PartialEq> Changes<T> {
+    fn between(old: &[T], new: &[T]) -> Self {
+        Changes {
+            added: new.iter().filter(|t| !old.contains(t)).cloned().collect(),
+            removed: old.iter().filter(|t| !new.contains(t)).cloned().collect(),
+        }
+    }
+}
+
+/// What committing a generation changed compared to the one it replaced
+#[derive(Debug, Serialize)]
+pub struct RegistryDiff {
+    pub generation: u64,
+    pub keybindings: Changes<RuntimeKeybinding>,
+    pub pointer_bindings: Changes<RuntimePointerBinding>,
+    pub rules: Changes<RuntimeRule>,
+    pub layer_rules: Changes<RuntimeLayerRule>,
+    pub subscriptions: Changes<String>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    active: Generation,
+    pending: Option<Generation>,
+    last_id: u64,
+}
+
+/// Keybindings, window rules and event subscriptions of the runtime, by generation. A script
+/// that reloads its config registers everything again into a new generation while the previous
+/// one stays in effect, and committing it replaces the previous one at once. Shared with the
+/// runtime end of the event channel, which filters by the subscriptions.
+#[derive(Debug, Clone, Default)]
+pub struct Registry(Rc<RefCell<Inner>>);
+
+impl Registry {
+    /// Starts filling a new generation, dropping one that was never committed
+    pub fn begin(&self) -> u64 {
+        let mut inner = self.0.borrow_mut();
+        if let Some(pending) = &inner.pending {
+            slog_scope::info!("Dropping generation {}, it was never committed", pending.id);
+        }
+        inner.last_id += 1;
+        let id = inner.last_id;
+        inner.pending = Some(Generation {
+            id,
+            ..Default::default()
+        });
+        id
+    }
+
+    fn pending<T>(
+        &self,
+        generation: u64,
+        f: impl FnOnce(&mut Generation) -> T,
+    ) -> anyhow::Result<T> {
+        let mut inner = self.0.borrow_mut();
+        match inner.pending.as_mut() {
+            Some(pending) if pending.id == generation => Ok(f(pending)),
+            _ => Err(anyhow!("Generation {} is not being loaded", generation)),
+        }
+    }
+
+    /// Replaces the binding of the same keys in `generation`
+    pub fn bind_key(&self, generation: u64, binding: RuntimeKeybinding) -> anyhow::Result<()> {
+        parse_chord(&binding.keys)?;
+        self.pending(generation, |pending| {
+            pending
+                .keybindings
+                .retain(|b| b.keys != binding.keys || b.mode != binding.mode);
+            pending.keybindings.push(binding);
+        })
+    }
+
+    /// Replaces the binding of the same button in the same context in `generation`
+    pub fn bind_pointer(
+        &self,
+        generation: u64,
+        binding: RuntimePointerBinding,
+    ) -> anyhow::Result<()> {
+        parse_pointer_trigger(&binding.button)?;
+        self.pending(generation, |pending| {
+            pending
+                .pointer_bindings
+                .retain(|b| b.button != binding.button || b.context != binding.context);
+            pending.pointer_bindings.push(binding);
+        })
+    }
+
+    pub fn add_rule(&self, generation: u64, rule: RuntimeRule) -> anyhow::Result<()> {
+        if rule.app_id.is_none() && rule.executable.is_none() {
+            bail!("A rule needs an app id or an executable");
+        }
+        self.pending(generation, |pending| {
+            if !pending.rules.contains(&rule) {
+                pending.rules.push(rule);
+            }
+        })
+    }
+
+    /// Replaces the rule of the same namespace in `generation`
+    pub fn add_layer_rule(&self, generation: u64, rule: RuntimeLayerRule) -> anyhow::Result<()> {
+        self.pending(generation, |pending| {
+            pending
+                .layer_rules
+                .retain(|r| r.namespace != rule.namespace);
+            pending.layer_rules.push(rule);
+        })
+    }
+
+    pub fn subscribe(&self, generation: u64, events: Vec<String>) -> anyhow::Result<()> {
+        self.pending(generation, |pending| {
+            for event in events {
+                if !pending.subscriptions.contains(&event) {
+                    pending.subscriptions.push(event);
+                }
+            }
+        })
+    }
+
+    /// Forgets everything the runtime registered, once it was stopped. Events of the old
+    /// generations don't match the new empty one.
+    pub fn clear(&self) {
+        let mut inner = self.0.borrow_mut();
+        inner.last_id += 1;
+        inner.active = Generation {
+            id: inner.last_id,
+            ..Default::default()
+        };
+        inner.pending = None;
+    }
+
+    /// Id of the generation in effect, 0 before the first commit
+    pub fn generation(&self) -> u64 {
+        self.0.borrow().active.id
+    }
+
+    /// Whether the event named `event` goes to the runtime
+    pub fn subscribed(&self, event: &str) -> bool {
+        let inner = self.0.borrow();
+        inner.active.subscriptions.is_empty()
+            || ALWAYS_DELIVERED.contains(&event)
+            || inner.active.subscriptions.iter().any(|s| s == event)
+    }
+
+    /// Whether the script listed the event named `event` itself, a script without
+    /// subscriptions gets every event but is not asked to decide on requests
+    pub fn explicitly_subscribed(&self, event: &str) -> bool {
+        let inner = self.0.borrow();
+        inner.active.subscriptions.iter().any(|s| s == event)
+    }
+
+    fn commit(&self, generation: u64) -> anyhow::Result<(Generation, RegistryDiff)> {
+        let mut inner = self.0.borrow_mut();
+        let pending = match inner.pending.take() {
+            Some(pending) if pending.id == generation => pending,
+            other => {
+                inner.pending = other;
+                bail!("Generation {} is not being loaded", generation);
+            }
+        };
+        let previous = std::mem::replace(&mut inner.active, pending.clone());
+        let diff = RegistryDiff {
+            generation,
+            keybindings: Changes::between(&previous.keybindings, &pending.keybindings),
+            pointer_bindings: Changes::between(
+                &previous.pointer_bindings,
+                &pending.pointer_bindings,
+            ),
+            rules: Changes::between(&previous.rules, &pending.rules),
+            layer_rules: Changes::between(&previous.layer_rules, &pending.layer_rules),
+            subscriptions: Changes::between(&previous.subscriptions, &pending.subscriptions),
+        };
+        Ok((pending, diff))
+    }
+}
+
+impl CommonState {
+    /// Puts the keybindings and rules of `generation` in place of those of the previous one
+    pub fn commit_generation(&mut self, generation: u64) -> anyhow::Result<RegistryDiff> {
+        let (committed, diff) = self.runtime_registry.commit(generation)?;
+        self.keybindings.set_runtime(&committed.keybindings);
+        self.pointer_bindings
+            .set_runtime(&committed.pointer_bindings);
+        self.shell.rules.set_runtime(&committed.rules);
+        self.shell.layer_rules.set_runtime(&committed.layer_rules);
+        slog_scope::info!(
+            "Committed runtime generation {}: {} keybindings, {} pointer bindings, {} rules, {} layer rules",
+            generation,
+            committed.keybindings.len(),
+            committed.pointer_bindings.len(),
+            committed.rules.len(),
+            committed.layer_rules.len()
+        );
+        Ok(diff)
+    }
+}
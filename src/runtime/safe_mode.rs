@@ -0,0 +1,51 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use smithay::reexports::wayland_server::DisplayHandle;
+
+use crate::{
+    input::keybindings::Keybindings,
+    shell::{
+        layout::{set_struts, zones::set_drop_zones, Struts},
+        switcher::SwitcherStyle,
+    },
+    state::CommonState,
+};
+
+impl CommonState {
+    /// Stops the script and drops everything it registered or changed, so a broken config can't
+    /// lock the user out. Windows stay where they are, new requests get the built-in policy and
+    /// the default keybindings work again unless `electrum.toml` has its own.
+    ///
+    /// The script is terminated here whatever `watchdog.terminate_runtime` says. One stuck in a
+    /// synchronous loop also blocks the key press that asks for this, SIGUSR1 reaches the
+    /// watchdog thread instead, which stops it and comes back here.
+    pub fn enter_safe_mode(&mut self, dh: &DisplayHandle) {
+        slog_scope::warn!("Entering safe mode, the script is stopped");
+        self.watchdog.terminate_runtime();
+        self.runtime_bridge.detach();
+        self.runtime_registry.clear();
+
+        self.keybindings = Keybindings::new(&self.config, false);
+        self.pointer_bindings.set_runtime(&[]);
+        self.shell.rules.set_runtime(&[]);
+        self.shell.layer_rules.set_runtime(&[]);
+
+        // Policies the script set at runtime go back to what `electrum.toml` says
+        self.transfers.set_enabled(false);
+        self.overlays.clear();
+        self.set_switcher_style(SwitcherStyle::default());
+        let outputs = self.shell.outputs.clone();
+        let namespaces = self.config.auto_hide.namespaces.clone();
+        self.shell
+            .auto_hide
+            .set_namespaces(dh, &outputs, namespaces);
+        for output in &outputs {
+            set_struts(output, Struts::default());
+            set_drop_zones(output, Vec::new());
+        }
+        self.frame_policy = self.config.frame_policy;
+        self.focus_follows_warp = self.config.pointer_warp.focus;
+        self.set_idle_inhibited(false);
+        self.set_low_power(None);
+    }
+}
@@ -0,0 +1,182 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::HashMap,
+    env,
+    ffi::{OsStr, OsString},
+    fs,
+    os::unix::{fs::MetadataExt, io::FromRawFd, net::UnixListener},
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use anyhow::{ensure, Context};
+use zbus::blocking::Connection;
+
+/// Variables other session components need to find the compositor.
+const EXPORTED_VARIABLES: &[&str] = &[
+    "WAYLAND_DISPLAY",
+    "XDG_CURRENT_DESKTOP",
+    "XDG_SESSION_TYPE",
+    "XDG_SESSION_DESKTOP",
+    "ELECTRUM_SOCKET",
+];
+
+/// Sets the session variables for this process and exports them to the systemd user
+/// manager and the dbus activation environment, so portals, pipewire and dbus activated
+/// applications are able to connect.
+pub fn export_environment(socket: &OsStr) {
+    env::set_var("WAYLAND_DISPLAY", socket);
+    env::set_var("XDG_SESSION_TYPE", "wayland");
+    if env::var_os("XDG_CURRENT_DESKTOP").is_none() {
+        env::set_var("XDG_CURRENT_DESKTOP", "electrum");
+    }
+    if env::var_os("XDG_SESSION_DESKTOP").is_none() {
+        env::set_var("XDG_SESSION_DESKTOP", "electrum");
+    }
+
+    let variables = EXPORTED_VARIABLES
+        .iter()
+        .filter_map(|key| env::var(key).ok().map(|value| (*key, value)))
+        .collect::<Vec<_>>();
+
+    // Talking to the bus may block for a while, don't hold up the event loop
+    std::thread::spawn(move || {
+        if let Err(err) = update_environment(&variables) {
+            slog_scope::warn!("Failed to export session environment: {}", err);
+        }
+    });
+}
+
+/// First file descriptor passed by socket activation, see sd_listen_fds(3)
+const LISTEN_FDS_START: i32 = 3;
+
+/// The listening wayland socket passed by systemd socket activation and its name, if
+/// there is one in `XDG_RUNTIME_DIR` that belongs to us. The activation variables are
+/// cleared so children don't take them as their own.
+pub fn activated_socket() -> Option<(UnixListener, OsString)> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    for key in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(key);
+    }
+    if pid?.parse::<u32>().ok()? != std::process::id() || fds?.parse::<i32>().ok()? < 1 {
+        return None;
+    }
+
+    // Only the first socket is used, further ones are left alone
+    let listener = unsafe { UnixListener::from_raw_fd(LISTEN_FDS_START) };
+    let _ = nix::fcntl::fcntl(
+        LISTEN_FDS_START,
+        nix::fcntl::FcntlArg::F_SETFD(nix::fcntl::FdFlag::FD_CLOEXEC),
+    );
+    if let Err(err) = listener.set_nonblocking(true) {
+        slog_scope::warn!("Passed wayland socket is unusable: {}", err);
+        return None;
+    }
+    let path = match listener.local_addr() {
+        Ok(addr) => addr.as_pathname().map(Path::to_path_buf),
+        Err(_) => None,
+    };
+    let path = match path {
+        Some(path) => path,
+        None => {
+            slog_scope::warn!("Passed wayland socket has no path, ignoring it");
+            return None;
+        }
+    };
+    if let Err(err) = check_socket_path(&path) {
+        slog_scope::warn!("Ignoring passed wayland socket: {:#}", err);
+        return None;
+    }
+    path.file_name().map(|name| (listener, name.to_os_string()))
+}
+
+/// Clients only look for sockets in `XDG_RUNTIME_DIR`, and one owned by somebody else could be
+/// listened on by them too
+fn check_socket_path(path: &Path) -> anyhow::Result<()> {
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR").context("XDG_RUNTIME_DIR is not set")?;
+    let runtime_dir = fs::canonicalize(&runtime_dir)
+        .with_context(|| format!("Failed to resolve {:?}", runtime_dir))?;
+    let parent = path
+        .parent()
+        .and_then(|parent| fs::canonicalize(parent).ok());
+    ensure!(
+        parent.as_deref() == Some(runtime_dir.as_path()),
+        "{} is not in {}",
+        path.display(),
+        runtime_dir.display()
+    );
+
+    let metadata =
+        fs::symlink_metadata(path).with_context(|| format!("Failed to stat {}", path.display()))?;
+    let uid = nix::unistd::getuid().as_raw();
+    ensure!(
+        metadata.uid() == uid,
+        "{} belongs to uid {}, not {}",
+        path.display(),
+        metadata.uid(),
+        uid
+    );
+    Ok(())
+}
+
+/// Runs `command` through the shell, pointed at this compositor even if the
+/// environment was not exported. Returns the pid of the shell.
+pub fn spawn(command: &str, socket: &OsStr) -> Option<u32> {
+    let child = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(command)
+        .env("WAYLAND_DISPLAY", socket)
+        .stdin(Stdio::null())
+        .spawn();
+
+    match child {
+        Ok(mut child) => {
+            let pid = child.id();
+            slog_scope::info!("Spawned {:?} ({})", command, pid);
+            // Reap the child, so it doesn't linger as a zombie
+            std::thread::spawn(move || child.wait());
+            Some(pid)
+        }
+        Err(err) => {
+            slog_scope::warn!("Failed to spawn {:?}: {}", command, err);
+            None
+        }
+    }
+}
+
+fn update_environment(variables: &[(&str, String)]) -> anyhow::Result<()> {
+    let connection = Connection::session().with_context(|| "Failed to connect to session bus")?;
+
+    let assignments = variables
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>();
+    connection
+        .call_method(
+            Some("org.freedesktop.systemd1"),
+            "/org/freedesktop/systemd1",
+            Some("org.freedesktop.systemd1.Manager"),
+            "SetEnvironment",
+            &(assignments,),
+        )
+        .with_context(|| "Failed to update systemd user environment")?;
+
+    let activation = variables
+        .iter()
+        .map(|(key, value)| (*key, value.as_str()))
+        .collect::<HashMap<_, _>>();
+    connection
+        .call_method(
+            Some("org.freedesktop.DBus"),
+            "/org/freedesktop/DBus",
+            Some("org.freedesktop.DBus"),
+            "UpdateActivationEnvironment",
+            &(activation,),
+        )
+        .with_context(|| "Failed to update dbus activation environment")?;
+
+    slog_scope::info!("Exported session environment");
+    Ok(())
+}
@@ -0,0 +1,395 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::time::{Duration, Instant};
+
+use calloop::timer::{TimeoutAction, Timer};
+use serde::Deserialize;
+use smithay::{
+    desktop::{
+        layer_map_for_output,
+        space::{RenderZindex, SurfaceTree},
+        LayerSurface,
+    },
+    reexports::wayland_server::{protocol::wl_surface::WlSurface, DisplayHandle, Resource},
+    utils::{IsAlive, Logical, Point, Rectangle},
+    wayland::{
+        compositor::with_states,
+        output::Output,
+        shell::wlr_layer::{Anchor, Layer, LayerSurfaceCachedState},
+    },
+};
+
+use crate::{
+    backend::renderer::CustomElem,
+    runtime::messages::RuntimeMessage,
+    state::{output::OutputExt, CommonState, State},
+};
+
+/// Logical pixels from the screen edge at which a hidden panel comes back
+const REVEAL_DISTANCE: f64 = 2.0;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AutoHideConfig {
+    /// Namespaces of layer surfaces that slide away while the pointer is not over them
+    pub namespaces: Vec<String>,
+    /// Milliseconds the pointer has to be away before a panel hides
+    pub delay: u64,
+    /// Milliseconds panels take to slide in or out
+    pub duration: u64,
+}
+
+impl Default for AutoHideConfig {
+    fn default() -> Self {
+        Self {
+            namespaces: Vec::new(),
+            delay: 500,
+            duration: 150,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Visibility {
+    /// In the layer map, the pointer left it at the instant if set
+    Shown {
+        left: Option<Instant>,
+    },
+    /// Out of the layer map and sliding away since the instant
+    Hiding(Instant),
+    Hidden,
+    /// Sliding in since the instant, mapped again once it is there
+    Revealing(Instant),
+}
+
+struct Panel {
+    layer: LayerSurface,
+    output: Output,
+    edge: Edge,
+    /// Where it was when it was last shown, relative to the output
+    geometry: Rectangle<i32, Logical>,
+    visibility: Visibility,
+}
+
+/// A panel started to hide or to come back
+#[derive(Debug, Clone)]
+pub struct VisibilityChange {
+    pub namespace: String,
+    pub output: String,
+    pub visible: bool,
+}
+
+/// Layer surfaces that are taken out of their layer map while the pointer is not over them, and
+/// come back when it hits the edge they are anchored to
+#[derive(Default)]
+pub struct AutoHide {
+    config: AutoHideConfig,
+    panels: Vec<Panel>,
+    changes: Vec<VisibilityChange>,
+}
+
+impl AutoHide {
+    pub fn new(config: AutoHideConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Starts hiding `layer` if its namespace is listed and it is anchored to a single edge
+    pub fn layer_mapped(&mut self, layer: &LayerSurface, output: &Output) {
+        let namespace = layer.namespace();
+        if !self.config.namespaces.iter().any(|n| n == namespace) {
+            return;
+        }
+        if self.panels.iter().any(|panel| &panel.layer == layer) {
+            return;
+        }
+        let anchor = with_states(layer.wl_surface(), |states| {
+            states
+                .cached_state
+                .current::<LayerSurfaceCachedState>()
+                .anchor
+        });
+        let edge = match anchored_edge(anchor) {
+            Some(edge) => edge,
+            None => {
+                slog_scope::debug!("Not hiding {}, it is not anchored to one edge", namespace);
+                return;
+            }
+        };
+        self.panels.push(Panel {
+            layer: layer.clone(),
+            output: output.clone(),
+            edge,
+            geometry: Rectangle::default(),
+            visibility: Visibility::Shown {
+                left: Some(Instant::now()),
+            },
+        });
+    }
+
+    /// Replaces the namespaces that hide. Panels that don't anymore are mapped right away, mapped
+    /// layers that now do start hiding.
+    pub fn set_namespaces(
+        &mut self,
+        dh: &DisplayHandle,
+        outputs: &[Output],
+        namespaces: Vec<String>,
+    ) {
+        self.config.namespaces = namespaces;
+        let namespaces = &self.config.namespaces;
+        let changes = &mut self.changes;
+        self.panels.retain(|panel| {
+            if namespaces.iter().any(|n| n == panel.layer.namespace()) {
+                return true;
+            }
+            if !matches!(panel.visibility, Visibility::Shown { .. }) {
+                let _ = layer_map_for_output(&panel.output).map_layer(dh, &panel.layer);
+                changes.push(panel.change(true));
+            }
+            false
+        });
+
+        for output in outputs {
+            let layers = layer_map_for_output(output)
+                .layers()
+                .cloned()
+                .collect::<Vec<_>>();
+            for layer in layers {
+                self.layer_mapped(&layer, output);
+            }
+        }
+    }
+
+    /// Follows the pointer to `position` in global coordinates, over `focus`. Returns how long
+    /// until a panel it just left has to hide.
+    pub fn pointer_moved(
+        &mut self,
+        position: Point<f64, Logical>,
+        focus: Option<&WlSurface>,
+    ) -> Option<Duration> {
+        let now = Instant::now();
+        let mut due = None;
+        for panel in &mut self.panels {
+            let output_geometry = panel.output.geometry();
+            let relative = position - output_geometry.loc.to_f64();
+            match &mut panel.visibility {
+                Visibility::Shown { left } => {
+                    let over_panel = layer_map_for_output(&panel.output)
+                        .layer_geometry(&panel.layer)
+                        .map(|geometry| geometry.to_f64().contains(relative))
+                        .unwrap_or(false);
+                    // Popups of the panel count as the panel
+                    let over_client = focus
+                        .map(|surface| surface.id().same_client_as(&panel.layer.wl_surface().id()))
+                        .unwrap_or(false);
+                    if over_panel || over_client {
+                        *left = None;
+                    } else if left.is_none() {
+                        *left = Some(now);
+                        due = Some(Duration::from_millis(self.config.delay));
+                    }
+                }
+                Visibility::Hidden => {
+                    if at_edge(panel.edge, panel.geometry, output_geometry.size, relative) {
+                        panel.visibility = Visibility::Revealing(now);
+                        self.changes.push(panel.change(true));
+                    }
+                }
+                Visibility::Hiding(_) | Visibility::Revealing(_) => {}
+            }
+        }
+        due
+    }
+
+    /// Takes panels out of their layer map once the pointer was away long enough and puts them
+    /// back once they finished sliding in. Returns what changed since the last call.
+    pub fn refresh(&mut self, dh: &DisplayHandle) -> Vec<VisibilityChange> {
+        let now = Instant::now();
+        let delay = Duration::from_millis(self.config.delay);
+        let duration = Duration::from_millis(self.config.duration);
+        self.panels.retain(|panel| panel.layer.wl_surface().alive());
+
+        for panel in &mut self.panels {
+            match panel.visibility {
+                Visibility::Shown { left: Some(left) } if now.duration_since(left) >= delay => {
+                    let mut map = layer_map_for_output(&panel.output);
+                    match map.layer_geometry(&panel.layer) {
+                        Some(geometry) => panel.geometry = geometry,
+                        // Not configured yet
+                        None => continue,
+                    }
+                    map.unmap_layer(&panel.layer);
+                    map.arrange(dh);
+                    panel.visibility = Visibility::Hiding(now);
+                    self.changes.push(panel.change(false));
+                }
+                Visibility::Hiding(started) if now.duration_since(started) >= duration => {
+                    panel.visibility = Visibility::Hidden;
+                }
+                Visibility::Revealing(started) if now.duration_since(started) >= duration => {
+                    let mut map = layer_map_for_output(&panel.output);
+                    if let Err(err) = map.map_layer(dh, &panel.layer) {
+                        slog_scope::warn!("Failed to show {}: {}", panel.layer.namespace(), err);
+                    }
+                    panel.visibility = Visibility::Shown { left: None };
+                }
+                _ => {}
+            }
+        }
+        std::mem::take(&mut self.changes)
+    }
+
+    /// Panels sliding in or out on `output`
+    pub fn elements(&self, output: &Output) -> Vec<CustomElem> {
+        let now = Instant::now();
+        let duration = self.config.duration.max(1) as f64 / 1000.0;
+        self.panels
+            .iter()
+            .filter(|panel| &panel.output == output)
+            .filter_map(|panel| {
+                let shown = match panel.visibility {
+                    Visibility::Hiding(started) => {
+                        1.0 - now.duration_since(started).as_secs_f64() / duration
+                    }
+                    Visibility::Revealing(started) => {
+                        now.duration_since(started).as_secs_f64() / duration
+                    }
+                    Visibility::Shown { .. } | Visibility::Hidden => return None,
+                };
+                let shown = shown.clamp(0.0, 1.0);
+                // Slower towards both ends
+                let hidden = 1.0 - shown * shown * (3.0 - 2.0 * shown);
+
+                let size = panel.geometry.size;
+                let offset = match panel.edge {
+                    Edge::Top => (0.0, -size.h as f64 * hidden),
+                    Edge::Bottom => (0.0, size.h as f64 * hidden),
+                    Edge::Left => (-size.w as f64 * hidden, 0.0),
+                    Edge::Right => (size.w as f64 * hidden, 0.0),
+                };
+                let offset = Point::<f64, Logical>::from(offset).to_i32_round();
+                Some(CustomElem::from(SurfaceTree {
+                    surface: panel.layer.wl_surface().clone(),
+                    position: panel.geometry.loc + offset,
+                    z_index: z_index(panel.layer.wl_surface()),
+                }))
+            })
+            .collect()
+    }
+}
+
+impl Panel {
+    fn change(&self, visible: bool) -> VisibilityChange {
+        VisibilityChange {
+            namespace: self.layer.namespace().to_string(),
+            output: self.output.name(),
+            visible,
+        }
+    }
+}
+
+impl State {
+    /// Lets auto-hiding panels know where the pointer went
+    pub(crate) fn auto_hide_pointer_moved(
+        &mut self,
+        position: Point<f64, Logical>,
+        focus: Option<&WlSurface>,
+    ) {
+        let due = self.common.shell.auto_hide.pointer_moved(position, focus);
+        if let Some(due) = due {
+            // Only wakes up the event loop, hiding happens in the refresh after any dispatch
+            let _ = self
+                .common
+                .event_loop_handle
+                .insert_source(Timer::from_duration(due), |_, _, _| TimeoutAction::Drop);
+        }
+    }
+}
+
+impl CommonState {
+    /// Moves auto-hiding panels along and tells the runtime and shell clients what changed
+    pub fn refresh_auto_hide(&mut self, dh: &DisplayHandle) {
+        for change in self.shell.auto_hide.refresh(dh) {
+            self.electrum_shells.panel_visibility(&change);
+            let runtime_sender = &self.shell.active_workspace().runtime_sender;
+            let _ = runtime_sender.send(RuntimeMessage::PanelVisibility {
+                namespace: change.namespace,
+                output: change.output,
+                visible: change.visible,
+            });
+        }
+    }
+}
+
+/// The edge a layer surface is attached to, `None` if it stretches along two sides or floats
+fn anchored_edge(anchor: Anchor) -> Option<Edge> {
+    let horizontal = Anchor::LEFT | Anchor::RIGHT;
+    let vertical = Anchor::TOP | Anchor::BOTTOM;
+    if anchor.contains(Anchor::TOP) && !anchor.contains(Anchor::BOTTOM) {
+        if anchor.contains(horizontal) || !anchor.intersects(horizontal) {
+            return Some(Edge::Top);
+        }
+    }
+    if anchor.contains(Anchor::BOTTOM) && !anchor.contains(Anchor::TOP) {
+        if anchor.contains(horizontal) || !anchor.intersects(horizontal) {
+            return Some(Edge::Bottom);
+        }
+    }
+    if anchor.contains(Anchor::LEFT) && !anchor.contains(Anchor::RIGHT) {
+        if anchor.contains(vertical) || !anchor.intersects(vertical) {
+            return Some(Edge::Left);
+        }
+    }
+    if anchor.contains(Anchor::RIGHT) && !anchor.contains(Anchor::LEFT) {
+        if anchor.contains(vertical) || !anchor.intersects(vertical) {
+            return Some(Edge::Right);
+        }
+    }
+    None
+}
+
+/// Whether `pos`, relative to the output, is at the edge of a hidden panel with `geometry`
+fn at_edge(
+    edge: Edge,
+    geometry: Rectangle<i32, Logical>,
+    output_size: smithay::utils::Size<i32, Logical>,
+    pos: Point<f64, Logical>,
+) -> bool {
+    let geometry = geometry.to_f64();
+    let along_x = pos.x >= geometry.loc.x && pos.x < geometry.loc.x + geometry.size.w;
+    let along_y = pos.y >= geometry.loc.y && pos.y < geometry.loc.y + geometry.size.h;
+    // The pointer is clamped to the output size, not one pixel short of it
+    let (w, h) = (output_size.w as f64, output_size.h as f64);
+    match edge {
+        Edge::Top => along_x && pos.y <= REVEAL_DISTANCE,
+        Edge::Bottom => along_x && pos.y >= h - 1.0 - REVEAL_DISTANCE,
+        Edge::Left => along_y && pos.x <= REVEAL_DISTANCE,
+        Edge::Right => along_y && pos.x >= w - 1.0 - REVEAL_DISTANCE,
+    }
+}
+
+fn z_index(surface: &WlSurface) -> u8 {
+    let layer = with_states(surface, |states| {
+        states
+            .cached_state
+            .current::<LayerSurfaceCachedState>()
+            .layer
+    });
+    let z_index = match layer {
+        Layer::Background => RenderZindex::Background,
+        Layer::Bottom => RenderZindex::Bottom,
+        Layer::Top => RenderZindex::Top,
+        Layer::Overlay => RenderZindex::Overlay,
+    };
+    z_index as u8
+}
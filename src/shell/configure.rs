@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{cell::RefCell, collections::VecDeque};
+
+use smithay::{
+    desktop::{Kind, Window},
+    reexports::{
+        wayland_protocols::xdg::shell::server::xdg_toplevel::State as XdgState,
+        wayland_server::protocol::wl_surface::WlSurface,
+    },
+    utils::{Logical, Rectangle},
+    wayland::{output::Output, shell::xdg::ToplevelState, Serial},
+};
+
+use super::{grab::grabbed_window, layout::usable_zone};
+use crate::state::{CommonState, State};
+
+type AckHook = Box<dyn FnOnce(&mut State, &Window)>;
+
+/// Configures of one toplevel. Changes to its state from anywhere are gathered and go out as a
+/// single configure per event loop iteration, so the client never sees half of them.
+#[derive(Default)]
+struct ConfigureQueue(RefCell<QueueState>);
+
+#[derive(Default)]
+struct QueueState {
+    /// The pending state changed since the last configure
+    dirty: bool,
+    /// Hooks waiting for the next configure to be sent
+    next: Vec<AckHook>,
+    /// Configures the client did not ack yet, oldest first, with the hooks waiting for them
+    sent: VecDeque<(Serial, Vec<AckHook>)>,
+}
+
+fn queue(window: &Window) -> &ConfigureQueue {
+    window
+        .user_data()
+        .insert_if_missing(ConfigureQueue::default);
+    window.user_data().get::<ConfigureQueue>().unwrap()
+}
+
+/// Changes the pending state of `window`, it is sent with the next flush together with everything
+/// else that changed until then
+pub fn configure(window: &Window, f: impl FnOnce(&mut ToplevelState)) {
+    match window.toplevel() {
+        Kind::Xdg(toplevel) => toplevel.with_pending_state(f),
+    }
+    queue(window).0.borrow_mut().dirty = true;
+}
+
+/// Tells `window` how large it can get on `output` without covering panels, through the
+/// `bounds` of the configure. Clients pick their initial size and the size they restore to from
+/// it. Nothing is sent if the bounds stay the same.
+pub fn set_bounds(window: &Window, output: &Output) {
+    let bounds = usable_zone(output).size;
+    let changed = match window.toplevel() {
+        Kind::Xdg(toplevel) => {
+            toplevel.with_pending_state(|state| state.bounds.replace(bounds) != Some(bounds))
+        }
+    };
+    if changed {
+        queue(window).0.borrow_mut().dirty = true;
+    }
+}
+
+/// Edges of a window that are against the border of the usable zone or another tiled window, so
+/// the client draws them square and without shadows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TiledEdges {
+    pub left: bool,
+    pub right: bool,
+    pub top: bool,
+    pub bottom: bool,
+}
+
+impl TiledEdges {
+    /// The edges of `target` that touch the border of `zone` or one of `neighbours`
+    pub fn of(
+        target: Rectangle<i32, Logical>,
+        zone: Rectangle<i32, Logical>,
+        neighbours: &[Rectangle<i32, Logical>],
+    ) -> Self {
+        let (right, bottom) = (target.loc.x + target.size.w, target.loc.y + target.size.h);
+        let overlaps_y = |other: &Rectangle<i32, Logical>| {
+            other.loc.y < bottom && target.loc.y < other.loc.y + other.size.h
+        };
+        let overlaps_x = |other: &Rectangle<i32, Logical>| {
+            other.loc.x < right && target.loc.x < other.loc.x + other.size.w
+        };
+        TiledEdges {
+            left: target.loc.x <= zone.loc.x
+                || neighbours
+                    .iter()
+                    .any(|other| other.loc.x + other.size.w == target.loc.x && overlaps_y(other)),
+            right: right >= zone.loc.x + zone.size.w
+                || neighbours
+                    .iter()
+                    .any(|other| other.loc.x == right && overlaps_y(other)),
+            top: target.loc.y <= zone.loc.y
+                || neighbours
+                    .iter()
+                    .any(|other| other.loc.y + other.size.h == target.loc.y && overlaps_x(other)),
+            bottom: bottom >= zone.loc.y + zone.size.h
+                || neighbours
+                    .iter()
+                    .any(|other| other.loc.y == bottom && overlaps_x(other)),
+        }
+    }
+
+    /// Sets the tiled states of these edges and unsets those of the others
+    pub fn apply(self, state: &mut ToplevelState) {
+        for (edge, tiled) in [
+            (XdgState::TiledLeft, self.left),
+            (XdgState::TiledRight, self.right),
+            (XdgState::TiledTop, self.top),
+            (XdgState::TiledBottom, self.bottom),
+        ] {
+            if tiled {
+                state.states.set(edge);
+            } else {
+                state.states.unset(edge);
+            }
+        }
+    }
+}
+
+/// Runs `hook` once the client acked the next configure of `window`, which is sent even if
+/// nothing changed
+pub fn on_ack(window: &Window, hook: impl FnOnce(&mut State, &Window) + 'static) {
+    let mut queue = queue(window).0.borrow_mut();
+    queue.dirty = true;
+    queue.next.push(Box::new(hook));
+}
+
+/// Sends what changed for `window` right away, instead of with the next flush
+pub fn flush(window: &Window) {
+    let mut queue = queue(window).0.borrow_mut();
+    if !queue.dirty {
+        return;
+    }
+    queue.dirty = false;
+    let serial = match window.toplevel() {
+        Kind::Xdg(toplevel) => toplevel.send_configure(),
+    };
+    let hooks = std::mem::take(&mut queue.next);
+    queue.sent.push_back((serial, hooks));
+}
+
+impl CommonState {
+    /// Sends one configure to every window whose state changed, mapped or being moved
+    pub fn flush_configures(&mut self) {
+        let grabbed = self.seats.iter().filter_map(grabbed_window);
+        let mapped = self
+            .shell
+            .workspaces
+            .iter()
+            .flat_map(|workspace| workspace.space.windows().cloned());
+        for window in mapped.chain(grabbed).collect::<Vec<_>>() {
+            flush(&window);
+        }
+    }
+}
+
+impl State {
+    /// Runs the hooks of the configures up to `serial` of the window of `surface`, acking a
+    /// configure also acks the ones sent before it
+    pub fn configure_acked(&mut self, surface: &WlSurface, serial: Serial) {
+        let window = self.common.shell.mapped_window(surface).or_else(|| {
+            self.common
+                .seats
+                .iter()
+                .filter_map(grabbed_window)
+                .find(|window| window.toplevel().wl_surface() == surface)
+        });
+        let window = match window {
+            Some(window) => window,
+            None => return,
+        };
+
+        let mut hooks = Vec::new();
+        {
+            let mut queue = queue(&window).0.borrow_mut();
+            while let Some((sent, _)) = queue.sent.front() {
+                if *sent > serial {
+                    break;
+                }
+                hooks.extend(queue.sent.pop_front().unwrap().1);
+            }
+        }
+        for hook in hooks {
+            hook(self, &window);
+        }
+    }
+}
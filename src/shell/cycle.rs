@@ -0,0 +1,60 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use serde::{Deserialize, Serialize};
+use smithay::{desktop::Window, reexports::wayland_server::DisplayHandle, wayland::seat::Seat};
+
+use super::{window::WindowExt, Shell};
+use crate::state::State;
+
+/// Which way to go through the windows of a workspace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Cycle {
+    Next,
+    Previous,
+}
+
+impl Shell {
+    /// The window opened after the one `seat` focuses on the active workspace, or before it,
+    /// wrapping around. Going by the order windows opened in keeps the cycle the same however
+    /// often it goes round, unlike the focus stack. Shaded windows are skipped.
+    pub fn window_in_cycle(&self, seat: &Seat<State>, cycle: Cycle) -> Option<Window> {
+        let workspace = self.active_workspace();
+        let mut windows = workspace
+            .space
+            .windows()
+            .filter(|window| window.shaded().is_none())
+            .cloned()
+            .collect::<Vec<_>>();
+        windows.sort_by_key(|window| window.id());
+        if windows.is_empty() {
+            return None;
+        }
+
+        let focused = workspace.focus_stack(seat).last();
+        let index = focused.and_then(|focused| windows.iter().position(|w| *w == focused));
+        let len = windows.len();
+        let next = match (index, cycle) {
+            (Some(index), Cycle::Next) => (index + 1) % len,
+            (Some(index), Cycle::Previous) => (index + len - 1) % len,
+            (None, Cycle::Next) => 0,
+            (None, Cycle::Previous) => len - 1,
+        };
+        Some(windows.swap_remove(next))
+    }
+}
+
+impl State {
+    /// Moves the focus of `seat` to the next or previous window of the workspace, returning it
+    pub fn cycle_windows(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &Seat<State>,
+        cycle: Cycle,
+    ) -> Option<Window> {
+        let window = self.common.shell.window_in_cycle(seat, cycle)?;
+        let surface = window.toplevel().wl_surface().clone();
+        self.common.set_focus(dh, Some(&surface), seat, None);
+        Some(window)
+    }
+}
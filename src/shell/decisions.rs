@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use calloop::{
+    timer::{TimeoutAction, Timer},
+    RegistrationToken,
+};
+use serde::Deserialize;
+use smithay::wayland::SERIAL_COUNTER;
+
+use crate::{
+    runtime::messages::RuntimeMessage, shell::layout::zones::ZoneRect, state::CommonState,
+};
+
+/// What happens to requests the runtime did not decide on in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecisionPolicy {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DecisionConfig {
+    /// Milliseconds the runtime gets to answer a request
+    pub timeout: u64,
+    pub policy: DecisionPolicy,
+}
+
+impl Default for DecisionConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 200,
+            policy: DecisionPolicy::Allow,
+        }
+    }
+}
+
+/// The answer of the runtime to a maximize, unmaximize or resize request
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Allow,
+    Deny,
+    /// Places a window that asked to be maximized here instead, relative to the output
+    Geometry(ZoneRect),
+}
+
+impl From<DecisionPolicy> for Decision {
+    fn from(policy: DecisionPolicy) -> Self {
+        match policy {
+            DecisionPolicy::Allow => Decision::Allow,
+            DecisionPolicy::Deny => Decision::Deny,
+        }
+    }
+}
+
+/// Requests held back until the runtime decided on them, by correlation id
+#[derive(Default)]
+pub struct PendingDecisions {
+    next_id: u64,
+    /// With the time they were deferred at
+    pending: HashMap<u64, (RuntimeMessage, RegistrationToken, Instant)>,
+}
+
+impl PendingDecisions {
+    /// The correlation id of the next deferred request
+    pub fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+}
+
+impl CommonState {
+    /// Holds `request` back until the runtime decided on it with the correlation id `id`, or
+    /// the timeout ran out
+    pub fn defer_request(&mut self, id: u64, request: RuntimeMessage) {
+        let timeout = Duration::from_millis(self.config.decisions.timeout);
+        let token = self.event_loop_handle.insert_source(
+            Timer::from_duration(timeout),
+            move |_, _, data| {
+                data.state.common.decide(id, None);
+                TimeoutAction::Drop
+            },
+        );
+        match token {
+            Ok(token) => {
+                self.decisions
+                    .pending
+                    .insert(id, (request, token, Instant::now()));
+            }
+            Err(_) => {
+                slog_scope::warn!("Failed to start the timeout of request {}", id);
+                self.apply_decision(request, self.config.decisions.policy.into());
+            }
+        }
+    }
+
+    /// Carries out request `id` as the runtime decided, `None` if it did not answer in time
+    pub fn decide(&mut self, id: u64, decision: Option<Decision>) {
+        let (request, token, deferred) = match self.decisions.pending.remove(&id) {
+            Some(pending) => pending,
+            None => {
+                slog_scope::debug!("Request {} was already decided on", id);
+                return;
+            }
+        };
+        let decision = match decision {
+            Some(decision) => {
+                self.event_loop_handle.remove(token);
+                self.metrics.decision(deferred.elapsed());
+                decision
+            }
+            None => {
+                slog_scope::debug!("The runtime did not decide on request {} in time", id);
+                self.metrics.decision_timed_out();
+                self.config.decisions.policy.into()
+            }
+        };
+        self.apply_decision(request, decision);
+    }
+
+    /// Carries out a request the runtime is not asked about as the configured policy says
+    pub fn undecided_request(&mut self, request: RuntimeMessage) {
+        self.apply_decision(request, self.config.decisions.policy.into());
+    }
+
+    fn apply_decision(&mut self, request: RuntimeMessage, decision: Decision) {
+        let workspace = self.shell.active_workspace_mut();
+        match (decision, request) {
+            (Decision::Deny, RuntimeMessage::ResizeRequest { seat, serial, .. }) => {
+                if let Some(pointer) = seat.get_pointer() {
+                    if pointer.has_grab(serial) {
+                        pointer.unset_grab(SERIAL_COUNTER.next_serial(), 0);
+                    }
+                }
+            }
+            (Decision::Deny, _) => {}
+            (Decision::Geometry(target), RuntimeMessage::MaximizeRequest { window, output }) => {
+                workspace
+                    .layer
+                    .tile_window(&mut workspace.space, window, &output, target.into())
+            }
+            (Decision::Geometry(_), request) => {
+                slog_scope::warn!("A geometry only applies to maximize requests, allowing instead");
+                workspace.default_request(request);
+            }
+            (Decision::Allow, request) => workspace.default_request(request),
+        }
+    }
+}
@@ -0,0 +1,163 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use serde::{Deserialize, Serialize};
+use smithay::{
+    desktop::Window,
+    reexports::wayland_server::DisplayHandle,
+    utils::{Logical, Point, Rectangle},
+    wayland::seat::Seat,
+};
+
+use super::{window::WindowExt, Shell};
+use crate::state::{output::active_output, State};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl Direction {
+    /// How far `to` is from `from` along this direction and across it, `None` if it is not in
+    /// this direction at all
+    fn distances(self, from: Point<f64, Logical>, to: Point<f64, Logical>) -> Option<(f64, f64)> {
+        let (along, across) = match self {
+            Direction::Left => (from.x - to.x, to.y - from.y),
+            Direction::Right => (to.x - from.x, to.y - from.y),
+            Direction::Up => (from.y - to.y, to.x - from.x),
+            Direction::Down => (to.y - from.y, to.x - from.x),
+        };
+        (along > 0.0).then_some((along, across.abs()))
+    }
+
+    fn opposite(self) -> Self {
+        match self {
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DirectionalFocusConfig {
+    /// Without a window in the direction, focus the one furthest in the other direction
+    pub wrap: bool,
+    /// Windows on other outputs count, otherwise focus stays on the output of the focused window
+    pub across_outputs: bool,
+    /// Moves the pointer to the center of the window when the focus goes to another output
+    pub warp_pointer: bool,
+}
+
+impl Default for DirectionalFocusConfig {
+    fn default() -> Self {
+        Self {
+            wrap: false,
+            across_outputs: true,
+            warp_pointer: false,
+        }
+    }
+}
+
+impl Shell {
+    /// The window closest to the one `seat` focuses in `direction`, between their centers.
+    /// Distance across the direction counts double, so a window straight ahead wins over a
+    /// closer one off to the side. Shaded windows and those behind a fullscreen window are
+    /// skipped.
+    pub fn window_in_direction(
+        &self,
+        seat: &Seat<State>,
+        direction: Direction,
+        config: &DirectionalFocusConfig,
+    ) -> Option<Window> {
+        let workspace = self.active_workspace();
+        let focused = workspace.focus_stack(seat).last()?;
+        let from = center(self.window_rect(&focused)?);
+        let output = workspace.space.output_under(from).next().cloned();
+
+        let candidates = workspace
+            .space
+            .windows()
+            .filter(|window| **window != focused && window.shaded().is_none())
+            .filter_map(|window| Some((window, center(self.window_rect(window)?))))
+            .filter(|(window, center)| {
+                let under = workspace.space.output_under(*center).next();
+                if !config.across_outputs && under != output.as_ref() {
+                    return false;
+                }
+                under
+                    .and_then(|output| workspace.get_filling(output))
+                    .map_or(true, |filling| filling.window == **window)
+            })
+            .collect::<Vec<_>>();
+
+        let score = |(along, across): (f64, f64)| along + 2.0 * across;
+        let closest = candidates
+            .iter()
+            .filter_map(|(window, to)| Some((*window, score(direction.distances(from, *to)?))))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+        if let Some((window, _)) = closest {
+            return Some(window.clone());
+        }
+        if !config.wrap {
+            return None;
+        }
+
+        // Wrapping around lands on the far end, still preferring windows in line
+        candidates
+            .iter()
+            .filter_map(|(window, to)| {
+                let (along, across) = direction.opposite().distances(from, *to)?;
+                Some((*window, 2.0 * across - along))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(window, _)| window.clone())
+    }
+
+    fn window_rect(&self, window: &Window) -> Option<Rectangle<i32, Logical>> {
+        let location = self.active_workspace().space.window_location(window)?;
+        Some(Rectangle::from_loc_and_size(
+            location,
+            window.geometry().size,
+        ))
+    }
+}
+
+fn center(rect: Rectangle<i32, Logical>) -> Point<f64, Logical> {
+    let rect = rect.to_f64();
+    rect.loc + Point::from((rect.size.w / 2.0, rect.size.h / 2.0))
+}
+
+impl State {
+    /// Moves the focus of `seat` to the next window in `direction`, returning it
+    pub fn focus_direction(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &Seat<State>,
+        direction: Direction,
+    ) -> Option<Window> {
+        let config = &self.common.config.directional_focus;
+        let window = self
+            .common
+            .shell
+            .window_in_direction(seat, direction, config)?;
+        let surface = window.toplevel().wl_surface().clone();
+        self.common.set_focus(dh, Some(&surface), seat, None);
+
+        if self.common.config.directional_focus.warp_pointer {
+            let center = self.window_center(&window);
+            let output = center.and_then(|center| self.common.shell.output_at(center).cloned());
+            if let (Some(center), Some(output)) = (center, output) {
+                if output != active_output(seat, &self.common) {
+                    self.warp_pointer_to(dh, seat, center, true);
+                }
+            }
+        }
+        Some(window)
+    }
+}
@@ -0,0 +1,573 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! A compositor without a backend or script and a client inside the test process, talking over
+//! a socket pair. Tests create toplevels and layer surfaces with the sizes and commits they need
+//! and look at the shell afterwards.
+//!
+//! ```ignore
+//! let mut fixture = Fixture::new(Config::default());
+//! fixture.add_output("DP-1", (1920, 1080));
+//! let toplevel = fixture.toplevel("org.example.App", (800, 600));
+//! fixture.set_fullscreen(&toplevel, true);
+//! let window = fixture.window(&toplevel).unwrap();
+//! assert!(fixture.shell().active_workspace().is_fullscreen(&window));
+//! ```
+
+use std::{
+    collections::HashMap,
+    ffi::{CString, OsString},
+    os::unix::net::UnixStream,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use calloop::channel::{channel, Event};
+use nix::{
+    sys::memfd::{memfd_create, MemFdCreateFlag},
+    unistd::{close, ftruncate},
+};
+use sctk::reexports::{
+    client::{
+        backend::ObjectId,
+        protocol::{
+            wl_buffer, wl_callback, wl_compositor, wl_registry, wl_shm, wl_shm_pool, wl_surface,
+        },
+        Connection, Dispatch, EventQueue, Proxy, QueueHandle,
+    },
+    protocols::xdg::shell::client::{xdg_surface, xdg_toplevel, xdg_wm_base},
+    protocols_wlr::layer_shell::v1::client::{zwlr_layer_shell_v1, zwlr_layer_surface_v1},
+};
+use smithay::{
+    desktop::{layer_map_for_output, LayerSurface, Window},
+    reexports::{calloop::EventLoop, wayland_server::Display},
+    utils::Transform,
+    wayland::output::{Mode, Output, PhysicalProperties, Scale, Subpixel},
+};
+
+use super::Shell;
+use crate::{
+    config::Config,
+    log::LogState,
+    state::{Data, LoopData, State},
+};
+
+/// Dispatches in a row without the client hearing back, before a roundtrip is given up on
+const MAX_DISPATCHES: usize = 100;
+
+/// A compositor and one client of it
+pub struct Fixture {
+    event_loop: EventLoop<'static, LoopData>,
+    data: Data,
+    client: FakeClient,
+}
+
+/// A toplevel of the fake client
+pub struct FakeToplevel {
+    surface: wl_surface::WlSurface,
+    xdg_surface: xdg_surface::XdgSurface,
+    toplevel: xdg_toplevel::XdgToplevel,
+}
+
+/// A layer surface of the fake client
+pub struct FakeLayer {
+    surface: wl_surface::WlSurface,
+    layer_surface: zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+}
+
+struct FakeClient {
+    connection: Connection,
+    queue: EventQueue<ClientState>,
+    state: ClientState,
+}
+
+#[derive(Default)]
+struct ClientState {
+    compositor: Option<wl_compositor::WlCompositor>,
+    shm: Option<wl_shm::WlShm>,
+    wm_base: Option<xdg_wm_base::XdgWmBase>,
+    layer_shell: Option<zwlr_layer_shell_v1::ZwlrLayerShellV1>,
+    /// The last configure serial of xdg and layer surfaces, not acked yet
+    serials: HashMap<ObjectId, u32>,
+    /// The size of the last configure of toplevels and layer surfaces, 0 leaves it to the client
+    sizes: HashMap<ObjectId, (i32, i32)>,
+    /// Toplevels the compositor asked to close
+    closed: Vec<ObjectId>,
+}
+
+impl Fixture {
+    /// A compositor without outputs, with the client connected
+    pub fn new(config: Config) -> Self {
+        let event_loop = EventLoop::try_new().expect("Failed to create the event loop");
+        let mut display = Display::<State>::new().expect("Failed to create the display");
+
+        // Without a script every request gets the default answer, like in the compositor
+        let (runtime_sender, runtime_channel) = channel();
+        event_loop
+            .handle()
+            .insert_source(runtime_channel, |message, _, data| {
+                if let Event::Msg(message) = message {
+                    data.state
+                        .common
+                        .shell
+                        .active_workspace_mut()
+                        .default_request(message);
+                }
+            })
+            .expect("Failed to insert the runtime channel");
+
+        let state = State::new(
+            &display.handle(),
+            OsString::from("wayland-fixture"),
+            event_loop.handle(),
+            event_loop.get_signal(),
+            LogState::detached(),
+            config,
+            runtime_sender,
+        );
+
+        let (server, client) = UnixStream::pair().expect("Failed to create a socket pair");
+        let client_state = state.new_client_state(None, false);
+        display
+            .handle()
+            .insert_client(server, Arc::new(client_state))
+            .expect("Failed to insert the client");
+        let connection = Connection::from_socket(client).expect("Failed to connect");
+        let queue = connection.new_event_queue();
+        connection.display().get_registry(&queue.handle(), ());
+
+        let mut fixture = Fixture {
+            event_loop,
+            data: Data { display, state },
+            client: FakeClient {
+                connection,
+                queue,
+                state: ClientState::default(),
+            },
+        };
+        fixture.roundtrip();
+        fixture
+    }
+
+    pub fn shell(&self) -> &Shell {
+        &self.data.state.common.shell
+    }
+
+    /// Connects an output with the physical `size`, placed by the output layout like any other
+    pub fn add_output(&mut self, name: &str, size: (i32, i32)) -> Output {
+        let props = PhysicalProperties {
+            size: (0, 0).into(),
+            subpixel: Subpixel::Unknown,
+            make: String::from("ELECTRUM"),
+            model: name.to_string(),
+        };
+        let mode = Mode {
+            size: size.into(),
+            refresh: 60_000,
+        };
+        let output = Output::new(name.to_string(), props, None);
+        output.add_mode(mode);
+        output.set_preferred(mode);
+        output.change_current_state(
+            Some(mode),
+            Some(Transform::Normal),
+            Some(Scale::Integer(1)),
+            Some((0, 0).into()),
+        );
+        self.data.state.output_connected(&output);
+        self.roundtrip();
+        output
+    }
+
+    pub fn remove_output(&mut self, output: &Output) {
+        self.data.state.output_disconnected(output);
+        self.roundtrip();
+    }
+
+    /// A toplevel mapped with a buffer of `size`, or the size of its first configure if the
+    /// compositor picked one
+    pub fn toplevel(&mut self, app_id: &str, size: (i32, i32)) -> FakeToplevel {
+        let qh = self.client.queue.handle();
+        let globals = &self.client.state;
+        let surface = globals.compositor.as_ref().unwrap().create_surface(&qh, ());
+        let xdg_surface = globals
+            .wm_base
+            .as_ref()
+            .unwrap()
+            .get_xdg_surface(&surface, &qh, ());
+        let toplevel = xdg_surface.get_toplevel(&qh, ());
+        toplevel.set_app_id(app_id.to_string());
+        surface.commit();
+        self.roundtrip();
+
+        let toplevel = FakeToplevel {
+            surface,
+            xdg_surface,
+            toplevel,
+        };
+        let size = self.configured_size(&toplevel).unwrap_or(size);
+        self.commit(&toplevel, size);
+        toplevel
+    }
+
+    /// Acks the last configure and commits a buffer of `size`
+    pub fn commit(&mut self, toplevel: &FakeToplevel, size: (i32, i32)) {
+        if let Some(serial) = self.client.state.serials.remove(&toplevel.xdg_surface.id()) {
+            toplevel.xdg_surface.ack_configure(serial);
+        }
+        toplevel
+            .xdg_surface
+            .set_window_geometry(0, 0, size.0, size.1);
+        self.attach_buffer(&toplevel.surface, size);
+        toplevel.surface.commit();
+        self.roundtrip();
+    }
+
+    /// Commits without a new buffer, only acking the last configure
+    pub fn ack(&mut self, toplevel: &FakeToplevel) {
+        if let Some(serial) = self.client.state.serials.remove(&toplevel.xdg_surface.id()) {
+            toplevel.xdg_surface.ack_configure(serial);
+        }
+        toplevel.surface.commit();
+        self.roundtrip();
+    }
+
+    /// The size of the last configure, `None` if the client may pick it
+    pub fn configured_size(&self, toplevel: &FakeToplevel) -> Option<(i32, i32)> {
+        self.client
+            .state
+            .sizes
+            .get(&toplevel.toplevel.id())
+            .copied()
+            .filter(|(w, h)| *w > 0 && *h > 0)
+    }
+
+    /// Whether the compositor asked the toplevel to close
+    pub fn close_requested(&self, toplevel: &FakeToplevel) -> bool {
+        self.client.state.closed.contains(&toplevel.toplevel.id())
+    }
+
+    pub fn set_fullscreen(&mut self, toplevel: &FakeToplevel, fullscreen: bool) {
+        if fullscreen {
+            toplevel.toplevel.set_fullscreen(None);
+        } else {
+            toplevel.toplevel.unset_fullscreen();
+        }
+        self.roundtrip();
+    }
+
+    pub fn set_maximized(&mut self, toplevel: &FakeToplevel, maximized: bool) {
+        if maximized {
+            toplevel.toplevel.set_maximized();
+        } else {
+            toplevel.toplevel.unset_maximized();
+        }
+        self.roundtrip();
+    }
+
+    /// Unmaps and destroys the toplevel
+    pub fn destroy(&mut self, toplevel: FakeToplevel) {
+        toplevel.toplevel.destroy();
+        toplevel.xdg_surface.destroy();
+        toplevel.surface.destroy();
+        self.roundtrip();
+    }
+
+    /// The window of the compositor for `toplevel`, once it is mapped
+    pub fn window(&self, toplevel: &FakeToplevel) -> Option<Window> {
+        let id = toplevel.surface.id().protocol_id();
+        self.shell().workspaces.iter().find_map(|workspace| {
+            workspace
+                .space
+                .windows()
+                .find(|window| window.toplevel().wl_surface().id().protocol_id() == id)
+                .cloned()
+        })
+    }
+
+    /// The window the keyboard of the seat focuses
+    pub fn focused(&self) -> Option<Window> {
+        let seat = &self.data.state.common.last_active_seat;
+        self.shell().active_workspace().focus_stack(seat).last()
+    }
+
+    /// A layer surface with the size it asks for and an exclusive zone, mapped once the
+    /// compositor configured it
+    pub fn layer_surface(
+        &mut self,
+        namespace: &str,
+        layer: zwlr_layer_shell_v1::Layer,
+        anchor: zwlr_layer_surface_v1::Anchor,
+        size: (i32, i32),
+        exclusive_zone: i32,
+    ) -> FakeLayer {
+        let qh = self.client.queue.handle();
+        let globals = &self.client.state;
+        let surface = globals.compositor.as_ref().unwrap().create_surface(&qh, ());
+        // Outputs are only known to the compositor, the layer goes where it puts it
+        let layer_surface = globals.layer_shell.as_ref().unwrap().get_layer_surface(
+            &surface,
+            None,
+            layer,
+            namespace.to_string(),
+            &qh,
+            (),
+        );
+        layer_surface.set_anchor(anchor);
+        layer_surface.set_size(size.0 as u32, size.1 as u32);
+        layer_surface.set_exclusive_zone(exclusive_zone);
+        surface.commit();
+        self.roundtrip();
+
+        let fake = FakeLayer {
+            surface,
+            layer_surface,
+        };
+        let configured = self
+            .client
+            .state
+            .sizes
+            .get(&fake.layer_surface.id())
+            .copied()
+            .unwrap_or(size);
+        if let Some(serial) = self.client.state.serials.remove(&fake.layer_surface.id()) {
+            fake.layer_surface.ack_configure(serial);
+        }
+        self.attach_buffer(&fake.surface, configured);
+        fake.surface.commit();
+        self.roundtrip();
+        fake
+    }
+
+    /// The layer surface of the compositor for `layer`, on whichever output it ended up
+    pub fn layer(&self, layer: &FakeLayer) -> Option<LayerSurface> {
+        let id = layer.surface.id().protocol_id();
+        self.shell().outputs().find_map(|output| {
+            layer_map_for_output(output)
+                .layers()
+                .find(|layer| layer.wl_surface().id().protocol_id() == id)
+                .cloned()
+        })
+    }
+
+    pub fn destroy_layer(&mut self, layer: FakeLayer) {
+        layer.layer_surface.destroy();
+        layer.surface.destroy();
+        self.roundtrip();
+    }
+
+    /// Runs both sides until the compositor answered everything sent so far, along with what the
+    /// main loop does between dispatches
+    pub fn roundtrip(&mut self) {
+        let done = Arc::new(AtomicBool::new(false));
+        let qh = self.client.queue.handle();
+        self.client.connection.display().sync(&qh, done.clone());
+
+        for _ in 0..MAX_DISPATCHES {
+            self.client
+                .connection
+                .flush()
+                .expect("The compositor closed the connection");
+            self.event_loop
+                .dispatch(Some(Duration::ZERO), &mut self.data)
+                .expect("Failed to dispatch the event loop");
+            let dh = self.data.display.handle();
+            self.data
+                .display
+                .dispatch_clients(&mut self.data.state)
+                .expect("Failed to dispatch the client");
+            let common = &mut self.data.state.common;
+            common.shell.refresh(&dh);
+            common.refresh_focus(&dh);
+            common.flush_configures();
+            self.data
+                .display
+                .flush_clients()
+                .expect("Failed to flush the client");
+
+            if let Some(guard) = self.client.connection.prepare_read() {
+                // Nothing to read yet is fine, the compositor may still be busy
+                let _ = guard.read();
+            }
+            self.client
+                .queue
+                .dispatch_pending(&mut self.client.state)
+                .expect("Failed to dispatch the events of the client");
+            if done.load(Ordering::SeqCst) {
+                return;
+            }
+        }
+        panic!("The compositor did not answer a roundtrip");
+    }
+
+    /// Attaches a buffer of `size` in transparent black, it is only looked at for its size
+    fn attach_buffer(&mut self, surface: &wl_surface::WlSurface, size: (i32, i32)) {
+        let qh = self.client.queue.handle();
+        let (width, height) = (size.0.max(1), size.1.max(1));
+        let len = width * height * 4;
+        let name = CString::new("electrum-fixture").unwrap();
+        let fd =
+            memfd_create(&name, MemFdCreateFlag::MFD_CLOEXEC).expect("Failed to create a memfd");
+        ftruncate(fd, len as i64).expect("Failed to size the memfd");
+        let pool = self
+            .client
+            .state
+            .shm
+            .as_ref()
+            .unwrap()
+            .create_pool(fd, len, &qh, ());
+        let buffer = pool.create_buffer(
+            0,
+            width,
+            height,
+            width * 4,
+            wl_shm::Format::Argb8888,
+            &qh,
+            (),
+        );
+        pool.destroy();
+        let _ = close(fd);
+        surface.attach(Some(&buffer), 0, 0);
+        surface.damage_buffer(0, 0, width, height);
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, ()> for ClientState {
+    fn event(
+        state: &mut Self,
+        registry: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_registry::Event::Global {
+            name, interface, ..
+        } = event
+        {
+            match interface.as_str() {
+                "wl_compositor" => state.compositor = Some(registry.bind(name, 4, qh, ())),
+                "wl_shm" => state.shm = Some(registry.bind(name, 1, qh, ())),
+                "xdg_wm_base" => state.wm_base = Some(registry.bind(name, 1, qh, ())),
+                "zwlr_layer_shell_v1" => state.layer_shell = Some(registry.bind(name, 1, qh, ())),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Dispatch<wl_callback::WlCallback, Arc<AtomicBool>> for ClientState {
+    fn event(
+        _: &mut Self,
+        _: &wl_callback::WlCallback,
+        event: wl_callback::Event,
+        done: &Arc<AtomicBool>,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event {
+            done.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Dispatch<xdg_wm_base::XdgWmBase, ()> for ClientState {
+    fn event(
+        _: &mut Self,
+        wm_base: &xdg_wm_base::XdgWmBase,
+        event: xdg_wm_base::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let xdg_wm_base::Event::Ping { serial } = event {
+            wm_base.pong(serial);
+        }
+    }
+}
+
+impl Dispatch<xdg_surface::XdgSurface, ()> for ClientState {
+    fn event(
+        state: &mut Self,
+        xdg_surface: &xdg_surface::XdgSurface,
+        event: xdg_surface::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let xdg_surface::Event::Configure { serial } = event {
+            state.serials.insert(xdg_surface.id(), serial);
+        }
+    }
+}
+
+impl Dispatch<xdg_toplevel::XdgToplevel, ()> for ClientState {
+    fn event(
+        state: &mut Self,
+        toplevel: &xdg_toplevel::XdgToplevel,
+        event: xdg_toplevel::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            xdg_toplevel::Event::Configure { width, height, .. } => {
+                state.sizes.insert(toplevel.id(), (width, height));
+            }
+            xdg_toplevel::Event::Close => state.closed.push(toplevel.id()),
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, ()> for ClientState {
+    fn event(
+        state: &mut Self,
+        layer_surface: &zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+        event: zwlr_layer_surface_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let zwlr_layer_surface_v1::Event::Configure {
+            serial,
+            width,
+            height,
+        } = event
+        {
+            let id = layer_surface.id();
+            state.serials.insert(id.clone(), serial);
+            state.sizes.insert(id, (width as i32, height as i32));
+        }
+    }
+}
+
+/// Interfaces whose events the fixture doesn't care about
+macro_rules! ignore_events {
+    ($($interface:ty),* $(,)?) => {
+        $(
+            impl Dispatch<$interface, ()> for ClientState {
+                fn event(
+                    _: &mut Self,
+                    _: &$interface,
+                    _: <$interface as Proxy>::Event,
+                    _: &(),
+                    _: &Connection,
+                    _: &QueueHandle<Self>,
+                ) {
+                }
+            }
+        )*
+    };
+}
+
+ignore_events!(
+    wl_compositor::WlCompositor,
+    wl_surface::WlSurface,
+    wl_shm::WlShm,
+    wl_shm_pool::WlShmPool,
+    wl_buffer::WlBuffer,
+    zwlr_layer_shell_v1::ZwlrLayerShellV1,
+);
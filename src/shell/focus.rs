@@ -1,5 +1,5 @@
 use crate::{
-    shell::{Shell, Workspace},
+    shell::{configure::configure, window::WindowExt, Shell, Workspace},
     state::{xdg_shell::PopupGrabData, CommonState, State},
 };
 use indexmap::IndexSet;
@@ -13,18 +13,47 @@ use smithay::{
     },
 };
 use std::{
-    cell::{Ref, RefCell, RefMut},
+    cell::{Cell, Ref, RefCell, RefMut},
     collections::HashMap,
     sync::Mutex,
 };
 
 pub struct FocusStack<'a>(Ref<'a, IndexSet<Window>>);
+
+/// Set on a window that was focused without being raised, until it loses the focus
+#[derive(Default)]
+struct Unraised(Cell<bool>);
+
+/// Keeps `window` where it is in the stack the next time it is focused, and for as long as it
+/// keeps the focus
+pub fn set_unraised(window: &Window, unraised: bool) {
+    window.user_data().insert_if_missing(Unraised::default);
+    window
+        .user_data()
+        .get::<Unraised>()
+        .unwrap()
+        .0
+        .set(unraised);
+}
+
+fn unraised(window: &Window) -> bool {
+    window
+        .user_data()
+        .get::<Unraised>()
+        .map(|unraised| unraised.0.get())
+        .unwrap_or(false)
+}
 pub struct FocusStackMut<'a>(RefMut<'a, IndexSet<Window>>);
 
 impl<'a> FocusStack<'a> {
     pub fn last(&self) -> Option<Window> {
         self.0.iter().rev().find(|w| w.toplevel().alive()).cloned()
     }
+
+    /// Windows that are still around, the most recently focused first
+    pub fn iter_recent(&self) -> impl Iterator<Item = &Window> {
+        self.0.iter().rev().filter(|w| w.toplevel().alive())
+    }
 }
 
 impl<'a> FocusStackMut<'a> {
@@ -145,14 +174,25 @@ impl Shell {
             })
             .collect::<Vec<_>>();
 
+        // Getting focus is all the attention a window can ask for
+        for focused in focused_windows.iter().filter(|window| window.urgent()) {
+            self.set_urgent(focused, false);
+        }
+
         for _ in self.outputs.iter() {
-            let workspace = &mut self.workspaces[0];
-            for focused in focused_windows.iter() {
+            let workspace = &mut self.workspaces[self.active];
+            for focused in focused_windows.iter().filter(|window| !unraised(window)) {
                 workspace.space.raise_window(focused, true);
             }
             for window in workspace.space.windows() {
-                window.set_activated(focused_windows.contains(window));
-                window.configure();
+                let activated = focused_windows.contains(window);
+                if !activated {
+                    set_unraised(window, false);
+                }
+                // Only windows whose activation changed get a configure
+                if window.set_activated(activated) {
+                    configure(window, |_| {});
+                }
             }
         }
     }
@@ -168,6 +208,17 @@ impl CommonState {
     ) {
         self.shell.set_focus(dh, surface, active_seat, serial);
         self.shell.update_active(self.seats.iter());
+
+        let window = surface.and_then(|surface| {
+            self.shell
+                .active_workspace()
+                .space
+                .window_for_surface(surface, WindowSurfaceType::ALL)
+                .cloned()
+        });
+        if let Some(window) = window {
+            self.restore_window_layout(dh, &window);
+        }
     }
 
     pub fn refresh_focus(&mut self, dh: &DisplayHandle) {
@@ -0,0 +1,144 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use smithay::{
+    desktop::{layer_map_for_output, Window},
+    wayland::{output::Output, seat::Seat},
+};
+
+use super::{grab::moved_windows, window::WindowExt, Shell};
+use crate::state::State;
+
+/// How often windows that can't be seen get frame callbacks with [`FramePolicy::Throttle`]
+const HIDDEN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When frame callbacks are sent to windows that are not visible
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FramePolicy {
+    /// Every window gets a frame callback every frame
+    Always,
+    /// Hidden windows get one frame callback per second
+    Throttle,
+    /// Hidden windows get no frame callbacks at all
+    Suspend,
+}
+
+impl Default for FramePolicy {
+    fn default() -> Self {
+        FramePolicy::Throttle
+    }
+}
+
+/// Why a window might not need frame callbacks.
+///
+/// Anything but `Visible` is what the `suspended` state of xdg_toplevel is meant for, but that
+/// came with xdg-shell version 6 and the pinned smithay and wayland-protocols stop at an older
+/// version. Until they are updated, withholding frame callbacks is the only hint clients get.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Visibility {
+    Visible,
+    /// Behind a fullscreen window
+    Occluded,
+    /// Outside of every output
+    Offscreen,
+    /// On a workspace that is not shown
+    OtherWorkspace,
+}
+
+#[derive(Default)]
+struct FrameState {
+    last_sent: Cell<Option<Instant>>,
+    throttle: Cell<Option<Duration>>,
+}
+
+impl FramePolicy {
+    fn interval(&self, visibility: Visibility) -> Option<Duration> {
+        match (self, visibility) {
+            (_, Visibility::Visible) | (FramePolicy::Always, _) => Some(Duration::ZERO),
+            (FramePolicy::Throttle, _) => Some(HIDDEN_INTERVAL),
+            (FramePolicy::Suspend, _) => None,
+        }
+    }
+}
+
+/// Limits a window to `fps` frame callbacks per second, regardless of the policy
+pub fn throttle_window(window: &Window, fps: Option<u32>) {
+    window.user_data().insert_if_missing(FrameState::default);
+    let interval = fps.map(|fps| Duration::from_secs(1) / fps.max(1));
+    window
+        .user_data()
+        .get::<FrameState>()
+        .unwrap()
+        .throttle
+        .set(interval);
+}
+
+impl Shell {
+    /// Sends frame callbacks to the windows on `output`, and to the ones on no output at all
+    pub fn send_frames(
+        &self,
+        output: &Output,
+        seats: &[Seat<State>],
+        policy: FramePolicy,
+        time: u32,
+    ) {
+        let now = Instant::now();
+        let active = self.active_workspace().idx;
+        for workspace in &self.workspaces {
+            let fullscreen = workspace.get_filling(output).map(|filling| filling.window);
+            for window in workspace.space.windows() {
+                let outputs = workspace.space.outputs_for_window(window);
+                if !outputs.is_empty() && !outputs.contains(output) {
+                    continue;
+                }
+
+                let visibility = if workspace.idx != active {
+                    Visibility::OtherWorkspace
+                } else if outputs.is_empty() {
+                    Visibility::Offscreen
+                } else if fullscreen.as_ref().map(|w| w != window).unwrap_or(false) {
+                    Visibility::Occluded
+                } else {
+                    Visibility::Visible
+                };
+                send_frame(window, policy.interval(visibility), now, time);
+            }
+        }
+
+        // Windows being moved are not in any space
+        for window in moved_windows(seats, output) {
+            send_frame(&window, policy.interval(Visibility::Visible), now, time);
+        }
+
+        for layer in layer_map_for_output(output).layers() {
+            layer.send_frame(time);
+        }
+    }
+}
+
+fn send_frame(window: &Window, interval: Option<Duration>, now: Instant, time: u32) {
+    window.user_data().insert_if_missing(FrameState::default);
+    let state = window.user_data().get::<FrameState>().unwrap();
+
+    let interval = match interval {
+        // Visible games skip the per-window limit, but still only get one per repaint
+        Some(Duration::ZERO) if window.game_mode() => Duration::ZERO,
+        Some(interval) => interval.max(state.throttle.get().unwrap_or_default()),
+        None => return,
+    };
+    let due = state
+        .last_sent
+        .get()
+        .map(|last| now.duration_since(last) >= interval)
+        .unwrap_or(true);
+    if due {
+        window.send_frame(time);
+        state.last_sent.set(Some(now));
+    }
+}
@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use smithay::{desktop::Window, utils::IsAlive, wayland::output::Output};
+
+/// Fullscreen windows by workspace and output name. The workspaces of a `Shell` share one, so a
+/// workspace that is switched away from still has its fullscreen windows when it is shown again.
+#[derive(Default, Clone)]
+pub struct FullscreenManager(Rc<RefCell<HashMap<(u8, String), Window>>>);
+
+impl FullscreenManager {
+    /// The fullscreen window of `workspace` on `output`
+    pub fn get(&self, workspace: u8, output: &Output) -> Option<Window> {
+        self.0
+            .borrow()
+            .get(&(workspace, output.name()))
+            .filter(|window| window.alive())
+            .cloned()
+    }
+
+    pub fn contains(&self, workspace: u8, window: &Window) -> bool {
+        self.0
+            .borrow()
+            .iter()
+            .any(|((idx, _), w)| *idx == workspace && w == window)
+    }
+
+    /// Makes `window` the fullscreen window of `workspace` on `output`, unless there already is
+    /// one
+    pub fn insert(&self, workspace: u8, output: &Output, window: &Window) -> bool {
+        let mut windows = self.0.borrow_mut();
+        let key = (workspace, output.name());
+        if windows.contains_key(&key) {
+            return false;
+        }
+        windows.insert(key, window.clone());
+        true
+    }
+
+    pub fn remove(&self, workspace: u8, window: &Window) {
+        self.0
+            .borrow_mut()
+            .retain(|(idx, _), w| *idx != workspace || w != window);
+    }
+
+    /// The fullscreen windows of `workspace` on outputs other than `outputs`
+    pub fn orphaned(&self, workspace: u8, outputs: &[Output]) -> Vec<Window> {
+        self.0
+            .borrow()
+            .iter()
+            .filter(|((idx, name), _)| {
+                *idx == workspace && !outputs.iter().any(|o| &o.name() == name)
+            })
+            .map(|(_, window)| window.clone())
+            .collect()
+    }
+
+    /// The fullscreen windows of `workspace`, with the name of their output
+    pub fn windows(&self, workspace: u8) -> Vec<(String, Window)> {
+        self.0
+            .borrow()
+            .iter()
+            .filter(|((idx, _), _)| *idx == workspace)
+            .map(|((_, name), window)| (name.clone(), window.clone()))
+            .collect()
+    }
+
+    pub fn cleanup(&self) {
+        self.0.borrow_mut().retain(|_, window| window.alive());
+    }
+}
@@ -1,8 +1,12 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
-use crate::state::{
-    output::{active_output, OutputExt},
-    State,
+use crate::{
+    backend::renderer::CustomElem,
+    shell::layout::FLOATING_INDEX,
+    state::{
+        output::{active_output, set_active_output, OutputExt},
+        State,
+    },
 };
 
 use super::Shell;
@@ -29,7 +33,11 @@ use smithay::{
         Serial,
     },
 };
-use std::cell::RefCell;
+use std::{
+    cell::RefCell,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
 
 impl Shell {
     pub fn move_request(
@@ -41,10 +49,12 @@ impl Shell {
     ) {
         // TODO touch grab
         if let Some(pointer) = seat.get_pointer() {
-            let workspace = self
-                .space_for_window_mut(window.toplevel().wl_surface())
-                .unwrap();
-            if workspace.fullscreen.values().any(|w| w == window) {
+            // Clients can ask for windows that are not mapped (anymore)
+            let workspace = match self.space_for_window_mut(window.toplevel().wl_surface()) {
+                Some(workspace) => workspace,
+                None => return,
+            };
+            if workspace.is_fullscreen(window) {
                 return;
             }
 
@@ -53,9 +63,12 @@ impl Shell {
                 .space
                 .outputs_for_window(&window)
                 .into_iter()
-                .find(|o| o.geometry().contains(pos.to_i32_round()))
-                .unwrap();
-            let mut initial_window_location = workspace.space.window_location(&window).unwrap();
+                .find(|o| o.geometry().contains(pos.to_i32_round()));
+            let (output, mut initial_window_location) =
+                match (output, workspace.space.window_location(&window)) {
+                    (Some(output), Some(location)) => (output, location),
+                    _ => return,
+                };
 
             let _ = match &window.toplevel() {
                 Kind::Xdg(surface) => {
@@ -66,7 +79,8 @@ impl Shell {
                             .layer
                             .unmaximize_request(&mut workspace.space, window);
                         let new_size = surface.with_pending_state(|state| state.size);
-                        let ratio = pos.x / output.geometry().size.w as f64;
+                        let output_geometry = output.geometry().to_f64();
+                        let ratio = (pos.x - output_geometry.loc.x) / output_geometry.size.w;
 
                         initial_window_location = new_size
                             .map(|size| (pos.x - (size.w as f64 * ratio), pos.y).into())
@@ -84,40 +98,76 @@ impl Shell {
                 window: window.clone(),
                 initial_cursor_location: pointer.current_location(),
                 initial_window_location,
+                zone: None,
             };
             let grab = MoveSurfaceGrab::new(start_data, window.clone(), seat);
 
-            *seat
-                .user_data()
-                .get::<SeatMoveGrabState>()
-                .unwrap()
-                .borrow_mut() = Some(state);
+            *move_grab_state(seat).borrow_mut() = Some(state);
             pointer.set_grab(grab, serial, Focus::Clear);
         }
     }
 
-    fn drop_move(&mut self, dh: &DisplayHandle, seat: &Seat<State>, _output: &Output) {
-        if let Some(move_state) = seat
-            .user_data()
-            .get::<SeatMoveGrabState>()
-            .unwrap()
-            .borrow_mut()
-            .take()
-        {
-            let pointer = seat.get_pointer().unwrap();
+    /// Lets `window` follow the pointer of `seat` with the pointer at `offset` in it, like a
+    /// move that leaves the pointer to a drag. Dropping it with `drop_move` maps it where it is.
+    pub(crate) fn carry_window(
+        &mut self,
+        window: &Window,
+        seat: &Seat<State>,
+        offset: Point<i32, Logical>,
+    ) {
+        let pointer = match seat.get_pointer() {
+            Some(pointer) => pointer,
+            None => return,
+        };
+        if let Some(workspace) = self.space_for_window_mut(window.toplevel().wl_surface()) {
+            if workspace.is_fullscreen(window) {
+                return;
+            }
+            workspace.layer.unmap_window(&mut workspace.space, window);
+        }
+
+        let cursor_location = pointer.current_location();
+        *move_grab_state(seat).borrow_mut() = Some(MoveGrabState {
+            window: window.clone(),
+            initial_cursor_location: cursor_location,
+            initial_window_location: cursor_location.to_i32_round() - offset,
+            zone: None,
+        });
+    }
+
+    pub(crate) fn drop_move(&mut self, dh: &DisplayHandle, seat: &Seat<State>, _output: &Output) {
+        if let Some(move_state) = move_grab_state(seat).borrow_mut().take() {
+            let pointer = match seat.get_pointer() {
+                Some(pointer) => pointer,
+                None => return,
+            };
+            let window_location = move_state
+                .window_location(pointer.current_location())
+                .to_i32_round();
             let window = move_state.window;
 
             if window.alive() {
-                let delta = pointer.current_location() - move_state.initial_cursor_location;
-                let window_location =
-                    (move_state.initial_window_location.to_f64() + delta).to_i32_round();
                 let surface = window.toplevel().wl_surface().clone();
+                // Carried windows can be dropped before their first buffer
+                self.pending_windows
+                    .retain(|(pending, _)| *pending != window);
 
                 let workspace = self.active_workspace_mut();
 
-                workspace
-                    .layer
-                    .map_window(&mut workspace.space, window, &seat, window_location);
+                match move_state.zone {
+                    Some((output, target)) => {
+                        workspace
+                            .layer
+                            .tile_window(&mut workspace.space, window, &output, target)
+                    }
+                    // The grab kept the active output of the seat under the pointer
+                    None => workspace.layer.map_window(
+                        &mut workspace.space,
+                        window,
+                        &seat,
+                        window_location,
+                    ),
+                }
 
                 self.set_focus(dh, Some(&surface), &seat, None);
             }
@@ -127,25 +177,90 @@ impl Shell {
 
 pub type SeatMoveGrabState = RefCell<Option<MoveGrabState>>;
 
+/// The move state of `seat`, inserted on first use for seats not created through `add_seat`
+fn move_grab_state(seat: &Seat<State>) -> &SeatMoveGrabState {
+    let userdata = seat.user_data();
+    userdata.insert_if_missing(SeatMoveGrabState::default);
+    userdata.get::<SeatMoveGrabState>().unwrap()
+}
+
+/// The window `seat` is moving, it is not mapped meanwhile
+pub fn grabbed_window(seat: &Seat<State>) -> Option<Window> {
+    seat.user_data()
+        .get::<SeatMoveGrabState>()
+        .and_then(|state| state.borrow().as_ref().map(|state| state.window.clone()))
+}
+
 pub struct MoveGrabState {
     window: Window,
     initial_cursor_location: Point<f64, Logical>,
     initial_window_location: Point<i32, Logical>,
+    /// The drop zone under the pointer, with the output it is on
+    pub zone: Option<(Output, Rectangle<i32, Logical>)>,
+}
+
+impl MoveGrabState {
+    /// Where the window is while the pointer is at `cursor_location`, in global coordinates
+    fn window_location(&self, cursor_location: Point<f64, Logical>) -> Point<f64, Logical> {
+        self.initial_window_location.to_f64() + (cursor_location - self.initial_cursor_location)
+    }
 }
 
 pub struct MoveGrabRenderElement {
-    seat_id: usize,
+    id: usize,
     window: Window,
+    /// Relative to the output it is drawn on
     window_location: Point<f64, Logical>,
 }
 
+/// The window `seat` moves and its location relative to `output`, if it is at least partly on it
+pub fn moved_window(seat: &Seat<State>, output: &Output) -> Option<(Window, Point<f64, Logical>)> {
+    let cursor_location = seat.get_pointer()?.current_location();
+    let move_state = move_grab_state(seat).borrow();
+    let move_state = move_state.as_ref().filter(|state| state.window.alive())?;
+
+    let window = &move_state.window;
+    let location = move_state.window_location(cursor_location);
+    let mut bbox = window.bbox_with_popups();
+    bbox.loc += location.to_i32_round() - window.geometry().loc;
+    let output_geometry = output.geometry();
+    bbox.overlaps(output_geometry)
+        .then(|| (window.clone(), location - output_geometry.loc.to_f64()))
+}
+
+/// The windows moved by `seats` that are at least partly on `output`
+pub fn moved_windows(seats: &[Seat<State>], output: &Output) -> Vec<Window> {
+    seats
+        .iter()
+        .filter_map(|seat| moved_window(seat, output))
+        .map(|(window, _)| window)
+        .collect()
+}
+
+/// Draws the windows moved by `seats` under the cursor, they are not in the space meanwhile
+pub fn move_grab_elements(seats: &[Seat<State>], output: &Output) -> Vec<CustomElem> {
+    let mut elements = Vec::new();
+    for (seat_index, seat) in seats.iter().enumerate() {
+        if let Some((window, window_location)) = moved_window(seat, output) {
+            let mut hasher = DefaultHasher::new();
+            ("move-grab", seat_index).hash(&mut hasher);
+            elements.push(CustomElem::from(MoveGrabRenderElement {
+                id: hasher.finish() as usize,
+                window,
+                window_location,
+            }));
+        }
+    }
+    elements
+}
+
 impl<R> RenderElement<R> for MoveGrabRenderElement
 where
     R: Renderer + ImportAll,
     <R as Renderer>::TextureId: 'static,
 {
     fn id(&self) -> usize {
-        self.seat_id
+        self.id
     }
 
     fn location(&self, scale: impl Into<Scale<f64>>) -> Point<f64, Physical> {
@@ -191,6 +306,11 @@ where
     ) -> Result<(), <R as Renderer>::Error> {
         draw_window(renderer, frame, &self.window, scale, position, damage, log)
     }
+
+    /// Above the windows that stayed in the space
+    fn z_index(&self) -> u8 {
+        FLOATING_INDEX + 1
+    }
 }
 
 pub struct MoveSurfaceGrab {
@@ -209,6 +329,18 @@ impl PointerGrab<State> for MoveSurfaceGrab {
     ) {
         // While the grab is active, no client has pointer focus
         handle.motion(event.location, None, event.serial, event.time);
+
+        // Drops land on the output under the pointer
+        if let Some(output) = state.common.shell.output_at(event.location) {
+            if output != &active_output(&self.seat, &state.common) {
+                set_active_output(&self.seat, output);
+            }
+        }
+
+        let zone = state.common.shell.drop_zone_at(event.location);
+        if let Some(move_state) = move_grab_state(&self.seat).borrow_mut().as_mut() {
+            move_state.zone = zone;
+        }
         if !self.window.alive() {
             self.ungrab(dh, state, handle, event.serial, event.time);
         }
@@ -270,6 +402,7 @@ impl MoveSurfaceGrab {
 
         state.common.event_loop_handle.insert_idle(move |data| {
             data.state.common.shell.drop_move(&dh, &seat, &output);
+            data.state.common.constrain_windows();
         });
         handle.unset_grab(serial, time);
     }
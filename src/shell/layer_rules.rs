@@ -0,0 +1,113 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use serde::{Deserialize, Serialize};
+use smithay::{
+    reexports::wayland_server::protocol::wl_surface::WlSurface,
+    wayland::{
+        compositor::with_states,
+        shell::wlr_layer::{ExclusiveZone, KeyboardInteractivity, Layer, LayerSurfaceCachedState},
+    },
+};
+
+use crate::runtime::registry::RuntimeLayerRule;
+
+/// A layer of the layer shell, as rules name it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayerName {
+    Background,
+    Bottom,
+    Top,
+    Overlay,
+}
+
+impl From<LayerName> for Layer {
+    fn from(name: LayerName) -> Self {
+        match name {
+            LayerName::Background => Layer::Background,
+            LayerName::Bottom => Layer::Bottom,
+            LayerName::Top => Layer::Top,
+            LayerName::Overlay => Layer::Overlay,
+        }
+    }
+}
+
+/// What a rule overrides of a layer surface, kept for as long as the surface lives
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LayerRule {
+    pub layer: Option<Layer>,
+    pub ignore_exclusive_zone: bool,
+    pub no_focus: bool,
+    /// Shown as a placeholder in screenshots, like private windows
+    pub private: bool,
+    /// Blur what is behind the surface, see [`crate::backend::renderer::blur`]
+    pub blur: bool,
+}
+
+/// Rules for layer surfaces by namespace, added by the committed generation of the runtime
+#[derive(Debug, Default)]
+pub struct LayerRules {
+    runtime: Vec<(String, LayerRule)>,
+}
+
+impl LayerRules {
+    /// Replaces every rule of the runtime
+    pub fn set_runtime(&mut self, rules: &[RuntimeLayerRule]) {
+        self.runtime = rules
+            .iter()
+            .map(|rule| {
+                let overrides = LayerRule {
+                    layer: rule.layer.map(Layer::from),
+                    ignore_exclusive_zone: rule.ignore_exclusive_zone,
+                    no_focus: rule.no_focus,
+                    private: rule.private,
+                    blur: rule.blur,
+                };
+                (rule.namespace.clone(), overrides)
+            })
+            .collect();
+    }
+
+    pub fn rule(&self, namespace: &str) -> Option<LayerRule> {
+        self.runtime
+            .iter()
+            .find(|(name, _)| name == namespace)
+            .map(|(_, rule)| *rule)
+    }
+}
+
+/// Gives the layer surface `surface` the overrides of `rule`, once when it is mapped
+pub fn set_layer_rule(surface: &WlSurface, rule: LayerRule) {
+    with_states(surface, |states| {
+        states.data_map.insert_if_missing_threadsafe(|| rule);
+    });
+    commit_layer_rule(surface);
+}
+
+/// The rule the layer surface `surface` was mapped with
+pub fn layer_rule(surface: &WlSurface) -> Option<LayerRule> {
+    with_states(surface, |states| {
+        states.data_map.get::<LayerRule>().copied()
+    })
+}
+
+/// Puts the overrides back in place after every commit, the state the client sent replaces them
+pub fn commit_layer_rule(surface: &WlSurface) {
+    with_states(surface, |states| {
+        let rule = match states.data_map.get::<LayerRule>() {
+            Some(rule) => rule,
+            None => return,
+        };
+        let mut state = states.cached_state.current::<LayerSurfaceCachedState>();
+        if let Some(layer) = rule.layer {
+            state.layer = layer;
+        }
+        if rule.ignore_exclusive_zone {
+            // Other surfaces still push it aside, it just doesn't reserve space itself
+            state.exclusive_zone = ExclusiveZone::Neutral;
+        }
+        if rule.no_focus {
+            state.keyboard_interactivity = KeyboardInteractivity::None;
+        }
+    });
+}
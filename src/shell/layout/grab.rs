@@ -1,7 +1,7 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use smithay::{
-    desktop::{Kind, Window},
+    desktop::{Space, Window},
     reexports::{
         wayland_protocols::xdg::shell::server::xdg_toplevel, wayland_server::DisplayHandle,
     },
@@ -13,12 +13,15 @@ use smithay::{
             PointerInnerHandle,
         },
         shell::xdg::SurfaceCachedState,
-        Serial,
     },
 };
 use std::{cell::RefCell, convert::TryFrom};
 
-use crate::state::State;
+use super::FLOATING_INDEX;
+use crate::{
+    shell::configure::{configure, on_ack},
+    state::State,
+};
 
 bitflags::bitflags! {
     struct ResizeEdge: u32 {
@@ -65,12 +68,10 @@ enum ResizeState {
     /// The surface is not being resized.
     NotResizing,
     /// The surface is currently being resized.
-    #[allow(dead_code)]
     Resizing(ResizeData),
     /// The resize has finished, and the surface needs to ack the final configure.
-    WaitingForFinalAck(ResizeData, Serial),
+    WaitingForFinalAck(ResizeData),
     /// The resize has finished, and the surface needs to commit its final state.
-    #[allow(dead_code)]
     WaitingForCommit(ResizeData),
 }
 
@@ -88,6 +89,36 @@ pub struct ResizeSurfaceGrab {
     last_window_size: Size<i32, Logical>,
 }
 
+impl ResizeSurfaceGrab {
+    pub fn start(
+        start_data: PointerGrabStartData,
+        window: Window,
+        edges: xdg_toplevel::ResizeEdge,
+        initial_window_location: Point<i32, Logical>,
+        initial_window_size: Size<i32, Logical>,
+    ) -> ResizeSurfaceGrab {
+        let edges = ResizeEdge::from(edges);
+        let user_data = window.user_data();
+        user_data.insert_if_missing(|| RefCell::new(ResizeState::default()));
+        *user_data
+            .get::<RefCell<ResizeState>>()
+            .unwrap()
+            .borrow_mut() = ResizeState::Resizing(ResizeData {
+            edges,
+            initial_window_location,
+            initial_window_size,
+        });
+
+        ResizeSurfaceGrab {
+            start_data,
+            window,
+            edges,
+            initial_window_size,
+            last_window_size: initial_window_size,
+        }
+    }
+}
+
 impl PointerGrab<State> for ResizeSurfaceGrab {
     fn motion(
         &mut self,
@@ -152,20 +183,16 @@ impl PointerGrab<State> for ResizeSurfaceGrab {
 
         self.last_window_size = (new_window_width, new_window_height).into();
 
-        match &self.window.toplevel() {
-            Kind::Xdg(xdg) => {
-                xdg.with_pending_state(|state| {
-                    state.states.set(xdg_toplevel::State::Resizing);
-                    state.size = Some(self.last_window_size);
-                });
-                xdg.send_configure();
-            }
-        }
+        let size = self.last_window_size;
+        configure(&self.window, |state| {
+            state.states.set(xdg_toplevel::State::Resizing);
+            state.size = Some(size);
+        });
     }
 
     fn button(
         &mut self,
-        _data: &mut State,
+        data: &mut State,
         _dh: &DisplayHandle,
         handle: &mut PointerInnerHandle<'_, State>,
         event: &ButtonEvent,
@@ -180,26 +207,29 @@ impl PointerGrab<State> for ResizeSurfaceGrab {
                 return;
             }
 
-            #[allow(irrefutable_let_patterns)]
-            if let Kind::Xdg(xdg) = &self.window.toplevel() {
-                xdg.with_pending_state(|state| {
-                    state.states.unset(xdg_toplevel::State::Resizing);
-                    state.size = Some(self.last_window_size);
-                });
-                xdg.send_configure();
-            }
+            let size = self.last_window_size;
+            configure(&self.window, |state| {
+                state.states.unset(xdg_toplevel::State::Resizing);
+                state.size = Some(size);
+            });
 
-            let mut resize_state = self
-                .window
-                .user_data()
-                .get::<RefCell<ResizeState>>()
-                .unwrap()
-                .borrow_mut();
-            if let ResizeState::Resizing(resize_data) = *resize_state {
-                *resize_state = ResizeState::WaitingForFinalAck(resize_data, event.serial);
-            } else {
-                panic!("invalid resize state: {:?}", resize_state);
+            if let Some(resize_state) = self.window.user_data().get::<RefCell<ResizeState>>() {
+                let mut resize_state = resize_state.borrow_mut();
+                if let ResizeState::Resizing(resize_data) = *resize_state {
+                    *resize_state = ResizeState::WaitingForFinalAck(resize_data);
+                    on_ack(&self.window, |_, window| {
+                        let resize_state = window.user_data().get::<RefCell<ResizeState>>();
+                        let mut resize_state = resize_state.unwrap().borrow_mut();
+                        if let ResizeState::WaitingForFinalAck(resize_data) = *resize_state {
+                            *resize_state = ResizeState::WaitingForCommit(resize_data);
+                        }
+                    });
+                } else {
+                    slog_scope::warn!("Resize grab ended in state {:?}", resize_state);
+                }
             }
+
+            data.common.constrain_windows();
         }
     }
 
@@ -217,3 +247,41 @@ impl PointerGrab<State> for ResizeSurfaceGrab {
         &self.start_data
     }
 }
+
+/// Keeps the edges opposite of the ones being dragged in place as the client commits new sizes,
+/// until it committed the size of the final configure
+pub fn resize_commit(space: &mut Space, window: &Window) {
+    let resize_state = match window.user_data().get::<RefCell<ResizeState>>() {
+        Some(resize_state) => resize_state,
+        None => return,
+    };
+    let mut resize_state = resize_state.borrow_mut();
+    let resize_data = match *resize_state {
+        ResizeState::Resizing(resize_data) | ResizeState::WaitingForFinalAck(resize_data) => {
+            resize_data
+        }
+        ResizeState::WaitingForCommit(resize_data) => {
+            *resize_state = ResizeState::NotResizing;
+            resize_data
+        }
+        ResizeState::NotResizing => return,
+    };
+    if !resize_data.edges.intersects(ResizeEdge::TOP_LEFT) {
+        return;
+    }
+
+    let size = window.geometry().size;
+    let mut location = match space.window_location(window) {
+        Some(location) => location,
+        None => return,
+    };
+    if resize_data.edges.intersects(ResizeEdge::LEFT) {
+        location.x =
+            resize_data.initial_window_location.x + resize_data.initial_window_size.w - size.w;
+    }
+    if resize_data.edges.intersects(ResizeEdge::TOP) {
+        location.y =
+            resize_data.initial_window_location.y + resize_data.initial_window_size.h - size.h;
+    }
+    space.map_window(window, location, FLOATING_INDEX, false);
+}
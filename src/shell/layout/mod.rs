@@ -1,21 +1,47 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
+use serde::Deserialize;
 use smithay::{
     desktop::{layer_map_for_output, space::RenderZindex, Kind, Space, Window},
-    reexports::wayland_protocols::xdg::shell::server::xdg_toplevel::State as XdgState,
+    reexports::{
+        wayland_protocols::xdg::shell::server::xdg_toplevel::{ResizeEdge, State as XdgState},
+        wayland_server::backend::ObjectId,
+    },
     utils::{Logical, Point, Rectangle},
     wayland::{
-        compositor::with_states, output::Output, seat::Seat,
+        compositor::with_states,
+        output::Output,
+        seat::{Focus, PointerGrabStartData, Seat},
         shell::xdg::XdgToplevelSurfaceRoleAttributes,
+        Serial,
     },
 };
-use std::{collections::HashSet, sync::Mutex};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    sync::Mutex,
+};
 
-use crate::state::{output::ActiveOutput, State};
+use crate::{
+    shell::{
+        configure::{configure, set_bounds, TiledEdges},
+        window::WindowExt,
+    },
+    state::{
+        output::{ActiveOutput, OutputExt},
+        State,
+    },
+};
+
+pub use self::grab::resize_commit;
+use self::grab::ResizeSurfaceGrab;
 
 mod grab;
+pub mod zones;
 
 pub const FLOATING_INDEX: u8 = RenderZindex::Shell as u8 + 1;
+/// Where shaded windows are kept in the space, so they are neither drawn nor under the pointer
+const SHADED_LOCATION: (i32, i32) = (-1_000_000, -1_000_000);
 
 #[derive(Debug, Default)]
 pub struct Layout {
@@ -26,6 +52,8 @@ pub struct Layout {
 #[derive(Default)]
 pub struct WindowUserDataInner {
     last_geometry: Rectangle<i32, Logical>,
+    /// Placed into a tile, whether or not any of its edges touch something
+    tiled: bool,
 }
 pub type WindowUserData = Mutex<WindowUserDataInner>;
 
@@ -60,13 +88,18 @@ impl Layout {
             .get::<WindowUserData>()
             .map(|u| u.lock().unwrap().last_geometry);
         let mut win_geo = window.geometry();
+        window.set_shaded(None);
 
-        let layers = layer_map_for_output(&output);
-        let geometry = layers.non_exclusive_zone();
+        let geometry = usable_zone(output);
 
         let mut geo_updated = false;
-        if let Some(size) = last_geometry.clone().map(|g| g.size) {
-            geo_updated = win_geo.size == size;
+        // Also gives windows leaving a tile or maximized state their floating size back
+        if let Some(size) = last_geometry
+            .clone()
+            .map(|g| g.size)
+            .filter(|size| size.w > 0 && size.h > 0)
+        {
+            geo_updated = true;
             win_geo.size = size;
         }
         {
@@ -121,48 +154,258 @@ impl Layout {
                     .into()
             });
 
-        #[allow(irrefutable_let_patterns)]
-        if let Kind::Xdg(xdg) = &window.toplevel() {
-            xdg.with_pending_state(|state| {
-                state.states.unset(XdgState::TiledLeft);
-                state.states.unset(XdgState::TiledRight);
-                state.states.unset(XdgState::TiledTop);
-                state.states.unset(XdgState::TiledBottom);
-                if geo_updated {
-                    state.size = Some(win_geo.size);
-                }
-            });
-            xdg.send_configure();
-        }
+        set_bounds(&window, output);
+        set_tiled(&window, false);
+        configure(&window, |state| {
+            TiledEdges::default().apply(state);
+            if geo_updated {
+                state.size = Some(win_geo.size);
+            }
+        });
 
         space.map_window(&window, position, FLOATING_INDEX, false);
         self.windows.insert(window);
     }
 
-    pub fn unmap_window(&mut self, space: &mut Space, window: &Window) {
-        #[allow(irrefutable_let_patterns)]
-        let is_maximized = match &window.toplevel() {
-            Kind::Xdg(surface) => {
-                surface.with_pending_state(|state| state.states.contains(XdgState::Maximized))
-            }
+    /// Maps `window` into the `target` rectangle of `output`, like a drop zone
+    pub fn tile_window(
+        &mut self,
+        space: &mut Space,
+        window: Window,
+        output: &Output,
+        target: Rectangle<i32, Logical>,
+    ) {
+        let output_loc = match space.output_geometry(output) {
+            Some(geometry) => geometry.loc,
+            None => return,
         };
+        self.unshade_window(space, &window);
+        // Remembered for when it leaves the tile, unless it was tiled or maximized already
+        if !is_tiled(&window) && !is_maximized(&window) {
+            store_last_geometry(space, &window);
+        }
+        set_bounds(&window, output);
+        configure(&window, |state| {
+            state.states.unset(XdgState::Maximized);
+            state.size = Some(target.size);
+        });
+        space.map_window(&window, output_loc + target.loc, FLOATING_INDEX, true);
+        set_tiled(&window, true);
+        self.windows.insert(window);
+        self.update_tiled_edges(space, output);
+    }
 
-        if !is_maximized {
-            if let Some(location) = space.window_location(window) {
-                let user_data = window.user_data();
-                user_data.insert_if_missing(|| WindowUserData::default());
-                user_data
-                    .get::<WindowUserData>()
-                    .unwrap()
-                    .lock()
-                    .unwrap()
-                    .last_geometry = Rectangle::from_loc_and_size(location, window.geometry().size);
-            }
+    /// Tells every tile on `output` which of its edges touch the border of the usable zone or
+    /// another tile, after one came or went
+    fn update_tiled_edges(&self, space: &Space, output: &Output) {
+        let output_loc = match space.output_geometry(output) {
+            Some(geometry) => geometry.loc,
+            None => return,
+        };
+        // Relative to the output like the usable zone, at the size they were last asked for
+        let tiles = self
+            .windows
+            .iter()
+            .filter(|window| is_tiled(window))
+            .filter(|window| space.outputs_for_window(window).contains(output))
+            .filter_map(|window| {
+                let location = space.window_location(window)?;
+                let size = match window.toplevel() {
+                    Kind::Xdg(surface) => surface.with_pending_state(|state| state.size),
+                };
+                let size = size.unwrap_or_else(|| window.geometry().size);
+                Some((
+                    window,
+                    Rectangle::from_loc_and_size(location - output_loc, size),
+                ))
+            })
+            .collect::<Vec<_>>();
+        let zone = usable_zone(output);
+        for (window, tile) in &tiles {
+            let neighbours = tiles
+                .iter()
+                .filter(|(other, _)| other != window)
+                .map(|(_, other)| *other)
+                .collect::<Vec<_>>();
+            let edges = TiledEdges::of(*tile, zone, &neighbours);
+            configure(window, |state| edges.apply(state));
         }
+    }
+
+    pub fn unmap_window(&mut self, space: &mut Space, window: &Window) {
+        self.unshade_window(space, window);
+        // Tiled windows keep the floating geometry from before they were tiled, they get it back
+        // when they are mapped again
+        let tiled = is_tiled(window);
+        if !is_maximized(window) && !tiled {
+            store_last_geometry(space, window);
+        }
+        let outputs = space.outputs_for_window(window);
 
         space.unmap_window(window);
         self.pending_windows.retain(|w| w != window);
         self.windows.remove(window);
+        // Its neighbours lose an edge
+        if tiled {
+            set_tiled(window, false);
+            for output in &outputs {
+                self.update_tiled_edges(space, output);
+            }
+        }
+    }
+
+    pub fn maximize_request(&mut self, space: &mut Space, window: &Window, output: &Output) {
+        let output_loc = match space.output_geometry(output) {
+            Some(geometry) => geometry.loc,
+            None => return,
+        };
+        let zone = usable_zone(output);
+        self.unshade_window(space, window);
+        store_last_geometry(space, window);
+
+        set_bounds(window, output);
+        configure(window, |state| {
+            state.states.set(XdgState::Maximized);
+            state.size = Some(zone.size);
+        });
+        space.map_window(window, output_loc + zone.loc, FLOATING_INDEX, true);
+    }
+
+    /// Fits the maximized windows on `output` into its usable zone again, after the output
+    /// changed size, and updates the bounds of every window on it
+    pub fn rearrange_output(&mut self, space: &mut Space, output: &Output) {
+        let output_loc = match space.output_geometry(output) {
+            Some(geometry) => geometry.loc,
+            None => return,
+        };
+        let zone = usable_zone(output);
+        // Maximized windows start inside the zone, so that corner is still on the output
+        let on_output = space
+            .windows()
+            .filter(|window| {
+                space
+                    .window_location(window)
+                    .map(|location| space.output_under(location.to_f64()).any(|o| o == output))
+                    .unwrap_or(false)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for window in &on_output {
+            set_bounds(window, output);
+        }
+
+        for window in on_output.into_iter().filter(is_maximized) {
+            configure(&window, |state| state.size = Some(zone.size));
+            space.map_window(&window, output_loc + zone.loc, FLOATING_INDEX, false);
+        }
+    }
+
+    /// Moves windows back until `min_visible` logical pixels of them and their top edge are in
+    /// the usable zone of one of `outputs`, onto the closest one. They stay at the layer of the
+    /// space `z_index` returns for them.
+    pub fn constrain_windows(
+        &mut self,
+        space: &mut Space,
+        outputs: &[Output],
+        min_visible: i32,
+        skip: &[Window],
+        z_index: impl Fn(&Window) -> u8,
+    ) {
+        if min_visible <= 0 || outputs.is_empty() {
+            return;
+        }
+        let zones = outputs
+            .iter()
+            .map(|output| {
+                let mut zone = usable_zone(output);
+                zone.loc += output.geometry().loc;
+                zone
+            })
+            .collect::<Vec<_>>();
+
+        let windows = self
+            .windows
+            .iter()
+            .filter(|window| !skip.contains(window) && !is_maximized(window))
+            .filter(|window| window.shaded().is_none())
+            .cloned()
+            .collect::<Vec<_>>();
+        for window in windows {
+            let location = match space.window_location(&window) {
+                Some(location) => location,
+                None => continue,
+            };
+            // Only the window geometry counts as visible, not the shadows around it
+            let geometry = match space.window_geometry(&window) {
+                Some(geometry) => geometry,
+                None => continue,
+            };
+            if zones
+                .iter()
+                .any(|zone| constrained(geometry, *zone, min_visible) == geometry.loc)
+            {
+                continue;
+            }
+
+            let center = geometry.loc + geometry.size.to_point().downscale(2);
+            let zone = zones
+                .iter()
+                .min_by_key(|zone| distance_squared(center, **zone))
+                .unwrap();
+            let new_location =
+                location + (constrained(geometry, *zone, min_visible) - geometry.loc);
+            slog_scope::debug!(
+                "Moving window from {:?} to {:?} to keep it on screen",
+                location,
+                new_location
+            );
+            space.map_window(&window, new_location, z_index(&window), false);
+        }
+    }
+
+    /// Collapses `window` to a bar with its title where it is. The window stays mapped, but out
+    /// of sight, so it counts as hidden for frame callbacks.
+    pub fn shade_window(&mut self, space: &mut Space, window: &Window) {
+        if window.shaded().is_some() {
+            return;
+        }
+        if let Some(location) = space.window_location(window) {
+            window.set_shaded(Some(location));
+            space.map_window(window, SHADED_LOCATION, FLOATING_INDEX, false);
+        }
+    }
+
+    /// Puts a shaded window back where its bar is
+    pub fn unshade_window(&mut self, space: &mut Space, window: &Window) {
+        if let Some(location) = window.shaded() {
+            window.set_shaded(None);
+            space.map_window(window, location, FLOATING_INDEX, true);
+        }
+    }
+
+    pub fn resize_request(
+        &mut self,
+        space: &Space,
+        window: &Window,
+        seat: &Seat<State>,
+        serial: Serial,
+        start_data: PointerGrabStartData,
+        edges: ResizeEdge,
+    ) {
+        let pointer = match seat.get_pointer() {
+            Some(pointer) => pointer,
+            None => return,
+        };
+        if let Some(location) = space.window_location(window) {
+            let grab = ResizeSurfaceGrab::start(
+                start_data,
+                window.clone(),
+                edges,
+                location,
+                window.geometry().size,
+            );
+            pointer.set_grab(grab, serial, Focus::Clear);
+        }
     }
 
     pub fn unmaximize_request(&mut self, space: &mut Space, window: &Window) {
@@ -170,21 +413,170 @@ impl Layout {
             .user_data()
             .get::<WindowUserData>()
             .map(|u| u.lock().unwrap().last_geometry);
-        match window.toplevel() {
-            Kind::Xdg(toplevel) => {
-                toplevel.with_pending_state(|state| {
-                    state.states.unset(XdgState::Maximized);
-                    state.size = last_geometry.map(|g| g.size);
-                });
-                toplevel.send_configure();
-            }
-        }
+        configure(window, |state| {
+            state.states.unset(XdgState::Maximized);
+            state.size = last_geometry.map(|g| g.size);
+        });
         if let Some(last_location) = last_geometry.map(|g| g.loc) {
             space.map_window(&window, last_location, FLOATING_INDEX, true);
         }
     }
 }
 
+/// Areas of an output reserved through `electrum_shell_v1`, in output coordinates
+#[derive(Default)]
+struct ReservedAreas(RefCell<Vec<(ObjectId, Rectangle<i32, Logical>)>>);
+
+pub fn reserve_area(output: &Output, id: ObjectId, area: Rectangle<i32, Logical>) {
+    output.user_data().insert_if_missing(ReservedAreas::default);
+    let areas = output.user_data().get::<ReservedAreas>().unwrap();
+    areas.0.borrow_mut().push((id, area));
+}
+
+pub fn release_area(output: &Output, id: &ObjectId) {
+    if let Some(areas) = output.user_data().get::<ReservedAreas>() {
+        areas.0.borrow_mut().retain(|(area_id, _)| area_id != id);
+    }
+}
+
+/// Space kept free along the edges of an output, for bars the runtime draws itself
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Struts {
+    pub top: i32,
+    pub bottom: i32,
+    pub left: i32,
+    pub right: i32,
+}
+
+#[derive(Default)]
+struct OutputStruts(Cell<Struts>);
+
+/// Replaces the struts of `output`
+pub fn set_struts(output: &Output, struts: Struts) {
+    output.user_data().insert_if_missing(OutputStruts::default);
+    output
+        .user_data()
+        .get::<OutputStruts>()
+        .unwrap()
+        .0
+        .set(struts);
+}
+
+/// The part of `output` windows are placed in: what layer surfaces and struts leave over, cut
+/// down by every reserved area on the side that keeps the most space
+pub fn usable_zone(output: &Output) -> Rectangle<i32, Logical> {
+    let mut zone = layer_map_for_output(output).non_exclusive_zone();
+    if let Some(struts) = output.user_data().get::<OutputStruts>() {
+        // Struts only cut into the zone where it reaches them
+        let struts = struts.0.get();
+        let size = output.geometry().size;
+        let left = zone.loc.x.max(struts.left);
+        let top = zone.loc.y.max(struts.top);
+        let right = (zone.loc.x + zone.size.w).min(size.w - struts.right);
+        let bottom = (zone.loc.y + zone.size.h).min(size.h - struts.bottom);
+        zone = Rectangle::from_extemities((left, top), (right.max(left), bottom.max(top)));
+    }
+    let areas = match output.user_data().get::<ReservedAreas>() {
+        Some(areas) => areas.0.borrow().clone(),
+        None => return zone,
+    };
+
+    for (_, area) in areas {
+        if !zone.overlaps(area) {
+            continue;
+        }
+        let (zone_right, zone_bottom) = (zone.loc.x + zone.size.w, zone.loc.y + zone.size.h);
+        let (area_right, area_bottom) = (area.loc.x + area.size.w, area.loc.y + area.size.h);
+        let candidates = [
+            Rectangle::from_extemities((area_right, zone.loc.y), (zone_right, zone_bottom)),
+            Rectangle::from_extemities(zone.loc, (area.loc.x, zone_bottom)),
+            Rectangle::from_extemities((zone.loc.x, area_bottom), (zone_right, zone_bottom)),
+            Rectangle::from_extemities(zone.loc, (zone_right, area.loc.y)),
+        ];
+        if let Some(best) = candidates
+            .into_iter()
+            .filter(|rect| rect.size.w > 0 && rect.size.h > 0)
+            .max_by_key(|rect| rect.size.w * rect.size.h)
+        {
+            zone = best;
+        }
+    }
+    zone
+}
+
+/// The closest location of `geometry` that keeps `min_visible` pixels of it in `zone`, with its
+/// top edge inside so the titlebar can be grabbed
+fn constrained(
+    geometry: Rectangle<i32, Logical>,
+    zone: Rectangle<i32, Logical>,
+    min_visible: i32,
+) -> Point<i32, Logical> {
+    let visible_w = min_visible.min(geometry.size.w);
+    let visible_h = min_visible.min(geometry.size.h);
+    let x = geometry
+        .loc
+        .x
+        .min(zone.loc.x + zone.size.w - visible_w)
+        .max(zone.loc.x - geometry.size.w + visible_w);
+    let y = geometry
+        .loc
+        .y
+        .min(zone.loc.y + zone.size.h - visible_h)
+        .max(zone.loc.y);
+    (x, y).into()
+}
+
+fn distance_squared(point: Point<i32, Logical>, rect: Rectangle<i32, Logical>) -> i64 {
+    let dx = (rect.loc.x - point.x)
+        .max(point.x - (rect.loc.x + rect.size.w))
+        .max(0) as i64;
+    let dy = (rect.loc.y - point.y)
+        .max(point.y - (rect.loc.y + rect.size.h))
+        .max(0) as i64;
+    dx * dx + dy * dy
+}
+
+fn is_maximized(window: &Window) -> bool {
+    match window.toplevel() {
+        Kind::Xdg(surface) => {
+            surface.with_pending_state(|state| state.states.contains(XdgState::Maximized))
+        }
+    }
+}
+
+fn is_tiled(window: &Window) -> bool {
+    window
+        .user_data()
+        .get::<WindowUserData>()
+        .map(|data| data.lock().unwrap().tiled)
+        .unwrap_or(false)
+}
+
+fn set_tiled(window: &Window, tiled: bool) {
+    let user_data = window.user_data();
+    user_data.insert_if_missing(WindowUserData::default);
+    user_data
+        .get::<WindowUserData>()
+        .unwrap()
+        .lock()
+        .unwrap()
+        .tiled = tiled;
+}
+
+fn store_last_geometry(space: &Space, window: &Window) {
+    if let Some(location) = space.window_location(window) {
+        let user_data = window.user_data();
+        user_data.insert_if_missing(|| WindowUserData::default());
+        user_data
+            .get::<WindowUserData>()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .last_geometry = Rectangle::from_loc_and_size(location, window.geometry().size);
+    }
+}
+
 fn output_from_seat(seat: Option<&Seat<State>>, space: &Space) -> Option<Output> {
     seat.and_then(|seat| {
         seat.user_data()
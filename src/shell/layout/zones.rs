@@ -0,0 +1,142 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    cell::RefCell,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use serde::Deserialize;
+use smithay::{
+    desktop::space::RenderZindex,
+    utils::{Logical, Point, Rectangle},
+    wayland::{output::Output, seat::Seat},
+};
+
+use crate::{
+    backend::renderer::{element::SolidColorElement, CustomElem},
+    shell::{grab::SeatMoveGrabState, Shell},
+    state::{output::OutputExt, State},
+};
+
+/// Outline of the zone a moved window would be dropped into
+const PREVIEW_COLOR: [f32; 4] = [0.35, 0.55, 0.95, 1.0];
+const PREVIEW_BORDER: i32 = 4;
+
+/// Logical rectangle relative to an output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct ZoneRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl From<ZoneRect> for Rectangle<i32, Logical> {
+    fn from(rect: ZoneRect) -> Self {
+        Rectangle::from_loc_and_size((rect.x, rect.y), (rect.width, rect.height))
+    }
+}
+
+/// Where windows can be dropped while they are moved, in logical coordinates relative to the
+/// output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct DropZone {
+    /// The area the pointer has to be in
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    /// Where the window goes, the area itself if not set
+    #[serde(default)]
+    pub target: Option<ZoneRect>,
+}
+
+impl DropZone {
+    /// Zones and targets without a size can't be dropped into
+    pub fn has_size(&self) -> bool {
+        let target = self.target();
+        self.width > 0 && self.height > 0 && target.size.w > 0 && target.size.h > 0
+    }
+
+    fn area(&self) -> Rectangle<i32, Logical> {
+        Rectangle::from_loc_and_size((self.x, self.y), (self.width, self.height))
+    }
+
+    fn target(&self) -> Rectangle<i32, Logical> {
+        self.target.map(Into::into).unwrap_or_else(|| self.area())
+    }
+}
+
+#[derive(Default)]
+struct OutputDropZones(RefCell<Vec<DropZone>>);
+
+/// Replaces the drop zones of `output`, earlier zones win where they overlap
+pub fn set_drop_zones(output: &Output, zones: Vec<DropZone>) {
+    output
+        .user_data()
+        .insert_if_missing(OutputDropZones::default);
+    *output
+        .user_data()
+        .get::<OutputDropZones>()
+        .unwrap()
+        .0
+        .borrow_mut() = zones;
+}
+
+impl Shell {
+    /// The output and target of the drop zone at `location`, in global coordinates
+    pub fn drop_zone_at(
+        &self,
+        location: Point<f64, Logical>,
+    ) -> Option<(Output, Rectangle<i32, Logical>)> {
+        let output = self.output_at(location)?;
+        let zones = output.user_data().get::<OutputDropZones>()?;
+        let relative = location - output.geometry().loc.to_f64();
+        let target = zones
+            .0
+            .borrow()
+            .iter()
+            .find(|zone| zone.area().to_f64().contains(relative))
+            .map(DropZone::target)?;
+        Some((output.clone(), target))
+    }
+}
+
+/// Outlines of the drop zones windows are moved over on `output`
+pub fn drop_zone_elements(seats: &[Seat<State>], output: &Output) -> Vec<CustomElem> {
+    let mut elements = Vec::new();
+    for (seat_index, seat) in seats.iter().enumerate() {
+        let zone = seat
+            .user_data()
+            .get::<SeatMoveGrabState>()
+            .and_then(|state| state.borrow().as_ref().and_then(|state| state.zone.clone()));
+        let target = match zone {
+            Some((zone_output, target)) if &zone_output == output => target,
+            _ => continue,
+        };
+
+        let (loc, size) = (target.loc, target.size);
+        let border = PREVIEW_BORDER.min(size.w / 2).min(size.h / 2);
+        let edges = [
+            Rectangle::from_loc_and_size(loc, (size.w, border)),
+            Rectangle::from_loc_and_size((loc.x, loc.y + size.h - border), (size.w, border)),
+            Rectangle::from_loc_and_size((loc.x, loc.y + border), (border, size.h - 2 * border)),
+            Rectangle::from_loc_and_size(
+                (loc.x + size.w - border, loc.y + border),
+                (border, size.h - 2 * border),
+            ),
+        ];
+        for (index, edge) in edges.into_iter().enumerate() {
+            let mut hasher = DefaultHasher::new();
+            ("drop-zone", seat_index, index).hash(&mut hasher);
+            elements.push(CustomElem::from(SolidColorElement::with_id(
+                hasher.finish() as usize,
+                edge,
+                PREVIEW_COLOR,
+                RenderZindex::Overlay as u8,
+            )));
+        }
+    }
+    elements
+}
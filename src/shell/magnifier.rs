@@ -0,0 +1,83 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use smithay::{
+    desktop::Window,
+    utils::{Logical, Point},
+    wayland::output::Output,
+};
+
+use super::{window::WindowExt, workspace::Workspace, Shell};
+
+/// How a magnified window is drawn onto its output, scaled up as far as it fits and centered
+/// with the rest of the output left black
+pub struct Magnification {
+    pub window: Window,
+    /// Factor the window is scaled up by
+    pub zoom: f64,
+    /// Where the origin of the window surface ends up, relative to the output
+    pub location: Point<f64, Logical>,
+}
+
+impl Magnification {
+    /// `position` relative to the output, in coordinates relative to the window surface
+    pub fn to_window(&self, position: Point<f64, Logical>) -> Point<f64, Logical> {
+        (position - self.location).downscale(self.zoom)
+    }
+}
+
+impl Workspace {
+    /// The window filling `output`, either fullscreen at its size or magnified
+    pub fn get_filling(&self, output: &Output) -> Option<Magnification> {
+        match self.get_fullscreen(output) {
+            Some(window) => Some(Magnification {
+                window,
+                zoom: 1.0,
+                location: (0.0, 0.0).into(),
+            }),
+            None => self.get_magnified(output),
+        }
+    }
+
+    /// The magnified window on `output`, a fullscreen window is shown instead of it
+    pub fn get_magnified(&self, output: &Output) -> Option<Magnification> {
+        if self.get_fullscreen(output).is_some() {
+            return None;
+        }
+        let output_geometry = self.space.output_geometry(output)?.to_f64();
+        let window = self
+            .space
+            .windows()
+            .filter(|window| window.magnified())
+            .find(|window| self.space.outputs_for_window(window).contains(output))?;
+
+        let geometry = window.geometry().to_f64();
+        if geometry.size.w <= 0.0 || geometry.size.h <= 0.0 {
+            return None;
+        }
+        let zoom = (output_geometry.size.w / geometry.size.w)
+            .min(output_geometry.size.h / geometry.size.h);
+        let letterbox = Point::from((
+            (output_geometry.size.w - geometry.size.w * zoom) / 2.0,
+            (output_geometry.size.h - geometry.size.h * zoom) / 2.0,
+        ));
+        Some(Magnification {
+            window: window.clone(),
+            zoom,
+            location: letterbox - geometry.loc.upscale(zoom),
+        })
+    }
+}
+
+impl Shell {
+    /// Fills the output of `window` with it or goes back to the desktop, only one window of the
+    /// active workspace is magnified at a time
+    pub fn set_magnified(&mut self, window: &Window, magnified: bool) {
+        let space = &self.active_workspace().space;
+        if magnified {
+            for other in space.windows().filter(|other| *other != window) {
+                other.set_magnified(false);
+            }
+        }
+        window.set_magnified(magnified);
+    }
+}
@@ -1,8 +1,10 @@
+use std::sync::Mutex;
+
 use calloop::channel::Sender;
 use smithay::{
     desktop::{layer_map_for_output, LayerSurface, PopupManager, Window, WindowSurfaceType},
     reexports::wayland_server::{protocol::wl_surface::WlSurface, DisplayHandle},
-    utils::{Coordinate, Logical, Point},
+    utils::{Coordinate, Logical, Point, Rectangle},
     wayland::{
         compositor::with_states,
         output::Output,
@@ -11,27 +13,78 @@ use smithay::{
             wlr_layer::{
                 KeyboardInteractivity, Layer, LayerSurfaceCachedState, WlrLayerShellState,
             },
-            xdg::{PopupSurface, PositionerState, XdgShellState},
+            xdg::{PopupSurface, PositionerState, XdgShellState, XdgToplevelSurfaceRoleAttributes},
         },
     },
 };
 
+pub mod autohide;
+pub mod configure;
+pub mod cycle;
+pub mod decisions;
+pub mod direction;
+#[cfg(test)]
+pub mod fixture;
 pub mod focus;
+pub mod frames;
+pub mod fullscreen;
 pub mod grab;
+pub mod layer_rules;
 pub mod layout;
+pub mod magnifier;
+pub mod pip;
+pub mod reconnect;
+pub mod rules;
+pub mod switcher;
+pub mod transition;
+pub mod window;
 pub mod workspace;
 
-use crate::{runtime::messages::RuntimeMessage, state::State};
+use crate::{
+    backend::renderer::shade::SHADE_HEIGHT,
+    runtime::messages::RuntimeMessage,
+    state::{output::OutputExt, CommonState, State},
+};
 
-use self::workspace::Workspace;
+use self::{
+    autohide::{AutoHide, AutoHideConfig},
+    fullscreen::FullscreenManager,
+    grab::grabbed_window,
+    layer_rules::{set_layer_rule, LayerRules},
+    layout::usable_zone,
+    pip::{PipConfig, PipManager},
+    reconnect::StaleOutputs,
+    rules::WindowRules,
+    transition::WorkspaceTransition,
+    window::{WindowExt, WindowInfo},
+    workspace::{Workspace, WorkspaceConfig},
+};
 
 pub struct Shell {
     pub workspaces: Vec<Workspace>,
+    /// Index of the shown workspace in `workspaces`
+    active: usize,
+    /// Name of the workspace shown before, for going back and forth
+    previous_workspace: Option<String>,
+    workspace_config: WorkspaceConfig,
+    /// Slide from the workspace shown before the last switch
+    transition: Option<WorkspaceTransition>,
+    /// Fullscreen windows of all workspaces
+    pub fullscreen: FullscreenManager,
+    /// Picture-in-picture windows of all workspaces
+    pub pip: PipManager,
     pub outputs: Vec<Output>,
     pub popups: PopupManager,
 
     pub pending_windows: Vec<(Window, Seat<State>)>,
     pub pending_layers: Vec<(LayerSurface, Output, Seat<State>)>,
+    /// Placement of new windows, used for autostarted programs
+    pub rules: WindowRules,
+    /// Overrides for layer surfaces by namespace
+    pub layer_rules: LayerRules,
+    pub auto_hide: AutoHide,
+    /// Where windows were on monitors that went away
+    stale_outputs: StaleOutputs,
 
     // Wayland State
     pub layer_shell_state: WlrLayerShellState,
@@ -39,31 +92,167 @@ pub struct Shell {
 }
 
 impl Shell {
-    pub fn new(dh: &DisplayHandle, rs: Sender<RuntimeMessage>) -> Self {
-        Self {
-            // TODO: Make a way to create new Workspaces
-            workspaces: vec![Workspace::new(0, rs)],
+    pub fn new(
+        dh: &DisplayHandle,
+        rs: Sender<RuntimeMessage>,
+        auto_hide: AutoHideConfig,
+        workspace_config: WorkspaceConfig,
+        pip: PipConfig,
+    ) -> Self {
+        let fullscreen = FullscreenManager::default();
+        let pip = PipManager::new(pip);
+        let first = workspace_config
+            .persistent
+            .first()
+            .cloned()
+            .unwrap_or_else(|| String::from("1"));
+        let mut shell = Self {
+            workspaces: vec![Workspace::new(
+                0,
+                first,
+                rs,
+                fullscreen.clone(),
+                pip.clone(),
+            )],
+            active: 0,
+            previous_workspace: None,
+            workspace_config,
+            transition: None,
+            fullscreen,
+            pip,
             outputs: Vec::new(),
             popups: PopupManager::new(slog_scope::logger()),
 
             pending_windows: Vec::new(),
             pending_layers: Vec::new(),
+            rules: WindowRules::default(),
+            layer_rules: LayerRules::default(),
+            auto_hide: AutoHide::new(auto_hide),
+            stale_outputs: StaleOutputs::default(),
 
             layer_shell_state: WlrLayerShellState::new::<State, _>(dh, slog_scope::logger()),
             xdg_shell_state: XdgShellState::new::<State, _>(dh, slog_scope::logger()),
+        };
+        let persistent = shell.workspace_config.persistent.clone();
+        for name in persistent.into_iter().skip(1) {
+            if !shell.workspaces.iter().any(|w| w.name == name) {
+                if let Err(err) = shell.add_workspace(name) {
+                    slog_scope::warn!("Skipping persistent workspaces: {}", err);
+                    break;
+                }
+            }
         }
+        shell
     }
 
     pub fn outputs(&self) -> impl Iterator<Item = &Output> {
         self.outputs.iter()
     }
 
+    /// The output containing `location`, in global coordinates
+    pub fn output_at(&self, location: Point<f64, Logical>) -> Option<&Output> {
+        self.outputs()
+            .find(|output| output.geometry().to_f64().contains(location))
+    }
+
     pub fn active_workspace(&self) -> &Workspace {
-        &self.workspaces.get(0).unwrap()
+        &self.workspaces[self.active]
     }
 
     pub fn active_workspace_mut(&mut self) -> &mut Workspace {
-        &mut self.workspaces[0]
+        &mut self.workspaces[self.active]
+    }
+
+    pub fn find_window(&self, id: usize) -> Option<Window> {
+        self.workspaces
+            .iter()
+            .flat_map(|workspace| workspace.space.windows())
+            .find(|window| window.id() == id)
+            .cloned()
+    }
+
+    pub fn window_infos(&self) -> Vec<WindowInfo> {
+        self.workspaces
+            .iter()
+            .flat_map(|workspace| {
+                workspace.space.windows().map(|window| WindowInfo {
+                    workspace: Some(workspace.name.clone()),
+                    ..window.info()
+                })
+            })
+            .collect()
+    }
+
+    /// Collapses `window` to its title bar or brings it back, fullscreen windows stay as they are
+    pub fn set_shaded(&mut self, window: &Window, shaded: bool) {
+        let workspace = self.active_workspace_mut();
+        if workspace.is_fullscreen(window) {
+            return;
+        }
+        if shaded {
+            workspace.layer.shade_window(&mut workspace.space, window);
+        } else {
+            workspace.layer.unshade_window(&mut workspace.space, window);
+        }
+    }
+
+    /// The shaded window whose bar is at `location`
+    pub fn shaded_under(&self, location: Point<f64, Logical>) -> Option<Window> {
+        self.active_workspace()
+            .space
+            .windows()
+            .filter(|window| {
+                window.shaded().map_or(false, |loc| {
+                    let size = (window.geometry().size.w, SHADE_HEIGHT);
+                    Rectangle::from_loc_and_size(loc, size)
+                        .to_f64()
+                        .contains(location)
+                })
+            })
+            .last()
+            .cloned()
+    }
+
+    /// Marks a window as demanding attention, the runtime is told about every change
+    pub fn set_urgent(&mut self, window: &Window, urgent: bool) {
+        if window.set_urgent(urgent) {
+            let _ = self
+                .active_workspace()
+                .runtime_sender
+                .send(RuntimeMessage::WindowUrgency {
+                    id: window.id(),
+                    urgent,
+                });
+        }
+    }
+
+    /// The window of the toplevel `surface`, if it is mapped in some workspace
+    pub fn mapped_window(&self, surface: &WlSurface) -> Option<Window> {
+        self.workspaces.iter().find_map(|workspace| {
+            workspace
+                .space
+                .window_for_surface(surface, WindowSurfaceType::TOPLEVEL)
+                .cloned()
+        })
+    }
+
+    /// Takes a window whose client committed a null buffer off its workspace, it is configured
+    /// and mapped again like a new window once it has a buffer
+    pub fn unmap_toplevel(&mut self, window: &Window, seat: &Seat<State>) {
+        if let Some(workspace) = self.space_for_window_mut(window.toplevel().wl_surface()) {
+            workspace.leave_pip(window);
+            workspace.fullscreen.remove(workspace.idx, window);
+            workspace.layer.unmap_window(&mut workspace.space, window);
+        }
+        with_states(window.toplevel().wl_surface(), |states| {
+            if let Some(attributes) = states
+                .data_map
+                .get::<Mutex<XdgToplevelSurfaceRoleAttributes>>()
+            {
+                attributes.lock().unwrap().initial_configure_sent = false;
+            }
+        });
+        self.pending_windows.push((window.clone(), seat.clone()));
     }
 
     pub fn space_for_window_mut(&mut self, surface: &WlSurface) -> Option<&mut Workspace> {
@@ -81,18 +270,21 @@ impl Shell {
             output,
             &mut self.workspaces,
             None,
-            0,
+            self.active,
             output.current_location(),
         );
     }
 
     pub fn remove_output(&mut self, output: &Output) {
+        if self.outputs.contains(output) {
+            self.remember_output(output);
+        }
         self.outputs.retain(|o| o != output);
         remap_output(output, &mut self.workspaces, None, None, None);
     }
 
     pub fn refresh_outputs(&mut self) {
-        let workspace = &mut self.workspaces[0];
+        let workspace = &mut self.workspaces[self.active];
         for output in self.outputs.iter() {
             workspace
                 .space
@@ -100,9 +292,31 @@ impl Shell {
         }
     }
 
+    /// Reconfigures what depends on the size of `output`, after its mode changed
+    pub fn rearrange_output(&mut self, output: &Output) {
+        for workspace in &mut self.workspaces {
+            workspace.rearrange_output(output);
+        }
+    }
+
+    /// Moves windows that ended up outside of every output, after they were moved or resized
+    /// or outputs changed, back onto the closest one
+    pub fn constrain_windows(&mut self, min_visible: i32) {
+        let outputs = self.outputs.clone();
+        for workspace in &mut self.workspaces {
+            workspace.constrain_windows(&outputs, min_visible);
+        }
+    }
+
     pub fn refresh(&mut self, dh: &DisplayHandle) {
-        let workspace = &mut self.workspaces[0];
-        workspace.refresh(dh);
+        for workspace in &mut self.workspaces {
+            workspace.refresh(dh, &self.outputs);
+        }
+        // The last window of a hidden workspace closed
+        if self.remove_empty_workspaces() {
+            self.report_workspaces();
+        }
+        self.refresh_transition();
 
         for output in &self.outputs {
             let mut map = layer_map_for_output(output);
@@ -119,16 +333,23 @@ impl Shell {
         let (layer_surface, output, seat) = self.pending_layers.remove(pos);
 
         let surface = layer_surface.wl_surface();
+        if let Some(rule) = self.layer_rules.rule(layer_surface.namespace()) {
+            set_layer_rule(surface, rule);
+        }
+        // Top surfaces are hidden behind fullscreen and magnified windows
+        let covered = self.active_workspace().get_filling(&output).is_some();
         let wants_focus = {
             with_states(surface, |states| {
                 let state = states.cached_state.current::<LayerSurfaceCachedState>();
-                matches!(state.layer, Layer::Top | Layer::Overlay)
+                (state.layer == Layer::Overlay || (state.layer == Layer::Top && !covered))
                     && state.keyboard_interactivity != KeyboardInteractivity::None
             })
         };
 
-        let mut map = layer_map_for_output(&output);
-        map.map_layer(dh, &layer_surface).unwrap();
+        layer_map_for_output(&output)
+            .map_layer(dh, &layer_surface)
+            .unwrap();
+        self.auto_hide.layer_mapped(&layer_surface, &output);
 
         if wants_focus {
             self.set_focus(dh, Some(surface), &seat, None)
@@ -143,11 +364,40 @@ impl Shell {
             .unwrap();
         let (window, seat) = self.pending_windows.remove(pos);
         let surface = window.toplevel().wl_surface().clone();
-        let workspace = self.active_workspace_mut();
+        // Torn off in a drag, the window follows the pointer until the drop maps it
+        if grabbed_window(&seat).as_ref() == Some(&window) {
+            return;
+        }
 
-        workspace
-            .space
-            .map_window(&window, Point::from((0, 0)), 0, false);
+        let placement = self.rules.placement(&window).unwrap_or_default();
+        // Centered in the usable area of the assigned output
+        let position = placement
+            .output
+            .and_then(|name| self.outputs.iter().find(|o| o.name() == name))
+            .map(|output| {
+                let zone = usable_zone(output);
+                let size = window.geometry().size;
+                output.current_location()
+                    + zone.loc
+                    + Point::from(((zone.size.w - size.w) / 2, (zone.size.h - size.h) / 2))
+            })
+            .unwrap_or_else(|| Point::from((0, 0)));
+        if let Some(workspace) = placement.workspace {
+            let _ = self
+                .active_workspace()
+                .runtime_sender
+                .send(RuntimeMessage::WindowAssigned {
+                    id: window.id(),
+                    workspace,
+                });
+        }
+
+        if placement.fake_fullscreen {
+            window.set_fake_fullscreen(true);
+        }
+        window.set_click_raise(placement.click_raise);
+        let workspace = self.active_workspace_mut();
+        workspace.space.map_window(&window, position, 0, false);
 
         self.set_focus(dh, Some(&surface), &seat, None);
     }
@@ -167,6 +417,13 @@ impl Shell {
     }
 }
 
+impl CommonState {
+    /// Keeps `min_visible` pixels of every floating window on screen
+    pub fn constrain_windows(&mut self) {
+        self.shell.constrain_windows(self.config.min_visible);
+    }
+}
+
 fn remap_output(
     output: &Output,
     spaces: &mut [Workspace],
@@ -0,0 +1,342 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use smithay::{
+    desktop::{space::RenderZindex, Kind, Space, Window, WindowSurfaceType},
+    reexports::{
+        wayland_protocols::xdg::shell::server::xdg_toplevel::State as XdgState,
+        wayland_server::protocol::wl_surface::WlSurface,
+    },
+    utils::{IsAlive, Logical, Point, Rectangle, Size},
+    wayland::output::Output,
+};
+
+use super::{
+    configure::{configure, TiledEdges},
+    layout::{usable_zone, FLOATING_INDEX},
+    Workspace,
+};
+
+/// Above floating windows, below top layer surfaces
+const PIP_INDEX: u8 = RenderZindex::Shell as u8 + 2;
+
+/// Corner of the usable zone a picture-in-picture window stays in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PipConfig {
+    /// Logical width of picture-in-picture windows, their height keeps the aspect ratio
+    pub width: i32,
+    /// Logical pixels between them and the edges of the usable zone
+    pub margin: i32,
+    /// Milliseconds the pointer has to rest on one before it takes clicks
+    pub hover_delay: u64,
+}
+
+impl Default for PipConfig {
+    fn default() -> Self {
+        Self {
+            width: 400,
+            margin: 16,
+            hover_delay: 500,
+        }
+    }
+}
+
+struct PipWindow {
+    window: Window,
+    corner: Corner,
+    output: Output,
+    /// Where the window was and how large, to put it back
+    restore: Rectangle<i32, Logical>,
+    maximized: bool,
+    /// Since when the pointer is over it
+    hovered: Option<Instant>,
+}
+
+struct Inner {
+    config: PipConfig,
+    windows: Vec<PipWindow>,
+}
+
+/// Windows shrunk into a corner of their output, kept above the others. Pointer input goes
+/// through them to what is below until the pointer rested on one. The workspaces of a `Shell`
+/// share one, like the fullscreen windows.
+#[derive(Clone)]
+pub struct PipManager(Rc<RefCell<Inner>>);
+
+impl PipManager {
+    pub fn new(config: PipConfig) -> Self {
+        PipManager(Rc::new(RefCell::new(Inner {
+            config,
+            windows: Vec::new(),
+        })))
+    }
+
+    pub fn contains(&self, window: &Window) -> bool {
+        self.0
+            .borrow()
+            .windows
+            .iter()
+            .any(|pip| pip.window == *window)
+    }
+
+    /// Layer of the space `window` is kept at, picture-in-picture windows above the others
+    pub fn z_index(&self, window: &Window) -> u8 {
+        if self.contains(window) {
+            PIP_INDEX
+        } else {
+            FLOATING_INDEX
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().windows.is_empty()
+    }
+
+    /// Notes that the pointer is over `window`, `None` if over nothing. Returns whether the
+    /// window lets pointer input through, which a picture-in-picture window does until the
+    /// pointer rested on it for `hover_delay`.
+    fn pointer_over(&self, window: Option<&Window>) -> bool {
+        let mut inner = self.0.borrow_mut();
+        let delay = Duration::from_millis(inner.config.hover_delay);
+        let mut passes = false;
+        for pip in &mut inner.windows {
+            if Some(&pip.window) == window {
+                let since = *pip.hovered.get_or_insert_with(Instant::now);
+                passes = since.elapsed() < delay;
+            } else {
+                pip.hovered = None;
+            }
+        }
+        passes
+    }
+
+    fn cleanup(&self) {
+        self.0.borrow_mut().windows.retain(|pip| pip.window.alive());
+    }
+}
+
+impl Workspace {
+    /// Layer of the space `window` is kept at, picture-in-picture windows above the others
+    pub fn z_index(&self, window: &Window) -> u8 {
+        self.pip.z_index(window)
+    }
+
+    /// Shrinks `window` into `corner` of `output` and keeps it above the other windows, or moves
+    /// it to another corner if it already is in picture-in-picture mode. Fullscreen windows are
+    /// left alone.
+    pub fn enter_pip(&mut self, window: &Window, output: &Output, corner: Corner) {
+        self.pip.cleanup();
+        if self.is_fullscreen(window) {
+            slog_scope::debug!("Not shrinking the fullscreen window {:?}", window);
+            return;
+        }
+        {
+            let mut inner = self.pip.0.borrow_mut();
+            if let Some(pip) = inner.windows.iter_mut().find(|pip| pip.window == *window) {
+                pip.corner = corner;
+                pip.output = output.clone();
+            } else {
+                self.layer.unshade_window(&mut self.space, window);
+                let location = match self.space.window_location(window) {
+                    Some(location) => location,
+                    None => return,
+                };
+                let geometry = window.geometry();
+                let zone = usable_zone(output);
+                let width = inner.config.width.min(zone.size.w / 2).max(1);
+                let height = (width * geometry.size.h / geometry.size.w.max(1)).max(1);
+                let size = Size::from((width, height));
+
+                inner.windows.push(PipWindow {
+                    window: window.clone(),
+                    corner,
+                    output: output.clone(),
+                    restore: Rectangle::from_loc_and_size(location, geometry.size),
+                    maximized: is_maximized(window),
+                    hovered: None,
+                });
+                configure(window, |state| {
+                    state.states.unset(XdgState::Maximized);
+                    TiledEdges::default().apply(state);
+                    state.size = Some(size);
+                });
+                // Placed for the size it was asked for, commits of the new size move it again
+                let margin = inner.config.margin;
+                place(&mut self.space, window, output, corner, size, margin);
+                return;
+            }
+        }
+        self.place_pip(window);
+    }
+
+    /// Puts a picture-in-picture window back where it was, as large as it was
+    pub fn leave_pip(&mut self, window: &Window) {
+        let pip = {
+            let mut inner = self.pip.0.borrow_mut();
+            match inner.windows.iter().position(|pip| pip.window == *window) {
+                Some(index) => inner.windows.remove(index),
+                None => return,
+            }
+        };
+        let zone = usable_zone(&pip.output);
+        let output_loc = self
+            .space
+            .output_geometry(&pip.output)
+            .map(|geometry| geometry.loc)
+            .unwrap_or_else(|| (0, 0).into());
+        if pip.maximized {
+            configure(window, |state| {
+                state.states.set(XdgState::Maximized);
+                state.size = Some(zone.size);
+            });
+            self.space
+                .map_window(window, output_loc + zone.loc, FLOATING_INDEX, true);
+        } else {
+            configure(window, |state| state.size = Some(pip.restore.size));
+            self.space
+                .map_window(window, pip.restore.loc, FLOATING_INDEX, true);
+        }
+    }
+
+    /// Moves a picture-in-picture window back into its corner, after it changed size or was
+    /// moved away. A window that went fullscreen or was maximized meanwhile stays where it is,
+    /// out of picture-in-picture mode.
+    pub fn place_pip(&mut self, window: &Window) {
+        if !self.pip.contains(window) {
+            return;
+        }
+        if self.is_fullscreen(window) || is_maximized(window) {
+            self.pip
+                .0
+                .borrow_mut()
+                .windows
+                .retain(|pip| pip.window != *window);
+            return;
+        }
+        let inner = self.pip.0.borrow();
+        let pip = match inner.windows.iter().find(|pip| pip.window == *window) {
+            Some(pip) => pip,
+            None => return,
+        };
+        // Windows of an output that went away go to any other
+        let output = if self.space.outputs().any(|output| *output == pip.output) {
+            pip.output.clone()
+        } else {
+            match self.space.outputs().next() {
+                Some(output) => output.clone(),
+                None => return,
+            }
+        };
+        let size = window.geometry().size;
+        place(
+            &mut self.space,
+            window,
+            &output,
+            pip.corner,
+            size,
+            inner.config.margin,
+        );
+    }
+
+    /// Moves the picture-in-picture windows on `output` into their corners again, after its
+    /// size or usable zone changed
+    pub fn rearrange_pip(&mut self, output: &Output) {
+        self.pip.cleanup();
+        let windows = self
+            .pip
+            .0
+            .borrow()
+            .windows
+            .iter()
+            .filter(|pip| pip.output == *output)
+            .map(|pip| pip.window.clone())
+            .collect::<Vec<_>>();
+        for window in windows {
+            self.place_pip(&window);
+        }
+    }
+
+    /// The window surface under `point` that takes pointer input, with its location. Windows
+    /// in picture-in-picture mode let it through to what is below them until the pointer
+    /// rested on them.
+    pub fn surface_under(
+        &self,
+        point: Point<f64, Logical>,
+    ) -> Option<(Window, WlSurface, Point<i32, Logical>)> {
+        let under = self.space.surface_under(point, WindowSurfaceType::ALL);
+        if self.pip.is_empty() {
+            return under;
+        }
+        let window = under.as_ref().map(|(window, _, _)| window);
+        if !self.pip.pointer_over(window) {
+            return under;
+        }
+
+        // The topmost window that is not in picture-in-picture mode
+        let windows = self.space.windows().cloned().collect::<Vec<_>>();
+        windows
+            .iter()
+            .rev()
+            .filter(|window| !self.pip.contains(window))
+            .find_map(|window| {
+                let location = self.space.window_location(window)? - window.geometry().loc;
+                window
+                    .surface_under(point - location.to_f64(), WindowSurfaceType::ALL)
+                    .map(|(surface, loc)| (window.clone(), surface, loc + location))
+            })
+    }
+}
+
+/// Maps `window` of `size` into `corner` of the usable zone of `output`, above other windows
+fn place(
+    space: &mut Space,
+    window: &Window,
+    output: &Output,
+    corner: Corner,
+    size: Size<i32, Logical>,
+    margin: i32,
+) {
+    let output_loc = match space.output_geometry(output) {
+        Some(geometry) => geometry.loc,
+        None => return,
+    };
+    let zone = usable_zone(output);
+    let left = zone.loc.x + margin;
+    let right = zone.loc.x + zone.size.w - size.w - margin;
+    let top = zone.loc.y + margin;
+    let bottom = zone.loc.y + zone.size.h - size.h - margin;
+    let location = output_loc
+        + Point::from(match corner {
+            Corner::TopLeft => (left, top),
+            Corner::TopRight => (right, top),
+            Corner::BottomLeft => (left, bottom),
+            Corner::BottomRight => (right, bottom),
+        });
+    if space.window_location(window) != Some(location) {
+        space.map_window(window, location, PIP_INDEX, false);
+    }
+}
+
+fn is_maximized(window: &Window) -> bool {
+    match window.toplevel() {
+        Kind::Xdg(surface) => {
+            surface.with_pending_state(|state| state.states.contains(XdgState::Maximized))
+        }
+    }
+}
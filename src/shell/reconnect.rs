@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use smithay::{
+    desktop::Window,
+    utils::{Logical, Point, Rectangle},
+    wayland::output::Output,
+};
+
+use super::{window::WindowExt, Shell};
+use crate::state::output::OutputExt;
+
+/// How many monitors that went away are remembered, the oldest is forgotten first
+const MAX_STALE_OUTPUTS: usize = 8;
+
+/// What a monitor is recognized by when it comes back. No backend reads the EDID serial, so the
+/// connector tells apart two monitors of the same model and a monitor moved to another
+/// connector counts as a new one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputIdentity {
+    make: String,
+    model: String,
+    connector: String,
+}
+
+impl OutputIdentity {
+    pub fn of(output: &Output) -> Self {
+        let properties = output.physical_properties();
+        Self {
+            make: properties.make,
+            model: properties.model,
+            connector: output.name(),
+        }
+    }
+}
+
+/// A window that was on a monitor when it went away
+struct StaleWindow {
+    window: Window,
+    /// Relative to the monitor
+    location: Point<i32, Logical>,
+    fullscreen: bool,
+}
+
+/// Windows of monitors that went away, put back when the monitor comes back
+#[derive(Default)]
+pub struct StaleOutputs(Vec<(OutputIdentity, Vec<StaleWindow>)>);
+
+impl Shell {
+    /// Remembers where the windows on `output` are, before it goes away
+    pub(super) fn remember_output(&mut self, output: &Output) {
+        let output_geometry = output.geometry();
+        let windows = self
+            .workspaces
+            .iter()
+            .flat_map(|workspace| {
+                workspace.space.windows().filter_map(move |window| {
+                    if window.shaded().is_some() {
+                        return None;
+                    }
+                    let location = workspace.space.window_location(window)?;
+                    let geometry = Rectangle::from_loc_and_size(location, window.geometry().size);
+                    let center = geometry.loc + geometry.size.to_point().downscale(2);
+                    output_geometry.contains(center).then(|| StaleWindow {
+                        window: window.clone(),
+                        location: location - output_geometry.loc,
+                        fullscreen: workspace.is_fullscreen(window),
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let identity = OutputIdentity::of(output);
+        let stale = &mut self.stale_outputs.0;
+        stale.retain(|(other, _)| *other != identity);
+        for (_, windows) in stale.iter_mut() {
+            windows.retain(|stale| stale.window.toplevel().alive());
+        }
+        if windows.is_empty() {
+            return;
+        }
+        if stale.len() == MAX_STALE_OUTPUTS {
+            stale.remove(0);
+        }
+        stale.push((identity, windows));
+    }
+
+    /// Puts the windows that were on the monitor of `output` the last time it was connected back
+    /// where they were. Workspaces span every output, so windows are still on theirs.
+    pub fn restore_output(&mut self, output: &Output) {
+        if !self.outputs.contains(output) {
+            return;
+        }
+        let identity = OutputIdentity::of(output);
+        let stale = &mut self.stale_outputs.0;
+        let windows = match stale.iter().position(|(other, _)| *other == identity) {
+            Some(index) => stale.remove(index).1,
+            None => return,
+        };
+        let output_location = output.current_location();
+        let mut restored = 0;
+        for stale in windows {
+            let workspace = self.workspaces.iter_mut().find(|workspace| {
+                workspace
+                    .space
+                    .windows()
+                    .any(|window| *window == stale.window)
+            });
+            let workspace = match workspace {
+                Some(workspace) => workspace,
+                None => continue,
+            };
+            let z_index = workspace.z_index(&stale.window);
+            workspace.space.map_window(
+                &stale.window,
+                output_location + stale.location,
+                z_index,
+                false,
+            );
+            if stale.fullscreen {
+                workspace.fullscreen_request(&stale.window, output);
+            }
+            restored += 1;
+        }
+        slog_scope::info!("Restored {} windows onto {}", restored, output.name());
+    }
+}
@@ -0,0 +1,176 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use smithay::desktop::Window;
+
+use crate::{
+    config::Autostart,
+    input::click_raise::RaisePolicy,
+    runtime::registry::RuntimeRule,
+    shell::window::{WindowExt, WindowInfo},
+};
+
+/// Which windows a rule applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleMatch {
+    AppId(String),
+    /// Windows of clients running this binary, resolved like `/proc/<pid>/exe`
+    Executable(PathBuf),
+    /// Windows of this process or any process it started
+    Process(u32),
+}
+
+/// Where new windows go, for a limited time after the rule was added
+#[derive(Debug, Clone)]
+pub struct WindowRule {
+    pub matches: RuleMatch,
+    pub workspace: Option<u32>,
+    pub output: Option<String>,
+    pub expires: Instant,
+}
+
+/// Placement of a window a rule matched
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Placement {
+    pub workspace: Option<u32>,
+    pub output: Option<String>,
+    /// Maximize the window when it asks for fullscreen
+    pub fake_fullscreen: bool,
+    /// What clicking the window does to its place in the stack, `None` goes by `[input]`
+    pub click_raise: Option<RaisePolicy>,
+}
+
+#[derive(Debug, Default)]
+pub struct WindowRules {
+    temporary: Vec<WindowRule>,
+    /// Added by the committed generation of the runtime, they don't expire
+    runtime: Vec<(RuleMatch, Placement)>,
+}
+
+impl WindowRules {
+    pub fn add(&mut self, rule: WindowRule) {
+        self.temporary.push(rule);
+    }
+
+    /// Replaces every rule of the runtime
+    pub fn set_runtime(&mut self, rules: &[RuntimeRule]) {
+        self.runtime = rules
+            .iter()
+            .filter_map(|rule| {
+                let matches = match (&rule.app_id, &rule.executable) {
+                    (Some(app_id), _) => RuleMatch::AppId(app_id.clone()),
+                    (None, Some(executable)) => RuleMatch::Executable(
+                        fs::canonicalize(executable).unwrap_or_else(|_| executable.clone()),
+                    ),
+                    (None, None) => return None,
+                };
+                let placement = Placement {
+                    workspace: rule.workspace,
+                    output: rule.output.clone(),
+                    fake_fullscreen: rule.fake_fullscreen,
+                    click_raise: rule.click_raise,
+                };
+                Some((matches, placement))
+            })
+            .collect();
+    }
+
+    /// Rule for programs started with the compositor, if they are assigned anywhere
+    pub fn add_autostart(&mut self, autostart: &Autostart, pid: Option<u32>) {
+        let (app_id, executable, workspace, output, timeout) = match autostart {
+            Autostart::Command(_) => return,
+            Autostart::Assigned {
+                app_id,
+                executable,
+                workspace,
+                output,
+                timeout,
+                ..
+            } => (app_id, executable, workspace, output, timeout),
+        };
+        let matches = match (app_id, executable, pid) {
+            (Some(app_id), _, _) => RuleMatch::AppId(app_id.clone()),
+            (None, Some(executable), _) => {
+                // Symlinks like /usr/bin/foo -> /opt/foo/foo are resolved in /proc as well
+                let executable =
+                    fs::canonicalize(executable).unwrap_or_else(|_| executable.clone());
+                RuleMatch::Executable(executable)
+            }
+            (None, None, Some(pid)) => RuleMatch::Process(pid),
+            (None, None, None) => return,
+        };
+        self.add(WindowRule {
+            matches,
+            workspace: *workspace,
+            output: output.clone(),
+            expires: Instant::now() + Duration::from_secs(*timeout),
+        });
+    }
+
+    /// Placement from the first rule matching `window`, expired rules are dropped on the way.
+    /// Rules of programs that were just started come before those of the runtime.
+    pub fn placement(&mut self, window: &Window) -> Option<Placement> {
+        let now = Instant::now();
+        self.temporary.retain(|rule| rule.expires > now);
+        if self.temporary.is_empty() && self.runtime.is_empty() {
+            return None;
+        }
+
+        let info = window.info();
+        self.temporary
+            .iter()
+            .find(|rule| rule.matches.matches(&info))
+            .map(|rule| Placement {
+                workspace: rule.workspace,
+                output: rule.output.clone(),
+                fake_fullscreen: false,
+                click_raise: None,
+            })
+            .or_else(|| {
+                self.runtime
+                    .iter()
+                    .find(|(matches, _)| matches.matches(&info))
+                    .map(|(_, placement)| placement.clone())
+            })
+    }
+}
+
+impl RuleMatch {
+    fn matches(&self, info: &WindowInfo) -> bool {
+        match self {
+            RuleMatch::AppId(id) => info.app_id.as_ref() == Some(id),
+            RuleMatch::Executable(path) => info.executable.as_ref() == Some(path),
+            RuleMatch::Process(ancestor) => info
+                .pid
+                .map(|pid| is_descendant(pid, *ancestor))
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Whether `ancestor` is `pid` or one of its parents. Commands go through `sh -c` and often
+/// fork again, so the client is rarely the process that was spawned.
+pub(crate) fn is_descendant(mut pid: u32, ancestor: u32) -> bool {
+    while pid > 1 {
+        if pid == ancestor {
+            return true;
+        }
+        pid = match parent_pid(pid) {
+            Some(parent) => parent,
+            None => return false,
+        };
+    }
+    false
+}
+
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name is in parentheses and may contain spaces, the parent follows the state
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
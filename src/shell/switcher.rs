@@ -0,0 +1,215 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{path::PathBuf, sync::Arc};
+
+use image::RgbaImage;
+
+use serde::Deserialize;
+use smithay::{
+    desktop::Window,
+    reexports::wayland_server::DisplayHandle,
+    utils::IsAlive,
+    wayland::{output::Output, seat::Seat},
+};
+
+use crate::{
+    backend::renderer::switcher::SwitcherCache,
+    shell::window::WindowExt,
+    state::{output::active_output, CommonState, State},
+};
+
+/// Looks of the window switcher, can be changed by the runtime
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SwitcherStyle {
+    /// Logical pixels, like all other sizes
+    pub width: u32,
+    pub icon_size: u32,
+    pub font_size: f32,
+    pub padding: u32,
+    /// Colors are RGBA
+    pub background: [u8; 4],
+    pub highlight: [u8; 4],
+    pub text: [u8; 4],
+    /// Font file for titles, picked by fontconfig if not set
+    pub font: Option<PathBuf>,
+}
+
+impl Default for SwitcherStyle {
+    fn default() -> Self {
+        Self {
+            width: 480,
+            icon_size: 32,
+            font_size: 15.0,
+            padding: 8,
+            background: [32, 32, 32, 230],
+            highlight: [64, 104, 196, 255],
+            text: [255, 255, 255, 255],
+            font: None,
+        }
+    }
+}
+
+/// What a key pressed while the switcher is open does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwitcherKey {
+    Next,
+    Previous,
+    Cancel,
+    /// Swallowed, so the focused window doesn't see it
+    Ignore,
+}
+
+/// An open window switcher
+pub struct Switcher {
+    /// Most recently focused first
+    pub windows: Vec<Window>,
+    /// Title and icon of each window, resolved when the switcher opened
+    pub entries: Vec<(String, Option<Arc<RgbaImage>>)>,
+    pub selected: usize,
+    pub output: Output,
+    seat: Seat<State>,
+}
+
+#[derive(Default)]
+pub struct SwitcherState {
+    pub open: Option<Switcher>,
+    pub style: SwitcherStyle,
+    /// Changes whenever the switcher looks different
+    pub generation: usize,
+    pub cache: SwitcherCache,
+}
+
+impl SwitcherState {
+    pub fn is_open(&self) -> bool {
+        self.open.is_some()
+    }
+
+    fn set_style(&mut self, style: SwitcherStyle) {
+        self.style = style;
+        self.generation += 1;
+    }
+
+    fn cycle(&mut self, forward: bool) {
+        if let Some(switcher) = &mut self.open {
+            let count = switcher.windows.len();
+            switcher.selected = if forward {
+                (switcher.selected + 1) % count
+            } else {
+                (switcher.selected + count - 1) % count
+            };
+            self.generation += 1;
+        }
+    }
+}
+
+impl CommonState {
+    /// Changes the looks of the switcher, its font is loaded in the background
+    pub fn set_switcher_style(&mut self, style: SwitcherStyle) {
+        self.fonts.load(&self.event_loop_handle, &style.font);
+        self.switcher.set_style(style);
+    }
+
+    /// Starts decoding the icon of the app of `window`, so it is there once the switcher opens
+    pub fn preload_switcher_icon(&mut self, window: &Window) {
+        let icon = window
+            .info()
+            .app_id
+            .and_then(|app_id| self.app_info.lookup(&app_id).icon);
+        if let Some(icon) = icon {
+            self.icons.get(&self.event_loop_handle, &icon);
+        }
+    }
+
+    /// Looks up the titles and icons of the open switcher again, once more icons were decoded
+    pub fn refresh_switcher(&mut self) {
+        let windows = match &self.switcher.open {
+            Some(switcher) => switcher.windows.clone(),
+            None => return,
+        };
+        let entries = self.switcher.cache.entries(
+            &self.app_info,
+            &mut self.icons,
+            &self.event_loop_handle,
+            &windows,
+        );
+        if let Some(switcher) = &mut self.switcher.open {
+            switcher.entries = entries;
+        }
+        self.switcher.generation += 1;
+    }
+
+    /// Opens the switcher with the window focused before the current one selected, or moves on
+    /// to the next window if it is open already
+    pub fn open_switcher(&mut self, seat: &Seat<State>) {
+        if self.switcher.is_open() {
+            self.switcher.cycle(true);
+            return;
+        }
+
+        let workspace = self.shell.active_workspace();
+        let mut windows = workspace
+            .focus_stack(seat)
+            .iter_recent()
+            .cloned()
+            .collect::<Vec<_>>();
+        for window in workspace.space.windows() {
+            if !windows.contains(window) {
+                windows.push(window.clone());
+            }
+        }
+        if windows.is_empty() {
+            return;
+        }
+
+        let entries = self.switcher.cache.entries(
+            &self.app_info,
+            &mut self.icons,
+            &self.event_loop_handle,
+            &windows,
+        );
+        self.switcher.open = Some(Switcher {
+            selected: (windows.len() > 1) as usize,
+            windows,
+            entries,
+            output: active_output(seat, self),
+            seat: seat.clone(),
+        });
+        self.switcher.generation += 1;
+    }
+
+    pub fn switcher_key(&mut self, key: SwitcherKey) {
+        match key {
+            SwitcherKey::Next => self.switcher.cycle(true),
+            SwitcherKey::Previous => self.switcher.cycle(false),
+            SwitcherKey::Cancel => {
+                self.switcher.open = None;
+                self.switcher.generation += 1;
+            }
+            SwitcherKey::Ignore => {}
+        }
+    }
+
+    /// Closes the switcher without focusing anything, if `seat` opened it
+    pub fn cancel_switcher(&mut self, seat: &Seat<State>) {
+        if matches!(&self.switcher.open, Some(switcher) if switcher.seat == *seat) {
+            self.switcher_key(SwitcherKey::Cancel);
+        }
+    }
+
+    /// Closes the switcher and focuses the selected window, once the modifiers are let go
+    pub fn confirm_switcher(&mut self, dh: &DisplayHandle) {
+        let switcher = match self.switcher.open.take() {
+            Some(switcher) => switcher,
+            None => return,
+        };
+        self.switcher.generation += 1;
+
+        if let Some(window) = switcher.windows.get(switcher.selected) {
+            if window.alive() {
+                let surface = window.toplevel().wl_surface().clone();
+                self.set_focus(dh, Some(&surface), &switcher.seat, None);
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use super::{workspace::Workspace, Shell};
+
+/// How the slide between workspaces speeds up and slows down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Easing {
+    Linear,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps the elapsed share of the duration to the share of the distance covered
+    pub fn apply(self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut if t < 0.5 => 4.0 * t.powi(3),
+            Easing::EaseInOut => 1.0 - (-2.0 * t + 2.0).powi(3) / 2.0,
+        }
+    }
+}
+
+/// The workspace that was shown before a switch, sliding out while the new one slides in
+pub struct WorkspaceTransition {
+    /// Key of the outgoing workspace, it may be removed during the slide
+    from: u8,
+    /// 1 if the incoming workspace comes in from the right, -1 if from the left
+    direction: f64,
+    started: Instant,
+    duration: Duration,
+    easing: Easing,
+}
+
+impl WorkspaceTransition {
+    pub fn new(from: u8, forward: bool, duration: Duration, easing: Easing) -> Self {
+        Self {
+            from,
+            direction: if forward { 1.0 } else { -1.0 },
+            started: Instant::now(),
+            duration,
+            easing,
+        }
+    }
+
+    /// Eased progress of the slide, `None` once it is over
+    fn progress(&self) -> Option<f64> {
+        let elapsed = self.started.elapsed();
+        if self.duration.is_zero() || elapsed >= self.duration {
+            return None;
+        }
+        Some(
+            self.easing
+                .apply(elapsed.as_secs_f64() / self.duration.as_secs_f64()),
+        )
+    }
+}
+
+/// A frame of a running slide
+pub struct TransitionFrame<'a> {
+    /// `None` if the outgoing workspace was removed meanwhile
+    pub from: Option<&'a Workspace>,
+    /// Horizontal offsets of the outgoing and the incoming workspace, in output widths
+    pub from_offset: f64,
+    pub to_offset: f64,
+}
+
+impl Shell {
+    /// The slide between workspaces that is running right now
+    pub fn transition(&self) -> Option<TransitionFrame<'_>> {
+        let transition = self.transition.as_ref()?;
+        let progress = transition.progress()?;
+        Some(TransitionFrame {
+            from: self.workspaces.iter().find(|w| w.idx == transition.from),
+            from_offset: -transition.direction * progress,
+            to_offset: transition.direction * (1.0 - progress),
+        })
+    }
+
+    /// Forgets the slide once it is over
+    pub(super) fn refresh_transition(&mut self) {
+        let running = self.transition.as_ref().and_then(|t| t.progress());
+        if running.is_none() {
+            self.transition = None;
+        }
+    }
+}
@@ -0,0 +1,263 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{cell::Cell, path::PathBuf, sync::Mutex};
+
+use serde::Serialize;
+use smithay::{
+    desktop::Window,
+    reexports::wayland_server::protocol::wl_surface::WlSurface,
+    utils::{Logical, Point},
+    wayland::{compositor::with_states, shell::xdg::XdgToplevelSurfaceRoleAttributes},
+};
+
+use crate::{
+    id::id_gen,
+    input::click_raise::RaisePolicy,
+    state::{
+        content_type::{surface_content_type, ContentType},
+        credentials::window_credentials,
+        tearing_control::{surface_presentation_hint, PresentationHint},
+        toplevel_icon::toplevel_icon,
+    },
+};
+
+id_gen!(next_window_id, WINDOW_ID, WINDOW_IDS);
+
+/// Stable identifier of a window, used to refer to it from the runtime and over IPC
+#[repr(transparent)]
+pub struct WindowId(pub usize);
+
+impl Default for WindowId {
+    fn default() -> WindowId {
+        WindowId(next_window_id())
+    }
+}
+
+impl Drop for WindowId {
+    fn drop(&mut self) {
+        WINDOW_IDS.lock().unwrap().remove(&self.0);
+    }
+}
+
+#[derive(Default)]
+struct Urgent(Cell<bool>);
+
+/// Game mode set by the runtime, `None` follows the hints of the client
+#[derive(Default)]
+struct GameMode(Cell<Option<bool>>);
+
+/// Set for windows whose content must not show up in screenshots or thumbnails
+#[derive(Default)]
+struct Private(Cell<bool>);
+
+/// Where a shaded window shows its title bar, `None` unless it is shaded
+#[derive(Default)]
+struct Shaded(Cell<Option<Point<i32, Logical>>>);
+
+/// Set for the window that is scaled up to fill its output
+#[derive(Default)]
+struct Magnified(Cell<bool>);
+
+/// Set for windows whose fullscreen requests are taken as maximize requests
+#[derive(Default)]
+struct FakeFullscreen(Cell<bool>);
+
+/// Raise policy of a window from the rule that matched it
+#[derive(Default)]
+struct ClickRaise(Cell<Option<RaisePolicy>>);
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowInfo {
+    pub id: usize,
+    pub title: Option<String>,
+    pub app_id: Option<String>,
+    pub urgent: bool,
+    pub game_mode: bool,
+    pub private: bool,
+    pub shaded: bool,
+    pub magnified: bool,
+    pub fake_fullscreen: bool,
+    /// Theme icon the client set for the window through xdg-toplevel-icon, `None` if it set
+    /// none or only pixels
+    pub icon_name: Option<String>,
+    /// Name of the workspace the window is on, `None` until it is mapped
+    pub workspace: Option<String>,
+    /// Process of the client, `None` if its credentials could not be read
+    pub pid: Option<u32>,
+    pub executable: Option<PathBuf>,
+}
+
+pub trait WindowExt {
+    fn id(&self) -> usize;
+    /// Whether the window demands attention. Only reported to the runtime and over IPC, there
+    /// is no foreign-toplevel protocol to carry it.
+    fn urgent(&self) -> bool;
+    /// Returns whether the state changed
+    fn set_urgent(&self, urgent: bool) -> bool;
+    /// Whether the window gets a frame callback every repaint while visible and skips animations
+    fn game_mode(&self) -> bool;
+    /// Overrides the game mode hints of the client, `None` goes back to them
+    fn set_game_mode(&self, enabled: Option<bool>);
+    /// Whether captures show a placeholder instead of the window
+    fn private(&self) -> bool;
+    fn set_private(&self, private: bool);
+    /// Where the window is drawn as just a title bar, `None` unless it is shaded. The window
+    /// itself is moved out of sight meanwhile.
+    fn shaded(&self) -> Option<Point<i32, Logical>>;
+    fn set_shaded(&self, location: Option<Point<i32, Logical>>);
+    /// Whether the window is shown scaled up to fill its output
+    fn magnified(&self) -> bool;
+    fn set_magnified(&self, magnified: bool);
+    /// Whether the window is maximized when it asks for fullscreen, e.g. a game meant to stay
+    /// windowed
+    fn fake_fullscreen(&self) -> bool;
+    fn set_fake_fullscreen(&self, enabled: bool);
+    /// What clicking the window does to its place in the stack, `None` unless a rule set it
+    fn click_raise(&self) -> Option<RaisePolicy>;
+    fn set_click_raise(&self, policy: Option<RaisePolicy>);
+    fn title(&self) -> Option<String>;
+    fn info(&self) -> WindowInfo;
+}
+
+impl WindowExt for Window {
+    fn id(&self) -> usize {
+        self.user_data().insert_if_missing(WindowId::default);
+        self.user_data().get::<WindowId>().unwrap().0
+    }
+
+    fn urgent(&self) -> bool {
+        self.user_data()
+            .get::<Urgent>()
+            .map(|urgent| urgent.0.get())
+            .unwrap_or(false)
+    }
+
+    fn set_urgent(&self, urgent: bool) -> bool {
+        self.user_data().insert_if_missing(Urgent::default);
+        self.user_data().get::<Urgent>().unwrap().0.replace(urgent) != urgent
+    }
+
+    fn game_mode(&self) -> bool {
+        self.user_data()
+            .get::<GameMode>()
+            .and_then(|game_mode| game_mode.0.get())
+            .unwrap_or_else(|| wants_game_mode(self.toplevel().wl_surface()))
+    }
+
+    fn set_game_mode(&self, enabled: Option<bool>) {
+        self.user_data().insert_if_missing(GameMode::default);
+        self.user_data().get::<GameMode>().unwrap().0.set(enabled);
+    }
+
+    fn private(&self) -> bool {
+        self.user_data()
+            .get::<Private>()
+            .map(|private| private.0.get())
+            .unwrap_or(false)
+    }
+
+    fn set_private(&self, private: bool) {
+        self.user_data().insert_if_missing(Private::default);
+        self.user_data().get::<Private>().unwrap().0.set(private);
+    }
+
+    fn shaded(&self) -> Option<Point<i32, Logical>> {
+        self.user_data()
+            .get::<Shaded>()
+            .and_then(|shaded| shaded.0.get())
+    }
+
+    fn set_shaded(&self, location: Option<Point<i32, Logical>>) {
+        self.user_data().insert_if_missing(Shaded::default);
+        self.user_data().get::<Shaded>().unwrap().0.set(location);
+    }
+
+    fn magnified(&self) -> bool {
+        self.user_data()
+            .get::<Magnified>()
+            .map(|magnified| magnified.0.get())
+            .unwrap_or(false)
+    }
+
+    fn set_magnified(&self, magnified: bool) {
+        self.user_data().insert_if_missing(Magnified::default);
+        self.user_data()
+            .get::<Magnified>()
+            .unwrap()
+            .0
+            .set(magnified);
+    }
+
+    fn fake_fullscreen(&self) -> bool {
+        self.user_data()
+            .get::<FakeFullscreen>()
+            .map(|fake| fake.0.get())
+            .unwrap_or(false)
+    }
+
+    fn set_fake_fullscreen(&self, enabled: bool) {
+        self.user_data().insert_if_missing(FakeFullscreen::default);
+        self.user_data()
+            .get::<FakeFullscreen>()
+            .unwrap()
+            .0
+            .set(enabled);
+    }
+
+    fn click_raise(&self) -> Option<RaisePolicy> {
+        self.user_data()
+            .get::<ClickRaise>()
+            .and_then(|policy| policy.0.get())
+    }
+
+    fn set_click_raise(&self, policy: Option<RaisePolicy>) {
+        self.user_data().insert_if_missing(ClickRaise::default);
+        self.user_data().get::<ClickRaise>().unwrap().0.set(policy);
+    }
+
+    fn title(&self) -> Option<String> {
+        toplevel_names(self).0
+    }
+
+    fn info(&self) -> WindowInfo {
+        let (title, app_id) = toplevel_names(self);
+
+        let credentials = window_credentials(self);
+        WindowInfo {
+            id: self.id(),
+            title,
+            app_id,
+            urgent: self.urgent(),
+            game_mode: self.game_mode(),
+            private: self.private(),
+            shaded: self.shaded().is_some(),
+            magnified: self.magnified(),
+            fake_fullscreen: self.fake_fullscreen(),
+            icon_name: toplevel_icon(self.toplevel().wl_surface()).and_then(|icon| icon.name),
+            workspace: None,
+            pid: credentials.as_ref().map(|credentials| credentials.pid),
+            executable: credentials.and_then(|credentials| credentials.executable),
+        }
+    }
+}
+
+/// Title and app id the client set
+fn toplevel_names(window: &Window) -> (Option<String>, Option<String>) {
+    with_states(window.toplevel().wl_surface(), |states| {
+        states
+            .data_map
+            .get::<Mutex<XdgToplevelSurfaceRoleAttributes>>()
+            .map(|attrs| {
+                let attrs = attrs.lock().unwrap();
+                (attrs.title.clone(), attrs.app_id.clone())
+            })
+            .unwrap_or_default()
+    })
+}
+
+/// Whether the client of `surface` marked it as a game, or accepts tearing
+pub fn wants_game_mode(surface: &WlSurface) -> bool {
+    surface_content_type(surface) == ContentType::Game
+        || surface_presentation_hint(surface) == PresentationHint::Async
+}
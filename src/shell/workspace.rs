@@ -1,15 +1,17 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
+use anyhow::bail;
 use calloop::channel::Sender;
+use serde::{Deserialize, Serialize};
 use smithay::{
-    desktop::{Kind, Space, Window},
+    desktop::{Space, Window},
     reexports::{
         wayland_protocols::xdg::shell::server::xdg_toplevel::{self, ResizeEdge},
         wayland_server::DisplayHandle,
     },
-    utils::IsAlive,
+    utils::{Logical, Size},
     wayland::{
         output::Output,
         seat::{PointerGrabStartData, Seat},
@@ -17,48 +19,135 @@ use smithay::{
     },
 };
 
-use crate::{runtime::messages::RuntimeMessage, state::State};
+use crate::{
+    backend::renderer::background::Background,
+    runtime::messages::RuntimeMessage,
+    state::{CommonState, State},
+};
+
+use super::{
+    configure::configure,
+    fullscreen::FullscreenManager,
+    layout::Layout,
+    pip::PipManager,
+    remap_output,
+    transition::{Easing, WorkspaceTransition},
+    window::WindowExt,
+    Shell,
+};
 
-use super::layout::Layout;
+/// Workspaces that can exist at once, switching to a new one fails beyond that
+const MAX_WORKSPACES: usize = 64;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WorkspaceConfig {
+    /// Workspaces that exist from the start and are never removed, the first one is shown
+    pub persistent: Vec<String>,
+    /// Switching to the shown workspace goes back to the one shown before
+    pub auto_back_and_forth: bool,
+    /// Removes workspaces that are not shown once their last window is gone
+    pub remove_empty: bool,
+    /// Milliseconds the old workspace slides out and the new one in on a switch, 0 switches
+    /// at once
+    pub switch_duration: u64,
+    /// `linear`, `ease-out` or `ease-in-out`
+    pub switch_easing: Easing,
+}
+
+impl Default for WorkspaceConfig {
+    fn default() -> Self {
+        Self {
+            persistent: vec![String::from("1")],
+            auto_back_and_forth: false,
+            remove_empty: true,
+            switch_duration: 250,
+            switch_easing: Easing::EaseOut,
+        }
+    }
+}
+
+/// The workspaces in the order they were created, for bars
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkspacesInfo {
+    pub active: String,
+    pub workspaces: Vec<String>,
+}
 
 pub struct Workspace {
+    /// Key of the workspace in the fullscreen windows and focus stacks, reused once it is removed
     pub idx: u8,
+    pub name: String,
     pub space: Space,
-    pub fullscreen: HashMap<String, Window>,
+    /// Shared with the other workspaces of the shell
+    pub fullscreen: FullscreenManager,
+    /// Shared like `fullscreen`
+    pub pip: PipManager,
     pub runtime_sender: Sender<RuntimeMessage>,
     pub layer: Layout,
+    /// Drawn below the windows on outputs without a background of their own
+    pub background: Option<Background>,
+    /// By output name
+    pub output_backgrounds: HashMap<String, Background>,
 }
 
 impl Workspace {
-    pub fn new(idx: u8, rs: Sender<RuntimeMessage>) -> Self {
+    pub fn new(
+        idx: u8,
+        name: String,
+        rs: Sender<RuntimeMessage>,
+        fullscreen: FullscreenManager,
+        pip: PipManager,
+    ) -> Self {
         Self {
             idx,
+            name,
             space: Space::new(slog_scope::logger()),
-            fullscreen: HashMap::new(),
+            fullscreen,
+            pip,
             runtime_sender: rs,
             layer: Layout::new(),
+            background: None,
+            output_backgrounds: HashMap::new(),
         }
     }
 
-    pub fn refresh(&mut self, dh: &DisplayHandle) {
-        let outputs = self.space.outputs().collect::<Vec<_>>();
-        let dead_windows = self
-            .fullscreen
-            .iter()
-            .filter(|(name, _)| !outputs.iter().any(|o| o.name() == **name))
-            .map(|(_, w)| w)
-            .cloned()
-            .collect::<Vec<_>>();
-        for window in dead_windows {
+    /// What is drawn below the windows on `output`
+    pub fn background(&self, output: &Output) -> Option<&Background> {
+        self.output_backgrounds
+            .get(&output.name())
+            .or(self.background.as_ref())
+    }
+
+    /// Changes the background of the output called `output`, or of the whole workspace
+    pub fn set_background(&mut self, output: Option<String>, background: Option<Background>) {
+        match (output, background) {
+            (Some(output), Some(background)) => {
+                self.output_backgrounds.insert(output, background);
+            }
+            (Some(output), None) => {
+                self.output_backgrounds.remove(&output);
+            }
+            (None, background) => self.background = background,
+        }
+    }
+
+    /// Leaves fullscreen for windows whose output is no longer among `outputs`
+    pub fn refresh(&mut self, dh: &DisplayHandle, outputs: &[Output]) {
+        for window in self.fullscreen.orphaned(self.idx, outputs) {
             self.unfullscreen_request(&window);
         }
-        self.fullscreen.retain(|_, w| w.alive());
+        self.fullscreen.cleanup();
         self.space.refresh(dh);
     }
 
+    pub fn is_fullscreen(&self, window: &Window) -> bool {
+        self.fullscreen.contains(self.idx, window)
+    }
+
     /// Deno Function
     pub fn maximize_request(&mut self, window: &Window, output: &Output) {
-        if self.fullscreen.values().any(|w| w == window) {
+        if self.is_fullscreen(window) {
             return;
         }
 
@@ -72,7 +161,7 @@ impl Workspace {
 
     /// Deno Function
     pub fn unmaximize_request(&mut self, window: &Window) {
-        if self.fullscreen.values().any(|w| w == window) {
+        if self.is_fullscreen(window) {
             return self.unfullscreen_request(window);
         }
 
@@ -92,57 +181,42 @@ impl Workspace {
         start_data: PointerGrabStartData,
         edges: ResizeEdge,
     ) {
-        if self.fullscreen.values().any(|w| w == window) {
+        if self.is_fullscreen(window) {
             return;
         }
 
+        // The button is still held and the serial fresh only now, a denial ends the grab again
+        self.layer
+            .resize_request(&self.space, window, seat, serial, start_data, edges);
         self.runtime_sender
             .send(RuntimeMessage::ResizeRequest {
                 window: window.clone(),
                 seat: seat.clone(),
                 serial,
-                start_data,
                 edges,
             })
             .unwrap();
     }
 
     pub fn fullscreen_request(&mut self, window: &Window, output: &Output) {
-        if self.fullscreen.contains_key(&output.name()) {
+        if !self.fullscreen.insert(self.idx, output, window) {
             return;
         }
+        self.layer.unshade_window(&mut self.space, window);
 
-        #[allow(irrefutable_let_patterns)]
-        if let Kind::Xdg(xdg) = &window.toplevel() {
-            xdg.with_pending_state(|state| {
-                state.states.set(xdg_toplevel::State::Fullscreen);
-                state.size = Some(
-                    output
-                        .current_mode()
-                        .map(|m| m.size)
-                        .unwrap_or((0, 0).into())
-                        .to_f64()
-                        .to_logical(output.current_scale().fractional_scale())
-                        .to_i32_round(),
-                );
-            });
-
-            xdg.send_configure();
-            self.fullscreen.insert(output.name(), window.clone());
-        }
+        configure(window, |state| {
+            state.states.set(xdg_toplevel::State::Fullscreen);
+            state.size = Some(fullscreen_size(output));
+        });
     }
 
     /// Deno Function
     pub fn unfullscreen_request(&mut self, window: &Window) {
-        if self.fullscreen.values().any(|w| w == window) {
-            #[allow(irrefutable_let_patterns)]
-            if let Kind::Xdg(xdg) = &window.toplevel() {
-                xdg.with_pending_state(|state| {
-                    state.states.unset(xdg_toplevel::State::Fullscreen);
-                    state.size = None;
-                });
-                xdg.send_configure();
-            }
+        if self.is_fullscreen(window) {
+            configure(window, |state| {
+                state.states.unset(xdg_toplevel::State::Fullscreen);
+                state.size = None;
+            });
 
             self.runtime_sender
                 .send(RuntimeMessage::UnfullscreenRequest {
@@ -150,15 +224,299 @@ impl Workspace {
                 })
                 .unwrap();
 
-            self.fullscreen.retain(|_, w| w != window);
+            self.fullscreen.remove(self.idx, window);
+        }
+    }
+
+    /// What the compositor does with a request nothing in the runtime handled
+    pub fn default_request(&mut self, message: RuntimeMessage) {
+        match message {
+            RuntimeMessage::MaximizeRequest { window, output } => {
+                self.layer
+                    .maximize_request(&mut self.space, &window, &output)
+            }
+            RuntimeMessage::UnmaximizeRequest { window } => {
+                self.layer.unmaximize_request(&mut self.space, &window)
+            }
+            // The grab started with the request already
+            RuntimeMessage::ResizeRequest { .. } => {}
+            // The window was already unfullscreened, this is only informational
+            RuntimeMessage::UnfullscreenRequest { .. } => {}
+            _ => {}
+        }
+    }
+
+    /// Configures the maximized and fullscreen windows on `output` for its current mode
+    pub fn rearrange_output(&mut self, output: &Output) {
+        self.layer.rearrange_output(&mut self.space, output);
+
+        // Also when this workspace is not shown, so it is right once it is again
+        if let Some(window) = self.fullscreen.get(self.idx, output) {
+            configure(&window, |state| state.size = Some(fullscreen_size(output)));
         }
+        self.rearrange_pip(output);
     }
 
-    pub fn get_fullscreen(&self, output: &Output) -> Option<&Window> {
+    /// Moves floating windows that are mostly outside of `outputs` back onto one
+    pub fn constrain_windows(&mut self, outputs: &[Output], min_visible: i32) {
+        let fullscreen = self
+            .fullscreen
+            .windows(self.idx)
+            .into_iter()
+            .map(|(_, window)| window)
+            .collect::<Vec<_>>();
+        let pip = &self.pip;
+        self.layer.constrain_windows(
+            &mut self.space,
+            outputs,
+            min_visible,
+            &fullscreen,
+            |window| pip.z_index(window),
+        );
+    }
+
+    /// Takes fullscreen requests of `window` as maximize requests from now on, or stops. A
+    /// fullscreen window leaves fullscreen and asks to be maximized right away.
+    pub fn set_fake_fullscreen(&mut self, window: &Window, enabled: bool) {
+        window.set_fake_fullscreen(enabled);
+        if !enabled {
+            return;
+        }
+        let output = self
+            .space
+            .outputs()
+            .find(|output| self.get_fullscreen(output).as_ref() == Some(window))
+            .cloned();
+        if let Some(output) = output {
+            self.unfullscreen_request(window);
+            self.maximize_request(window, &output);
+        }
+    }
+
+    pub fn get_fullscreen(&self, output: &Output) -> Option<Window> {
         if !self.space.outputs().any(|o| o == output) {
             return None;
         }
 
-        self.fullscreen.get(&output.name()).filter(|w| w.alive())
+        self.fullscreen.get(self.idx, output)
+    }
+}
+
+fn fullscreen_size(output: &Output) -> Size<i32, Logical> {
+    output
+        .current_mode()
+        .map(|m| m.size)
+        .unwrap_or((0, 0).into())
+        .to_f64()
+        .to_logical(output.current_scale().fractional_scale())
+        .to_i32_round()
+}
+
+impl Shell {
+    /// Shows the workspace called `name`, created if there is none. With `auto_back_and_forth`
+    /// asking for the shown one goes back to the previous one, `animate` slides the new one in.
+    /// Returns whether another workspace is shown now.
+    pub fn switch_workspace(&mut self, name: &str, animate: bool) -> anyhow::Result<bool> {
+        let current = self.active_workspace().name.clone();
+        let name = match &self.previous_workspace {
+            Some(previous) if name == current && self.workspace_config.auto_back_and_forth => {
+                previous.clone()
+            }
+            _ => name.to_string(),
+        };
+        if name == current {
+            return Ok(false);
+        }
+
+        let index = match self.workspaces.iter().position(|w| w.name == name) {
+            Some(index) => index,
+            None => self.add_workspace(name)?,
+        };
+        for output in &self.outputs {
+            let location = output.current_location();
+            remap_output(output, &mut self.workspaces, self.active, index, location);
+        }
+        let duration = Duration::from_millis(self.workspace_config.switch_duration);
+        self.transition = (animate && !duration.is_zero()).then(|| {
+            WorkspaceTransition::new(
+                self.active_workspace().idx,
+                index > self.active,
+                duration,
+                self.workspace_config.switch_easing,
+            )
+        });
+        self.active = index;
+        self.previous_workspace = Some(current);
+        self.remove_empty_workspaces();
+        Ok(true)
+    }
+
+    /// Goes back to the workspace shown before the current one
+    pub fn workspace_back_and_forth(&mut self, animate: bool) -> anyhow::Result<bool> {
+        match self.previous_workspace.clone() {
+            Some(previous) => self.switch_workspace(&previous, animate),
+            None => Ok(false),
+        }
+    }
+
+    /// Appends a workspace called `name`, returns its index
+    pub(super) fn add_workspace(&mut self, name: String) -> anyhow::Result<usize> {
+        if self.workspaces.len() >= MAX_WORKSPACES {
+            bail!(
+                "There are {} workspaces already, no room for {}",
+                MAX_WORKSPACES,
+                name
+            );
+        }
+        // Free below `MAX_WORKSPACES`, which fits into an idx
+        let idx = (0..=u8::MAX)
+            .find(|idx| self.workspaces.iter().all(|w| w.idx != *idx))
+            .unwrap();
+        let runtime_sender = self.active_workspace().runtime_sender.clone();
+        let workspace = Workspace::new(
+            idx,
+            name,
+            runtime_sender,
+            self.fullscreen.clone(),
+            self.pip.clone(),
+        );
+        self.workspaces.push(workspace);
+        Ok(self.workspaces.len() - 1)
+    }
+
+    /// Drops the workspaces that are neither shown nor persistent and have no windows left,
+    /// returns whether there were any
+    pub(super) fn remove_empty_workspaces(&mut self) -> bool {
+        if !self.workspace_config.remove_empty {
+            return false;
+        }
+        let active = self.active_workspace().idx;
+        let persistent = &self.workspace_config.persistent;
+        let count = self.workspaces.len();
+        self.workspaces.retain(|workspace| {
+            workspace.idx == active
+                || persistent.contains(&workspace.name)
+                || workspace.space.windows().next().is_some()
+        });
+        self.active = self
+            .workspaces
+            .iter()
+            .position(|workspace| workspace.idx == active)
+            .unwrap();
+        // Going back would only create it again, empty
+        let workspaces = &self.workspaces;
+        self.previous_workspace = self
+            .previous_workspace
+            .take()
+            .filter(|previous| workspaces.iter().any(|w| &w.name == previous));
+        self.workspaces.len() != count
+    }
+
+    pub fn workspaces_info(&self) -> WorkspacesInfo {
+        WorkspacesInfo {
+            active: self.active_workspace().name.clone(),
+            workspaces: self.workspaces.iter().map(|w| w.name.clone()).collect(),
+        }
+    }
+
+    /// Tells the runtime which workspaces there are, after they or the shown one changed
+    pub(super) fn report_workspaces(&self) {
+        let WorkspacesInfo { active, workspaces } = self.workspaces_info();
+        let runtime_sender = &self.active_workspace().runtime_sender;
+        let _ = runtime_sender.send(RuntimeMessage::WorkspacesChanged { active, workspaces });
+    }
+}
+
+impl CommonState {
+    /// Shows the workspace called `name` and focuses the window focused last on it, fails if
+    /// there is no room for another workspace
+    pub fn switch_workspace(&mut self, dh: &DisplayHandle, name: &str) -> anyhow::Result<()> {
+        let animate = self.animations();
+        if self.shell.switch_workspace(name, animate)? {
+            self.workspace_switched(dh);
+        }
+        Ok(())
+    }
+
+    pub fn workspace_back_and_forth(&mut self, dh: &DisplayHandle) -> anyhow::Result<()> {
+        let animate = self.animations();
+        if self.shell.workspace_back_and_forth(animate)? {
+            self.workspace_switched(dh);
+        }
+        Ok(())
+    }
+
+    fn workspace_switched(&mut self, dh: &DisplayHandle) {
+        for seat in self.seats.clone() {
+            let focused = self.shell.active_workspace().focus_stack(&seat).last();
+            let surface = focused.map(|window| window.toplevel().wl_surface().clone());
+            self.set_focus(dh, surface.as_ref(), &seat, None);
+        }
+        self.shell.report_workspaces();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sctk::reexports::protocols_wlr::layer_shell::v1::client::{
+        zwlr_layer_shell_v1::Layer, zwlr_layer_surface_v1::Anchor,
+    };
+
+    use crate::{config::Config, shell::fixture::Fixture};
+
+    #[test]
+    fn fullscreen_fills_the_output() {
+        let mut fixture = Fixture::new(Config::default());
+        fixture.add_output("DP-1", (1920, 1080));
+        let toplevel = fixture.toplevel("org.example.App", (800, 600));
+        let window = fixture.window(&toplevel).unwrap();
+
+        fixture.set_fullscreen(&toplevel, true);
+        assert!(fixture.shell().active_workspace().is_fullscreen(&window));
+        assert_eq!(fixture.configured_size(&toplevel), Some((1920, 1080)));
+        fixture.commit(&toplevel, (1920, 1080));
+
+        fixture.set_fullscreen(&toplevel, false);
+        assert!(!fixture.shell().active_workspace().is_fullscreen(&window));
+    }
+
+    #[test]
+    fn maximized_windows_leave_exclusive_zones_free() {
+        let mut fixture = Fixture::new(Config::default());
+        fixture.add_output("DP-1", (1920, 1080));
+        let panel = fixture.layer_surface(
+            "panel",
+            Layer::Top,
+            Anchor::Top | Anchor::Left | Anchor::Right,
+            (0, 30),
+            30,
+        );
+        assert!(fixture.layer(&panel).is_some());
+
+        let toplevel = fixture.toplevel("org.example.App", (800, 600));
+        fixture.set_maximized(&toplevel, true);
+        assert_eq!(fixture.configured_size(&toplevel), Some((1920, 1050)));
+        fixture.ack(&toplevel);
+
+        fixture.destroy_layer(panel);
+        fixture.set_maximized(&toplevel, false);
+        fixture.destroy(toplevel);
+    }
+
+    #[test]
+    fn focus_goes_back_when_a_window_closes() {
+        let mut fixture = Fixture::new(Config::default());
+        let output = fixture.add_output("DP-1", (1920, 1080));
+        let first = fixture.toplevel("org.example.First", (800, 600));
+        let second = fixture.toplevel("org.example.Second", (800, 600));
+        assert_eq!(fixture.focused(), fixture.window(&second));
+
+        fixture.destroy(second);
+        assert_eq!(fixture.focused(), fixture.window(&first));
+
+        fixture.window(&first).unwrap().toplevel().send_close();
+        fixture.roundtrip();
+        assert!(fixture.close_requested(&first));
+        fixture.remove_output(&output);
     }
 }
@@ -0,0 +1,141 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use smithay::{
+    desktop::Kind,
+    reexports::calloop::{
+        signals::{Signal, Signals},
+        timer::{TimeoutAction, Timer},
+        EventLoop,
+    },
+};
+
+use crate::{
+    runtime::messages::RuntimeMessage,
+    state::{BackendData, CommonState, Data, LoopData},
+};
+
+/// How long clients get to close their windows before the compositor goes away
+const CLOSE_GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownState {
+    Running,
+    /// Waiting for the runtime to run its shutdown hook
+    WaitingForRuntime,
+    /// Toplevels were asked to close
+    Closing,
+}
+
+pub fn init_signals(event_loop: &mut EventLoop<LoopData>) -> anyhow::Result<()> {
+    let signals = Signals::new(&[Signal::SIGTERM, Signal::SIGINT])?;
+    event_loop
+        .handle()
+        .insert_source(signals, |event, _, data| {
+            slog_scope::info!("Received {:?}", event.signal());
+            data.state.common.request_shutdown();
+        })
+        .map_err(|_| anyhow!("Failed to initialise signal handlers"))?;
+    Ok(())
+}
+
+impl CommonState {
+    /// Starts the shutdown sequence, a second request stops immediately
+    pub fn request_shutdown(&mut self) {
+        if self.shutdown != ShutdownState::Running {
+            slog_scope::warn!("Shutdown requested again, stopping now");
+            self.should_stop = true;
+            return;
+        }
+
+        slog_scope::info!("Shutting down, notifying runtime");
+        self.shutdown = ShutdownState::WaitingForRuntime;
+        let _ = self
+            .shell
+            .active_workspace()
+            .runtime_sender
+            .send(RuntimeMessage::Shutdown);
+
+        let timeout = Duration::from_millis(self.config.shutdown_timeout);
+        if self
+            .event_loop_handle
+            .insert_source(Timer::from_duration(timeout), |_, _, data| {
+                if data.state.common.shutdown == ShutdownState::WaitingForRuntime {
+                    slog_scope::warn!("Runtime did not finish its shutdown hook in time");
+                    data.state.common.close_clients();
+                }
+                TimeoutAction::Drop
+            })
+            .is_err()
+        {
+            self.close_clients();
+        }
+    }
+
+    /// Called once the runtime is done with its shutdown hook
+    pub fn runtime_ready_for_shutdown(&mut self) {
+        if self.shutdown == ShutdownState::WaitingForRuntime {
+            self.close_clients();
+        }
+    }
+
+    fn close_clients(&mut self) {
+        slog_scope::info!("Closing all toplevels");
+        self.shutdown = ShutdownState::Closing;
+
+        for workspace in &self.shell.workspaces {
+            for window in workspace.space.windows() {
+                #[allow(irrefutable_let_patterns)]
+                if let Kind::Xdg(xdg) = window.toplevel() {
+                    xdg.send_close();
+                }
+            }
+        }
+
+        if self
+            .event_loop_handle
+            .insert_source(Timer::from_duration(CLOSE_GRACE_PERIOD), |_, _, data| {
+                data.state.common.should_stop = true;
+                TimeoutAction::Drop
+            })
+            .is_err()
+        {
+            self.should_stop = true;
+        }
+    }
+
+    /// Whether every window went away after being asked to close
+    pub fn clients_closed(&self) -> bool {
+        self.shutdown == ShutdownState::Closing
+            && self
+                .shell
+                .workspaces
+                .iter()
+                .all(|workspace| workspace.space.windows().next().is_none())
+    }
+}
+
+impl Data {
+    /// Final teardown after the event loop stopped
+    pub fn teardown(&mut self) {
+        let _ = self.display.flush_clients();
+
+        if let Some(token) = self.state.common.socket_token.take() {
+            self.state.common.event_loop_handle.remove(token);
+        }
+        if let Some(token) = self.state.common.sandbox_token.take() {
+            self.state.common.event_loop_handle.remove(token);
+        }
+        self.state.common.ipc.take();
+        self.state.common.portal.take();
+        self.state.common.notifications.take();
+
+        for output in self.state.common.shell.outputs.clone() {
+            self.state.common.shell.remove_output(&output);
+        }
+        self.state.backend = BackendData::Unset;
+        slog_scope::info!("Teardown complete");
+    }
+}
@@ -0,0 +1,190 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use smithay::reexports::wayland_server::backend::ClientId;
+
+use super::credentials::ClientCredentials;
+use crate::config::Config;
+
+/// How many denials are remembered
+const DENIAL_HISTORY: usize = 64;
+
+pub const ELECTRUM_SHELL: &str = "electrum_shell_v1";
+
+/// Globals only clients granted them by an access rule see. Electrum implements no other
+/// privileged protocol so far, rules naming one are ignored with a warning.
+pub const PRIVILEGED_PROTOCOLS: &[&str] = &[ELECTRUM_SHELL];
+
+/// Grants the client running `executable` the privileged `protocols`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AccessRule {
+    pub executable: PathBuf,
+    pub protocols: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessDenial {
+    pub client: String,
+    pub pid: Option<u32>,
+    pub executable: Option<PathBuf>,
+    pub protocol: String,
+}
+
+/// Denials of all clients, shared with their `ClientAccess`
+#[derive(Default, Clone)]
+pub struct AccessLog(Arc<Mutex<VecDeque<AccessDenial>>>);
+
+impl AccessLog {
+    fn push(&self, denial: AccessDenial) {
+        let mut denials = self.0.lock().unwrap();
+        if denials.len() == DENIAL_HISTORY {
+            denials.pop_front();
+        }
+        denials.push_back(denial);
+    }
+
+    /// The last denials, oldest first
+    pub fn denials(&self) -> Vec<AccessDenial> {
+        self.0.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// The privileged protocols one client was granted, matched against the rules of when it
+/// connected the first time it asks for the globals
+pub struct ClientAccess {
+    granted: Mutex<Option<Vec<String>>>,
+    denied: Mutex<HashSet<&'static str>>,
+    credentials: Option<ClientCredentials>,
+    sandboxed: bool,
+    shell_clients: Vec<PathBuf>,
+    rules: Vec<AccessRule>,
+    log: AccessLog,
+}
+
+impl ClientAccess {
+    /// Sandboxed clients are granted nothing, whatever the rules say
+    pub fn new(
+        config: &Config,
+        credentials: Option<ClientCredentials>,
+        sandboxed: bool,
+        log: AccessLog,
+    ) -> Self {
+        ClientAccess {
+            granted: Mutex::new(None),
+            denied: Mutex::new(HashSet::new()),
+            credentials,
+            sandboxed,
+            shell_clients: config.shell_clients.clone(),
+            rules: config.access.clone(),
+            log,
+        }
+    }
+
+    /// Whether the client was granted `protocol`. Its global is only advertised to clients that
+    /// were, so nobody else can bind it. Clients it is hidden from are recorded once per protocol.
+    pub fn may_view(&self, client_id: ClientId, protocol: &'static str) -> bool {
+        let granted = {
+            let mut granted = self.granted.lock().unwrap();
+            granted
+                .get_or_insert_with(|| self.granted_protocols())
+                .iter()
+                .any(|granted| granted == protocol)
+        };
+        if granted {
+            return true;
+        }
+        if !self.denied.lock().unwrap().insert(protocol) {
+            return false;
+        }
+
+        let pid = self.credentials.as_ref().map(|c| c.pid);
+        let executable = self.credentials.as_ref().and_then(|c| c.executable.clone());
+        slog_scope::debug!(
+            "Hiding {} from client {:?} ({})",
+            protocol,
+            client_id,
+            executable
+                .as_ref()
+                .map(|exe| exe.display().to_string())
+                .unwrap_or_else(|| "unknown executable".to_string())
+        );
+        self.log.push(AccessDenial {
+            client: format!("{:?}", client_id),
+            pid,
+            executable,
+            protocol: protocol.to_string(),
+        });
+        false
+    }
+
+    fn granted_protocols(&self) -> Vec<String> {
+        if self.sandboxed {
+            return Vec::new();
+        }
+        let granted =
+            granted_protocols(&self.shell_clients, &self.rules, self.credentials.as_ref());
+        if let Some(exe) = self
+            .credentials
+            .as_ref()
+            .and_then(|c| c.executable.as_ref())
+        {
+            if !granted.is_empty() {
+                slog_scope::info!("Granting {} access to {:?}", exe.display(), granted);
+            }
+        }
+        granted
+    }
+}
+
+fn is_our_user(credentials: &ClientCredentials) -> bool {
+    credentials.uid == nix::unistd::getuid().as_raw()
+}
+
+/// The privileged protocols a client with `credentials` is granted: it has to run as our user,
+/// from an executable named by an access rule, or listed in `shell_clients` for
+/// `electrum_shell_v1`
+fn granted_protocols(
+    shell_clients: &[PathBuf],
+    rules: &[AccessRule],
+    credentials: Option<&ClientCredentials>,
+) -> Vec<String> {
+    let credentials = match credentials {
+        Some(credentials) if is_our_user(credentials) => credentials,
+        _ => return Vec::new(),
+    };
+    let exe = match &credentials.executable {
+        Some(exe) => exe,
+        None => return Vec::new(),
+    };
+    let matches = |path: &PathBuf| {
+        fs::canonicalize(path)
+            .map(|path| &path == exe)
+            .unwrap_or(false)
+    };
+
+    let mut granted = Vec::new();
+    if shell_clients.iter().any(matches) {
+        granted.push(ELECTRUM_SHELL.to_string());
+    }
+    for rule in rules.iter().filter(|rule| matches(&rule.executable)) {
+        for protocol in &rule.protocols {
+            if !PRIVILEGED_PROTOCOLS.contains(&protocol.as_str()) {
+                slog_scope::warn!(
+                    "Ignoring {} in access rule, it is not a privileged protocol Electrum has",
+                    protocol
+                );
+            } else if !granted.contains(protocol) {
+                granted.push(protocol.clone());
+            }
+        }
+    }
+    granted
+}
@@ -5,20 +5,31 @@ use std::sync::Mutex;
 use smithay::{
     backend::renderer::utils::{on_commit_buffer_handler, with_renderer_surface_state},
     delegate_compositor,
-    desktop::{layer_map_for_output, Kind, LayerSurface, PopupKind, WindowSurfaceType},
+    desktop::{layer_map_for_output, Kind, LayerSurface, PopupKind, Window, WindowSurfaceType},
     reexports::wayland_server::{protocol::wl_surface::WlSurface, DisplayHandle},
     wayland::{
-        compositor::{with_states, CompositorHandler, CompositorState},
+        compositor::{
+            with_states, BufferAssignment, CompositorHandler, CompositorState, SurfaceAttributes,
+        },
+        output::Output,
         shell::{
             wlr_layer::LayerSurfaceAttributes,
-            xdg::{
-                ToplevelSurface, XdgPopupSurfaceRoleAttributes, XdgToplevelSurfaceRoleAttributes,
-            },
+            xdg::{XdgPopupSurfaceRoleAttributes, XdgToplevelSurfaceRoleAttributes},
         },
     },
 };
 
-use super::{output::active_output, State};
+use super::{
+    content_type::commit_content_type, diagnostics::track_commit, output::active_output,
+    tearing_control::commit_presentation_hint, toplevel_icon::commit_toplevel_icon,
+    xdg_shell::validate_toplevel_state, State,
+};
+use crate::shell::{
+    configure::{configure, flush, set_bounds},
+    layer_rules::commit_layer_rule,
+    layout::resize_commit,
+    window::WindowExt,
+};
 
 impl CompositorHandler for State {
     fn compositor_state(&mut self) -> &mut CompositorState {
@@ -27,8 +38,42 @@ impl CompositorHandler for State {
 
     /// Deno Function
     fn commit(&mut self, dh: &DisplayHandle, surface: &WlSurface) {
+        // The buffer of a window being unmapped is only drawable until it is released below
+        if removes_buffer(surface) {
+            if let Some(window) = self.common.shell.mapped_window(surface) {
+                self.snapshot_closing(&window);
+            }
+        }
+
         // Load buffers
         on_commit_buffer_handler(surface);
+        track_commit(dh, surface);
+        commit_content_type(surface);
+        commit_presentation_hint(surface);
+        commit_toplevel_icon(surface);
+        commit_layer_rule(surface);
+
+        // A broken state is not worth mapping or laying out
+        let toplevel = self
+            .common
+            .shell
+            .pending_windows
+            .iter()
+            .map(|(window, _)| window.clone())
+            .find(|window| window.toplevel().wl_surface() == surface)
+            .or_else(|| self.common.shell.mapped_window(surface));
+        if let Some(Kind::Xdg(toplevel)) = toplevel.as_ref().map(|window| window.toplevel()) {
+            if !validate_toplevel_state(toplevel) {
+                return;
+            }
+        }
+
+        if let Some(window) = toplevel.filter(|window| {
+            self.common.shell.mapped_window(surface).as_ref() == Some(window)
+                && !with_renderer_surface_state(surface, |state| state.wl_buffer().is_some())
+        }) {
+            self.toplevel_unmapped(&window);
+        }
 
         // Map Windows, Layers, Popups
         if let Some((window, seat)) = self
@@ -40,15 +85,21 @@ impl CompositorHandler for State {
             .cloned()
         {
             match window.toplevel() {
-                Kind::Xdg(toplevel) => {
-                    if self.toplevel_ensure_initial_configure(&toplevel)
+                Kind::Xdg(_) => {
+                    // TODO: Active Output
+                    let output = active_output(&seat, &self.common);
+                    if self.toplevel_ensure_initial_configure(&window, &output)
                         && with_renderer_surface_state(&surface, |state| {
                             state.wl_buffer().is_some()
                         })
                     {
-                        // TODO: Active Output
-                        let output = active_output(&seat, &self.common);
+                        if let Some(app_id) = window.info().app_id {
+                            if self.common.config.private_apps.contains(&app_id) {
+                                window.set_private(true);
+                            }
+                        }
                         self.common.shell.map_window(&window, &output, dh);
+                        self.common.preload_switcher_icon(&window);
                     } else {
                         return;
                     }
@@ -80,6 +131,16 @@ impl CompositorHandler for State {
         for workspace in &self.common.shell.workspaces {
             workspace.space.commit(surface);
         }
+        if let Some(workspace) = self.common.shell.space_for_window_mut(surface) {
+            let window = workspace
+                .space
+                .window_for_surface(surface, WindowSurfaceType::TOPLEVEL)
+                .cloned();
+            if let Some(window) = window {
+                resize_commit(&mut workspace.space, &window);
+                workspace.place_pip(&window);
+            }
+        }
 
         if let Some(output) = self.common.shell.outputs().find(|o| {
             let map = layer_map_for_output(o);
@@ -92,8 +153,8 @@ impl CompositorHandler for State {
 }
 
 impl State {
-    fn toplevel_ensure_initial_configure(&mut self, toplevel: &ToplevelSurface) -> bool {
-        let initial_configure_sent = with_states(toplevel.wl_surface(), |states| {
+    fn toplevel_ensure_initial_configure(&mut self, window: &Window, output: &Output) -> bool {
+        let initial_configure_sent = with_states(window.toplevel().wl_surface(), |states| {
             states
                 .data_map
                 .get::<Mutex<XdgToplevelSurfaceRoleAttributes>>()
@@ -103,8 +164,11 @@ impl State {
                 .initial_configure_sent
         });
         if !initial_configure_sent {
-            toplevel.with_pending_state(|states| states.size = None);
-            toplevel.send_configure();
+            // The client waits for it before its first buffer, there is nothing to gather. The
+            // bounds keep it from picking a size larger than the output it will be mapped on.
+            configure(window, |state| state.size = None);
+            set_bounds(window, output);
+            flush(window);
         }
         initial_configure_sent
     }
@@ -141,9 +205,21 @@ impl State {
                 .initial_configure_sent
         });
         if !initial_configure_sent {
-            popup.send_configure().expect("initial configure failed");
+            if let Err(err) = popup.send_configure() {
+                slog_scope::warn!("Initial configure of popup failed: {:?}", err);
+            }
         }
     }
 }
 
+/// Whether `surface` just committed a null buffer
+fn removes_buffer(surface: &WlSurface) -> bool {
+    with_states(surface, |states| {
+        matches!(
+            states.cached_state.current::<SurfaceAttributes>().buffer,
+            Some(BufferAssignment::Removed)
+        )
+    })
+}
+
 delegate_compositor!(State);
@@ -0,0 +1,151 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::sync::Mutex;
+
+use smithay::{
+    reexports::{
+        wayland_protocols::wp::content_type::v1::server::{
+            wp_content_type_manager_v1::{self, WpContentTypeManagerV1},
+            wp_content_type_v1::{self, WpContentTypeV1},
+        },
+        wayland_server::{
+            protocol::wl_surface::WlSurface, Client, DataInit, Dispatch, DisplayHandle,
+            GlobalDispatch, New, Resource, WEnum,
+        },
+    },
+    utils::IsAlive,
+    wayland::compositor::with_states,
+};
+
+use super::State;
+
+pub use wp_content_type_v1::Type as ContentType;
+
+/// Double-buffered content type of a surface
+struct ContentTypeState {
+    has_object: bool,
+    pending: ContentType,
+    current: ContentType,
+}
+
+type ContentTypeData = Mutex<ContentTypeState>;
+
+/// The content type hint of `surface`, `ContentType::None` if the client didn't set one
+pub fn surface_content_type(surface: &WlSurface) -> ContentType {
+    with_states(surface, |states| {
+        states
+            .data_map
+            .get::<ContentTypeData>()
+            .map(|state| state.lock().unwrap().current)
+            .unwrap_or(ContentType::None)
+    })
+}
+
+/// Applies the pending content type, called on every commit
+pub fn commit_content_type(surface: &WlSurface) {
+    with_states(surface, |states| {
+        if let Some(state) = states.data_map.get::<ContentTypeData>() {
+            let mut state = state.lock().unwrap();
+            state.current = state.pending;
+        }
+    });
+}
+
+pub fn init_content_type(dh: &DisplayHandle) {
+    dh.create_global::<State, WpContentTypeManagerV1, _>(1, ());
+}
+
+impl GlobalDispatch<WpContentTypeManagerV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<WpContentTypeManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<WpContentTypeManagerV1, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        resource: &WpContentTypeManagerV1,
+        request: wp_content_type_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_content_type_manager_v1::Request::GetSurfaceContentType { id, surface } => {
+                let already_constructed = with_states(&surface, |states| {
+                    states.data_map.insert_if_missing_threadsafe(|| {
+                        Mutex::new(ContentTypeState {
+                            has_object: false,
+                            pending: ContentType::None,
+                            current: ContentType::None,
+                        })
+                    });
+                    let mut state = states
+                        .data_map
+                        .get::<ContentTypeData>()
+                        .unwrap()
+                        .lock()
+                        .unwrap();
+                    std::mem::replace(&mut state.has_object, true)
+                });
+
+                if already_constructed {
+                    resource.post_error(
+                        wp_content_type_manager_v1::Error::AlreadyConstructed,
+                        "surface already has a content type object",
+                    );
+                    return;
+                }
+                data_init.init(id, surface);
+            }
+            wp_content_type_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<WpContentTypeV1, WlSurface> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WpContentTypeV1,
+        request: wp_content_type_v1::Request,
+        surface: &WlSurface,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        let update = |f: &dyn Fn(&mut ContentTypeState)| {
+            if surface.alive() {
+                with_states(surface, |states| {
+                    if let Some(state) = states.data_map.get::<ContentTypeData>() {
+                        f(&mut state.lock().unwrap());
+                    }
+                });
+            }
+        };
+
+        match request {
+            wp_content_type_v1::Request::SetContentType { content_type } => {
+                let content_type = match content_type {
+                    WEnum::Value(content_type) => content_type,
+                    WEnum::Unknown(_) => ContentType::None,
+                };
+                update(&|state| state.pending = content_type);
+            }
+            // Destroying the object resets the content type with the next commit
+            wp_content_type_v1::Request::Destroy => update(&|state| {
+                state.has_object = false;
+                state.pending = ContentType::None;
+            }),
+            _ => unreachable!(),
+        }
+    }
+}
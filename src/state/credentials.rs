@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    fs,
+    os::unix::{io::AsRawFd, net::UnixStream},
+    path::PathBuf,
+};
+
+use serde::Serialize;
+use smithay::{
+    desktop::Window,
+    reexports::wayland_server::{protocol::wl_surface::WlSurface, DisplayHandle, Resource},
+};
+
+use super::ClientState;
+
+/// Who is on the other end of a client socket, taken once when it connects
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientCredentials {
+    pub pid: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// `None` if the process is gone or belongs to someone else
+    pub executable: Option<PathBuf>,
+}
+
+impl ClientCredentials {
+    pub fn from_stream(stream: &UnixStream) -> Option<Self> {
+        let credentials = match nix::sys::socket::getsockopt(
+            stream.as_raw_fd(),
+            nix::sys::socket::sockopt::PeerCredentials,
+        ) {
+            Ok(credentials) => credentials,
+            Err(err) => {
+                slog_scope::warn!("Failed to get client credentials: {}", err);
+                return None;
+            }
+        };
+        let pid = credentials.pid() as u32;
+        Some(Self {
+            pid,
+            uid: credentials.uid(),
+            gid: credentials.gid(),
+            executable: fs::read_link(format!("/proc/{}/exe", pid)).ok(),
+        })
+    }
+
+    /// Credentials of the client owning `surface`
+    pub fn of_surface(dh: &DisplayHandle, surface: &WlSurface) -> Option<Self> {
+        dh.get_client(surface.id())
+            .ok()?
+            .get_data::<ClientState>()?
+            .credentials
+            .clone()
+    }
+}
+
+struct WindowCredentials(Option<ClientCredentials>);
+
+/// Remembers the credentials of the client of a new window, so they outlive the client
+pub fn set_window_credentials(window: &Window, credentials: Option<ClientCredentials>) {
+    window
+        .user_data()
+        .insert_if_missing(|| WindowCredentials(credentials));
+}
+
+pub fn window_credentials(window: &Window) -> Option<ClientCredentials> {
+    window
+        .user_data()
+        .get::<WindowCredentials>()
+        .and_then(|credentials| credentials.0.clone())
+}
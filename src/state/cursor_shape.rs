@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::cell::{Cell, RefCell};
+
+use smithay::{
+    reexports::wayland_server::{
+        protocol::{wl_pointer::WlPointer, wl_surface::WlSurface},
+        Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+    },
+    wayland::{
+        seat::{CursorImageStatus, Seat},
+        Serial,
+    },
+};
+
+use super::State;
+
+use self::generated::{
+    wp_cursor_shape_device_v1::{self, WpCursorShapeDeviceV1},
+    wp_cursor_shape_manager_v1::{self, WpCursorShapeManagerV1},
+};
+
+#[allow(non_upper_case_globals, non_camel_case_types, unused)]
+mod generated {
+    use smithay::reexports::wayland_protocols::wp::tablet::zv2::server::*;
+    use smithay::reexports::wayland_server;
+    use smithay::reexports::wayland_server::protocol::*;
+
+    pub mod __interfaces {
+        use smithay::reexports::wayland_protocols::wp::tablet::zv2::__interfaces::*;
+        use smithay::reexports::wayland_server::backend as wayland_backend;
+        use smithay::reexports::wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("resources/protocols/cursor-shape-v1.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("resources/protocols/cursor-shape-v1.xml");
+}
+
+pub use wp_cursor_shape_device_v1::Shape;
+
+/// The device a cursor shape object was created for
+pub enum CursorShapeDevice {
+    /// With the seat of the pointer, `None` if no seat has one
+    Pointer(Option<Seat<State>>),
+    /// There are no tablets yet, shapes set for tools go nowhere
+    TabletTool,
+}
+
+/// The surface the pointer of a seat last entered, with the serial of the enter event
+#[derive(Default)]
+struct PointerEnter(RefCell<Option<(WlSurface, Serial)>>);
+
+/// Shape a client picked for the cursor of a seat instead of a surface, only used while the
+/// cursor status is `CursorImageStatus::Default`
+#[derive(Default)]
+struct CursorShape(Cell<Option<Shape>>);
+
+/// The named cursor the host shows for `seat`
+pub fn cursor_shape(seat: &Seat<State>) -> Shape {
+    seat.user_data()
+        .get::<CursorShape>()
+        .and_then(|shape| shape.0.get())
+        .unwrap_or(Shape::Default)
+}
+
+/// Notes that the pointer of `seat` moves to `focus` with `serial`, which is an enter if the
+/// surface changed. Grabs decide the focus themselves, so this is only called for motion
+/// without a grab and by grabs that move the focus.
+pub fn pointer_entered(seat: &Seat<State>, focus: Option<&WlSurface>, serial: Serial) {
+    seat.user_data().insert_if_missing(PointerEnter::default);
+    let mut enter = seat
+        .user_data()
+        .get::<PointerEnter>()
+        .unwrap()
+        .0
+        .borrow_mut();
+    if enter.as_ref().map(|(surface, _)| surface) != focus {
+        *enter = focus.map(|surface| (surface.clone(), serial));
+    }
+}
+
+/// Whether the pointer of `seat` is on a surface of the client owning `resource`, with `serial`
+/// as the serial of its enter event if one is given
+fn client_has_pointer(
+    seat: &Seat<State>,
+    resource: &impl Resource,
+    serial: Option<Serial>,
+) -> bool {
+    let enter = match seat.user_data().get::<PointerEnter>() {
+        Some(enter) => enter.0.borrow(),
+        None => return false,
+    };
+    match &*enter {
+        Some((surface, entered)) => {
+            surface.id().same_client_as(&resource.id())
+                && serial.map_or(true, |serial| serial == *entered)
+        }
+        None => false,
+    }
+}
+
+/// A cursor surface or hiding the cursor replaces the shape
+pub fn reset_cursor_shape(seat: &Seat<State>) {
+    if let Some(shape) = seat.user_data().get::<CursorShape>() {
+        shape.0.set(None);
+    }
+}
+
+pub fn init_cursor_shape(dh: &DisplayHandle) {
+    dh.create_global::<State, WpCursorShapeManagerV1, _>(1, ());
+}
+
+impl State {
+    /// The seat of `pointer`. A wl_pointer does not tell, so with more than one seat it is the
+    /// one whose pointer is on a surface of its client, else the first one with a pointer.
+    fn pointer_seat(&self, pointer: &WlPointer) -> Option<Seat<State>> {
+        let seats = &self.common.seats;
+        seats
+            .iter()
+            .find(|seat| client_has_pointer(seat, pointer, None))
+            .or_else(|| seats.iter().find(|seat| seat.get_pointer().is_some()))
+            .cloned()
+    }
+}
+
+impl GlobalDispatch<WpCursorShapeManagerV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<WpCursorShapeManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<WpCursorShapeManagerV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &WpCursorShapeManagerV1,
+        request: wp_cursor_shape_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_cursor_shape_manager_v1::Request::GetPointer {
+                cursor_shape_device,
+                pointer,
+            } => {
+                let seat = state.pointer_seat(&pointer);
+                data_init.init(cursor_shape_device, CursorShapeDevice::Pointer(seat));
+            }
+            wp_cursor_shape_manager_v1::Request::GetTabletToolV2 {
+                cursor_shape_device,
+                ..
+            } => {
+                data_init.init(cursor_shape_device, CursorShapeDevice::TabletTool);
+            }
+            wp_cursor_shape_manager_v1::Request::Destroy => {}
+        }
+    }
+}
+
+impl Dispatch<WpCursorShapeDeviceV1, CursorShapeDevice> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        resource: &WpCursorShapeDeviceV1,
+        request: wp_cursor_shape_device_v1::Request,
+        device: &CursorShapeDevice,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_cursor_shape_device_v1::Request::SetShape { serial, shape } => {
+                let shape = match shape {
+                    WEnum::Value(shape) => shape,
+                    WEnum::Unknown(value) => {
+                        resource.post_error(
+                            wp_cursor_shape_device_v1::Error::InvalidShape,
+                            format!("unknown cursor shape {}", value),
+                        );
+                        return;
+                    }
+                };
+                let seat = match device {
+                    CursorShapeDevice::Pointer(Some(seat)) => seat,
+                    _ => return,
+                };
+                // Like a cursor surface, only the client the pointer entered can change it, and
+                // only for the latest enter
+                if !client_has_pointer(seat, resource, Some(Serial::from(serial))) {
+                    return;
+                }
+                seat.user_data().insert_if_missing(CursorShape::default);
+                seat.user_data()
+                    .get::<CursorShape>()
+                    .unwrap()
+                    .0
+                    .set(Some(shape));
+                if let Some(status) = seat.user_data().get::<RefCell<CursorImageStatus>>() {
+                    *status.borrow_mut() = CursorImageStatus::Default;
+                }
+            }
+            wp_cursor_shape_device_v1::Request::Destroy => {}
+        }
+    }
+}
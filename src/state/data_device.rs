@@ -4,33 +4,40 @@ use std::cell::RefCell;
 
 use smithay::{
     delegate_data_device,
-    reexports::wayland_server::protocol::{wl_data_source::WlDataSource, wl_surface::WlSurface},
+    reexports::wayland_server::{
+        protocol::{wl_data_source::WlDataSource, wl_surface::WlSurface},
+        Resource,
+    },
     wayland::{
         data_device::{
-            ClientDndGrabHandler, DataDeviceHandler, DataDeviceState, ServerDndGrabHandler,
+            with_source_metadata, ClientDndGrabHandler, DataDeviceHandler, DataDeviceState,
         },
         seat::Seat,
     },
 };
 
-use super::State;
+use super::{selection::SelectionKind, State};
 
 pub struct DnDIcon {
     surface: RefCell<Option<WlSurface>>,
 }
 
 impl ClientDndGrabHandler for State {
-    fn started(
-        &mut self,
-        _source: Option<WlDataSource>,
-        icon: Option<WlSurface>,
-        seat: Seat<Self>,
-    ) {
+    fn started(&mut self, source: Option<WlDataSource>, icon: Option<WlSurface>, seat: Seat<Self>) {
         let user_data = seat.user_data();
         user_data.insert_if_missing(|| DnDIcon {
             surface: RefCell::new(None),
         });
         *user_data.get::<DnDIcon>().unwrap().surface.borrow_mut() = icon;
+        if let Some(source) = source {
+            let mime_types = with_source_metadata(&source, |metadata| metadata.mime_types.clone())
+                .unwrap_or_default();
+            let app_id = self.common.source_app_id(source.id());
+            self.common
+                .transfers
+                .drag_started(&seat, source.id(), app_id, mime_types);
+            self.toplevel_drag_started(&source, &seat);
+        }
     }
     fn dropped(&mut self, seat: Seat<Self>) {
         seat.user_data()
@@ -39,13 +46,25 @@ impl ClientDndGrabHandler for State {
             .surface
             .borrow_mut()
             .take();
+        self.common.transfers.drag_ended(&seat);
+        self.toplevel_drag_dropped(&seat);
     }
 }
-impl ServerDndGrabHandler for State {}
 impl DataDeviceHandler for State {
     fn data_device_state(&self) -> &DataDeviceState {
         &self.common.data_device_state
     }
+
+    fn new_selection(&mut self, source: Option<WlDataSource>) {
+        let mime_types = source.as_ref().map(|source| {
+            with_source_metadata(source, |metadata| metadata.mime_types.clone()).unwrap_or_default()
+        });
+        self.common.selection_changed(
+            SelectionKind::Clipboard,
+            source.map(|source| source.id()),
+            mime_types,
+        );
+    }
 }
 
 delegate_data_device!(State);
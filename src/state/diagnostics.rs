@@ -0,0 +1,222 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use smithay::{
+    backend::renderer::utils::with_renderer_surface_state,
+    reexports::wayland_server::{
+        backend::{ClientId, DisconnectReason, ObjectId},
+        protocol::{wl_buffer::WlBuffer, wl_surface::WlSurface},
+        DisplayHandle, Resource,
+    },
+    utils::IsAlive,
+    wayland::compositor::{with_states, SurfaceAttributes},
+};
+
+use super::ClientState;
+
+/// How many protocol errors are remembered after their client disconnected
+const PROTOCOL_ERROR_HISTORY: usize = 32;
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+/// Buffers and surfaces tracked per client at most, more are left out of its report
+const MAX_TRACKED: usize = 256;
+
+/// Resource usage of a single client, updated on every commit
+#[derive(Default)]
+pub struct ClientDiagnostics {
+    commits: AtomicU64,
+    frame_callbacks: AtomicU64,
+    inner: Mutex<ClientDiagnosticsInner>,
+}
+
+#[derive(Default)]
+struct ClientDiagnosticsInner {
+    /// Pruned of destroyed ones once per `RATE_WINDOW`
+    buffers: HashMap<ObjectId, WlBuffer>,
+    surfaces: HashMap<ObjectId, (WlSurface, (i32, i32))>,
+    window_start: Option<Instant>,
+    window_callbacks: u64,
+    frame_callback_rate: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProtocolErrorRecord {
+    pub client: String,
+    pub code: u32,
+    pub object: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientReport {
+    pub client: String,
+    pub commits: u64,
+    pub frame_callbacks: u64,
+    /// Frame callbacks requested per second over the last measured second
+    pub frame_callback_rate: f64,
+    pub buffers: usize,
+    /// Buffer sizes of all live surfaces
+    pub surface_sizes: Vec<(i32, i32)>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticsReport {
+    pub clients: Vec<ClientReport>,
+    pub protocol_errors: Vec<ProtocolErrorRecord>,
+}
+
+/// Registry of all connected clients, shared with their `ClientState`
+#[derive(Default, Clone)]
+pub struct Diagnostics {
+    clients: Arc<Mutex<HashMap<ClientId, Arc<ClientDiagnostics>>>>,
+    protocol_errors: Arc<Mutex<VecDeque<ProtocolErrorRecord>>>,
+}
+
+impl Diagnostics {
+    pub fn client_connected(&self, client_id: ClientId, diagnostics: Arc<ClientDiagnostics>) {
+        self.clients.lock().unwrap().insert(client_id, diagnostics);
+    }
+
+    pub fn client_disconnected(&self, client_id: ClientId, reason: &DisconnectReason) {
+        self.clients.lock().unwrap().remove(&client_id);
+
+        if let DisconnectReason::ProtocolError(err) = reason {
+            slog_scope::warn!(
+                "Client {:?} disconnected with protocol error {} on {}@{}: {}",
+                client_id,
+                err.code,
+                err.object_interface,
+                err.object_id,
+                err.message
+            );
+            let mut errors = self.protocol_errors.lock().unwrap();
+            if errors.len() == PROTOCOL_ERROR_HISTORY {
+                errors.pop_front();
+            }
+            errors.push_back(ProtocolErrorRecord {
+                client: format!("{:?}", client_id),
+                code: err.code,
+                object: format!("{}@{}", err.object_interface, err.object_id),
+                message: err.message.clone(),
+            });
+        }
+    }
+
+    /// Ids of all connected clients
+    pub fn client_ids(&self) -> Vec<ClientId> {
+        self.clients.lock().unwrap().keys().cloned().collect()
+    }
+
+    pub fn report(&self) -> DiagnosticsReport {
+        DiagnosticsReport {
+            clients: self
+                .clients
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(id, diagnostics)| diagnostics.report(id))
+                .collect(),
+            protocol_errors: self
+                .protocol_errors
+                .lock()
+                .unwrap()
+                .iter()
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+impl ClientDiagnostics {
+    pub fn commit(&self, surface: &WlSurface) {
+        self.commits.fetch_add(1, Ordering::Relaxed);
+
+        let callbacks = with_states(surface, |states| {
+            states
+                .cached_state
+                .current::<SurfaceAttributes>()
+                .frame_callbacks
+                .len() as u64
+        });
+        self.frame_callbacks.fetch_add(callbacks, Ordering::Relaxed);
+
+        let (buffer, size) = with_renderer_surface_state(surface, |state| {
+            (
+                state.wl_buffer().cloned(),
+                state.buffer_size().map(|size| (size.w, size.h)),
+            )
+        });
+
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(buffer) = buffer {
+            let id = buffer.id();
+            if inner.buffers.len() < MAX_TRACKED || inner.buffers.contains_key(&id) {
+                inner.buffers.insert(id, buffer);
+            }
+        }
+        let id = surface.id();
+        let tracked = inner.surfaces.len() < MAX_TRACKED || inner.surfaces.contains_key(&id);
+        match size {
+            Some(size) if tracked => {
+                inner.surfaces.insert(id, (surface.clone(), size));
+            }
+            Some(_) => {}
+            None => {
+                inner.surfaces.remove(&id);
+            }
+        }
+
+        let now = Instant::now();
+        let window_start = *inner.window_start.get_or_insert(now);
+        inner.window_callbacks += callbacks;
+        let elapsed = now.duration_since(window_start);
+        if elapsed >= RATE_WINDOW {
+            inner.frame_callback_rate = inner.window_callbacks as f64 / elapsed.as_secs_f64();
+            inner.window_callbacks = 0;
+            inner.window_start = Some(now);
+            inner.prune();
+        }
+    }
+
+    fn report(&self, client_id: &ClientId) -> ClientReport {
+        let mut inner = self.inner.lock().unwrap();
+        inner.prune();
+
+        ClientReport {
+            client: format!("{:?}", client_id),
+            commits: self.commits.load(Ordering::Relaxed),
+            frame_callbacks: self.frame_callbacks.load(Ordering::Relaxed),
+            frame_callback_rate: inner.frame_callback_rate,
+            buffers: inner.buffers.len(),
+            surface_sizes: inner.surfaces.values().map(|(_, size)| *size).collect(),
+        }
+    }
+}
+
+impl ClientDiagnosticsInner {
+    /// Forgets buffers and surfaces the client destroyed
+    fn prune(&mut self) {
+        self.buffers.retain(|_, buffer| buffer.alive());
+        self.surfaces.retain(|_, (surface, _)| surface.alive());
+    }
+}
+
+/// Records a commit of `surface` for the owning client
+pub fn track_commit(dh: &DisplayHandle, surface: &WlSurface) {
+    if let Ok(client) = dh.get_client(surface.id()) {
+        if let Some(client_state) = client.get_data::<ClientState>() {
+            client_state.diagnostics.commit(surface);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fs::File,
+    io::{self, Write},
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    rc::Rc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail};
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use smithay::{
+    reexports::{
+        calloop::{
+            generic::Generic,
+            timer::{TimeoutAction, Timer},
+            Interest, LoopHandle, Mode, PostAction,
+        },
+        wayland_server::{protocol::wl_data_device_manager::DndAction, DisplayHandle},
+    },
+    wayland::{
+        data_device::{start_dnd, ServerDndGrabHandler, SourceMetadata},
+        SERIAL_COUNTER,
+    },
+};
+
+use super::{LoopData, State};
+use crate::{runtime::messages::RuntimeMessage, shell::grab::grabbed_window};
+
+/// How long the drop target gets to read the data of a drag before it is closed on it
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A drag the runtime started, with the data it offers by mime type
+#[derive(Debug, Default)]
+pub struct ServerDrag {
+    offers: HashMap<String, Vec<u8>>,
+    action: Option<DndAction>,
+    dropped: bool,
+}
+
+fn action_name(action: Option<DndAction>) -> Option<&'static str> {
+    let action = action?;
+    if action.contains(DndAction::Move) {
+        Some("move")
+    } else if action.contains(DndAction::Copy) {
+        Some("copy")
+    } else if action.contains(DndAction::Ask) {
+        Some("ask")
+    } else {
+        None
+    }
+}
+
+impl State {
+    /// Turns the pointer button the last active seat is holding down into a drag offering
+    /// `offers`, e.g. `text/uri-list` for files out of a launcher
+    pub fn start_drag(
+        &mut self,
+        dh: &DisplayHandle,
+        offers: HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        if offers.is_empty() {
+            bail!("A drag needs at least one mime type");
+        }
+        let seat = self.common.last_active_seat.clone();
+        let pointer = seat
+            .get_pointer()
+            .ok_or_else(|| anyhow!("The seat has no pointer"))?;
+        if grabbed_window(&seat).is_some() {
+            bail!("The pointer is moving a window");
+        }
+        // Pressing a button grabs the pointer until it is let go, that grab becomes the drag
+        let start_data = pointer
+            .grab_start_data()
+            .ok_or_else(|| anyhow!("No pointer button is held down"))?;
+
+        let metadata = SourceMetadata {
+            mime_types: offers.keys().cloned().collect(),
+            dnd_action: DndAction::Copy | DndAction::Move,
+        };
+        self.common.server_drag = Some(ServerDrag {
+            offers: offers
+                .into_iter()
+                .map(|(mime_type, data)| (mime_type, data.into_bytes()))
+                .collect(),
+            ..ServerDrag::default()
+        });
+        start_dnd(
+            dh,
+            &seat,
+            SERIAL_COUNTER.next_serial(),
+            start_data,
+            metadata,
+        );
+        Ok(())
+    }
+
+    fn drag_ended(&mut self, dropped: bool) {
+        let action = self
+            .common
+            .server_drag
+            .as_ref()
+            .and_then(|drag| drag.action);
+        let runtime_sender = &self.common.shell.active_workspace().runtime_sender;
+        let _ = runtime_sender.send(RuntimeMessage::DragEnded {
+            dropped,
+            action: action_name(action).filter(|_| dropped).map(String::from),
+        });
+    }
+}
+
+impl ServerDndGrabHandler for State {
+    fn action(&mut self, action: DndAction) {
+        if let Some(drag) = &mut self.common.server_drag {
+            drag.action = Some(action);
+        }
+    }
+
+    fn dropped(&mut self) {
+        if let Some(drag) = &mut self.common.server_drag {
+            drag.dropped = true;
+        }
+        self.drag_ended(true);
+    }
+
+    fn cancelled(&mut self) {
+        let dropped = self
+            .common
+            .server_drag
+            .take()
+            .map_or(false, |drag| drag.dropped);
+        // The drop was already reported
+        if !dropped {
+            self.drag_ended(false);
+        }
+    }
+
+    fn send(&mut self, mime_type: String, fd: RawFd) {
+        // Safe, the fd is ours to close
+        let file = unsafe { File::from_raw_fd(fd) };
+        let data = match self
+            .common
+            .server_drag
+            .as_ref()
+            .and_then(|drag| drag.offers.get(&mime_type))
+        {
+            Some(data) => data.clone(),
+            None => {
+                slog_scope::debug!("Drag data for {} requested, but not offered", mime_type);
+                return;
+            }
+        };
+        if let Err(err) = send_later(&self.common.event_loop_handle, file, data) {
+            slog_scope::debug!("Failed to send drag data: {}", err);
+        }
+    }
+
+    fn finished(&mut self) {
+        self.common.server_drag = None;
+    }
+}
+
+/// Writes `data` to `file` from the event loop whenever the client reading it makes room, and
+/// closes it after `SEND_TIMEOUT` if the client doesn't get to it
+fn send_later(
+    handle: &LoopHandle<'static, LoopData>,
+    file: File,
+    data: Vec<u8>,
+) -> anyhow::Result<()> {
+    fcntl(file.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+    let done = Rc::new(Cell::new(false));
+
+    let finished = done.clone();
+    let mut written = 0;
+    let token = handle
+        .insert_source(
+            Generic::new(file, Interest::WRITE, Mode::Level),
+            move |_, file, _| {
+                while written < data.len() {
+                    match file.write(&data[written..]) {
+                        Ok(n) => written += n,
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                            return Ok(PostAction::Continue)
+                        }
+                        Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                        Err(err) => {
+                            slog_scope::debug!("Failed to send drag data: {}", err);
+                            break;
+                        }
+                    }
+                }
+                finished.set(true);
+                Ok(PostAction::Remove)
+            },
+        )
+        .map_err(|_| anyhow!("Failed to insert drag data source"))?;
+
+    handle
+        .insert_source(Timer::from_duration(SEND_TIMEOUT), move |_, _, data| {
+            // Once it is done the token may already belong to another source
+            if !done.get() {
+                slog_scope::debug!("The drop target did not read the drag data in time");
+                data.state.common.event_loop_handle.remove(token);
+            }
+            TimeoutAction::Drop
+        })
+        .map_err(|_| anyhow!("Failed to insert drag data timer"))?;
+    Ok(())
+}
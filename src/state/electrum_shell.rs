@@ -0,0 +1,196 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    fs::File,
+    os::unix::io::{AsRawFd, RawFd},
+};
+
+use smithay::{
+    reexports::wayland_server::{
+        backend::{ClientId, ObjectId},
+        protocol::wl_shm,
+        Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+    },
+    utils::Rectangle,
+    wayland::output::Output,
+};
+
+use super::{access::ELECTRUM_SHELL, ClientState, CommonState, State};
+use crate::{
+    runtime::messages::RuntimeMessage,
+    shell::{
+        autohide::VisibilityChange,
+        layout::{release_area, reserve_area},
+    },
+};
+
+use self::generated::{
+    electrum_shell_reservation_v1::{self, ElectrumShellReservationV1},
+    electrum_shell_v1::{self, ElectrumShellV1},
+};
+
+#[allow(non_upper_case_globals, non_camel_case_types, unused)]
+mod generated {
+    use smithay::reexports::wayland_server;
+    use smithay::reexports::wayland_server::protocol::*;
+
+    pub mod __interfaces {
+        use smithay::reexports::wayland_server::backend as wayland_backend;
+        use smithay::reexports::wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("resources/protocols/electrum-shell-v1.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("resources/protocols/electrum-shell-v1.xml");
+}
+
+pub fn init_electrum_shell(dh: &DisplayHandle) {
+    dh.create_global::<State, ElectrumShellV1, _>(2, ());
+}
+
+/// Bound `electrum_shell_v1` objects, for events that are not answers to a request
+#[derive(Default)]
+pub struct ShellClients(Vec<ElectrumShellV1>);
+
+impl ShellClients {
+    pub fn panel_visibility(&self, change: &VisibilityChange) {
+        for shell in self.0.iter().filter(|shell| shell.version() >= 2) {
+            shell.panel_visibility(
+                change.namespace.clone(),
+                change.output.clone(),
+                change.visible as u32,
+            );
+        }
+    }
+}
+
+impl GlobalDispatch<ElectrumShellV1, ()> for State {
+    fn bind(
+        state: &mut Self,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ElectrumShellV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let shell = data_init.init(resource, ());
+        state.common.electrum_shells.0.push(shell);
+    }
+
+    /// Only clients listed in `shell_clients` or an access rule see it, the others can't bind it
+    fn can_view(client: Client, _global_data: &()) -> bool {
+        client
+            .get_data::<ClientState>()
+            .map(|data| data.access.may_view(client.id(), ELECTRUM_SHELL))
+            .unwrap_or(false)
+    }
+}
+
+impl Dispatch<ElectrumShellV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ElectrumShellV1,
+        request: electrum_shell_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            electrum_shell_v1::Request::GetWindows => {
+                for info in state.common.shell.window_infos() {
+                    resource.window(info.id as u32, info.title, info.app_id, info.urgent as u32);
+                }
+                resource.windows_done();
+            }
+            electrum_shell_v1::Request::GetThumbnail { window, max_size } => {
+                state.common.send_thumbnail(resource, window, max_size);
+            }
+            electrum_shell_v1::Request::SwitchWorkspace { index } => {
+                let runtime_sender = &state.common.shell.active_workspace().runtime_sender;
+                let _ = runtime_sender.send(RuntimeMessage::WorkspaceRequested { index });
+            }
+            electrum_shell_v1::Request::ReserveArea {
+                id,
+                output,
+                x,
+                y,
+                width,
+                height,
+            } => {
+                if width <= 0 || height <= 0 {
+                    resource.post_error(
+                        electrum_shell_v1::Error::InvalidArea,
+                        "Reserved area has no size",
+                    );
+                    return;
+                }
+                let output = Output::from_resource(&output);
+                let reservation = data_init.init(id, output.clone());
+                if let Some(output) = output {
+                    let area = Rectangle::from_loc_and_size((x, y), (width, height));
+                    reserve_area(&output, reservation.id(), area);
+                }
+            }
+            electrum_shell_v1::Request::Destroy => {}
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .common
+            .electrum_shells
+            .0
+            .retain(|shell| shell.id() != resource);
+    }
+}
+
+impl Dispatch<ElectrumShellReservationV1, Option<Output>> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ElectrumShellReservationV1,
+        request: electrum_shell_reservation_v1::Request,
+        _data: &Option<Output>,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            electrum_shell_reservation_v1::Request::Destroy => {}
+        }
+    }
+
+    fn destroyed(_state: &mut Self, _client: ClientId, resource: ObjectId, data: &Option<Output>) {
+        if let Some(output) = data {
+            release_area(output, &resource);
+        }
+    }
+}
+
+impl CommonState {
+    fn send_thumbnail(&mut self, resource: &ElectrumShellV1, window: u32, max_size: u32) {
+        let thumbnail = self
+            .request_thumbnail(window as usize, max_size.max(1))
+            .ok()
+            .flatten();
+        let file = thumbnail
+            .as_ref()
+            .and_then(|thumbnail| File::open(&thumbnail.path).ok());
+
+        match (thumbnail, file) {
+            (Some(thumbnail), Some(file)) => {
+                let fd: RawFd = file.as_raw_fd();
+                resource.thumbnail(
+                    window,
+                    fd,
+                    thumbnail.width,
+                    thumbnail.height,
+                    thumbnail.stride,
+                    wl_shm::Format::Abgr8888 as u32,
+                    thumbnail.serial as u32,
+                );
+            }
+            _ => resource.thumbnail_unavailable(window),
+        }
+    }
+}
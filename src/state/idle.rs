@@ -0,0 +1,264 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use smithay::{
+    reexports::calloop::{
+        timer::{TimeoutAction, Timer},
+        RegistrationToken,
+    },
+    utils::Rectangle,
+    wayland::output::Output,
+};
+
+use super::{
+    content_type::{surface_content_type, ContentType},
+    CommonState,
+};
+use crate::{
+    backend::renderer::{element::SolidColorElement, CustomElem},
+    runtime::messages::RuntimeMessage,
+    shell::window::WindowExt,
+};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct IdleConfig {
+    /// Seconds without input until outputs dim, 0 never dims
+    pub dim: u64,
+    /// Brightness of dimmed outputs, from 0 to 1
+    pub dim_brightness: f32,
+    /// Seconds without input until outputs turn off, 0 never turns them off
+    pub off: u64,
+    /// Seconds without input until the runtime is asked to lock the session, 0 never asks
+    pub lock: u64,
+    /// Milliseconds a change of brightness fades over
+    pub fade: u64,
+    /// Keep outputs on while a fullscreen window shows a video or game, also without the
+    /// script inhibiting idling
+    pub inhibit_fullscreen_media: bool,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self {
+            dim: 0,
+            dim_brightness: 0.4,
+            off: 0,
+            lock: 0,
+            fade: 1000,
+            inhibit_fullscreen_media: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleStage {
+    Active,
+    Dimmed,
+    Off,
+}
+
+impl IdleStage {
+    pub fn name(self) -> &'static str {
+        match self {
+            IdleStage::Active => "active",
+            IdleStage::Dimmed => "dim",
+            IdleStage::Off => "off",
+        }
+    }
+}
+
+/// How long there was no input and how far outputs went dark because of it
+pub struct IdleState {
+    last_activity: Instant,
+    stage: IdleStage,
+    /// Set once the runtime was asked to lock in this idle period
+    lock_requested: bool,
+    /// Set by the runtime, the stages don't advance meanwhile
+    inhibited: bool,
+    /// Brightness when the last fade started, and when it started
+    fade_start: (f32, Instant),
+    /// When the event loop is woken up next to advance the stages, by the one timer doing so
+    wakeup: Option<(Instant, RegistrationToken)>,
+}
+
+impl Default for IdleState {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            last_activity: now,
+            stage: IdleStage::Active,
+            lock_requested: false,
+            inhibited: false,
+            fade_start: (1.0, now),
+            wakeup: None,
+        }
+    }
+}
+
+impl IdleState {
+    fn target_brightness(&self, config: &IdleConfig) -> f32 {
+        match self.stage {
+            IdleStage::Active => 1.0,
+            IdleStage::Dimmed => config.dim_brightness.clamp(0.0, 1.0),
+            IdleStage::Off => 0.0,
+        }
+    }
+
+    /// Brightness outputs are shown at right now, somewhere in a fade to the current stage
+    pub fn brightness(&self, config: &IdleConfig) -> f32 {
+        let target = self.target_brightness(config);
+        let (from, started) = self.fade_start;
+        let fade = Duration::from_millis(config.fade);
+        if fade.is_zero() || started.elapsed() >= fade {
+            return target;
+        }
+        let t = started.elapsed().as_secs_f32() / fade.as_secs_f32();
+        from + (target - from) * t
+    }
+
+    fn set_stage(&mut self, stage: IdleStage, config: &IdleConfig) {
+        self.fade_start = (self.brightness(config), Instant::now());
+        self.stage = stage;
+    }
+
+    /// The stage `idle` of no input leads to
+    fn stage_after(idle: Duration, config: &IdleConfig) -> IdleStage {
+        let reached = |seconds: u64| seconds > 0 && idle >= Duration::from_secs(seconds);
+        if reached(config.off) {
+            IdleStage::Off
+        } else if reached(config.dim) {
+            IdleStage::Dimmed
+        } else {
+            IdleStage::Active
+        }
+    }
+
+    /// When the next stage or the lock is due
+    fn next_deadline(&self, config: &IdleConfig) -> Option<Instant> {
+        if self.inhibited {
+            return None;
+        }
+        let idle = self.last_activity.elapsed();
+        [config.dim, config.off]
+            .into_iter()
+            .chain((!self.lock_requested).then(|| config.lock))
+            .filter(|seconds| *seconds > 0)
+            .map(Duration::from_secs)
+            .filter(|timeout| *timeout > idle)
+            .min()
+            .map(|timeout| self.last_activity + timeout)
+    }
+}
+
+impl CommonState {
+    /// Input came in, outputs light up again and the idle time starts over
+    pub fn idle_activity(&mut self) {
+        self.idle.last_activity = Instant::now();
+        self.idle.lock_requested = false;
+        if self.idle.stage != IdleStage::Active {
+            self.idle.set_stage(IdleStage::Active, &self.config.idle);
+            self.report_idle_stage(IdleStage::Active.name());
+        }
+    }
+
+    /// Keeps the stages from advancing, or lets them again counting from now
+    pub fn set_idle_inhibited(&mut self, inhibited: bool) {
+        self.idle.inhibited = inhibited;
+        self.idle_activity();
+    }
+
+    /// Moves on to the stage the time without input leads to, once every loop iteration
+    pub fn refresh_idle(&mut self) {
+        if self.idle.inhibited {
+            return;
+        }
+        if self.config.idle.inhibit_fullscreen_media && self.fullscreen_media_visible() {
+            // Counts as input, the idle time starts once the window leaves fullscreen
+            self.idle_activity();
+            return;
+        }
+        let config = &self.config.idle;
+        let idle = self.idle.last_activity.elapsed();
+
+        let stage = IdleState::stage_after(idle, config);
+        if stage != self.idle.stage {
+            self.idle.set_stage(stage, config);
+            self.report_idle_stage(stage.name());
+        }
+        let lock_due = config.lock > 0 && idle >= Duration::from_secs(config.lock);
+        if lock_due && !self.idle.lock_requested {
+            self.idle.lock_requested = true;
+            self.report_idle_stage("lock");
+        }
+
+        // Only wakes up the event loop, the stage changes in the refresh after any dispatch. Input
+        // moves the deadline back, a timer due before it just wakes the loop early and the refresh
+        // then sets up the next one.
+        let deadline = match self.idle.next_deadline(&self.config.idle) {
+            Some(deadline) => deadline,
+            None => return,
+        };
+        if let Some((pending, token)) = self.idle.wakeup {
+            if pending <= deadline {
+                return;
+            }
+            self.event_loop_handle.remove(token);
+            self.idle.wakeup = None;
+        }
+        let timer = Timer::from_deadline(deadline);
+        let token = self.event_loop_handle.insert_source(timer, |_, _, data| {
+            data.state.common.idle.wakeup = None;
+            TimeoutAction::Drop
+        });
+        if let Ok(token) = token {
+            self.idle.wakeup = Some((deadline, token));
+        }
+    }
+
+    /// Whether a fullscreen window on the active workspace is marked as a video or a game, by
+    /// its content type or game mode
+    fn fullscreen_media_visible(&self) -> bool {
+        let workspace = self.shell.active_workspace();
+        self.shell
+            .outputs()
+            .filter_map(|output| workspace.get_fullscreen(output))
+            .any(|window| {
+                let content_type = surface_content_type(window.toplevel().wl_surface());
+                matches!(content_type, ContentType::Video | ContentType::Game) || window.game_mode()
+            })
+    }
+
+    fn report_idle_stage(&self, stage: &str) {
+        let runtime_sender = &self.shell.active_workspace().runtime_sender;
+        let _ = runtime_sender.send(RuntimeMessage::IdleStage {
+            stage: stage.to_string(),
+        });
+    }
+}
+
+/// Darkens `output` as far as idling dimmed it, drawn above everything else
+pub fn idle_element(state: &CommonState, output: &Output) -> Option<CustomElem> {
+    let brightness = state.idle.brightness(&state.config.idle);
+    if brightness >= 1.0 {
+        return None;
+    }
+    let alpha = 1.0 - brightness.clamp(0.0, 1.0);
+    let size = output
+        .current_mode()
+        .map(|mode| mode.size)
+        .unwrap_or_else(|| (0, 0).into())
+        .to_f64()
+        .to_logical(output.current_scale().fractional_scale())
+        .to_i32_round();
+    // The id changes with the brightness, so each step of a fade damages the whole output
+    let id = usize::MAX - (alpha * 255.0).round() as usize;
+    Some(CustomElem::from(SolidColorElement::with_id(
+        id,
+        Rectangle::from_loc_and_size((0, 0), size),
+        [0.0, 0.0, 0.0, alpha],
+        u8::MAX,
+    )))
+}
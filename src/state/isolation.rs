@@ -0,0 +1,90 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{any::Any, io, panic::AssertUnwindSafe};
+
+use smithay::reexports::wayland_server::{
+    backend::{protocol::ProtocolError, ClientId, DisconnectReason},
+    protocol::wl_display,
+};
+
+use super::{ClientState, Data};
+use crate::{
+    crash::{self, panic_message},
+    runtime::messages::RuntimeMessage,
+};
+
+impl Data {
+    /// Dispatches the requests of every client on its own, so a handler that panics only costs
+    /// the client that sent the request its connection
+    pub fn dispatch_clients(&mut self) -> io::Result<usize> {
+        let mut dispatched = 0;
+        for client_id in self.state.common.diagnostics.client_ids() {
+            let result = crash::catch_unwind(AssertUnwindSafe(|| {
+                self.display
+                    .backend()
+                    .dispatch_single_client(&mut self.state, client_id.clone())
+            }));
+            match result {
+                Ok(Ok(count)) => dispatched += count,
+                // The backend already disconnected it
+                Ok(Err(err)) => slog_scope::debug!("Failed to read from {:?}: {}", client_id, err),
+                Err(payload) => self.client_panicked(client_id, payload),
+            }
+        }
+
+        // Clears the readiness of the display and picks up clients that connected meanwhile
+        match crash::catch_unwind(AssertUnwindSafe(|| {
+            self.display.dispatch_clients(&mut self.state)
+        })) {
+            Ok(result) => result.map(|count| dispatched + count),
+            Err(payload) => {
+                slog_scope::error!(
+                    "Handling a client request panicked: {}",
+                    panic_message(&*payload)
+                );
+                Ok(dispatched)
+            }
+        }
+    }
+
+    fn client_panicked(&mut self, client_id: ClientId, payload: Box<dyn Any + Send>) {
+        let message = panic_message(&*payload);
+        let handle = self.display.handle().backend_handle();
+        let credentials = handle
+            .get_client_data(client_id.clone())
+            .ok()
+            .and_then(|data| {
+                data.downcast_ref::<ClientState>()
+                    .and_then(|state| state.credentials.clone())
+            });
+        slog_scope::error!(
+            "Handling a request of {:?} panicked, disconnecting it: {}",
+            client_id,
+            message
+        );
+
+        // Shows up in the diagnostics like any other protocol error
+        handle.kill_client(
+            client_id.clone(),
+            DisconnectReason::ProtocolError(ProtocolError {
+                code: wl_display::Error::Implementation as u32,
+                object_id: 1,
+                object_interface: "wl_display".into(),
+                message: format!("compositor error: {}", message),
+            }),
+        );
+
+        let _ = self
+            .state
+            .common
+            .shell
+            .active_workspace()
+            .runtime_sender
+            .send(RuntimeMessage::ClientPanicked {
+                client: format!("{:?}", client_id),
+                pid: credentials.as_ref().map(|c| c.pid),
+                executable: credentials.and_then(|c| c.executable),
+                message,
+            });
+    }
+}
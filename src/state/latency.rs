@@ -0,0 +1,139 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use super::{BackendData, State};
+
+/// How many measurements are kept for the percentiles, and input events waiting for a frame
+const MAX_SAMPLES: usize = 1024;
+/// Input that changed nothing on screen is not waited for longer than this
+const MAX_PENDING: Duration = Duration::from_secs(1);
+
+/// Latency percentiles in milliseconds
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyReport {
+    pub enabled: bool,
+    pub backend: &'static str,
+    pub samples: usize,
+    /// From an input event arriving until it was handed to the focused client or a binding
+    pub delivery: Percentiles,
+    /// From an input event arriving until the next frame with new content was submitted
+    pub presentation: Percentiles,
+}
+
+/// Measures input-to-photon latency while it is turned on, off by default since every input
+/// event takes a timestamp
+#[derive(Default)]
+pub struct LatencyTracker {
+    enabled: bool,
+    /// Arrival of input events waiting for the next frame, oldest first
+    pending: VecDeque<Instant>,
+    delivery: VecDeque<Duration>,
+    presentation: VecDeque<Duration>,
+}
+
+impl LatencyTracker {
+    /// Turns measuring on or off, the samples so far are kept for the report
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.pending.clear();
+        }
+    }
+
+    /// Drops the samples so far, measuring goes on if it is turned on
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.delivery.clear();
+        self.presentation.clear();
+    }
+
+    /// Timestamp of an input event that just arrived, `None` while not measuring
+    pub fn arrived(&self) -> Option<Instant> {
+        self.enabled.then(Instant::now)
+    }
+
+    /// The input event that arrived at `arrival` was handled
+    pub fn delivered(&mut self, arrival: Instant) {
+        push_sample(&mut self.delivery, arrival.elapsed());
+        // Without a frame for a while nothing here would be counted anyway
+        while let Some(oldest) = self.pending.front() {
+            if oldest.elapsed() <= MAX_PENDING && self.pending.len() < MAX_SAMPLES {
+                break;
+            }
+            self.pending.pop_front();
+        }
+        self.pending.push_back(arrival);
+    }
+
+    /// A frame with new content was submitted, everything that arrived before is on screen
+    pub fn presented(&mut self) {
+        let now = Instant::now();
+        for arrival in self.pending.drain(..) {
+            let latency = now.duration_since(arrival);
+            if latency <= MAX_PENDING {
+                push_sample(&mut self.presentation, latency);
+            }
+        }
+    }
+
+    pub fn report(&self, backend: &'static str) -> LatencyReport {
+        LatencyReport {
+            enabled: self.enabled,
+            backend,
+            samples: self.presentation.len(),
+            delivery: percentiles(&self.delivery),
+            presentation: percentiles(&self.presentation),
+        }
+    }
+}
+
+fn push_sample(samples: &mut VecDeque<Duration>, sample: Duration) {
+    if samples.len() == MAX_SAMPLES {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
+
+fn percentiles(samples: &VecDeque<Duration>) -> Percentiles {
+    if samples.is_empty() {
+        return Percentiles::default();
+    }
+    let mut sorted = samples.iter().copied().collect::<Vec<_>>();
+    sorted.sort_unstable();
+    let at = |percentile: usize| {
+        let index = (sorted.len() * percentile / 100).min(sorted.len() - 1);
+        sorted[index].as_secs_f64() * 1000.0
+    };
+    Percentiles {
+        p50: at(50),
+        p90: at(90),
+        p99: at(99),
+        max: at(100),
+    }
+}
+
+impl State {
+    pub fn latency_report(&self) -> LatencyReport {
+        let backend = match self.backend {
+            BackendData::Winit(_) => "winit",
+            BackendData::Unset => "none",
+        };
+        self.common.latency.report(backend)
+    }
+}
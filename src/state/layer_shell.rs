@@ -15,7 +15,7 @@ use smithay::{
     },
 };
 
-use super::{output::active_output, State};
+use super::{output::active_output, sandbox::surface_sandboxed, State};
 
 impl WlrLayerShellHandler for State {
     fn shell_state(&mut self) -> &mut WlrLayerShellState {
@@ -24,12 +24,21 @@ impl WlrLayerShellHandler for State {
 
     fn new_layer_surface(
         &mut self,
-        _dh: &DisplayHandle,
+        dh: &DisplayHandle,
         surface: WlrLayerSurface,
         output: Option<WlOutput>,
         _layer: Layer,
         namespace: String,
     ) {
+        // Panels and overlays can cover everything and take the keyboard for good
+        if surface_sandboxed(dh, surface.wl_surface()) {
+            slog_scope::info!(
+                "Refusing layer surface {:?} of a sandboxed client",
+                namespace
+            );
+            surface.send_close();
+            return;
+        }
         let seat = self.common.last_active_seat.clone();
         let output = output
             .as_ref()
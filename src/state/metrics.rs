@@ -0,0 +1,191 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+
+use super::State;
+
+/// Upper bounds in seconds of the frame time buckets, around the usual refresh intervals
+const FRAME_BUCKETS: [f64; 9] = [0.001, 0.002, 0.004, 0.008, 0.016, 0.033, 0.066, 0.1, 0.25];
+/// Upper bounds in seconds of the runtime decision buckets, up to past the default timeout
+const DECISION_BUCKETS: [f64; 9] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.2, 0.5, 1.0];
+
+/// Observations counted into fixed buckets, like a Prometheus histogram
+struct Histogram {
+    bounds: &'static [f64],
+    /// Observations per bucket, the last one is `+Inf`
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += seconds;
+    }
+
+    /// Buckets are cumulative in the text format
+    fn write(&self, out: &mut String, name: &str, help: &str) {
+        header(out, name, help, "histogram");
+        let mut count = 0;
+        for (bound, bucket) in self.bounds.iter().zip(&self.counts) {
+            count += bucket;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        count += self.counts[self.bounds.len()];
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum);
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+/// Counters for dashboards of long running sessions, always on since counting is cheap
+pub struct Metrics {
+    frames_rendered: u64,
+    /// Render passes that found nothing to redraw
+    frames_skipped: u64,
+    frame_time: Histogram,
+    /// Input events by kind
+    input_events: BTreeMap<&'static str, u64>,
+    /// Until the runtime decided on a deferred request
+    decision_time: Histogram,
+    decision_timeouts: u64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            frames_rendered: 0,
+            frames_skipped: 0,
+            frame_time: Histogram::new(&FRAME_BUCKETS),
+            input_events: BTreeMap::new(),
+            decision_time: Histogram::new(&DECISION_BUCKETS),
+            decision_timeouts: 0,
+        }
+    }
+}
+
+impl Metrics {
+    /// A frame with new content took `duration` from starting to render until it was submitted
+    pub fn frame_rendered(&mut self, duration: Duration) {
+        self.frames_rendered += 1;
+        self.frame_time.observe(duration);
+    }
+
+    pub fn frame_skipped(&mut self) {
+        self.frames_skipped += 1;
+    }
+
+    pub fn input_event(&mut self, kind: &'static str) {
+        *self.input_events.entry(kind).or_default() += 1;
+    }
+
+    /// The runtime answered a deferred request after `duration`
+    pub fn decision(&mut self, duration: Duration) {
+        self.decision_time.observe(duration);
+    }
+
+    pub fn decision_timed_out(&mut self) {
+        self.decision_timeouts += 1;
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn single(out: &mut String, name: &str, help: &str, kind: &str, value: impl std::fmt::Display) {
+    header(out, name, help, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+impl State {
+    /// Everything counted so far and the current client and window counts, in the Prometheus
+    /// text exposition format
+    pub fn metrics_text(&self) -> String {
+        let metrics = &self.common.metrics;
+        let workspaces = &self.common.shell.workspaces;
+        let windows: usize = workspaces.iter().map(|w| w.space.windows().count()).sum();
+        let mut out = String::new();
+        single(
+            &mut out,
+            "electrum_uptime_seconds",
+            "Seconds since the compositor started.",
+            "gauge",
+            self.common.start_time.elapsed().as_secs_f64(),
+        );
+        single(
+            &mut out,
+            "electrum_frames_rendered_total",
+            "Frames with new content that were submitted.",
+            "counter",
+            metrics.frames_rendered,
+        );
+        single(
+            &mut out,
+            "electrum_frames_skipped_total",
+            "Render passes that had nothing to redraw.",
+            "counter",
+            metrics.frames_skipped,
+        );
+        metrics.frame_time.write(
+            &mut out,
+            "electrum_frame_time_seconds",
+            "Time from starting to render a frame until it was submitted.",
+        );
+
+        header(
+            &mut out,
+            "electrum_input_events_total",
+            "Input events processed, by kind.",
+            "counter",
+        );
+        for (kind, count) in &metrics.input_events {
+            let name = "electrum_input_events_total";
+            let _ = writeln!(out, "{}{{kind=\"{}\"}} {}", name, kind, count);
+        }
+
+        single(
+            &mut out,
+            "electrum_clients",
+            "Connected Wayland clients.",
+            "gauge",
+            self.common.diagnostics.client_ids().len(),
+        );
+        single(
+            &mut out,
+            "electrum_windows",
+            "Mapped windows on all workspaces.",
+            "gauge",
+            windows,
+        );
+
+        metrics.decision_time.write(
+            &mut out,
+            "electrum_runtime_decision_seconds",
+            "Time the runtime took to decide on a deferred request.",
+        );
+        single(
+            &mut out,
+            "electrum_runtime_decision_timeouts_total",
+            "Deferred requests the runtime did not decide on in time.",
+            "counter",
+            metrics.decision_timeouts,
+        );
+        out
+    }
+}
@@ -1,10 +1,10 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::{ffi::OsString, time::Instant};
+use std::{ffi::OsString, sync::Arc, time::Instant};
 
 use smithay::{
     reexports::{
-        calloop::{channel::Sender, LoopHandle, LoopSignal},
+        calloop::{channel::Sender, LoopHandle, LoopSignal, RegistrationToken},
         wayland_server::{
             backend::{ClientData, ClientId, DisconnectReason},
             Display, DisplayHandle,
@@ -14,29 +14,93 @@ use smithay::{
         compositor::CompositorState,
         data_device::DataDeviceState,
         dmabuf::DmabufState,
-        output::OutputManagerState,
+        output::{Output, OutputManagerState},
         primary_selection::PrimarySelectionState,
         seat::{Seat, SeatState},
         shm::ShmState,
         viewporter::ViewporterState,
+        xdg_activation::XdgActivationState,
     },
 };
 
+use self::{
+    access::{AccessLog, ClientAccess},
+    credentials::ClientCredentials,
+    diagnostics::{ClientDiagnostics, Diagnostics},
+    drag::ServerDrag,
+    electrum_shell::ShellClients,
+    idle::IdleState,
+    latency::LatencyTracker,
+    metrics::Metrics,
+    output::ReportedOutputs,
+    output_config::OutputState,
+    profiles::OutputProfiles,
+    selection::Selections,
+    thumbnails::Thumbnails,
+    toplevel_drag::ToplevelDrag,
+    transfers::TransferFilter,
+};
 use crate::{
-    backend::winit::state::WinitState, input::add_seat, log::LogState,
-    runtime::messages::RuntimeMessage, shell::Shell,
+    appinfo::AppInfoCache,
+    backend::{
+        renderer::{
+            background::BackgroundTextures, blur::BlurState, closing::ClosingWindows, fonts::Fonts,
+            icons::Icons, overlay::Overlays, shade::ShadeBars, textures::TextureCache,
+        },
+        winit::state::WinitState,
+    },
+    backlight::Backlights,
+    config::Config,
+    crash::CrashReporter,
+    input::{
+        accessibility::AccessibilityConfig, add_seat, keybindings::Keybindings,
+        layouts::KeyboardLayouts, pointer_bindings::PointerBindings,
+    },
+    ipc::IpcState,
+    log::LogState,
+    notifications::NotificationState,
+    plugins::NativePlugins,
+    portal::{PortalState, ScreenshotRequest},
+    power::PowerState,
+    runtime::{bridge::RuntimeBridge, messages::RuntimeMessage, registry::Registry},
+    shell::{decisions::PendingDecisions, frames::FramePolicy, switcher::SwitcherState, Shell},
+    shutdown::ShutdownState,
+    watchdog::Watchdog,
 };
 
+pub(crate) mod access;
 mod buffer;
 mod compositor;
+pub(crate) mod content_type;
+pub(crate) mod credentials;
+pub(crate) mod cursor_shape;
 mod data_device;
+pub(crate) mod drag;
+pub(crate) mod diagnostics;
 mod dmabuf;
+pub(crate) mod electrum_shell;
+pub(crate) mod idle;
+mod isolation;
+pub(crate) mod latency;
 mod layer_shell;
+pub(crate) mod metrics;
 pub(crate) mod output;
+pub(crate) mod output_config;
 mod primary_selection;
+pub(crate) mod profiles;
+pub(crate) mod sandbox;
 pub(crate) mod seat;
+pub(crate) mod selection;
 mod shm;
+pub(crate) mod single_pixel_buffer;
+pub(crate) mod tearing_control;
+pub(crate) mod thumbnails;
+pub(crate) mod toplevel_drag;
+pub(crate) mod toplevel_icon;
+pub(crate) mod transfers;
+pub(crate) mod tree;
 mod viewporter;
+mod xdg_activation;
 pub(crate) mod xdg_shell;
 
 pub enum BackendData {
@@ -51,12 +115,46 @@ impl BackendData {
             _ => unreachable!("Called winit() in non-winit backend"),
         }
     }
+
+    /// Whether the backend can show `output` in `state`
+    pub fn test_output(&self, output: &Output, state: &OutputState) -> anyhow::Result<()> {
+        match self {
+            BackendData::Winit(winit_state) => winit_state.test_output(output, state),
+            BackendData::Unset => Ok(()),
+        }
+    }
 }
 
-pub struct ClientState {}
+pub struct ClientState {
+    pub diagnostics: Arc<ClientDiagnostics>,
+    registry: Diagnostics,
+    /// Privileged globals like `electrum_shell_v1` it may bind
+    pub access: ClientAccess,
+    /// Peer credentials of the socket, `None` if they could not be read
+    pub credentials: Option<ClientCredentials>,
+    /// Connected through the sandbox socket or spawned into the sandbox
+    pub sandboxed: bool,
+}
 impl ClientData for ClientState {
-    fn initialized(&self, _client_id: ClientId) {}
-    fn disconnected(&self, _client_id: ClientId, _reason: DisconnectReason) {}
+    fn initialized(&self, client_id: ClientId) {
+        if let Some(credentials) = &self.credentials {
+            slog_scope::debug!(
+                "Client {:?} connected, pid {} ({})",
+                client_id,
+                credentials.pid,
+                credentials
+                    .executable
+                    .as_ref()
+                    .map(|exe| exe.display().to_string())
+                    .unwrap_or_else(|| "unknown executable".to_string())
+            );
+        }
+        self.registry
+            .client_connected(client_id, self.diagnostics.clone());
+    }
+    fn disconnected(&self, client_id: ClientId, reason: DisconnectReason) {
+        self.registry.client_disconnected(client_id, &reason);
+    }
 }
 
 pub struct Data {
@@ -73,6 +171,10 @@ pub struct State {
 
 pub struct CommonState {
     pub socket: OsString,
+    pub socket_token: Option<RegistrationToken>,
+    /// Name of the socket of sandboxed clients, if it is open
+    pub sandbox_socket: Option<OsString>,
+    pub sandbox_token: Option<RegistrationToken>,
     pub event_loop_handle: LoopHandle<'static, LoopData>,
     pub event_loop_signal: LoopSignal,
 
@@ -82,7 +184,57 @@ pub struct CommonState {
 
     pub start_time: Instant,
     pub should_stop: bool,
+    pub shutdown: ShutdownState,
     pub log: LogState,
+    pub config: Config,
+    pub keybindings: Keybindings,
+    pub pointer_bindings: PointerBindings,
+    pub keyboard_layouts: KeyboardLayouts,
+    pub accessibility: AccessibilityConfig,
+    pub frame_policy: FramePolicy,
+    /// Warping the pointer focuses the window it lands on
+    pub focus_follows_warp: bool,
+    pub idle: IdleState,
+    pub output_profiles: OutputProfiles,
+    pub reported_outputs: ReportedOutputs,
+    pub selections: Selections,
+    /// The drag the runtime started, while a client may still read its data
+    pub server_drag: Option<ServerDrag>,
+    /// Drags of clients that can carry a window along
+    pub toplevel_drags: Vec<ToplevelDrag>,
+    pub portal: Option<PortalState>,
+    pub notifications: Option<NotificationState>,
+    pub screenshot_requests: Vec<ScreenshotRequest>,
+    pub thumbnails: Thumbnails,
+    pub textures: TextureCache,
+    pub closing_windows: ClosingWindows,
+    pub switcher: SwitcherState,
+    pub overlays: Overlays,
+    pub background_textures: BackgroundTextures,
+    pub shade_bars: ShadeBars,
+    pub blur: BlurState,
+    pub power: PowerState,
+    pub backlights: Backlights,
+    /// Shared objects loaded as plugins, with their ops and render hooks
+    pub native_plugins: NativePlugins,
+    pub app_info: AppInfoCache,
+    pub icons: Icons,
+    pub fonts: Fonts,
+    pub diagnostics: Diagnostics,
+    pub access_log: AccessLog,
+    pub latency: LatencyTracker,
+    pub metrics: Metrics,
+    pub ipc: Option<IpcState>,
+    /// What the runtime registered, shared with the runtime end of the event channel
+    pub runtime_registry: Registry,
+    /// Stops the worker in safe mode
+    pub runtime_bridge: RuntimeBridge,
+    pub watchdog: Watchdog,
+    pub crash: CrashReporter,
+    pub electrum_shells: ShellClients,
+    pub decisions: PendingDecisions,
+    /// Which clients get offered the selections of other apps
+    pub transfers: TransferFilter,
 
     // Wayland State
     pub compositor_state: CompositorState,
@@ -93,6 +245,7 @@ pub struct CommonState {
     pub seat_state: SeatState<State>,
     pub shm_state: ShmState,
     pub viewporter_state: ViewporterState,
+    pub xdg_activation_state: XdgActivationState,
 }
 
 impl State {
@@ -102,25 +255,95 @@ impl State {
         handle: LoopHandle<'static, LoopData>,
         signal: LoopSignal,
         log: LogState,
+        config: Config,
         runtime_sender: Sender<RuntimeMessage>,
     ) -> Self {
-        let initial_seat = add_seat(dh, "seat-0".to_string());
+        let transfers = TransferFilter::new(
+            config.transfer_filter.clone(),
+            dh,
+            runtime_sender.clone(),
+            handle.clone(),
+        );
+        let initial_seat = add_seat(dh, "seat-0".to_string(), &transfers);
+        content_type::init_content_type(dh);
+        single_pixel_buffer::init_single_pixel_buffer(dh);
+        tearing_control::init_tearing_control(dh);
+        cursor_shape::init_cursor_shape(dh);
+        toplevel_drag::init_toplevel_drag(dh);
+        toplevel_icon::init_toplevel_icon(dh);
+        electrum_shell::init_electrum_shell(dh);
+        let app_info = AppInfoCache::new(&config.icon_theme, &handle);
+        // Shade bars and the default switcher style draw with the fontconfig default
+        let mut fonts = Fonts::default();
+        fonts.load(&handle, &None);
 
         Self {
             backend: BackendData::Unset,
             common: CommonState {
                 socket,
+                socket_token: None,
+                sandbox_socket: None,
+                sandbox_token: None,
                 event_loop_handle: handle,
                 event_loop_signal: signal,
 
                 // TODO: Have input managers handle this
-                shell: Shell::new(&dh, runtime_sender),
+                shell: Shell::new(
+                    &dh,
+                    runtime_sender,
+                    config.auto_hide.clone(),
+                    config.workspaces.clone(),
+                    config.pip.clone(),
+                ),
                 seats: vec![initial_seat.clone()],
                 last_active_seat: initial_seat,
 
                 start_time: Instant::now(),
                 should_stop: false,
+                shutdown: ShutdownState::Running,
                 log,
+                frame_policy: config.frame_policy,
+                focus_follows_warp: config.pointer_warp.focus,
+                idle: IdleState::default(),
+                app_info,
+                icons: Icons::default(),
+                fonts,
+                keybindings: Keybindings::default(),
+                pointer_bindings: PointerBindings::new(&config),
+                keyboard_layouts: KeyboardLayouts::new(&config.input),
+                accessibility: config.accessibility,
+                config,
+                output_profiles: OutputProfiles::default(),
+                reported_outputs: ReportedOutputs::default(),
+                selections: Selections::default(),
+                server_drag: None,
+                toplevel_drags: Vec::new(),
+                portal: None,
+                notifications: None,
+                screenshot_requests: Vec::new(),
+                thumbnails: Thumbnails::default(),
+                textures: TextureCache::default(),
+                closing_windows: ClosingWindows::default(),
+                switcher: SwitcherState::default(),
+                overlays: Overlays::default(),
+                background_textures: BackgroundTextures::default(),
+                shade_bars: ShadeBars::default(),
+                blur: BlurState::default(),
+                power: PowerState::default(),
+                backlights: Backlights::default(),
+                native_plugins: NativePlugins::default(),
+                diagnostics: Diagnostics::default(),
+                access_log: AccessLog::default(),
+                latency: LatencyTracker::default(),
+                metrics: Metrics::default(),
+                ipc: None,
+                runtime_registry: Registry::default(),
+                runtime_bridge: RuntimeBridge::default(),
+                watchdog: Watchdog::default(),
+                crash: CrashReporter::default(),
+                electrum_shells: ShellClients::default(),
+                decisions: PendingDecisions::default(),
+                transfers,
 
                 compositor_state: CompositorState::new::<Self, _>(dh, slog_scope::logger()),
                 data_device_state: DataDeviceState::new::<Self, _>(dh, slog_scope::logger()),
@@ -133,11 +356,27 @@ impl State {
                 seat_state: SeatState::<Self>::new(),
                 shm_state: ShmState::new::<Self, _>(dh, vec![], slog_scope::logger()),
                 viewporter_state: ViewporterState::new::<Self, _>(dh, slog_scope::logger()),
+                xdg_activation_state: XdgActivationState::new::<Self, _>(dh, slog_scope::logger()),
             },
         }
     }
 
-    pub fn new_client_state(&self) -> ClientState {
-        ClientState {}
+    pub fn new_client_state(
+        &self,
+        credentials: Option<ClientCredentials>,
+        sandboxed: bool,
+    ) -> ClientState {
+        ClientState {
+            diagnostics: Arc::new(ClientDiagnostics::default()),
+            registry: self.common.diagnostics.clone(),
+            access: ClientAccess::new(
+                &self.common.config,
+                credentials.clone(),
+                sandboxed,
+                self.common.access_log.clone(),
+            ),
+            credentials,
+            sandboxed,
+        }
     }
 }
@@ -2,6 +2,7 @@
 
 use std::cell::RefCell;
 
+use serde::Serialize;
 use smithay::{
     delegate_output,
     utils::{Logical, Rectangle, Transform},
@@ -9,6 +10,110 @@ use smithay::{
 };
 
 use super::{CommonState, State};
+use crate::runtime::messages::RuntimeMessage;
+
+/// An output as the runtime sees it, positions and sizes are logical unless noted
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputInfo {
+    /// Position in the order the outputs were connected in
+    pub index: usize,
+    pub name: String,
+    /// Make, model and connector, e.g. `Dell Inc. DELL U2720Q (DP-1)`
+    pub description: String,
+    pub make: String,
+    pub model: String,
+    /// Whether the output is turned on and part of the layout
+    pub enabled: bool,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    /// Size of the current mode in pixels, before the transform
+    pub mode_width: i32,
+    pub mode_height: i32,
+    /// Refresh rate of the current mode in Hz
+    pub refresh: f64,
+    pub scale: f64,
+    /// `normal`, `90`, `180`, `270`, `flipped`, `flipped-90`, `flipped-180` or `flipped-270`
+    pub transform: &'static str,
+    /// Physical size in millimeters, `0` if unknown
+    pub physical_width: i32,
+    pub physical_height: i32,
+}
+
+impl OutputInfo {
+    pub fn new(index: usize, output: &Output, enabled: bool) -> Self {
+        let properties = output.physical_properties();
+        let geometry = output.geometry();
+        let mode = output.current_mode();
+        let transform = match Transform::from(output.current_transform()) {
+            Transform::Normal => "normal",
+            Transform::_90 => "90",
+            Transform::_180 => "180",
+            Transform::_270 => "270",
+            Transform::Flipped => "flipped",
+            Transform::Flipped90 => "flipped-90",
+            Transform::Flipped180 => "flipped-180",
+            Transform::Flipped270 => "flipped-270",
+        };
+        OutputInfo {
+            index,
+            name: output.name(),
+            description: format!(
+                "{} {} ({})",
+                properties.make,
+                properties.model,
+                output.name()
+            ),
+            make: properties.make,
+            model: properties.model,
+            enabled,
+            x: geometry.loc.x,
+            y: geometry.loc.y,
+            width: geometry.size.w,
+            height: geometry.size.h,
+            mode_width: mode.map(|mode| mode.size.w).unwrap_or(0),
+            mode_height: mode.map(|mode| mode.size.h).unwrap_or(0),
+            refresh: mode.map(|mode| mode.refresh as f64 / 1000.0).unwrap_or(0.0),
+            scale: output.current_scale().fractional_scale(),
+            transform,
+            physical_width: properties.size.w,
+            physical_height: properties.size.h,
+        }
+    }
+}
+
+/// What the runtime was last told the outputs look like
+#[derive(Debug, Default)]
+pub struct ReportedOutputs(Vec<OutputInfo>);
+
+impl CommonState {
+    /// Every connected output, also those that are turned off
+    pub fn output_infos(&self) -> Vec<OutputInfo> {
+        self.output_profiles
+            .connected()
+            .iter()
+            .enumerate()
+            .map(|(index, output)| {
+                let enabled = self.shell.outputs().any(|o| o == output);
+                OutputInfo::new(index, output, enabled)
+            })
+            .collect()
+    }
+
+    /// Sends `OutputsChanged` to the runtime if anything about the outputs changed since the
+    /// last time, after outputs were connected, disconnected or configured
+    pub fn report_outputs(&mut self) {
+        let outputs = self.output_infos();
+        if outputs == self.reported_outputs.0 {
+            return;
+        }
+        self.reported_outputs.0 = outputs.clone();
+        let runtime_sender = &self.shell.active_workspace().runtime_sender;
+        let _ = runtime_sender.send(RuntimeMessage::OutputsChanged { outputs });
+    }
+}
 
 pub trait OutputExt {
     fn geometry(&self) -> Rectangle<i32, Logical>;
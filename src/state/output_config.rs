@@ -0,0 +1,265 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Context};
+use smithay::{
+    utils::{Logical, Point},
+    wayland::output::{Mode, Output, Scale},
+};
+
+use super::{profiles::output_matches, CommonState, State};
+use crate::{
+    backend::renderer::{
+        color::{icc_profile, output_filter, set_icc_profile, set_output_filter, test_color},
+        filter::OutputFilter,
+    },
+    config::OutputConfig,
+    runtime::messages::RuntimeMessage,
+};
+
+/// Pixel densities that are readable at scale 1, laptop panels are closer to the eyes than
+/// monitors
+const BUILTIN_DPI: f64 = 135.0;
+const EXTERNAL_DPI: f64 = 110.0;
+/// Highest scale picked automatically
+const MAX_AUTO_SCALE: f64 = 3.0;
+
+/// Everything a configuration sets on one output
+#[derive(Debug, Clone)]
+pub struct OutputState {
+    pub enabled: bool,
+    pub mode: Option<Mode>,
+    pub scale: Scale,
+    pub position: Point<i32, Logical>,
+    pub icc_profile: Option<PathBuf>,
+    pub filter: Option<OutputFilter>,
+}
+
+impl OutputState {
+    /// What `output` looks like right now
+    pub fn current(output: &Output, enabled: bool) -> Self {
+        OutputState {
+            enabled,
+            mode: output.current_mode(),
+            scale: output.current_scale(),
+            position: output.current_location(),
+            icc_profile: icc_profile(output),
+            filter: output_filter(output),
+        }
+    }
+}
+
+/// Pending states of a set of outputs, like a wlr-output-management configuration they are
+/// tested as a whole and either all applied or none
+#[derive(Debug, Clone, Default)]
+pub struct OutputConfiguration(Vec<(Output, OutputState)>);
+
+impl OutputConfiguration {
+    pub fn push(&mut self, output: &Output, state: OutputState) {
+        self.0.retain(|(o, _)| o != output);
+        self.0.push((output.clone(), state));
+    }
+}
+
+impl CommonState {
+    /// The state `config` asks for on `output`. A mode, scale or position it leaves out stays as
+    /// it is, a missing ICC profile or filter turns it off like in `electrum.toml`.
+    pub fn pending_output_state(
+        &self,
+        output: &Output,
+        config: &OutputConfig,
+    ) -> anyhow::Result<OutputState> {
+        let mut state = OutputState::current(output, config.enabled);
+        if let Some(mode) = &config.mode {
+            state.mode = Some(
+                find_mode(output, mode)
+                    .ok_or_else(|| anyhow!("Output {} has no mode {}", output.name(), mode))?,
+            );
+        }
+        if let Some(scale) = config.scale {
+            if !scale.is_finite() || scale <= 0.0 {
+                bail!(
+                    "Scale {} of output {} is not positive",
+                    scale,
+                    output.name()
+                );
+            }
+            state.scale = scale_from(scale);
+        }
+        if let Some(position) = config.position {
+            state.position = position.into();
+        }
+        state.icc_profile = config.icc_profile.clone();
+        state.filter = config.filter;
+        test_color(state.icc_profile.as_deref(), state.filter)
+            .with_context(|| format!("Output {}", output.name()))?;
+        Ok(state)
+    }
+
+    /// Snapshot of every output in `configuration`, to go back to
+    fn current_output_configuration(
+        &self,
+        configuration: &OutputConfiguration,
+    ) -> OutputConfiguration {
+        OutputConfiguration(
+            configuration
+                .0
+                .iter()
+                .map(|(output, _)| {
+                    let enabled = self.shell.outputs().any(|o| o == output);
+                    (output.clone(), OutputState::current(output, enabled))
+                })
+                .collect(),
+        )
+    }
+
+    fn commit_output_configuration(
+        &mut self,
+        configuration: &OutputConfiguration,
+    ) -> anyhow::Result<()> {
+        for (output, state) in &configuration.0 {
+            let mode_changed = state.mode.is_some() && state.mode != output.current_mode();
+            let scale = state.scale.fractional_scale();
+            if scale != output.current_scale().fractional_scale() {
+                let runtime_sender = &self.shell.active_workspace().runtime_sender;
+                let _ = runtime_sender.send(RuntimeMessage::ScaleChanged {
+                    output: output.name(),
+                    scale,
+                });
+            }
+            output.change_current_state(state.mode, None, Some(state.scale), Some(state.position));
+            set_icc_profile(output, state.icc_profile.as_deref())?;
+            set_output_filter(output, state.filter)?;
+
+            let mapped = self.shell.outputs().any(|o| o == output);
+            match (state.enabled, mapped) {
+                (true, false) => {
+                    self.shell.add_output(output);
+                    self.shell.restore_output(output);
+                }
+                (false, true) => self.shell.remove_output(output),
+                (true, true) if mode_changed => self.shell.rearrange_output(output),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl State {
+    /// Checks that `configuration` can be applied, without changing anything
+    pub fn test_output_configuration(
+        &self,
+        configuration: &OutputConfiguration,
+    ) -> anyhow::Result<()> {
+        // Never turn off every output, that would end the session
+        let untouched = self
+            .common
+            .shell
+            .outputs()
+            .any(|output| !configuration.0.iter().any(|(o, _)| o == output));
+        let enabled = configuration.0.iter().any(|(_, state)| state.enabled);
+        if !configuration.0.is_empty() && !untouched && !enabled {
+            bail!("The configuration turns off every output");
+        }
+
+        for (output, state) in &configuration.0 {
+            self.backend.test_output(output, state)?;
+        }
+        Ok(())
+    }
+
+    /// Applies all of `configuration` or, if it fails to test or to apply, nothing
+    pub fn apply_output_configuration(
+        &mut self,
+        configuration: &OutputConfiguration,
+    ) -> anyhow::Result<()> {
+        self.test_output_configuration(configuration)?;
+
+        let previous = self.common.current_output_configuration(configuration);
+        let result = self.common.commit_output_configuration(configuration);
+        if let Err(err) = &result {
+            slog_scope::warn!("Rolling back output configuration: {:#}", err);
+            if let Err(err) = self.common.commit_output_configuration(&previous) {
+                slog_scope::error!("Failed to roll back output configuration: {:#}", err);
+            }
+        }
+        self.common.shell.refresh_outputs();
+        self.common.constrain_windows();
+        self.common.report_outputs();
+        result
+    }
+
+    /// Tests or applies `configs` for the connected outputs they match, the others stay as they
+    /// are
+    pub fn configure_outputs(
+        &mut self,
+        configs: &[OutputConfig],
+        test_only: bool,
+    ) -> anyhow::Result<()> {
+        let connected = self.common.output_profiles.connected().to_vec();
+        let mut configuration = OutputConfiguration::default();
+        for config in configs {
+            let output = connected
+                .iter()
+                .find(|output| output_matches(config, output))
+                .ok_or_else(|| anyhow!("No output named {}", config.name))?;
+            configuration.push(output, self.common.pending_output_state(output, config)?);
+        }
+
+        if test_only {
+            self.test_output_configuration(&configuration)
+        } else {
+            self.apply_output_configuration(&configuration)
+        }
+    }
+}
+
+/// Scale that makes `mode` of `output` about as readable as a 96 DPI screen at desktop distance,
+/// from the physical size in its EDID. `None` if the size is unknown or made up.
+pub fn preferred_scale(output: &Output, mode: Option<Mode>) -> Option<Scale> {
+    let size = output.physical_properties().size;
+    let mode = mode?;
+    // Projectors and TVs tend to report their aspect ratio instead
+    if size.w < 100 || size.h < 60 || [(160, 90), (160, 100)].contains(&(size.w, size.h)) {
+        return None;
+    }
+
+    let dpi = mode.size.w as f64 / (size.w as f64 / 25.4);
+    let builtin = ["eDP", "LVDS", "DSI"]
+        .iter()
+        .any(|prefix| output.name().starts_with(prefix));
+    let reference = if builtin { BUILTIN_DPI } else { EXTERNAL_DPI };
+    let scale = ((dpi / reference) * 4.0).round() / 4.0;
+    Some(scale_from(scale.clamp(1.0, MAX_AUTO_SCALE)))
+}
+
+fn scale_from(scale: f64) -> Scale {
+    if scale.fract() == 0.0 {
+        Scale::Integer(scale as i32)
+    } else {
+        Scale::Fractional(scale)
+    }
+}
+
+/// Parses `1920x1080` or `1920x1080@59.94`, without a refresh rate the highest one wins
+fn find_mode(output: &Output, mode: &str) -> Option<Mode> {
+    let (size, refresh) = match mode.split_once('@') {
+        Some((size, refresh)) => (size, Some(refresh.parse::<f64>().ok()?)),
+        None => (mode, None),
+    };
+    let (w, h) = size.split_once('x')?;
+    let (w, h) = (w.trim().parse::<i32>().ok()?, h.trim().parse::<i32>().ok()?);
+
+    output
+        .modes()
+        .into_iter()
+        .filter(|mode| mode.size == (w, h).into())
+        .filter(|mode| {
+            refresh
+                .map(|refresh| (mode.refresh - (refresh * 1000.0) as i32).abs() < 500)
+                .unwrap_or(true)
+        })
+        .max_by_key(|mode| mode.refresh)
+}
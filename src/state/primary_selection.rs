@@ -1,15 +1,39 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
-use smithay::{delegate_primary_selection, wayland::primary_selection::PrimarySelectionHandler};
+use std::os::unix::io::RawFd;
 
-use super::State;
+use smithay::{
+    delegate_primary_selection,
+    reexports::{
+        wayland_protocols::wp::primary_selection::zv1::server::zwp_primary_selection_source_v1::ZwpPrimarySelectionSourceV1,
+        wayland_server::Resource,
+    },
+    wayland::primary_selection::{
+        with_source_metadata, PrimarySelectionHandler, PrimarySelectionState,
+    },
+};
+
+use super::{selection::SelectionKind, State};
 
 impl PrimarySelectionHandler for State {
-    fn primary_selection_state(
-        &self,
-    ) -> &smithay::wayland::primary_selection::PrimarySelectionState {
+    fn primary_selection_state(&self) -> &PrimarySelectionState {
         &self.common.primary_selection_state
     }
+
+    fn new_selection(&mut self, source: Option<ZwpPrimarySelectionSourceV1>) {
+        let mime_types = source.as_ref().map(|source| {
+            with_source_metadata(source, |metadata| metadata.mime_types.clone()).unwrap_or_default()
+        });
+        self.common.selection_changed(
+            SelectionKind::Primary,
+            source.map(|source| source.id()),
+            mime_types,
+        );
+    }
+
+    fn send_selection(&mut self, mime_type: String, fd: RawFd) {
+        self.common.send_primary_selection(mime_type, fd);
+    }
 }
 
 delegate_primary_selection!(State);
@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use smithay::wayland::output::Output;
+
+use super::{
+    output_config::{preferred_scale, OutputConfiguration, OutputState},
+    CommonState, State,
+};
+use crate::{
+    config::{OutputConfig, OutputProfile},
+    runtime::messages::RuntimeMessage,
+};
+
+/// Outputs the backend knows about, not all of them are necessarily mapped
+#[derive(Debug, Default)]
+pub struct OutputProfiles {
+    connected: Vec<Output>,
+    active: Option<String>,
+}
+
+impl OutputProfiles {
+    pub fn connected(&self) -> &[Output] {
+        &self.connected
+    }
+}
+
+impl State {
+    /// Called by backends for every new output, instead of mapping it directly
+    pub fn output_connected(&mut self, output: &Output) {
+        self.common.output_profiles.connected.push(output.clone());
+        self.apply_output_layout();
+        self.common.shell.restore_output(output);
+        // Also when the layout could not be applied, the output is there
+        self.common.report_outputs();
+    }
+
+    pub fn output_disconnected(&mut self, output: &Output) {
+        self.common
+            .output_profiles
+            .connected
+            .retain(|o| o != output);
+        self.common.shell.remove_output(output);
+        self.apply_output_layout();
+        self.common.report_outputs();
+    }
+
+    /// Applies the first profile matching exactly the connected outputs, or the
+    /// plain `outputs` settings if there is none
+    pub fn apply_output_layout(&mut self) {
+        let common = &mut self.common;
+        let connected = common.output_profiles.connected.clone();
+        let profile = common
+            .config
+            .profiles
+            .iter()
+            .find(|profile| profile_matches(profile, &connected))
+            .cloned();
+
+        let profile_name = profile.as_ref().map(|profile| profile.name.clone());
+        if profile_name != common.output_profiles.active {
+            match &profile_name {
+                Some(name) => slog_scope::info!("Applying output profile {}", name),
+                None if !common.config.profiles.is_empty() => {
+                    slog_scope::info!("No output profile matches the connected outputs")
+                }
+                None => {}
+            }
+            let runtime_sender = &common.shell.active_workspace().runtime_sender;
+            let _ = runtime_sender.send(RuntimeMessage::OutputProfile {
+                name: profile_name.clone(),
+            });
+            common.output_profiles.active = profile_name;
+        }
+
+        let configs = profile
+            .map(|profile| profile.outputs)
+            .unwrap_or_else(|| common.config.outputs.clone());
+        let result = layout_configuration(common, &connected, &configs)
+            .and_then(|configuration| self.apply_output_configuration(&configuration));
+        if let Err(err) = result {
+            slog_scope::warn!(
+                "Failed to apply the output layout, turning on every output as it is: {:#}",
+                err
+            );
+            let mut fallback = OutputConfiguration::default();
+            for output in &connected {
+                fallback.push(output, OutputState::current(output, true));
+            }
+            if let Err(err) = self.apply_output_configuration(&fallback) {
+                slog_scope::error!("Failed to turn on the outputs: {:#}", err);
+            }
+        }
+    }
+}
+
+/// The states `configs` ask for on the `connected` outputs, outputs without one are turned on.
+/// Outputs without a configured scale get the one that fits their pixel density.
+fn layout_configuration(
+    common: &CommonState,
+    connected: &[Output],
+    configs: &[OutputConfig],
+) -> anyhow::Result<OutputConfiguration> {
+    let find_config = |output: &Output| configs.iter().find(|c| output_matches(c, output));
+
+    // Never turn off every output, that would end the session
+    let disable_allowed = connected
+        .iter()
+        .any(|output| find_config(output).map(|c| c.enabled).unwrap_or(true));
+
+    let mut configuration = OutputConfiguration::default();
+    for output in connected {
+        let config = find_config(output);
+        let mut state = match config {
+            Some(config) => common.pending_output_state(output, config)?,
+            None => OutputState::current(output, true),
+        };
+        state.enabled |= !disable_allowed;
+        // A configured scale always wins
+        if common.config.auto_scale && config.and_then(|c| c.scale).is_none() {
+            if let Some(scale) = preferred_scale(output, state.mode) {
+                state.scale = scale;
+            }
+        }
+        configuration.push(output, state);
+    }
+    Ok(configuration)
+}
+
+fn profile_matches(profile: &OutputProfile, connected: &[Output]) -> bool {
+    profile.outputs.len() == connected.len()
+        && connected.iter().all(|output| {
+            profile
+                .outputs
+                .iter()
+                .any(|config| output_matches(config, output))
+        })
+}
+
+pub fn output_matches(config: &OutputConfig, output: &Output) -> bool {
+    let properties = output.physical_properties();
+    config.name == output.name()
+        || config.name == format!("{} {}", properties.make, properties.model)
+}
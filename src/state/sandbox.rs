@@ -0,0 +1,122 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    ffi::{OsStr, OsString},
+    io,
+    os::unix::{io::AsRawFd, net::UnixStream, process::CommandExt},
+    process::{Command, Stdio},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use serde::Deserialize;
+use smithay::{
+    reexports::{
+        calloop::{EventLoop, RegistrationToken},
+        wayland_server::{protocol::wl_surface::WlSurface, Client, DisplayHandle, Resource},
+    },
+    wayland::socket::ListeningSocketSource,
+};
+
+use super::{credentials::ClientCredentials, ClientState, Data, LoopData};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    /// Listen on a second wayland socket whose clients never see privileged globals
+    pub socket: bool,
+    /// Name of that socket in `XDG_RUNTIME_DIR`, the main one with `-sandbox` appended if not set
+    pub socket_name: Option<String>,
+}
+
+/// Whether `client` connected through the sandbox socket or was spawned into the sandbox
+pub fn is_sandboxed(client: &Client) -> bool {
+    client
+        .get_data::<ClientState>()
+        .map(|data| data.sandboxed)
+        .unwrap_or(false)
+}
+
+/// Whether the client owning `surface` is sandboxed
+pub fn surface_sandboxed(dh: &DisplayHandle, surface: &WlSurface) -> bool {
+    dh.get_client(surface.id())
+        .map(|client| is_sandboxed(&client))
+        .unwrap_or(false)
+}
+
+/// Listens on the sandbox socket next to `main_socket`, returning its name
+pub fn init_sandbox_socket(
+    event_loop: &mut EventLoop<LoopData>,
+    config: &SandboxConfig,
+    main_socket: &OsStr,
+) -> anyhow::Result<(OsString, RegistrationToken)> {
+    let name = config
+        .socket_name
+        .clone()
+        .unwrap_or_else(|| format!("{}-sandbox", main_socket.to_string_lossy()));
+    let socket_source = ListeningSocketSource::with_name(&name, None)
+        .with_context(|| format!("Failed to bind {}", name))?;
+    let socket_name = socket_source.socket_name().to_os_string();
+    slog_scope::info!("Listening on {:?} for sandboxed clients", socket_name);
+
+    let token = event_loop
+        .handle()
+        .insert_source(socket_source, |stream, _, data| {
+            crate::insert_client(data, stream, true)
+        })
+        .with_context(|| "Failed to initialise the sandbox socket")?;
+    Ok((socket_name, token))
+}
+
+impl Data {
+    /// Runs `command` through the shell as a sandboxed client. It gets its connection as
+    /// `WAYLAND_SOCKET`, so it needs no socket to connect to, and the sandbox socket as
+    /// `WAYLAND_DISPLAY` for the processes it starts, if there is one. Returns the pid of the
+    /// shell.
+    pub fn spawn_sandboxed(&mut self, command: &str) -> anyhow::Result<u32> {
+        let (server, client) = UnixStream::pair().context("Failed to create a socket pair")?;
+        let fd = client.as_raw_fd();
+
+        let mut child = Command::new("/bin/sh");
+        child
+            .arg("-c")
+            .arg(command)
+            .env("WAYLAND_SOCKET", fd.to_string())
+            .stdin(Stdio::null());
+        match &self.state.common.sandbox_socket {
+            Some(socket) => child.env("WAYLAND_DISPLAY", socket),
+            None => child.env_remove("WAYLAND_DISPLAY"),
+        };
+        // Only this end is inherited, the socket pair is close-on-exec like every other fd
+        unsafe {
+            child.pre_exec(move || {
+                fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()))
+                    .map(|_| ())
+                    .map_err(io::Error::from)
+            });
+        }
+        let mut child = child
+            .spawn()
+            .with_context(|| format!("Failed to spawn {:?}", command))?;
+        drop(client);
+        let pid = child.id();
+        slog_scope::info!("Spawned {:?} ({}) in the sandbox", command, pid);
+        // Reap the child, so it doesn't linger as a zombie
+        std::thread::spawn(move || child.wait());
+
+        // The peer of a socket pair is this process, the child is known by its pid instead
+        let credentials = ClientCredentials {
+            pid,
+            uid: nix::unistd::getuid().as_raw(),
+            gid: nix::unistd::getgid().as_raw(),
+            executable: None,
+        };
+        let client_state = self.state.new_client_state(Some(credentials), true);
+        self.display
+            .handle()
+            .insert_client(server, Arc::new(client_state))
+            .context("Failed to add the client")?;
+        Ok(pid)
+    }
+}
@@ -0,0 +1,212 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    fs::File,
+    io::{ErrorKind, Read},
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, ensure};
+use futures::channel::oneshot;
+use nix::{
+    errno::Errno,
+    poll::{poll, PollFd, PollFlags},
+};
+use serde::{Deserialize, Serialize};
+use smithay::{
+    reexports::wayland_server::{backend::ObjectId, Resource},
+    wayland::{
+        data_device::request_data_device_client_selection,
+        primary_selection::{
+            clear_primary_selection, request_primary_client_selection, set_primary_selection,
+        },
+        seat::Seat,
+    },
+};
+
+use super::{transfers::app_id, CommonState, State};
+use crate::runtime::messages::RuntimeMessage;
+
+/// Reads from JS are cut off after this many bytes unless asked for less
+pub const DEFAULT_READ_LIMIT: usize = 1024 * 1024;
+/// How long the owner of a selection gets to write all of it before a read fails
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionKind {
+    Clipboard,
+    Primary,
+    /// Drag and drop, only ever asked about in `TransferRequest`s
+    Drag,
+}
+
+/// The seats whose clients currently own a selection
+#[derive(Default)]
+pub struct Selections {
+    clipboard: Option<Seat<State>>,
+    primary: Option<Seat<State>>,
+}
+
+impl CommonState {
+    /// A client took over or dropped a selection on the last active seat, offering it through
+    /// the data source `source`
+    pub fn selection_changed(
+        &mut self,
+        kind: SelectionKind,
+        source: Option<ObjectId>,
+        mime_types: Option<Vec<String>>,
+    ) {
+        let seat = mime_types.as_ref().map(|_| self.last_active_seat.clone());
+        match kind {
+            SelectionKind::Clipboard => self.selections.clipboard = seat,
+            SelectionKind::Primary => {
+                self.selections.primary = seat.clone();
+                self.mirror_primary_selection(seat, mime_types.clone());
+            }
+            SelectionKind::Drag => return,
+        }
+
+        let owner = source.clone().and_then(|source| self.source_app_id(source));
+        self.transfers
+            .set_owner(kind, source, owner, mime_types.clone(), &self.seats);
+
+        let runtime_sender = &self.shell.active_workspace().runtime_sender;
+        let _ = runtime_sender.send(RuntimeMessage::SelectionChanged {
+            kind,
+            mime_types: mime_types.unwrap_or_default(),
+        });
+    }
+
+    /// The app id of a window the client of the data source `source` mapped, if it mapped any
+    pub fn source_app_id(&self, source: ObjectId) -> Option<String> {
+        let client = self.transfers.client(source)?;
+        self.shell
+            .workspaces
+            .iter()
+            .flat_map(|workspace| workspace.space.windows())
+            .map(|window| window.toplevel().wl_surface())
+            .find(|surface| {
+                self.transfers.client(surface.id()).map(|owner| owner.id()) == Some(client.id())
+            })
+            .and_then(app_id)
+    }
+
+    /// Offers the primary selection on every other seat as well, pastes there are
+    /// forwarded to the owning client through `send_primary_selection`
+    fn mirror_primary_selection(
+        &mut self,
+        owner: Option<Seat<State>>,
+        mime_types: Option<Vec<String>>,
+    ) {
+        let seats = self
+            .seats
+            .iter()
+            .filter(|seat| Some(*seat) != owner.as_ref())
+            .cloned()
+            .collect::<Vec<_>>();
+        if seats.is_empty() {
+            return;
+        }
+
+        // Handlers don't get a display handle
+        self.event_loop_handle.insert_idle(move |data| {
+            let dh = data.display.handle();
+            for seat in &seats {
+                match &mime_types {
+                    Some(mime_types) => set_primary_selection(&dh, seat, mime_types.clone()),
+                    None => clear_primary_selection(&dh, seat),
+                }
+            }
+        });
+    }
+
+    pub fn send_primary_selection(&mut self, mime_type: String, fd: RawFd) {
+        match &self.selections.primary {
+            Some(owner) => {
+                if let Err(err) = request_primary_client_selection(owner, mime_type, fd) {
+                    slog_scope::warn!("Failed to forward primary selection: {:?}", err);
+                }
+            }
+            None => slog_scope::debug!("Primary selection requested, but nobody owns it"),
+        }
+        // The client got its own copy of the fd
+        let _ = nix::unistd::close(fd);
+    }
+
+    /// Reads up to `limit` bytes of a selection in a background thread, failing if the client
+    /// takes longer than `READ_TIMEOUT`
+    pub fn read_selection(
+        &mut self,
+        kind: SelectionKind,
+        mime_type: String,
+        limit: usize,
+        reply: oneshot::Sender<Result<Vec<u8>, String>>,
+    ) {
+        let result = match kind {
+            SelectionKind::Clipboard => self.selections.clipboard.as_ref(),
+            SelectionKind::Primary => self.selections.primary.as_ref(),
+            SelectionKind::Drag => None,
+        }
+        .ok_or_else(|| anyhow!("No client owns the {:?} selection", kind))
+        .and_then(|seat| {
+            let (read, write) = nix::unistd::pipe()?;
+            let requested = match kind {
+                SelectionKind::Clipboard => {
+                    request_data_device_client_selection(seat, mime_type, write)
+                        .map_err(|err| anyhow!("{:?}", err))
+                }
+                SelectionKind::Primary => request_primary_client_selection(seat, mime_type, write)
+                    .map_err(|err| anyhow!("{:?}", err)),
+                SelectionKind::Drag => Err(anyhow!("Drags can't be read")),
+            };
+            let _ = nix::unistd::close(write);
+            // Safe, nothing else holds on to the read end
+            let file = unsafe { File::from_raw_fd(read) };
+            requested.map(|_| file)
+        });
+
+        match result {
+            Ok(file) => {
+                // The client writes whenever it gets to it, don't block the event loop
+                std::thread::spawn(move || {
+                    let result = read_until_deadline(file, limit).map_err(|err| err.to_string());
+                    let _ = reply.send(result);
+                });
+            }
+            Err(err) => {
+                let _ = reply.send(Err(err.to_string()));
+            }
+        }
+    }
+}
+
+/// Reads up to `limit` bytes of `file` until it is closed, giving up once `READ_TIMEOUT` passed
+fn read_until_deadline(mut file: File, limit: usize) -> anyhow::Result<Vec<u8>> {
+    let deadline = Instant::now() + READ_TIMEOUT;
+    let mut contents = Vec::new();
+    let mut buffer = [0; 4096];
+    while contents.len() < limit {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let mut fds = [PollFd::new(file.as_raw_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, left.as_millis() as i32) {
+            Ok(ready) => ensure!(
+                ready > 0,
+                "The client did not write the selection within {} seconds",
+                READ_TIMEOUT.as_secs()
+            ),
+            Err(Errno::EINTR) => continue,
+            Err(err) => return Err(err.into()),
+        }
+
+        let len = buffer.len().min(limit - contents.len());
+        match file.read(&mut buffer[..len]) {
+            Ok(0) => break,
+            Ok(read) => contents.extend_from_slice(&buffer[..read]),
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(contents)
+}
@@ -2,6 +2,7 @@
 
 use smithay::{
     delegate_shm,
+    reexports::wayland_server::{protocol::wl_shm, DisplayHandle},
     wayland::shm::{ShmHandler, ShmState},
 };
 
@@ -14,3 +15,68 @@ impl ShmHandler for State {
 }
 
 delegate_shm!(State);
+
+/// The wl_shm format called `name`, like `"rgb565"`
+fn parse_format(name: &str) -> Option<wl_shm::Format> {
+    let format = match name.to_ascii_lowercase().as_str() {
+        "argb8888" => wl_shm::Format::Argb8888,
+        "xrgb8888" => wl_shm::Format::Xrgb8888,
+        "abgr8888" => wl_shm::Format::Abgr8888,
+        "xbgr8888" => wl_shm::Format::Xbgr8888,
+        "rgba8888" => wl_shm::Format::Rgba8888,
+        "rgbx8888" => wl_shm::Format::Rgbx8888,
+        "bgra8888" => wl_shm::Format::Bgra8888,
+        "bgrx8888" => wl_shm::Format::Bgrx8888,
+        "rgb565" => wl_shm::Format::Rgb565,
+        "bgr565" => wl_shm::Format::Bgr565,
+        "rgb888" => wl_shm::Format::Rgb888,
+        "bgr888" => wl_shm::Format::Bgr888,
+        "argb2101010" => wl_shm::Format::Argb2101010,
+        "xrgb2101010" => wl_shm::Format::Xrgb2101010,
+        "abgr2101010" => wl_shm::Format::Abgr2101010,
+        "xbgr2101010" => wl_shm::Format::Xbgr2101010,
+        _ => return None,
+    };
+    Some(format)
+}
+
+/// The formats out of `requested` the renderer can import, besides argb8888 and xrgb8888 which
+/// are always advertised
+fn negotiate_formats(requested: &[String], supported: &[wl_shm::Format]) -> Vec<wl_shm::Format> {
+    let mut formats = Vec::new();
+    for name in requested {
+        let format = match parse_format(name) {
+            Some(format) => format,
+            None => {
+                slog_scope::warn!("Unknown shm format {}", name);
+                continue;
+            }
+        };
+        if matches!(format, wl_shm::Format::Argb8888 | wl_shm::Format::Xrgb8888)
+            || formats.contains(&format)
+        {
+            continue;
+        }
+        if !supported.contains(&format) {
+            slog_scope::info!(
+                "Not advertising shm format {}, the renderer cannot import it",
+                name
+            );
+            continue;
+        }
+        formats.push(format);
+    }
+    formats
+}
+
+impl State {
+    /// Advertises the `shm_formats` the renderer of the backend supports, called before clients
+    /// can connect and bind the placeholder global created with the state
+    pub fn init_shm(&mut self, dh: &DisplayHandle, supported: &[wl_shm::Format]) {
+        let formats = negotiate_formats(&self.common.config.shm_formats, supported);
+        slog_scope::debug!("Additional shm formats: {:?}", formats);
+
+        dh.remove_global::<State>(self.common.shm_state.global());
+        self.common.shm_state = ShmState::new::<Self, _>(dh, formats, slog_scope::logger());
+    }
+}
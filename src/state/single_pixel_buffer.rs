@@ -0,0 +1,106 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::cell::RefCell;
+
+use smithay::{
+    backend::renderer::utils::RendererSurfaceState,
+    reexports::{
+        wayland_protocols::wp::single_pixel_buffer::v1::server::wp_single_pixel_buffer_manager_v1::{
+            self, WpSinglePixelBufferManagerV1,
+        },
+        wayland_server::{
+            protocol::wl_buffer::{self, WlBuffer},
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+        },
+    },
+    wayland::compositor::SurfaceData,
+};
+
+use super::State;
+
+/// Color of a buffer created through `wp_single_pixel_buffer_manager_v1`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SinglePixelBuffer {
+    pub color: [f32; 4],
+}
+
+impl SinglePixelBuffer {
+    fn from_rgba(r: u32, g: u32, b: u32, a: u32) -> Self {
+        let channel = |value: u32| value as f64 / u32::MAX as f64;
+        Self {
+            color: [
+                channel(r) as f32,
+                channel(g) as f32,
+                channel(b) as f32,
+                channel(a) as f32,
+            ],
+        }
+    }
+}
+
+/// Color of the single pixel buffer currently attached to a surface, if any
+pub fn single_pixel_color(states: &SurfaceData) -> Option<[f32; 4]> {
+    states
+        .data_map
+        .get::<RefCell<RendererSurfaceState>>()
+        .and_then(|state| {
+            state
+                .borrow()
+                .wl_buffer()
+                .and_then(|buffer| buffer.data::<SinglePixelBuffer>().map(|data| data.color))
+        })
+}
+
+pub fn init_single_pixel_buffer(dh: &DisplayHandle) {
+    dh.create_global::<State, WpSinglePixelBufferManagerV1, _>(1, ());
+}
+
+impl GlobalDispatch<WpSinglePixelBufferManagerV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<WpSinglePixelBufferManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<WpSinglePixelBufferManagerV1, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WpSinglePixelBufferManagerV1,
+        request: wp_single_pixel_buffer_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_single_pixel_buffer_manager_v1::Request::CreateU32RgbaBuffer { id, r, g, b, a } => {
+                data_init.init(id, SinglePixelBuffer::from_rgba(r, g, b, a));
+            }
+            wp_single_pixel_buffer_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<WlBuffer, SinglePixelBuffer> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WlBuffer,
+        request: wl_buffer::Request,
+        _data: &SinglePixelBuffer,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wl_buffer::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
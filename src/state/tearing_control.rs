@@ -0,0 +1,163 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::sync::Mutex;
+
+use smithay::{
+    reexports::wayland_server::{
+        protocol::wl_surface::WlSurface, Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch,
+        New, Resource, WEnum,
+    },
+    utils::IsAlive,
+    wayland::compositor::with_states,
+};
+
+use super::State;
+
+use self::generated::{
+    wp_tearing_control_manager_v1::{self, WpTearingControlManagerV1},
+    wp_tearing_control_v1::{self, WpTearingControlV1},
+};
+
+#[allow(non_upper_case_globals, non_camel_case_types, unused)]
+mod generated {
+    use smithay::reexports::wayland_server;
+    use smithay::reexports::wayland_server::protocol::*;
+
+    pub mod __interfaces {
+        use smithay::reexports::wayland_server::backend as wayland_backend;
+        use smithay::reexports::wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("resources/protocols/tearing-control-v1.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("resources/protocols/tearing-control-v1.xml");
+}
+
+pub use wp_tearing_control_v1::PresentationHint;
+
+/// Double-buffered presentation hint of a surface
+struct TearingControlState {
+    has_object: bool,
+    pending: PresentationHint,
+    current: PresentationHint,
+}
+
+type TearingControlData = Mutex<TearingControlState>;
+
+/// The presentation hint of `surface`, `PresentationHint::Vsync` if the client didn't set one
+pub fn surface_presentation_hint(surface: &WlSurface) -> PresentationHint {
+    with_states(surface, |states| {
+        states
+            .data_map
+            .get::<TearingControlData>()
+            .map(|state| state.lock().unwrap().current)
+            .unwrap_or(PresentationHint::Vsync)
+    })
+}
+
+/// Applies the pending presentation hint, called on every commit
+pub fn commit_presentation_hint(surface: &WlSurface) {
+    with_states(surface, |states| {
+        if let Some(state) = states.data_map.get::<TearingControlData>() {
+            let mut state = state.lock().unwrap();
+            state.current = state.pending;
+        }
+    });
+}
+
+pub fn init_tearing_control(dh: &DisplayHandle) {
+    dh.create_global::<State, WpTearingControlManagerV1, _>(1, ());
+}
+
+impl GlobalDispatch<WpTearingControlManagerV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<WpTearingControlManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<WpTearingControlManagerV1, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        resource: &WpTearingControlManagerV1,
+        request: wp_tearing_control_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_tearing_control_manager_v1::Request::GetTearingControl { id, surface } => {
+                let already_constructed = with_states(&surface, |states| {
+                    states.data_map.insert_if_missing_threadsafe(|| {
+                        Mutex::new(TearingControlState {
+                            has_object: false,
+                            pending: PresentationHint::Vsync,
+                            current: PresentationHint::Vsync,
+                        })
+                    });
+                    let mut state = states
+                        .data_map
+                        .get::<TearingControlData>()
+                        .unwrap()
+                        .lock()
+                        .unwrap();
+                    std::mem::replace(&mut state.has_object, true)
+                });
+
+                if already_constructed {
+                    resource.post_error(
+                        wp_tearing_control_manager_v1::Error::TearingControlExists,
+                        "surface already has a tearing control object",
+                    );
+                    return;
+                }
+                data_init.init(id, surface);
+            }
+            wp_tearing_control_manager_v1::Request::Destroy => {}
+        }
+    }
+}
+
+impl Dispatch<WpTearingControlV1, WlSurface> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WpTearingControlV1,
+        request: wp_tearing_control_v1::Request,
+        surface: &WlSurface,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        let update = |f: &dyn Fn(&mut TearingControlState)| {
+            if surface.alive() {
+                with_states(surface, |states| {
+                    if let Some(state) = states.data_map.get::<TearingControlData>() {
+                        f(&mut state.lock().unwrap());
+                    }
+                });
+            }
+        };
+
+        match request {
+            wp_tearing_control_v1::Request::SetPresentationHint { hint } => {
+                let hint = match hint {
+                    WEnum::Value(hint) => hint,
+                    WEnum::Unknown(_) => PresentationHint::Vsync,
+                };
+                update(&|state| state.pending = hint);
+            }
+            // Destroying the object goes back to vsync with the next commit
+            wp_tearing_control_v1::Request::Destroy => update(&|state| {
+                state.has_object = false;
+                state.pending = PresentationHint::Vsync;
+            }),
+        }
+    }
+}
@@ -0,0 +1,284 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context};
+use calloop::{
+    channel::{channel, Event, Sender},
+    LoopHandle,
+};
+use image::RgbaImage;
+use serde::Serialize;
+use smithay::desktop::Window;
+
+use super::{CommonState, LoopData};
+use crate::shell::window::WindowExt;
+
+/// Thumbnails nobody asked for in this long stop being rendered
+const KEEPALIVE: Duration = Duration::from_secs(5);
+
+/// Where to find the pixels of a thumbnail, the file is replaced on every update
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Thumbnail {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub stride: u32,
+    /// `wl_shm` format name of the pixel data
+    pub format: &'static str,
+    /// Increases with every update
+    pub serial: u64,
+}
+
+struct Entry {
+    max_size: u32,
+    requested: Instant,
+    updated: Option<Instant>,
+    /// While the writer has a newer one than `thumbnail`, which is not rendered again until then
+    writing: bool,
+    thumbnail: Option<Thumbnail>,
+}
+
+/// What the writer thread does, in order
+enum Job {
+    Write {
+        id: usize,
+        path: PathBuf,
+        image: RgbaImage,
+    },
+    Remove(PathBuf),
+}
+
+/// A thumbnail the writer is done with, `Err` if it could not be written
+struct Written {
+    id: usize,
+    path: PathBuf,
+    size: Result<(u32, u32), String>,
+}
+
+/// Windows that get downscaled snapshots rendered for external consumers. The files are written
+/// by a thread of their own, never while rendering.
+#[derive(Default)]
+pub struct Thumbnails {
+    entries: HashMap<usize, Entry>,
+    /// Set up the first time a thumbnail is written
+    jobs: Option<mpsc::Sender<Job>>,
+}
+
+impl Thumbnails {
+    fn remove_file(&mut self, path: &Path) {
+        let job = Job::Remove(path.to_path_buf());
+        let sent = match &self.jobs {
+            Some(jobs) => jobs.send(job).is_ok(),
+            None => false,
+        };
+        if !sent {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    fn jobs(&mut self, handle: &LoopHandle<'static, LoopData>) -> Option<&mpsc::Sender<Job>> {
+        if self.jobs.is_none() {
+            let (sender, written) = channel::<Written>();
+            let inserted = handle.insert_source(written, |event, _, data| {
+                if let Event::Msg(written) = event {
+                    data.state.common.thumbnail_written(written);
+                }
+            });
+            if inserted.is_err() {
+                slog_scope::warn!("Failed to register the thumbnail channel");
+                return None;
+            }
+            let (jobs, receiver) = mpsc::channel();
+            let spawned = std::thread::Builder::new()
+                .name("thumbnails".to_string())
+                .spawn(move || write_thread(receiver, sender));
+            match spawned {
+                Ok(_) => self.jobs = Some(jobs),
+                Err(err) => slog_scope::warn!("Failed to start writing thumbnails: {}", err),
+            }
+        }
+        self.jobs.as_ref()
+    }
+}
+
+fn write_thread(jobs: mpsc::Receiver<Job>, sender: Sender<Written>) {
+    for job in jobs {
+        let written = match job {
+            Job::Write { id, path, image } => {
+                let size = write(&path, &image)
+                    .map(|_| (image.width(), image.height()))
+                    .map_err(|err| format!("{:?}", err));
+                Written { id, path, size }
+            }
+            Job::Remove(path) => {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+        };
+        // The compositor is gone if this fails
+        if sender.send(written).is_err() {
+            return;
+        }
+    }
+}
+
+/// Readers never see a half written file, and only the user sees it at all
+fn write(path: &Path, image: &RgbaImage) -> anyhow::Result<()> {
+    let tmp = path.with_extension("tmp");
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)
+        .and_then(|mut file| file.write_all(image.as_raw()))
+        .and_then(|_| fs::rename(&tmp, path))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+impl CommonState {
+    /// Keeps the thumbnail of window `id` updated for a while, returns the last one rendered
+    pub fn request_thumbnail(
+        &mut self,
+        id: usize,
+        max_size: u32,
+    ) -> anyhow::Result<Option<Thumbnail>> {
+        if self.shell.find_window(id).is_none() {
+            return Err(anyhow!("No window with id {}", id));
+        }
+
+        let now = Instant::now();
+        let entry = self.thumbnails.entries.entry(id).or_insert(Entry {
+            max_size,
+            requested: now,
+            updated: None,
+            writing: false,
+            thumbnail: None,
+        });
+        if entry.max_size != max_size {
+            entry.max_size = max_size;
+            entry.updated = None;
+        }
+        entry.requested = now;
+        Ok(entry.thumbnail.clone())
+    }
+
+    /// Windows whose thumbnail should be rendered with the current frame, and their maximum size
+    pub fn due_thumbnails(&mut self) -> Vec<(Window, u32)> {
+        let now = Instant::now();
+        let interval = Duration::from_millis(self.config.thumbnail_interval);
+        let shell = &self.shell;
+        let textures = &mut self.textures;
+
+        let mut due = Vec::new();
+        let mut stale = Vec::new();
+        self.thumbnails.entries.retain(|id, entry| {
+            let window = match shell.find_window(*id) {
+                Some(window) if now.duration_since(entry.requested) < KEEPALIVE => window,
+                _ => {
+                    if let Some(thumbnail) = entry.thumbnail.take() {
+                        stale.push(thumbnail.path);
+                    }
+                    textures.evict(*id);
+                    return false;
+                }
+            };
+
+            let outdated = entry
+                .updated
+                .map(|updated| now.duration_since(updated) >= interval)
+                .unwrap_or(true);
+            if outdated && !entry.writing {
+                due.push((window, entry.max_size));
+            }
+            true
+        });
+        for path in stale {
+            self.thumbnails.remove_file(&path);
+        }
+        due
+    }
+
+    /// Hands a freshly rendered thumbnail to the writer, it is reported once it is in the
+    /// runtime directory
+    pub fn thumbnail_rendered(&mut self, window: &Window, image: RgbaImage) -> anyhow::Result<()> {
+        let id = window.id();
+        let path = self.thumbnail_path(id)?;
+        if !self.thumbnails.entries.contains_key(&id) {
+            return Ok(());
+        }
+        self.thumbnails
+            .jobs(&self.event_loop_handle)
+            .ok_or_else(|| anyhow!("Thumbnails can't be written"))?
+            .send(Job::Write { id, path, image })
+            .map_err(|_| anyhow!("The thumbnail writer stopped"))?;
+
+        if let Some(entry) = self.thumbnails.entries.get_mut(&id) {
+            entry.updated = Some(Instant::now());
+            entry.writing = true;
+        }
+        Ok(())
+    }
+
+    fn thumbnail_written(&mut self, written: Written) {
+        let entry = match self.thumbnails.entries.get_mut(&written.id) {
+            Some(entry) => entry,
+            // Nobody wants it anymore
+            None => {
+                self.thumbnails.remove_file(&written.path);
+                return;
+            }
+        };
+        entry.writing = false;
+        let (width, height) = match written.size {
+            Ok(size) => size,
+            Err(err) => {
+                slog_scope::debug!("Thumbnail failed: {}", err);
+                return;
+            }
+        };
+        let serial = entry.thumbnail.as_ref().map(|t| t.serial + 1).unwrap_or(0);
+        entry.thumbnail = Some(Thumbnail {
+            path: written.path,
+            width,
+            height,
+            stride: width * 4,
+            // RGBA bytes are a little endian abgr8888
+            format: "abgr8888",
+            serial,
+        });
+    }
+
+    /// `$XDG_RUNTIME_DIR/electrum-<wayland socket>-thumbnail-<id>.raw`, there is no other
+    /// directory only the user can read
+    fn thumbnail_path(&self, id: usize) -> anyhow::Result<PathBuf> {
+        let mut path = env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .ok_or_else(|| anyhow!("XDG_RUNTIME_DIR is not set, thumbnails are not written"))?;
+        path.push(format!(
+            "electrum-{}-thumbnail-{}.raw",
+            self.socket.to_string_lossy(),
+            id
+        ));
+        Ok(path)
+    }
+}
+
+impl Drop for Thumbnails {
+    fn drop(&mut self) {
+        for thumbnail in self.entries.values().filter_map(|e| e.thumbnail.as_ref()) {
+            let _ = fs::remove_file(&thumbnail.path);
+        }
+    }
+}
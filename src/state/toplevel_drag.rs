@@ -0,0 +1,238 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use smithay::{
+    desktop::{Kind, Window},
+    reexports::{
+        wayland_protocols::xdg::shell::server::xdg_toplevel::XdgToplevel,
+        wayland_server::{
+            backend::{ClientId, ObjectId},
+            protocol::wl_data_source::WlDataSource,
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+        },
+    },
+    utils::{Logical, Point},
+    wayland::seat::Seat,
+};
+
+use super::{output::active_output, sandbox::is_sandboxed, State};
+
+use self::generated::{
+    xdg_toplevel_drag_manager_v1::{self, XdgToplevelDragManagerV1},
+    xdg_toplevel_drag_v1::{self, XdgToplevelDragV1},
+};
+
+#[allow(non_upper_case_globals, non_camel_case_types, unused)]
+mod generated {
+    use smithay::reexports::wayland_protocols::xdg::shell::server::*;
+    use smithay::reexports::wayland_server;
+    use smithay::reexports::wayland_server::protocol::*;
+
+    pub mod __interfaces {
+        use smithay::reexports::wayland_protocols::xdg::shell::__interfaces::*;
+        use smithay::reexports::wayland_server::backend as wayland_backend;
+        use smithay::reexports::wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("resources/protocols/xdg-toplevel-drag-v1.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("resources/protocols/xdg-toplevel-drag-v1.xml");
+}
+
+/// A drag that can carry a window along, like a browser tab torn off into a window of its own
+pub struct ToplevelDrag {
+    drag: XdgToplevelDragV1,
+    source: WlDataSource,
+    /// The attached window and where the pointer is in it
+    toplevel: Option<(XdgToplevel, Point<i32, Logical>)>,
+    /// The seat dragging, `None` until the drag started
+    seat: Option<Seat<State>>,
+}
+
+pub fn init_toplevel_drag(dh: &DisplayHandle) {
+    dh.create_global::<State, XdgToplevelDragManagerV1, _>(1, ());
+}
+
+impl State {
+    /// A client started dragging `source` with `seat`, an attached window follows the pointer
+    pub(super) fn toplevel_drag_started(&mut self, source: &WlDataSource, seat: &Seat<State>) {
+        let drags = &mut self.common.toplevel_drags;
+        let drag = drags
+            .iter_mut()
+            .find(|drag| drag.source == *source)
+            .map(|drag| {
+                drag.seat = Some(seat.clone());
+                drag.drag.clone()
+            });
+        if let Some(drag) = drag {
+            self.carry_attached(&drag);
+        }
+    }
+
+    /// The drag of `seat` ended, the window it carried is mapped where it was dropped
+    pub(super) fn toplevel_drag_dropped(&mut self, seat: &Seat<State>) {
+        let drags = &mut self.common.toplevel_drags;
+        let drag = match drags
+            .iter_mut()
+            .find(|drag| drag.seat.as_ref() == Some(seat))
+        {
+            Some(drag) => drag,
+            None => return,
+        };
+        drag.seat = None;
+        if drag.toplevel.take().is_none() {
+            return;
+        }
+        // Outside of the drag grab, like dropping a moved window
+        let seat = seat.clone();
+        self.common.event_loop_handle.insert_idle(move |data| {
+            let dh = data.display.handle();
+            let output = active_output(&seat, &data.state.common);
+            data.state.common.shell.drop_move(&dh, &seat, &output);
+            data.state.common.constrain_windows();
+        });
+    }
+
+    /// Starts carrying the window attached to `drag`, once both the drag started and a window
+    /// is attached
+    fn carry_attached(&mut self, drag: &XdgToplevelDragV1) {
+        let drags = &self.common.toplevel_drags;
+        let (toplevel, offset, seat) = match drags.iter().find(|d| d.drag == *drag) {
+            Some(ToplevelDrag {
+                toplevel: Some((toplevel, offset)),
+                seat: Some(seat),
+                ..
+            }) => (toplevel.clone(), *offset, seat.clone()),
+            _ => return,
+        };
+        if let Some(window) = self.toplevel_window(&toplevel) {
+            self.common.shell.carry_window(&window, &seat, offset);
+        }
+    }
+
+    /// The window of `toplevel`, mapped or not
+    pub(super) fn toplevel_window(&self, toplevel: &XdgToplevel) -> Option<Window> {
+        let shell = &self.common.shell;
+        let pending = shell.pending_windows.iter().map(|(window, _)| window);
+        let mapped = shell.workspaces.iter().flat_map(|w| w.space.windows());
+        pending
+            .chain(mapped)
+            .find(|window| match window.toplevel() {
+                Kind::Xdg(surface) => surface.xdg_toplevel() == toplevel,
+            })
+            .cloned()
+    }
+}
+
+impl GlobalDispatch<XdgToplevelDragManagerV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<XdgToplevelDragManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+
+    /// Sandboxed clients don't get to move windows along with their drags
+    fn can_view(client: Client, _global_data: &()) -> bool {
+        !is_sandboxed(&client)
+    }
+}
+
+impl Dispatch<XdgToplevelDragManagerV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &XdgToplevelDragManagerV1,
+        request: xdg_toplevel_drag_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            xdg_toplevel_drag_manager_v1::Request::GetXdgToplevelDrag { id, data_source } => {
+                let drags = &mut state.common.toplevel_drags;
+                if drags.iter().any(|drag| drag.source == data_source) {
+                    resource.post_error(
+                        xdg_toplevel_drag_manager_v1::Error::InvalidSource,
+                        "data source already used for a toplevel drag",
+                    );
+                    return;
+                }
+                let drag = data_init.init(id, ());
+                drags.push(ToplevelDrag {
+                    drag,
+                    source: data_source,
+                    toplevel: None,
+                    seat: None,
+                });
+            }
+            xdg_toplevel_drag_manager_v1::Request::Destroy => {}
+        }
+    }
+}
+
+impl Dispatch<XdgToplevelDragV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &XdgToplevelDragV1,
+        request: xdg_toplevel_drag_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        let attached = state
+            .common
+            .toplevel_drags
+            .iter()
+            .find(|drag| drag.drag == *resource)
+            .map(|drag| (drag.toplevel.clone(), drag.seat.is_some()));
+        let (toplevel, dragging) = match attached {
+            Some(attached) => attached,
+            None => return,
+        };
+
+        match request {
+            xdg_toplevel_drag_v1::Request::Attach {
+                toplevel: new_toplevel,
+                x_offset,
+                y_offset,
+            } => {
+                // Windows that are gone since don't count
+                let attached = toplevel.map_or(false, |(toplevel, _)| {
+                    state.toplevel_window(&toplevel).is_some()
+                });
+                if attached {
+                    resource.post_error(
+                        xdg_toplevel_drag_v1::Error::ToplevelAttached,
+                        "a toplevel is already attached",
+                    );
+                    return;
+                }
+                let drags = &mut state.common.toplevel_drags;
+                if let Some(drag) = drags.iter_mut().find(|drag| drag.drag == *resource) {
+                    drag.toplevel = Some((new_toplevel, (x_offset, y_offset).into()));
+                }
+                state.carry_attached(resource);
+            }
+            xdg_toplevel_drag_v1::Request::Destroy => {
+                if dragging {
+                    resource.post_error(
+                        xdg_toplevel_drag_v1::Error::OngoingDrag,
+                        "the drag has not ended",
+                    );
+                }
+            }
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .common
+            .toplevel_drags
+            .retain(|drag| drag.drag.id() != resource);
+    }
+}
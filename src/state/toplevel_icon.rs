@@ -0,0 +1,276 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::sync::{Arc, Mutex};
+
+use image::RgbaImage;
+use smithay::{
+    reexports::wayland_server::{
+        protocol::{wl_buffer::WlBuffer, wl_shm::Format, wl_surface::WlSurface},
+        Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+    },
+    wayland::{compositor::with_states, shm::with_buffer_contents},
+};
+
+use super::{sandbox::is_sandboxed, State};
+use crate::appinfo::PREFERRED_SIZE;
+
+use self::generated::{
+    xdg_toplevel_icon_manager_v1::{self, XdgToplevelIconManagerV1},
+    xdg_toplevel_icon_v1::{self, XdgToplevelIconV1},
+};
+
+#[allow(non_upper_case_globals, non_camel_case_types, unused)]
+mod generated {
+    use smithay::reexports::wayland_protocols::xdg::shell::server::*;
+    use smithay::reexports::wayland_server;
+    use smithay::reexports::wayland_server::protocol::*;
+
+    pub mod __interfaces {
+        use smithay::reexports::wayland_protocols::xdg::shell::__interfaces::*;
+        use smithay::reexports::wayland_server::backend as wayland_backend;
+        use smithay::reexports::wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("resources/protocols/xdg-toplevel-icon-v1.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("resources/protocols/xdg-toplevel-icon-v1.xml");
+}
+
+/// The icon a client set for one of its windows, in place of the one of its application
+#[derive(Clone, Default)]
+pub struct ToplevelIcon {
+    pub name: Option<String>,
+    /// The icon called `name` in the icon theme once it was decoded, until then or if there is no
+    /// such icon the largest buffer. Straight alpha.
+    pub image: Option<Arc<RgbaImage>>,
+}
+
+/// An icon object until it is set on a window, after that it can't change anymore
+#[derive(Default)]
+pub struct IconBuilder {
+    name: Option<String>,
+    /// Copies of the buffers by their size in pixels and scale
+    buffers: Vec<(u32, i32, Arc<RgbaImage>)>,
+    immutable: bool,
+}
+
+/// Double-buffered icon of a toplevel surface, `None` is the one of its application
+#[derive(Default)]
+struct ToplevelIconState {
+    pending: Option<Option<ToplevelIcon>>,
+    current: Option<ToplevelIcon>,
+}
+
+type ToplevelIconData = Mutex<ToplevelIconState>;
+
+/// The icon the client set for the toplevel `surface`
+pub fn toplevel_icon(surface: &WlSurface) -> Option<ToplevelIcon> {
+    with_states(surface, |states| {
+        states
+            .data_map
+            .get::<ToplevelIconData>()
+            .and_then(|state| state.lock().unwrap().current.clone())
+    })
+}
+
+/// Sets the image of the icon called `name`, if `surface` still has that icon
+pub fn named_icon_decoded(surface: &WlSurface, name: &str, image: Arc<RgbaImage>) {
+    with_states(surface, |states| {
+        if let Some(state) = states.data_map.get::<ToplevelIconData>() {
+            let mut state = state.lock().unwrap();
+            let state = &mut *state;
+            let pending = state.pending.as_mut().and_then(Option::as_mut);
+            for icon in pending.into_iter().chain(state.current.as_mut()) {
+                if icon.name.as_deref() == Some(name) {
+                    icon.image = Some(image.clone());
+                }
+            }
+        }
+    });
+}
+
+/// Applies the icon set since the last commit, called on every commit
+pub fn commit_toplevel_icon(surface: &WlSurface) {
+    with_states(surface, |states| {
+        if let Some(state) = states.data_map.get::<ToplevelIconData>() {
+            let mut state = state.lock().unwrap();
+            if let Some(icon) = state.pending.take() {
+                state.current = icon;
+            }
+        }
+    });
+}
+
+pub fn init_toplevel_icon(dh: &DisplayHandle) {
+    dh.create_global::<State, XdgToplevelIconManagerV1, _>(1, ());
+}
+
+/// Copies an argb8888 or xrgb8888 shm buffer, `None` if it is neither or not square
+fn copy_buffer(buffer: &WlBuffer) -> Option<RgbaImage> {
+    with_buffer_contents(buffer, |slice, data| {
+        if data.width != data.height || data.width <= 0 {
+            return None;
+        }
+        let opaque = match data.format {
+            Format::Argb8888 => false,
+            Format::Xrgb8888 => true,
+            _ => return None,
+        };
+        let size = data.width as u32;
+        let mut image = RgbaImage::new(size, size);
+        for (y, row) in image.rows_mut().enumerate() {
+            let start = data.offset as usize + y * data.stride as usize;
+            let source = slice.get(start..start + size as usize * 4)?;
+            for (pixel, bgra) in row.zip(source.chunks_exact(4)) {
+                let alpha = if opaque { 255 } else { bgra[3] };
+                // Buffers are premultiplied, images drawn by the compositor are not
+                let unpremultiply = |c: u8| match alpha {
+                    0 => 0,
+                    _ => (c as u32 * 255 / alpha as u32).min(255) as u8,
+                };
+                pixel.0 = [
+                    unpremultiply(bgra[2]),
+                    unpremultiply(bgra[1]),
+                    unpremultiply(bgra[0]),
+                    alpha,
+                ];
+            }
+        }
+        Some(image)
+    })
+    .ok()
+    .flatten()
+}
+
+impl GlobalDispatch<XdgToplevelIconManagerV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<XdgToplevelIconManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let manager = data_init.init(resource, ());
+        // The size icons are looked up at for the switcher and docks
+        manager.icon_size(PREFERRED_SIZE as i32);
+        manager.done();
+    }
+
+    /// Hidden from sandboxed clients, their icons could pass for those of other apps
+    fn can_view(client: Client, _global_data: &()) -> bool {
+        !is_sandboxed(&client)
+    }
+}
+
+impl Dispatch<XdgToplevelIconManagerV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &XdgToplevelIconManagerV1,
+        request: xdg_toplevel_icon_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            xdg_toplevel_icon_manager_v1::Request::CreateIcon { id } => {
+                data_init.init(id, Mutex::new(IconBuilder::default()));
+            }
+            xdg_toplevel_icon_manager_v1::Request::SetIcon { toplevel, icon } => {
+                let window = match state.toplevel_window(&toplevel) {
+                    Some(window) => window,
+                    None => return,
+                };
+                let common = &mut state.common;
+                common.icons.forget_window(&window);
+                let icon = icon.and_then(|icon| {
+                    let builder = icon.data::<Mutex<IconBuilder>>()?;
+                    let mut builder = builder.lock().unwrap();
+                    builder.immutable = true;
+                    // Decoded on a worker, the buffers stand in until it is done
+                    let named = builder.name.as_deref().and_then(|name| {
+                        let path = common.app_info.icon(name)?;
+                        let handle = &common.event_loop_handle;
+                        common.icons.get_for_window(handle, &window, name, &path)
+                    });
+                    let image = named.or_else(|| {
+                        builder
+                            .buffers
+                            .iter()
+                            .max_by_key(|(size, _, _)| *size)
+                            .map(|(_, _, image)| image.clone())
+                    });
+                    // An empty icon resets like no icon
+                    (builder.name.is_some() || image.is_some()).then(|| ToplevelIcon {
+                        name: builder.name.clone(),
+                        image,
+                    })
+                });
+                with_states(window.toplevel().wl_surface(), |states| {
+                    states
+                        .data_map
+                        .insert_if_missing_threadsafe(ToplevelIconData::default);
+                    let state = states.data_map.get::<ToplevelIconData>().unwrap();
+                    state.lock().unwrap().pending = Some(icon);
+                });
+            }
+            xdg_toplevel_icon_manager_v1::Request::Destroy => {}
+        }
+    }
+}
+
+impl Dispatch<XdgToplevelIconV1, Mutex<IconBuilder>> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        resource: &XdgToplevelIconV1,
+        request: xdg_toplevel_icon_v1::Request,
+        data: &Mutex<IconBuilder>,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        let mut builder = data.lock().unwrap();
+        let immutable = || {
+            resource.post_error(
+                xdg_toplevel_icon_v1::Error::Immutable,
+                "the icon was already set on a toplevel",
+            )
+        };
+        match request {
+            xdg_toplevel_icon_v1::Request::SetName { icon_name } => {
+                if builder.immutable {
+                    return immutable();
+                }
+                // Only names of the icon theme, never files
+                if icon_name.is_empty() || icon_name.contains('/') {
+                    slog_scope::debug!("Ignoring icon name {:?}", icon_name);
+                    return;
+                }
+                builder.name = Some(icon_name);
+            }
+            xdg_toplevel_icon_v1::Request::AddBuffer { buffer, scale } => {
+                if builder.immutable {
+                    return immutable();
+                }
+                // Copied right away, so the buffer may go whenever the client likes
+                let image = match copy_buffer(&buffer) {
+                    Some(image) => image,
+                    None => {
+                        resource.post_error(
+                            xdg_toplevel_icon_v1::Error::InvalidBuffer,
+                            "icon buffers must be square argb8888 or xrgb8888 shm buffers",
+                        );
+                        return;
+                    }
+                };
+                let size = image.width();
+                builder
+                    .buffers
+                    .retain(|(other, other_scale, _)| (*other, *other_scale) != (size, scale));
+                builder.buffers.push((size, scale, Arc::new(image)));
+            }
+            xdg_toplevel_icon_v1::Request::Destroy => {}
+        }
+    }
+}
@@ -0,0 +1,429 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+    sync::Mutex,
+    time::Duration,
+};
+
+use calloop::{
+    channel::Sender,
+    timer::{TimeoutAction, Timer},
+    LoopHandle, RegistrationToken,
+};
+use serde::Deserialize;
+use smithay::{
+    reexports::wayland_server::{
+        backend::{ClientId, ObjectId},
+        protocol::wl_surface::WlSurface,
+        Client, DisplayHandle, Resource,
+    },
+    utils::{Logical, Point},
+    wayland::{
+        compositor::with_states, data_device::set_data_device_focus,
+        primary_selection::set_primary_focus, seat::Seat,
+        shell::xdg::XdgToplevelSurfaceRoleAttributes,
+    },
+};
+
+use super::{selection::SelectionKind, LoopData, State};
+use crate::{runtime::messages::RuntimeMessage, shell::decisions::DecisionPolicy};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TransferFilterConfig {
+    /// Milliseconds the runtime gets to allow a transfer
+    pub timeout: u64,
+    pub policy: DecisionPolicy,
+}
+
+impl Default for TransferFilterConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 50,
+            policy: DecisionPolicy::Deny,
+        }
+    }
+}
+
+/// The client that owns a selection or drag, its app and what it offers
+struct Owner {
+    client: Option<ClientId>,
+    app_id: Option<String>,
+    mime_types: Vec<String>,
+}
+
+impl Owner {
+    /// Apps may always paste their own selections
+    fn owns(&self, client: &Client, app_id: &Option<String>) -> bool {
+        self.client == Some(client.id()) || (self.app_id.is_some() && &self.app_id == app_id)
+    }
+}
+
+/// A drag of a client, with the clients the runtime answered for so far
+struct ClientDrag {
+    seat: Seat<State>,
+    allowed: HashSet<ClientId>,
+    denied: HashSet<ClientId>,
+}
+
+enum Verdict {
+    Allow,
+    Deny,
+    Ask(RuntimeMessage),
+}
+
+impl From<DecisionPolicy> for Verdict {
+    fn from(policy: DecisionPolicy) -> Self {
+        match policy {
+            DecisionPolicy::Allow => Verdict::Allow,
+            DecisionPolicy::Deny => Verdict::Deny,
+        }
+    }
+}
+
+struct PendingTransfer {
+    seat: Seat<State>,
+    client: Client,
+    kind: SelectionKind,
+    token: RegistrationToken,
+}
+
+struct Inner {
+    enabled: bool,
+    config: TransferFilterConfig,
+    owners: HashMap<SelectionKind, Owner>,
+    next_id: u64,
+    pending: HashMap<u64, PendingTransfer>,
+    drag: Option<ClientDrag>,
+}
+
+/// Decides which clients get offered the selections and drags of other apps. Selections are
+/// only offered to the client with keyboard focus, so once the runtime enables the filter, a
+/// focused client of another app than the owner gets them after the runtime allowed it. Drags
+/// skip the surfaces of other apps until the runtime allowed them. Every seat shares one,
+/// through its user data, with `CommonState`.
+#[derive(Clone)]
+pub struct TransferFilter {
+    inner: Rc<RefCell<Inner>>,
+    dh: DisplayHandle,
+    runtime_sender: Sender<RuntimeMessage>,
+    event_loop_handle: LoopHandle<'static, LoopData>,
+}
+
+impl TransferFilter {
+    pub fn new(
+        config: TransferFilterConfig,
+        dh: &DisplayHandle,
+        runtime_sender: Sender<RuntimeMessage>,
+        event_loop_handle: LoopHandle<'static, LoopData>,
+    ) -> Self {
+        TransferFilter {
+            inner: Rc::new(RefCell::new(Inner {
+                enabled: false,
+                config,
+                owners: HashMap::new(),
+                next_id: 0,
+                pending: HashMap::new(),
+                drag: None,
+            })),
+            dh: dh.clone(),
+            runtime_sender,
+            event_loop_handle,
+        }
+    }
+
+    /// Turns asking the runtime on or off, turning it off allows the transfers still waiting for
+    /// an answer
+    pub fn set_enabled(&self, enabled: bool) {
+        self.inner.borrow_mut().enabled = enabled;
+        if !enabled {
+            let pending = self
+                .inner
+                .borrow()
+                .pending
+                .keys()
+                .copied()
+                .collect::<Vec<_>>();
+            for id in pending {
+                self.decide(id, Some(true));
+            }
+        }
+    }
+
+    /// The client `resource` belongs to, if it is still connected
+    pub fn client(&self, resource: ObjectId) -> Option<Client> {
+        self.dh.get_client(resource).ok()
+    }
+
+    /// Records the new owner of a selection and asks again for the clients focused by `seats`.
+    /// `source` is the data source of the owner, `app_id` the app of its client.
+    pub fn set_owner(
+        &self,
+        kind: SelectionKind,
+        source: Option<ObjectId>,
+        app_id: Option<String>,
+        mime_types: Option<Vec<String>>,
+        seats: &[Seat<State>],
+    ) {
+        {
+            let mut inner = self.inner.borrow_mut();
+            match mime_types {
+                Some(mime_types) => {
+                    let client = source
+                        .and_then(|source| self.client(source))
+                        .map(|client| client.id());
+                    inner.owners.insert(
+                        kind,
+                        Owner {
+                            client,
+                            app_id,
+                            mime_types,
+                        },
+                    );
+                }
+                None => {
+                    inner.owners.remove(&kind);
+                }
+            }
+        }
+        for seat in seats {
+            let focus = seat
+                .get_keyboard()
+                .and_then(|keyboard| keyboard.current_focus());
+            self.offer(seat, focus.as_ref(), kind);
+        }
+    }
+
+    /// Starts filtering the surfaces a drag out of `source` on `seat` is offered to
+    pub fn drag_started(
+        &self,
+        seat: &Seat<State>,
+        source: ObjectId,
+        app_id: Option<String>,
+        mime_types: Vec<String>,
+    ) {
+        let client = self.client(source).map(|client| client.id());
+        let mut inner = self.inner.borrow_mut();
+        inner.owners.insert(
+            SelectionKind::Drag,
+            Owner {
+                client,
+                app_id,
+                mime_types,
+            },
+        );
+        inner.drag = Some(ClientDrag {
+            seat: seat.clone(),
+            allowed: HashSet::new(),
+            denied: HashSet::new(),
+        });
+    }
+
+    pub fn drag_ended(&self, seat: &Seat<State>) {
+        let mut inner = self.inner.borrow_mut();
+        if matches!(&inner.drag, Some(drag) if &drag.seat == seat) {
+            inner.drag = None;
+            inner.owners.remove(&SelectionKind::Drag);
+        }
+    }
+
+    /// Where motion during a drag on `seat` goes, the surface under the pointer unless it
+    /// belongs to another app the runtime did not allow the drag for. One it allows gets the
+    /// drag with the next motion.
+    pub fn drag_target(
+        &self,
+        seat: &Seat<State>,
+        under: Option<(WlSurface, Point<i32, Logical>)>,
+    ) -> Option<(WlSurface, Point<i32, Logical>)> {
+        let dragging = matches!(&self.inner.borrow().drag, Some(drag) if &drag.seat == seat);
+        let (surface, _) = match &under {
+            Some(under) if dragging => under,
+            _ => return under,
+        };
+        let client = self.client(surface.id())?;
+        match self.verdict(seat, &client, app_id(surface), SelectionKind::Drag) {
+            Verdict::Allow => under,
+            Verdict::Deny => None,
+            Verdict::Ask(message) => {
+                let _ = self.runtime_sender.send(message);
+                None
+            }
+        }
+    }
+
+    /// Offers the selections to the client of `focus`, as far as they may be
+    pub fn focus_changed(&self, seat: &Seat<State>, focus: Option<&WlSurface>) {
+        self.offer(seat, focus, SelectionKind::Clipboard);
+        self.offer(seat, focus, SelectionKind::Primary);
+    }
+
+    fn offer(&self, seat: &Seat<State>, focus: Option<&WlSurface>, kind: SelectionKind) {
+        let client = match focus.and_then(|surface| self.dh.get_client(surface.id()).ok()) {
+            Some(client) => client,
+            None => return self.set_focus(seat, None, kind),
+        };
+        match self.verdict(seat, &client, focus.and_then(app_id), kind) {
+            Verdict::Allow => self.set_focus(seat, Some(client), kind),
+            Verdict::Deny => self.set_focus(seat, None, kind),
+            // Nothing is offered until the runtime answered
+            Verdict::Ask(message) => {
+                self.set_focus(seat, None, kind);
+                let _ = self.runtime_sender.send(message);
+            }
+        }
+    }
+
+    fn verdict(
+        &self,
+        seat: &Seat<State>,
+        client: &Client,
+        target: Option<String>,
+        kind: SelectionKind,
+    ) -> Verdict {
+        let mut inner = self.inner.borrow_mut();
+        if !inner.enabled {
+            return Verdict::Allow;
+        }
+        let (source, mime_types) = match inner.owners.get(&kind) {
+            Some(owner) if !owner.owns(client, &target) => {
+                (owner.app_id.clone(), owner.mime_types.clone())
+            }
+            _ => return Verdict::Allow,
+        };
+        if kind == SelectionKind::Drag {
+            // Every motion over the same client asks, only the first one goes to the runtime
+            if let Some(drag) = &inner.drag {
+                if drag.allowed.contains(&client.id()) {
+                    return Verdict::Allow;
+                }
+                if drag.denied.contains(&client.id()) {
+                    return Verdict::Deny;
+                }
+            }
+            let asked = inner
+                .pending
+                .values()
+                .any(|pending| pending.kind == kind && pending.client.id() == client.id());
+            if asked {
+                return Verdict::Deny;
+            }
+        }
+
+        // A newer request for the same selection on this seat replaces the older one
+        let outdated = inner
+            .pending
+            .iter()
+            .filter(|(_, pending)| &pending.seat == seat && pending.kind == kind)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for outdated in outdated {
+            if let Some(pending) = inner.pending.remove(&outdated) {
+                self.event_loop_handle.remove(pending.token);
+            }
+        }
+
+        inner.next_id += 1;
+        let id = inner.next_id;
+        let timeout = Duration::from_millis(inner.config.timeout);
+        let token = self.event_loop_handle.insert_source(
+            Timer::from_duration(timeout),
+            move |_, _, data| {
+                data.state.common.transfers.decide(id, None);
+                TimeoutAction::Drop
+            },
+        );
+        let token = match token {
+            Ok(token) => token,
+            Err(_) => {
+                slog_scope::warn!("Failed to start the timeout of transfer {}", id);
+                return inner.config.policy.into();
+            }
+        };
+        inner.pending.insert(
+            id,
+            PendingTransfer {
+                seat: seat.clone(),
+                client: client.clone(),
+                kind,
+                token,
+            },
+        );
+        Verdict::Ask(RuntimeMessage::TransferRequest {
+            request: id,
+            kind,
+            source,
+            target,
+            mime_types,
+        })
+    }
+
+    /// Offers the selection of transfer `id` if the runtime allowed it, `None` if it did not
+    /// answer in time
+    pub fn decide(&self, id: u64, allow: Option<bool>) {
+        let (pending, policy) = {
+            let mut inner = self.inner.borrow_mut();
+            match inner.pending.remove(&id) {
+                Some(pending) => (pending, inner.config.policy),
+                None => {
+                    slog_scope::debug!("Transfer {} was already decided on", id);
+                    return;
+                }
+            }
+        };
+        let allow = match allow {
+            Some(allow) => {
+                self.event_loop_handle.remove(pending.token);
+                allow
+            }
+            None => {
+                slog_scope::debug!("The runtime did not decide on transfer {} in time", id);
+                policy == DecisionPolicy::Allow
+            }
+        };
+        if pending.kind == SelectionKind::Drag {
+            let mut inner = self.inner.borrow_mut();
+            if let Some(drag) = inner.drag.as_mut().filter(|drag| drag.seat == pending.seat) {
+                if allow {
+                    drag.allowed.insert(pending.client.id());
+                } else {
+                    drag.denied.insert(pending.client.id());
+                }
+            }
+            return;
+        }
+        if !allow {
+            return;
+        }
+
+        // Focus may have moved on meanwhile
+        let focused = pending
+            .seat
+            .get_keyboard()
+            .and_then(|keyboard| keyboard.current_focus())
+            .and_then(|surface| self.dh.get_client(surface.id()).ok());
+        if focused.as_ref().map(Client::id) == Some(pending.client.id()) {
+            self.set_focus(&pending.seat, focused, pending.kind);
+        }
+    }
+
+    fn set_focus(&self, seat: &Seat<State>, client: Option<Client>, kind: SelectionKind) {
+        match kind {
+            SelectionKind::Clipboard => set_data_device_focus(&self.dh, seat, client),
+            SelectionKind::Primary => set_primary_focus(&self.dh, seat, client),
+            SelectionKind::Drag => {}
+        }
+    }
+}
+
+/// The app id of the toplevel `surface` belongs to, if it is one
+pub fn app_id(surface: &WlSurface) -> Option<String> {
+    with_states(surface, |states| {
+        states
+            .data_map
+            .get::<Mutex<XdgToplevelSurfaceRoleAttributes>>()
+            .and_then(|attrs| attrs.lock().unwrap().app_id.clone())
+    })
+}
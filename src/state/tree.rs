@@ -0,0 +1,236 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use serde::Serialize;
+use smithay::{
+    desktop::{layer_map_for_output, Kind, Window},
+    reexports::wayland_protocols::xdg::shell::server::xdg_toplevel::State as XdgState,
+    wayland::{
+        output::Output,
+        shell::{wlr_layer::Layer as WlrLayer, xdg::ToplevelStateSet},
+    },
+};
+
+use super::{output::OutputInfo, State};
+use crate::{
+    input::seats::SeatInfo,
+    shell::{
+        grab::grabbed_window,
+        window::{WindowExt, WindowInfo},
+        workspace::Workspace,
+    },
+};
+
+/// Everything the compositor tracks about outputs, workspaces, windows and seats, for bug reports
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tree {
+    pub outputs: Vec<OutputNode>,
+    pub workspaces: Vec<WorkspaceNode>,
+    /// Windows that did not commit a buffer yet
+    pub pending_windows: Vec<WindowInfo>,
+    pub seats: Vec<SeatNode>,
+    pub keybinding_mode: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputNode {
+    #[serde(flatten)]
+    pub info: OutputInfo,
+    /// Bottom to top
+    pub layers: Vec<LayerNode>,
+}
+
+/// A layer surface, positioned relative to its output
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayerNode {
+    pub namespace: String,
+    /// `background`, `bottom`, `top` or `overlay`
+    pub layer: &'static str,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceNode {
+    pub name: String,
+    pub active: bool,
+    /// Bottom to top
+    pub windows: Vec<WindowNode>,
+}
+
+/// A mapped window, positioned globally by its window geometry
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowNode {
+    #[serde(flatten)]
+    pub info: WindowInfo,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    /// States the client acknowledged, like `maximized`, `fullscreen` or `tiled-left`
+    pub states: Vec<&'static str>,
+    /// Whether the client was sent a state it did not commit yet
+    pub pending_configure: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SeatNode {
+    #[serde(flatten)]
+    pub info: SeatInfo,
+    pub pointer: Option<(f64, f64)>,
+    pub pointer_grab: bool,
+    pub keyboard_grab: bool,
+    /// The window the seat is moving, it is not mapped meanwhile
+    pub moving: Option<usize>,
+    /// Windows of the shown workspace, the most recently focused first
+    pub focus_stack: Vec<usize>,
+}
+
+impl State {
+    pub fn tree(&self) -> Tree {
+        let shell = &self.common.shell;
+        // Disabled outputs are listed as well, without layer surfaces
+        let outputs = self
+            .common
+            .output_infos()
+            .into_iter()
+            .map(|info| OutputNode {
+                layers: shell
+                    .outputs()
+                    .find(|output| output.name() == info.name)
+                    .map(layer_nodes)
+                    .unwrap_or_default(),
+                info,
+            })
+            .collect();
+
+        let active = shell.active_workspace();
+        let workspaces = shell
+            .workspaces
+            .iter()
+            .map(|workspace| WorkspaceNode {
+                name: workspace.name.clone(),
+                active: workspace.idx == active.idx,
+                windows: workspace
+                    .space
+                    .windows()
+                    .map(|window| window_node(workspace, window))
+                    .collect(),
+            })
+            .collect();
+
+        let seats = self
+            .common
+            .seats
+            .iter()
+            .zip(self.seat_infos())
+            .map(|(seat, info)| {
+                let pointer = seat.get_pointer();
+                SeatNode {
+                    info,
+                    pointer: pointer.as_ref().map(|pointer| {
+                        let location = pointer.current_location();
+                        (location.x, location.y)
+                    }),
+                    pointer_grab: pointer.map_or(false, |pointer| pointer.is_grabbed()),
+                    keyboard_grab: seat.get_keyboard().map_or(false, |k| k.is_grabbed()),
+                    moving: grabbed_window(seat).map(|window| window.id()),
+                    focus_stack: active
+                        .focus_stack(seat)
+                        .iter_recent()
+                        .map(|window| window.id())
+                        .collect(),
+                }
+            })
+            .collect();
+
+        Tree {
+            outputs,
+            workspaces,
+            pending_windows: shell
+                .pending_windows
+                .iter()
+                .map(|(window, _)| window.info())
+                .collect(),
+            seats,
+            keybinding_mode: self.common.keybindings.mode().to_string(),
+        }
+    }
+}
+
+fn layer_nodes(output: &Output) -> Vec<LayerNode> {
+    let layer_map = layer_map_for_output(output);
+    let layers = [
+        (WlrLayer::Background, "background"),
+        (WlrLayer::Bottom, "bottom"),
+        (WlrLayer::Top, "top"),
+        (WlrLayer::Overlay, "overlay"),
+    ];
+    layers
+        .into_iter()
+        .flat_map(|(layer, name)| {
+            layer_map
+                .layers_on(layer)
+                .map(move |surface| (surface, name))
+        })
+        .filter_map(|(surface, layer)| {
+            let geometry = layer_map.layer_geometry(surface)?;
+            Some(LayerNode {
+                namespace: surface.namespace().to_string(),
+                layer,
+                x: geometry.loc.x,
+                y: geometry.loc.y,
+                width: geometry.size.w,
+                height: geometry.size.h,
+            })
+        })
+        .collect()
+}
+
+fn window_node(workspace: &Workspace, window: &Window) -> WindowNode {
+    let location = workspace.space.window_location(window).unwrap_or_default();
+    let size = window.geometry().size;
+    let (states, pending_configure) = match window.toplevel() {
+        Kind::Xdg(toplevel) => {
+            let current = toplevel.current_state();
+            let pending = toplevel.with_pending_state(|state| state.clone());
+            (state_names(&current.states), pending != current)
+        }
+    };
+    WindowNode {
+        info: WindowInfo {
+            workspace: Some(workspace.name.clone()),
+            ..window.info()
+        },
+        x: location.x,
+        y: location.y,
+        width: size.w,
+        height: size.h,
+        states,
+        pending_configure,
+    }
+}
+
+fn state_names(states: &ToplevelStateSet) -> Vec<&'static str> {
+    [
+        (XdgState::Maximized, "maximized"),
+        (XdgState::Fullscreen, "fullscreen"),
+        (XdgState::Resizing, "resizing"),
+        (XdgState::Activated, "activated"),
+        (XdgState::TiledLeft, "tiled-left"),
+        (XdgState::TiledRight, "tiled-right"),
+        (XdgState::TiledTop, "tiled-top"),
+        (XdgState::TiledBottom, "tiled-bottom"),
+    ]
+    .into_iter()
+    .filter(|(state, _)| states.contains(*state))
+    .map(|(_, name)| name)
+    .collect()
+}
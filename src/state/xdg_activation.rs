@@ -0,0 +1,75 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::time::Duration;
+
+use smithay::{
+    delegate_xdg_activation,
+    desktop::WindowSurfaceType,
+    reexports::wayland_server::{protocol::wl_surface::WlSurface, DisplayHandle},
+    wayland::xdg_activation::{
+        XdgActivationHandler, XdgActivationState, XdgActivationToken, XdgActivationTokenData,
+    },
+};
+
+use super::State;
+
+/// Tokens older than this only mark the window as urgent
+const ACTIVATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl XdgActivationHandler for State {
+    fn activation_state(&mut self) -> &mut XdgActivationState {
+        &mut self.common.xdg_activation_state
+    }
+
+    fn request_activation(
+        &mut self,
+        dh: &DisplayHandle,
+        _token: XdgActivationToken,
+        token_data: XdgActivationTokenData,
+        surface: WlSurface,
+    ) {
+        let window = match self
+            .common
+            .shell
+            .space_for_window_mut(&surface)
+            .and_then(|workspace| {
+                workspace
+                    .space
+                    .window_for_surface(&surface, WindowSurfaceType::TOPLEVEL)
+                    .cloned()
+            }) {
+            Some(window) => window,
+            None => return,
+        };
+
+        // Focus may only be handed over by the window that currently has it,
+        // everything else has to wait for the user to notice
+        let seat = self.common.last_active_seat.clone();
+        let focused = self
+            .common
+            .shell
+            .active_workspace()
+            .focus_stack(&seat)
+            .last()
+            .map(|window| window.toplevel().wl_surface().clone());
+        let from_focused = token_data.surface.is_some() && token_data.surface == focused;
+
+        if from_focused && token_data.timestamp.elapsed() < ACTIVATION_TIMEOUT {
+            let surface = window.toplevel().wl_surface().clone();
+            self.common.set_focus(dh, Some(&surface), &seat, None);
+        } else {
+            self.common.shell.set_urgent(&window, true);
+        }
+    }
+
+    fn destroy_activation(
+        &mut self,
+        _dh: &DisplayHandle,
+        _token: XdgActivationToken,
+        _token_data: XdgActivationTokenData,
+        _surface: WlSurface,
+    ) {
+    }
+}
+
+delegate_xdg_activation!(State);
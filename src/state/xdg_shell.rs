@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::cell::Cell;
+use std::{cell::Cell, time::Duration};
 
 use smithay::{
     delegate_xdg_shell,
@@ -9,23 +9,30 @@ use smithay::{
         Window, WindowSurfaceType,
     },
     reexports::{
-        wayland_protocols::xdg::shell::server::xdg_toplevel::ResizeEdge,
+        wayland_protocols::xdg::shell::server::xdg_toplevel::{self, ResizeEdge},
         wayland_server::{
             protocol::{wl_output::WlOutput, wl_seat::WlSeat, wl_surface::WlSurface},
             DisplayHandle,
         },
     },
     wayland::{
+        compositor::with_states,
         output::Output,
         seat::{Focus, PointerGrabStartData, Seat},
         shell::xdg::{
-            PopupSurface, PositionerState, ToplevelSurface, XdgShellHandler, XdgShellState,
+            Configure, PopupSurface, PositionerState, SurfaceCachedState, ToplevelSurface,
+            XdgShellHandler, XdgShellState,
         },
         Serial,
     },
 };
 
-use super::{output::active_output, State};
+use super::{
+    credentials::{set_window_credentials, ClientCredentials},
+    output::active_output,
+    BackendData, State,
+};
+use crate::{backend::renderer::closing::ClosingWindow, shell::window::WindowExt};
 
 pub type PopupGrabData = Cell<Option<PopupGrab>>;
 
@@ -34,9 +41,11 @@ impl XdgShellHandler for State {
         &mut self.common.shell.xdg_shell_state
     }
 
-    fn new_toplevel(&mut self, _dh: &DisplayHandle, surface: ToplevelSurface) {
+    fn new_toplevel(&mut self, dh: &DisplayHandle, surface: ToplevelSurface) {
         let seat = &self.common.last_active_seat;
+        let credentials = ClientCredentials::of_surface(dh, surface.wl_surface());
         let window = Window::new(Kind::Xdg(surface));
+        set_window_credentials(&window, credentials);
 
         self.common
             .shell
@@ -59,17 +68,23 @@ impl XdgShellHandler for State {
             self.common.shell.unconstrain_popup(&surface, &positioner);
 
             if surface.send_configure().is_ok() {
-                self.common
+                if let Err(err) = self
+                    .common
                     .shell
                     .popups
                     .track_popup(PopupKind::from(surface))
-                    .unwrap();
+                {
+                    slog_scope::warn!("Failed to track popup: {}", err);
+                }
             }
         }
     }
 
     fn grab(&mut self, dh: &DisplayHandle, surface: PopupSurface, seat: WlSeat, serial: Serial) {
-        let seat = Seat::from_resource(&seat).unwrap();
+        let seat = match Seat::from_resource(&seat) {
+            Some(seat) => seat,
+            None => return,
+        };
         let ret = self
             .common
             .shell
@@ -141,18 +156,15 @@ impl XdgShellHandler for State {
         seat: WlSeat,
         serial: Serial,
     ) {
-        let seat = Seat::from_resource(&seat).unwrap();
+        let seat = match Seat::from_resource(&seat) {
+            Some(seat) => seat,
+            None => return,
+        };
         if let Some(start_data) = check_grab_preconditions(&seat, surface.wl_surface(), serial) {
-            let workspace = self
-                .common
-                .shell
-                .space_for_window_mut(surface.wl_surface())
-                .unwrap();
-            let window = workspace
-                .space
-                .window_for_surface(surface.wl_surface(), WindowSurfaceType::TOPLEVEL)
-                .unwrap()
-                .clone();
+            let window = match self.common.shell.mapped_window(surface.wl_surface()) {
+                Some(window) => window,
+                None => return,
+            };
 
             self.common
                 .shell
@@ -168,20 +180,18 @@ impl XdgShellHandler for State {
         serial: Serial,
         edges: ResizeEdge,
     ) {
-        let seat = Seat::from_resource(&seat).unwrap();
+        let seat = match Seat::from_resource(&seat) {
+            Some(seat) => seat,
+            None => return,
+        };
         if let Some(start_data) = check_grab_preconditions(&seat, surface.wl_surface(), serial) {
-            let workspace = self
-                .common
-                .shell
-                .space_for_window_mut(surface.wl_surface())
-                .unwrap();
-            let window = workspace
-                .space
-                .window_for_surface(surface.wl_surface(), WindowSurfaceType::TOPLEVEL)
-                .unwrap()
-                .clone();
-
-            workspace.resize_request(&window, &seat, serial, start_data, edges);
+            let window = match self.common.shell.mapped_window(surface.wl_surface()) {
+                Some(window) => window,
+                None => return,
+            };
+            if let Some(workspace) = self.common.shell.space_for_window_mut(surface.wl_surface()) {
+                workspace.resize_request(&window, &seat, serial, start_data, edges);
+            }
         }
     }
 
@@ -234,7 +244,11 @@ impl XdgShellHandler for State {
                 .window_for_surface(surface, WindowSurfaceType::TOPLEVEL)
                 .unwrap()
                 .clone();
-            workspace.fullscreen_request(&window, &output)
+            if window.fake_fullscreen() {
+                workspace.maximize_request(&window, &output)
+            } else {
+                workspace.fullscreen_request(&window, &output)
+            }
         }
     }
 
@@ -246,9 +260,118 @@ impl XdgShellHandler for State {
                 .window_for_surface(surface, WindowSurfaceType::TOPLEVEL)
                 .unwrap()
                 .clone();
-            workspace.unfullscreen_request(&window)
+            if window.fake_fullscreen() {
+                // Also leaves a real fullscreen from before the window was switched over
+                workspace.unmaximize_request(&window)
+            } else {
+                workspace.unfullscreen_request(&window)
+            }
+        }
+    }
+
+    fn ack_configure(&mut self, _dh: &DisplayHandle, surface: WlSurface, configure: Configure) {
+        if let Configure::Toplevel(configure) = configure {
+            self.configure_acked(&surface, configure.serial);
         }
     }
+
+    fn toplevel_destroyed(&mut self, _dh: &DisplayHandle, surface: ToplevelSurface) {
+        let surface = surface.wl_surface();
+        let window = self
+            .common
+            .shell
+            .space_for_window_mut(surface)
+            .and_then(|workspace| {
+                workspace
+                    .space
+                    .window_for_surface(surface, WindowSurfaceType::TOPLEVEL)
+                    .cloned()
+            });
+        if let Some(window) = &window {
+            self.common.textures.evict(window.id());
+            self.common.icons.forget_window(window);
+            // The surfaces are still around, so this is the last chance to draw the window
+            self.snapshot_closing(window);
+        }
+    }
+}
+
+impl State {
+    /// Starts the close animation of `window`, while its surfaces still have their buffers.
+    /// Called before it is destroyed or unmapped.
+    pub fn snapshot_closing(&mut self, window: &Window) {
+        let duration = match self.common.animations() {
+            true => Duration::from_millis(self.common.config.close_animation),
+            false => Duration::ZERO,
+        };
+        let max_snapshots = self.common.config.max_closing_windows;
+        let renderer = match &mut self.backend {
+            BackendData::Winit(winit) if !duration.is_zero() && max_snapshots > 0 => {
+                winit.backend.renderer()
+            }
+            _ => return,
+        };
+        // Games skip the animation, private windows are not snapshotted at all
+        if window.game_mode() || window.private() {
+            return;
+        }
+
+        // Only windows on the active workspace are seen closing
+        let workspace = self.common.shell.active_workspace();
+        if workspace
+            .space
+            .window_for_surface(window.toplevel().wl_surface(), WindowSurfaceType::TOPLEVEL)
+            .is_none()
+        {
+            return;
+        }
+        let z_index = workspace.z_index(window);
+        match ClosingWindow::snapshot(renderer, &workspace.space, window, z_index, duration) {
+            Ok(closing) => self.common.closing_windows.push(closing, max_snapshots),
+            Err(err) => slog_scope::debug!("No close animation: {:?}", err),
+        }
+    }
+
+    /// A mapped window committed a null buffer
+    pub fn toplevel_unmapped(&mut self, window: &Window) {
+        self.common.textures.evict(window.id());
+        self.common.icons.forget_window(window);
+        let seat = self.common.last_active_seat.clone();
+        self.common.shell.unmap_toplevel(window, &seat);
+    }
+}
+
+/// Disconnects the client of `toplevel` with a protocol error if its committed window geometry
+/// is empty or its size bounds contradict each other, returns whether the state is valid
+pub fn validate_toplevel_state(toplevel: &ToplevelSurface) -> bool {
+    let (geometry, min_size, max_size) = with_states(toplevel.wl_surface(), |states| {
+        let state = states.cached_state.current::<SurfaceCachedState>();
+        (state.geometry, state.min_size, state.max_size)
+    });
+
+    let error = if let Some(geometry) = geometry.filter(|g| g.size.w <= 0 || g.size.h <= 0) {
+        format!(
+            "window geometry {}x{} is not positive",
+            geometry.size.w, geometry.size.h
+        )
+    } else if min_size.w < 0 || min_size.h < 0 || max_size.w < 0 || max_size.h < 0 {
+        "negative min or max size".to_string()
+    } else if (max_size.w != 0 && min_size.w > max_size.w)
+        || (max_size.h != 0 && min_size.h > max_size.h)
+    {
+        format!(
+            "min size {}x{} is larger than max size {}x{}",
+            min_size.w, min_size.h, max_size.w, max_size.h
+        )
+    } else {
+        return true;
+    };
+
+    slog_scope::warn!("Client sent an invalid toplevel state: {}", error);
+    toplevel
+        .xdg_toplevel()
+        .post_error(xdg_toplevel::Error::InvalidSize, error);
+    false
 }
 
 fn check_grab_preconditions(
@@ -259,14 +382,14 @@ fn check_grab_preconditions(
     use smithay::reexports::wayland_server::Resource;
 
     // TODO: touch resize.
-    let pointer = seat.get_pointer().unwrap();
+    let pointer = seat.get_pointer()?;
 
     // Check that this surface has a click grab.
     if !pointer.has_grab(serial) {
         return None;
     }
 
-    let start_data = pointer.grab_start_data().unwrap();
+    let start_data = pointer.grab_start_data()?;
 
     // If the focus was for a different surface, ignore the request.
     if start_data.focus.is_none()
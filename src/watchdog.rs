@@ -0,0 +1,246 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+use calloop::{
+    timer::{TimeoutAction, Timer},
+    EventLoop,
+};
+use deno_core::v8::IsolateHandle;
+use nix::{
+    libc::c_int,
+    sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal},
+};
+use serde::Deserialize;
+
+use crate::LoopData;
+
+/// How often the event loop shows it is still running, and the watchdog looks
+const BEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// What the runtime is recorded as, the only event the watchdog can interrupt
+pub const RUNTIME_EVENT: &str = "runtime";
+/// Recorded after a watched future returns, for whatever comes next without recording itself
+const UNRECORDED_EVENT: &str = "an unrecorded event";
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    /// Seconds without progress before the event loop counts as stalled, 0 turns the watchdog off
+    pub timeout: u64,
+    /// Stop the script if it is what the event loop is stuck in
+    pub terminate_runtime: bool,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 10,
+            terminate_runtime: false,
+        }
+    }
+}
+
+struct Progress {
+    /// Last time the heartbeat timer of the event loop ran
+    beat: Instant,
+    /// The event source that was dispatched last, and when it started
+    event: &'static str,
+    event_started: Instant,
+}
+
+#[derive(Default)]
+struct Inner {
+    progress: Option<Mutex<Progress>>,
+    runtime: Mutex<Option<IsolateHandle>>,
+    /// Set by the watchdog thread after it stopped the script, for the event loop to drop what
+    /// the script registered
+    terminated: AtomicBool,
+}
+
+/// Notices when the event loop stops making progress, from a thread of its own.
+///
+/// Event sources record themselves with [`Watchdog::event`] before they do their work, so a
+/// stall can be blamed on the last one. A disabled watchdog records nothing.
+#[derive(Clone, Default)]
+pub struct Watchdog(Arc<Inner>);
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        if config.timeout == 0 {
+            return Self::default();
+        }
+        let now = Instant::now();
+        Self(Arc::new(Inner {
+            progress: Some(Mutex::new(Progress {
+                beat: now,
+                event: "startup",
+                event_started: now,
+            })),
+            runtime: Mutex::new(None),
+            terminated: AtomicBool::new(false),
+        }))
+    }
+
+    /// Starts the heartbeat timer and the thread watching it. The thread runs even with the
+    /// watchdog off, to notice SIGUSR1 asking for safe mode.
+    pub fn start(
+        &self,
+        event_loop: &mut EventLoop<LoopData>,
+        config: WatchdogConfig,
+    ) -> anyhow::Result<()> {
+        let watchdog = self.clone();
+        event_loop
+            .handle()
+            .insert_source(Timer::from_duration(BEAT_INTERVAL), move |_, _, data| {
+                watchdog.beat();
+                if watchdog.0.terminated.swap(false, Ordering::Relaxed) {
+                    let dh = data.display.handle();
+                    data.state.common.enter_safe_mode(&dh);
+                }
+                TimeoutAction::ToDuration(BEAT_INTERVAL)
+            })
+            .map_err(|_| anyhow::anyhow!("Failed to insert watchdog timer"))?;
+
+        let action = SigAction::new(
+            SigHandler::Handler(request_safe_mode),
+            SaFlags::SA_RESTART,
+            SigSet::empty(),
+        );
+        // Only stores to an atomic, which is all a signal handler may do
+        unsafe { sigaction(Signal::SIGUSR1, &action) }
+            .with_context(|| "Failed to handle SIGUSR1")?;
+
+        let watchdog = self.clone();
+        thread::Builder::new()
+            .name("watchdog".to_string())
+            .spawn(move || watchdog.watch(config))
+            .with_context(|| "Failed to start watchdog thread")?;
+        Ok(())
+    }
+
+    /// Lets the watchdog stop the script, if it is configured to
+    pub fn set_runtime(&self, handle: IsolateHandle) {
+        *self.0.runtime.lock().unwrap() = Some(handle);
+    }
+
+    /// Stops the script where it is, from any thread. Unlike cancelling the worker this also
+    /// interrupts JavaScript that never yields. Returns whether there was a script to stop.
+    pub fn terminate_runtime(&self) -> bool {
+        match self.0.runtime.lock().unwrap().take() {
+            Some(runtime) => runtime.terminate_execution(),
+            None => false,
+        }
+    }
+
+    /// Records that the event loop started dispatching `event`
+    pub fn event(&self, event: &'static str) {
+        if let Some(progress) = &self.0.progress {
+            let mut progress = progress.lock().unwrap();
+            progress.event = event;
+            progress.event_started = Instant::now();
+        }
+    }
+
+    /// Records `name` while `future` is polled
+    pub fn watch_future<F: Future>(&self, name: &'static str, future: F) -> WatchedFuture<F> {
+        WatchedFuture {
+            watchdog: self.clone(),
+            name,
+            future: Box::pin(future),
+        }
+    }
+
+    fn beat(&self) {
+        if let Some(progress) = &self.0.progress {
+            progress.lock().unwrap().beat = Instant::now();
+        }
+    }
+
+    fn watch(&self, config: WatchdogConfig) {
+        let timeout = Duration::from_secs(config.timeout);
+        let mut stalled_since = None;
+        loop {
+            thread::sleep(BEAT_INTERVAL);
+            if SAFE_MODE_REQUESTED.swap(false, Ordering::Relaxed) {
+                // Whatever `terminate_runtime` says, the user asked for it
+                slog_scope::warn!("Received SIGUSR1, stopping the script");
+                self.terminate_runtime();
+                self.0.terminated.store(true, Ordering::Relaxed);
+            }
+            let progress = match &self.0.progress {
+                Some(progress) => progress,
+                None => continue,
+            };
+            let (beat, event, event_started) = {
+                let progress = progress.lock().unwrap();
+                (progress.beat, progress.event, progress.event_started)
+            };
+
+            let stalled = beat.elapsed();
+            if stalled < timeout {
+                if let Some(since) = stalled_since.take() {
+                    slog_scope::warn!(
+                        "Event loop is running again after {:.1?}",
+                        beat.duration_since(since)
+                    );
+                }
+                continue;
+            }
+            // Once per stall
+            if stalled_since.is_some() {
+                continue;
+            }
+            stalled_since = Some(beat);
+
+            slog_scope::error!(
+                "Event loop made no progress for {} seconds, stuck in {} for {:.1?}",
+                stalled.as_secs(),
+                event,
+                event_started.elapsed()
+            );
+            if event == RUNTIME_EVENT && config.terminate_runtime {
+                if self.terminate_runtime() {
+                    slog_scope::error!(
+                        "Terminated the runtime, windows are not managed by the script anymore"
+                    );
+                    self.0.terminated.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+/// Set by the SIGUSR1 handler, for the watchdog thread to pick up
+static SAFE_MODE_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_safe_mode(_: c_int) {
+    SAFE_MODE_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+pub struct WatchedFuture<F> {
+    watchdog: Watchdog,
+    name: &'static str,
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for WatchedFuture<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        self.watchdog.event(self.name);
+        let poll = self.future.as_mut().poll(cx);
+        self.watchdog.event(UNRECORDED_EVENT);
+        poll
+    }
+}